@@ -145,6 +145,19 @@ class MainService : Service() {
                 Log.d(logTag, "from rust:stop_capture")
                 stopCapture()
             }
+            "cm_notify" -> {
+                try {
+                    val jsonObject = JSONObject(arg1)
+                    val id = (jsonObject["id"] as String).toInt()
+                    val category = jsonObject["category"] as String
+                    val sound = jsonObject["mode"] == "sound"
+                    val username = jsonObject["peer_name"] as String
+                    val peerId = jsonObject["peer_id"] as String
+                    cmNotification(id, category, sound, username, peerId)
+                } catch (e: JSONException) {
+                    e.printStackTrace()
+                }
+            }
             else -> {
             }
         }
@@ -681,6 +694,25 @@ class MainService : Service() {
         notificationManager.notify(getClientNotifyID(clientID), notification)
     }
 
+    private fun cmNotification(
+        clientID: Int,
+        category: String,
+        sound: Boolean,
+        username: String,
+        peerId: String
+    ) {
+        val notification = notificationBuilder
+            .setOngoing(false)
+            .setPriority(NotificationCompat.PRIORITY_MAX)
+            .setDefaults(if (sound) NotificationCompat.DEFAULT_SOUND else 0)
+            .setContentTitle(translate("cm_notify_$category"))
+            .setContentText("$username - $peerId")
+            .build()
+        // The builder is shared, the other notifications stay silent.
+        notificationBuilder.setDefaults(0)
+        notificationManager.notify(getClientNotifyID(clientID), notification)
+    }
+
     private fun getClientNotifyID(clientID: Int): Int {
         return clientID + NOTIFY_ID_OFFSET
     }
@@ -2060,13 +2060,15 @@ connectMainDesktop(
   required bool isTcpTunneling,
   required bool isRDP,
   bool? forceRelay,
+  bool? viewOnly,
 }) async {
   if (isFileTransfer) {
     await rustDeskWinManager.newFileTransfer(id, forceRelay: forceRelay);
   } else if (isTcpTunneling || isRDP) {
     await rustDeskWinManager.newPortForward(id, isRDP, forceRelay: forceRelay);
   } else {
-    await rustDeskWinManager.newRemoteDesktop(id, forceRelay: forceRelay);
+    await rustDeskWinManager.newRemoteDesktop(id,
+        forceRelay: forceRelay, viewOnly: viewOnly);
   }
 }
 
@@ -2074,6 +2076,7 @@ connectMainDesktop(
 /// If [isFileTransfer], starts a session only for file transfer.
 /// If [isTcpTunneling], starts a session only for tcp tunneling.
 /// If [isRDP], starts a session only for rdp.
+/// If [viewOnly], no input is sent to the peer of the remote desktop session.
 connect(
   BuildContext context,
   String id, {
@@ -2081,6 +2084,7 @@ connect(
   bool isTcpTunneling = false,
   bool isRDP = false,
   bool forceRelay = false,
+  bool viewOnly = false,
 }) async {
   if (id == '') return;
   if (!isDesktop || desktopType == DesktopType.main) {
@@ -2110,6 +2114,7 @@ connect(
         isTcpTunneling: isTcpTunneling,
         isRDP: isRDP,
         forceRelay: forceRelay2,
+        viewOnly: viewOnly,
       );
     } else {
       await rustDeskWinManager.call(WindowType.Main, kWindowConnect, {
@@ -2118,6 +2123,7 @@ connect(
         'isTcpTunneling': isTcpTunneling,
         'isRDP': isRDP,
         'forceRelay': forceRelay,
+        'viewOnly': viewOnly,
       });
     }
   } else {
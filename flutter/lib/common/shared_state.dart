@@ -77,9 +77,11 @@ class CurrentDisplayState {
 class ConnectionType {
   final Rx<String> _secure = kInvalidValueStr.obs;
   final Rx<String> _direct = kInvalidValueStr.obs;
+  final Rx<String> _relay = ''.obs;
 
   Rx<String> get secure => _secure;
   Rx<String> get direct => _direct;
+  Rx<String> get relay => _relay;
 
   static String get strSecure => 'secure';
   static String get strInsecure => 'insecure';
@@ -92,6 +94,11 @@ class ConnectionType {
 
   void setDirect(bool v) {
     _direct.value = v ? strDirect : strIndirect;
+    if (v) _relay.value = '';
+  }
+
+  void setRelay(String relay) {
+    _relay.value = relay;
   }
 
   bool isValid() {
@@ -286,6 +293,41 @@ class PeerStringOption {
       Get.find<RxString>(tag: tag(id, opt));
 }
 
+class RemoteVolume {
+  // "ok", "unsupported" or "denied", empty until told by the peer.
+  final RxString state = ''.obs;
+  final RxInt percent = 0.obs;
+  final RxBool muted = false.obs;
+
+  void update(String state, int percent, bool muted) {
+    this.state.value = state;
+    if (state == 'ok') {
+      this.percent.value = percent;
+      this.muted.value = muted;
+    }
+  }
+}
+
+class RemoteVolumeState {
+  static String tag(String id) => 'remote_volume_$id';
+
+  static void init(String id) {
+    final key = tag(id);
+    if (!Get.isRegistered(tag: key)) {
+      Get.put(RemoteVolume(), tag: key);
+    }
+  }
+
+  static void delete(String id) {
+    final key = tag(id);
+    if (Get.isRegistered(tag: key)) {
+      Get.delete(tag: key);
+    }
+  }
+
+  static RemoteVolume find(String id) => Get.find<RemoteVolume>(tag: tag(id));
+}
+
 class UnreadChatCountState {
   static String tag(id) => 'unread_chat_count_$id';
 
@@ -319,6 +361,7 @@ initSharedStates(String id) {
   FingerprintState.init(id);
   PeerBoolOption.init(id, 'zoom-cursor', () => false);
   UnreadChatCountState.init(id);
+  RemoteVolumeState.init(id);
   if (isMobile) ConnectionTypeState.init(id); // desktop in other places
 }
 
@@ -332,5 +375,6 @@ removeSharedStates(String id) {
   FingerprintState.delete(id);
   PeerBoolOption.delete(id, 'zoom-cursor');
   UnreadChatCountState.delete(id);
+  RemoteVolumeState.delete(id);
   if (isMobile) ConnectionTypeState.delete(id);
 }
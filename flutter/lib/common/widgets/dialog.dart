@@ -54,6 +54,144 @@ class RegexValidationRule extends ValidationRule {
   }
 }
 
+const _kTotpSetupEvent = 'totp_setup';
+
+/// Generate a new 2FA secret, it takes effect after a valid code of the
+/// authenticator app is entered.
+void setup2faDialog(Function() onChanged) {
+  platformFFI.registerEventHandler(_kTotpSetupEvent, _kTotpSetupEvent,
+      (evt) async {
+    platformFFI.unregisterEventHandler(_kTotpSetupEvent, _kTotpSetupEvent);
+    _showTotpSetupDialog(evt['secret'] ?? '', evt['uri'] ?? '', onChanged);
+  });
+  bind.mainSetup2Fa();
+}
+
+void _showTotpSetupDialog(String secret, String uri, Function() onChanged) {
+  final controller = TextEditingController();
+  var msg = '';
+  var isInProgress = false;
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      final code = controller.text.trim();
+      if (code.isEmpty) return;
+      setState(() => isInProgress = true);
+      try {
+        await bind.mainEnable2Fa(code: code);
+      } catch (e) {
+        setState(() {
+          isInProgress = false;
+          msg = translate('Wrong 2FA Code');
+        });
+        return;
+      }
+      close();
+      onChanged();
+      generate2faRecoveryCodesDialog();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Enable 2FA')),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        mainAxisSize: MainAxisSize.min,
+        children: [
+          Text(translate('enable_2fa_tip')),
+          const SizedBox(height: 12.0),
+          SelectionArea(
+              child: Column(
+            crossAxisAlignment: CrossAxisAlignment.start,
+            children: [
+              Text(secret,
+                  style: const TextStyle(
+                      fontFamily: 'monospace', fontWeight: FontWeight.bold)),
+              const SizedBox(height: 4.0),
+              Text(uri, style: const TextStyle(fontSize: 12)),
+            ],
+          )),
+          const SizedBox(height: 12.0),
+          DialogTextField(
+            title: translate('2fa_code_tip'),
+            controller: controller,
+            errorText: msg.isEmpty ? null : msg,
+            prefixIcon: const Icon(Icons.pin_outlined),
+          ),
+          if (isInProgress) const LinearProgressIndicator(),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+/// Disabling 2FA is only allowed on this device, while it's not remote
+/// controlled.
+void disable2faDialog(Function() onChanged) {
+  gFFI.dialogManager.show((setState, close, context) {
+    submit() async {
+      close();
+      try {
+        await bind.cmDisable2Fa();
+      } catch (e) {
+        showToast(translate('2fa_local_only_tip'));
+      }
+      onChanged();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Disable 2FA')),
+      content: Text(translate('disable_2fa_tip')),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+/// The recovery codes are only shown once, the old codes are invalidated.
+void generate2faRecoveryCodesDialog() async {
+  final List<String> codes;
+  try {
+    codes = await bind.cmGenerate2FaRecoveryCodes();
+  } catch (e) {
+    showToast(translate('2fa_local_only_tip'));
+    return;
+  }
+  gFFI.dialogManager.show((setState, close, context) {
+    return CustomAlertDialog(
+      title: Text(translate('Generate recovery codes')),
+      content: Column(
+        crossAxisAlignment: CrossAxisAlignment.start,
+        mainAxisSize: MainAxisSize.min,
+        children: [
+          Text(translate('2fa_recovery_codes_tip')),
+          const SizedBox(height: 12.0),
+          SelectionArea(
+              child: Text(codes.join('\n'),
+                  style: const TextStyle(fontFamily: 'monospace'))),
+        ],
+      ),
+      actions: [
+        dialogButton('Copy', onPressed: () {
+          Clipboard.setData(ClipboardData(text: codes.join('\n')));
+          showToast(translate('Copied'));
+        }, isOutline: true),
+        dialogButton('OK', onPressed: close),
+      ],
+      onSubmit: close,
+      onCancel: close,
+    );
+  });
+}
+
 void changeIdDialog() {
   var newId = "";
   var msg = "";
@@ -513,6 +651,64 @@ void enterPasswordDialog(
   );
 }
 
+/// The peer requires the 6-digit code of the authenticator app, or a recovery
+/// code, after the password is accepted.
+void enter2faDialog(SessionID sessionId, OverlayDialogManager dialogManager,
+    String type, String title) {
+  final controller = TextEditingController();
+  dialogManager.dismissAll();
+  dialogManager.show(tag: '$sessionId-$type', (setState, close, context) {
+    cancel() {
+      close();
+      closeConnection();
+    }
+
+    submit() {
+      final code = controller.text.trim();
+      if (code.isEmpty) return;
+      bind.sessionSend2Fa(sessionId: sessionId, code: code);
+      close();
+      dialogManager.showLoading(translate('Logging in...'),
+          onCancel: closeConnection);
+    }
+
+    return CustomAlertDialog(
+      title: Row(
+        mainAxisAlignment: MainAxisAlignment.center,
+        children: [
+          Icon(Icons.security_rounded, color: MyTheme.accent),
+          Text(translate('2FA Required')).paddingOnly(left: 10),
+        ],
+      ),
+      content: Column(mainAxisSize: MainAxisSize.min, children: [
+        if (title == 'Wrong 2FA Code')
+          Text(translate(title), style: TextStyle(color: Colors.red))
+              .marginOnly(bottom: 8),
+        DialogTextField(
+          title: translate('2fa_code_tip'),
+          controller: controller,
+          prefixIcon: const Icon(Icons.pin_outlined),
+        ),
+      ]),
+      actions: [
+        dialogButton(
+          'Cancel',
+          icon: Icon(Icons.close_rounded),
+          onPressed: cancel,
+          isOutline: true,
+        ),
+        dialogButton(
+          'OK',
+          icon: Icon(Icons.done_rounded),
+          onPressed: submit,
+        ),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
 void enterUserLoginDialog(
     SessionID sessionId, OverlayDialogManager dialogManager) async {
   await _connectDialog(
@@ -947,6 +1143,39 @@ void showElevationError(SessionID sessionId, String type, String title,
   });
 }
 
+/// The session is paused until the new fingerprint of the peer is trusted.
+void showFingerprintMismatchDialog(SessionID sessionId, String type,
+    String title, String text, String oldFingerprint, String newFingerprint,
+    OverlayDialogManager dialogManager) {
+  dialogManager.dismissAll();
+  dialogManager.show(tag: '$sessionId-$type', (setState, close, context) {
+    void submit() {
+      close();
+      bind.sessionAcceptNewFingerprint(sessionId: sessionId);
+    }
+
+    void cancel() {
+      close();
+      closeConnection();
+    }
+
+    final fingerprints = '${translate('Fingerprint')}:\n'
+        '$oldFingerprint\n\u2192 $newFingerprint';
+    return CustomAlertDialog(
+      title: null,
+      content: SelectionArea(
+          child: msgboxContent(
+              type, title, '${translate(text)}\n\n$fingerprints')),
+      actions: [
+        dialogButton('Cancel', onPressed: cancel, isOutline: true),
+        dialogButton('Trust and continue', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: cancel,
+    );
+  });
+}
+
 void showWaitAcceptDialog(SessionID sessionId, String type, String title,
     String text, OverlayDialogManager dialogManager) {
   dialogManager.dismissAll();
@@ -997,6 +1226,54 @@ void showRestartRemoteDevice(PeerInfo pi, String id, SessionID sessionId,
   if (res == true) bind.sessionRestartRemoteDevice(sessionId: sessionId);
 }
 
+void showRemoteVolumeDialog(
+    String id, SessionID sessionId, OverlayDialogManager dialogManager) {
+  final volume = RemoteVolumeState.find(id);
+  // The peer answers with the latest state, and keeps it in sync.
+  bind.sessionGetRemoteVolume(sessionId: sessionId);
+  final dragging = RxnDouble();
+  dialogManager.show((setState, close, context) => CustomAlertDialog(
+        title: Text(translate('Remote volume')),
+        content: Obx(() {
+          final enabled = volume.state.value == 'ok';
+          final percent = dragging.value ?? volume.percent.value.toDouble();
+          return Row(children: [
+            IconButton(
+              icon: Icon(volume.muted.value
+                  ? Icons.volume_off_rounded
+                  : Icons.volume_up_rounded),
+              tooltip: translate(volume.muted.value ? 'Unmute' : 'Mute'),
+              onPressed: enabled
+                  ? () => bind.sessionSetRemoteMuted(
+                      sessionId: sessionId, muted: !volume.muted.value)
+                  : null,
+            ),
+            Expanded(
+              child: Slider(
+                value: percent,
+                min: 0,
+                max: 100,
+                divisions: 100,
+                onChanged: enabled ? (v) => dragging.value = v : null,
+                onChangeEnd: (v) {
+                  dragging.value = null;
+                  // Shown at once, corrected by the answer of the peer.
+                  volume.percent.value = v.round();
+                  bind.sessionSetRemoteVolume(
+                      sessionId: sessionId, percent: v.round());
+                },
+              ),
+            ),
+            SizedBox(width: 48, child: Text('${percent.round()}%')),
+          ]);
+        }),
+        actions: [
+          dialogButton('Close', onPressed: close, isOutline: true),
+        ],
+        onCancel: close,
+      ));
+}
+
 showSetOSPassword(
   SessionID sessionId,
   bool login,
@@ -476,6 +476,17 @@ class QualityMonitor extends StatelessWidget {
     );
   }
 
+  // The average legs of the frames, the first one is unknown till the clocks are compared.
+  List<Widget> _latencyRows(Map<String, dynamic> breakdown) {
+    String ms(dynamic v) =>
+        v == null ? '-' : '${(v as num).toStringAsFixed(1)}ms';
+    return [
+      _row("Capture to receive", ms(breakdown['capture_to_receive'])),
+      _row("Receive to decode", ms(breakdown['receive_to_decode'])),
+      _row("Decode to render", ms(breakdown['decode_to_render'])),
+    ];
+  }
+
   @override
   Widget build(BuildContext context) => ChangeNotifierProvider.value(
       value: qualityMonitorModel,
@@ -499,6 +510,13 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      _row(
+                          "Decoder", qualityMonitorModel.data.decoder ?? '-'),
+                      if (qualityMonitorModel.data.downscale != null)
+                        _row("Downscale", qualityMonitorModel.data.downscale!),
+                      if (qualityMonitorModel.data.latencyBreakdown != null)
+                        ..._latencyRows(
+                            qualityMonitorModel.data.latencyBreakdown!),
                     ],
                   ),
                 )
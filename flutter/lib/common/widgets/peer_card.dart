@@ -458,6 +458,7 @@ abstract class BasePeerCard extends StatelessWidget {
     bool isFileTransfer = false,
     bool isTcpTunneling = false,
     bool isRDP = false,
+    bool viewOnly = false,
   }) {
     return MenuEntryButton<String>(
       childBuilder: (TextStyle? style) => Text(
@@ -472,6 +473,7 @@ abstract class BasePeerCard extends StatelessWidget {
           isFileTransfer: isFileTransfer,
           isTcpTunneling: isTcpTunneling,
           isRDP: isRDP,
+          viewOnly: viewOnly,
         );
       },
       padding: menuPadding,
@@ -490,6 +492,18 @@ abstract class BasePeerCard extends StatelessWidget {
     );
   }
 
+  /// No input is sent to the peer, until the peer allows it.
+  @protected
+  MenuEntryBase<String> _viewOnlyConnectAction(
+      BuildContext context, String id) {
+    return _connectCommonAction(
+      context,
+      id,
+      translate('Connect view-only'),
+      viewOnly: true,
+    );
+  }
+
   @protected
   MenuEntryBase<String> _transferFileAction(BuildContext context, String id) {
     return _connectCommonAction(
@@ -858,6 +872,7 @@ class RecentPeerCard extends BasePeerCard {
       BuildContext context) async {
     final List<MenuEntryBase<String>> menuItems = [
       _connectAction(context, peer),
+      if (isDesktop) _viewOnlyConnectAction(context, peer.id),
       _transferFileAction(context, peer.id),
     ];
 
@@ -915,6 +930,7 @@ class FavoritePeerCard extends BasePeerCard {
       BuildContext context) async {
     final List<MenuEntryBase<String>> menuItems = [
       _connectAction(context, peer),
+      if (isDesktop) _viewOnlyConnectAction(context, peer.id),
       _transferFileAction(context, peer.id),
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
@@ -966,6 +982,7 @@ class DiscoveredPeerCard extends BasePeerCard {
       BuildContext context) async {
     final List<MenuEntryBase<String>> menuItems = [
       _connectAction(context, peer),
+      if (isDesktop) _viewOnlyConnectAction(context, peer.id),
       _transferFileAction(context, peer.id),
     ];
 
@@ -1019,6 +1036,7 @@ class AddressBookPeerCard extends BasePeerCard {
       BuildContext context) async {
     final List<MenuEntryBase<String>> menuItems = [
       _connectAction(context, peer),
+      if (isDesktop) _viewOnlyConnectAction(context, peer.id),
       _transferFileAction(context, peer.id),
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
@@ -1087,6 +1105,7 @@ class MyGroupPeerCard extends BasePeerCard {
       BuildContext context) async {
     final List<MenuEntryBase<String>> menuItems = [
       _connectAction(context, peer),
+      if (isDesktop) _viewOnlyConnectAction(context, peer.id),
       _transferFileAction(context, peer.id),
     ];
     if (isDesktop && peer.platform != kPeerPlatformAndroid) {
@@ -1307,7 +1326,8 @@ class TagPainter extends CustomPainter {
 void connectInPeerTab(BuildContext context, String id, PeerTabIndex tab,
     {bool isFileTransfer = false,
     bool isTcpTunneling = false,
-    bool isRDP = false}) async {
+    bool isRDP = false,
+    bool viewOnly = false}) async {
   if (tab == PeerTabIndex.ab) {
     // If recent peer's alias is empty, set it to ab's alias
     // Because the platform is not set, it may not take effect, but it is more important not to display if the connection is not successful
@@ -1324,5 +1344,6 @@ void connectInPeerTab(BuildContext context, String id, PeerTabIndex tab,
   connect(context, id,
       isFileTransfer: isFileTransfer,
       isTcpTunneling: isTcpTunneling,
-      isRDP: isRDP);
+      isRDP: isRDP,
+      viewOnly: viewOnly);
 }
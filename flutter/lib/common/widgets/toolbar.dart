@@ -121,6 +121,11 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
     v.add(TTextMenu(
         child: Text(translate('Paste')),
         onPressed: () async {
+          // Not read at all if blocked, see `text_clipboard.rs`.
+          if (!bind.sessionIsTextClipboardRequired(sessionId: sessionId)) {
+            showToast(translate('clipboard_read_blocked_tip'));
+            return;
+          }
           ClipboardData? data = await Clipboard.getData(Clipboard.kTextPlain);
           if (data != null && data.text != null) {
             bind.sessionInputString(
@@ -186,6 +191,15 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
               showRestartRemoteDevice(pi, id, sessionId, ffi.dialogManager)),
     );
   }
+  // the output volume of the remote
+  if (pi.isSupportRemoteVolume) {
+    v.add(
+      TTextMenu(
+          child: Text(translate('Remote volume')),
+          onPressed: () =>
+              showRemoteVolumeDialog(id, sessionId, ffi.dialogManager)),
+    );
+  }
   // insertLock
   if (!ffiModel.viewOnly && ffi.ffiModel.keyboard) {
     v.add(
@@ -349,9 +363,7 @@ Future<List<TRadioMenu<String>>> toolbarCodec(
   if (!visible) return [];
   onChanged(String? value) async {
     if (value == null) return;
-    await bind.sessionPeerOption(
-        sessionId: sessionId, name: 'codec-preference', value: value);
-    bind.sessionChangePreferCodec(sessionId: sessionId);
+    bind.sessionSetCodecPreference(sessionId: sessionId, codec: value);
   }
 
   TRadioMenu<String> radio(String label, String value, bool enabled) {
@@ -428,6 +440,56 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         ffi.qualityMonitorModel.checkShowQualityMonitor(sessionId);
       },
       child: Text(translate('Show quality monitor'))));
+  // force software decoding, the decoders are created again without reconnecting
+  final forceSoftwareDecode = await bind.sessionGetPeerOption(
+      sessionId: sessionId, name: 'force-software-decode');
+  v.add(TToggleMenu(
+      value: forceSoftwareDecode == 'Y',
+      onChanged: (value) async {
+        if (value == null) return;
+        await bind.sessionForceSoftwareDecode(
+            sessionId: sessionId, software: value);
+      },
+      child: Text(translate('Force software decoding'))));
+  // the host lowers the frame rate when busy, unless the quality is prioritized
+  final throttled = ffiModel.hostThrottled['fps_percent'];
+  v.add(TToggleMenu(
+      value: bind.sessionGetToggleOptionSync(
+          sessionId: sessionId, arg: 'prioritize-quality'),
+      onChanged: (value) async {
+        if (value == null) return;
+        await bind.sessionPrioritizeQuality(sessionId: sessionId, v: value);
+      },
+      child: Text(throttled == null
+          ? translate('Prioritize quality')
+          : '${translate('Prioritize quality')} (${translate('host_throttled_tip')} $throttled%)')));
+  // stamp the frames on the host to break down the latency in the quality monitor
+  if (ffiModel.pi.isSupportFrameTimestamps) {
+    v.add(TToggleMenu(
+        value: bind.sessionGetToggleOptionSync(
+            sessionId: sessionId, arg: 'latency-breakdown'),
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionMeasureLatency(sessionId: sessionId, v: value);
+          if (!value) ffi.qualityMonitorModel.data.latencyBreakdown = null;
+        },
+        child: Text(translate('Measure latency'))));
+  }
+  // downscale frames, empty if enabled automatically on the low-memory devices
+  if (isMobile) {
+    final option = 'downscale-frames';
+    final value =
+        await bind.sessionGetPeerOption(sessionId: sessionId, name: option);
+    final scale = ffi.qualityMonitorModel.data.downscale;
+    v.add(TToggleMenu(
+        value: value == 'Y' || (value.isEmpty && scale != null),
+        onChanged: (value) async {
+          if (value == null) return;
+          await bind.sessionPeerOption(
+              sessionId: sessionId, name: option, value: value ? 'Y' : 'N');
+        },
+        child: Text(translate('Downscale frames'))));
+  }
   // mute
   if (perms['audio'] != false) {
     final option = 'disable-audio';
@@ -456,6 +518,19 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         },
         child: Text(translate('Enable file copy and paste'))));
   }
+  // forward the print jobs of the remote to the local printer
+  if (perms['printer'] != false) {
+    final option = 'enable-print-forward';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Forward print jobs'))));
+  }
   // disable clipboard
   if (ffiModel.keyboard && perms['clipboard'] != false) {
     final enabled = !ffiModel.viewOnly;
@@ -473,6 +548,19 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
             : null,
         child: Text(translate('Disable clipboard'))));
   }
+  // disable clipboard read, the local clipboard is never read for the peer
+  if (isMobile && ffiModel.keyboard && perms['clipboard'] != false) {
+    final option = 'disable-clipboard-read';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Disable clipboard read'))));
+  }
   // lock after session end
   if (ffiModel.keyboard) {
     final option = 'lock-after-session-end';
@@ -544,10 +632,14 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
     v.add(TToggleMenu(
         value: value,
-        onChanged: (value) async {
+        onChanged: (value) {
           if (value == null) return;
-          await bind.sessionToggleOption(sessionId: sessionId, value: option);
-          bind.sessionChangePreferCodec(sessionId: sessionId);
+          final mode = bind.sessionSetColorAccuracy(
+              sessionId: sessionId, on: value);
+          if (!value || mode.isEmpty) return;
+          final factor = jsonDecode(mode)['bitrate_factor'];
+          showToast(
+              '${translate('color_accuracy_bitrate_tip')} (x$factor)');
         },
         child: Text(translate('True color (4:4:4)'))));
   }
@@ -595,6 +687,23 @@ List<TToggleMenu> toolbarPrivacyMode(
     return [];
   }
 
+  // The displays can be powered off by the impls reporting `display_off` in the details,
+  // the option is sent with the next turn-on request.
+  final displayOffMenu = <TToggleMenu>[];
+  if (privacyModeImpls.any((e) {
+    final impl = e as List<dynamic>;
+    return impl.length > 2 && (impl[2] as Map?)?['display_off'] == true;
+  })) {
+    final option = 'privacy-mode-display-off';
+    displayOffMenu.add(TToggleMenu(
+        value: bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option),
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Power off the displays'))));
+  }
+
   if (privacyModeImpls.length == 1) {
     final implKey = (privacyModeImpls[0] as List<dynamic>)[0] as String;
     return [
@@ -602,7 +711,8 @@ List<TToggleMenu> toolbarPrivacyMode(
         bind.sessionTogglePrivacyMode(
             sessionId: sid, implKey: implKey, on: privacyModeState.isEmpty);
         togglePrivacyModeTime = DateTime.now();
-      })
+      }),
+      ...displayOffMenu,
     ];
   } else {
     return privacyModeImpls.map((e) {
@@ -617,6 +727,7 @@ List<TToggleMenu> toolbarPrivacyMode(
             bind.sessionTogglePrivacyMode(
                 sessionId: sessionId, implKey: implKey, on: value);
           });
-    }).toList();
+    }).toList()
+      ..addAll(displayOffMenu);
   }
 }
@@ -24,12 +24,16 @@ const String kPlatformAdditionsIsInstalled = "is_installed";
 const String kPlatformAdditionsVirtualDisplays = "virtual_displays";
 const String kPlatformAdditionsHasFileClipboard = "has_file_clipboard";
 const String kPlatformAdditionsSupportedPrivacyModeImpl = "supported_privacy_mode_impl";
+const String kPlatformAdditionsCapabilities = "capabilities";
 
 const String kPeerPlatformWindows = "Windows";
 const String kPeerPlatformLinux = "Linux";
 const String kPeerPlatformMacOS = "Mac OS";
 const String kPeerPlatformAndroid = "Android";
 
+// The error of the rename or remove canceled, see `fs::file_op::CANCELED`.
+const String kFileOpCanceled = "Canceled";
+
 const double kScrollbarThickness = 12.0;
 
 /// [kAppTypeMain] used by 'Desktop Main Page' , 'Mobile (Client and Server)', "Install Page"
@@ -68,6 +72,8 @@ const String kOptionOpenNewConnInTabs = "enable-open-new-connections-in-tabs";
 const String kOptionOpenInTabs = "allow-open-in-tabs";
 const String kOptionOpenInWindows = "allow-open-in-windows";
 const String kOptionForceAlwaysRelay = "force-always-relay";
+const String kOptionNoProxy = "no-proxy";
+const String kOptionProxyDns = "proxy-dns";
 
 const String kUniLinksPrefix = "rustdesk://";
 const String kUrlActionClose = "close";
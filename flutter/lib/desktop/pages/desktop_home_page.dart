@@ -36,6 +36,9 @@ const borderColor = Color(0xFF2F65BA);
 class _DesktopHomePageState extends State<DesktopHomePage>
     with AutomaticKeepAliveClientMixin {
   final _leftPaneScrollController = ScrollController();
+  static const _kRestoreSessionsProgress = 'restore_sessions_progress';
+  static const _kWolResult = 'wol_result';
+  static const _kBackgroundSessionFinished = 'background_session_finished';
 
   @override
   bool get wantKeepAlive => true;
@@ -643,6 +646,7 @@ class _DesktopHomePageState extends State<DesktopHomePage>
           isTcpTunneling: call.arguments['isTcpTunneling'],
           isRDP: call.arguments['isRDP'],
           forceRelay: call.arguments['forceRelay'],
+          viewOnly: call.arguments['viewOnly'],
         );
       } else if (call.method == kWindowEventMoveTabToNewWindow) {
         final args = call.arguments.split(',');
@@ -668,6 +672,113 @@ class _DesktopHomePageState extends State<DesktopHomePage>
       }
     });
     _uniLinksSubscription = listenUniLinks();
+    platformFFI.registerEventHandler(
+        _kRestoreSessionsProgress, _kRestoreSessionsProgress, (evt) async {
+      _onRestoreSessionsProgress(evt);
+    });
+    platformFFI.registerEventHandler(_kWolResult, _kWolResult, (evt) async {
+      _onWolResult(evt);
+    });
+    platformFFI.registerEventHandler(
+        _kBackgroundSessionFinished, _kBackgroundSessionFinished, (evt) async {
+      _onBackgroundSessionFinished(evt);
+    });
+    WidgetsBinding.instance.addPostFrameCallback((_) => _checkRestoreSessions());
+  }
+
+  void _checkRestoreSessions() {
+    final List<dynamic> restorable;
+    try {
+      restorable = jsonDecode(bind.mainGetRestorableSessions());
+    } catch (e) {
+      debugPrint('Failed to decode the sessions to restore: $e');
+      return;
+    }
+    if (restorable.isEmpty) return;
+    final ids = restorable.map((e) => e['peer_id'] as String).join(', ');
+    gFFI.dialogManager.show((setState, close, context) {
+      submit() {
+        bind.mainRestoreSessions();
+        close();
+      }
+
+      cancel() {
+        bind.mainDiscardRestorableSessions();
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: Text(translate('Restore previous sessions')),
+        content: Text('${translate('restore_sessions_tip')}\n\n$ids'),
+        actions: [
+          dialogButton('Cancel', onPressed: cancel, isOutline: true),
+          dialogButton('OK', onPressed: submit),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+  }
+
+  void _onRestoreSessionsProgress(Map<String, dynamic> evt) {
+    final peerId = evt['peer_id'] ?? '';
+    switch (evt['state']) {
+      case 'open':
+        connect(context, peerId, forceRelay: evt['force_relay'] == 'true');
+        break;
+      case 'offline':
+        showToast('${translate('restore_session_offline_tip')}: $peerId');
+        break;
+      case 'timeout':
+        showToast('${translate('restore_session_timeout_tip')}: $peerId');
+        break;
+    }
+  }
+
+  // The transfers kept in the background after closing the window are done.
+  void _onBackgroundSessionFinished(Map<String, dynamic> evt) {
+    final peerId = evt['peer_id'] ?? '';
+    final isFileTransfer = evt['conn_type'] == 'FILE_TRANSFER';
+    final text = evt['reason'] == 'done'
+        ? 'background_session_done_tip'
+        : 'background_session_disconnected_tip';
+    gFFI.dialogManager.show((setState, close, context) {
+      reopen() {
+        close();
+        connect(context, peerId, isFileTransfer: isFileTransfer);
+      }
+
+      return CustomAlertDialog(
+        title: Text(peerId),
+        content: Text(translate(text)),
+        actions: [
+          dialogButton('Reopen', onPressed: reopen, isOutline: true),
+          dialogButton('OK', onPressed: close),
+        ],
+        onSubmit: close,
+        onCancel: close,
+      );
+    });
+  }
+
+  void _onWolResult(Map<String, dynamic> evt) {
+    final target = evt['target_id']?.isNotEmpty == true
+        ? evt['target_id']
+        : evt['mac'] ?? '';
+    switch (evt['state']) {
+      case 'sent':
+        showToast('${translate('wol_sent_tip')}: $target');
+        break;
+      case 'failed':
+        showToast('${translate(evt['error'] ?? '')}: $target');
+        break;
+      case 'online':
+        showToast('${translate('wol_online_tip')}: $target');
+        break;
+      case 'timeout':
+        showToast('${translate('wol_timeout_tip')}: $target');
+        break;
+    }
   }
 
   @override
@@ -675,6 +786,11 @@ class _DesktopHomePageState extends State<DesktopHomePage>
     _uniLinksSubscription?.cancel();
     Get.delete<RxBool>(tag: 'stop-service');
     _updateTimer?.cancel();
+    platformFFI.unregisterEventHandler(
+        _kRestoreSessionsProgress, _kRestoreSessionsProgress);
+    platformFFI.unregisterEventHandler(_kWolResult, _kWolResult);
+    platformFFI.unregisterEventHandler(
+        _kBackgroundSessionFinished, _kBackgroundSessionFinished);
     super.dispose();
   }
 
@@ -323,7 +323,12 @@ class _GeneralState extends State<_General> {
       _OptionCheckBox(context, 'Confirm before closing multiple tabs',
           'enable-confirm-closing-tabs',
           isServer: false),
+      _OptionCheckBox(context, 'Restore sessions after restart',
+          'allow-restore-sessions',
+          isServer: false),
       _OptionCheckBox(context, 'Adaptive bitrate', 'enable-abr'),
+      _OptionCheckBox(
+          context, 'Lower the frame rate when busy', 'allow-load-guard'),
       wallpaper(),
       _OptionCheckBox(
         context,
@@ -634,6 +639,19 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
           children: [
             _OptionCheckBox(context, 'Enable keyboard/mouse', 'enable-keyboard',
                 enabled: enabled, fakeValue: fakeValue),
+            _ComboBox(
+                keys: ['', 'no-system-keys', 'text-only'],
+                values: [
+                  translate('keyboard_level_full'),
+                  translate('keyboard_level_no-system-keys'),
+                  translate('keyboard_level_text-only'),
+                ],
+                enabled: enabled,
+                initialKey: bind.mainGetOptionSync(key: 'keyboard-level'),
+                onChanged: (level) async {
+                  await bind.mainSetOption(key: 'keyboard-level', value: level);
+                  setState(() {});
+                }).marginOnly(left: _kContentHSubMargin),
             _OptionCheckBox(context, 'Enable clipboard', 'enable-clipboard',
                 enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(
@@ -649,6 +667,21 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
             _OptionCheckBox(
                 context, 'Enable recording session', 'enable-record-session',
                 enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Enable remote system information',
+                'enable-remote-sysinfo',
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable remote printer', 'enable-remote-printer',
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable remote annotation', 'enable-remote-annotation',
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable remote Wake-on-LAN', 'enable-remote-wol',
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Enable remote volume control',
+                'enable-remote-volume',
+                enabled: enabled, fakeValue: fakeValue),
             if (Platform.isWindows)
               _OptionCheckBox(
                   context, 'Enable blocking user input', 'enable-block-input',
@@ -777,10 +810,72 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
           reverse: true, enabled: enabled),
       ...directIp(context),
       whitelist(),
+      ...twoFactor(context, enabled),
+      ...peerAclError(context, enabled),
       ...autoDisconnect(context),
     ]);
   }
 
+  // The stored peer acl rules are invalid, all the peers are denied.
+  List<Widget> peerAclError(BuildContext context, bool enabled) {
+    String? error;
+    try {
+      error = jsonDecode(bind.mainGetPeerAcls())['error'];
+    } catch (_) {}
+    if (error == null) return [];
+    return [
+      Row(
+        children: [
+          const Icon(Icons.warning_amber_rounded,
+                  color: Color.fromARGB(255, 255, 204, 0))
+              .marginOnly(right: 5),
+          Expanded(
+              child: Tooltip(
+            message: error,
+            child: Text(translate('peer_acl_invalid_tip'),
+                style: TextStyle(color: _disabledTextColor(context, enabled))),
+          )),
+        ],
+      ).marginOnly(left: _kContentHMargin),
+      _SubButton('Reset', () async {
+        await bind.mainResetPeerAcls();
+        setState(() {});
+      }, enabled),
+    ];
+  }
+
+  List<Widget> twoFactor(BuildContext context, bool enabled) {
+    final has2fa = bind.mainHas2Fa();
+    update() => setState(() {});
+    onChanged(bool? checked) async {
+      if (checked == true) {
+        setup2faDialog(update);
+      } else {
+        disable2faDialog(update);
+      }
+    }
+
+    return [
+      GestureDetector(
+        child: Row(
+          children: [
+            Checkbox(value: has2fa, onChanged: enabled ? onChanged : null)
+                .marginOnly(right: 5),
+            Expanded(
+                child: Text(
+              translate('Enable 2FA'),
+              style: TextStyle(color: _disabledTextColor(context, enabled)),
+            ))
+          ],
+        ),
+        onTap: enabled ? () => onChanged(!has2fa) : null,
+      ).marginOnly(left: _kCheckBoxLeftMargin),
+      if (has2fa)
+        _SubButton('Generate recovery codes', generate2faRecoveryCodesDialog,
+            enabled),
+    ];
+  }
+
   shareRdp(BuildContext context, bool enabled) {
     onChanged(bool b) async {
       await bind.mainSetShareRdp(enable: b);
@@ -2043,7 +2138,11 @@ void changeSocks5Proxy() async {
   var proxyController = TextEditingController(text: proxy);
   var userController = TextEditingController(text: username);
   var pwdController = TextEditingController(text: password);
+  var noProxyController = TextEditingController(
+      text: await bind.mainGetOption(key: kOptionNoProxy));
   RxBool obscure = true.obs;
+  RxBool localDns =
+      (await bind.mainGetOption(key: kOptionProxyDns) == 'local').obs;
 
   var isInProgress = false;
   gFFI.dialogManager.show((setState, close, context) {
@@ -2063,7 +2162,7 @@ void changeSocks5Proxy() async {
       password = pwdController.text.trim();
 
       if (proxy.isNotEmpty) {
-        proxyMsg = translate(await bind.mainTestIfValidServer(server: proxy));
+        proxyMsg = translate(await bind.mainTestIfValidProxy(proxy: proxy));
         if (proxyMsg.isEmpty) {
           // ignore
         } else {
@@ -2073,6 +2172,10 @@ void changeSocks5Proxy() async {
       }
       await bind.mainSetSocks(
           proxy: proxy, username: username, password: password);
+      await bind.mainSetOption(
+          key: kOptionNoProxy, value: noProxyController.text.trim());
+      await bind.mainSetOption(
+          key: kOptionProxyDns, value: localDns.value ? 'local' : '');
       close();
     }
 
@@ -2094,6 +2197,7 @@ void changeSocks5Proxy() async {
                 Expanded(
                   child: TextField(
                     decoration: InputDecoration(
+                        hintText: 'socks5://host:port, http://host:port',
                         errorText: proxyMsg.isNotEmpty ? proxyMsg : null),
                     controller: proxyController,
                     autofocus: true,
@@ -2137,7 +2241,31 @@ void changeSocks5Proxy() async {
                       )),
                 ),
               ],
+            ).marginOnly(bottom: 8),
+            Row(
+              children: [
+                ConstrainedBox(
+                    constraints: const BoxConstraints(minWidth: 140),
+                    child: Text(
+                      '${translate("No proxy for")}:',
+                      textAlign: TextAlign.right,
+                    ).marginOnly(right: 10)),
+                Expanded(
+                  child: TextField(
+                    decoration: const InputDecoration(
+                        hintText: '<local>, 10.0.0.0/8, .example.com'),
+                    controller: noProxyController,
+                  ),
+                ),
+              ],
             ),
+            Obx(() => CheckboxListTile(
+                  contentPadding: EdgeInsets.zero,
+                  controlAffinity: ListTileControlAffinity.leading,
+                  value: localDns.value,
+                  onChanged: (v) => localDns.value = v == true,
+                  title: Text(translate('Resolve host names locally')),
+                )),
             // NOT use Offstage to wrap LinearProgressIndicator
             if (isInProgress)
               const LinearProgressIndicator().marginOnly(top: 8),
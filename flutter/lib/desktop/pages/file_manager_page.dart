@@ -214,7 +214,7 @@ class _FileManagerPageState extends State<FileManagerPage>
                               Offstage(
                                 offstage: item.state != JobState.inProgress,
                                 child: Text(
-                                  '${translate("Speed")} ${readableFileSize(item.speed)}/s',
+                                  '${translate("Speed")} ${readableFileSize(item.speed)}/s${item.streams > 1 ? ' (${item.streams} ${translate("Streams")})' : ''}',
                                   style: TextStyle(
                                     fontSize: 12,
                                     color: MyTheme.darkGray,
@@ -739,11 +739,33 @@ class _FileManagerViewState extends State<FileManagerView> {
                       color: Theme.of(context).cardColor,
                       hoverColor: Theme.of(context).hoverColor,
                     ),
+                    Obx(() => MenuButton(
+                          onPressed: selectedItems.items.length == 1 &&
+                                  controller.isSupportFileOps
+                              ? () async {
+                                  await controller
+                                      .renameDialog(selectedItems.items.first);
+                                  selectedItems.clear();
+                                }
+                              : null,
+                          child: Icon(
+                            Icons.drive_file_rename_outline,
+                            size: 18,
+                            color: Theme.of(context).tabBarTheme.labelColor,
+                          ),
+                          color: Theme.of(context).cardColor,
+                          hoverColor: Theme.of(context).hoverColor,
+                        )),
                     Obx(() => MenuButton(
                           onPressed: SelectedItems.valid(selectedItems.items)
                               ? () async {
-                                  await (controller
-                                      .removeAction(selectedItems));
+                                  if (controller.isSupportFileOps) {
+                                    await controller
+                                        .deleteAction(selectedItems);
+                                  } else {
+                                    await (controller
+                                        .removeAction(selectedItems));
+                                  }
                                   selectedItems.clear();
                                 }
                               : null,
@@ -5,6 +5,7 @@ import 'package:desktop_multi_window/desktop_multi_window.dart';
 import 'package:flutter/material.dart';
 import 'package:flutter_hbb/common.dart';
 import 'package:flutter_hbb/consts.dart';
+import 'package:flutter_hbb/models/model.dart';
 import 'package:flutter_hbb/models/state_model.dart';
 import 'package:flutter_hbb/desktop/pages/file_manager_page.dart';
 import 'package:flutter_hbb/desktop/widgets/tabbar_widget.dart';
@@ -124,6 +125,12 @@ class _FileManagerTabPageState extends State<FileManagerTabPage> {
   Future<bool> handleWindowCloseButton() async {
     final connLength = tabController.state.value.tabs.length;
     if (connLength <= 1) {
+      if (connLength == 1) {
+        final tag = 'ft_${tabController.state.value.tabs[0].key}';
+        final ffi =
+            Get.isRegistered<FFI>(tag: tag) ? Get.find<FFI>(tag: tag) : null;
+        if (!await closeActivityConfirmDialog(ffi)) return false;
+      }
       tabController.clear();
       return true;
     } else {
@@ -45,6 +45,7 @@ class RemotePage extends StatefulWidget {
     required this.tabController,
     this.switchUuid,
     this.forceRelay,
+    this.viewOnly,
   }) : super(key: key);
 
   final String id;
@@ -56,6 +57,7 @@ class RemotePage extends StatefulWidget {
   final ToolbarState toolbarState;
   final String? switchUuid;
   final bool? forceRelay;
+  final bool? viewOnly;
   final SimpleWrapper<State<RemotePage>?> _lastState = SimpleWrapper(null);
   final DesktopTabController tabController;
 
@@ -114,6 +116,7 @@ class _RemotePageState extends State<RemotePage>
       password: widget.password,
       switchUuid: widget.switchUuid,
       forceRelay: widget.forceRelay,
+      viewOnly: widget.viewOnly ?? false,
       tabWindowId: widget.tabWindowId,
       display: widget.display,
       displays: widget.displays,
@@ -668,21 +671,26 @@ class _ImagePaintState extends State<ImagePaint> {
     }
     final curDisplay = ffiModel.pi.currentDisplay;
     for (var i = 0; i < displays.length; i++) {
-      final textureId = widget
-          .renderTextures[curDisplay == kAllDisplayValue ? i : curDisplay]
-          ?.textureId;
+      final display = curDisplay == kAllDisplayValue ? i : curDisplay;
+      final textureId = widget.renderTextures[display]?.textureId;
       if (textureId != null) {
-        children.add(Positioned(
-          left: (displays[i].x - rect.left) * s + offset.dx,
-          top: (displays[i].y - rect.top) * s + offset.dy,
-          width: displays[i].width * s,
-          height: displays[i].height * s,
-          child: Obx(() => Texture(
-                textureId: textureId.value,
-                filterQuality:
-                    isViewOriginal ? FilterQuality.none : FilterQuality.low,
-              )),
-        ));
+        // The frames may be of a region of the display, see "frame_viewports".
+        children.add(Obx(() {
+          final region = ffiModel.frameViewports[display] ??
+              Rect.fromLTWH(0, 0, displays[i].width.toDouble(),
+                  displays[i].height.toDouble());
+          return Positioned(
+            left: (displays[i].x + region.left - rect.left) * s + offset.dx,
+            top: (displays[i].y + region.top - rect.top) * s + offset.dy,
+            width: region.width * s,
+            height: region.height * s,
+            child: Obx(() => Texture(
+                  textureId: textureId.value,
+                  filterQuality:
+                      isViewOriginal ? FilterQuality.none : FilterQuality.low,
+                )),
+          );
+        }));
       }
     }
     return SizedBox(
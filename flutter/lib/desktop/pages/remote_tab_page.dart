@@ -8,6 +8,7 @@ import 'package:flutter/material.dart';
 import 'package:flutter_hbb/common.dart';
 import 'package:flutter_hbb/common/shared_state.dart';
 import 'package:flutter_hbb/consts.dart';
+import 'package:flutter_hbb/models/model.dart';
 import 'package:flutter_hbb/models/state_model.dart';
 import 'package:flutter_hbb/desktop/pages/remote_page.dart';
 import 'package:flutter_hbb/desktop/widgets/remote_toolbar.dart';
@@ -95,6 +96,7 @@ class _ConnectionTabPageState extends State<ConnectionTabPage> {
           tabController: tabController,
           switchUuid: params['switch_uuid'],
           forceRelay: params['forceRelay'],
+          viewOnly: params['viewOnly'],
         ),
       ));
       _update_remote_count();
@@ -153,6 +155,7 @@ class _ConnectionTabPageState extends State<ConnectionTabPage> {
             tabController: tabController,
             switchUuid: switchUuid,
             forceRelay: args['forceRelay'],
+            viewOnly: args['viewOnly'],
           ),
         ));
       } else if (call.method == kWindowDisableGrabKeyboard) {
@@ -269,6 +272,10 @@ class _ConnectionTabPageState extends State<ConnectionTabPage> {
                 } else {
                   msgConn = translate("Relayed and unencrypted connection");
                 }
+                final relay = connectionType.relay.value;
+                if (!direct && relay.isNotEmpty) {
+                  msgConn += '\n${translate('Relay Server')}: $relay';
+                }
                 var msgFingerprint = '${translate('Fingerprint')}:\n';
                 var fingerprint = FingerprintState.find(key).value;
                 if (fingerprint.isEmpty) {
@@ -479,6 +486,12 @@ class _ConnectionTabPageState extends State<ConnectionTabPage> {
   Future<bool> handleWindowCloseButton() async {
     final connLength = tabController.length;
     if (connLength <= 1) {
+      if (connLength == 1) {
+        final tag = tabController.state.value.tabs[0].key;
+        final ffi =
+            Get.isRegistered<FFI>(tag: tag) ? Get.find<FFI>(tag: tag) : null;
+        if (!await closeActivityConfirmDialog(ffi)) return false;
+      }
       tabController.clear();
       return true;
     } else {
@@ -471,7 +471,25 @@ class _CmHeaderState extends State<_CmHeader>
                           ),
                           style: TextStyle(color: Colors.white),
                         ),
-                      )
+                      ),
+                    if (client.authorized && client.sessionRecorded)
+                      Tooltip(
+                        message: translate("Session recorded"),
+                        child: Icon(
+                          Icons.fiber_manual_record,
+                          color: Colors.red,
+                          size: 14,
+                        ),
+                      ).marginOnly(left: 8.0),
+                    if (client.authorized && client.viewOnly)
+                      Tooltip(
+                        message: translate("View-only"),
+                        child: Icon(
+                          Icons.visibility_rounded,
+                          color: Colors.white,
+                          size: 14,
+                        ),
+                      ).marginOnly(left: 8.0),
                   ],
                 ))
               ],
@@ -592,7 +610,10 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
                       client.keyboard = enabled;
                     });
                   },
-                  translate('Enable keyboard/mouse'),
+                  client.keyboardLevel == 'full'
+                      ? translate('Enable keyboard/mouse')
+                      : '${translate('Enable keyboard/mouse')} '
+                          '(${translate('keyboard_level_${client.keyboardLevel}')})',
                 ),
                 buildPermissionIcon(
                   client.clipboard,
@@ -654,6 +675,18 @@ class _PrivilegeBoardState extends State<_PrivilegeBoard> {
                   },
                   translate('Enable recording session'),
                 ),
+                buildPermissionIcon(
+                  client.annotation,
+                  Icons.draw_rounded,
+                  (enabled) {
+                    bind.cmSwitchPermission(
+                        connId: client.id, name: "annotation", enabled: enabled);
+                    setState(() {
+                      client.annotation = enabled;
+                    });
+                  },
+                  translate('Enable remote annotation'),
+                ),
                 // only windows support block input
                 if (Platform.isWindows)
                   buildPermissionIcon(
@@ -752,6 +785,77 @@ class _CmControlPanel extends StatelessWidget {
             ],
           ),
         ),
+        Offstage(
+          offstage: !client.viewOnlyOffRequested,
+          child: Column(
+            children: [
+              Text(translate('view_only_off_request_tip'))
+                  .marginOnly(bottom: buttonBottomMargin),
+              Row(
+                children: [
+                  Expanded(
+                    child: buildButton(context,
+                        color: MyTheme.accent,
+                        onClick: () => answerViewOnlyOff(true),
+                        icon: Icon(
+                          Icons.keyboard_rounded,
+                          color: Colors.white,
+                          size: 14,
+                        ),
+                        text: "Accept",
+                        textColor: Colors.white),
+                  ),
+                  Expanded(
+                    child: buildButton(
+                      context,
+                      color: Colors.red,
+                      onClick: () => answerViewOnlyOff(false),
+                      icon: Icon(
+                        Icons.visibility_rounded,
+                        color: Colors.white,
+                        size: 14,
+                      ),
+                      text: "Dismiss",
+                      textColor: Colors.white,
+                    ),
+                  )
+                ],
+              ),
+            ],
+          ),
+        ),
+        // The picture-in-picture of the frames sent to the controller.
+        Offstage(
+          offstage: client.type_() != ClientType.remote,
+          child: Obx(() => Column(
+                children: [
+                  if (client.watchFrame.value != null)
+                    Tooltip(
+                      message:
+                          '${translate('Display')} ${client.watchDisplay.value + 1}',
+                      child: Image.memory(
+                        client.watchFrame.value!,
+                        fit: BoxFit.contain,
+                        gaplessPlayback: true,
+                      ),
+                    ).marginOnly(bottom: 4),
+                  buildButton(
+                    context,
+                    color: MyTheme.accent,
+                    onClick: () => model.toggleWatch(client),
+                    icon: Icon(
+                      client.watching.value
+                          ? Icons.visibility_off_rounded
+                          : Icons.visibility_rounded,
+                      color: Colors.white,
+                      size: 14,
+                    ),
+                    text: client.watching.value ? 'Stop watching' : 'Watch',
+                    textColor: Colors.white,
+                  ),
+                ],
+              )),
+        ),
         Offstage(
           offstage: !client.fromSwitch,
           child: buildButton(context,
@@ -958,6 +1062,10 @@ class _CmControlPanel extends StatelessWidget {
   void closeVoiceCall() {
     bind.cmCloseVoiceCall(id: client.id);
   }
+
+  void answerViewOnlyOff(bool accept) {
+    bind.cmAnswerViewOnlyOff(connId: client.id, accept: accept);
+  }
 }
 
 void checkClickTime(int id, Function() callback) async {
@@ -1034,6 +1142,16 @@ class __FileTransferLogPageState extends State<_FileTransferLogPage> {
             Text(translate('Create Folder'))
           ],
         );
+      case CmFileAction.rename:
+        return Column(
+          children: [
+            Icon(
+              Icons.drive_file_rename_outline,
+              color: Theme.of(context).tabBarTheme.labelColor,
+            ),
+            Text(translate('Rename'))
+          ],
+        );
     }
   }
 
@@ -1287,7 +1287,16 @@ class _ResolutionsMenuState extends State<_ResolutionsMenu> {
     if (lastGroupValue == _kCustomResolutionValue) {
       _groupValue = _kCustomResolutionValue;
     } else {
-      _groupValue = '${rect?.width.toInt()}x${rect?.height.toInt()}';
+      final w = rect?.width.toInt();
+      final h = rect?.height.toInt();
+      final rate = pi.currentDisplay < pi.displays.length
+          ? pi.displays[pi.currentDisplay].refreshRate
+          : 0;
+      final cur = _resolutionValue(Resolution(w ?? 0, h ?? 0, rate));
+      // The peer may list the sizes only.
+      _groupValue = resolutions.any((r) => _resolutionValue(r) == cur)
+          ? cur
+          : '${w}x$h';
     }
   }
 
@@ -1338,25 +1347,38 @@ class _ResolutionsMenuState extends State<_ResolutionsMenu> {
 
     int? w;
     int? h;
+    int refreshRate = 0;
     if (value == _kCustomResolutionValue) {
       w = int.tryParse(_customWidth.text);
       h = int.tryParse(_customHeight.text);
     } else {
-      final list = value.split('x');
+      // "WxH" or "WxH@rate".
+      final parts = value.split('@');
+      final list = parts[0].split('x');
       if (list.length == 2) {
         w = int.tryParse(list[0]);
         h = int.tryParse(list[1]);
       }
+      if (parts.length == 2) {
+        refreshRate = int.tryParse(parts[1]) ?? 0;
+      }
     }
 
     if (w != null && h != null) {
-      if (w != rect?.width.toInt() || h != rect?.height.toInt()) {
-        await _changeResolution(context, w, h);
+      final curRate = pi.currentDisplay < pi.displays.length
+          ? pi.displays[pi.currentDisplay].refreshRate
+          : 0;
+      if (w != rect?.width.toInt() ||
+          h != rect?.height.toInt() ||
+          (refreshRate > 0 && refreshRate != curRate)) {
+        await _changeResolution(context, w, h, refreshRate: refreshRate);
       }
     }
   }
 
-  _changeResolution(BuildContext context, int w, int h) async {
+  /// [refreshRate] is 0 to let the peer choose it.
+  _changeResolution(BuildContext context, int w, int h,
+      {int refreshRate = 0}) async {
     if (pi.currentDisplay == kAllDisplayValue) {
       return;
     }
@@ -1365,6 +1387,7 @@ class _ResolutionsMenuState extends State<_ResolutionsMenu> {
       display: pi.currentDisplay,
       width: w,
       height: h,
+      refreshRate: refreshRate,
     );
     Future.delayed(Duration(seconds: 3), () async {
       final rect = ffiModel.rect;
@@ -1458,13 +1481,19 @@ class _ResolutionsMenuState extends State<_ResolutionsMenu> {
     );
   }
 
+  String _resolutionValue(Resolution r) => r.refreshRate > 0
+      ? '${r.width}x${r.height}@${r.refreshRate}'
+      : '${r.width}x${r.height}';
+
   List<Widget> _supportedResolutionMenuButtons() => resolutions
       .map((e) => RdoMenuButton(
-          value: '${e.width}x${e.height}',
+          value: _resolutionValue(e),
           groupValue: _groupValue,
           onChanged: (String? value) => _onChanged(context, value),
           ffi: widget.ffi,
-          child: Text('${e.width}x${e.height}')))
+          child: Text(e.refreshRate > 0
+              ? '${e.width}x${e.height} @ ${e.refreshRate}Hz'
+              : '${e.width}x${e.height}')))
       .toList();
 
   Resolution? _getBestFitResolution() {
@@ -1605,6 +1634,7 @@ class _KeyboardMenu extends StatelessWidget {
         hoverColor: _ToolbarTheme.hoverBlueColor,
         menuChildren: [
           keyboardMode(modeOnly),
+          inputPolicy(),
           localKeyboardType(),
           inputSource(),
           Divider(),
@@ -1662,6 +1692,30 @@ class _KeyboardMenu extends StatelessWidget {
     });
   }
 
+  inputPolicy() {
+    return Obx(() {
+      final policy = ffi.ffiModel.inputPolicy;
+      final level = policy['level'] ?? 'full';
+      final blocked = policy['blocked'] ?? '';
+      if (level == 'full' && blocked.isEmpty) return Offstage();
+      var text = '${translate('Keyboard restricted')}: '
+          '${translate('keyboard_level_$level')}';
+      if (blocked.isNotEmpty) {
+        text += ', $blocked';
+      }
+      final dropped = policy['dropped'] ?? '0';
+      if (dropped != '0') {
+        text += ' (${translate('Keys dropped')}: $dropped)';
+      }
+      return Column(
+        children: [
+          Divider(),
+          MenuButton(child: Text(text), onPressed: null, ffi: ffi),
+        ],
+      );
+    });
+  }
+
   localKeyboardType() {
     final localPlatform = getLocalPlatformForKBLayoutType(pi.platform);
     final visible = localPlatform != '';
@@ -1726,6 +1780,13 @@ class _KeyboardMenu extends StatelessWidget {
         onChanged: enabled
             ? (value) async {
                 if (value == null) return;
+                if (ffiModel.viewOnlySession) {
+                  // The input is allowed after the peer confirms.
+                  if (!value) {
+                    bind.sessionRequestViewOnlyOff(sessionId: ffi.sessionId);
+                  }
+                  return;
+                }
                 await bind.sessionToggleOption(
                     sessionId: ffi.sessionId, value: 'view-only');
                 ffiModel.setViewOnly(id, value);
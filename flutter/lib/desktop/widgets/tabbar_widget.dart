@@ -1,4 +1,5 @@
 import 'dart:async';
+import 'dart:convert';
 import 'dart:io';
 import 'dart:math';
 import 'dart:ui' as ui;
@@ -11,6 +12,7 @@ import 'package:flutter_hbb/common.dart';
 import 'package:flutter_hbb/consts.dart';
 import 'package:flutter_hbb/desktop/pages/remote_page.dart';
 import 'package:flutter_hbb/main.dart';
+import 'package:flutter_hbb/models/model.dart';
 import 'package:flutter_hbb/models/platform_model.dart';
 import 'package:flutter_hbb/models/state_model.dart';
 import 'package:flutter_svg/flutter_svg.dart';
@@ -811,6 +813,51 @@ Future<bool> closeConfirmDialog() async {
   return res == true;
 }
 
+/// Ask before closing the last window of the peer while the transfers, the
+/// recording or the voice call are active. The transfers may be kept in the
+/// background, see [FFI.closeInBackground]. Returns false if canceled.
+Future<bool> closeActivityConfirmDialog(FFI? ffi) async {
+  if (ffi == null) return true;
+  final json = bind.sessionHasBlockingActivity(sessionId: ffi.sessionId);
+  if (json.isEmpty) return true;
+  final activity = jsonDecode(json);
+  if (activity['blocking'] != true) return true;
+  final transfers = (activity['transfers'] as List).length;
+  final recording = (activity['recording'] as List).isNotEmpty;
+  final voiceCall = (activity['voice_call'] as String).isNotEmpty;
+  final res = await gFFI.dialogManager.show<String>((setState, close, context) {
+    return CustomAlertDialog(
+      title: Row(children: [
+        const Icon(Icons.warning_amber_sharp,
+            color: Colors.redAccent, size: 28),
+        const SizedBox(width: 10),
+        Text(translate("Warning")),
+      ]),
+      content: Column(
+          mainAxisAlignment: MainAxisAlignment.start,
+          crossAxisAlignment: CrossAxisAlignment.start,
+          children: [
+            if (transfers > 0)
+              Text('${translate('close_active_transfers_tip')} ($transfers)'),
+            if (recording) Text(translate('close_active_recording_tip')),
+            if (voiceCall) Text(translate('close_active_voice_call_tip')),
+          ]),
+      actions: [
+        dialogButton("Cancel", onPressed: close, isOutline: true),
+        if (transfers > 0)
+          dialogButton("Keep transfers in background",
+              onPressed: () => close('background'), isOutline: true),
+        dialogButton("Close", onPressed: () => close('close')),
+      ],
+      onCancel: close,
+    );
+  });
+  if (res == 'background') {
+    ffi.closeInBackground = true;
+  }
+  return res == 'background' || res == 'close';
+}
+
 class _ListView extends StatelessWidget {
   final DesktopTabController controller;
 
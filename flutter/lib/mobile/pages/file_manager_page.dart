@@ -278,8 +278,13 @@ class _FileManagerPageState extends State<FileManagerPage> {
                   onPressed: selectedItems != null
                       ? () async {
                           if (selectedItems.items.isNotEmpty) {
-                            await currentFileController
-                                .removeAction(selectedItems);
+                            if (currentFileController.isSupportFileOps) {
+                              await currentFileController
+                                  .deleteAction(selectedItems);
+                            } else {
+                              await currentFileController
+                                  .removeAction(selectedItems);
+                            }
                             selectedItems.items.clear();
                             selectMode.value = SelectMode.none;
                           }
@@ -482,6 +487,11 @@ class _FileManagerViewState extends State<FileManagerView> {
                                   child: Text(translate("Delete")),
                                   value: "delete",
                                 ),
+                                PopupMenuItem(
+                                  child: Text(translate("Rename")),
+                                  value: "rename",
+                                  enabled: controller.isSupportFileOps,
+                                ),
                                 PopupMenuItem(
                                   child: Text(translate("Multi Select")),
                                   value: "multi_select",
@@ -497,7 +507,13 @@ class _FileManagerViewState extends State<FileManagerView> {
                               if (v == "delete") {
                                 final items = SelectedItems(isLocal: isLocal);
                                 items.add(entries[index]);
-                                controller.removeAction(items);
+                                if (controller.isSupportFileOps) {
+                                  controller.deleteAction(items);
+                                } else {
+                                  controller.removeAction(items);
+                                }
+                              } else if (v == "rename") {
+                                controller.renameDialog(entries[index]);
                               } else if (v == "multi_select") {
                                 _selectedItems.clear();
                                 widget.selectMode.toggle(isLocal);
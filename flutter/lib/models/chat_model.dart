@@ -450,13 +450,43 @@ class ChatModel with ChangeNotifier {
     if (_currentKey.connId == clientModeID && parent.target != null) {
       bind.sessionSendChat(sessionId: sessionId, text: message.text);
     } else {
-      bind.cmSendChat(connId: _currentKey.connId, msg: message.text);
+      _cmSendMessage(_currentKey.connId, message.text);
     }
 
     notifyListeners();
     inputNode.requestFocus();
   }
 
+  /// The cm starts a conversation with the client, recorded the same as a reply.
+  void sendToClient(int connId, String text) {
+    final trimmedText = text.trim();
+    final client = parent.target?.serverModel.clients
+        .firstWhereOrNull((client) => client.id == connId);
+    if (trimmedText.isEmpty || client == null) {
+      return;
+    }
+    insertMessage(MessageKey(client.peerId, connId),
+        ChatMessage(text: trimmedText, user: me, createdAt: DateTime.now()));
+    _cmSendMessage(connId, trimmedText);
+    notifyListeners();
+  }
+
+  void _cmSendMessage(int connId, String text) async {
+    try {
+      await bind.cmSendMessage(connId: connId, text: text);
+    } catch (e) {
+      showToast('${translate('Failed to send the message')}: $e');
+    }
+  }
+
+  /// The result of the message sent by `cmSendMessage`.
+  void onChatDelivery(Map<String, dynamic> evt) {
+    final error = evt['error'] ?? '';
+    if (error.isNotEmpty) {
+      showToast('${translate('Failed to send the message')}: $error');
+    }
+  }
+
   insertMessage(MessageKey key, ChatMessage message) {
     updateConnIdOfKey(key);
     if (!_messages.containsKey(key)) {
@@ -33,6 +33,8 @@ class CmFileModel {
       _onFileRemove(evt['remove']);
     } else if (evt['create_dir'] != null) {
       _onDirCreate(evt['create_dir']);
+    } else if (evt['rename'] != null) {
+      _onFileRename(evt['rename']);
     }
   }
 
@@ -190,6 +192,34 @@ class CmFileModel {
       debugPrint('$e');
     }
   }
+
+  _onFileRename(dynamic log) {
+    try {
+      dynamic d = jsonDecode(log);
+      FileRenameLog data = FileRenameLog.fromJson(d);
+      Client? client =
+          gFFI.serverModel.clients.firstWhereOrNull((e) => e.id == data.connId);
+      var jobTable = _jobTables[data.connId];
+      if (jobTable == null) {
+        debugPrint("jobTable should not be null");
+        return;
+      }
+      jobTable.add(CmFileLog()
+        ..id = data.id
+        ..fileName = "${data.path} -> ${data.newName}"
+        ..action = CmFileAction.rename
+        ..state = JobState.done);
+      final currentSelectedTab =
+          gFFI.serverModel.tabController.state.value.selectedTabInfo;
+      if (!(gFFI.chatModel.isShowCMSidePage &&
+          currentSelectedTab.key == data.connId.toString())) {
+        client?.unreadChatMessageCount.value += 1;
+      }
+      jobTable.refresh();
+    } catch (e) {
+      debugPrint('$e');
+    }
+  }
 }
 
 enum CmFileAction {
@@ -198,6 +228,7 @@ enum CmFileAction {
   localToRemote,
   remove,
   createDir,
+  rename,
 }
 
 class CmFileLog {
@@ -285,3 +316,25 @@ class FileActionLog {
           dir: d['dir'] ?? false,
         );
 }
+
+class FileRenameLog {
+  int id = 0;
+  int connId = 0;
+  String path = '';
+  String newName = '';
+
+  FileRenameLog({
+    required this.connId,
+    required this.id,
+    required this.path,
+    required this.newName,
+  });
+
+  FileRenameLog.fromJson(dynamic d)
+      : this(
+          connId: d['connId'] ?? 0,
+          id: d['id'] ?? 0,
+          path: d['path'] ?? '',
+          newName: d['newName'] ?? '',
+        );
+}
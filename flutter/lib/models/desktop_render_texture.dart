@@ -9,6 +9,7 @@ final useTextureRender = bind.mainUseTextureRender();
 class RenderTexture {
   final RxInt textureId = RxInt(-1);
   int _textureKey = -1;
+  int _texturePtr = 0;
   int _display = 0;
   SessionID? _sessionId;
 
@@ -27,6 +28,7 @@ class RenderTexture {
       textureRenderer.createTexture(_textureKey).then((id) async {
         if (id != -1) {
           final ptr = await textureRenderer.getTexturePtr(_textureKey);
+          _texturePtr = ptr;
           platformFFI.registerTexture(sessionId, display, ptr);
           textureId.value = id;
         }
@@ -38,9 +40,12 @@ class RenderTexture {
     if (useTextureRender && _textureKey != -1 && _sessionId != null) {
       if (unregisterTexture) {
         platformFFI.registerTexture(_sessionId!, display, 0);
+        // The frame being rendered into the texture must be done before it's closed.
+        await bind.waitTextureReleased(ptr: _texturePtr);
       }
       await textureRenderer.closeTexture(_textureKey);
       _textureKey = -1;
+      _texturePtr = 0;
     }
   }
 }
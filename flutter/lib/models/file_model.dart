@@ -87,6 +87,10 @@ class FileModel {
     await remoteController.refresh();
   }
 
+  // The side of the rename or remove in place.
+  FileController fileOpController(Map<String, dynamic> evt) =>
+      evt['is_remote'] == 'true' ? remoteController : localController;
+
   void receiveFileDir(Map<String, dynamic> evt) {
     if (evt['is_local'] == "false") {
       // init remote home, the remote connection will send one dir event when established. TODO opt
@@ -643,11 +647,173 @@ class FileController {
         path: path,
         isRemote: !isLocal);
   }
+
+  // The renames and removes in place waiting for the result, by the id,
+  // completed with the error, empty if done.
+  final _fileOps = <int, Completer<String>>{};
+  // The progress of the recursive remove running, null if unknown.
+  final fileOpProgressValue = Rx<double?>(null);
+
+  bool get isSupportFileOps =>
+      isLocal || (rootState.target?.ffiModel.pi.isSupportFileOps ?? false);
+
+  void fileOpProgress(Map<String, dynamic> evt) {
+    final removed = int.tryParse(evt['removed'] ?? '') ?? 0;
+    final total = int.tryParse(evt['total'] ?? '') ?? 0;
+    fileOpProgressValue.value = total > 0 ? removed / total : null;
+  }
+
+  void fileOpResult(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '') ?? 0;
+    _fileOps.remove(id)?.complete(evt['err'] ?? '');
+  }
+
+  Future<String> _runFileOp(int id, void Function() start) {
+    final completer = Completer<String>();
+    _fileOps[id] = completer;
+    start();
+    return completer.future;
+  }
+
+  void _showFileOpError(String err) {
+    dialogManager?.show((setState, close, context) {
+      return CustomAlertDialog(
+        title: Text(translate("Error")),
+        content: Text(translate(err)),
+        actions: [
+          dialogButton("OK", onPressed: close),
+        ],
+        onSubmit: close,
+        onCancel: close,
+      );
+    });
+  }
+
+  /// Rename the entry in place, the listing is refreshed after.
+  Future<void> renameAction(Entry item, String newName) async {
+    if (newName.isEmpty || newName == item.name) {
+      return;
+    }
+    final id = JobController.jobID.next();
+    final err = await _runFileOp(
+        id,
+        () => bind.sessionRenameFile(
+            sessionId: sessionId,
+            actId: id,
+            path: item.path,
+            newName: newName,
+            isRemote: !isLocal));
+    if (err.isNotEmpty) {
+      _showFileOpError(err);
+    }
+    refresh();
+  }
+
+  Future<void> renameDialog(Entry item) async {
+    final name = TextEditingController(text: item.name);
+    final newName = await dialogManager?.show<String>(
+        (setState, Function(String? v) close, context) {
+      submit() => close(name.value.text.trim());
+      cancel() => close(null);
+      return CustomAlertDialog(
+        title: Text(translate("Rename")),
+        content: TextFormField(
+          decoration: InputDecoration(
+            labelText: translate("Please enter the new name"),
+          ),
+          controller: name,
+          autofocus: true,
+        ),
+        actions: [
+          dialogButton(
+            "Cancel",
+            icon: Icon(Icons.close_rounded),
+            onPressed: cancel,
+            isOutline: true,
+          ),
+          dialogButton(
+            "OK",
+            icon: Icon(Icons.done_rounded),
+            onPressed: submit,
+          ),
+        ],
+        onSubmit: submit,
+        onCancel: cancel,
+      );
+    });
+    if (newName != null) {
+      await renameAction(item, newName);
+    }
+  }
+
+  /// Delete the entries in place, the directories recursively, with the
+  /// progress, canceled by the dialog.
+  Future<void> deleteAction(SelectedItems items) async {
+    if (items.isLocal != isLocal) {
+      debugPrint("Failed to delete, wrong files");
+      return;
+    }
+    final confirm = await showRemoveDialog(
+        translate("Delete"), items.items.map((e) => e.path).join("\n"), false);
+    if (confirm != true) {
+      return;
+    }
+    for (var item in items.items) {
+      final id = JobController.jobID.next();
+      final tag = "file-op-$id";
+      fileOpProgressValue.value = null;
+      cancel() => bind.sessionCancelJob(sessionId: sessionId, actId: id);
+      dialogManager?.show((setState, close, context) {
+        return CustomAlertDialog(
+          title: Text(translate("Deleting")),
+          content: Column(
+            crossAxisAlignment: CrossAxisAlignment.start,
+            children: [
+              Text(item.name),
+              Obx(() => LinearProgressIndicator(
+                    value: fileOpProgressValue.value,
+                  )).paddingOnly(top: 12),
+            ],
+          ),
+          actions: [
+            dialogButton("Cancel",
+                icon: Icon(Icons.close_rounded),
+                onPressed: cancel,
+                isOutline: true),
+          ],
+          onCancel: cancel,
+        );
+      }, tag: tag);
+      final err = await _runFileOp(
+          id,
+          () => bind.sessionDeletePath(
+              sessionId: sessionId,
+              actId: id,
+              path: item.path,
+              recursive: item.isDirectory,
+              isRemote: !isLocal));
+      dialogManager?.dismissByTag(tag);
+      if (err.isNotEmpty) {
+        // The entries removed before are not restored.
+        if (err != kFileOpCanceled) {
+          _showFileOpError(err);
+        }
+        break;
+      }
+    }
+    history.removeWhere(
+        (element) => items.items.any((e) => element.startsWith(e.path)));
+    refresh();
+  }
 }
 
 class JobController {
   static final JobID jobID = JobID();
   final jobTable = List<JobProgress>.empty(growable: true).obs;
+  // The overview of all the active jobs, null if none.
+  final summary = Rx<TransferSummary?>(null);
+  // The directories mirrored to the peer, by the sync id.
+  final syncStates = RxMap<int, SyncState>();
   final jobResultListener = JobResultListener<Map<String, dynamic>>();
   final GetSessionID getSessionID;
   SessionID get sessionId => getSessionID();
@@ -693,6 +859,68 @@ class JobController {
     }
   }
 
+  void updateSummary(Map<String, dynamic> evt) {
+    try {
+      final s = TransferSummary.fromEvent(evt);
+      summary.value = s.jobs > 0 ? s : null;
+    } catch (e) {
+      debugPrint("Failed to updateSummary, evt: ${evt.toString()}");
+    }
+  }
+
+  void updateStreams(Map<String, dynamic> evt) {
+    final jobIndex = getJob(int.parse(evt['id']));
+    if (jobIndex != -1) {
+      jobTable[jobIndex].streams = int.tryParse(evt['streams']) ?? 1;
+      jobTable.refresh();
+    }
+  }
+
+  void updateSyncState(Map<String, dynamic> evt) {
+    try {
+      final state = SyncState.fromEvent(evt);
+      if (state.state == 'stopped') {
+        syncStates.remove(state.id);
+      } else {
+        syncStates[state.id] = state;
+      }
+    } catch (e) {
+      debugPrint("Failed to updateSyncState, evt: ${evt.toString()}");
+    }
+  }
+
+  // Mirrors the local [path] to the remote [to] until stopped.
+  // [overwrite] is "skip", "ask" or "" (overwrite).
+  int startSync(String path, String to,
+      {bool delete = false, String overwrite = ""}) {
+    final id = JobController.jobID.next();
+    bind.sessionStartSync(
+        sessionId: sessionId,
+        actId: id,
+        path: path,
+        to: to,
+        delete: delete,
+        overwrite: overwrite);
+    return id;
+  }
+
+  Future<void> stopSync(int id) async {
+    await bind.sessionStopSync(sessionId: sessionId, actId: id);
+  }
+
+  void jobRenamedFiles(Map<String, dynamic> evt) {
+    try {
+      final List<dynamic> files = json.decode(evt['files']);
+      if (files.isEmpty) return;
+      final first = files.first;
+      final more = files.length > 1 ? ' (+${files.length - 1})' : '';
+      showToast(
+          '${translate('job_renamed_files_tip')}: ${first['from']} -> ${first['to']}$more');
+    } catch (e) {
+      debugPrint("Failed to jobRenamedFiles, evt: ${evt.toString()}");
+    }
+  }
+
   void jobDone(Map<String, dynamic> evt) async {
     if (jobResultListener.isListening) {
       jobResultListener.complete(evt);
@@ -740,6 +968,7 @@ class JobController {
     bool showHidden = jobDetail['show_hidden'];
     int fileNum = jobDetail['file_num'];
     bool isRemote = jobDetail['is_remote'];
+    int streams = jobDetail['streams'] ?? 1;
     final currJobId = JobController.jobID.next();
     String fileName = path.basename(isRemote ? remote : to);
     var jobProgress = JobProgress()
@@ -751,6 +980,7 @@ class JobController {
       ..remote = remote
       ..to = to
       ..showHidden = showHidden
+      ..streams = streams
       ..state = JobState.paused;
     jobTable.add(jobProgress);
     bind.sessionAddJob(
@@ -1017,6 +1247,35 @@ extension JobStateDisplay on JobState {
   }
 }
 
+class SyncState {
+  final int id;
+  // idle, scanning, syncing, error or stopped
+  final String state;
+  final int files;
+  final String error;
+
+  SyncState.fromEvent(Map<String, dynamic> evt)
+      : id = int.parse(evt['id']),
+        state = evt['state'],
+        files = int.tryParse(evt['files'] ?? '') ?? 0,
+        error = evt['error'] ?? '';
+}
+
+class TransferSummary {
+  final int jobs;
+  // bytes per second
+  final double speed;
+  final int remaining;
+  // seconds, null if unknown
+  final int? eta;
+
+  TransferSummary.fromEvent(Map<String, dynamic> evt)
+      : jobs = int.parse(evt['jobs']),
+        speed = double.parse(evt['speed']),
+        remaining = int.parse(evt['remaining']),
+        eta = int.tryParse(evt['eta'] ?? '');
+}
+
 class JobProgress {
   JobState state = JobState.none;
   var id = 0;
@@ -1036,6 +1295,8 @@ class JobProgress {
   var showHidden = false;
   var err = "";
   int lastTransferredSize = 0;
+  // The streams the large files are split into.
+  var streams = 1;
 
   clear() {
     state = JobState.none;
@@ -1049,6 +1310,7 @@ class JobProgress {
     remote = "";
     to = "";
     err = "";
+    streams = 1;
   }
 
   String display() {
@@ -31,6 +31,7 @@ import 'package:flutter_custom_cursor/cursor_manager.dart';
 import 'package:flutter_svg/flutter_svg.dart';
 import 'package:get/get.dart';
 import 'package:uuid/uuid.dart';
+import 'package:url_launcher/url_launcher.dart';
 import 'package:window_manager/window_manager.dart';
 
 import '../common.dart';
@@ -98,12 +99,35 @@ class FfiModel with ChangeNotifier {
   Timer? _timer;
   var _reconnects = 1;
   bool _viewOnly = false;
+  // No input is sent until the peer allows it, see `FFI.start`.
+  bool _viewOnlySession = false;
   WeakReference<FFI> parent;
   late final SessionID sessionId;
 
   RxBool waitForImageDialogShow = true.obs;
   Timer? waitForImageTimer;
   RxBool waitForFirstImage = true.obs;
+  // "active", "locked" or "display_off", shown as a banner over the remote image.
+  RxString hostDisplayState = 'active'.obs;
+  // The displays whose frames stopped coming, display index -> seconds since the last frame.
+  RxMap<int, int> videoStalled = <int, int>{}.obs;
+  // The activity shown on the tab, see "session_activity".
+  RxMap<String, String> sessionActivity = <String, String>{}.obs;
+  // The local clipboard can be read for the peer, mobile only, see "text_clipboard_required".
+  RxBool textClipboardRequired = true.obs;
+  // The keyboard restrictions of the host: level, blocked and dropped, see "input_policy".
+  RxMap<String, String> inputPolicy = <String, String>{}.obs;
+  // The alias, color and revision shared by the windows of the peer, see "session_meta_changed".
+  // The notes are fetched by `sessionGetMeta`.
+  RxMap<String, String> sessionMeta = <String, String>{}.obs;
+  // The fps percent, cpu and encode load of the host, see "host_throttled".
+  RxMap<String, String> hostThrottled = <String, String>{}.obs;
+  // The regions of the displays in the frames, display index -> region relative to the display,
+  // the displays not in the map are whole, see "frame_viewports".
+  RxMap<int, Rect> frameViewports = <int, Rect>{}.obs;
+  // Attached to the connected session of another window, whose login is reused, see
+  // `sessionAddSync`. Cleared by the next message box, e.g. of a reconnection.
+  bool reusingConnection = false;
 
   Rect? get rect => _rect;
   bool get isOriginalResolutionSet =>
@@ -128,6 +152,7 @@ class FfiModel with ChangeNotifier {
   bool get isPeerAndroid => _pi.platform == kPeerPlatformAndroid;
 
   bool get viewOnly => _viewOnly;
+  bool get viewOnlySession => _viewOnlySession;
 
   set inputBlocked(v) {
     _inputBlocked = v;
@@ -235,7 +260,7 @@ class FfiModel with ChangeNotifier {
     }
     if (data.lastCursorId.isNotEmpty) {
       updateLastCursorId(data.lastCursorId);
-      handleCursorId(data.lastCursorId);
+      await handleCursorId(data.lastCursorId);
     }
   }
 
@@ -249,11 +274,61 @@ class FfiModel with ChangeNotifier {
         handlePeerInfo(evt, peerId, false);
       } else if (name == 'sync_peer_info') {
         handleSyncPeerInfo(evt, sessionId, peerId);
+      } else if (name == 'displays_changed') {
+        handleDisplaysChanged(evt, sessionId, peerId);
       } else if (name == 'sync_platform_additions') {
         handlePlatformAdditions(evt, sessionId, peerId);
       } else if (name == 'connection_ready') {
         setConnectionType(
             peerId, evt['secure'] == 'true', evt['direct'] == 'true');
+      } else if (name == 'relay_server') {
+        try {
+          ConnectionTypeState.find(peerId).setRelay(evt['relay'] ?? '');
+        } catch (e) {
+          //
+        }
+      } else if (name == 'print_job_progress') {
+        if (evt['received'] == '0') {
+          showToast(translate('print_job_receiving_tip'));
+        }
+      } else if (name == 'print_job_received') {
+        showPrintJobReceivedDialog(
+            sessionId, evt['name'] ?? '', evt['path'] ?? '', dialogManager);
+      } else if (name == 'print_job_failed') {
+        showToast('${translate('print_job_failed_tip')}: ${evt['error']}');
+      } else if (name == 'staging_evicted') {
+        handleStagingEvicted(evt);
+      } else if (name == 'remote_volume_changed') {
+        handleRemoteVolumeChanged(evt, peerId);
+      } else if (name == 'relay_fallback') {
+        showToast(translate('relay_fallback_tip'),
+            timeout: const Duration(seconds: 6));
+      } else if (name == 'color_mode_fallback') {
+        showToast(
+            '${translate('color_mode_fallback_${evt['reason']}_tip')}'
+            ' (${translate('Display')} ${int.parse(evt['display']) + 1})');
+      } else if (name == 'session_panicked') {
+        handleSessionPanicked(evt, sessionId);
+      } else if (name == 'peer_data_truncated') {
+        showToast(
+            '${translate('peer_data_truncated_tip')}: ${evt['field'] ?? ''}');
+      } else if (name == 'fingerprint_mismatch') {
+        final dialogManager = parent.target?.dialogManager;
+        if (dialogManager != null) {
+          showFingerprintMismatchDialog(sessionId, evt['type'], evt['title'],
+              evt['text'], evt['old'], evt['new'], dialogManager);
+        }
+      } else if (name == 'update_view_only') {
+        _viewOnlySession = evt['view_only'] == 'true';
+        setViewOnly(
+            peerId,
+            _viewOnlySession ||
+                bind.sessionGetToggleOptionSync(
+                    sessionId: sessionId, arg: 'view-only'));
+      } else if (name == 'view_only_blocked') {
+        showToast(translate('view_only_blocked_tip'));
+      } else if (name == 'texture_replaced') {
+        handleTextureReplaced(evt, sessionId);
       } else if (name == 'switch_display') {
         // switch display is kept for backward compatibility
         handleSwitchDisplay(evt, sessionId, peerId);
@@ -262,11 +337,22 @@ class FfiModel with ChangeNotifier {
         await handleCursorData(evt);
       } else if (name == 'cursor_id') {
         updateLastCursorId(evt);
-        handleCursorId(evt);
+        await handleCursorId(evt);
       } else if (name == 'cursor_position') {
         await parent.target?.cursorModel.updateCursorPosition(evt, peerId);
       } else if (name == 'clipboard') {
         Clipboard.setData(ClipboardData(text: evt['content']));
+      } else if (name == 'capture_reduced') {
+        showToast(translate('capture_reduced_tip'),
+            timeout: const Duration(seconds: 6));
+      } else if (name == 'capture_restored') {
+        if (evt['reason'] != 'user') {
+          showToast(translate('capture_restored_tip'));
+        }
+      } else if (name == 'clipboard_truncated') {
+        showToast(translate('clipboard_truncated_tip'));
+      } else if (name == 'text_clipboard_required') {
+        textClipboardRequired.value = evt['required'] == 'true';
       } else if (name == 'permission') {
         updatePermission(evt, peerId);
       } else if (name == 'chat_client_mode') {
@@ -279,11 +365,23 @@ class FfiModel with ChangeNotifier {
         parent.target?.fileModel.receiveFileDir(evt);
       } else if (name == 'job_progress') {
         parent.target?.fileModel.jobController.tryUpdateJobProgress(evt);
+      } else if (name == 'transfer_summary') {
+        parent.target?.fileModel.jobController.updateSummary(evt);
+      } else if (name == 'job_streams') {
+        parent.target?.fileModel.jobController.updateStreams(evt);
+      } else if (name == 'sync_state') {
+        parent.target?.fileModel.jobController.updateSyncState(evt);
+      } else if (name == 'job_renamed_files') {
+        parent.target?.fileModel.jobController.jobRenamedFiles(evt);
       } else if (name == 'job_done') {
         parent.target?.fileModel.jobController.jobDone(evt);
         parent.target?.fileModel.refreshAll();
       } else if (name == 'job_error') {
         parent.target?.fileModel.jobController.jobError(evt);
+      } else if (name == 'file_op_progress') {
+        parent.target?.fileModel.fileOpController(evt).fileOpProgress(evt);
+      } else if (name == 'file_op_done' || name == 'file_op_error') {
+        parent.target?.fileModel.fileOpController(evt).fileOpResult(evt);
       } else if (name == 'override_file_confirm') {
         parent.target?.fileModel.postOverrideFileConfirm(evt);
       } else if (name == 'load_last_job') {
@@ -296,6 +394,12 @@ class FfiModel with ChangeNotifier {
         parent.target?.serverModel.onClientRemove(evt);
       } else if (name == 'update_quality_status') {
         parent.target?.qualityMonitorModel.updateQualityStatus(evt);
+      } else if (name == 'decoder_info') {
+        parent.target?.qualityMonitorModel.updateDecoderInfo(evt);
+      } else if (name == 'input_policy') {
+        inputPolicy.value = evt.map((k, v) => MapEntry(k, v.toString()));
+      } else if (name == 'annotation_ack') {
+        parent.target?.annotationModel.onAck(evt);
       } else if (name == 'update_block_input_state') {
         updateBlockInputState(evt, peerId);
       } else if (name == 'update_privacy_mode') {
@@ -305,10 +409,39 @@ class FfiModel with ChangeNotifier {
         parent.target?.serverModel.setShowElevation(show);
       } else if (name == 'cancel_msgbox') {
         cancelMsgBox(evt, sessionId);
-      } else if (name == 'switch_back') {
-        final peer_id = evt['peer_id'].toString();
-        await bind.sessionSwitchSides(sessionId: sessionId);
-        closeConnection(id: peer_id);
+      } else if (name == 'switch_back_state') {
+        // "completed" and "failed" are pushed by `sessionSwitchSides`.
+        if (evt['state'] == 'initiated') {
+          final peer_id = evt['peer_id'].toString();
+          await bind.sessionSwitchSides(sessionId: sessionId);
+          closeConnection(id: peer_id);
+        }
+      } else if (name == 'host_display_state') {
+        hostDisplayState.value = evt['state'] ?? 'active';
+      } else if (name == 'video_stalled') {
+        videoStalled[int.parse(evt['display'])] = int.parse(evt['secs']);
+      } else if (name == 'video_recovered') {
+        videoStalled.remove(int.parse(evt['display']));
+      } else if (name == 'host_throttled') {
+        evt.remove('name');
+        if (evt['fps_percent'] == '100') {
+          hostThrottled.clear();
+        } else {
+          hostThrottled.value = evt.map((k, v) => MapEntry(k, v.toString()));
+        }
+      } else if (name == 'frame_viewports') {
+        _handleFrameViewports(evt);
+      } else if (name == 'session_meta_changed') {
+        evt.remove('name');
+        final revision = int.tryParse(evt['revision'] ?? '') ?? 0;
+        final current = int.tryParse(sessionMeta['revision'] ?? '') ?? 0;
+        // The events may come out of order with the revision returned by `sessionSetMeta`.
+        if (revision >= current) {
+          sessionMeta.value = evt.map((k, v) => MapEntry(k, v.toString()));
+        }
+      } else if (name == 'session_activity') {
+        evt.remove('name');
+        sessionActivity.value = evt.map((k, v) => MapEntry(k, v.toString()));
       } else if (name == 'portable_service_running') {
         _handlePortableServiceRunning(peerId, evt);
       } else if (name == 'on_url_scheme_received') {
@@ -329,6 +462,18 @@ class FfiModel with ChangeNotifier {
         parent.target?.chatModel.onVoiceCallIncoming();
       } else if (name == 'update_voice_call_state') {
         parent.target?.serverModel.updateVoiceCallState(evt);
+      } else if (name == 'update_view_only_state') {
+        parent.target?.serverModel.updateViewOnlyState(evt);
+      } else if (name == 'update_permission_state') {
+        parent.target?.serverModel.updatePermissionState(evt);
+      } else if (name == 'update_session_recording_state') {
+        parent.target?.serverModel.updateSessionRecordingState(evt);
+      } else if (name == 'update_permission_grant') {
+        parent.target?.serverModel.updatePermissionGrant(evt);
+      } else if (name == 'cm_chat_delivery') {
+        parent.target?.chatModel.onChatDelivery(evt);
+      } else if (name == 'cm_watch_frame') {
+        parent.target?.serverModel.onWatchFrame(evt);
       } else if (name == 'fingerprint') {
         FingerprintState.find(peerId).value = evt['fingerprint'] ?? '';
       } else if (name == 'plugin_manager') {
@@ -351,6 +496,10 @@ class FfiModel with ChangeNotifier {
             }
           }
         }
+      } else if (name == 'cm_notify') {
+        parent.target?.serverModel.onCmNotify(evt);
+      } else if (name == 'service_call_result') {
+        parent.target?.serverModel.onServiceCallResult(evt);
       } else if (name == "cm_file_transfer_log") {
         if (isDesktop) {
           gFFI.cmFileModel.onFileTransferLog(evt);
@@ -385,6 +534,23 @@ class FfiModel with ChangeNotifier {
     platformFFI.setEventCallback(startEventListener(sessionId, peerId));
   }
 
+  _handleFrameViewports(Map<String, dynamic> evt) {
+    final viewports = <int, Rect>{};
+    try {
+      final Map<String, dynamic> m = json.decode(evt['viewports']);
+      m.forEach((k, v) {
+        viewports[int.parse(k)] = Rect.fromLTWH(
+            (v['x'] as num).toDouble(),
+            (v['y'] as num).toDouble(),
+            (v['width'] as num).toDouble(),
+            (v['height'] as num).toDouble());
+      });
+    } catch (e) {
+      debugPrint('Failed to parse frame viewports: $e');
+    }
+    frameViewports.value = viewports;
+  }
+
   _handlePortableServiceRunning(String peerId, Map<String, dynamic> evt) {
     final running = evt['running'] == 'true';
     parent.target?.elevationModel.onPortableServiceRunning(running);
@@ -460,6 +626,8 @@ class FfiModel with ChangeNotifier {
         int.tryParse(evt['original_width']) ?? kInvalidResolutionValue;
     newDisplay.originalHeight =
         int.tryParse(evt['original_height']) ?? kInvalidResolutionValue;
+    newDisplay.refreshRate = int.tryParse(evt['refresh_rate'] ?? '') ?? 0;
+    newDisplay.scale = double.tryParse(evt['scale'] ?? '') ?? 0;
     _pi.displays[display] = newDisplay;
 
     if (!_pi.isSupportMultiUiSession || _pi.currentDisplay == display) {
@@ -494,12 +662,26 @@ class FfiModel with ChangeNotifier {
     final dialogManager = parent.target!.dialogManager;
     final type = evt['type'];
     final title = evt['title'];
-    final text = evt['text'];
+    var text = evt['text'];
     final link = evt['link'];
+    if (evt['keyed'] == 'true') {
+      // The text is a lang key, translated with the params.
+      text = translate(text);
+      final Map<String, dynamic> params = jsonDecode(evt['params'] ?? '{}');
+      params.forEach((k, v) => text = text.replaceAll('{$k}', v.toString()));
+    }
+    if (reusingConnection) {
+      reusingConnection = false;
+      // Answered by the window connecting the session.
+      if (type == 'input-password' || type == 're-input-password') return;
+      if (type == 'input-2fa') return;
+    }
     if (type == 're-input-password') {
       wrongPasswordDialog(sessionId, dialogManager, type, title, text);
     } else if (type == 'input-password') {
       enterPasswordDialog(sessionId, dialogManager);
+    } else if (type == 'input-2fa') {
+      enter2faDialog(sessionId, dialogManager, type, title);
     } else if (type == 'session-login' || type == 'session-re-login') {
       enterUserLoginDialog(sessionId, dialogManager);
     } else if (type == 'session-login-password' ||
@@ -526,6 +708,29 @@ class FfiModel with ChangeNotifier {
     }
   }
 
+  /// The texture of a display is re-created, e.g. during resizing.
+  /// Request a fresh frame, the peer may not send one until the screen changes.
+  handleTextureReplaced(Map<String, dynamic> evt, SessionID sessionId) {
+    final display = int.tryParse(evt['display'] ?? '');
+    if (display != null && display >= 0 && display < pi.displays.length) {
+      bind.sessionRefresh(sessionId: sessionId, display: display);
+    } else {
+      // The composite texture of all displays.
+      sessionRefreshVideo(sessionId, pi);
+    }
+  }
+
+  /// The session is closed by a panic of the core, the message and the backtrace hash are shown
+  /// for reporting.
+  handleSessionPanicked(Map<String, dynamic> evt, SessionID sessionId) {
+    if (parent.target == null) return;
+    final dialogManager = parent.target!.dialogManager;
+    final text =
+        '${translate(evt['text'])}\n\n${evt['message'] ?? ''} (${evt['backtrace'] ?? ''})';
+    showMsgBox(sessionId, evt['type'], evt['title'], text, '', false,
+        dialogManager);
+  }
+
   /// Show a message box with [type], [title] and [text].
   showMsgBox(SessionID sessionId, String type, String title, String text,
       String link, bool hasRetry, OverlayDialogManager dialogManager,
@@ -554,6 +759,54 @@ class FfiModel with ChangeNotifier {
         onCancel: closeConnection);
   }
 
+  /// The job printed on the remote is saved locally, it's opened by the default app of the OS
+  /// to be printed.
+  void handleRemoteVolumeChanged(Map<String, dynamic> evt, String peerId) {
+    final state = evt['state'] ?? '';
+    try {
+      RemoteVolumeState.find(peerId).update(state,
+          int.tryParse(evt['percent'] ?? '') ?? 0, evt['muted'] == 'true');
+    } catch (e) {
+      //
+    }
+    if (state == 'unsupported' || state == 'denied') {
+      showToast(translate('remote_volume_${state}_tip'));
+    }
+  }
+
+  void handleStagingEvicted(Map<String, dynamic> evt) {
+    try {
+      final List<dynamic> files = jsonDecode(evt['files']);
+      if (files.isEmpty) return;
+      showToast('${translate('staging_evicted_tip')} (${files.length})');
+    } catch (e) {
+      debugPrint('Failed to handle staging_evicted: $e');
+    }
+  }
+
+  void showPrintJobReceivedDialog(SessionID sessionId, String name,
+      String path, OverlayDialogManager dialogManager) {
+    dialogManager.show(tag: '$sessionId-print-job-$path',
+        (setState, close, context) {
+      onPrint() {
+        launchUrl(Uri.file(path));
+        close();
+      }
+
+      return CustomAlertDialog(
+        title: null,
+        content: msgboxContent('info', 'Print job received',
+            '${translate('print_job_received_tip')}\n\n$name'),
+        actions: [
+          dialogButton('Close', onPressed: close, isOutline: true),
+          dialogButton('Print', onPressed: onPrint),
+        ],
+        onSubmit: onPrint,
+        onCancel: close,
+      );
+    });
+  }
+
   void showRelayHintDialog(SessionID sessionId, String type, String title,
       String text, OverlayDialogManager dialogManager, String peerId) {
     dialogManager.show(tag: '$sessionId-$type', (setState, close, context) {
@@ -708,10 +961,12 @@ class FfiModel with ChangeNotifier {
       parent.target?.elevationModel.onPeerInfo(_pi);
     }
     if (connType == ConnType.defaultConn) {
+      _viewOnlySession = bind.sessionIsViewOnly(sessionId: sessionId);
       setViewOnly(
           peerId,
-          bind.sessionGetToggleOptionSync(
-              sessionId: sessionId, arg: 'view-only'));
+          _viewOnlySession ||
+              bind.sessionGetToggleOptionSync(
+                  sessionId: sessionId, arg: 'view-only'));
     }
     if (connType == ConnType.defaultConn) {
       final platformAdditions = evt['platform_additions'];
@@ -833,15 +1088,19 @@ class FfiModel with ChangeNotifier {
       for (int i = 0; i < dynamicArray.length; i++) {
         var width = dynamicArray[i]["width"];
         var height = dynamicArray[i]["height"];
+        var refreshRate = dynamicArray[i]["refresh_rate"];
         if (width is int && width > 0 && height is int && height > 0) {
-          arr.add(Resolution(width, height));
+          arr.add(Resolution(
+              width, height, refreshRate is int ? refreshRate : 0));
         }
       }
       arr.sort((a, b) {
         if (b.width != a.width) {
           return b.width - a.width;
-        } else {
+        } else if (b.height != a.height) {
           return b.height - a.height;
+        } else {
+          return b.refreshRate - a.refreshRate;
         }
       });
       _pi.resolutions = arr;
@@ -859,6 +1118,8 @@ class FfiModel with ChangeNotifier {
     d.cursorEmbedded = evt['cursor_embedded'] == 1;
     d.originalWidth = evt['original_width'] ?? kInvalidResolutionValue;
     d.originalHeight = evt['original_height'] ?? kInvalidResolutionValue;
+    d.refreshRate = evt['refresh_rate'] ?? 0;
+    d.scale = evt['scale']?.toDouble() ?? 0;
     return d;
   }
 
@@ -866,9 +1127,19 @@ class FfiModel with ChangeNotifier {
     parent.target?.cursorModel.id = int.parse(evt['id']);
   }
 
-  handleCursorId(Map<String, dynamic> evt) {
+  handleCursorId(Map<String, dynamic> evt) async {
     cachedPeerData.lastCursorId = evt;
-    parent.target?.cursorModel.updateCursorId(evt);
+    final cursorModel = parent.target?.cursorModel;
+    if (cursorModel == null) return;
+    final id = int.parse(evt['id']);
+    if (!cursorModel.isCached(id)) {
+      // The cursor data may be sent to another ui session, e.g. a new window.
+      final data = bind.sessionGetCachedCursor(sessionId: sessionId, id: id);
+      if (data.isNotEmpty) {
+        await handleCursorData(Map<String, dynamic>.from(json.decode(data)));
+      }
+    }
+    cursorModel.updateCursorId(evt);
   }
 
   handleCursorData(Map<String, dynamic> evt) async {
@@ -882,48 +1153,83 @@ class FfiModel with ChangeNotifier {
     if (evt['displays'] != null) {
       cachedPeerData.peerInfo['displays'] = evt['displays'];
       List<dynamic> displays = json.decode(evt['displays']);
-      List<Display> newDisplays = [];
-      for (int i = 0; i < displays.length; ++i) {
-        newDisplays.add(evtToDisplay(displays[i]));
+      _updateDisplays(displays, sessionId, peerId);
+    }
+    notifyListeners();
+  }
+
+  /// Handle the changed displays by index, only the [evt] 'added', 'removed'
+  /// and 'modified' displays are sent.
+  handleDisplaysChanged(
+      Map<String, dynamic> evt, SessionID sessionId, String peerId) async {
+    final List<dynamic> displays =
+        json.decode(cachedPeerData.peerInfo['displays'] ?? '[]');
+    final List<dynamic> removed = json.decode(evt['removed'] ?? '[]');
+    if (removed.isNotEmpty) {
+      // Only the trailing displays are removed.
+      final minRemoved = removed.cast<int>().reduce(min);
+      if (minRemoved < displays.length) {
+        displays.removeRange(minRemoved, displays.length);
       }
-      _pi.displays.value = newDisplays;
-      _pi.displaysCount.value = _pi.displays.length;
+    }
+    for (final key in ['modified', 'added']) {
+      final List<dynamic> changed = json.decode(evt[key] ?? '[]');
+      for (final d in changed) {
+        final index = d['index'] as int;
+        if (index < displays.length) {
+          displays[index] = d;
+        } else if (index == displays.length) {
+          displays.add(d);
+        } else {
+          debugPrint('Unreachable, the display $index is not contiguous');
+        }
+      }
+    }
+    cachedPeerData.peerInfo['displays'] = jsonEncode(displays);
+    _updateDisplays(displays, sessionId, peerId);
+    notifyListeners();
+  }
 
-      if (_pi.currentDisplay == kAllDisplayValue) {
+  _updateDisplays(List<dynamic> displays, SessionID sessionId, String peerId) {
+    List<Display> newDisplays = [];
+    for (int i = 0; i < displays.length; ++i) {
+      newDisplays.add(evtToDisplay(displays[i]));
+    }
+    _pi.displays.value = newDisplays;
+    _pi.displaysCount.value = _pi.displays.length;
+
+    if (_pi.currentDisplay == kAllDisplayValue) {
+      updateCurDisplay(sessionId);
+      // to-do: What if the displays are changed?
+    } else {
+      if (_pi.currentDisplay >= 0 && _pi.currentDisplay < _pi.displays.length) {
         updateCurDisplay(sessionId);
-        // to-do: What if the displays are changed?
       } else {
-        if (_pi.currentDisplay >= 0 &&
-            _pi.currentDisplay < _pi.displays.length) {
-          updateCurDisplay(sessionId);
-        } else {
-          if (_pi.displays.isNotEmpty) {
-            // Notify to switch display
-            msgBox(sessionId, 'custom-nook-nocancel-hasclose-info', 'Prompt',
-                'display_is_plugged_out_msg', '', parent.target!.dialogManager);
-            final newDisplay = pi.primaryDisplay == kInvalidDisplayIndex
-                ? 0
-                : pi.primaryDisplay;
-            final displays = newDisplay;
-            bind.sessionSwitchDisplay(
-              isDesktop: isDesktop,
-              sessionId: sessionId,
-              value: Int32List.fromList([displays]),
-            );
-
-            if (_pi.isSupportMultiUiSession) {
-              // If the peer supports multi-ui-session, no switch display message will be send back.
-              // We need to update the display manually.
-              switchToNewDisplay(newDisplay, sessionId, peerId);
-            }
-          } else {
-            msgBox(sessionId, 'nocancel-error', 'Prompt', 'No Displays', '',
-                parent.target!.dialogManager);
+        if (_pi.displays.isNotEmpty) {
+          // Notify to switch display
+          msgBox(sessionId, 'custom-nook-nocancel-hasclose-info', 'Prompt',
+              'display_is_plugged_out_msg', '', parent.target!.dialogManager);
+          final newDisplay = pi.primaryDisplay == kInvalidDisplayIndex
+              ? 0
+              : pi.primaryDisplay;
+          final displays = newDisplay;
+          bind.sessionSwitchDisplay(
+            isDesktop: isDesktop,
+            sessionId: sessionId,
+            value: Int32List.fromList([displays]),
+          );
+
+          if (_pi.isSupportMultiUiSession) {
+            // If the peer supports multi-ui-session, no switch display message will be send back.
+            // We need to update the display manually.
+            switchToNewDisplay(newDisplay, sessionId, peerId);
           }
+        } else {
+          msgBox(sessionId, 'nocancel-error', 'Prompt', 'No Displays', '',
+              parent.target!.dialogManager);
         }
       }
     }
-    notifyListeners();
   }
 
   handlePlatformAdditions(
@@ -1209,6 +1515,7 @@ class CanvasModel with ChangeNotifier {
   final ScrollController _vertical = ScrollController();
 
   final _imageOverflow = false.obs;
+  Timer? _viewportTimer;
 
   WeakReference<FFI> parent;
 
@@ -1431,6 +1738,48 @@ class CanvasModel with ChangeNotifier {
     if (notify) notifyListeners();
   }
 
+  @override
+  void notifyListeners() {
+    super.notifyListeners();
+    _viewportTimer?.cancel();
+    _viewportTimer = Timer(const Duration(milliseconds: 300), _requestViewport);
+  }
+
+  @override
+  void dispose() {
+    _viewportTimer?.cancel();
+    super.dispose();
+  }
+
+  // Request the peer to capture the part of the display visible only, if zoomed in.
+  // The scroll bars move the canvas without `_x` and `_y`, the whole display is requested then.
+  _requestViewport() {
+    final ffiModel = parent.target?.ffiModel;
+    final rect = ffiModel?.rect;
+    if (ffiModel == null ||
+        rect == null ||
+        ffiModel.pi.currentDisplay == kAllDisplayValue ||
+        _scale <= 0) {
+      return;
+    }
+    var visible = Rect.fromLTWH(-_x / _scale, -_y / _scale,
+            size.width / _scale, size.height / _scale)
+        .intersect(Rect.fromLTWH(0, 0, rect.width, rect.height));
+    if (_scrollStyle == ScrollStyle.scrollbar ||
+        visible.isEmpty ||
+        (visible.width >= rect.width && visible.height >= rect.height)) {
+      visible = Rect.zero;
+    }
+    bind.sessionSetViewport(
+        sessionId: sessionId,
+        display: ffiModel.pi.currentDisplay,
+        x: visible.left.floor(),
+        y: visible.top.floor(),
+        width: visible.width.ceil(),
+        height: visible.height.ceil(),
+        scale: _scale * _devicePixelRatio);
+  }
+
   updateScrollPercent() {
     final percentX = _horizontal.hasClients
         ? _horizontal.position.extentBefore /
@@ -1840,6 +2189,8 @@ class CursorModel with ChangeNotifier {
     }
   }
 
+  bool isCached(int id) => _cacheMap.containsKey(id);
+
   updateCursorId(Map<String, dynamic> evt) {
     if (!_updateCurData()) {
       debugPrint(
@@ -1912,6 +2263,14 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  // The active decoder, hardware or software.
+  String? decoder;
+  // The scale of the frames downscaled on the device, null if not downscaled.
+  String? downscale;
+  // display index -> {fps, delay, chroma}
+  Map<String, dynamic>? displays;
+  // {capture_to_receive, receive_to_decode, decode_to_render, frames} in ms, if measured
+  Map<String, dynamic>? latencyBreakdown;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -1968,11 +2327,127 @@ class QualityMonitorModel with ChangeNotifier {
       if ((evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      final downscale = evt['downscale'] as String?;
+      _data.downscale =
+          downscale == null || downscale == '1.00' ? null : downscale;
+      final latencyBreakdown = evt['latency_breakdown'] as String?;
+      if (latencyBreakdown != null && latencyBreakdown.isNotEmpty) {
+        _data.latencyBreakdown =
+            jsonDecode(latencyBreakdown) as Map<String, dynamic>;
+      }
+      final displays = evt['displays'] as String?;
+      if (displays != null && displays.isNotEmpty) {
+        _data.displays = jsonDecode(displays) as Map<String, dynamic>;
+        final currentDisplay = parent.target?.ffiModel.pi.currentDisplay;
+        if (currentDisplay != null && currentDisplay != kAllDisplayValue) {
+          final chroma = _data.displays?[currentDisplay.toString()]?['chroma'];
+          if (chroma != null) _data.chroma = chroma;
+        }
+      }
       notifyListeners();
     } catch (e) {
       //
     }
   }
+
+  updateDecoderInfo(Map<String, dynamic> evt) {
+    final pi = parent.target?.ffiModel.pi;
+    if (pi != null &&
+        pi.currentDisplay != kAllDisplayValue &&
+        evt['display'] != pi.currentDisplay.toString()) {
+      return;
+    }
+    final adapter = evt['adapter'] as String? ?? '';
+    var decoder = evt['hardware'] == 'true'
+        ? 'HW${adapter.isEmpty ? '' : ' ($adapter)'}'
+        : 'SW';
+    final reason = evt['reason'];
+    if (reason == 'forced-software') {
+      decoder += ', ${translate('Forced')}';
+    } else if (reason == 'init-failure' || reason == 'hw-init-failure') {
+      decoder += ', ${translate('Initialization failed')}';
+    }
+    _data.decoder = decoder;
+    notifyListeners();
+  }
+}
+
+/// The strokes drawn on the screen of the host, each one acked by "annotation_ack".
+class AnnotationModel {
+  WeakReference<FFI> parent;
+
+  AnnotationModel(this.parent);
+
+  var _nextId = 1;
+
+  /// The strokes sent and not acked yet.
+  final pending = RxSet<int>();
+
+  /// The error of the strokes failing to be drawn.
+  final errors = RxMap<int, String>();
+
+  int nextId() => _nextId++;
+
+  /// [points] are the positions of the pointer events, translated to the host the same as the
+  /// mouse events. Sending the same [id] again replaces the stroke, e.g. while drawing it.
+  void send(int id, String shape, List<Offset> points,
+      {int color = 0xFFFF0000, int width = 4, int ttlMs = 0}) {
+    final ffi = parent.target;
+    if (ffi == null) return;
+    final remote = <int>[];
+    for (final p in points) {
+      final pos = ffi.inputModel.handlePointerDevicePos(
+          kPointerEventKindTouch, p.dx, p.dy, false, '');
+      if (pos != null) {
+        remote.add(pos.x.toInt());
+        remote.add(pos.y.toInt());
+      }
+    }
+    if (remote.length < 4) return;
+    pending.add(id);
+    bind.sessionSendAnnotation(
+        sessionId: ffi.sessionId,
+        id: id,
+        display: _displayOf(ffi, remote[0], remote[1]),
+        shape: shape,
+        points: Int32List.fromList(remote),
+        color: color,
+        width: width,
+        ttlMs: ttlMs);
+  }
+
+  void clear() {
+    final ffi = parent.target;
+    if (ffi == null) return;
+    pending.clear();
+    errors.clear();
+    bind.sessionClearAnnotations(sessionId: ffi.sessionId);
+  }
+
+  void onAck(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    if (id == null) return;
+    pending.remove(id);
+    final error = evt['error'] ?? '';
+    if (error.isEmpty) {
+      errors.remove(id);
+    } else {
+      errors[id] = error;
+      showToast(translate(error));
+    }
+  }
+
+  // The display containing the point, in the coordinates of the mouse events.
+  int _displayOf(FFI ffi, int x, int y) {
+    final pi = ffi.ffiModel.pi;
+    for (var i = 0; i < pi.displays.length; i++) {
+      final d = pi.displays[i];
+      if (x >= d.x && x < d.x + d.width && y >= d.y && y < d.y + d.height) {
+        return i;
+      }
+    }
+    return pi.currentDisplay == kAllDisplayValue ? 0 : pi.currentDisplay;
+  }
 }
 
 class RecordingModel with ChangeNotifier {
@@ -2074,6 +2549,10 @@ class FFI {
   var closed = false;
   var auditNote = '';
 
+  /// Keep the transfers in the background when the session is closed, if it's
+  /// the last window of the peer.
+  var closeInBackground = false;
+
   /// dialogManager use late to ensure init after main page binding [globalKey]
   late final dialogManager = OverlayDialogManager();
 
@@ -2090,6 +2569,7 @@ class FFI {
   late final UserModel userModel; // global
   late final PeerTabModel peerTabModel; // global
   late final QualityMonitorModel qualityMonitorModel; // session
+  late final AnnotationModel annotationModel; // session
   late final RecordingModel recordingModel; // session
   late final InputModel inputModel; // session
   late final ElevationModel elevationModel; // session
@@ -2109,6 +2589,7 @@ class FFI {
     abModel = AbModel(WeakReference(this));
     groupModel = GroupModel(WeakReference(this));
     qualityMonitorModel = QualityMonitorModel(WeakReference(this));
+    annotationModel = AnnotationModel(WeakReference(this));
     recordingModel = RecordingModel(WeakReference(this));
     inputModel = InputModel(WeakReference(this));
     elevationModel = ElevationModel(WeakReference(this));
@@ -2135,6 +2616,9 @@ class FFI {
     int? tabWindowId,
     int? display,
     List<int>? displays,
+    bool viewOnly = false,
+    bool audioOnly = false,
+    String? directTarget,
   }) {
     closed = false;
     auditNote = '';
@@ -2154,7 +2638,6 @@ class FFI {
     // If tabWindowId != null, this session is a "tab -> window" one.
     // Else this session is a new one.
     if (tabWindowId == null) {
-      // ignore: unused_local_variable
       final addRes = bind.sessionAddSync(
         sessionId: sessionId,
         id: id,
@@ -2164,7 +2647,22 @@ class FFI {
         switchUuid: switchUuid ?? '',
         forceRelay: forceRelay ?? false,
         password: password ?? '',
+        rdpOptions: '',
+        recordRemoteId: true,
+        viewOnly: viewOnly,
+        audioOnly: audioOnly,
+        directTarget: directTarget ?? '',
       );
+      // The json of the insertion if added, the error otherwise.
+      try {
+        final Map<String, dynamic> added = jsonDecode(addRes);
+        if (added['attached'] == true && added['state'] == 'connected') {
+          ffiModel.reusingConnection = true;
+          showToast(translate('Reusing the existing connection'));
+        }
+      } catch (e) {
+        debugPrint('Failed to add session $id: $addRes');
+      }
     } else if (display != null) {
       if (displays == null) {
         debugPrint(
@@ -2306,7 +2804,11 @@ class FFI {
     canvasModel.clear();
     inputModel.resetModifiers();
     if (closeSession) {
-      await bind.sessionClose(sessionId: sessionId);
+      if (closeInBackground) {
+        bind.sessionCloseInBackground(sessionId: sessionId);
+      } else {
+        await bind.sessionClose(sessionId: sessionId);
+      }
     }
     debugPrint('model $id closed');
     id = '';
@@ -2332,6 +2834,9 @@ class Display {
   bool cursorEmbedded = false;
   int originalWidth = kInvalidResolutionValue;
   int originalHeight = kInvalidResolutionValue;
+  // 0 if unknown.
+  int refreshRate = 0;
+  double scale = 0;
 
   Display() {
     width = (isDesktop || isWebDesktop)
@@ -2368,11 +2873,13 @@ class Display {
 class Resolution {
   int width = 0;
   int height = 0;
-  Resolution(this.width, this.height);
+  // 0 if unknown, or to let the peer choose.
+  int refreshRate = 0;
+  Resolution(this.width, this.height, [this.refreshRate = 0]);
 
   @override
   String toString() {
-    return 'Resolution($width,$height)';
+    return 'Resolution($width,$height,$refreshRate)';
   }
 }
 
@@ -2406,6 +2913,14 @@ class PeerInfo with ChangeNotifier {
       platformAdditions[kPlatformAdditionsIsInstalled] == true;
   List<int> get virtualDisplays => List<int>.from(
       platformAdditions[kPlatformAdditionsVirtualDisplays] ?? []);
+  bool get isSupportRemoteVolume =>
+      platformAdditions[kPlatformAdditionsCapabilities]?['remote_volume'] ==
+      true;
+  bool get isSupportFrameTimestamps =>
+      platformAdditions[kPlatformAdditionsCapabilities]?['frame_timestamps'] ==
+      true;
+  bool get isSupportFileOps =>
+      platformAdditions[kPlatformAdditionsCapabilities]?['file_ops'] == true;
 
   bool get isSupportMultiDisplay => isDesktop && isSupportMultiUiSession;
 
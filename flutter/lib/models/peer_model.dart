@@ -144,6 +144,7 @@ class Peers extends ChangeNotifier {
   final RxList<Peer>? initPeers;
   UpdateEvent event = UpdateEvent.load;
   static const _cbQueryOnlines = 'callback_query_onlines';
+  static const _cbPeerCardUpdated = 'peer_card_updated';
 
   Peers(
       {required this.name, required this.initPeers, required this.loadEvent}) {
@@ -154,11 +155,15 @@ class Peers extends ChangeNotifier {
     platformFFI.registerEventHandler(loadEvent, name, (evt) async {
       _updatePeers(evt);
     });
+    platformFFI.registerEventHandler(_cbPeerCardUpdated, name, (evt) async {
+      _updatePeerCard(evt);
+    });
   }
 
   @override
   void dispose() {
     platformFFI.unregisterEventHandler(_cbQueryOnlines, name);
+    platformFFI.unregisterEventHandler(_cbPeerCardUpdated, name);
     platformFFI.unregisterEventHandler(loadEvent, name);
     super.dispose();
   }
@@ -196,6 +201,23 @@ class Peers extends ChangeNotifier {
     notifyListeners();
   }
 
+  // The info of the peer is refreshed by a connection.
+  void _updatePeerCard(Map<String, dynamic> evt) {
+    final changes = evt['changes'];
+    if (changes is! Map) return;
+    var updated = false;
+    for (var peer in peers.where((p) => p.id == evt['id'])) {
+      peer.platform = changes['platform'] ?? peer.platform;
+      peer.hostname = changes['hostname'] ?? peer.hostname;
+      peer.username = changes['username'] ?? peer.username;
+      updated = true;
+    }
+    if (updated) {
+      event = UpdateEvent.load;
+      notifyListeners();
+    }
+  }
+
   void _updatePeers(Map<String, dynamic> evt) {
     final onlineStates = _getOnlineStates();
     if (initPeers != null) {
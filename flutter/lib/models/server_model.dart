@@ -3,6 +3,7 @@ import 'dart:convert';
 import 'dart:io';
 
 import 'package:flutter/material.dart';
+import 'package:flutter/services.dart';
 import 'package:flutter_hbb/consts.dart';
 import 'package:flutter_hbb/main.dart';
 import 'package:flutter_hbb/models/chat_model.dart';
@@ -428,6 +429,9 @@ class ServerModel with ChangeNotifier {
         if (value && !_isStart) {
           startService();
         }
+        if (value) {
+          bind.cmServiceReady();
+        }
         break;
       case "input":
         if (_inputOk != value) {
@@ -621,6 +625,7 @@ class ServerModel with ChangeNotifier {
             tabController.remove(index);
           } else {
             _clients[index].disconnected = true;
+            _clients[index].stopWatching();
           }
         }
         parent.target?.dialogManager.dismissByTag(getLoginDialogTag(id));
@@ -635,6 +640,32 @@ class ServerModel with ChangeNotifier {
     }
   }
 
+  // The notification of the local user, per the policy of the category.
+  // Android shows it by the service, see `cmNotification` of MainService.
+  void onCmNotify(Map<String, dynamic> evt) {
+    if (isAndroid) return;
+    final category = evt['category'] ?? '';
+    final name = evt['peer_name'] ?? '';
+    final peerId = evt['peer_id'] ?? '';
+    showToast('${translate('cm_notify_$category')}: $name - $peerId');
+    if (evt['mode'] == 'sound') {
+      SystemSound.play(SystemSoundType.alert);
+    }
+  }
+
+  // The call of the foreground service is given up, see `service_calls.rs`.
+  // The connection is still accepted by the in-app dialog.
+  void onServiceCallResult(Map<String, dynamic> evt) {
+    debugPrint("service call result: $evt");
+    if (evt['result'] != 'failed') return;
+    if (evt['method'] == 'add_connection') {
+      final id = int.tryParse(evt['id'] ?? '');
+      final client = _clients.firstWhereOrNull((c) => c.id == id);
+      if (client == null || client.authorized) return;
+      showToast(translate('service_notification_failed_tip'));
+    }
+  }
+
   Future<void> closeAll() async {
     await Future.wait(
         _clients.map((client) => bind.cmCloseConnection(connId: client.id)));
@@ -673,6 +704,104 @@ class ServerModel with ChangeNotifier {
       debugPrint("updateVoiceCallState failed: $e");
     }
   }
+
+  void updateViewOnlyState(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].viewOnly = client.viewOnly;
+        _clients[index].viewOnlyOffRequested = client.viewOnlyOffRequested;
+        if (client.viewOnlyOffRequested) {
+          Future.delayed(Duration.zero, () {
+            windowOnTop(null);
+          });
+        }
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateViewOnlyState failed: $e");
+    }
+  }
+
+  /// The permissions switched by the options of the host.
+  void updatePermissionState(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].keyboard = client.keyboard;
+        _clients[index].clipboard = client.clipboard;
+        _clients[index].audio = client.audio;
+        _clients[index].file = client.file;
+        _clients[index].restart = client.restart;
+        _clients[index].recording = client.recording;
+        _clients[index].blockInput = client.blockInput;
+        _clients[index].annotation = client.annotation;
+        _clients[index].keyboardLevel = client.keyboardLevel;
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updatePermissionState failed: $e");
+    }
+  }
+
+  /// The session is recorded by the host, or the recording is stopped, e.g. the disk is full.
+  void updateSessionRecordingState(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].sessionRecorded = client.sessionRecorded;
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateSessionRecordingState failed: $e");
+    }
+  }
+
+  /// Watch the frames sent to the controller, or stop watching.
+  Future<void> toggleWatch(Client client) async {
+    final on = !client.watching.value;
+    try {
+      await bind.cmWatch(connId: client.id, on: on);
+    } catch (e) {
+      showToast('${translate('Failed to watch the session')}: $e');
+      return;
+    }
+    if (on) {
+      client.watching.value = true;
+    } else {
+      client.stopWatching();
+    }
+  }
+
+  /// A frame sent to the controller watched by `toggleWatch`.
+  void onWatchFrame(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    final client = _clients.firstWhereOrNull((c) => c.id == id);
+    if (client == null || !client.watching.value) return;
+    try {
+      client.watchFrame.value = base64Decode(evt['data'] ?? '');
+      client.watchDisplay.value = int.tryParse(evt['display'] ?? '') ?? 0;
+    } catch (e) {
+      debugPrint('Failed to decode the watch frame: $e');
+    }
+  }
+
+  /// The countdown of the permission granted for a while, the expiry is followed by
+  /// "update_permission_state".
+  void updatePermissionGrant(Map<String, dynamic> evt) {
+    final id = int.tryParse(evt['id'] ?? '');
+    final index = _clients.indexWhere((element) => element.id == id);
+    if (index == -1) return;
+    final secs = int.tryParse(evt['secs'] ?? '') ?? 0;
+    if (secs > 0) {
+      _clients[index].permissionGrants[evt['name']] = secs;
+    } else {
+      _clients[index].permissionGrants.remove(evt['name']);
+    }
+  }
 }
 
 enum ClientType {
@@ -695,16 +824,35 @@ class Client {
   bool restart = false;
   bool recording = false;
   bool blockInput = false;
+  bool annotation = false;
+  // "full", "no-system-keys" or "text-only".
+  String keyboardLevel = 'full';
   bool disconnected = false;
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  // No input is accepted, the peer asks to allow it by `viewOnlyOffRequested`.
+  bool viewOnly = false;
+  bool viewOnlyOffRequested = false;
+  // Recorded by the host, not the `recording` permission of the peer.
+  bool sessionRecorded = false;
 
   RxInt unreadChatMessageCount = 0.obs;
+  // permission name -> the seconds remaining of the grant for a while.
+  RxMap<String, int> permissionGrants = <String, int>{}.obs;
+  // The latest jpeg of the frames sent to the controller and its display, see `toggleWatch`.
+  RxBool watching = false.obs;
+  Rxn<Uint8List> watchFrame = Rxn<Uint8List>();
+  RxInt watchDisplay = 0.obs;
 
   Client(this.id, this.authorized, this.isFileTransfer, this.name, this.peerId,
       this.keyboard, this.clipboard, this.audio);
 
+  void stopWatching() {
+    watching.value = false;
+    watchFrame.value = null;
+  }
+
   Client.fromJson(Map<String, dynamic> json) {
     id = json['id'];
     authorized = json['authorized'];
@@ -719,10 +867,15 @@ class Client {
     restart = json['restart'];
     recording = json['recording'];
     blockInput = json['block_input'];
+    annotation = json['annotation'] ?? false;
+    keyboardLevel = json['keyboard_level'] ?? 'full';
     disconnected = json['disconnected'];
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
     incomingVoiceCall = json['incoming_voice_call'];
+    viewOnly = json['view_only'] ?? false;
+    viewOnlyOffRequested = json['view_only_off_requested'] ?? false;
+    sessionRecorded = json['session_recorded'] ?? false;
   }
 
   Map<String, dynamic> toJson() {
@@ -740,6 +893,8 @@ class Client {
     data['restart'] = restart;
     data['recording'] = recording;
     data['block_input'] = blockInput;
+    data['annotation'] = annotation;
+    data['keyboard_level'] = keyboardLevel;
     data['disconnected'] = disconnected;
     data['from_switch'] = fromSwitch;
     return data;
@@ -193,6 +193,7 @@ class RustDeskMultiWindowManager {
     bool? forceRelay,
     String? switchUuid,
     bool? isRDP,
+    bool? viewOnly,
   }) async {
     var params = {
       "type": type.index,
@@ -206,6 +207,9 @@ class RustDeskMultiWindowManager {
     if (isRDP != null) {
       params['isRDP'] = isRDP;
     }
+    if (viewOnly == true) {
+      params['viewOnly'] = viewOnly;
+    }
     final msg = jsonEncode(params);
 
     // separate window for file transfer is not supported
@@ -229,6 +233,7 @@ class RustDeskMultiWindowManager {
     String? password,
     String? switchUuid,
     bool? forceRelay,
+    bool? viewOnly,
   }) async {
     return await newSession(
       WindowType.RemoteDesktop,
@@ -238,6 +243,7 @@ class RustDeskMultiWindowManager {
       password: password,
       forceRelay: forceRelay,
       switchUuid: switchUuid,
+      viewOnly: viewOnly,
     );
   }
 
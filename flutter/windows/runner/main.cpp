@@ -12,6 +12,7 @@
 
 typedef char** (*FUNC_RUSTDESK_CORE_MAIN)(int*);
 typedef void (*FUNC_RUSTDESK_FREE_ARGS)( char**, int);
+typedef int (*FUNC_RUSTDESK_ARGS_ESCAPED)();
 const char* uniLinksPrefix = "rustdesk://";
 /// Note: `--server`, `--service` are already handled in [core_main.rs].
 const std::vector<std::string> parameters_white_list = {"--install", "--cm"};
@@ -59,6 +60,12 @@ int APIENTRY wWinMain(_In_ HINSTANCE instance, _In_opt_ HINSTANCE prev,
   }
   std::vector<std::string> rust_args(c_args, c_args + args_len);
   free_c_args(c_args, args_len);
+  FUNC_RUSTDESK_ARGS_ESCAPED args_escaped =
+      (FUNC_RUSTDESK_ARGS_ESCAPED)GetProcAddress(hInstance, "rustdesk_core_main_args_escaped");
+  if (args_escaped && args_escaped() > 0)
+  {
+    std::cout << args_escaped() << " arguments contain NUL, escaped as %00." << std::endl;
+  }
 
   // Uri links dispatch
   HWND hwnd = ::FindWindow(_T("FLUTTER_RUNNER_WIN32_WINDOW"), _T("RustDesk"));
@@ -62,6 +62,7 @@ lazy_static::lazy_static! {
     static ref KEY_PAIR: Arc<Mutex<Option<KeyPair>>> = Default::default();
     static ref USER_DEFAULT_CONFIG: Arc<RwLock<(UserDefaultConfig, Instant)>> = Arc::new(RwLock::new((UserDefaultConfig::load(), Instant::now())));
     pub static ref NEW_STORED_PEER_CONFIG: Arc<Mutex<HashSet<String>>> = Default::default();
+    static ref FINGERPRINT_CONFIG: Arc<RwLock<FingerprintConfig>> = Arc::new(RwLock::new(FingerprintConfig::load()));
 }
 
 lazy_static::lazy_static! {
@@ -1071,6 +1072,7 @@ impl PeerConfig {
 
     pub fn remove(id: &str) {
         fs::remove_file(Self::path(id)).ok();
+        LocalConfig::remove_view_states(id);
     }
 
     fn path(id: &str) -> PathBuf {
@@ -1309,6 +1311,22 @@ pub struct LocalConfig {
     // Various data for flutter ui
     #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
     ui_flutter: HashMap<String, String>,
+    // The view states of the remote displays, keyed by the peer id
+    #[serde(default, deserialize_with = "deserialize_hashmap_view_states")]
+    view_states: HashMap<String, Vec<ViewState>>,
+}
+
+/// The window placement and the view settings of a remote display, saved by the flutter ui.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ViewState {
+    #[serde(default, deserialize_with = "deserialize_i32")]
+    pub display: i32,
+    /// Identifies the remote monitor, the state is dropped if the display index is reused
+    /// by another monitor.
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub monitor: String,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub state: String,
 }
 
 impl LocalConfig {
@@ -1411,6 +1429,72 @@ impl LocalConfig {
             config.store();
         }
     }
+
+    pub fn get_view_state(id: &str, display: i32, monitor: &str) -> Option<String> {
+        LOCAL_CONFIG
+            .read()
+            .unwrap()
+            .view_states
+            .get(id)?
+            .iter()
+            .find(|s| s.display == display && s.monitor == monitor)
+            .map(|s| s.state.clone())
+    }
+
+    /// Empty `state` removes the saved one.
+    pub fn set_view_state(id: &str, display: i32, monitor: &str, state: String) {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        let states = config.view_states.entry(id.to_owned()).or_default();
+        let old = states.clone();
+        states.retain(|s| s.display != display);
+        if !state.is_empty() {
+            states.push(ViewState {
+                display,
+                monitor: monitor.to_owned(),
+                state,
+            });
+        }
+        if *states == old {
+            return;
+        }
+        if states.is_empty() {
+            config.view_states.remove(id);
+        }
+        config.store();
+    }
+
+    /// Drop the states of the displays which are gone, `monitors` are of the current displays.
+    pub fn retain_view_states(id: &str, monitors: &[String]) {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if let Some(states) = config.view_states.get_mut(id) {
+            if !retain_view_states(states, monitors) {
+                return;
+            }
+            if states.is_empty() {
+                config.view_states.remove(id);
+            }
+            config.store();
+        }
+    }
+
+    pub fn remove_view_states(id: &str) {
+        let mut config = LOCAL_CONFIG.write().unwrap();
+        if config.view_states.remove(id).is_some() {
+            config.store();
+        }
+    }
+}
+
+/// Returns true if any state is dropped.
+fn retain_view_states(states: &mut Vec<ViewState>, monitors: &[String]) -> bool {
+    let len = states.len();
+    states.retain(|s| {
+        usize::try_from(s.display)
+            .ok()
+            .and_then(|i| monitors.get(i))
+            .map_or(false, |m| *m == s.monitor)
+    });
+    states.len() != len
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -1572,6 +1656,80 @@ impl UserDefaultConfig {
     }
 }
 
+/// The trusted fingerprints of the peers, recorded on the first connection.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FingerprintConfig {
+    #[serde(default, deserialize_with = "deserialize_hashmap_string_string")]
+    fingerprints: HashMap<String, String>,
+}
+
+impl FingerprintConfig {
+    fn load() -> FingerprintConfig {
+        Config::load_::<FingerprintConfig>("_fingerprints")
+    }
+
+    fn store(&self) {
+        Config::store_(self, "_fingerprints");
+    }
+
+    /// Check the fingerprint of the peer, it's recorded if the peer has no trusted fingerprint.
+    /// Return the trusted fingerprint if it's different.
+    pub fn check(peer_id: &str, fingerprint: &str) -> Option<String> {
+        let mut config = FINGERPRINT_CONFIG.write().unwrap();
+        let (old, store) = config.check_(peer_id, fingerprint);
+        if store {
+            config.store();
+        }
+        old
+    }
+
+    fn check_(&mut self, peer_id: &str, fingerprint: &str) -> (Option<String>, bool) {
+        match self.fingerprints.get(peer_id) {
+            Some(old) if old == fingerprint => (None, false),
+            Some(old) => (Some(old.clone()), false),
+            None => {
+                self.fingerprints
+                    .insert(peer_id.to_owned(), fingerprint.to_owned());
+                (None, true)
+            }
+        }
+    }
+
+    /// Trust the fingerprint of the peer, replacing the old one.
+    pub fn set(peer_id: &str, fingerprint: &str) {
+        let mut config = FINGERPRINT_CONFIG.write().unwrap();
+        if config.fingerprints.get(peer_id).map(|f| f.as_str()) == Some(fingerprint) {
+            return;
+        }
+        config
+            .fingerprints
+            .insert(peer_id.to_owned(), fingerprint.to_owned());
+        config.store();
+    }
+
+    pub fn get(peer_id: &str) -> Option<String> {
+        FINGERPRINT_CONFIG
+            .read()
+            .unwrap()
+            .fingerprints
+            .get(peer_id)
+            .cloned()
+    }
+
+    pub fn list() -> HashMap<String, String> {
+        FINGERPRINT_CONFIG.read().unwrap().fingerprints.clone()
+    }
+
+    pub fn remove(peer_id: &str) -> bool {
+        let mut config = FINGERPRINT_CONFIG.write().unwrap();
+        if config.fingerprints.remove(peer_id).is_none() {
+            return false;
+        }
+        config.store();
+        true
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct AbPeer {
     #[serde(
@@ -1802,6 +1960,7 @@ deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
 deserialize_default!(deserialize_hashmap_string_bool,  HashMap<String, bool>);
 deserialize_default!(deserialize_hashmap_resolutions, HashMap<String, Resolution>);
+deserialize_default!(deserialize_hashmap_view_states, HashMap<String, Vec<ViewState>>);
 
 #[cfg(test)]
 mod tests {
@@ -1851,6 +2010,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fingerprint_config() {
+        let mut cfg = FingerprintConfig::default();
+        assert_eq!(cfg.check_("123", "aaaa"), (None, true));
+        assert_eq!(cfg.check_("123", "aaaa"), (None, false));
+        assert_eq!(cfg.check_("123", "bbbb"), (Some("aaaa".to_owned()), false));
+        // A mismatch never replaces the trusted fingerprint.
+        assert_eq!(cfg.fingerprints.get("123").map(|f| f.as_str()), Some("aaaa"));
+        assert_eq!(cfg.check_("456", "bbbb"), (None, true));
+
+        let s = toml::to_string_pretty(&cfg).unwrap();
+        assert_eq!(toml::from_str::<FingerprintConfig>(&s), Ok(cfg));
+    }
+
+    #[test]
+    fn test_view_states() {
+        let state = |display, monitor: &str| ViewState {
+            display,
+            monitor: monitor.to_owned(),
+            state: format!("{{\"display\": {}}}", display),
+        };
+        let mut cfg = LocalConfig::default();
+        cfg.view_states.insert(
+            "123".to_owned(),
+            vec![state(0, "A@0,0"), state(1, "B@1920,0"), state(2, "C@0,1080")],
+        );
+        let s = toml::to_string_pretty(&cfg).unwrap();
+        let cfg2 = toml::from_str::<LocalConfig>(&s).unwrap();
+        assert_eq!(cfg2.view_states, cfg.view_states);
+
+        // B is moved to index 0, C is removed.
+        let mut states = cfg.view_states.remove("123").unwrap();
+        let monitors = ["B@1920,0".to_owned(), "A@0,0".to_owned()];
+        assert!(retain_view_states(&mut states, &monitors));
+        assert!(states.is_empty());
+
+        let mut states = vec![state(0, "A@0,0"), state(1, "B@1920,0"), state(-1, "A@0,0")];
+        let monitors = ["A@0,0".to_owned(), "B@1920,0".to_owned()];
+        assert!(retain_view_states(&mut states, &monitors));
+        assert_eq!(states, vec![state(0, "A@0,0"), state(1, "B@1920,0")]);
+        assert!(!retain_view_states(&mut states, &monitors));
+
+        let cfg = toml::from_str::<LocalConfig>("view_states = 1").unwrap();
+        assert!(cfg.view_states.is_empty());
+    }
+
     #[test]
     fn test_peer_config_deserialize() {
         let default_peer_config = toml::from_str::<PeerConfig>("").unwrap();
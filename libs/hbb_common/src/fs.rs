@@ -14,6 +14,12 @@ use crate::{
     config::Config,
 };
 
+pub mod file_name;
+pub mod file_op;
+pub mod multi_stream;
+
+const BLOCK_SIZE: usize = 128 * 1024;
+
 pub fn read_dir(path: &Path, include_hidden: bool) -> ResultType<FileDirectory> {
     let mut dir = FileDirectory {
         path: get_string(path),
@@ -220,6 +226,10 @@ pub struct TransferJob {
     file_skipped: bool,
     file_is_waiting: bool,
     default_overwrite_strategy: Option<bool>,
+    /// The streams the large files are split into, see `multi_stream`.
+    pub streams: u32,
+    #[serde(skip_serializing)]
+    ranges: Option<multi_stream::RangedReader>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -236,6 +246,8 @@ pub struct TransferJobMeta {
     pub file_num: i32,
     #[serde(default)]
     pub is_remote: bool,
+    #[serde(default)]
+    pub streams: u32,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -324,6 +336,27 @@ impl TransferJob {
         })
     }
 
+    /// The read job of the given files under `path`, e.g. the changed ones of a synced directory.
+    pub fn new_read_files(
+        id: i32,
+        remote: String,
+        path: String,
+        files: Vec<FileEntry>,
+        enable_overwrite_detection: bool,
+    ) -> Self {
+        log::info!("new read {}, {} files", path, files.len());
+        let total_size = files.iter().map(|x| x.size).sum();
+        Self {
+            id,
+            remote,
+            path: get_path(&path),
+            files,
+            total_size,
+            enable_overwrite_detection,
+            ..Default::default()
+        }
+    }
+
     #[inline]
     pub fn files(&self) -> &Vec<FileEntry> {
         &self.files
@@ -406,22 +439,33 @@ impl TransferJob {
             let path = format!("{}.download", get_string(&path));
             self.file = Some(File::create(&path).await?);
         }
-        if block.compressed {
-            let tmp = decompress(&block.data);
-            self.file
-                .as_mut()
-                .ok_or(anyhow!("file is None"))?
-                .write_all(&tmp)
-                .await?;
-            self.finished_size += tmp.len() as u64;
+        let tmp;
+        let data = if block.compressed {
+            tmp = decompress(&block.data);
+            &tmp[..]
         } else {
+            &block.data[..]
+        };
+        if block.stream > 0 {
+            // The blocks of the ranges arrive in any order, each is written at its offset,
+            // within the size of the file.
+            let size = self.files[file_num].size;
+            match block.offset.checked_add(data.len() as u64) {
+                Some(end) if end <= size => {}
+                _ => bail!("Wrong offset"),
+            }
             self.file
                 .as_mut()
                 .ok_or(anyhow!("file is None"))?
-                .write_all(&block.data)
+                .seek(SeekFrom::Start(block.offset))
                 .await?;
-            self.finished_size += block.data.len() as u64;
         }
+        self.file
+            .as_mut()
+            .ok_or(anyhow!("file is None"))?
+            .write_all(data)
+            .await?;
+        self.finished_size += data.len() as u64;
         self.transferred += block.data.len() as u64;
         Ok(())
     }
@@ -443,8 +487,18 @@ impl TransferJob {
         }
         let name = &self.files[file_num].name;
         if self.file.is_none() {
-            match File::open(self.join(name)).await {
+            let path = self.join(name);
+            match File::open(&path).await {
                 Ok(file) => {
+                    self.ranges = match file.metadata().await {
+                        Ok(meta) if self.streams > 1 => multi_stream::RangedReader::new(
+                            path,
+                            meta.len(),
+                            self.streams,
+                            BLOCK_SIZE,
+                        ),
+                        _ => None,
+                    };
                     self.file = Some(file);
                     self.file_confirmed = false;
                     self.file_is_waiting = false;
@@ -464,8 +518,10 @@ impl TransferJob {
             }
             return Ok(None);
         }
-        const BUF_SIZE: usize = 128 * 1024;
-        let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+        if self.ranges.is_some() {
+            return self.read_ranged(file_num).await.map(Some);
+        }
+        let mut buf: Vec<u8> = vec![0; BLOCK_SIZE];
         let mut compressed = false;
         let mut offset: usize = 0;
         loop {
@@ -485,7 +541,7 @@ impl TransferJob {
                 }
                 Ok(n) => {
                     offset += n;
-                    if n == 0 || offset == BUF_SIZE {
+                    if n == 0 || offset == BLOCK_SIZE {
                         break;
                     }
                 }
@@ -517,6 +573,51 @@ impl TransferJob {
         }))
     }
 
+    // The next block read by any of the ranges, the empty block once all are read.
+    async fn read_ranged(&mut self, file_num: usize) -> ResultType<FileTransferBlock> {
+        let mut block = FileTransferBlock {
+            id: self.id,
+            file_num: file_num as _,
+            ..Default::default()
+        };
+        let next = match self.ranges.as_mut() {
+            Some(ranges) => ranges.next_block().await,
+            None => None,
+        };
+        let Some(res) = next else {
+            self.file_num += 1;
+            self.file = None;
+            self.ranges = None;
+            self.file_confirmed = false;
+            self.file_is_waiting = false;
+            return Ok(block);
+        };
+        let (chunk, mut buf) = match res {
+            Ok(read) => read,
+            Err(err) => {
+                self.file_num += 1;
+                self.file = None;
+                self.ranges = None;
+                self.file_confirmed = false;
+                self.file_is_waiting = false;
+                return Err(err.into());
+            }
+        };
+        self.finished_size += buf.len() as u64;
+        if !is_compressed_file(&self.files[file_num].name) {
+            let tmp = compress(&buf);
+            if tmp.len() < buf.len() {
+                buf = tmp;
+                block.compressed = true;
+            }
+        }
+        self.transferred += buf.len() as u64;
+        block.data = buf.into();
+        block.offset = chunk.offset;
+        block.stream = chunk.stream;
+        Ok(block)
+    }
+
     async fn send_current_digest(&mut self, stream: &mut Stream) -> ResultType<()> {
         let mut msg = Message::new();
         let mut resp = FileResponse::new();
@@ -612,6 +713,7 @@ impl TransferJob {
     pub fn set_file_skipped(&mut self) -> bool {
         log::debug!("skip file {} in job {}", self.file_num, self.id);
         self.file.take();
+        self.ranges.take();
         self.set_file_confirmed(false);
         self.set_file_is_waiting(false);
         self.file_num += 1;
@@ -649,6 +751,7 @@ impl TransferJob {
             file_num: self.file_num,
             show_hidden: self.show_hidden,
             is_remote: self.is_remote,
+            streams: self.streams,
         }
     }
 }
@@ -706,6 +809,7 @@ pub fn new_receive(
     file_num: i32,
     files: Vec<FileEntry>,
     total_size: u64,
+    streams: u32,
 ) -> Message {
     let mut action = FileAction::new();
     action.set_receive(FileTransferReceiveRequest {
@@ -714,6 +818,7 @@ pub fn new_receive(
         files,
         file_num,
         total_size,
+        streams,
         ..Default::default()
     });
     let mut msg_out = Message::new();
@@ -722,14 +827,21 @@ pub fn new_receive(
 }
 
 #[inline]
-pub fn new_send(id: i32, path: String, file_num: i32, include_hidden: bool) -> Message {
-    log::info!("new send: {}, id: {}", path, id);
+pub fn new_send(
+    id: i32,
+    path: String,
+    file_num: i32,
+    include_hidden: bool,
+    streams: u32,
+) -> Message {
+    log::info!("new send: {}, id: {}, streams: {}", path, id, streams);
     let mut action = FileAction::new();
     action.set_send(FileTransferSendRequest {
         id,
         path,
         include_hidden,
         file_num,
+        streams,
         ..Default::default()
     });
     let mut msg_out = Message::new();
@@ -0,0 +1,452 @@
+//! The names of the files received, checked against the rules of the local file system before
+//! any file is written.
+//!
+//! The name of an entry is the path relative to the target directory, joined by "/". The names
+//! escaping the target directory, e.g. "../a" or "/etc/a", are rejected. The names merely
+//! invalid on the local system, e.g. "aux.txt" or "a?b" on Windows, are handled by the
+//! [`NamePolicy`] in the option [`OPTION_FILE_NAME_POLICY`], the renamed ones are returned to be
+//! reported per file.
+
+use crate::{bail, message_proto::FileEntry, ResultType};
+use serde_derive::Serialize;
+use std::collections::HashSet;
+
+/// "fail", "rename" or "encode", "rename" by default.
+pub const OPTION_FILE_NAME_POLICY: &str = "file-name-policy";
+const MAX_COMPONENT_LEN: usize = 255;
+const WINDOWS_RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetOs {
+    Windows,
+    MacOs,
+    Linux,
+}
+
+impl TargetOs {
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else if cfg!(target_os = "macos") {
+            Self::MacOs
+        } else {
+            Self::Linux
+        }
+    }
+
+    fn is_separator(&self, c: char) -> bool {
+        c == '/' || (*self == Self::Windows && c == '\\')
+    }
+
+    fn is_invalid_char(&self, c: char) -> bool {
+        match self {
+            Self::Windows => c < ' ' || "<>:\"|?*".contains(c),
+            Self::MacOs => c == '\0' || c == ':',
+            Self::Linux => c == '\0',
+        }
+    }
+
+    fn is_case_insensitive(&self) -> bool {
+        *self != Self::Linux
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// The job fails before any file is written.
+    FailFast,
+    /// The invalid characters are replaced by "_", and a suffix is added to the reserved names.
+    Rename,
+    /// The invalid characters are percent-encoded.
+    PercentEncode,
+}
+
+impl NamePolicy {
+    pub fn from_option(v: &str) -> Self {
+        match v {
+            "fail" => Self::FailFast,
+            "encode" => Self::PercentEncode,
+            _ => Self::Rename,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Renamed {
+    pub file_num: i32,
+    pub from: String,
+    pub to: String,
+}
+
+fn is_windows_reserved(component: &str) -> bool {
+    // The extension doesn't matter, "aux.txt" is reserved too.
+    let stem = component.split('.').next().unwrap_or_default();
+    let stem = stem.trim_end_matches(' ').to_uppercase();
+    if WINDOWS_RESERVED.contains(&stem.as_str()) {
+        return true;
+    }
+    match stem
+        .strip_prefix("COM")
+        .or_else(|| stem.strip_prefix("LPT"))
+    {
+        Some(n) => n.len() == 1 && matches!(n.as_bytes()[0], b'1'..=b'9'),
+        None => false,
+    }
+}
+
+/// The reason the component is invalid on `os`, `None` if valid.
+pub fn check_component(component: &str, os: TargetOs) -> Option<&'static str> {
+    if component.chars().any(|c| os.is_invalid_char(c)) {
+        return Some("invalid character");
+    }
+    if component.len() > MAX_COMPONENT_LEN {
+        return Some("too long");
+    }
+    if os == TargetOs::Windows {
+        if component.ends_with('.') || component.ends_with(' ') {
+            return Some("trailing dot or space");
+        }
+        if is_windows_reserved(component) {
+            return Some("reserved name");
+        }
+    }
+    None
+}
+
+// The components of the relative path, an error if it escapes the target directory.
+fn components(name: &str, os: TargetOs) -> ResultType<Vec<&str>> {
+    let is_absolute = name.starts_with(|c| os.is_separator(c))
+        || (os == TargetOs::Windows && name.as_bytes().get(1) == Some(&b':'));
+    if is_absolute {
+        bail!("Unsafe file name, absolute path: {}", name);
+    }
+    let mut components = vec![];
+    for component in name.split(|c| os.is_separator(c)) {
+        match component {
+            ".." => bail!("Unsafe file name, path traversal: {}", name),
+            "" | "." => {}
+            c => components.push(c),
+        }
+    }
+    Ok(components)
+}
+
+fn rename_component(component: &str, os: TargetOs) -> String {
+    let mut renamed: String = component
+        .chars()
+        .map(|c| if os.is_invalid_char(c) { '_' } else { c })
+        .collect();
+    if os == TargetOs::Windows {
+        let trimmed = renamed.trim_end_matches(|c| c == '.' || c == ' ');
+        if trimmed.len() != renamed.len() {
+            renamed = format!("{}_", trimmed);
+        }
+        if is_windows_reserved(&renamed) {
+            let (stem, ext) = split_ext(&renamed);
+            renamed = format!("{}_{}", stem, ext);
+        }
+    }
+    truncate(renamed)
+}
+
+fn encode_component(component: &str, os: TargetOs) -> String {
+    let encode = |c: char, out: &mut String| {
+        let mut buf = [0; 4];
+        for b in c.encode_utf8(&mut buf).bytes() {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    };
+    let mut encoded = String::new();
+    let trailing = if os == TargetOs::Windows {
+        component.len() - component.trim_end_matches(|c| c == '.' || c == ' ').len()
+    } else {
+        0
+    };
+    let kept = component.len() - trailing;
+    for (i, c) in component.char_indices() {
+        // "%" is encoded too, to be decoded back.
+        if os.is_invalid_char(c) || c == '%' || i >= kept {
+            encode(c, &mut encoded);
+        } else {
+            encoded.push(c);
+        }
+    }
+    if os == TargetOs::Windows && is_windows_reserved(&encoded) {
+        let mut chars = encoded.chars();
+        let first = chars.next().unwrap_or_default();
+        let mut prefixed = String::new();
+        encode(first, &mut prefixed);
+        encoded = prefixed + chars.as_str();
+    }
+    truncate(encoded)
+}
+
+fn split_ext(name: &str) -> (&str, &str) {
+    match name.find('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    }
+}
+
+fn truncate(mut name: String) -> String {
+    if name.len() > MAX_COMPONENT_LEN {
+        let mut end = MAX_COMPONENT_LEN;
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        name.truncate(end);
+    }
+    name
+}
+
+fn name_key(name: &str, os: TargetOs) -> String {
+    if os.is_case_insensitive() {
+        name.to_lowercase()
+    } else {
+        name.to_owned()
+    }
+}
+
+// The name not taken yet, "a (1).txt" if "a.txt" is taken.
+fn unique(name: String, taken: &mut HashSet<String>, os: TargetOs) -> String {
+    if taken.insert(name_key(&name, os)) {
+        return name;
+    }
+    let (dir, file) = match name.rfind('/') {
+        Some(i) => (&name[..=i], &name[i + 1..]),
+        None => ("", name.as_str()),
+    };
+    let (stem, ext) = split_ext(file);
+    for n in 1.. {
+        let candidate = format!("{}{} ({}){}", dir, stem, n, ext);
+        if taken.insert(name_key(&candidate, os)) {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
+/// Check the names of the entries to write on `os`, the invalid ones are changed by `policy`.
+/// Returns the renamed entries, or an error if any name is unsafe, or invalid with
+/// [`NamePolicy::FailFast`].
+pub fn sanitize_entries(
+    entries: &mut [FileEntry],
+    os: TargetOs,
+    policy: NamePolicy,
+) -> ResultType<Vec<Renamed>> {
+    let mut sanitized = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        let mut changed = false;
+        let mut parts = vec![];
+        for component in components(&entry.name, os)? {
+            let Some(reason) = check_component(component, os) else {
+                parts.push(component.to_owned());
+                continue;
+            };
+            changed = true;
+            parts.push(match policy {
+                NamePolicy::FailFast => {
+                    bail!("Invalid file name, {}: {}", reason, entry.name)
+                }
+                NamePolicy::Rename => rename_component(component, os),
+                NamePolicy::PercentEncode => encode_component(component, os),
+            });
+        }
+        // A single file is written to the target path itself.
+        if parts.is_empty() && !entry.name.is_empty() {
+            bail!("Invalid file name: {}", entry.name);
+        }
+        sanitized.push((parts.join("/"), changed));
+    }
+    // Only the renamed ones may collide with the others, the names not changed are kept.
+    let mut taken = HashSet::new();
+    for (name, _) in sanitized.iter().filter(|(_, changed)| !changed) {
+        taken.insert(name_key(name, os));
+    }
+    let mut renamed = vec![];
+    for (i, (entry, (name, changed))) in entries.iter_mut().zip(sanitized).enumerate() {
+        let name = if changed {
+            unique(name, &mut taken, os)
+        } else {
+            name
+        };
+        if name != entry.name {
+            renamed.push(Renamed {
+                file_num: i as _,
+                from: std::mem::replace(&mut entry.name, name.clone()),
+                to: name,
+            });
+        }
+    }
+    Ok(renamed)
+}
+
+/// Check the new name of a file renamed in place on `os`, it must be a single valid component.
+pub fn check_new_name(name: &str, os: TargetOs) -> ResultType<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        bail!("Invalid file name: {}", name);
+    }
+    if name.contains(|c| os.is_separator(c))
+        || (os == TargetOs::Windows && name.as_bytes().get(1) == Some(&b':'))
+    {
+        bail!("Unsafe file name, not a name: {}", name);
+    }
+    if let Some(reason) = check_component(name, os) {
+        bail!("Invalid file name, {}: {}", reason, name);
+    }
+    Ok(())
+}
+
+/// Whether the names are the same file on `os`, e.g. "a.txt" and "A.TXT" on Windows.
+pub fn is_same_name(a: &str, b: &str, os: TargetOs) -> bool {
+    name_key(a, os) == name_key(b, os)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(names: &[&str]) -> Vec<FileEntry> {
+        names
+            .iter()
+            .map(|n| FileEntry {
+                name: n.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn sanitize(name: &str, os: TargetOs, policy: NamePolicy) -> Result<String, String> {
+        let mut entries = entries(&[name]);
+        sanitize_entries(&mut entries, os, policy)
+            .map(|_| entries[0].name.clone())
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_sanitize_names() {
+        use NamePolicy::*;
+        use TargetOs::*;
+        let ok = |s: &str| Ok(s.to_owned());
+        let table: Vec<(&str, TargetOs, NamePolicy, Result<String, String>)> = vec![
+            ("a.txt", Windows, FailFast, ok("a.txt")),
+            ("dir/sub/a.txt", Windows, FailFast, ok("dir/sub/a.txt")),
+            ("", Windows, FailFast, ok("")),
+            // Windows reserved names, with or without the extension.
+            ("aux.txt", Windows, Rename, ok("aux_.txt")),
+            ("dir/CON", Windows, Rename, ok("dir/CON_")),
+            ("com1.tar.gz", Windows, Rename, ok("com1_.tar.gz")),
+            ("lpt9", Windows, PercentEncode, ok("%6Cpt9")),
+            ("com0.txt", Windows, FailFast, ok("com0.txt")),
+            ("console.txt", Windows, FailFast, ok("console.txt")),
+            ("aux.txt", Linux, FailFast, ok("aux.txt")),
+            // Trailing dots and spaces.
+            ("a. ", Windows, Rename, ok("a_")),
+            ("dir./a", Windows, Rename, ok("dir_/a")),
+            ("a.", Windows, PercentEncode, ok("a%2E")),
+            ("a.", Linux, FailFast, ok("a.")),
+            // Invalid characters.
+            ("a?b:c.txt", Windows, Rename, ok("a_b_c.txt")),
+            ("50%<1>", Windows, PercentEncode, ok("50%25%3C1%3E")),
+            ("a:b", MacOs, Rename, ok("a_b")),
+            ("a:b", Linux, FailFast, ok("a:b")),
+            ("a\\b", Linux, FailFast, ok("a\\b")),
+            ("./a//b", Linux, FailFast, ok("a/b")),
+        ];
+        for (name, os, policy, expected) in table {
+            assert_eq!(
+                sanitize(name, os, policy),
+                expected,
+                "{:?} {:?} {:?}",
+                name,
+                os,
+                policy
+            );
+        }
+        // Fail fast with the reason.
+        for (name, os) in [("aux", Windows), ("a ", Windows), ("a|b", Windows)] {
+            let err = sanitize(name, os, FailFast).unwrap_err();
+            assert!(err.starts_with("Invalid file name"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_reject_traversal() {
+        use TargetOs::*;
+        let unsafe_names = [
+            ("../a", Linux),
+            ("a/../../b", Linux),
+            ("..", MacOs),
+            ("/etc/passwd", Linux),
+            ("..\\a", Windows),
+            ("a\\..\\..\\b", Windows),
+            ("\\\\server\\share\\a", Windows),
+            ("C:\\Windows\\a", Windows),
+            ("c:a", Windows),
+        ];
+        for (name, os) in unsafe_names {
+            for policy in [
+                NamePolicy::FailFast,
+                NamePolicy::Rename,
+                NamePolicy::PercentEncode,
+            ] {
+                let err = sanitize(name, os, policy).unwrap_err();
+                assert!(err.starts_with("Unsafe file name"), "{}: {}", name, err);
+            }
+        }
+        // A name is a name on Linux.
+        assert_eq!(
+            sanitize("..\\a", Linux, NamePolicy::FailFast),
+            Ok("..\\a".to_owned())
+        );
+        // The whole job is rejected.
+        let mut job = entries(&["a", "../b"]);
+        assert!(sanitize_entries(&mut job, Linux, NamePolicy::Rename).is_err());
+        assert_eq!(job[0].name, "a");
+    }
+
+    #[test]
+    fn test_renamed_unique() {
+        let mut job = entries(&["a?.txt", "A_.txt", "a*.txt", "b.txt"]);
+        let renamed = sanitize_entries(&mut job, TargetOs::Windows, NamePolicy::Rename).unwrap();
+        assert_eq!(
+            renamed,
+            vec![
+                Renamed {
+                    file_num: 0,
+                    from: "a?.txt".to_owned(),
+                    to: "a_ (1).txt".to_owned(),
+                },
+                Renamed {
+                    file_num: 2,
+                    from: "a*.txt".to_owned(),
+                    to: "a_ (2).txt".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(job[1].name, "A_.txt");
+        assert_eq!(job[3].name, "b.txt");
+    }
+
+    #[test]
+    fn test_check_new_name() {
+        use TargetOs::*;
+        for (name, os) in [("a.txt", Windows), ("a:b", Linux), ("..a", Linux)] {
+            assert!(check_new_name(name, os).is_ok(), "{}", name);
+        }
+        for (name, os) in [
+            ("", Linux),
+            ("..", Linux),
+            ("a/b", Linux),
+            ("../a", Linux),
+            ("a\\b", Windows),
+            ("c:a", Windows),
+            ("aux.txt", Windows),
+            ("a?", Windows),
+        ] {
+            assert!(check_new_name(name, os).is_err(), "{}", name);
+        }
+        assert!(is_same_name("a.txt", "A.TXT", Windows));
+        assert!(!is_same_name("a.txt", "A.TXT", Linux));
+    }
+}
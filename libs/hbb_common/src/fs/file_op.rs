@@ -0,0 +1,324 @@
+//! The operations in place of the file-transfer browser, renaming and removing a path, on the
+//! local side or on the host.
+//!
+//! The paths come from the peer, so only the absolute paths without ".." are accepted, and the
+//! root of a drive is never renamed or removed. The new name of a rename is checked by
+//! [`file_name::check_new_name`], a rename never overwrites another file.
+//!
+//! The recursive remove may take long, it runs on a blocking thread, reports the progress every
+//! [`PROGRESS_INTERVAL`] and stops at the next entry once canceled by [`Ops::cancel`]. The entries
+//! removed before are not restored. The symbolic links are removed, never followed.
+
+use super::{file_name, get_path};
+use crate::{bail, message_proto::*, ResultType};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+pub const CANCELED: &str = "Canceled";
+
+fn checked_path(path: &str) -> ResultType<PathBuf> {
+    let p = get_path(path);
+    if !p.is_absolute() {
+        bail!("Not an absolute path: {}", path);
+    }
+    if p.components().any(|c| c == Component::ParentDir) {
+        bail!("Unsafe path, path traversal: {}", path);
+    }
+    if p.parent().is_none() {
+        bail!("Refuse to change the root: {}", path);
+    }
+    Ok(p)
+}
+
+/// Rename `path` to `new_name` in the same directory, returns the new path.
+pub fn rename(path: &str, new_name: &str) -> ResultType<PathBuf> {
+    let from = checked_path(path)?;
+    let os = file_name::TargetOs::current();
+    file_name::check_new_name(new_name, os)?;
+    let (Some(dir), Some(old_name)) = (from.parent(), from.file_name()) else {
+        bail!("Invalid path: {}", path);
+    };
+    std::fs::symlink_metadata(&from)?;
+    let to = dir.join(new_name);
+    // Only the case is changed, it's the same file on the case-insensitive systems.
+    let same = file_name::is_same_name(&old_name.to_string_lossy(), new_name, os);
+    if !same && std::fs::symlink_metadata(&to).is_ok() {
+        bail!("Already exists: {}", new_name);
+    }
+    std::fs::rename(&from, &to)?;
+    Ok(to)
+}
+
+#[inline]
+fn is_dir(meta: &std::fs::Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileTypeExt;
+        // The symbolic link to a directory is removed as a directory on Windows.
+        if meta.file_type().is_symlink_dir() {
+            return true;
+        }
+    }
+    meta.is_dir()
+}
+
+fn remove_entry(path: &Path, dir: bool) -> ResultType<()> {
+    if dir {
+        std::fs::remove_dir(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// The files and the directories under `path`, the directories in the order found, so the
+// parents before the children.
+fn collect(path: &Path, canceled: &AtomicBool) -> ResultType<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = vec![];
+    let mut dirs = vec![path.to_path_buf()];
+    let mut i = 0;
+    while i < dirs.len() {
+        if canceled.load(Ordering::SeqCst) {
+            bail!(CANCELED);
+        }
+        for entry in std::fs::read_dir(&dirs[i])? {
+            let entry = entry?;
+            let meta = std::fs::symlink_metadata(entry.path())?;
+            // The links are not walked.
+            if meta.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+        i += 1;
+    }
+    Ok((files, dirs))
+}
+
+/// Remove `path`, a non-empty directory only if `recursive`. `progress` is called with the
+/// entries removed and the total while removing recursively. Returns the entries removed.
+pub fn remove_path(
+    path: &str,
+    recursive: bool,
+    canceled: &AtomicBool,
+    mut progress: impl FnMut(u64, u64),
+) -> ResultType<u64> {
+    let p = checked_path(path)?;
+    let meta = std::fs::symlink_metadata(&p)?;
+    if !meta.is_dir() || !recursive {
+        remove_entry(&p, is_dir(&meta))?;
+        return Ok(1);
+    }
+    let (files, dirs) = collect(&p, canceled)?;
+    let total = (files.len() + dirs.len()) as u64;
+    let mut removed = 0;
+    let mut last = Instant::now();
+    progress(removed, total);
+    let entries = files
+        .iter()
+        .map(|f| (f, false))
+        .chain(dirs.iter().rev().map(|d| (d, true)));
+    for (entry, dir) in entries {
+        if canceled.load(Ordering::SeqCst) {
+            bail!(CANCELED);
+        }
+        let dir = dir || std::fs::symlink_metadata(entry).map_or(false, |m| is_dir(&m));
+        remove_entry(entry, dir)?;
+        removed += 1;
+        if last.elapsed() >= PROGRESS_INTERVAL {
+            last = Instant::now();
+            progress(removed, total);
+        }
+    }
+    progress(removed, total);
+    Ok(removed)
+}
+
+/// The cancel flags of the operations running, by the id.
+#[derive(Debug, Default, Clone)]
+pub struct Ops(Arc<Mutex<HashMap<i32, Arc<AtomicBool>>>>);
+
+impl Ops {
+    /// The flag to check by the operation `id`, [`Ops::finish`] it when done.
+    pub fn start(&self, id: i32) -> Arc<AtomicBool> {
+        let canceled = Arc::new(AtomicBool::new(false));
+        self.0.lock().unwrap().insert(id, canceled.clone());
+        canceled
+    }
+
+    #[inline]
+    pub fn finish(&self, id: i32) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    /// Returns false if not running.
+    pub fn cancel(&self, id: i32) -> bool {
+        match self.0.lock().unwrap().get(&id) {
+            Some(canceled) => {
+                canceled.store(true, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cancel_all(&self) {
+        for canceled in self.0.lock().unwrap().values() {
+            canceled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[inline]
+pub fn new_progress(id: i32, removed: u64, total: u64) -> Message {
+    let mut resp = FileResponse::new();
+    resp.set_op_progress(FileOpProgress {
+        id,
+        removed,
+        total,
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_file_response(resp);
+    msg_out
+}
+
+#[inline]
+pub fn new_result<T>(id: i32, res: &ResultType<T>) -> Message {
+    let mut resp = FileResponse::new();
+    resp.set_op_result(FileOpResult {
+        id,
+        error: res
+            .as_ref()
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_file_response(resp);
+    msg_out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_op_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn s(p: &Path) -> &str {
+        p.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_rename() {
+        let dir = test_dir("rename");
+        let a = dir.join("a.txt");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+
+        let c = rename(s(&a), "c.txt").unwrap();
+        assert_eq!(c, dir.join("c.txt"));
+        assert!(!a.exists());
+        assert_eq!(std::fs::read_to_string(&c).unwrap(), "a");
+        // Never overwritten.
+        assert!(rename(s(&c), "b.txt").is_err());
+        assert_eq!(std::fs::read_to_string(dir.join("b.txt")).unwrap(), "b");
+        // Only a name in the same directory.
+        assert!(rename(s(&c), "../c.txt").is_err());
+        assert!(rename(s(&c), "sub/c.txt").is_err());
+        assert!(rename(s(&c), "").is_err());
+        assert!(rename(s(&dir.join("none")), "d.txt").is_err());
+        // Only the absolute paths without "..".
+        assert!(rename("c.txt", "d.txt").is_err());
+        assert!(rename(s(&dir.join("..").join("c.txt")), "d.txt").is_err());
+        // The case only.
+        let upper = rename(s(&c), "C.txt").unwrap();
+        assert!(upper.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_remove_path() {
+        let dir = test_dir("remove");
+        let canceled = AtomicBool::new(false);
+        let no_progress = |_, _| {};
+
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "a").unwrap();
+        assert_eq!(
+            remove_path(s(&file), false, &canceled, no_progress).unwrap(),
+            1
+        );
+        assert!(!file.exists());
+
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(tree.join("x").join("y")).unwrap();
+        std::fs::create_dir_all(tree.join("z")).unwrap();
+        for f in ["a", "x/b", "x/y/c", "z/d"] {
+            std::fs::write(tree.join(f), f).unwrap();
+        }
+        // Not empty.
+        assert!(remove_path(s(&tree), false, &canceled, no_progress).is_err());
+        assert!(tree.exists());
+
+        canceled.store(true, Ordering::SeqCst);
+        let err = remove_path(s(&tree), true, &canceled, no_progress).unwrap_err();
+        assert_eq!(err.to_string(), CANCELED);
+        assert!(tree.join("x/y/c").exists());
+
+        canceled.store(false, Ordering::SeqCst);
+        let mut reports = vec![];
+        let removed = remove_path(s(&tree), true, &canceled, |r, t| reports.push((r, t))).unwrap();
+        assert_eq!(removed, 8);
+        assert_eq!(reports.first(), Some(&(0, 8)));
+        assert_eq!(reports.last(), Some(&(8, 8)));
+        assert!(!tree.exists());
+        assert!(remove_path(s(&tree), true, &canceled, no_progress).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_link_not_followed() {
+        let dir = test_dir("link");
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("keep"), "keep").unwrap();
+        let tree = dir.join("tree");
+        std::fs::create_dir_all(&tree).unwrap();
+        std::os::unix::fs::symlink(&outside, tree.join("link")).unwrap();
+        let canceled = AtomicBool::new(false);
+        assert_eq!(
+            remove_path(s(&tree), true, &canceled, |_, _| {}).unwrap(),
+            2
+        );
+        assert!(outside.join("keep").exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_ops() {
+        let ops = Ops::default();
+        let canceled = ops.start(1);
+        assert!(!ops.cancel(2));
+        assert!(ops.cancel(1));
+        assert!(canceled.load(Ordering::SeqCst));
+        ops.finish(1);
+        assert!(!ops.cancel(1));
+    }
+}
@@ -0,0 +1,204 @@
+//! The large files transferred in ranges, as parallel logical streams over the connection.
+//!
+//! A file is split into up to `streams` contiguous ranges of at least [`MIN_RANGE_SIZE`]. Each range
+//! is read by its own task with its own file handle, up to [`WINDOW_PER_STREAM`] blocks ahead, and
+//! the blocks are sent in the order they are read, each with its offset and its stream, so the
+//! receiver writes it at its place whatever the order of the arrival. The streams are used only if
+//! the receiver asks for them by `streams` of the send or receive request. The old peers ignore it,
+//! and the blocks are sent in sequence, with the stream 0.
+//!
+//! A file is split only once it's confirmed, so the digest check of the existing file, to skip or
+//! resume it, is the same as the sequential transfer.
+
+use std::{io::SeekFrom, path::PathBuf};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+
+/// The local option of the streams of a large file, 1 (not split) by default.
+pub const OPTION_FILE_TRANSFER_STREAMS: &str = "file-transfer-streams";
+pub const MAX_STREAMS: u32 = 8;
+pub const MIN_RANGE_SIZE: u64 = 4 * 1024 * 1024;
+/// The blocks of each range read ahead and not sent yet.
+pub const WINDOW_PER_STREAM: usize = 2;
+
+pub fn streams_of(option: &str) -> u32 {
+    match option.trim().parse::<u32>() {
+        Ok(streams) if streams > 0 => streams.min(MAX_STREAMS),
+        _ => 1,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Starts from 1, 0 is the sequential transfer.
+    pub stream: u32,
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// The block read from a range.
+pub type RangedBlock = std::io::Result<(Chunk, Vec<u8>)>;
+
+/// Reads the ranges of a file in parallel, the reading tasks are stopped once it's dropped.
+#[derive(Debug)]
+pub struct RangedReader {
+    streams: u32,
+    rx: mpsc::Receiver<RangedBlock>,
+}
+
+impl RangedReader {
+    /// `None` if the file is too small to be split.
+    pub fn new(path: PathBuf, size: u64, streams: u32, block_size: usize) -> Option<Self> {
+        let ranges = split(size, streams, block_size)?;
+        let (tx, rx) = mpsc::channel(ranges.len() * WINDOW_PER_STREAM);
+        for (i, range) in ranges.iter().enumerate() {
+            tokio::spawn(read_range(
+                path.clone(),
+                i as u32 + 1,
+                *range,
+                block_size,
+                tx.clone(),
+            ));
+        }
+        Some(Self {
+            streams: ranges.len() as _,
+            rx,
+        })
+    }
+
+    #[inline]
+    pub fn streams(&self) -> u32 {
+        self.streams
+    }
+
+    /// The next block read by any of the ranges, `None` once all are read.
+    /// A range stops at its first error.
+    pub async fn next_block(&mut self) -> Option<RangedBlock> {
+        self.rx.recv().await
+    }
+}
+
+// Up to `streams` ranges, aligned to the blocks, the last one takes the rest.
+fn split(size: u64, streams: u32, block_size: usize) -> Option<Vec<(u64, u64)>> {
+    let streams = (streams.min(MAX_STREAMS) as u64).min(size / MIN_RANGE_SIZE);
+    if streams < 2 {
+        return None;
+    }
+    let block = block_size as u64;
+    let range_size = (size / streams).div_ceil(block) * block;
+    Some(
+        (0..streams)
+            .map(|i| (i * range_size, ((i + 1) * range_size).min(size)))
+            .filter(|(start, end)| start < end)
+            .collect(),
+    )
+}
+
+async fn read_range(
+    path: PathBuf,
+    stream: u32,
+    (mut offset, end): (u64, u64),
+    block_size: usize,
+    tx: mpsc::Sender<RangedBlock>,
+) {
+    let mut file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(err) => {
+            tx.send(Err(err)).await.ok();
+            return;
+        }
+    };
+    if let Err(err) = file.seek(SeekFrom::Start(offset)).await {
+        tx.send(Err(err)).await.ok();
+        return;
+    }
+    while offset < end {
+        let len = (end - offset).min(block_size as u64) as usize;
+        let mut buf = vec![0; len];
+        if let Err(err) = file.read_exact(&mut buf).await {
+            tx.send(Err(err)).await.ok();
+            return;
+        }
+        let chunk = Chunk {
+            stream,
+            offset,
+            len,
+        };
+        // The reader is dropped, e.g. the file is skipped or the job is cancelled.
+        if tx.send(Ok((chunk, buf))).await.is_err() {
+            return;
+        }
+        offset += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_streams_of() {
+        assert_eq!(streams_of(""), 1);
+        assert_eq!(streams_of("0"), 1);
+        assert_eq!(streams_of(" 4"), 4);
+        assert_eq!(streams_of("100"), MAX_STREAMS);
+    }
+
+    #[test]
+    fn test_split() {
+        // Too small to be split.
+        assert!(split(MIN_RANGE_SIZE * 2 - 1, 4, 1024).is_none());
+        assert!(split(100 * MB, 1, 1024).is_none());
+        assert_eq!(split(10 * MB, 4, 1024).unwrap().len(), 2);
+
+        let size = 17 * MB + 123;
+        let ranges = split(size, 4, 256 * 1024).unwrap();
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[1].0 % (256 * 1024), 0);
+        let mut end = 0;
+        for (start, range_end) in ranges {
+            assert_eq!(start, end);
+            end = range_end;
+        }
+        assert_eq!(end, size);
+    }
+
+    #[tokio::test]
+    async fn test_ranged_reader() {
+        let size = 9 * MB + 123;
+        let block_size = 256 * 1024;
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("multi-stream-test-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let mut reader = RangedReader::new(path.clone(), size, 4, block_size).unwrap();
+        assert_eq!(reader.streams(), 2);
+        let mut blocks = vec![];
+        while let Some(block) = reader.next_block().await {
+            blocks.push(block.unwrap());
+        }
+        std::fs::remove_file(&path).ok();
+        assert!(blocks.iter().any(|(c, _)| c.stream == 1));
+        assert!(blocks.iter().any(|(c, _)| c.stream == 2));
+        // All the bytes, each once.
+        blocks.sort_by_key(|(c, _)| c.offset);
+        let mut end = 0;
+        for (c, buf) in blocks.iter() {
+            assert_eq!(c.offset, end);
+            assert!(c.len > 0 && c.len <= block_size);
+            assert_eq!(buf.as_slice(), &data[c.offset as usize..][..c.len]);
+            end += c.len as u64;
+        }
+        assert_eq!(end, size);
+
+        // The error of a range is returned.
+        let mut reader = RangedReader::new(path, size, 4, block_size).unwrap();
+        assert!(reader.next_block().await.unwrap().is_err());
+    }
+}
@@ -16,12 +16,14 @@ use std::{
 };
 pub use tokio;
 pub use tokio_util;
+pub mod proxy;
 pub mod socket_client;
 pub mod tcp;
 pub mod udp;
 pub use env_logger;
 pub use log;
 pub mod bytes_codec;
+pub mod log_ring;
 #[cfg(feature = "quic")]
 pub mod quic;
 pub use anyhow::{self, bail};
@@ -333,7 +335,12 @@ pub fn init_log(_is_async: bool, _name: &str) -> Option<flexi_logger::LoggerHand
     #[cfg(debug_assertions)]
     {
         use env_logger::*;
-        init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
+        let env = Env::default().filter_or(DEFAULT_FILTER_ENV, "info");
+        let logger = Builder::from_env(env).build();
+        let max_level = logger.filter();
+        if log::set_boxed_logger(Box::new(log_ring::RingLogger::new(logger))).is_ok() {
+            log::set_max_level(max_level);
+        }
         None
     }
     #[cfg(not(debug_assertions))]
@@ -350,8 +357,14 @@ pub fn init_log(_is_async: bool, _name: &str) -> Option<flexi_logger::LoggerHand
             path.push(_name);
         }
         use flexi_logger::*;
-        if let Ok(x) = Logger::try_with_env_or_str("debug") {
-            logger_holder = x
+        if let Ok(spec) = LogSpecification::env_or_parse("debug") {
+            let max_level = spec
+                .module_filters()
+                .iter()
+                .map(|f| f.level_filter)
+                .max()
+                .unwrap_or(log::LevelFilter::Off);
+            logger_holder = Logger::with(spec)
                 .log_to_file(FileSpec::default().directory(path))
                 .write_mode(if _is_async {
                     WriteMode::Async
@@ -364,8 +377,14 @@ pub fn init_log(_is_async: bool, _name: &str) -> Option<flexi_logger::LoggerHand
                     Naming::Timestamps,
                     Cleanup::KeepLogFiles(6),
                 )
-                .start()
-                .ok();
+                .build()
+                .ok()
+                .and_then(|(logger, handle)| {
+                    // The ring is fed alongside the files, see `log_ring`.
+                    log::set_boxed_logger(Box::new(log_ring::RingLogger::new(logger))).ok()?;
+                    log::set_max_level(max_level);
+                    Some(handle)
+                });
         }
         logger_holder
     }
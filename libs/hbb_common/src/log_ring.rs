@@ -0,0 +1,141 @@
+//! A bounded ring of the recent log records, fed alongside the file logger.
+//!
+//! Every record takes one slot of the ring by an atomic sequence number, so the writers only
+//! lock their own slot and the ring can stay enabled in release builds.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    mpsc::SyncSender,
+    Mutex, RwLock,
+};
+
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: i64,
+    pub target: String,
+    pub message: String,
+}
+
+lazy_static::lazy_static! {
+    static ref RING: Vec<Mutex<Option<(usize, LogRecord)>>> =
+        (0..CAPACITY).map(|_| Mutex::new(None)).collect();
+    static ref LISTENER: RwLock<Option<SyncSender<LogRecord>>> = Default::default();
+    static ref PASSWORD_PATTERN: regex::Regex = regex::Regex::new(
+        r#"(?i)(pass(?:word|wd)?|pwd)("?\s*[:=]\s*"?)(\[[^\]]*\]|[^"\s,&}]+)"#
+    )
+    .unwrap();
+}
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+static HAS_LISTENER: AtomicBool = AtomicBool::new(false);
+
+/// Forward the records to the inner logger and keep the enabled ones in the ring.
+pub struct RingLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> RingLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+}
+
+impl<L: Log> Log for RingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+        push(LogRecord {
+            level: record.level(),
+            timestamp: crate::get_time(),
+            target: record.target().to_owned(),
+            message: redact(&record.args().to_string()),
+        });
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+fn push(record: LogRecord) {
+    if HAS_LISTENER.load(Ordering::Relaxed) {
+        if let Some(tx) = LISTENER.read().unwrap().as_ref() {
+            // Dropped if the listener is slow, it must not block the logging thread.
+            let _ = tx.try_send(record.clone());
+        }
+    }
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    *RING[seq % CAPACITY].lock().unwrap() = Some((seq, record));
+}
+
+/// The recent records not less severe than `level`, the oldest first, at most `limit`.
+pub fn recent(level: LevelFilter, limit: usize) -> Vec<LogRecord> {
+    let mut records = RING
+        .iter()
+        .filter_map(|slot| slot.lock().unwrap().clone())
+        .filter(|(_, r)| r.level <= level)
+        .collect::<Vec<_>>();
+    records.sort_by_key(|(seq, _)| *seq);
+    let skip = records.len().saturating_sub(limit);
+    records.into_iter().skip(skip).map(|(_, r)| r).collect()
+}
+
+/// Receive the new records, `None` to stop.
+pub fn set_listener(tx: Option<SyncSender<LogRecord>>) {
+    let mut lock = LISTENER.write().unwrap();
+    HAS_LISTENER.store(tx.is_some(), Ordering::Relaxed);
+    *lock = tx;
+}
+
+/// Mask the values of the password fields, in case they are logged by mistake.
+pub fn redact(message: &str) -> String {
+    PASSWORD_PATTERN
+        .replace_all(message, "$1$2***")
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("password=abc&id=1"), "password=***&id=1");
+        assert_eq!(
+            redact(r#"{"Password": "abc", "id": 1}"#),
+            r#"{"Password": "***", "id": 1}"#
+        );
+        assert_eq!(redact("passwd: [1, 2, 3], id: 1"), "passwd: ***, id: 1");
+        assert_eq!(redact("no secret here"), "no secret here");
+    }
+
+    #[test]
+    fn test_recent() {
+        for i in 0..CAPACITY + 10 {
+            let level = if i % 2 == 0 { Level::Info } else { Level::Debug };
+            push(LogRecord {
+                level,
+                timestamp: 0,
+                target: "test".to_owned(),
+                message: i.to_string(),
+            });
+        }
+        let all = recent(LevelFilter::Trace, usize::MAX);
+        assert_eq!(all.len(), CAPACITY);
+        assert_eq!(all.last().unwrap().message, (CAPACITY + 9).to_string());
+        let info = recent(LevelFilter::Info, 3);
+        let messages = info.into_iter().map(|r| r.message).collect::<Vec<_>>();
+        let expected = [CAPACITY + 4, CAPACITY + 6, CAPACITY + 8].map(|i| i.to_string());
+        assert_eq!(messages, expected.to_vec());
+    }
+}
@@ -1,9 +1,44 @@
 use crate::config::Config;
 use sodiumoxide::base64;
-use std::sync::{Arc, RwLock};
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
 lazy_static::lazy_static! {
-    pub static ref TEMPORARY_PASSWORD:Arc<RwLock<String>> = Arc::new(RwLock::new(Config::get_auto_password(temporary_password_length())));
+    pub static ref TEMPORARY_PASSWORD:Arc<RwLock<TemporaryPassword>> = Arc::new(RwLock::new(TemporaryPassword::new()));
+}
+
+pub struct TemporaryPassword {
+    password: String,
+    created: Instant,
+}
+
+impl TemporaryPassword {
+    fn new() -> Self {
+        Self {
+            password: Config::get_auto_password(temporary_password_length()),
+            created: Instant::now(),
+        }
+    }
+}
+
+/// The form of the password which authorized a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordForm {
+    Permanent,
+    Temporary,
+    OneTime,
+}
+
+impl PasswordForm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Permanent => "permanent",
+            Self::Temporary => "temporary",
+            Self::OneTime => "one-time",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,12 +57,75 @@ pub enum ApproveMode {
 
 // Should only be called in server
 pub fn update_temporary_password() {
-    *TEMPORARY_PASSWORD.write().unwrap() = Config::get_auto_password(temporary_password_length());
+    *TEMPORARY_PASSWORD.write().unwrap() = TemporaryPassword::new();
 }
 
 // Should only be called in server
 pub fn temporary_password() -> String {
-    TEMPORARY_PASSWORD.read().unwrap().clone()
+    rotate_expired(&TEMPORARY_PASSWORD, one_time_ttl());
+    TEMPORARY_PASSWORD.read().unwrap().password.clone()
+}
+
+/// Verify the temporary password. In the one-time mode, the password is rotated once it's used.
+/// The check and the rotation are done under the same write lock, so two connections can't
+/// consume the same one-time password.
+// Should only be called in server
+pub fn verify_temporary_password(verify: impl Fn(&str) -> bool) -> Option<(String, PasswordForm)> {
+    verify_temporary_password_(
+        &TEMPORARY_PASSWORD,
+        one_time_enabled(),
+        one_time_ttl(),
+        verify,
+    )
+}
+
+fn verify_temporary_password_(
+    store: &RwLock<TemporaryPassword>,
+    one_time: bool,
+    ttl: Option<Duration>,
+    verify: impl Fn(&str) -> bool,
+) -> Option<(String, PasswordForm)> {
+    rotate_expired(store, ttl);
+    let mut current = store.write().unwrap();
+    if current.password.is_empty() || !verify(&current.password) {
+        return None;
+    }
+    if !one_time {
+        return Some((current.password.clone(), PasswordForm::Temporary));
+    }
+    let consumed = std::mem::replace(&mut *current, TemporaryPassword::new());
+    Some((consumed.password, PasswordForm::OneTime))
+}
+
+fn rotate_expired(store: &RwLock<TemporaryPassword>, ttl: Option<Duration>) -> bool {
+    let Some(ttl) = ttl else {
+        return false;
+    };
+    if store.read().unwrap().created.elapsed() < ttl {
+        return false;
+    }
+    let mut current = store.write().unwrap();
+    // Another thread may have rotated it.
+    if current.created.elapsed() < ttl {
+        return false;
+    }
+    *current = TemporaryPassword::new();
+    true
+}
+
+pub fn one_time_enabled() -> bool {
+    Config::get_option("temporary-password-mode") == "one-time"
+}
+
+// The ttl of the one-time password in seconds, 0 means it's only rotated after use.
+fn one_time_ttl() -> Option<Duration> {
+    if !one_time_enabled() {
+        return None;
+    }
+    match Config::get_option("temporary-password-ttl").parse::<u64>() {
+        Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+        _ => None,
+    }
 }
 
 fn verification_method() -> VerificationMethod {
@@ -189,6 +287,58 @@ pub fn symmetric_crypt(data: &[u8], encrypt: bool) -> Result<Vec<u8>, ()> {
 
 mod test {
 
+    #[test]
+    fn test_one_time_password() {
+        use super::*;
+        use std::sync::Barrier;
+
+        let new_store = || {
+            Arc::new(RwLock::new(TemporaryPassword {
+                password: "one-time".to_owned(),
+                created: Instant::now(),
+            }))
+        };
+        let verify = |p: &str| p == "one-time";
+
+        let store = new_store();
+        for _ in 0..2 {
+            let (password, form) = verify_temporary_password_(&store, false, None, verify).unwrap();
+            assert_eq!(password, "one-time");
+            assert_eq!(form, PasswordForm::Temporary);
+        }
+
+        let (password, form) = verify_temporary_password_(&store, true, None, verify).unwrap();
+        assert_eq!(password, "one-time");
+        assert_eq!(form, PasswordForm::OneTime);
+        assert_ne!(store.read().unwrap().password, "one-time");
+        assert!(verify_temporary_password_(&store, true, None, verify).is_none());
+
+        // Two simultaneous verifications, only one of them can consume the password.
+        for _ in 0..20 {
+            let store = new_store();
+            let barrier = Arc::new(Barrier::new(2));
+            let handles = (0..2)
+                .map(|_| {
+                    let store = store.clone();
+                    let barrier = barrier.clone();
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        verify_temporary_password_(&store, true, None, verify)
+                    })
+                })
+                .collect::<Vec<_>>();
+            let consumed = handles.into_iter().filter_map(|h| h.join().unwrap()).count();
+            assert_eq!(consumed, 1);
+        }
+
+        // Expired by the ttl.
+        let store = new_store();
+        store.write().unwrap().created = Instant::now() - Duration::from_secs(10);
+        let ttl = Some(Duration::from_secs(5));
+        assert!(verify_temporary_password_(&store, true, ttl, verify).is_none());
+        assert!(!rotate_expired(&store, ttl));
+    }
+
     #[test]
     fn test() {
         use super::*;
@@ -1,5 +1,7 @@
 use crate::{
+    bail,
     config::{Config, NetworkType},
+    proxy::{self, Proxy},
     tcp::FramedStream,
     udp::FramedSocket,
     ResultType,
@@ -65,6 +67,14 @@ pub fn test_if_valid_server(host: &str) -> String {
     }
 }
 
+/// The global proxy "[scheme://]host:port", see [`Proxy::parse`].
+pub fn test_if_valid_proxy(proxy: &str) -> String {
+    match Proxy::parse(proxy) {
+        Err(err) => err.to_string(),
+        Ok(proxy) => test_if_valid_server(&proxy.addr),
+    }
+}
+
 pub trait IsResolvedSocketAddr {
     fn resolve(&self) -> Option<&SocketAddr>;
 }
@@ -106,16 +116,27 @@ pub async fn connect_tcp_local<
     local: Option<SocketAddr>,
     ms_timeout: u64,
 ) -> ResultType<FramedStream> {
-    if let Some(conf) = Config::get_socks() {
-        return FramedStream::connect(
-            conf.proxy.as_str(),
-            target,
-            local,
-            conf.username.as_str(),
-            conf.password.as_str(),
-            ms_timeout,
-        )
-        .await;
+    let proxy = proxy::global()?;
+    connect_tcp_via(target, local, proxy.as_ref(), ms_timeout).await
+}
+
+/// Connect via `proxy`, directly if `None` or the target is matched by [`proxy::NoProxy`].
+pub async fn connect_tcp_via<
+    't,
+    T: IntoTargetAddr<'t> + ToSocketAddrs + IsResolvedSocketAddr + std::fmt::Display,
+>(
+    target: T,
+    local: Option<SocketAddr>,
+    proxy: Option<&Proxy>,
+    ms_timeout: u64,
+) -> ResultType<FramedStream> {
+    if let Some(proxy) = proxy {
+        let target = target.to_string();
+        if !proxy::NoProxy::get().matches(&target) {
+            return proxy
+                .connect(&target, local, proxy::Dns::get(), ms_timeout)
+                .await;
+        }
     }
     if let Some(target) = target.resolve() {
         if let Some(local) = local {
@@ -184,14 +205,17 @@ pub async fn new_udp_for(
 }
 
 async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<FramedSocket> {
-    match Config::get_socks() {
+    match proxy::global()? {
         None => Ok(FramedSocket::new(local).await?),
-        Some(conf) => {
+        Some(proxy) => {
+            if proxy.scheme != proxy::Scheme::Socks5 {
+                bail!("UDP is not supported by the HTTP proxy");
+            }
             let socket = FramedSocket::new_proxy(
-                conf.proxy.as_str(),
+                proxy.addr.as_str(),
                 local,
-                conf.username.as_str(),
-                conf.password.as_str(),
+                proxy.username.as_str(),
+                proxy.password.as_str(),
                 ms_timeout,
             )
             .await?;
@@ -259,6 +283,9 @@ mod tests {
         // on Linux, "1" is resolved to "0.0.0.1"
         assert!(test_if_valid_server("1.1.1.1").is_empty());
         assert!(test_if_valid_server("1.1.1.1:1").is_empty());
+        assert!(test_if_valid_proxy("http://1.1.1.1:3128").is_empty());
+        assert!(test_if_valid_proxy("socks5://1.1.1.1:1080").is_empty());
+        assert!(!test_if_valid_proxy("ftp://1.1.1.1:21").is_empty());
     }
 
     #[test]
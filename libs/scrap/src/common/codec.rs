@@ -14,7 +14,7 @@ use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
-    CodecName, EncodeYuvFormat, ImageRgb,
+    CodecFormat, CodecName, EncodeYuvFormat, ImageRgb,
 };
 
 use hbb_common::{
@@ -310,6 +310,13 @@ impl Decoder {
     }
 
     pub fn new() -> Decoder {
+        Self::new_with(true)
+    }
+
+    /// Only the software decoders are created if `hardware` is false.
+    pub fn new_with(hardware: bool) -> Decoder {
+        #[cfg(not(any(feature = "hwcodec", feature = "mediacodec")))]
+        let _ = hardware;
         let vp8 = VpxDecoder::new(VpxDecoderConfig {
             codec: VpxVideoCodecId::VP8,
         })
@@ -324,7 +331,7 @@ impl Decoder {
             vp9,
             av1,
             #[cfg(feature = "hwcodec")]
-            hw: if enable_hwcodec_option() {
+            hw: if hardware && enable_hwcodec_option() {
                 HwDecoder::new_decoders()
             } else {
                 HwDecoders::default()
@@ -332,7 +339,7 @@ impl Decoder {
             #[cfg(feature = "hwcodec")]
             i420: vec![],
             #[cfg(feature = "mediacodec")]
-            media_codec: if enable_hwcodec_option() {
+            media_codec: if hardware && enable_hwcodec_option() {
                 MediaCodecDecoder::new_decoders()
             } else {
                 MediaCodecDecoders::default()
@@ -340,6 +347,45 @@ impl Decoder {
         }
     }
 
+    /// Whether the decoder of `format` is created.
+    pub fn initialized(&self, format: &CodecFormat) -> bool {
+        match format {
+            CodecFormat::VP8 => self.vp8.is_some(),
+            CodecFormat::VP9 => self.vp9.is_some(),
+            CodecFormat::AV1 => self.av1.is_some(),
+            #[cfg(feature = "hwcodec")]
+            CodecFormat::H264 => self.hw.h264.is_some(),
+            #[cfg(feature = "hwcodec")]
+            CodecFormat::H265 => self.hw.h265.is_some(),
+            #[cfg(feature = "mediacodec")]
+            CodecFormat::H264 => self.media_codec.h264.is_some(),
+            #[cfg(feature = "mediacodec")]
+            CodecFormat::H265 => self.media_codec.h265.is_some(),
+            _ => false,
+        }
+    }
+
+    /// The name of the hardware decoder of `format`, `None` if it's not created.
+    pub fn hw_adapter(&self, _format: &CodecFormat) -> Option<String> {
+        #[cfg(feature = "hwcodec")]
+        match _format {
+            CodecFormat::H264 => return self.hw.h264.as_ref().map(|d| d.info.name.clone()),
+            CodecFormat::H265 => return self.hw.h265.as_ref().map(|d| d.info.name.clone()),
+            _ => {}
+        }
+        #[cfg(feature = "mediacodec")]
+        match _format {
+            CodecFormat::H264 if self.media_codec.h264.is_some() => {
+                return Some("MediaCodec".to_owned())
+            }
+            CodecFormat::H265 if self.media_codec.h265.is_some() => {
+                return Some("MediaCodec".to_owned())
+            }
+            _ => {}
+        }
+        None
+    }
+
     // rgb [in/out] fmt and stride must be set in ImageRgb
     pub fn handle_video_frame(
         &mut self,
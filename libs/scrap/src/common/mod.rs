@@ -381,8 +381,32 @@ pub trait GoogleImage {
                         self.height() as _,
                     );
                 }
-                // (Chroma::I444, ImageFormat::Raw), new version libyuv have I444ToRAW
-                _ => log::error!("unsupported pixfmt: {:?}", self.chroma()),
+                (Chroma::I444, ImageFormat::Raw) => {
+                    // No I444ToRAW in the old libyuv, converted by ARGB with one more copy.
+                    let argb_stride =
+                        Self::get_bytes_per_row(rgb.w, ImageFormat::ARGB, rgb.stride());
+                    let mut argb = vec![0u8; rgb.h * argb_stride];
+                    super::I444ToARGB(
+                        planes[0],
+                        stride[0],
+                        planes[1],
+                        stride[1],
+                        planes[2],
+                        stride[2],
+                        argb.as_mut_ptr(),
+                        argb_stride as _,
+                        self.width() as _,
+                        self.height() as _,
+                    );
+                    super::ARGBToRAW(
+                        argb.as_ptr(),
+                        argb_stride as _,
+                        rgb.raw.as_mut_ptr(),
+                        bytes_per_row as _,
+                        self.width() as _,
+                        self.height() as _,
+                    );
+                }
             }
         }
     }
@@ -27,6 +27,8 @@ pub struct RecorderContext {
     pub server: bool,
     pub id: String,
     pub default_dir: String,
+    /// The directory to save to, the video save directory option or `default_dir` if empty.
+    pub dir: String,
     pub filename: String,
     pub width: usize,
     pub height: usize,
@@ -36,7 +38,10 @@ pub struct RecorderContext {
 
 impl RecorderContext {
     pub fn set_filename(&mut self) -> ResultType<()> {
-        let mut dir = Config::get_option("video-save-directory");
+        let mut dir = self.dir.clone();
+        if dir.is_empty() {
+            dir = Config::get_option("video-save-directory");
+        }
         if !dir.is_empty() {
             if !PathBuf::from(&dir).exists() {
                 std::fs::create_dir_all(&dir)?;
@@ -0,0 +1,361 @@
+//! TOTP (RFC 6238) second factor for the incoming connections.
+//!
+//! The secret and the salted hashes of the recovery codes are stored in the options of the
+//! server process, they can only be changed by [`crate::ipc::Data::Config`], the bulk option
+//! sync keeps them as they are.
+
+use hbb_common::{
+    bail,
+    config::Config,
+    log,
+    password_security::{decrypt_str_or_original, encrypt_str_or_original},
+    rand::{self, Rng},
+    ResultType,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+pub const OPTION_2FA: &str = "2fa";
+pub const OPTION_2FA_RECOVERY: &str = "2fa-recovery";
+/// The number of the time steps accepted before and after the current one.
+pub const OPTION_2FA_SKEW: &str = "2fa-skew";
+pub const PROTECTED_OPTIONS: [&str; 2] = [OPTION_2FA, OPTION_2FA_RECOVERY];
+
+const ENC_VERSION: &str = "00";
+const ENCRYPT_MAX_LEN: usize = 128;
+const SECRET_LEN: usize = 20;
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const DEFAULT_SKEW: u64 = 1;
+const MAX_SKEW: u64 = 5;
+const RECOVERY_CODE_NUM: usize = 8;
+const RECOVERY_CODE_CHARS: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_SALT_LEN: usize = 16;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+lazy_static::lazy_static! {
+    // The secret generated by `setup`, waiting for the first code.
+    static ref PENDING_SECRET: Mutex<Option<String>> = Default::default();
+    // The recovery codes are consumed one by one, a code can not be used by two connections.
+    static ref RECOVERY_LOCK: Mutex<()> = Default::default();
+}
+// The last accepted time step, a code can not be used twice.
+static LAST_STEP: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct Setup {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    !Config::get_option(OPTION_2FA).is_empty()
+}
+
+/// Generate a new secret and its provisioning uri.
+/// The secret takes effect after [`enable`] with a valid code.
+pub fn setup(id: &str) -> Setup {
+    let secret = base32_encode(&rand::random::<[u8; SECRET_LEN]>());
+    *PENDING_SECRET.lock().unwrap() = Some(secret.clone());
+    let issuer = crate::get_app_name();
+    let uri = format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        uri_encode(&issuer),
+        uri_encode(id),
+        secret,
+        uri_encode(&issuer),
+        DIGITS,
+        STEP
+    );
+    Setup { secret, uri }
+}
+
+/// Enable the second factor with the secret of the last [`setup`].
+pub fn enable(code: &str) -> ResultType<()> {
+    let Some(secret) = PENDING_SECRET.lock().unwrap().clone() else {
+        bail!("No pending 2FA setup");
+    };
+    if check_totp(&secret, code, now_secs(), DEFAULT_SKEW).is_none() {
+        bail!("Wrong 2FA Code");
+    }
+    store(OPTION_2FA, encrypt_str_or_original(&secret, ENC_VERSION, ENCRYPT_MAX_LEN))?;
+    store(OPTION_2FA_RECOVERY, "".to_owned())?;
+    *PENDING_SECRET.lock().unwrap() = None;
+    log::info!("2FA enabled");
+    Ok(())
+}
+
+/// Disable the second factor, must only be called on local confirmation.
+pub fn disable() -> ResultType<()> {
+    store(OPTION_2FA, "".to_owned())?;
+    store(OPTION_2FA_RECOVERY, "".to_owned())?;
+    log::info!("2FA disabled");
+    Ok(())
+}
+
+/// Replace the recovery codes, must only be called on local confirmation.
+/// Only the salted hashes are stored, the returned codes can not be shown again.
+pub fn generate_recovery_codes() -> ResultType<Vec<String>> {
+    if !is_enabled() {
+        bail!("2FA is not enabled");
+    }
+    let mut rng = rand::thread_rng();
+    let codes = (0..RECOVERY_CODE_NUM)
+        .map(|_| {
+            let chars: String = (0..8)
+                .map(|_| RECOVERY_CODE_CHARS[rng.gen_range(0..RECOVERY_CODE_CHARS.len())] as char)
+                .collect();
+            format!("{}-{}", &chars[..4], &chars[4..])
+        })
+        .collect::<Vec<_>>();
+    let hashes = codes
+        .iter()
+        .map(|c| {
+            let salt = hex::encode(rand::random::<[u8; RECOVERY_SALT_LEN]>());
+            format!("{}${}", salt, hash_recovery_code(&salt, c))
+        })
+        .collect::<Vec<_>>();
+    store(OPTION_2FA_RECOVERY, hashes.join(","))?;
+    log::info!("2FA recovery codes regenerated");
+    Ok(codes)
+}
+
+/// Verify the code sent by the peer, either a TOTP code or an unused recovery code.
+/// Called in the server process.
+pub fn verify(code: &str) -> bool {
+    let (secret, _, _) = decrypt_str_or_original(&Config::get_option(OPTION_2FA), ENC_VERSION);
+    if secret.is_empty() {
+        return false;
+    }
+    let code = code.trim();
+    if code.len() == DIGITS as usize {
+        let Some(step) = check_totp(&secret, code, now_secs(), skew()) else {
+            return false;
+        };
+        // fetch_max returns the previous value, equal means replay.
+        return LAST_STEP.fetch_max(step, Ordering::SeqCst) < step;
+    }
+    let _lock = RECOVERY_LOCK.lock().unwrap();
+    let mut hashes = Config::get_option(OPTION_2FA_RECOVERY)
+        .split(',')
+        .filter(|h| !h.is_empty())
+        .map(|h| h.to_owned())
+        .collect::<Vec<_>>();
+    let Some(pos) = hashes.iter().position(|h| match_recovery_code(h, code)) else {
+        return false;
+    };
+    hashes.remove(pos);
+    Config::set_option(OPTION_2FA_RECOVERY.to_owned(), hashes.join(","));
+    log::info!("2FA recovery code used, {} left", hashes.len());
+    true
+}
+
+/// Whether the protected option can be changed, called in the server process.
+/// Only enabling is allowed while the device is remote controlled, the confirmation of
+/// disabling and the recovery codes must be local.
+pub fn allow_change(name: &str, value: &str) -> bool {
+    if !crate::server::is_remote_controlled() {
+        return true;
+    }
+    (name == OPTION_2FA && !is_enabled()) || (name == OPTION_2FA_RECOVERY && value.is_empty())
+}
+
+/// Keep the protected options of the server process when the options are replaced as a whole.
+pub fn keep_protected_options(options: &mut HashMap<String, String>) {
+    for k in PROTECTED_OPTIONS {
+        let v = Config::get_option(k);
+        if v.is_empty() {
+            options.remove(k);
+        } else {
+            options.insert(k.to_owned(), v);
+        }
+    }
+}
+
+fn store(name: &str, value: String) -> ResultType<()> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        crate::ipc::set_config(name, value.clone())?;
+        if crate::ipc::get_config(name)?.unwrap_or_default() != value {
+            bail!("2fa_local_only_tip");
+        }
+    }
+    #[cfg(target_os = "android")]
+    if !allow_change(name, &value) {
+        bail!("2fa_local_only_tip");
+    }
+    Config::set_option(name.to_owned(), value);
+    Ok(())
+}
+
+fn skew() -> u64 {
+    Config::get_option(OPTION_2FA_SKEW)
+        .parse()
+        .unwrap_or(DEFAULT_SKEW)
+        .min(MAX_SKEW)
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn hash_recovery_code(salt: &str, code: &str) -> String {
+    let normalized = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(normalized.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+// The stored hash is "<salt>$<hash>", or the unsalted hash generated by the old versions.
+fn match_recovery_code(stored: &str, code: &str) -> bool {
+    let (salt, hash) = stored.split_once('$').unwrap_or(("", stored));
+    hash_recovery_code(salt, code) == hash
+}
+
+/// Return the matched time step.
+fn check_totp(secret: &str, code: &str, now: u64, skew: u64) -> Option<u64> {
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let key = base32_decode(secret)?;
+    let code: u32 = code.parse().ok()?;
+    let current = now / STEP;
+    (current.saturating_sub(skew)..=current + skew)
+        .find(|&step| hotp(&key, step) == Some(code))
+}
+
+fn hotp(key: &[u8], counter: u64) -> Option<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).ok()?;
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let bin = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    Some(bin % 10u32.pow(DIGITS))
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &b in data {
+        buffer = (buffer << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            out.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in s.trim_end_matches('=').bytes() {
+        let v = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            out.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+    Some(out)
+}
+
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+    }
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238 test vectors, truncated to 6 digits.
+        let secret = base32_encode(b"12345678901234567890");
+        for (now, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(check_totp(&secret, code, now, 0), Some(now / STEP));
+        }
+        // The code of the previous step is only accepted within the skew window.
+        assert_eq!(check_totp(&secret, "081804", 1111111109 + STEP, 0), None);
+        assert_eq!(
+            check_totp(&secret, "081804", 1111111109 + STEP, 1),
+            Some(1111111109 / STEP)
+        );
+        assert_eq!(check_totp(&secret, "12345", 59, 1), None);
+    }
+
+    #[test]
+    fn test_recovery_code_hash() {
+        assert_eq!(
+            hash_recovery_code("", "abcd-efgh"),
+            hash_recovery_code("", " ABCDEFGH ")
+        );
+        assert_ne!(
+            hash_recovery_code("", "abcd-efgh"),
+            hash_recovery_code("", "abcd-efgj")
+        );
+        // The same code is hashed differently by the salts.
+        assert_ne!(
+            hash_recovery_code("01", "abcd-efgh"),
+            hash_recovery_code("02", "abcd-efgh")
+        );
+        let stored = format!("01${}", hash_recovery_code("01", "abcd-efgh"));
+        assert!(match_recovery_code(&stored, "ABCD EFGH"));
+        assert!(!match_recovery_code(&stored, "abcd-efgj"));
+        let wrong_salt = format!("02${}", hash_recovery_code("01", "abcd-efgh"));
+        assert!(!match_recovery_code(&wrong_salt, "abcd-efgh"));
+        // The unsalted ones of the old versions.
+        let unsalted = hash_recovery_code("", "abcd-efgh");
+        assert!(match_recovery_code(&unsalted, "abcd-efgh"));
+    }
+}
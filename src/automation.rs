@@ -0,0 +1,593 @@
+//! The automation of the remote sessions over the local ipc, for the scripts and the cli.
+//!
+//! A client sends one [`Automation`] request, then reads the [`Automation::Response`]s until the
+//! one with `done`. The long-running requests, i.e. connecting and sending files, send their
+//! progress before. The sessions run without ui by [`HeadlessHandler`].
+//!
+//! Not as the other ipc by [`ipc::new_listener`], which all the local users can access, the socket
+//! is only of the owner, the unix socket of the mode 0600 or the named pipe of the default
+//! security. The clients of the other users are also rejected by their credentials, see
+//! [`Listener::accept`], as the requests connect to the peers by the saved passwords of the owner
+//! and send the files of the owner.
+
+use crate::{
+    client::{FileManager, Interface},
+    ipc::{self, Data},
+    ui_session_interface::{io_loop, Session},
+};
+use hbb_common::{
+    bail, config::Config, log, rendezvous_proto::ConnType, timeout, tokio, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+mod headless;
+pub use headless::{HeadlessHandler, SessionState};
+
+pub const IPC_POSTFIX: &str = "_automation";
+// The time to wait for the session to be connected, in milliseconds.
+const CONNECT_TIMEOUT: u64 = 30_000;
+
+lazy_static::lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<u64, Session<HeadlessHandler>>> = Default::default();
+}
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+// Not overlapping the job ids of the ui, which start from 0.
+static NEXT_JOB_ID: AtomicI32 = AtomicI32::new(1 << 24);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum Automation {
+    /// `conn_type`: "remote", "file-transfer" or "port-forward".
+    /// Done once connected, except the port forward sessions, which connect on the forwarded
+    /// connections.
+    Connect {
+        peer_id: String,
+        password: String,
+        conn_type: String,
+    },
+    /// Listen on `local_port`, forwarded to `remote_host:remote_port` by the peer.
+    PortForward {
+        session_id: u64,
+        local_port: i32,
+        remote_host: String,
+        remote_port: i32,
+    },
+    /// Upload the local `path` to the remote directory `to`, done once the job is done.
+    SendFile {
+        session_id: u64,
+        path: String,
+        to: String,
+        overwrite: bool,
+    },
+    State {
+        session_id: u64,
+    },
+    Close {
+        session_id: u64,
+    },
+    /// The result of a request, or the progress of it if not `done`.
+    /// The errors are `{"error": "..."}`.
+    Response {
+        done: bool,
+        result: Value,
+    },
+}
+
+/// Run the automation ipc server, the sessions are closed when the process exits.
+#[tokio::main]
+pub async fn start() {
+    if let Err(err) = listen(IPC_POSTFIX).await {
+        log::error!("Failed to start the automation ipc: {}", err);
+    }
+}
+
+async fn listen(postfix: &str) -> ResultType<()> {
+    serve(Listener::bind(postfix).await?).await
+}
+
+async fn serve(mut listener: Listener) -> ResultType<()> {
+    loop {
+        match listener.accept().await {
+            Ok(Some(stream)) => {
+                tokio::spawn(handle_connection(stream));
+            }
+            Ok(None) => {
+                log::warn!("Rejected the automation client of another user");
+            }
+            Err(err) => {
+                log::error!("Couldn't get automation client: {:?}", err);
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+type Connection = ipc::ConnectionTmpl<tokio::net::UnixStream>;
+#[cfg(windows)]
+type Connection = ipc::ConnectionTmpl<tokio::net::windows::named_pipe::NamedPipeServer>;
+
+#[cfg(not(windows))]
+struct Listener(tokio::net::UnixListener);
+
+#[cfg(not(windows))]
+impl Listener {
+    async fn bind(postfix: &str) -> ResultType<Self> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = Config::ipc_path(postfix);
+        if ipc::connect(1_000, postfix).await.is_ok() {
+            bail!("The automation ipc is already started at {}", path);
+        }
+        std::fs::remove_file(&path).ok();
+        let listener = tokio::net::UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o0600))?;
+        log::info!("Started automation ipc server at path: {}", path);
+        Ok(Self(listener))
+    }
+
+    /// `None` if the client is not of the owner.
+    async fn accept(&mut self) -> ResultType<Option<Connection>> {
+        let (stream, _) = self.0.accept().await?;
+        let uid = stream.peer_cred()?.uid();
+        if uid != unsafe { hbb_common::libc::geteuid() } {
+            return Ok(None);
+        }
+        Ok(Some(Connection::new(stream)))
+    }
+}
+
+#[cfg(windows)]
+struct Listener {
+    path: String,
+    pipe: tokio::net::windows::named_pipe::NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl Listener {
+    async fn bind(postfix: &str) -> ResultType<Self> {
+        let path = Config::ipc_path(postfix);
+        // The default security only allows the owner, the system and the admins to write.
+        let pipe = tokio::net::windows::named_pipe::ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&path)?;
+        log::info!("Started automation ipc server at path: {}", path);
+        Ok(Self { path, pipe })
+    }
+
+    /// `None` if the client is not of the owner.
+    async fn accept(&mut self) -> ResultType<Option<Connection>> {
+        use std::os::windows::io::AsRawHandle;
+        self.pipe.connect().await?;
+        let next = tokio::net::windows::named_pipe::ServerOptions::new().create(&self.path)?;
+        let pipe = std::mem::replace(&mut self.pipe, next);
+        let mut pid = 0;
+        if unsafe {
+            winapi::um::winbase::GetNamedPipeClientProcessId(pipe.as_raw_handle() as _, &mut pid)
+        } == 0
+        {
+            bail!(
+                "Failed to get the automation client, error {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        if !crate::platform::windows::is_process_of_current_user(pid)? {
+            return Ok(None);
+        }
+        Ok(Some(Connection::new(pipe)))
+    }
+}
+
+async fn handle_connection(mut stream: Connection) {
+    loop {
+        match stream.next().await {
+            Err(err) => {
+                log::trace!("automation ipc connection closed: {}", err);
+                break;
+            }
+            Ok(Some(Data::Automation(request))) => {
+                let result = match handle(request, &mut stream).await {
+                    Ok(result) => result,
+                    Err(err) => json!({ "error": err.to_string() }),
+                };
+                if respond(&mut stream, true, result).await.is_err() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn respond(stream: &mut Connection, done: bool, result: Value) -> ResultType<()> {
+    stream
+        .send(&Data::Automation(Automation::Response { done, result }))
+        .await
+}
+
+async fn handle(request: Automation, stream: &mut Connection) -> ResultType<Value> {
+    match request {
+        Automation::Connect {
+            peer_id,
+            password,
+            conn_type,
+        } => {
+            let conn_type = parse_conn_type(&conn_type)?;
+            let (session_id, session) = session_add(&peer_id, password, conn_type)?;
+            let mut rx = session.ui_handler.subscribe();
+            session_start(session.clone());
+            respond(stream, false, json!({ "session_id": session_id })).await?;
+            if conn_type == ConnType::PORT_FORWARD {
+                session.ui_handler.unsubscribe();
+                return Ok(json!({ "session_id": session_id }));
+            }
+            let res = timeout(CONNECT_TIMEOUT, async {
+                while let Some(event) = rx.recv().await {
+                    match event["name"].as_str() {
+                        Some(headless::STATE_CONNECTED) => return Ok(()),
+                        Some(headless::STATE_CLOSED) => bail!("closed"),
+                        _ => {}
+                    }
+                    if session.ui_handler.state().state == headless::STATE_ERROR {
+                        bail!("{}", session.ui_handler.state().error);
+                    }
+                    respond(stream, false, event).await?;
+                }
+                bail!("closed")
+            })
+            .await;
+            session.ui_handler.unsubscribe();
+            match res {
+                Ok(Ok(())) => Ok(json!({
+                    "session_id": session_id,
+                    "state": session.ui_handler.state(),
+                })),
+                Ok(Err(err)) => {
+                    session_close(session_id);
+                    Err(err)
+                }
+                Err(_) => {
+                    session_close(session_id);
+                    bail!("Timeout");
+                }
+            }
+        }
+        Automation::PortForward {
+            session_id,
+            local_port,
+            remote_host,
+            remote_port,
+        } => {
+            let session = get_session(session_id)?;
+            if !session.is_port_forward() {
+                bail!("not a port forward session");
+            }
+            if local_port <= 0 || remote_port <= 0 {
+                bail!("invalid port");
+            }
+            session.send(crate::client::Data::AddPortForward((
+                local_port,
+                remote_host,
+                remote_port,
+            )));
+            Ok(json!({ "session_id": session_id, "local_port": local_port }))
+        }
+        Automation::SendFile {
+            session_id,
+            path,
+            to,
+            overwrite,
+        } => {
+            let session = get_session(session_id)?;
+            if !session.is_file_transfer() {
+                bail!("not a file transfer session");
+            }
+            if session.ui_handler.state().state != headless::STATE_CONNECTED {
+                bail!("not connected");
+            }
+            let job_id = NEXT_JOB_ID.fetch_add(1, Ordering::SeqCst);
+            let mut rx = session.ui_handler.subscribe();
+            session.send_files(job_id, path, to, 0, false, false);
+            let res = async {
+                while let Some(event) = rx.recv().await {
+                    let state = session.ui_handler.state();
+                    if state.state != headless::STATE_CONNECTED {
+                        bail!("{} {}", state.state, state.error);
+                    }
+                    if event["id"].as_i64() != Some(job_id as _) {
+                        continue;
+                    }
+                    match event["name"].as_str().unwrap_or_default() {
+                        "job_done" => return Ok(()),
+                        "job_error" => bail!("{}", event["err"].as_str().unwrap_or_default()),
+                        "override_file_confirm" => {
+                            let file_num = event["file_num"].as_i64().unwrap_or_default();
+                            session.set_confirm_override_file(
+                                job_id,
+                                file_num as _,
+                                overwrite,
+                                true,
+                                true,
+                            );
+                        }
+                        _ => {}
+                    }
+                    respond(stream, false, event).await?;
+                }
+                bail!("closed")
+            }
+            .await;
+            session.ui_handler.unsubscribe();
+            res?;
+            Ok(json!({ "session_id": session_id, "job_id": job_id }))
+        }
+        Automation::State { session_id } => Ok(json!(get_session(session_id)?.ui_handler.state())),
+        Automation::Close { session_id } => {
+            if !session_close(session_id) {
+                bail!("no session {}", session_id);
+            }
+            Ok(json!({ "session_id": session_id }))
+        }
+        Automation::Response { .. } => bail!("unexpected response"),
+    }
+}
+
+fn parse_conn_type(conn_type: &str) -> ResultType<ConnType> {
+    Ok(match conn_type {
+        "" | "remote" => ConnType::DEFAULT_CONN,
+        "file-transfer" => ConnType::FILE_TRANSFER,
+        "port-forward" => ConnType::PORT_FORWARD,
+        _ => bail!("unsupported conn type {}", conn_type),
+    })
+}
+
+/// Create a session without ui, the same as `flutter::session_add` of the desktop ui.
+fn session_add(
+    peer_id: &str,
+    password: String,
+    conn_type: ConnType,
+) -> ResultType<(u64, Session<HeadlessHandler>)> {
+    if peer_id.is_empty() {
+        bail!("empty peer id");
+    }
+    let session: Session<HeadlessHandler> = Session {
+        password,
+        server_keyboard_enabled: Arc::new(RwLock::new(true)),
+        server_file_transfer_enabled: Arc::new(RwLock::new(true)),
+        server_clipboard_enabled: Arc::new(RwLock::new(true)),
+        ..Default::default()
+    };
+    session
+        .lc
+        .write()
+        .unwrap()
+        .initialize(peer_id.to_owned(), conn_type, None, false);
+    session.ui_handler.init(peer_id, conn_type);
+    let session_id = NEXT_SESSION_ID.fetch_add(1, Ordering::SeqCst);
+    SESSIONS.lock().unwrap().insert(session_id, session.clone());
+    Ok((session_id, session))
+}
+
+fn session_start(session: Session<HeadlessHandler>) {
+    std::thread::spawn(move || {
+        let round = session.connection_round_state.lock().unwrap().new_round();
+        io_loop(session.clone(), round);
+        session.ui_handler.on_closed();
+    });
+}
+
+fn get_session(session_id: u64) -> ResultType<Session<HeadlessHandler>> {
+    match SESSIONS.lock().unwrap().get(&session_id) {
+        Some(session) => Ok(session.clone()),
+        None => bail!("no session {}", session_id),
+    }
+}
+
+fn session_close(session_id: u64) -> bool {
+    let session = SESSIONS.lock().unwrap().remove(&session_id);
+    if let Some(session) = session {
+        session.close();
+        true
+    } else {
+        false
+    }
+}
+
+/// Send the request to the automation ipc server, `on_progress` is called with the progress.
+pub async fn request(
+    request: Automation,
+    mut on_progress: impl FnMut(&Value),
+) -> ResultType<Value> {
+    request_(IPC_POSTFIX, request, &mut on_progress).await
+}
+
+async fn request_(
+    postfix: &str,
+    request: Automation,
+    on_progress: &mut impl FnMut(&Value),
+) -> ResultType<Value> {
+    let mut c = ipc::connect(1_000, postfix).await?;
+    c.send(&Data::Automation(request)).await?;
+    loop {
+        match c.next().await? {
+            Some(Data::Automation(Automation::Response { done: true, result })) => {
+                if let Some(err) = result["error"].as_str() {
+                    bail!("{}", err);
+                }
+                return Ok(result);
+            }
+            Some(Data::Automation(Automation::Response { result, .. })) => on_progress(&result),
+            _ => {}
+        }
+    }
+}
+
+/// The cli of the automation, `request` is the json of an [`Automation`].
+/// The progress and the result are printed as json lines.
+#[tokio::main(flavor = "current_thread")]
+pub async fn cli_request(request: &str) -> ResultType<()> {
+    let request: Automation = serde_json::from_str(request)?;
+    let result = self::request(request, |progress| println!("{}", progress)).await?;
+    println!("{}", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request_test(postfix: &str, request: Automation) -> (ResultType<Value>, Vec<Value>) {
+        let mut progress = vec![];
+        let res = request_(postfix, request, &mut |p| progress.push(p.clone())).await;
+        (res, progress)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_automation_ipc() {
+        let postfix = "_automation_test";
+        // Bound before the requests, no wait.
+        let listener = Listener::bind(postfix).await.unwrap();
+        tokio::spawn(serve(listener));
+
+        let (res, _) = request_test(postfix, Automation::State { session_id: 0 }).await;
+        assert!(res.unwrap_err().to_string().contains("no session"));
+        let (res, _) = request_test(
+            postfix,
+            Automation::Connect {
+                peer_id: "123".to_owned(),
+                password: "".to_owned(),
+                conn_type: "rdp".to_owned(),
+            },
+        )
+        .await;
+        assert!(res.is_err());
+
+        // A loopback peer refusing the connection.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (res, progress) = request_test(
+            postfix,
+            Automation::Connect {
+                peer_id: format!("127.0.0.1:{}", port),
+                password: "".to_owned(),
+                conn_type: "file-transfer".to_owned(),
+            },
+        )
+        .await;
+        assert!(res.is_err());
+        let session_id = progress[0]["session_id"].as_u64().unwrap();
+        // Closed on failure.
+        let (res, _) = request_test(postfix, Automation::State { session_id }).await;
+        assert!(res.is_err());
+
+        // The port forward session is started at once, it connects on the forwarded connections.
+        let (res, _) = request_test(
+            postfix,
+            Automation::Connect {
+                peer_id: format!("127.0.0.1:{}", port),
+                password: "".to_owned(),
+                conn_type: "port-forward".to_owned(),
+            },
+        )
+        .await;
+        let session_id = res.unwrap()["session_id"].as_u64().unwrap();
+        let (res, _) = request_test(
+            postfix,
+            Automation::SendFile {
+                session_id,
+                path: "a".to_owned(),
+                to: "b".to_owned(),
+                overwrite: false,
+            },
+        )
+        .await;
+        assert!(res.unwrap_err().to_string().contains("not a file transfer"));
+        let (res, _) = request_test(
+            postfix,
+            Automation::PortForward {
+                session_id,
+                local_port: 0,
+                remote_host: "localhost".to_owned(),
+                remote_port: 22,
+            },
+        )
+        .await;
+        assert!(res.is_err());
+        let (res, _) = request_test(postfix, Automation::State { session_id }).await;
+        assert_eq!(res.unwrap()["conn_type"], "port-forward");
+        let (res, _) = request_test(postfix, Automation::Close { session_id }).await;
+        assert!(res.is_ok());
+        let (res, _) = request_test(postfix, Automation::Close { session_id }).await;
+        assert!(res.is_err());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_automation_ipc_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let postfix = "_automation_test_owner";
+        let listener = Listener::bind(postfix).await.unwrap();
+        let mode = std::fs::metadata(Config::ipc_path(postfix))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Not started twice.
+        tokio::spawn(serve(listener));
+        assert!(Listener::bind(postfix).await.is_err());
+    }
+
+    // A file transfer session to the host of this process, see `client::loopback`.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_automation_send_file() {
+        let postfix = "_automation_test_send";
+        tokio::spawn(serve(Listener::bind(postfix).await.unwrap()));
+        let dir = std::env::temp_dir().join(format!("automation-{}", uuid::Uuid::new_v4()));
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        let data: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(from.join("a.bin"), &data).unwrap();
+
+        let peer_id = format!("loopback-{}", uuid::Uuid::new_v4());
+        crate::client::loopback::listen(&peer_id);
+        let (res, _) = request_test(
+            postfix,
+            Automation::Connect {
+                peer_id,
+                password: hbb_common::password_security::temporary_password(),
+                conn_type: "file-transfer".to_owned(),
+            },
+        )
+        .await;
+        let res = res.unwrap();
+        assert_eq!(res["state"]["state"], headless::STATE_CONNECTED);
+        let session_id = res["session_id"].as_u64().unwrap();
+
+        let (res, progress) = request_test(
+            postfix,
+            Automation::SendFile {
+                session_id,
+                path: from.to_string_lossy().to_string(),
+                to: to.to_string_lossy().to_string(),
+                overwrite: false,
+            },
+        )
+        .await;
+        res.unwrap();
+        assert!(progress.iter().all(|p| p["name"] != "job_error"));
+        assert_eq!(std::fs::read(to.join("a.bin")).unwrap(), data);
+
+        let (res, _) = request_test(postfix, Automation::Close { session_id }).await;
+        assert!(res.is_ok());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,452 @@
+//! The [`InvokeUiSession`] of the sessions without ui.
+//!
+//! The video is discarded, the ui callbacks only update the [`SessionState`] and send the events
+//! to the subscriber, i.e. the automation request waiting for the session.
+
+use crate::{
+    client::translate,
+    ui_session_interface::{HookAction, InvokeUiSession, JobMeta},
+};
+use hbb_common::{
+    message_proto::*,
+    rendezvous_proto::ConnType,
+    tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+pub const STATE_CONNECTING: &str = "connecting";
+pub const STATE_CONNECTED: &str = "connected";
+pub const STATE_ERROR: &str = "error";
+pub const STATE_CLOSED: &str = "closed";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionState {
+    pub peer_id: String,
+    pub conn_type: String,
+    /// See the `STATE_*` constants.
+    pub state: String,
+    /// The text of the last error, empty if none.
+    pub error: String,
+    pub secured: Option<bool>,
+    pub direct: Option<bool>,
+    pub platform: String,
+    pub version: String,
+}
+
+#[derive(Clone, Default)]
+pub struct HeadlessHandler {
+    state: Arc<Mutex<SessionState>>,
+    subscriber: Arc<Mutex<Option<UnboundedSender<Value>>>>,
+}
+
+impl HeadlessHandler {
+    pub fn init(&self, peer_id: &str, conn_type: ConnType) {
+        *self.state.lock().unwrap() = SessionState {
+            peer_id: peer_id.to_owned(),
+            conn_type: conn_type_name(conn_type).to_owned(),
+            state: STATE_CONNECTING.to_owned(),
+            ..Default::default()
+        };
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// The events of the session, replacing the previous subscriber.
+    pub fn subscribe(&self) -> UnboundedReceiver<Value> {
+        let (tx, rx) = unbounded_channel();
+        *self.subscriber.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    pub fn unsubscribe(&self) {
+        self.subscriber.lock().unwrap().take();
+    }
+
+    /// Called when the io loop of the session exits.
+    pub fn on_closed(&self) {
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            let changed = state.state != STATE_ERROR && state.state != STATE_CLOSED;
+            if changed {
+                state.state = STATE_CLOSED.to_owned();
+            }
+            changed
+        };
+        if changed {
+            self.emit(json!({ "name": STATE_CLOSED }));
+        }
+    }
+
+    fn emit(&self, event: Value) {
+        if let Some(tx) = self.subscriber.lock().unwrap().as_ref() {
+            tx.send(event).ok();
+        }
+    }
+}
+
+pub fn conn_type_name(conn_type: ConnType) -> &'static str {
+    match conn_type {
+        ConnType::DEFAULT_CONN => "remote",
+        ConnType::FILE_TRANSFER => "file-transfer",
+        ConnType::PORT_FORWARD => "port-forward",
+        ConnType::RDP => "rdp",
+    }
+}
+
+// The msgboxes ending the session, the password can't be input without ui.
+fn is_error_msgbox(msgtype: &str) -> bool {
+    msgtype.contains("error") || msgtype.ends_with("input-password")
+}
+
+impl InvokeUiSession for HeadlessHandler {
+    fn set_cursor_data(&self, _cd: CursorData) {}
+
+    fn set_cursor_id(&self, _id: String) {}
+
+    fn set_cursor_position(&self, _cp: CursorPosition) {}
+
+    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool) {}
+
+    fn switch_display(&self, _display: &SwitchDisplay) {}
+
+    fn set_peer_info(&self, peer_info: &PeerInfo) {
+        let mut state = self.state.lock().unwrap();
+        state.platform = peer_info.platform.clone();
+        state.version = peer_info.version.clone();
+    }
+
+    fn set_displays(&self, _displays: &Vec<DisplayInfo>) {}
+
+    fn set_platform_additions(&self, _data: &str) {}
+
+    fn on_connected(&self, _conn_type: ConnType) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.state = STATE_CONNECTED.to_owned();
+            state.error.clear();
+        }
+        self.emit(json!({ "name": STATE_CONNECTED }));
+    }
+
+    fn update_privacy_mode(&self) {}
+
+    fn set_permission(&self, _name: &str, _value: bool) {}
+
+    fn set_permissions(&self, _permissions: &[(&str, bool)]) {}
+
+    fn close_success(&self) {}
+
+    fn update_quality_status(&self, _qs: QualityStatus) {}
+
+    fn set_connection_type(&self, is_secured: bool, direct: bool) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.secured = Some(is_secured);
+            state.direct = Some(direct);
+        }
+        self.emit(json!({
+            "name": "connection_ready",
+            "secured": is_secured,
+            "direct": direct,
+        }));
+    }
+
+    fn set_fingerprint(&self, _fingerprint: String) {}
+
+    fn fingerprint_mismatch(&self, _old: &str, _new: &str) {
+        self.msgbox(
+            "error",
+            "Fingerprint mismatch",
+            "fingerprint_mismatch_tip",
+            "",
+            false,
+        );
+    }
+
+    fn job_error(&self, id: i32, err: String, file_num: i32) {
+        self.emit(json!({
+            "name": "job_error",
+            "id": id,
+            "err": err,
+            "file_num": file_num,
+        }));
+    }
+
+    fn job_done(&self, id: i32, file_num: i32) {
+        self.emit(json!({ "name": "job_done", "id": id, "file_num": file_num }));
+    }
+
+    fn clear_all_jobs(&self) {}
+
+    fn new_message(&self, _msg: String) {}
+
+    fn update_transfer_list(&self) {}
+
+    fn load_last_job(&self, _cnt: i32, _job_json: &str) {}
+
+    fn update_folder_files(
+        &self,
+        _id: i32,
+        _entries: &Vec<FileEntry>,
+        _path: String,
+        _is_local: bool,
+        _only_count: bool,
+    ) {
+    }
+
+    fn confirm_delete_files(&self, _id: i32, _i: i32, _name: String) {}
+
+    fn override_file_confirm(
+        &self,
+        id: i32,
+        file_num: i32,
+        to: String,
+        is_upload: bool,
+        _is_identical: bool,
+    ) {
+        self.emit(json!({
+            "name": "override_file_confirm",
+            "id": id,
+            "file_num": file_num,
+            "to": to,
+            "is_upload": is_upload,
+        }));
+    }
+
+    fn update_block_input_state(&self, _on: bool) {}
+
+    fn job_progress(&self, id: i32, file_num: i32, speed: f64, finished_size: f64) {
+        self.emit(json!({
+            "name": "job_progress",
+            "id": id,
+            "file_num": file_num,
+            "speed": speed,
+            "finished_size": finished_size,
+        }));
+    }
+
+    fn transfer_summary(&self, summary: &crate::client::job_progress::TransferSummary) {
+        self.emit(json!({
+            "name": "transfer_summary",
+            "jobs": summary.jobs,
+            "speed": summary.speed,
+            "remaining": summary.remaining,
+            "eta": summary.eta,
+        }));
+    }
+
+    fn job_renamed_files(&self, id: i32, renamed: &[hbb_common::fs::file_name::Renamed]) {
+        self.emit(json!({
+            "name": "job_renamed_files",
+            "id": id,
+            "files": renamed,
+        }));
+    }
+
+    fn job_streams(&self, id: i32, streams: u32) {
+        self.emit(json!({
+            "name": "job_streams",
+            "id": id,
+            "streams": streams,
+        }));
+    }
+
+    fn sync_state(&self, id: i32, state: &crate::client::file_sync::SyncState) {
+        let mut event = json!(state);
+        event["name"] = json!("sync_state");
+        event["id"] = json!(id);
+        self.emit(event);
+    }
+
+    fn file_op_progress(&self, id: i32, removed: u64, total: u64, is_remote: bool) {
+        self.emit(json!({
+            "name": "file_op_progress",
+            "id": id,
+            "removed": removed,
+            "total": total,
+            "is_remote": is_remote,
+        }));
+    }
+
+    fn file_op_done(&self, id: i32, is_remote: bool) {
+        self.emit(json!({ "name": "file_op_done", "id": id, "is_remote": is_remote }));
+    }
+
+    fn file_op_error(&self, id: i32, err: String, is_remote: bool) {
+        self.emit(json!({
+            "name": "file_op_error",
+            "id": id,
+            "err": err,
+            "is_remote": is_remote,
+        }));
+    }
+
+    fn adapt_size(&self) {}
+
+    // Discarded, the frames are not shown or recorded.
+    fn on_rgba(&self, _display: usize, _rgba: &mut scrap::ImageRgb) {}
+
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, _link: &str, _retry: bool) {
+        let text = translate(text.to_owned());
+        if is_error_msgbox(msgtype) {
+            let mut state = self.state.lock().unwrap();
+            state.state = STATE_ERROR.to_owned();
+            state.error = format!("{}: {}", title, text);
+        }
+        self.emit(json!({
+            "name": "msgbox",
+            "type": msgtype,
+            "title": title,
+            "text": text,
+        }));
+    }
+
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        params: &[(&str, String)],
+        link: &str,
+        retry: bool,
+    ) {
+        let mut text = translate(text_key.to_owned());
+        for (k, v) in params {
+            text = text.replace(&format!("{{{}}}", k), v);
+        }
+        self.msgbox(msgtype, title, &text, link, retry);
+    }
+
+    fn cancel_msgbox(&self, _tag: &str) {}
+
+    fn switch_back_state(&self, _peer_id: &str, _state: &str) {}
+
+    fn portable_service_running(&self, _running: bool) {}
+
+    fn host_display_waking(&self, _waking: bool) {}
+
+    fn host_display_state(&self, _state: &str) {}
+
+    fn view_only_blocked(&self) {}
+
+    fn hotkey_blocked(&self, _rule: &str, _keys: &str) {}
+
+    fn on_video_congestion(&self, _congested: bool) -> Option<Vec<i32>> {
+        None
+    }
+
+    fn set_relay_server(&self, _relay: &str) {}
+
+    fn relay_fallback(&self, _preferred: &str, _err: &str) {}
+
+    fn update_view_only(&self, _view_only: bool) {}
+
+    fn update_audio_only(&self, _audio_only: bool) {}
+
+    fn network_probe_result(&self, _result: &str) {}
+
+    fn print_job_progress(&self, _id: u32, _name: &str, _received: u64, _total: u64) {}
+
+    fn print_job_received(&self, _id: u32, _name: &str, _path: &str) {}
+
+    fn print_job_failed(&self, _id: u32, _name: &str, _err: &str) {}
+
+    fn staging_evicted(&self, _files: &[crate::client::staging::StagedFile]) {}
+
+    fn remote_sysinfo(&self, _sysinfo: &str, _error: &str) {}
+
+    fn remote_volume_changed(&self, _state: &str, _percent: u32, _muted: bool) {}
+
+    fn remote_restarting(&self, _state: &str, _attempt: usize) {}
+
+    fn update_peer_capabilities(&self, _capabilities: &crate::common::PeerCapabilities) {}
+
+    fn codec_negotiated(&self, _codec: &str, _reason: &str) {}
+
+    fn decoder_info(&self, _display: usize, _info: &crate::client::decoder_select::DecoderInfo) {}
+
+    fn color_mode_fallback(&self, _fallback: &crate::client::color_mode::Fallback) {}
+
+    fn annotation_ack(&self, _id: u32, _error: &str) {}
+
+    fn wol_result(&self, _mac: &str, _error: &str, _sent: u32) {}
+
+    fn input_policy(&self, _level: &str, _blocked: &[String], _dropped: u64) {}
+
+    fn host_throttled(&self, _fps_percent: u32, _cpu: u32, _encode: u32) {}
+
+    fn frame_viewport(&self, _display: usize, _viewport: Option<&Viewport>) {}
+
+    fn on_voice_call_started(&self) {}
+
+    fn on_voice_call_closed(&self, _reason: &str) {}
+
+    fn on_voice_call_waiting(&self) {}
+
+    fn on_voice_call_incoming(&self) {}
+
+    fn get_rgba(&self, _display: usize) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn next_rgba(&self, _display: usize) {}
+
+    fn update_socks_stats(&self, _port: i32, _stats: &str) {}
+
+    fn update_port_forward_stats(&self, _stats: &str) {}
+
+    fn hook_key_event(&self, _evt: &KeyEvent) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn hook_clipboard(&self, _content: &mut String) -> HookAction {
+        HookAction::Continue
+    }
+
+    fn hook_file_job_start(&self, _job: &JobMeta) -> HookAction {
+        HookAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headless_state() {
+        let handler = HeadlessHandler::default();
+        handler.init("123", ConnType::FILE_TRANSFER);
+        let mut rx = handler.subscribe();
+        assert_eq!(handler.state().state, STATE_CONNECTING);
+        assert_eq!(handler.state().conn_type, "file-transfer");
+
+        handler.set_connection_type(true, false);
+        handler.on_connected(ConnType::FILE_TRANSFER);
+        assert_eq!(rx.try_recv().unwrap()["name"], "connection_ready");
+        assert_eq!(rx.try_recv().unwrap()["name"], STATE_CONNECTED);
+        let state = handler.state();
+        assert_eq!(state.state, STATE_CONNECTED);
+        assert_eq!((state.secured, state.direct), (Some(true), Some(false)));
+
+        // The informational msgboxes don't end the session.
+        handler.msgbox("success", "Successful", "Connected", "", false);
+        assert_eq!(handler.state().state, STATE_CONNECTED);
+        handler.msgbox("input-password", "Password Required", "", "", false);
+        assert_eq!(handler.state().state, STATE_ERROR);
+        assert_eq!(rx.try_recv().unwrap()["type"], "success");
+        assert_eq!(rx.try_recv().unwrap()["type"], "input-password");
+
+        // The error is kept once closed.
+        handler.on_closed();
+        assert_eq!(handler.state().state, STATE_ERROR);
+        assert!(rx.try_recv().is_err());
+
+        handler.unsubscribe();
+        handler.job_done(1, 0);
+        assert!(rx.try_recv().is_err());
+    }
+}
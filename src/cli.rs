@@ -180,6 +180,8 @@ pub async fn start_one_port_forward(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        Default::default(),
+        Default::default(),
     )
     .await
     {
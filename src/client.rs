@@ -37,6 +37,7 @@ use hbb_common::{
     get_version_number, log,
     message_proto::{option_message::BoolOption, *},
     protobuf::Message as _,
+    proxy::{self, Proxy},
     rand,
     rendezvous_proto::*,
     socket_client,
@@ -51,7 +52,7 @@ pub use helper::*;
 use scrap::{
     codec::Decoder,
     record::{Recorder, RecorderContext},
-    ImageFormat, ImageRgb,
+    CodecFormat, ImageFormat, ImageRgb,
 };
 
 use crate::{
@@ -69,9 +70,25 @@ use crate::{check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL};
 
 pub use super::lang::*;
 
+pub mod codec_negotiation;
+pub mod color_mode;
+pub mod decoder_select;
+pub mod direct_target;
+pub mod file_sync;
 pub mod file_trait;
 pub mod helper;
+pub mod hotkey_rules;
 pub mod io_loop;
+pub mod job_progress;
+pub mod latency;
+#[cfg(any(test, feature = "loopback"))]
+pub mod loopback;
+pub mod network_probe;
+pub mod print_job;
+pub mod rates;
+pub mod relay_select;
+pub mod staging;
+pub mod text_clipboard;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
@@ -94,6 +111,9 @@ pub const LOGIN_MSG_PASSWORD_EMPTY: &str = "Empty Password";
 pub const LOGIN_MSG_PASSWORD_WRONG: &str = "Wrong Password";
 pub const LOGIN_MSG_NO_PASSWORD_ACCESS: &str = "No Password Access";
 pub const LOGIN_MSG_OFFLINE: &str = "Offline";
+pub const LOGIN_MSG_2FA_REQUIRED: &str = "2FA Required";
+pub const LOGIN_MSG_2FA_WRONG: &str = "Wrong 2FA Code";
+pub const LOGIN_MSG_OUTSIDE_SCHEDULE: &str = "Outside Access Schedule";
 pub const LOGIN_SCREEN_WAYLAND: &str = "Wayland login screen is not supported";
 #[cfg(target_os = "linux")]
 pub const SCRAP_UBUNTU_HIGHER_REQUIRED: &str = "Wayland requires Ubuntu 21.04 or higher version.";
@@ -246,11 +266,31 @@ impl Client {
         conn_type: ConnType,
         interface: impl Interface,
     ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
+        interface.get_lch().write().unwrap().relay_server = None;
+        #[cfg(any(test, feature = "loopback"))]
+        if let Some(stream) = loopback::connect(peer) {
+            return Ok((stream, true, None));
+        }
+        let proxy = Self::get_proxy(&interface)?;
+        let proxy = proxy.as_ref();
+        let direct_target = interface.get_lch().read().unwrap().direct_target.clone();
+        if let Some(target) = direct_target {
+            return Ok((
+                socket_client::connect_tcp_via(target.addr(), None, proxy, CONNECT_TIMEOUT).await?,
+                true,
+                None,
+            ));
+        }
         // to-do: remember the port for each peer, so that we can retry easier
         if hbb_common::is_ip_str(peer) {
             return Ok((
-                socket_client::connect_tcp(check_port(peer, RELAY_PORT + 1), CONNECT_TIMEOUT)
-                    .await?,
+                socket_client::connect_tcp_via(
+                    check_port(peer, RELAY_PORT + 1),
+                    None,
+                    proxy,
+                    CONNECT_TIMEOUT,
+                )
+                .await?,
                 true,
                 None,
             ));
@@ -258,7 +298,7 @@ impl Client {
         // Allow connect to {domain}:{port}
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
-                socket_client::connect_tcp(peer, CONNECT_TIMEOUT).await?,
+                socket_client::connect_tcp_via(peer, None, proxy, CONNECT_TIMEOUT).await?,
                 true,
                 None,
             ));
@@ -287,13 +327,15 @@ impl Client {
             }
         };
 
-        let mut socket = socket_client::connect_tcp(&*rendezvous_server, CONNECT_TIMEOUT).await;
+        let mut socket =
+            socket_client::connect_tcp_via(&*rendezvous_server, None, proxy, CONNECT_TIMEOUT).await;
         debug_assert!(!servers.contains(&rendezvous_server));
         if socket.is_err() && !servers.is_empty() {
             log::info!("try the other servers: {:?}", servers);
             for server in servers {
                 let server = check_port(server, RENDEZVOUS_PORT);
-                socket = socket_client::connect_tcp(&*server, CONNECT_TIMEOUT).await;
+                socket =
+                    socket_client::connect_tcp_via(&*server, None, proxy, CONNECT_TIMEOUT).await;
                 if socket.is_ok() {
                     rendezvous_server = server;
                     break;
@@ -378,6 +420,8 @@ impl Client {
                             rr.relay_server
                         );
                         signed_id_pk = rr.pk().into();
+                        interface.get_lch().write().unwrap().relay_server =
+                            Some(rr.relay_server.clone());
                         let mut conn = Self::create_relay(
                             peer,
                             rr.uuid,
@@ -385,6 +429,7 @@ impl Client {
                             key,
                             conn_type,
                             my_addr.is_ipv4(),
+                            proxy,
                         )
                         .await?;
                         let pk =
@@ -426,11 +471,22 @@ impl Client {
             key,
             token,
             conn_type,
+            proxy,
             interface,
         )
         .await
     }
 
+    /// The proxy of the session by the peer option, see `hbb_common::proxy`.
+    fn get_proxy(interface: &impl Interface) -> ResultType<Option<Proxy>> {
+        let option = interface
+            .get_lch()
+            .read()
+            .unwrap()
+            .get_option(proxy::OPTION_PROXY);
+        proxy::of_peer(&option)
+    }
+
     /// Connect to the peer.
     async fn connect(
         local_addr: SocketAddr,
@@ -446,6 +502,7 @@ impl Client {
         key: &str,
         token: &str,
         conn_type: ConnType,
+        proxy: Option<&Proxy>,
         interface: impl Interface,
     ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
         let direct_failures = interface.get_lch().read().unwrap().direct_failures;
@@ -482,31 +539,51 @@ impl Client {
         }
         log::info!("peer address: {}, timeout: {}", peer, connect_timeout);
         let start = std::time::Instant::now();
-        // NOTICE: Socks5 is be used event in intranet. Which may be not a good way.
+        // The peers in the intranet are connected directly only if matched by the no-proxy list.
         let mut conn =
-            socket_client::connect_tcp_local(peer, Some(local_addr), connect_timeout).await;
+            socket_client::connect_tcp_via(peer, Some(local_addr), proxy, connect_timeout).await;
         let mut direct = !conn.is_err();
         interface.update_direct(Some(direct));
+        let mut relay_used = relay_server.to_owned();
         if interface.is_force_relay() || conn.is_err() {
-            if !relay_server.is_empty() {
+            let preferred = interface
+                .get_lch()
+                .read()
+                .unwrap()
+                .get_option(relay_select::OPTION_PREFERRED_RELAY);
+            let relays = relay_select::relays_to_try(&preferred, relay_server);
+            if relays.is_empty() {
+                bail!("Failed to make direct connection to remote desktop");
+            }
+            for (i, relay) in relays.iter().enumerate() {
                 conn = Self::request_relay(
                     peer_id,
-                    relay_server.to_owned(),
+                    relay.to_owned(),
                     rendezvous_server,
                     !signed_id_pk.is_empty(),
                     key,
                     token,
                     conn_type,
+                    proxy,
                 )
                 .await;
-                interface.update_direct(Some(false));
-                if let Err(e) = conn {
-                    bail!("Failed to connect via relay server: {}", e);
+                match &conn {
+                    Ok(_) => {
+                        relay_used = relay.to_owned();
+                        break;
+                    }
+                    Err(e) if *relay == preferred && i + 1 < relays.len() => {
+                        log::warn!("Failed to connect via the preferred relay {}: {}", relay, e);
+                        interface.on_relay_fallback(relay, &e.to_string());
+                    }
+                    Err(_) => {}
                 }
-                direct = false;
-            } else {
-                bail!("Failed to make direct connection to remote desktop");
             }
+            interface.update_direct(Some(false));
+            if let Err(e) = conn {
+                bail!("Failed to connect via relay server: {}", e);
+            }
+            direct = false;
         }
         if !relay_server.is_empty() && (direct_failures == 0) != direct {
             let n = if direct { 0 } else { 1 };
@@ -514,6 +591,11 @@ impl Client {
             interface.get_lch().write().unwrap().set_direct_failure(n);
         }
         let mut conn = conn?;
+        interface.get_lch().write().unwrap().relay_server = if direct {
+            None
+        } else {
+            Some(relay_used)
+        };
         log::info!("{:?} used to establish connection", start.elapsed());
         let pk = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn).await?;
         Ok((conn, direct, pk))
@@ -607,6 +689,7 @@ impl Client {
         key: &str,
         token: &str,
         conn_type: ConnType,
+        proxy: Option<&Proxy>,
     ) -> ResultType<Stream> {
         let mut succeed = false;
         let mut uuid = "".to_owned();
@@ -614,9 +697,10 @@ impl Client {
 
         for i in 1..=3 {
             // use different socket due to current hbbs implementation requiring different nat address for each attempt
-            let mut socket = socket_client::connect_tcp(rendezvous_server, CONNECT_TIMEOUT)
-                .await
-                .with_context(|| "Failed to connect to rendezvous server")?;
+            let mut socket =
+                socket_client::connect_tcp_via(rendezvous_server, None, proxy, CONNECT_TIMEOUT)
+                    .await
+                    .with_context(|| "Failed to connect to rendezvous server")?;
 
             if !key.is_empty() && !token.is_empty() {
                 // mainly for the security of token
@@ -659,7 +743,7 @@ impl Client {
         if !succeed {
             bail!("Timeout");
         }
-        Self::create_relay(peer, uuid, relay_server, key, conn_type, ipv4).await
+        Self::create_relay(peer, uuid, relay_server, key, conn_type, ipv4, proxy).await
     }
 
     /// Create a relay connection to the server.
@@ -670,9 +754,12 @@ impl Client {
         key: &str,
         conn_type: ConnType,
         ipv4: bool,
+        proxy: Option<&Proxy>,
     ) -> ResultType<Stream> {
-        let mut conn = socket_client::connect_tcp(
+        let mut conn = socket_client::connect_tcp_via(
             socket_client::ipv4_to_ipv6(check_port(relay_server, RELAY_PORT), ipv4),
+            None,
+            proxy,
             CONNECT_TIMEOUT,
         )
         .await
@@ -1020,6 +1107,8 @@ impl AudioHandler {
 /// Video handler for the [`Client`].
 pub struct VideoHandler {
     decoder: Decoder,
+    // Only the software decoders are created.
+    software: bool,
     pub rgb: ImageRgb,
     recorder: Arc<Mutex<Option<Recorder>>>,
     record: bool,
@@ -1028,10 +1117,11 @@ pub struct VideoHandler {
 
 impl VideoHandler {
     /// Create a new video handler.
-    pub fn new(_display: usize) -> Self {
-        log::info!("new video handler for display #{_display}");
+    pub fn new(_display: usize, software: bool) -> Self {
+        log::info!("new video handler for display #{_display}, software: {software}");
         VideoHandler {
-            decoder: Decoder::new(),
+            decoder: Decoder::new_with(!software),
+            software,
             rgb: ImageRgb::new(ImageFormat::ARGB, crate::DST_STRIDE_RGBA),
             recorder: Default::default(),
             record: false,
@@ -1066,7 +1156,21 @@ impl VideoHandler {
 
     /// Reset the decoder.
     pub fn reset(&mut self) {
-        self.decoder = Decoder::new();
+        self.decoder = Decoder::new_with(!self.software);
+    }
+
+    /// Create the decoder again, without the hardware ones if `software`.
+    pub fn set_software(&mut self, software: bool) {
+        self.software = software;
+        self.reset();
+    }
+
+    /// The decoder of the frames of `format`, `None` if the format is unknown.
+    pub fn select_decoder(&self, format: &CodecFormat) -> Option<decoder_select::Selection> {
+        if *format == CodecFormat::Unknown {
+            return None;
+        }
+        Some(decoder_select::select(format, self.software, &self.decoder))
     }
 
     /// Start or stop screen record.
@@ -1077,6 +1181,7 @@ impl VideoHandler {
                 server: false,
                 id,
                 default_dir: crate::ui_interface::default_video_save_directory(),
+                dir: "".to_owned(),
                 filename: "".to_owned(),
                 width: w as _,
                 height: h as _,
@@ -1102,11 +1207,14 @@ pub struct LoginConfigHandler {
     pub remember: bool,
     config: PeerConfig,
     pub port_forward: (String, i32),
+    pub port_forward_dynamic: bool, // SOCKS5 dynamic forwarding
     pub version: i64,
-    features: Option<Features>,
+    pub features: Option<Features>,
     pub session_id: u64, // used for local <-> server communication
     pub supported_encoding: SupportedEncoding,
     pub restarting_remote_device: bool,
+    /// Reconnecting to the restarting remote device, see [`Session::on_remote_restarting`].
+    pub restart_reconnecting: bool,
     pub force_relay: bool,
     pub direct: Option<bool>,
     pub received: bool,
@@ -1114,6 +1222,19 @@ pub struct LoginConfigHandler {
     pub save_ab_password_to_recent: bool, // true: connected with ab password
     pub other_server: Option<(String, String, String)>,
     pub custom_fps: Arc<Mutex<Option<usize>>>,
+    pub platform_additions: serde_json::Map<String, serde_json::Value>,
+    /// Set by `session_add`, no input is sent to the peer until the host confirms to turn it off.
+    pub view_only_session: bool,
+    /// Set by `session_add`, no video is requested until a display is switched to.
+    pub audio_only_session: bool,
+    /// The relay server of the current connection, `None` if connected directly.
+    pub relay_server: Option<String>,
+    /// Identify the remote monitors of the displays, see [`LocalConfig::get_view_state`].
+    pub view_state_monitors: Vec<String>,
+    /// The hardware codecs failing to decode, removed from the decodings sent to the peer.
+    pub hw_decoder_failed: Vec<CodecFormat>,
+    /// Parsed from the id of the direct target, the rendezvous server is not used.
+    pub direct_target: Option<direct_target::DirectTarget>,
 }
 
 impl Deref for LoginConfigHandler {
@@ -1188,7 +1309,15 @@ impl LoginConfigHandler {
         self.session_id = sid;
         self.supported_encoding = Default::default();
         self.restarting_remote_device = false;
-        self.force_relay = !self.get_option("force-always-relay").is_empty() || force_relay;
+        self.restart_reconnecting = false;
+        self.direct_target = if direct_target::DirectTarget::is_direct_id(&self.id) {
+            direct_target::DirectTarget::parse(&self.id).ok()
+        } else {
+            None
+        };
+        // No relay for the direct targets.
+        self.force_relay = self.direct_target.is_none()
+            && (!self.get_option("force-always-relay").is_empty() || force_relay);
         if let Some((real_id, server, key)) = &self.other_server {
             let other_server_key = self.get_option("other-server-key");
             if !other_server_key.is_empty() && key.is_empty() {
@@ -1401,6 +1530,32 @@ impl LoginConfigHandler {
                 BoolOption::No
             })
             .into();
+        } else if name == "disable-keep-awake" {
+            let disabled = !self.get_toggle_option(&name);
+            if disabled {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.keep_awake = (if disabled {
+                BoolOption::No
+            } else {
+                BoolOption::Yes
+            })
+            .into();
+        } else if name == print_job::OPTION_PRINT_FORWARD {
+            let enabled = !self.get_toggle_option(&name);
+            if enabled {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.print_forward = (if enabled {
+                BoolOption::Yes
+            } else {
+                BoolOption::No
+            })
+            .into();
         } else if name == "block-input" {
             option.block_input = BoolOption::Yes.into();
         } else if name == "unblock-input" {
@@ -1507,11 +1662,19 @@ impl LoginConfigHandler {
             }
             n += 1;
         }
-        let view_only = self.get_toggle_option("view-only");
+        let view_only = self.view_only_session || self.get_toggle_option("view-only");
+        if self.view_only_session {
+            msg.view_only = BoolOption::Yes.into();
+            n += 1;
+        }
         if view_only {
             msg.disable_keyboard = BoolOption::Yes.into();
             n += 1;
         }
+        if self.audio_only_session {
+            msg.audio_only = BoolOption::Yes.into();
+            n += 1;
+        }
         if view_only || self.get_toggle_option("show-remote-cursor") {
             msg.show_remote_cursor = BoolOption::Yes.into();
             n += 1;
@@ -1532,8 +1695,16 @@ impl LoginConfigHandler {
             msg.disable_clipboard = BoolOption::Yes.into();
             n += 1;
         }
+        if self.get_toggle_option("disable-keep-awake") {
+            msg.keep_awake = BoolOption::No.into();
+            n += 1;
+        }
+        if self.get_toggle_option(print_job::OPTION_PRINT_FORWARD) {
+            msg.print_forward = BoolOption::Yes.into();
+            n += 1;
+        }
         msg.supported_decoding =
-            hbb_common::protobuf::MessageField::some(Decoder::supported_decodings(Some(&self.id)));
+            hbb_common::protobuf::MessageField::some(self.supported_decoding());
         n += 1;
 
         if n > 0 {
@@ -1765,17 +1936,51 @@ impl LoginConfigHandler {
     ///
     /// * `username` - The name of the peer.
     /// * `pi` - The peer info.
+    /// Update the monitors of the remote displays, and drop the view states of the gone ones.
+    pub fn update_view_state_monitors(&mut self, displays: &[DisplayInfo]) {
+        self.view_state_monitors = displays
+            .iter()
+            .map(|d| format!("{}@{},{}", d.name, d.x, d.y))
+            .collect();
+        LocalConfig::retain_view_states(&self.id, &self.view_state_monitors);
+    }
+
+    pub fn get_view_state(&self, display: i32) -> String {
+        usize::try_from(display)
+            .ok()
+            .and_then(|i| self.view_state_monitors.get(i))
+            .and_then(|m| LocalConfig::get_view_state(&self.id, display, m))
+            .unwrap_or_default()
+    }
+
+    pub fn save_view_state(&self, display: i32, state: String) {
+        let monitor = usize::try_from(display)
+            .ok()
+            .and_then(|i| self.view_state_monitors.get(i));
+        if let Some(monitor) = monitor {
+            LocalConfig::set_view_state(&self.id, display, monitor, state);
+        }
+    }
+
     pub fn handle_peer_info(&mut self, pi: &PeerInfo) {
         if !pi.version.is_empty() {
             self.version = hbb_common::get_version_number(&pi.version);
         }
         self.features = pi.features.clone().into_option();
+        self.platform_additions = serde_json::from_str(&pi.platform_additions).unwrap_or_default();
+        self.update_view_state_monitors(&pi.displays);
         let serde = PeerInfoSerde {
             username: pi.username.clone(),
             hostname: pi.hostname.clone(),
             platform: pi.platform.clone(),
         };
         let mut config = self.load_config();
+        // The config is saved anyway below to update the file time, which orders the recent
+        // peers, but the main window is only told if the peer card is changed.
+        #[cfg(feature = "flutter")]
+        if self.conn_type.eq(&ConnType::DEFAULT_CONN) {
+            crate::flutter::push_peer_card_updated(&self.id, &config.info, &serde);
+        }
         config.info = serde;
         let password = self.password.clone();
         let password0 = config.password.clone();
@@ -1902,6 +2107,7 @@ impl LoginConfigHandler {
             ConnType::PORT_FORWARD | ConnType::RDP => lr.set_port_forward(PortForward {
                 host: self.port_forward.0.clone(),
                 port: self.port_forward.1,
+                dynamic: self.port_forward_dynamic,
                 ..Default::default()
             }),
             _ => {}
@@ -1912,11 +2118,23 @@ impl LoginConfigHandler {
         msg_out
     }
 
+    /// The decodings sent to the peer, without the failed or the forced off hardware codecs.
+    fn supported_decoding(&self) -> SupportedDecoding {
+        let mut decoding = Decoder::supported_decodings(Some(&self.id));
+        codec_negotiation::mask_decoding(&mut decoding, &self.hw_decoder_failed);
+        if self.get_toggle_option(decoder_select::OPTION_FORCE_SOFTWARE_DECODE) {
+            codec_negotiation::mask_decoding(
+                &mut decoding,
+                &[CodecFormat::H264, CodecFormat::H265],
+            );
+        }
+        decoding
+    }
+
     pub fn change_prefer_codec(&self) -> Message {
-        let decoding = scrap::codec::Decoder::supported_decodings(Some(&self.id));
         let mut misc = Misc::new();
         misc.set_option(OptionMessage {
-            supported_decoding: hbb_common::protobuf::MessageField::some(decoding),
+            supported_decoding: hbb_common::protobuf::MessageField::some(self.supported_decoding()),
             ..Default::default()
         });
         let mut msg_out = Message::new();
@@ -1941,6 +2159,7 @@ pub enum MediaData {
     AudioFormat(AudioFormat),
     Reset(usize),
     RecordScreen(bool, usize, i32, i32, String),
+    ForceSoftwareDecode(bool),
 }
 
 pub type MediaSender = mpsc::Sender<MediaData>;
@@ -1950,6 +2169,11 @@ struct VideoHandlerController {
     count: u128,
     duration: std::time::Duration,
     skip_beginning: u32,
+    // The consecutive decoding errors of the hardware codec.
+    hw_errors: usize,
+    chroma: Option<Chroma>,
+    // The decoder reported to the ui.
+    decoder_info: Option<decoder_select::DecoderInfo>,
 }
 
 /// Start video and audio thread.
@@ -1966,7 +2190,7 @@ pub fn start_video_audio_threads<F, T>(
     MediaSender,
     Arc<RwLock<HashMap<usize, ArrayQueue<VideoFrame>>>>,
     Arc<RwLock<HashMap<usize, usize>>>,
-    Arc<RwLock<Option<Chroma>>>,
+    Arc<RwLock<HashMap<usize, DecodeStats>>>,
 )
 where
     F: 'static + FnMut(usize, &mut scrap::ImageRgb) + Send,
@@ -1978,14 +2202,18 @@ where
     let mut video_callback = video_callback;
     let fps_map = Arc::new(RwLock::new(HashMap::new()));
     let decode_fps_map = fps_map.clone();
-    let chroma = Arc::new(RwLock::new(None));
-    let chroma_cloned = chroma.clone();
-    let mut last_chroma = None;
+    let decode_stats_map: Arc<RwLock<HashMap<usize, DecodeStats>>> = Default::default();
+    let decode_stats_map_cloned = decode_stats_map.clone();
 
     std::thread::spawn(move || {
         #[cfg(windows)]
         sync_cpu_usage();
         let mut handler_controller_map = Vec::new();
+        let mut force_software = session
+            .lc
+            .read()
+            .unwrap()
+            .get_toggle_option(decoder_select::OPTION_FORCE_SOFTWARE_DECODE);
         // let mut count = Vec::new();
         // let mut duration = std::time::Duration::ZERO;
         // let mut skip_beginning = Vec::new();
@@ -2018,23 +2246,74 @@ where
                         if handler_controller_map.len() <= display {
                             for _i in handler_controller_map.len()..=display {
                                 handler_controller_map.push(VideoHandlerController {
-                                    handler: VideoHandler::new(_i),
+                                    handler: VideoHandler::new(_i, force_software),
                                     count: 0,
                                     duration: std::time::Duration::ZERO,
                                     skip_beginning: 0,
+                                    hw_errors: 0,
+                                    chroma: None,
+                                    decoder_info: None,
                                 });
                             }
                         }
                         if let Some(handler_controller) = handler_controller_map.get_mut(display) {
                             let mut tmp_chroma = None;
-                            match handler_controller.handler.handle_frame(vf, &mut tmp_chroma) {
+                            let format = CodecFormat::from(&vf);
+                            if let Some(selection) =
+                                handler_controller.handler.select_decoder(&format)
+                            {
+                                if handler_controller.decoder_info.as_ref() != Some(&selection.info)
+                                {
+                                    session.decoder_info(display, &selection.info);
+                                    handler_controller.decoder_info = Some(selection.info);
+                                }
+                                // Not decodable, skipped till the host switches the codec.
+                                if selection.fall_back {
+                                    if !force_software {
+                                        session.on_hw_decoder_failed(format);
+                                    }
+                                    continue;
+                                }
+                            }
+                            let viewport = vf.viewport.clone().into_option();
+                            let capture_time = vf.capture_time;
+                            let res = handler_controller.handler.handle_frame(vf, &mut tmp_chroma);
+                            if res.is_ok() {
+                                handler_controller.hw_errors = 0;
+                            } else if codec_negotiation::is_hardware(&format) {
+                                handler_controller.hw_errors += 1;
+                                if handler_controller.hw_errors
+                                    == codec_negotiation::HW_DECODE_ERRORS_TO_FALLBACK
+                                {
+                                    session.on_hw_decoder_failed(format);
+                                    continue;
+                                }
+                            }
+                            match res {
                                 Ok(true) => {
+                                    session.frame_viewport(display, viewport.as_ref());
+                                    let decoded = std::time::Instant::now();
                                     video_callback(display, &mut handler_controller.handler.rgb);
+                                    if capture_time != 0 {
+                                        session.latency.lock().unwrap().on_rendered(
+                                            display,
+                                            capture_time,
+                                            decoded,
+                                            std::time::Instant::now(),
+                                        );
+                                    }
 
                                     // chroma
-                                    if tmp_chroma.is_some() && last_chroma != tmp_chroma {
-                                        last_chroma = tmp_chroma;
-                                        *chroma.write().unwrap() = tmp_chroma;
+                                    if tmp_chroma.is_some()
+                                        && handler_controller.chroma != tmp_chroma
+                                    {
+                                        handler_controller.chroma = tmp_chroma;
+                                        decode_stats_map
+                                            .write()
+                                            .unwrap()
+                                            .entry(display)
+                                            .or_default()
+                                            .chroma = tmp_chroma;
                                     }
 
                                     // fps calculation
@@ -2053,6 +2332,16 @@ where
                                                 / handler_controller.duration.as_millis())
                                                 as usize,
                                         );
+                                        decode_stats_map
+                                            .write()
+                                            .unwrap()
+                                            .entry(display)
+                                            .or_default()
+                                            .delay = Some(
+                                            (handler_controller.duration.as_millis()
+                                                / handler_controller.count)
+                                                as usize,
+                                        );
                                     }
                                     // Clear to get real-time fps
                                     if handler_controller.count > 150 {
@@ -2084,6 +2373,13 @@ where
                             handler_controler.handler.reset();
                         }
                     }
+                    MediaData::ForceSoftwareDecode(software) => {
+                        force_software = software;
+                        for handler_controller in handler_controller_map.iter_mut() {
+                            handler_controller.handler.set_software(software);
+                            handler_controller.hw_errors = 0;
+                        }
+                    }
                     MediaData::RecordScreen(start, display, w, h, id) => {
                         log::info!("record screen command: start: {start}, display: {display}");
                         if handler_controller_map.len() == 1 {
@@ -2114,7 +2410,7 @@ where
         audio_sender,
         video_queue_map_cloned,
         decode_fps_map,
-        chroma_cloned,
+        decode_stats_map_cloned,
     );
 }
 
@@ -2441,6 +2737,12 @@ lazy_static::lazy_static! {
             text: "Please wait for the remote side to accept your session request...",
             link: "",
             try_again: true,
+        }), (LOGIN_MSG_OUTSIDE_SCHEDULE, LoginErrorMsgBox{
+            msgtype: "error",
+            title: "Connection not allowed",
+            text: "access_schedule_rejected_tip",
+            link: "",
+            try_again: false,
         })]);
         Arc::new(map)
     };
@@ -2462,6 +2764,9 @@ pub fn handle_login_error(
         lc.write().unwrap().password = Default::default();
         interface.msgbox("re-input-password", err, "Do you want to enter again?", "");
         true
+    } else if err == LOGIN_MSG_2FA_REQUIRED || err == LOGIN_MSG_2FA_WRONG {
+        interface.msgbox("input-2fa", err, "", "");
+        true
     } else if LOGIN_ERROR_MAP.contains_key(err) {
         if let Some(msgbox_info) = LOGIN_ERROR_MAP.get(err) {
             interface.msgbox(
@@ -2624,6 +2929,16 @@ pub async fn handle_login_from_ui(
     send_login(lc.clone(), os_username, os_password, hash_password, peer).await;
 }
 
+/// Send the 2FA code after the password is accepted by the peer.
+pub async fn send_2fa(code: String, peer: &mut Stream) {
+    let mut msg_out = Message::new();
+    msg_out.set_auth_2fa(Auth2FA {
+        code,
+        ..Default::default()
+    });
+    allow_err!(peer.send(&msg_out).await);
+}
+
 async fn send_switch_login_request(
     lc: Arc<RwLock<LoginConfigHandler>>,
     peer: &mut Stream,
@@ -2650,6 +2965,18 @@ pub trait Interface: Send + Clone + 'static + Sized {
     /// Send message data to remote peer.
     fn send(&self, data: Data);
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str);
+    /// The msgbox whose text is a lang key with the params, the raw text is shown if not
+    /// supported.
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        _params: Vec<(&str, String)>,
+        link: &str,
+    ) {
+        self.msgbox(msgtype, title, text_key, link);
+    }
     fn handle_login_error(&self, err: &str) -> bool;
     fn handle_peer_info(&self, pi: PeerInfo);
     fn on_error(&self, err: &str) {
@@ -2678,6 +3005,22 @@ pub trait Interface: Send + Clone + 'static + Sized {
 
     fn swap_modifier_mouse(&self, _msg: &mut hbb_common::protos::message::MouseEvent) {}
 
+    fn update_port_forward_stats(&self, _stats: &str) {}
+
+    /// The fingerprint of the peer differs from the trusted one. The session is paused until
+    /// [`Data::AcceptFingerprint`] or [`Data::Close`] is received.
+    fn on_fingerprint_mismatch(&self, _old: &str, _new: &str) {
+        self.msgbox(
+            "error",
+            "Fingerprint mismatch",
+            "fingerprint_mismatch_tip",
+            "",
+        );
+    }
+
+    /// The preferred relay failed, the relay chosen by the rendezvous server is tried instead.
+    fn on_relay_fallback(&self, _preferred: &str, _err: &str) {}
+
     fn update_direct(&self, direct: Option<bool>) {
         self.get_lch().write().unwrap().direct = direct;
     }
@@ -2692,6 +3035,17 @@ pub trait Interface: Send + Clone + 'static + Sized {
         let lc = self.get_lch();
         let direct = lc.read().unwrap().direct;
         let received = lc.read().unwrap().received;
+        let direct_target = lc.read().unwrap().direct_target.clone();
+
+        if let Some(target) = direct_target {
+            log::error!("Direct connection to {} closed: {err}", target.addr());
+            let connected = direct.is_some();
+            if let Some(e) = direct_target::DirectError::classify(&text, connected, received) {
+                let params = vec![("addr", target.addr()), ("error", text)];
+                self.msgbox_keyed(e.msgtype(), title, e.text_key(), params, "");
+                return;
+            }
+        }
 
         let mut relay_hint = false;
         let mut relay_hint_type = "relay-hint";
@@ -2711,12 +3065,32 @@ pub trait Interface: Send + Clone + 'static + Sized {
         // relay-hint
         if cfg!(feature = "flutter") && relay_hint {
             self.msgbox(relay_hint_type, title, &text, "");
+        } else if let Some((text_key, params)) = connection_error_key(&text) {
+            self.msgbox_keyed("error", title, text_key, params, "");
         } else {
             self.msgbox("error", title, &text, "");
         }
     }
 }
 
+/// The lang key and the params of the known connection errors, see [`Interface::msgbox_keyed`].
+/// The keys keep the words checked by [`check_if_retry`].
+pub fn connection_error_key(err: &str) -> Option<(&'static str, Vec<(&'static str, String)>)> {
+    if err.contains("Remote desktop is offline") {
+        Some(("remote_offline_tip", vec![]))
+    } else if err.contains("Key mismatch") {
+        Some(("key_mismatch_tip", vec![]))
+    } else if err.contains(proxy::ERR_AUTH_FAILED) {
+        Some((proxy::ERR_AUTH_FAILED, vec![("error", err.to_owned())]))
+    } else if err.contains(proxy::ERR_UNREACHABLE) {
+        Some((proxy::ERR_UNREACHABLE, vec![("error", err.to_owned())]))
+    } else if err.contains("Connection refused") || err.contains("10061") {
+        Some(("connection_refused_tip", vec![("error", err.to_owned())]))
+    } else {
+        None
+    }
+}
+
 /// Data used by the client interface.
 #[derive(Clone)]
 pub enum Data {
@@ -2730,20 +3104,33 @@ pub enum Data {
     RemoveDir((i32, String)),
     RemoveFile((i32, String, i32, bool)),
     CreateDir((i32, String, bool)),
+    RenameFile((i32, String, String, bool)),
+    DeletePath((i32, String, bool, bool)),
     CancelJob(i32),
     RemovePortForward(i32),
     AddPortForward((i32, String, i32)),
+    StartSocks((i32, Option<(String, String)>)),
     #[cfg(not(feature = "flutter"))]
     ToggleClipboardFile,
     NewRDP,
     SetConfirmOverrideFile((i32, i32, bool, bool, bool)),
     AddJob((i32, String, String, i32, bool, bool)),
     ResumeJob((i32, bool)),
+    // Mirror the local directory to the remote one, see `file_sync`.
+    StartSync((i32, String, String, file_sync::SyncOptions)),
+    SyncChanges((i32, file_sync::Changes)),
+    SyncError((i32, String)),
     RecordScreen(bool, usize, i32, i32, String),
+    ForceSoftwareDecode(bool),
     ElevateDirect,
     ElevateWithLogon(String, String),
     NewVoiceCall,
     CloseVoiceCall,
+    // Trust the new fingerprint of the peer and resume the paused session.
+    AcceptFingerprint,
+    // Run the network probe, even if a file transfer job is active if true.
+    NetworkProbe(bool, std::sync::mpsc::Sender<ResultType<String>>),
+    Login2FA(String),
 }
 
 /// Keycode for key events.
@@ -2877,6 +3264,35 @@ lazy_static::lazy_static! {
     ].iter().cloned().collect();
 }
 
+/// The key of the peer in the fingerprint trust store, it's the same for all conn types.
+#[inline]
+pub fn fingerprint_key(id: &str) -> &str {
+    crate::ui_interface::handle_relay_id(id)
+}
+
+/// Check the fingerprint of the peer, it's trusted on the first use.
+/// Return the trusted fingerprint if it's different from `fingerprint`.
+pub fn check_fingerprint(id: &str, fingerprint: &str) -> Option<String> {
+    if fingerprint.is_empty() {
+        // No public key of the peer, e.g. connecting by ip directly.
+        // Nothing is trusted on the first use, but the missing key mismatches the trusted one.
+        return hbb_common::config::FingerprintConfig::get(fingerprint_key(id))
+            .filter(|old| !old.is_empty());
+    }
+    hbb_common::config::FingerprintConfig::check(fingerprint_key(id), fingerprint)
+}
+
+/// Trust the new fingerprint of the peer, replacing the old one.
+/// An empty fingerprint removes the trusted one, the next key is trusted on the first use.
+pub fn trust_fingerprint(id: &str, fingerprint: &str) {
+    log::info!("Trust the new fingerprint of {}", id);
+    if fingerprint.is_empty() {
+        hbb_common::config::FingerprintConfig::remove(fingerprint_key(id));
+        return;
+    }
+    hbb_common::config::FingerprintConfig::set(fingerprint_key(id), fingerprint);
+}
+
 /// Check if the given message is an error and can be retried.
 ///
 /// # Arguments
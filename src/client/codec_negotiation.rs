@@ -0,0 +1,265 @@
+//! The codec negotiation of a session.
+//!
+//! The host picks the codec from the decodings sent by the client, see
+//! `scrap::codec::Encoder::update`. The client mirrors the choice with the capabilities of both
+//! ends, so the ui is told what is supported and why the active codec changes.
+//!
+//! The hardware decoders failing to decode are removed from the decodings, then the host falls
+//! back to a software codec.
+
+use hbb_common::message_proto::{
+    supported_decoding::PreferCodec, SupportedDecoding, SupportedEncoding,
+};
+use scrap::CodecFormat;
+use serde_derive::Serialize;
+
+/// The option of the preferred codec of a peer.
+pub const OPTION_CODEC_PREFERENCE: &str = "codec-preference";
+
+/// The active codec is the preferred one, or the auto choice if no codec is preferred.
+pub const REASON_PREFERENCE: &str = "preference";
+/// The preferred codec is not supported by both ends.
+pub const REASON_FALLBACK: &str = "fallback";
+/// The hardware decoder of the preferred codec fails.
+pub const REASON_HW_INIT_FAILURE: &str = "hw-init-failure";
+
+/// The consecutive decoding errors of a hardware codec before falling back.
+pub const HW_DECODE_ERRORS_TO_FALLBACK: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CodecSupport {
+    /// Decodable by this side.
+    pub local: bool,
+    /// Encodable by the peer.
+    pub peer: bool,
+    pub hardware: bool,
+}
+
+impl CodecSupport {
+    #[inline]
+    pub fn usable(&self) -> bool {
+        self.local && self.peer
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CodecCapabilities {
+    pub vp8: CodecSupport,
+    pub vp9: CodecSupport,
+    pub av1: CodecSupport,
+    pub h264: CodecSupport,
+    pub h265: CodecSupport,
+}
+
+impl CodecCapabilities {
+    /// `decoding` is of this side and `encoding` of the peer.
+    /// The codecs in `hw_failed` are not decodable any more.
+    pub fn new(
+        decoding: &SupportedDecoding,
+        encoding: &SupportedEncoding,
+        hw_failed: &[CodecFormat],
+    ) -> Self {
+        let support = |ability: i32, peer: bool, hardware: bool, format: CodecFormat| {
+            CodecSupport {
+                local: ability > 0 && !hw_failed.contains(&format),
+                peer,
+                hardware,
+            }
+        };
+        Self {
+            vp8: support(decoding.ability_vp8, encoding.vp8, false, CodecFormat::VP8),
+            // VP9 is always supported by the peer.
+            vp9: support(decoding.ability_vp9, true, false, CodecFormat::VP9),
+            av1: support(decoding.ability_av1, encoding.av1, false, CodecFormat::AV1),
+            h264: support(decoding.ability_h264, encoding.h264, true, CodecFormat::H264),
+            h265: support(decoding.ability_h265, encoding.h265, true, CodecFormat::H265),
+        }
+    }
+
+    pub fn get(&self, format: &CodecFormat) -> Option<&CodecSupport> {
+        match format {
+            CodecFormat::VP8 => Some(&self.vp8),
+            CodecFormat::VP9 => Some(&self.vp9),
+            CodecFormat::AV1 => Some(&self.av1),
+            CodecFormat::H264 => Some(&self.h264),
+            CodecFormat::H265 => Some(&self.h265),
+            CodecFormat::Unknown => None,
+        }
+    }
+
+    #[inline]
+    pub fn usable(&self, format: &CodecFormat) -> bool {
+        self.get(format).map_or(false, |s| s.usable())
+    }
+
+    /// The codec expected to be picked by the host, the host may still pick VP8 for the auto
+    /// choice if it's short of memory.
+    pub fn expected_codec(&self, prefer: Option<&CodecFormat>) -> CodecFormat {
+        match prefer {
+            Some(p) if self.usable(p) => p.clone(),
+            _ if self.av1.usable() => CodecFormat::AV1,
+            _ => CodecFormat::VP9,
+        }
+    }
+}
+
+/// Parse the value of [`OPTION_CODEC_PREFERENCE`], `None` for auto.
+pub fn parse_codec(codec: &str) -> Option<CodecFormat> {
+    match codec {
+        "vp8" => Some(CodecFormat::VP8),
+        "vp9" => Some(CodecFormat::VP9),
+        "av1" => Some(CodecFormat::AV1),
+        "h264" => Some(CodecFormat::H264),
+        "h265" => Some(CodecFormat::H265),
+        _ => None,
+    }
+}
+
+/// The hardware codecs only, the software decoders are not reinitialized on errors.
+#[inline]
+pub fn is_hardware(format: &CodecFormat) -> bool {
+    matches!(format, CodecFormat::H264 | CodecFormat::H265)
+}
+
+/// Remove the failed codecs from the decodings sent to the host.
+pub fn mask_decoding(decoding: &mut SupportedDecoding, hw_failed: &[CodecFormat]) {
+    for format in hw_failed {
+        let prefer = match format {
+            CodecFormat::H264 => {
+                decoding.ability_h264 = 0;
+                PreferCodec::H264
+            }
+            CodecFormat::H265 => {
+                decoding.ability_h265 = 0;
+                PreferCodec::H265
+            }
+            _ => continue,
+        };
+        if decoding.prefer == prefer.into() {
+            decoding.prefer = PreferCodec::Auto.into();
+        }
+    }
+}
+
+/// Why `active` is negotiated, see the `REASON_*` constants.
+pub fn negotiated_reason(
+    prefer: Option<&CodecFormat>,
+    active: &CodecFormat,
+    hw_failed: &[CodecFormat],
+) -> &'static str {
+    match prefer {
+        None => REASON_PREFERENCE,
+        Some(p) if p == active => REASON_PREFERENCE,
+        Some(p) if hw_failed.contains(p) => REASON_HW_INIT_FAILURE,
+        Some(_) => REASON_FALLBACK,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoding(h264: bool, h265: bool) -> SupportedDecoding {
+        SupportedDecoding {
+            ability_vp8: 1,
+            ability_vp9: 1,
+            ability_av1: 1,
+            ability_h264: h264 as i32,
+            ability_h265: h265 as i32,
+            ..Default::default()
+        }
+    }
+
+    fn encoding(av1: bool, h264: bool, h265: bool) -> SupportedEncoding {
+        SupportedEncoding {
+            vp8: true,
+            av1,
+            h264,
+            h265,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let caps = CodecCapabilities::new(&decoding(true, false), &encoding(false, true, true), &[]);
+        assert!(caps.vp8.usable());
+        assert!(caps.vp9.usable());
+        assert!(!caps.av1.usable() && caps.av1.local);
+        assert!(caps.h264.usable() && caps.h264.hardware);
+        assert!(!caps.h265.usable() && caps.h265.peer);
+        assert!(!caps.usable(&CodecFormat::Unknown));
+
+        let caps = CodecCapabilities::new(
+            &decoding(true, false),
+            &encoding(false, true, true),
+            &[CodecFormat::H264],
+        );
+        assert!(!caps.h264.local && caps.h264.peer);
+    }
+
+    #[test]
+    fn test_fallback_matrix() {
+        use CodecFormat::*;
+        // (local h264, peer av1, peer h264, preference, hw failed, expected codec, reason)
+        let matrix = [
+            (true, true, true, None, vec![], AV1, REASON_PREFERENCE),
+            (true, false, true, None, vec![], VP9, REASON_PREFERENCE),
+            (true, true, true, Some(H264), vec![], H264, REASON_PREFERENCE),
+            (true, true, true, Some(VP8), vec![], VP8, REASON_PREFERENCE),
+            // Not decodable by this side.
+            (false, true, true, Some(H264), vec![], AV1, REASON_FALLBACK),
+            // Not encodable by the peer.
+            (true, false, false, Some(H264), vec![], VP9, REASON_FALLBACK),
+            (true, false, true, Some(AV1), vec![], VP9, REASON_FALLBACK),
+            // The hardware decoder fails.
+            (true, true, true, Some(H264), vec![H264], AV1, REASON_HW_INIT_FAILURE),
+            (true, false, true, Some(H264), vec![H264], VP9, REASON_HW_INIT_FAILURE),
+            // Another hardware decoder fails.
+            (true, true, true, Some(H264), vec![H265], H264, REASON_PREFERENCE),
+        ];
+        for (i, (local_h264, av1, h264, prefer, failed, expected, reason)) in
+            matrix.iter().enumerate()
+        {
+            let caps = CodecCapabilities::new(
+                &decoding(*local_h264, false),
+                &encoding(*av1, *h264, false),
+                failed,
+            );
+            let active = caps.expected_codec(prefer.as_ref());
+            assert_eq!(&active, expected, "case {}", i);
+            assert_eq!(
+                negotiated_reason(prefer.as_ref(), &active, failed),
+                *reason,
+                "case {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_mask_decoding() {
+        let mut d = decoding(true, true);
+        d.prefer = PreferCodec::H264.into();
+        mask_decoding(&mut d, &[CodecFormat::H264, CodecFormat::VP9]);
+        assert_eq!(d.ability_h264, 0);
+        assert_eq!(d.ability_h265, 1);
+        assert_eq!(d.ability_vp9, 1);
+        assert_eq!(d.prefer, PreferCodec::Auto.into());
+
+        let mut d = decoding(true, true);
+        d.prefer = PreferCodec::VP8.into();
+        mask_decoding(&mut d, &[CodecFormat::H265]);
+        assert_eq!(d.ability_h265, 0);
+        assert_eq!(d.prefer, PreferCodec::VP8.into());
+    }
+
+    #[test]
+    fn test_parse_codec() {
+        assert_eq!(parse_codec("h265"), Some(CodecFormat::H265));
+        assert_eq!(parse_codec("auto"), None);
+        assert_eq!(parse_codec(""), None);
+        assert!(is_hardware(&CodecFormat::H264));
+        assert!(!is_hardware(&CodecFormat::AV1));
+    }
+}
@@ -0,0 +1,211 @@
+//! The color accuracy mode of a session, the frames are encoded in 4:4:4 instead of 4:2:0, so the
+//! colored text is not smeared.
+//!
+//! The mode is asked by the peer option [`OPTION_COLOR_ACCURACY`] in the decodings sent to the
+//! host, toggling it renegotiates like the codec preference. The host encodes a display in 4:4:4
+//! only if the codec supports it on both ends, VP9 and AV1 now, and all the sessions of the
+//! display ask for it, see `scrap::codec::Encoder::use_i444`. The displays still in 4:2:0 are told
+//! by "color_mode_fallback", see [`Tracker`]. The decoders convert 4:4:4 to the rgba directly.
+//!
+//! The 4:4:4 frames carry twice the samples of 4:2:0, the bitrate is up to
+//! [`I444_BITRATE_FACTOR`] times, so the ui may warn on the slow links.
+
+use hbb_common::message_proto::{Chroma, SupportedDecoding, SupportedEncoding};
+use scrap::CodecFormat;
+use serde_derive::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// The peer option, "Y" to ask for 4:4:4.
+pub const OPTION_COLOR_ACCURACY: &str = "i444";
+/// 3 samples per pixel instead of 1.5, the bitrate of the same quality is up to this times.
+pub const I444_BITRATE_FACTOR: f32 = 2.0;
+
+/// The active codec can't encode or decode 4:4:4 on both ends.
+pub const REASON_CODEC: &str = "codec";
+/// The host keeps 4:2:0, e.g. another session of the display asks for it.
+pub const REASON_HOST: &str = "host";
+
+// The frames encoded before the host switches keep coming for a while.
+const SWITCH_GRACE: Duration = Duration::from_secs(3);
+
+/// Whether 4:4:4 of `codec` is supported by the `decoding` of this side and the `encoding` of the
+/// peer.
+pub fn i444_usable(
+    codec: &CodecFormat,
+    decoding: &SupportedDecoding,
+    encoding: &SupportedEncoding,
+) -> bool {
+    match codec {
+        CodecFormat::VP9 => decoding.i444.vp9 && encoding.i444.vp9,
+        CodecFormat::AV1 => decoding.i444.av1 && encoding.i444.av1,
+        _ => false,
+    }
+}
+
+/// The mode of the session, for the ui to warn before the bitrate goes up.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColorMode {
+    pub requested: bool,
+    /// 4:4:4 is usable by the active codec, unknown till the first frame.
+    pub usable: Option<bool>,
+    pub bitrate_factor: f32,
+}
+
+/// A display falling back to 4:2:0 while 4:4:4 is asked.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fallback {
+    pub display: usize,
+    pub reason: &'static str,
+    pub codec: String,
+    /// The bitrate factor if it were 4:4:4.
+    pub bitrate_factor: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct Tracker {
+    requested: bool,
+    codec: Option<CodecFormat>,
+    // The mode or the codec changes, the frames of the old one are not counted till the grace.
+    changed: Option<Instant>,
+    // The displays told, till they are in 4:4:4 again.
+    reported: HashSet<usize>,
+}
+
+impl Tracker {
+    #[inline]
+    pub fn codec(&self) -> Option<&CodecFormat> {
+        self.codec.as_ref()
+    }
+
+    pub fn on_codec(&mut self, codec: &CodecFormat, now: Instant) {
+        if self.codec.as_ref() != Some(codec) {
+            self.codec = Some(codec.clone());
+            self.changed = Some(now);
+        }
+    }
+
+    /// The displays in 4:2:0 by the chroma of their frames, not told yet.
+    /// `usable` is [`i444_usable`] of the active codec.
+    pub fn check(
+        &mut self,
+        requested: bool,
+        usable: bool,
+        chromas: &HashMap<usize, Option<Chroma>>,
+        now: Instant,
+    ) -> Vec<Fallback> {
+        if requested != self.requested {
+            self.requested = requested;
+            self.changed = Some(now);
+            self.reported.clear();
+        }
+        if !requested {
+            return vec![];
+        }
+        if let Some(changed) = self.changed {
+            if now.saturating_duration_since(changed) < SWITCH_GRACE {
+                return vec![];
+            }
+        }
+        let codec = self
+            .codec
+            .as_ref()
+            .map(|c| c.to_string())
+            .unwrap_or_default();
+        let mut fallbacks = vec![];
+        let mut displays: Vec<_> = chromas.iter().collect();
+        displays.sort_by_key(|(display, _)| **display);
+        for (display, chroma) in displays {
+            match chroma {
+                Some(Chroma::I420) => {
+                    if self.reported.insert(*display) {
+                        fallbacks.push(Fallback {
+                            display: *display,
+                            reason: if usable { REASON_HOST } else { REASON_CODEC },
+                            codec: codec.clone(),
+                            bitrate_factor: I444_BITRATE_FACTOR,
+                        });
+                    }
+                }
+                Some(Chroma::I444) => {
+                    self.reported.remove(display);
+                }
+                None => {}
+            }
+        }
+        fallbacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::message_proto::CodecAbility;
+
+    fn ability(vp9: bool, av1: bool) -> CodecAbility {
+        CodecAbility {
+            vp9,
+            av1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_i444_usable() {
+        let decoding = SupportedDecoding {
+            i444: Some(ability(true, true)).into(),
+            ..Default::default()
+        };
+        let encoding = SupportedEncoding {
+            i444: Some(ability(true, false)).into(),
+            ..Default::default()
+        };
+        assert!(i444_usable(&CodecFormat::VP9, &decoding, &encoding));
+        assert!(!i444_usable(&CodecFormat::AV1, &decoding, &encoding));
+        assert!(!i444_usable(&CodecFormat::H264, &decoding, &encoding));
+        // The old peer without the ability.
+        let encoding = SupportedEncoding::default();
+        assert!(!i444_usable(&CodecFormat::VP9, &decoding, &encoding));
+    }
+
+    #[test]
+    fn test_tracker() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let chromas = |v: &[(usize, Chroma)]| -> HashMap<usize, Option<Chroma>> {
+            v.iter().map(|(d, c)| (*d, Some(*c))).collect()
+        };
+        let mut tracker = Tracker::default();
+        tracker.on_codec(&CodecFormat::VP9, start);
+        let i420 = chromas(&[(0, Chroma::I420), (1, Chroma::I420)]);
+        assert!(tracker.check(false, true, &i420, at(10)).is_empty());
+
+        // The frames before the switch.
+        assert!(tracker.check(true, true, &i420, at(10)).is_empty());
+        let mixed = chromas(&[(0, Chroma::I444), (1, Chroma::I420)]);
+        let fallbacks = tracker.check(true, true, &mixed, at(14));
+        assert_eq!(fallbacks.len(), 1);
+        assert_eq!(fallbacks[0].display, 1);
+        assert_eq!(fallbacks[0].reason, REASON_HOST);
+        assert_eq!(fallbacks[0].codec, "VP9");
+        assert_eq!(fallbacks[0].bitrate_factor, I444_BITRATE_FACTOR);
+        // Told once.
+        assert!(tracker.check(true, true, &mixed, at(15)).is_empty());
+        let i444 = chromas(&[(0, Chroma::I444), (1, Chroma::I444)]);
+        assert!(tracker.check(true, true, &i444, at(16)).is_empty());
+
+        // Another codec.
+        tracker.on_codec(&CodecFormat::H264, at(20));
+        assert!(tracker.check(true, false, &i420, at(21)).is_empty());
+        let fallbacks = tracker.check(true, false, &i420, at(23));
+        assert_eq!(fallbacks.len(), 2);
+        assert!(fallbacks.iter().all(|f| f.reason == REASON_CODEC));
+
+        // Asked again.
+        assert!(tracker.check(false, false, &i420, at(24)).is_empty());
+        assert!(tracker.check(true, false, &i420, at(25)).is_empty());
+        assert_eq!(tracker.check(true, false, &i420, at(28)).len(), 2);
+    }
+}
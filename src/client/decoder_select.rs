@@ -0,0 +1,161 @@
+//! The decoder of each display, hardware or software, and what the ui is told about it.
+//!
+//! The hardware decoders may fail to be created on some GPUs or drivers, or glitch once created.
+//! The user may force the software decoding per peer, then the hardware codecs are removed from
+//! the decodings sent to the host, see [`super::codec_negotiation::mask_decoding`], and the
+//! decoders are created again without the hardware ones, without reconnecting.
+//!
+//! The decoders are probed by [`DecoderBackend`], so the selection is testable without the GPUs.
+
+use super::codec_negotiation::{is_hardware, REASON_HW_INIT_FAILURE};
+use scrap::CodecFormat;
+use serde_derive::Serialize;
+
+/// The peer option to decode in software only, "Y" if forced.
+pub const OPTION_FORCE_SOFTWARE_DECODE: &str = "force-software-decode";
+
+/// The decoder of the codec is created as expected.
+pub const REASON_DEFAULT: &str = "default";
+/// The software decoding is forced by the user.
+pub const REASON_FORCED_SOFTWARE: &str = "forced-software";
+/// The software decoder fails to be created.
+pub const REASON_INIT_FAILURE: &str = "init-failure";
+
+/// The decoders created for a display.
+pub trait DecoderBackend {
+    fn initialized(&self, format: &CodecFormat) -> bool;
+    /// The name of the hardware decoder of `format`, `None` if it's not created.
+    fn hw_adapter(&self, format: &CodecFormat) -> Option<String>;
+}
+
+impl DecoderBackend for scrap::codec::Decoder {
+    #[inline]
+    fn initialized(&self, format: &CodecFormat) -> bool {
+        scrap::codec::Decoder::initialized(self, format)
+    }
+
+    #[inline]
+    fn hw_adapter(&self, format: &CodecFormat) -> Option<String> {
+        scrap::codec::Decoder::hw_adapter(self, format)
+    }
+}
+
+/// Pushed by "decoder_info" once the decoder of a display starts or changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DecoderInfo {
+    pub codec: String,
+    pub hardware: bool,
+    /// The name of the hardware decoder, empty for the software ones.
+    pub adapter: String,
+    /// See the `REASON_*` constants.
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub info: DecoderInfo,
+    /// The hardware codec can't be decoded, the host should be asked to use a software one.
+    pub fall_back: bool,
+}
+
+/// The decoder of the frames of `format`.
+pub fn select(
+    format: &CodecFormat,
+    force_software: bool,
+    backend: &impl DecoderBackend,
+) -> Selection {
+    let info = |hardware: bool, adapter: String, reason: &'static str| DecoderInfo {
+        codec: format.to_string(),
+        hardware,
+        adapter,
+        reason,
+    };
+    if !is_hardware(format) {
+        let reason = if !backend.initialized(format) {
+            REASON_INIT_FAILURE
+        } else if force_software {
+            REASON_FORCED_SOFTWARE
+        } else {
+            REASON_DEFAULT
+        };
+        return Selection {
+            info: info(false, "".to_owned(), reason),
+            fall_back: false,
+        };
+    }
+    // The frames sent before the host switches the codec.
+    if force_software {
+        return Selection {
+            info: info(false, "".to_owned(), REASON_FORCED_SOFTWARE),
+            fall_back: true,
+        };
+    }
+    match backend.hw_adapter(format) {
+        Some(adapter) if backend.initialized(format) => Selection {
+            info: info(true, adapter, REASON_DEFAULT),
+            fall_back: false,
+        },
+        _ => Selection {
+            info: info(false, "".to_owned(), REASON_HW_INIT_FAILURE),
+            fall_back: true,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeBackend {
+        software: bool,
+        // Empty if the hardware decoder fails to be created.
+        adapter: &'static str,
+    }
+
+    impl DecoderBackend for FakeBackend {
+        fn initialized(&self, format: &CodecFormat) -> bool {
+            if is_hardware(format) {
+                !self.adapter.is_empty()
+            } else {
+                self.software
+            }
+        }
+
+        fn hw_adapter(&self, format: &CodecFormat) -> Option<String> {
+            if is_hardware(format) && !self.adapter.is_empty() {
+                Some(self.adapter.to_owned())
+            } else {
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_matrix() {
+        use CodecFormat::*;
+        // (format, forced, software created, hardware adapter, hardware, reason, fall back)
+        let matrix = [
+            (VP9, false, true, "nv", false, REASON_DEFAULT, false),
+            (AV1, true, true, "", false, REASON_FORCED_SOFTWARE, false),
+            (VP8, false, false, "", false, REASON_INIT_FAILURE, false),
+            (H264, false, true, "nv", true, REASON_DEFAULT, false),
+            (H265, true, true, "nv", false, REASON_FORCED_SOFTWARE, true),
+            (H264, false, true, "", false, REASON_HW_INIT_FAILURE, true),
+        ];
+        for (i, (format, forced, software, adapter, hardware, reason, fall_back)) in
+            matrix.iter().enumerate()
+        {
+            let backend = FakeBackend {
+                software: *software,
+                adapter: *adapter,
+            };
+            let selection = select(format, *forced, &backend);
+            assert_eq!(selection.info.codec, format.to_string(), "case {}", i);
+            assert_eq!(selection.info.hardware, *hardware, "case {}", i);
+            assert_eq!(selection.info.reason, *reason, "case {}", i);
+            assert_eq!(selection.fall_back, *fall_back, "case {}", i);
+            let expected_adapter = if *hardware { *adapter } else { "" };
+            assert_eq!(selection.info.adapter, expected_adapter, "case {}", i);
+        }
+    }
+}
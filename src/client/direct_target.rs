@@ -0,0 +1,236 @@
+//! The direct connection to an address, without the rendezvous server.
+//!
+//! A direct target is given to `session_add` apart from the id, the session id is then
+//! [`DirectTarget::to_id`], e.g. `direct://192.168.1.2:21118` or `direct://[fe80::1]:21119`,
+//! which is also the id kept in the recent peers and the exported peer configs.
+//! [`LoginConfigHandler::initialize`](super::LoginConfigHandler::initialize) parses it back.
+
+use hbb_common::{bail, config::RELAY_PORT, ResultType};
+use std::net::Ipv6Addr;
+
+pub const DIRECT_ID_PREFIX: &str = "direct://";
+/// The port of the direct access server of the peer.
+pub const DEFAULT_DIRECT_PORT: u16 = (RELAY_PORT + 1) as u16;
+
+/// The hint of the transport, only tcp is supported now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectProtocol {
+    #[default]
+    Tcp,
+}
+
+impl DirectProtocol {
+    fn parse(s: &str) -> ResultType<Self> {
+        match s.to_lowercase().as_str() {
+            "" | "tcp" => Ok(Self::Tcp),
+            _ => bail!("Unsupported protocol of the direct connection: {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectTarget {
+    /// The hostname or the ip, without the brackets of ipv6.
+    pub host: String,
+    pub port: u16,
+    pub protocol: DirectProtocol,
+}
+
+impl DirectTarget {
+    #[inline]
+    pub fn is_direct_id(id: &str) -> bool {
+        id.starts_with(DIRECT_ID_PREFIX)
+    }
+
+    /// Parse `host`, `host:port`, `[ipv6]:port` or a bare ipv6 literal, optionally prefixed by
+    /// [`DIRECT_ID_PREFIX`] and followed by `?protocol=tcp`.
+    pub fn parse(target: &str) -> ResultType<Self> {
+        let target = target.trim();
+        let target = target.strip_prefix(DIRECT_ID_PREFIX).unwrap_or(target);
+        let (addr, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut protocol = DirectProtocol::default();
+        for arg in query.split('&').filter(|arg| !arg.is_empty()) {
+            match arg.split_once('=') {
+                Some(("protocol", v)) => protocol = DirectProtocol::parse(v)?,
+                _ => bail!("Invalid option of the direct connection: {}", arg),
+            }
+        }
+        let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+            let Some((host, port)) = rest.split_once(']') else {
+                bail!("Invalid address {}, missing ']'", addr);
+            };
+            if host.parse::<Ipv6Addr>().is_err() {
+                bail!("Invalid ipv6 address {}", host);
+            }
+            match port {
+                "" => (host, None),
+                _ => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => bail!("Invalid address {}", addr),
+                },
+            }
+        } else if addr.matches(':').count() > 1 {
+            // A bare ipv6 literal can't have a port, it's ambiguous.
+            if addr.parse::<Ipv6Addr>().is_err() {
+                bail!("Invalid ipv6 address {}, use [address]:port with a port", addr);
+            }
+            (addr, None)
+        } else {
+            match addr.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (addr, None),
+            }
+        };
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || "/@\\".contains(c)) {
+            bail!("Invalid host {:?}", host);
+        }
+        let port = match port {
+            None => DEFAULT_DIRECT_PORT,
+            Some(port) => match port.parse::<u16>() {
+                Ok(port) if port > 0 => port,
+                _ => bail!("Invalid port {:?}", port),
+            },
+        };
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            protocol,
+        })
+    }
+
+    /// The address to connect, with the brackets of ipv6.
+    pub fn addr(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// The id of the session, parsed back by [`Self::parse`].
+    pub fn to_id(&self) -> String {
+        // The default protocol is omitted.
+        format!("{}{}", DIRECT_ID_PREFIX, self.addr())
+    }
+}
+
+/// The failures of the direct connections, shown by the msgbox types the ui can tell apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectError {
+    Refused,
+    TimedOut,
+    /// Connected, but closed before any message, not the service of the peer.
+    WrongService,
+}
+
+impl DirectError {
+    /// `connected`: the tcp connection was established.
+    /// `received`: any message was received from the peer.
+    pub fn classify(err: &str, connected: bool, received: bool) -> Option<Self> {
+        let err = err.to_lowercase();
+        if err.contains("refused") || err.contains("10061") {
+            return Some(Self::Refused);
+        }
+        if !connected
+            && (err.contains("timed out")
+                || err.contains("timeout")
+                || err.contains("elapsed")
+                || err.contains("10060"))
+        {
+            return Some(Self::TimedOut);
+        }
+        if connected && !received {
+            return Some(Self::WrongService);
+        }
+        None
+    }
+
+    pub fn msgtype(&self) -> &'static str {
+        match self {
+            Self::Refused => "direct-refused-error",
+            Self::TimedOut => "direct-timeout-error",
+            Self::WrongService => "direct-wrong-service-error",
+        }
+    }
+
+    /// The lang key of the text, with the params `addr` and `error`.
+    pub fn text_key(&self) -> &'static str {
+        match self {
+            Self::Refused => "direct_refused_tip",
+            Self::TimedOut => "direct_timeout_tip",
+            Self::WrongService => "direct_wrong_service_tip",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(target: &str) -> Option<(String, u16)> {
+        DirectTarget::parse(target).ok().map(|t| (t.host, t.port))
+    }
+
+    #[test]
+    fn test_parse_direct_target() {
+        let some = |host: &str, port: u16| Some((host.to_owned(), port));
+        assert_eq!(parse("192.168.1.2"), some("192.168.1.2", DEFAULT_DIRECT_PORT));
+        assert_eq!(parse(" 192.168.1.2:8000 "), some("192.168.1.2", 8000));
+        assert_eq!(parse("host.lan:21119"), some("host.lan", 21119));
+        assert_eq!(parse("[fe80::1]:21119"), some("fe80::1", 21119));
+        assert_eq!(parse("[::1]"), some("::1", DEFAULT_DIRECT_PORT));
+        assert_eq!(parse("fe80::1"), some("fe80::1", DEFAULT_DIRECT_PORT));
+        assert_eq!(parse("[2001:db8::ff]:1"), some("2001:db8::ff", 1));
+        assert_eq!(
+            parse("direct://[fe80::1]:21119?protocol=tcp"),
+            some("fe80::1", 21119)
+        );
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(":21118"), None);
+        assert_eq!(parse("[fe80::1]21119"), None);
+        assert_eq!(parse("[fe80::1"), None);
+        assert_eq!(parse("[host]:1"), None);
+        assert_eq!(parse("fe80::1:21119x"), None);
+        assert_eq!(parse("host:0"), None);
+        assert_eq!(parse("host:65536"), None);
+        assert_eq!(parse("host:21118?protocol=udp"), None);
+        assert_eq!(parse("id@server"), None);
+    }
+
+    #[test]
+    fn test_direct_id_round_trip() {
+        for target in ["10.0.0.1", "host.lan:8000", "[fe80::1]:21119", "::1"] {
+            let parsed = DirectTarget::parse(target).unwrap();
+            let id = parsed.to_id();
+            assert!(DirectTarget::is_direct_id(&id));
+            assert_eq!(DirectTarget::parse(&id).unwrap(), parsed);
+        }
+        assert_eq!(
+            DirectTarget::parse("::1").unwrap().to_id(),
+            "direct://[::1]:21118"
+        );
+    }
+
+    #[test]
+    fn test_classify_direct_error() {
+        let classify = DirectError::classify;
+        assert_eq!(
+            classify("Connection refused (os error 111)", false, false),
+            Some(DirectError::Refused)
+        );
+        assert_eq!(
+            classify("deadline has elapsed", false, false),
+            Some(DirectError::TimedOut)
+        );
+        assert_eq!(
+            classify("Reset by the peer", true, false),
+            Some(DirectError::WrongService)
+        );
+        // Connected, but the service never answers.
+        assert_eq!(classify("Timeout", true, false), Some(DirectError::WrongService));
+        // The errors after the handshake are left to the common ones.
+        assert_eq!(classify("Reset by the peer", true, true), None);
+        assert_eq!(classify("failed to lookup address", false, false), None);
+    }
+}
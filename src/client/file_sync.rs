@@ -0,0 +1,405 @@
+//! Keep a remote directory mirrored from a local one while the session is open.
+//!
+//! A thread per sync job scans the local directory every [`SCAN_INTERVAL`], and reports the
+//! changes to the io loop once the directory is quiet for [`DEBOUNCE`], so a file being written
+//! is sent once. The io loop sends the changed files by a read job of the usual file transfer,
+//! one batch at a time, and removes the deleted ones on the peer if [`SyncOptions::delete`].
+//!
+//! The scan keeps the size and the modified time of each file only, and fails with more than
+//! [`MAX_SYNC_FILES`] files, so the memory is bounded on huge trees. The thread stops once its
+//! [`SyncHandle`] is dropped, with the job or the session.
+
+use super::Data;
+use hbb_common::{
+    bail, fs::get_string, log, message_proto::FileEntry, tokio::sync::mpsc::UnboundedSender,
+    ResultType,
+};
+use serde_derive::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+pub const MAX_SYNC_FILES: usize = 100_000;
+// The stop of the thread is checked in this period while waiting for the next scan.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncOptions {
+    /// The files deleted locally are deleted on the peer, off by default.
+    pub delete: bool,
+    /// The strategy of the existing files which differ, the same as the remembered choice of
+    /// the override confirm, `None` to ask.
+    pub overwrite: Option<bool>,
+}
+
+impl SyncOptions {
+    /// `overwrite` is "overwrite", "skip" or "ask", "overwrite" by default.
+    pub fn new(delete: bool, overwrite: &str) -> Self {
+        let overwrite = match overwrite {
+            "skip" => Some(false),
+            "ask" => None,
+            _ => Some(true),
+        };
+        Self { delete, overwrite }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SyncState {
+    Idle,
+    Scanning,
+    Syncing { files: usize },
+    Error { error: String },
+    Stopped,
+}
+
+impl SyncState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "idle",
+            Self::Scanning => "scanning",
+            Self::Syncing { .. } => "syncing",
+            Self::Error { .. } => "error",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+/// The relative path of each file -> the size and the modified time.
+pub type Snapshot = HashMap<String, (u64, u64)>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Changes {
+    /// Created or modified, the names relative to the synced directory.
+    pub changed: Vec<FileEntry>,
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// Add the newer changes, a file changed again is sent once.
+    pub fn merge(&mut self, newer: Changes) {
+        for entry in newer.changed {
+            self.removed.retain(|n| *n != entry.name);
+            self.changed.retain(|e| e.name != entry.name);
+            self.changed.push(entry);
+        }
+        for name in newer.removed {
+            self.changed.retain(|e| e.name != name);
+            if !self.removed.contains(&name) {
+                self.removed.push(name);
+            }
+        }
+    }
+}
+
+/// The files under `root`, an error if more than `max`.
+pub fn scan(root: &Path, max: usize) -> ResultType<Snapshot> {
+    if !root.is_dir() {
+        bail!("Not a directory: {}", get_string(root));
+    }
+    let mut snapshot = Snapshot::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        // The directories removed meanwhile are skipped.
+        let Ok(entries) = std::fs::read_dir(root.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let name = dir.join(entry.file_name());
+            if meta.is_dir() {
+                dirs.push(name);
+            } else if meta.is_file() {
+                if snapshot.len() >= max {
+                    bail!("Too many files to sync, more than {}", max);
+                }
+                let modified = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                snapshot.insert(get_string(&name), (meta.len(), modified));
+            }
+        }
+    }
+    Ok(snapshot)
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Changes {
+    let mut changes = Changes::default();
+    for (name, (size, modified)) in new {
+        if old.get(name) != Some(&(*size, *modified)) {
+            changes.changed.push(FileEntry {
+                name: name.clone(),
+                size: *size,
+                modified_time: *modified,
+                ..Default::default()
+            });
+        }
+    }
+    for name in old.keys() {
+        if !new.contains_key(name) {
+            changes.removed.push(name.clone());
+        }
+    }
+    changes.changed.sort_by(|a, b| a.name.cmp(&b.name));
+    changes.removed.sort();
+    changes
+}
+
+/// The changes of the scans, reported once the directory is quiet.
+#[derive(Default)]
+pub struct DirWatch {
+    // The files reported, all are changed at first.
+    known: Snapshot,
+    // The last scan which differs from the known one, and since when.
+    changing: Option<(Snapshot, Instant)>,
+}
+
+impl DirWatch {
+    pub fn on_scan(&mut self, scan: Snapshot, now: Instant) -> Option<Changes> {
+        if scan == self.known {
+            self.changing = None;
+            return None;
+        }
+        match &self.changing {
+            Some((last, since)) if *last == scan => {
+                if now.saturating_duration_since(*since) < DEBOUNCE {
+                    return None;
+                }
+            }
+            _ => {
+                self.changing = Some((scan, now));
+                return None;
+            }
+        }
+        self.changing = None;
+        let changes = diff(&self.known, &scan);
+        self.known = scan;
+        Some(changes)
+    }
+}
+
+/// The thread watching the directory stops once dropped.
+pub struct SyncHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for SyncHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// A sync job of the io loop.
+pub struct SyncJob {
+    pub path: String,
+    pub to: String,
+    pub options: SyncOptions,
+    /// A batch of the changes is being sent.
+    pub in_flight: bool,
+    /// The changes waiting for the batch in flight.
+    pub pending: Changes,
+    _handle: SyncHandle,
+}
+
+impl SyncJob {
+    pub fn new(
+        id: i32,
+        path: String,
+        to: String,
+        options: SyncOptions,
+        sender: UnboundedSender<Data>,
+    ) -> Self {
+        let handle = spawn(id, PathBuf::from(&path), sender);
+        Self {
+            path,
+            to,
+            options,
+            in_flight: false,
+            pending: Default::default(),
+            _handle: handle,
+        }
+    }
+}
+
+/// Watch `root` in a thread, the changes and the errors are sent to the io loop as
+/// `Data::SyncChanges` and `Data::SyncError`.
+pub fn spawn(id: i32, root: PathBuf, sender: UnboundedSender<Data>) -> SyncHandle {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_cloned = stop.clone();
+    std::thread::spawn(move || {
+        let mut watch = DirWatch::default();
+        while !stop_cloned.load(Ordering::SeqCst) {
+            let started = Instant::now();
+            let msg = match scan(&root, MAX_SYNC_FILES) {
+                Ok(snapshot) => watch
+                    .on_scan(snapshot, started)
+                    .map(|changes| Data::SyncChanges((id, changes))),
+                Err(err) => Some(Data::SyncError((id, err.to_string()))),
+            };
+            let failed = matches!(msg, Some(Data::SyncError(_)));
+            if let Some(msg) = msg {
+                if sender.send(msg).is_err() {
+                    break;
+                }
+            }
+            if failed {
+                break;
+            }
+            while started.elapsed() < SCAN_INTERVAL && !stop_cloned.load(Ordering::SeqCst) {
+                std::thread::sleep(STOP_CHECK_INTERVAL);
+            }
+        }
+        log::info!("sync job {} of {:?} stopped", id, root);
+    });
+    SyncHandle { stop }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::tokio::{self, sync::mpsc::unbounded_channel, time::timeout};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            std::fs::remove_dir_all(&dir).ok();
+            std::fs::create_dir_all(dir.join("sub")).unwrap();
+            std::fs::write(dir.join("a.txt"), b"a").unwrap();
+            std::fs::write(dir.join("sub").join("b.txt"), b"bb").unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn b_txt() -> String {
+        get_string(&Path::new("sub").join("b.txt"))
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = TempDir::new("file_sync_scan");
+        let snapshot = scan(&dir.0, 10).unwrap();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["a.txt"].0, 1);
+        assert_eq!(snapshot[&b_txt()].0, 2);
+        // Bounded.
+        assert!(scan(&dir.0, 1).is_err());
+        assert!(scan(&dir.0.join("a.txt"), 10).is_err());
+    }
+
+    #[test]
+    fn test_debounce() {
+        let now = Instant::now();
+        let ms = |n: u64| now + Duration::from_millis(n);
+        let snapshot = |files: &[(&str, u64)]| -> Snapshot {
+            files
+                .iter()
+                .map(|(n, size)| (n.to_string(), (*size, 0)))
+                .collect()
+        };
+        let mut watch = DirWatch::default();
+        // Reported once quiet.
+        assert_eq!(watch.on_scan(snapshot(&[("a", 1)]), ms(0)), None);
+        let changes = watch.on_scan(snapshot(&[("a", 1)]), ms(1000)).unwrap();
+        assert_eq!(changes.changed.len(), 1);
+        assert_eq!(watch.on_scan(snapshot(&[("a", 1)]), ms(2000)), None);
+
+        // Still being written.
+        assert_eq!(watch.on_scan(snapshot(&[("a", 2)]), ms(3000)), None);
+        assert_eq!(watch.on_scan(snapshot(&[("a", 3)]), ms(3200)), None);
+        assert_eq!(watch.on_scan(snapshot(&[("a", 3)]), ms(3500)), None);
+        let changes = watch.on_scan(snapshot(&[("a", 3)]), ms(3700)).unwrap();
+        assert_eq!(changes.changed[0].size, 3);
+
+        // Changed back before quiet.
+        assert_eq!(watch.on_scan(snapshot(&[]), ms(4000)), None);
+        assert_eq!(watch.on_scan(snapshot(&[("a", 3)]), ms(5000)), None);
+        assert_eq!(watch.on_scan(snapshot(&[("a", 3)]), ms(6000)), None);
+
+        assert_eq!(watch.on_scan(snapshot(&[("b", 1)]), ms(7000)), None);
+        let changes = watch.on_scan(snapshot(&[("b", 1)]), ms(8000)).unwrap();
+        assert_eq!(changes.removed, vec!["a".to_owned()]);
+        assert_eq!(changes.changed[0].name, "b");
+    }
+
+    #[test]
+    fn test_merge_changes() {
+        let entry = |name: &str, size| FileEntry {
+            name: name.to_owned(),
+            size,
+            ..Default::default()
+        };
+        let mut changes = Changes {
+            changed: vec![entry("a", 1), entry("b", 1)],
+            removed: vec!["c".to_owned()],
+        };
+        changes.merge(Changes {
+            changed: vec![entry("a", 2), entry("c", 1)],
+            removed: vec!["b".to_owned()],
+        });
+        assert_eq!(changes.changed, vec![entry("a", 2), entry("c", 1)]);
+        assert_eq!(changes.removed, vec!["b".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_thread() {
+        let dir = TempDir::new("file_sync_watch");
+        let (tx, mut rx) = unbounded_channel();
+        let handle = spawn(1, dir.0.clone(), tx);
+        let wait = Duration::from_secs(10);
+        match timeout(wait, rx.recv()).await.unwrap() {
+            Some(Data::SyncChanges((1, changes))) => {
+                assert_eq!(changes.changed.len(), 2);
+                assert!(changes.removed.is_empty());
+            }
+            _ => panic!("the initial changes expected"),
+        }
+        std::fs::write(dir.0.join("c.txt"), b"ccc").unwrap();
+        std::fs::remove_file(dir.0.join("a.txt")).unwrap();
+        match timeout(wait, rx.recv()).await.unwrap() {
+            Some(Data::SyncChanges((1, changes))) => {
+                assert_eq!(changes.changed.len(), 1);
+                assert_eq!(changes.changed[0].name, "c.txt");
+                assert_eq!(changes.removed, vec!["a.txt".to_owned()]);
+            }
+            _ => panic!("the changes expected"),
+        }
+        // The thread stops, the sender is dropped.
+        drop(handle);
+        assert!(timeout(wait, rx.recv()).await.unwrap().is_none());
+
+        // Fails on the directory removed.
+        let (tx, mut rx) = unbounded_channel();
+        let _handle = spawn(2, dir.0.join("none"), tx);
+        assert!(matches!(
+            timeout(wait, rx.recv()).await.unwrap(),
+            Some(Data::SyncError((2, _)))
+        ));
+        assert!(timeout(wait, rx.recv()).await.unwrap().is_none());
+    }
+}
@@ -73,6 +73,15 @@ pub trait FileManager: Interface {
         self.send(Data::CreateDir((id, path, is_remote)));
     }
 
+    fn rename_file(&self, id: i32, path: String, new_name: String, is_remote: bool) {
+        self.send(Data::RenameFile((id, path, new_name, is_remote)));
+    }
+
+    // Canceled as the jobs, see `cancel_job`.
+    fn delete_path(&self, id: i32, path: String, recursive: bool, is_remote: bool) {
+        self.send(Data::DeletePath((id, path, recursive, is_remote)));
+    }
+
     fn send_files(
         &self,
         id: i32,
@@ -115,6 +124,16 @@ pub trait FileManager: Interface {
         self.send(Data::ResumeJob((id, is_remote)));
     }
 
+    fn start_sync(&self, id: i32, path: String, to: String, delete: bool, overwrite: &str) {
+        let options = super::file_sync::SyncOptions::new(delete, overwrite);
+        self.send(Data::StartSync((id, path, to, options)));
+    }
+
+    // Stopped as the jobs, see `cancel_job`.
+    fn stop_sync(&self, id: i32) {
+        self.send(Data::CancelJob(id));
+    }
+
     fn set_confirm_override_file(
         &self,
         id: i32,
@@ -1,10 +1,22 @@
 use hbb_common::{
     get_time,
-    message_proto::{Message, VoiceCallRequest, VoiceCallResponse},
+    message_proto::{Chroma, Message, VoiceCallRequest, VoiceCallResponse},
 };
 use scrap::CodecFormat;
+use serde_derive::Serialize;
 use std::collections::HashMap;
 
+/// The metrics of one display, keyed by the display index in [`QualityStatus::displays`].
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DisplayQuality {
+    pub fps: i32,
+    /// The average decoding time of a frame in ms, the delay added by this display.
+    pub delay: Option<i32>,
+    pub chroma: Option<String>,
+}
+
+/// `fps`, `delay` and `chroma` are the totals of the session,
+/// `displays` is the breakdown of the displays being captured.
 #[derive(Debug, Default)]
 pub struct QualityStatus {
     pub speed: Option<String>,
@@ -13,6 +25,66 @@ pub struct QualityStatus {
     pub target_bitrate: Option<i32>,
     pub codec_format: Option<CodecFormat>,
     pub chroma: Option<String>,
+    pub displays: HashMap<usize, DisplayQuality>,
+    /// Measured if asked, see `latency`.
+    pub latency_breakdown: Option<super::latency::Breakdown>,
+    /// 4:4:4 is asked, the active one of each display is its `chroma`, see `color_mode`.
+    pub color_accuracy: Option<bool>,
+}
+
+impl QualityStatus {
+    /// The status of the displays being shown, `fps` is the frames shown per second of each display.
+    pub fn of_displays(
+        fps: HashMap<usize, i32>,
+        decode_stats: &HashMap<usize, DecodeStats>,
+    ) -> Self {
+        let displays: HashMap<_, _> = fps
+            .iter()
+            .map(|(display, fps)| {
+                let stats = decode_stats.get(display).cloned().unwrap_or_default();
+                let quality = DisplayQuality {
+                    fps: *fps,
+                    delay: stats.delay.map(|d| d as i32),
+                    chroma: Some(chroma_name(stats.chroma).to_owned()),
+                };
+                (*display, quality)
+            })
+            .collect();
+        let mut chromas: Vec<_> = displays
+            .values()
+            .filter_map(|q| q.chroma.clone())
+            .filter(|c| c != "-")
+            .collect();
+        chromas.sort();
+        chromas.dedup();
+        let chroma = if chromas.is_empty() {
+            "-".to_owned()
+        } else {
+            chromas.join("/")
+        };
+        Self {
+            fps,
+            chroma: Some(chroma),
+            displays,
+            ..Default::default()
+        }
+    }
+}
+
+/// The decoding stats of a display.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DecodeStats {
+    pub chroma: Option<Chroma>,
+    /// The average decoding time of a frame in ms, none until enough frames are decoded.
+    pub delay: Option<usize>,
+}
+
+fn chroma_name(chroma: Option<Chroma>) -> &'static str {
+    match chroma {
+        Some(Chroma::I444) => "4:4:4",
+        Some(Chroma::I420) => "4:2:0",
+        None => "-",
+    }
 }
 
 #[inline]
@@ -35,3 +107,43 @@ pub fn new_voice_call_response(request_timestamp: i64, accepted: bool) -> Messag
     msg.set_voice_call_response(resp);
     msg
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_status_of_displays() {
+        let stats = |chroma, delay| DecodeStats { chroma, delay };
+        let qs = QualityStatus::of_displays(
+            HashMap::from([(0, 30)]),
+            &HashMap::from([(0, stats(Some(Chroma::I444), Some(5)))]),
+        );
+        assert_eq!(qs.fps, HashMap::from([(0, 30)]));
+        assert_eq!(qs.chroma.as_deref(), Some("4:4:4"));
+        assert_eq!(
+            qs.displays[&0],
+            DisplayQuality {
+                fps: 30,
+                delay: Some(5),
+                chroma: Some("4:4:4".to_owned()),
+            }
+        );
+
+        // The displays not decoded yet, and the ones with different chroma.
+        let qs = QualityStatus::of_displays(
+            HashMap::from([(0, 30), (1, 20), (2, 0)]),
+            &HashMap::from([
+                (0, stats(Some(Chroma::I444), Some(5))),
+                (1, stats(Some(Chroma::I420), None)),
+            ]),
+        );
+        assert_eq!(qs.chroma.as_deref(), Some("4:2:0/4:4:4"));
+        assert_eq!(qs.displays[&1].delay, None);
+        assert_eq!(qs.displays[&2].chroma.as_deref(), Some("-"));
+        assert_eq!(
+            QualityStatus::of_displays(Default::default(), &Default::default()).chroma,
+            Some("-".to_owned())
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! The hotkey rules of a peer, deciding if a key combination, e.g. Alt+Tab or Win+L, is sent to
+//! the peer, kept local or blocked. `Session::send_key_event` consults them before the key events
+//! are sent.
+//!
+//! A pattern is the modifiers and a key joined by "+", e.g. "alt+tab", "ctrl+shift+esc" or
+//! "meta+l". The modifiers must match exactly, i.e. "alt+tab" doesn't match Shift+Alt+Tab, and the
+//! left and right modifiers are the same. The keys are identified by the control keys and the
+//! characters in the legacy mode, and by the keycodes of the peer platform in the map and
+//! translate modes, i.e. the physical keys.
+//!
+//! The rules are kept in the peer option [`OPTION_HOTKEY_RULES`] as json, so they're exported and
+//! imported along with the peer config.
+
+use hbb_common::{
+    bail,
+    message_proto::{key_event, ControlKey, KeyEvent, KeyboardMode},
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_HOTKEY_RULES: &str = "hotkey-rules";
+/// The same rule is reported once in the interval.
+const BLOCKED_NOTIFY_INTERVAL: Duration = Duration::from_secs(3);
+
+const CTRL: u8 = 1;
+const ALT: u8 = 1 << 1;
+const SHIFT: u8 = 1 << 2;
+const META: u8 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HotkeyAction {
+    /// Not sent to the peer.
+    Local,
+    Remote,
+    /// Not sent to the peer, and the ui is told.
+    Blocked,
+    /// Not sent to the peer, the output device of the peer is muted or unmuted.
+    #[serde(rename = "toggle-remote-mute")]
+    ToggleRemoteMute,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotkeyRule {
+    pub name: String,
+    /// The pattern, e.g. "alt+tab".
+    pub keys: String,
+    pub action: HotkeyAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Pattern {
+    modifiers: u8,
+    key: String,
+}
+
+enum Identified {
+    Modifier(u8),
+    Key(String),
+}
+
+fn modifier_of_name(name: &str) -> Option<u8> {
+    match name {
+        "ctrl" | "control" => Some(CTRL),
+        "alt" | "option" => Some(ALT),
+        "shift" => Some(SHIFT),
+        "meta" | "win" | "cmd" | "command" | "super" => Some(META),
+        _ => None,
+    }
+}
+
+// The name of a key, shared by the patterns, the control keys and the rdev keys.
+fn canonical_key(name: &str) -> String {
+    let name = name.to_lowercase();
+    // The letters and the digits of rdev, e.g. "KeyA" and "Num1".
+    if let Some(c) = name.strip_prefix("key").filter(|c| c.len() == 1) {
+        return c.to_owned();
+    }
+    if let Some(d) = name
+        .strip_prefix("num")
+        .filter(|d| d.len() == 1 && d.chars().all(|c| c.is_ascii_digit()))
+    {
+        return d.to_owned();
+    }
+    match name.as_str() {
+        "return" => "enter",
+        "esc" => "escape",
+        "del" => "delete",
+        "pgup" => "pageup",
+        "pgdn" => "pagedown",
+        "uparrow" => "up",
+        "downarrow" => "down",
+        "leftarrow" => "left",
+        "rightarrow" => "right",
+        "snapshot" | "printscr" => "printscreen",
+        _ => return name,
+    }
+    .to_owned()
+}
+
+fn parse_pattern(keys: &str) -> ResultType<Pattern> {
+    let mut modifiers = 0;
+    let mut key = None;
+    for part in keys.split('+').map(|p| p.trim().to_lowercase()) {
+        if part.is_empty() {
+            bail!("Invalid hotkey {:?}", keys);
+        }
+        if let Some(m) = modifier_of_name(&part) {
+            modifiers |= m;
+        } else if key.is_none() {
+            key = Some(canonical_key(&part));
+        } else {
+            bail!("Invalid hotkey {:?}, more than one key", keys);
+        }
+    }
+    let Some(key) = key else {
+        bail!("Invalid hotkey {:?}, no key", keys);
+    };
+    Ok(Pattern { modifiers, key })
+}
+
+fn modifier_of_control_key(ck: ControlKey) -> Option<u8> {
+    match ck {
+        ControlKey::Control | ControlKey::RControl => Some(CTRL),
+        ControlKey::Alt | ControlKey::RAlt => Some(ALT),
+        ControlKey::Shift | ControlKey::RShift => Some(SHIFT),
+        ControlKey::Meta | ControlKey::RWin => Some(META),
+        _ => None,
+    }
+}
+
+fn identify_rdev_key(key: rdev::Key) -> Option<Identified> {
+    Some(match key {
+        rdev::Key::ControlLeft | rdev::Key::ControlRight => Identified::Modifier(CTRL),
+        rdev::Key::Alt | rdev::Key::AltGr => Identified::Modifier(ALT),
+        rdev::Key::ShiftLeft | rdev::Key::ShiftRight => Identified::Modifier(SHIFT),
+        rdev::Key::MetaLeft | rdev::Key::MetaRight => Identified::Modifier(META),
+        rdev::Key::Unknown(_) => return None,
+        _ => Identified::Key(canonical_key(&format!("{:?}", key))),
+    })
+}
+
+// `peer`: the platform of the peer, lowercase without whitespaces.
+fn identify(evt: &KeyEvent, peer: &str) -> Option<Identified> {
+    match &evt.union {
+        Some(key_event::Union::ControlKey(ck)) => {
+            // The actions of the session, e.g. "Insert Ctrl + Alt + Del", are not hotkeys.
+            let ck = ck.enum_value().ok()?;
+            if matches!(ck, ControlKey::CtrlAltDel | ControlKey::LockScreen) {
+                return None;
+            }
+            Some(match modifier_of_control_key(ck) {
+                Some(m) => Identified::Modifier(m),
+                None => Identified::Key(canonical_key(&format!("{:?}", ck))),
+            })
+        }
+        Some(key_event::Union::Chr(code)) => {
+            if evt.mode.enum_value_or_default() == KeyboardMode::Legacy {
+                let c = char::from_u32(*code)?;
+                return Some(Identified::Key(c.to_lowercase().to_string()));
+            }
+            let key = match peer {
+                "windows" => rdev::win_key_from_scancode(*code),
+                "macos" => rdev::macos_key_from_code(*code as _),
+                "linux" => rdev::linux_key_from_code(*code),
+                _ => return None,
+            };
+            identify_rdev_key(key)
+        }
+        Some(key_event::Union::Unicode(u)) => {
+            let c = char::from_u32(*u)?;
+            Some(Identified::Key(c.to_lowercase().to_string()))
+        }
+        Some(key_event::Union::Seq(seq)) => {
+            let mut chars = seq.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(Identified::Key(c.to_lowercase().to_string())),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn modifiers_of_event(evt: &KeyEvent) -> u8 {
+    evt.modifiers
+        .iter()
+        .filter_map(|ck| ck.enum_value().ok().and_then(modifier_of_control_key))
+        .fold(0, |acc, m| acc | m)
+}
+
+#[derive(Default)]
+pub struct HotkeyRules {
+    loaded: bool,
+    rules: Vec<(HotkeyRule, Pattern)>,
+    // The modifiers held, tracked from the key events. The map mode has no modifiers in the events.
+    held: u8,
+    // key -> the index of the rule which kept its press from the peer, the release is kept too.
+    suppressed: HashMap<String, usize>,
+    // rule name -> the last time reported.
+    last_blocked: HashMap<String, Instant>,
+}
+
+impl HotkeyRules {
+    /// Parse and check the json of the rules.
+    pub fn parse(json: &str) -> ResultType<Vec<HotkeyRule>> {
+        if json.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        let rules: Vec<HotkeyRule> = serde_json::from_str(json)?;
+        for rule in rules.iter() {
+            parse_pattern(&rule.keys)?;
+        }
+        Ok(rules)
+    }
+
+    #[inline]
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Replace the rules, the first matched rule wins.
+    pub fn set(&mut self, rules: Vec<HotkeyRule>) {
+        self.rules = rules
+            .into_iter()
+            .filter_map(|rule| {
+                let pattern = parse_pattern(&rule.keys).ok()?;
+                Some((rule, pattern))
+            })
+            .collect();
+        self.suppressed.clear();
+        self.last_blocked.clear();
+        self.loaded = true;
+    }
+
+    pub fn rules(&self) -> Vec<HotkeyRule> {
+        self.rules.iter().map(|(rule, _)| rule.clone()).collect()
+    }
+
+    /// The rule matching the key event, `None` if no rule matches.
+    ///
+    /// `peer`: the platform of the peer, lowercase without whitespaces.
+    pub fn check(&mut self, evt: &KeyEvent, peer: &str) -> Option<&HotkeyRule> {
+        let key = match identify(evt, peer)? {
+            Identified::Modifier(m) => {
+                if evt.down {
+                    self.held |= m;
+                } else if !evt.press {
+                    self.held &= !m;
+                }
+                return None;
+            }
+            Identified::Key(key) => key,
+        };
+        if !evt.down && !evt.press {
+            let i = self.suppressed.remove(&key)?;
+            return self.rules.get(i).map(|(rule, _)| rule);
+        }
+        // The modifiers of the legacy mode are in the events.
+        let modifiers = if evt.mode.enum_value_or_default() == KeyboardMode::Legacy {
+            modifiers_of_event(evt)
+        } else {
+            self.held | modifiers_of_event(evt)
+        };
+        let i = self
+            .rules
+            .iter()
+            .position(|(_, p)| p.modifiers == modifiers && p.key == key)?;
+        let rule = &self.rules[i].0;
+        if rule.action != HotkeyAction::Remote && evt.down {
+            self.suppressed.insert(key, i);
+        }
+        Some(rule)
+    }
+
+    /// Returns true if the blocked rule should be reported to the ui.
+    pub fn should_notify_blocked(&mut self, name: &str, now: Instant) -> bool {
+        if let Some(last) = self.last_blocked.get(name) {
+            if now.saturating_duration_since(*last) < BLOCKED_NOTIFY_INTERVAL {
+                return false;
+            }
+        }
+        self.last_blocked.insert(name.to_owned(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(json: &str) -> HotkeyRules {
+        let mut rules = HotkeyRules::default();
+        rules.set(HotkeyRules::parse(json).unwrap());
+        rules
+    }
+
+    fn legacy(ck: ControlKey, down: bool, modifiers: &[ControlKey]) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(ck);
+        evt.down = down;
+        evt.mode = KeyboardMode::Legacy.into();
+        evt.modifiers = modifiers.iter().map(|m| (*m).into()).collect();
+        evt
+    }
+
+    fn map(key: rdev::Key, down: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_chr(rdev::linux_keycode_from_key(key).unwrap());
+        evt.down = down;
+        evt.mode = KeyboardMode::Map.into();
+        evt
+    }
+
+    fn matched(rules: &mut HotkeyRules, evt: &KeyEvent) -> Option<(String, HotkeyAction)> {
+        rules
+            .check(evt, "linux")
+            .map(|rule| (rule.name.clone(), rule.action))
+    }
+
+    #[test]
+    fn test_parse_hotkey_rules() {
+        let parsed = HotkeyRules::parse(
+            r#"[{"name": "switch", "keys": "Alt + Tab", "action": "local"},
+                {"name": "lock", "keys": "win+l", "action": "blocked"},
+                {"name": "mute", "keys": "ctrl+alt+m", "action": "toggle-remote-mute"}]"#,
+        )
+        .unwrap();
+        assert_eq!(parsed[1].action, HotkeyAction::Blocked);
+        assert_eq!(parsed[2].action, HotkeyAction::ToggleRemoteMute);
+        assert_eq!(
+            parse_pattern(&parsed[0].keys).unwrap(),
+            Pattern {
+                modifiers: ALT,
+                key: "tab".to_owned()
+            }
+        );
+        assert_eq!(parse_pattern("ctrl+shift+Esc").unwrap().key, "escape");
+        assert_eq!(parse_pattern("cmd+option+q").unwrap().modifiers, META | ALT);
+        assert!(HotkeyRules::parse("").unwrap().is_empty());
+        for keys in ["", "alt", "alt+", "alt+a+b"] {
+            assert!(parse_pattern(keys).is_err(), "{}", keys);
+        }
+        assert!(HotkeyRules::parse(r#"[{"name": "x", "keys": "a", "action": "drop"}]"#).is_err());
+
+        // Round trip through the peer option.
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(HotkeyRules::parse(&json).unwrap(), parsed);
+    }
+
+    #[test]
+    fn test_hotkey_rules_logical_keys() {
+        let mut rules = rules(
+            r#"[{"name": "switch", "keys": "alt+tab", "action": "local"},
+                {"name": "back", "keys": "shift+alt+tab", "action": "remote"}]"#,
+        );
+        let alt = [ControlKey::Alt];
+        assert_eq!(
+            matched(&mut rules, &legacy(ControlKey::Tab, true, &alt)),
+            Some(("switch".to_owned(), HotkeyAction::Local))
+        );
+        // The release of the kept press is kept too.
+        assert_eq!(
+            matched(&mut rules, &legacy(ControlKey::Tab, false, &[])),
+            Some(("switch".to_owned(), HotkeyAction::Local))
+        );
+        assert_eq!(matched(&mut rules, &legacy(ControlKey::Tab, false, &[])), None);
+        // Only the modifiers differ.
+        assert_eq!(
+            matched(
+                &mut rules,
+                &legacy(ControlKey::Tab, true, &[ControlKey::RShift, ControlKey::Alt])
+            ),
+            Some(("back".to_owned(), HotkeyAction::Remote))
+        );
+        assert_eq!(matched(&mut rules, &legacy(ControlKey::Tab, false, &[])), None);
+        assert_eq!(
+            matched(&mut rules, &legacy(ControlKey::Tab, true, &[ControlKey::Control])),
+            None
+        );
+        assert_eq!(matched(&mut rules, &legacy(ControlKey::Tab, true, &[])), None);
+    }
+
+    #[test]
+    fn test_hotkey_rules_physical_keys() {
+        let mut rules = rules(r#"[{"name": "lock", "keys": "meta+l", "action": "blocked"}]"#);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, true)), None);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, false)), None);
+
+        // The modifiers are tracked from the events of the map mode.
+        assert_eq!(matched(&mut rules, &map(rdev::Key::MetaRight, true)), None);
+        let blocked = Some(("lock".to_owned(), HotkeyAction::Blocked));
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, true)), blocked);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, false)), blocked);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::ShiftLeft, true)), None);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, true)), None);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::ShiftLeft, false)), None);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::MetaRight, false)), None);
+        assert_eq!(matched(&mut rules, &map(rdev::Key::KeyL, true)), None);
+
+        let start = Instant::now();
+        assert!(rules.should_notify_blocked("lock", start));
+        assert!(!rules.should_notify_blocked("lock", start + Duration::from_secs(1)));
+        assert!(rules.should_notify_blocked("other", start + Duration::from_secs(1)));
+        assert!(rules.should_notify_blocked("lock", start + BLOCKED_NOTIFY_INTERVAL));
+    }
+}
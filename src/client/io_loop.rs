@@ -16,12 +16,9 @@ use hbb_common::sleep;
 use hbb_common::tokio::sync::mpsc::error::TryRecvError;
 use hbb_common::{
     allow_err,
-    config::{PeerConfig, TransferSerde},
+    config::{LocalConfig, PeerConfig, TransferSerde},
     fs,
-    fs::{
-        can_enable_overwrite_detection, get_job, get_string, new_send_confirm, DigestCheckResult,
-        RemoveJobMeta,
-    },
+    fs::{get_job, get_string, new_send_confirm, DigestCheckResult, RemoveJobMeta},
     get_time, log,
     message_proto::permission_info::Permission,
     message_proto::*,
@@ -39,16 +36,24 @@ use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
 use scrap::CodecFormat;
 
 use crate::client::{
-    new_voice_call_request, Client, MediaData, MediaSender, QualityStatus, MILLI1, SEC30,
+    file_sync, job_progress, latency, network_probe, new_voice_call_request, print_job, rates, Client, DecodeStats, MediaData, MediaSender,
+    QualityStatus, MILLI1, SEC30,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::common::{self, update_clipboard};
 use crate::common::{get_default_sound_input, set_sound_input};
-use crate::ui_session_interface::{InvokeUiSession, Session};
+use crate::ui_session_interface::{self, HookAction, InvokeUiSession, JobMeta, Session};
 #[cfg(not(any(target_os = "ios")))]
 use crate::{audio_service, ConnInner, CLIENT_SERVER};
 use crate::{client::Data, client::Interface};
 
+// The privacy mode usually takes a few seconds to turn on.
+const PRIVACY_REARM_TIMEOUT: Duration = Duration::from_secs(10);
+// The network delay in ms regarded as congested.
+const CONGESTED_DELAY: u32 = 500;
+/// The peer toggle option to drop the frames while the display of the host is off.
+pub const OPTION_PAUSE_DECODING_DISPLAY_OFF: &str = "pause-decoding-display-off";
+
 pub struct Remote<T: InvokeUiSession> {
     handler: Session<T>,
     video_queue_map: Arc<RwLock<HashMap<usize, ArrayQueue<VideoFrame>>>>,
@@ -62,8 +67,11 @@ pub struct Remote<T: InvokeUiSession> {
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
     remove_jobs: HashMap<i32, RemoveJob>,
+    sync_jobs: HashMap<i32, file_sync::SyncJob>,
+    // The local renames and removes running, see `fs::file_op`.
+    file_ops: fs::file_op::Ops,
     timer: Interval,
-    last_update_jobs_status: (Instant, HashMap<i32, u64>),
+    job_progress: job_progress::ProgressTracker,
     is_connected: bool,
     first_frame: bool,
     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
@@ -74,7 +82,20 @@ pub struct Remote<T: InvokeUiSession> {
     elevation_requested: bool,
     fps_control_map: HashMap<usize, FpsControl>,
     decode_fps_map: Arc<RwLock<HashMap<usize, usize>>>,
-    chroma: Arc<RwLock<Option<Chroma>>>,
+    decode_stats_map: Arc<RwLock<HashMap<usize, DecodeStats>>>,
+    // The new fingerprint of the peer waiting to be accepted, the session is paused meanwhile.
+    pending_fingerprint: Option<String>,
+    // The echoes of the running network probe.
+    network_probe_echo: Option<mpsc::UnboundedSender<u32>>,
+    // The privacy mode is turned on right after login to the restarted peer,
+    // the frames are not shown until it's back or the deadline.
+    privacy_rearmed: bool,
+    privacy_rearm_deadline: Option<Instant>,
+    // The display of the host is off and decoding is paused by the peer option, the frames are dropped.
+    host_display_paused: bool,
+    // The network delay in ms measured by the peer.
+    network_delay: u32,
+    print_receiver: print_job::PrintReceiver,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -87,10 +108,9 @@ impl<T: InvokeUiSession> Remote<T> {
         sender: mpsc::UnboundedSender<Data>,
         frame_count_map: Arc<RwLock<HashMap<usize, usize>>>,
         decode_fps: Arc<RwLock<HashMap<usize, usize>>>,
-        chroma: Arc<RwLock<Option<Chroma>>>,
+        decode_stats: Arc<RwLock<HashMap<usize, DecodeStats>>>,
     ) -> Self {
         Self {
-            handler,
             video_queue_map: video_queue,
             video_sender,
             audio_sender,
@@ -99,8 +119,12 @@ impl<T: InvokeUiSession> Remote<T> {
             read_jobs: Vec::new(),
             write_jobs: Vec::new(),
             remove_jobs: Default::default(),
+            sync_jobs: Default::default(),
+            file_ops: Default::default(),
             timer: time::interval(SEC30),
-            last_update_jobs_status: (Instant::now(), Default::default()),
+            job_progress: job_progress::ProgressTracker::new(job_progress::rate_of(
+                &LocalConfig::get_option(job_progress::OPTION_JOB_PROGRESS_RATE),
+            )),
             is_connected: false,
             first_frame: false,
             #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
@@ -113,7 +137,15 @@ impl<T: InvokeUiSession> Remote<T> {
             elevation_requested: false,
             fps_control_map: Default::default(),
             decode_fps_map: decode_fps,
-            chroma,
+            decode_stats_map: decode_stats,
+            pending_fingerprint: None,
+            network_probe_echo: None,
+            privacy_rearmed: false,
+            privacy_rearm_deadline: None,
+            host_display_paused: false,
+            network_delay: 0,
+            print_receiver: print_job::PrintReceiver::new(handler.staging.clone()),
+            handler,
         }
     }
 
@@ -141,11 +173,20 @@ impl<T: InvokeUiSession> Remote<T> {
                     .lock()
                     .unwrap()
                     .set_connected();
-                self.handler.set_connection_type(peer.is_secured(), direct); // flutter -> connection_ready
                 self.handler.update_direct(Some(direct));
+                let fingerprint = crate::common::pk_to_fingerprint(pk.unwrap_or_default());
+                let id = self.handler.get_id();
+                // The connection is ready only after the fingerprint is accepted.
+                if let Some(old) = crate::client::check_fingerprint(&id, &fingerprint) {
+                    log::warn!("Fingerprint mismatch of {}, pause the session", id);
+                    self.handler.on_fingerprint_mismatch(&old, &fingerprint);
+                    self.pending_fingerprint = Some(fingerprint.clone());
+                } else {
+                    // flutter -> connection_ready
+                    self.handler.on_connection_ready(peer.is_secured(), direct);
+                }
                 if conn_type == ConnType::DEFAULT_CONN {
-                    self.handler
-                        .set_fingerprint(crate::common::pk_to_fingerprint(pk.unwrap_or_default()));
+                    self.handler.set_fingerprint(fingerprint);
                 }
 
                 // just build for now
@@ -172,14 +213,20 @@ impl<T: InvokeUiSession> Remote<T> {
 
                 let mut status_timer = time::interval(Duration::new(1, 0));
                 let mut fps_instant = Instant::now();
+                let mut speed_rate = rates::Rate::since(std::time::Instant::now());
 
                 loop {
                     tokio::select! {
-                        res = peer.next() => {
+                        res = peer.next(), if self.pending_fingerprint.is_none() => {
                             if let Some(res) = res {
                                 match res {
                                     Err(err) => {
-                                        self.handler.on_establish_connection_error(err.to_string());
+                                        if self.handler.is_restarting_remote_device() {
+                                            log::info!("Restart remote device: {}", err);
+                                            self.handler.on_remote_restarting();
+                                        } else {
+                                            self.handler.on_establish_connection_error(err.to_string());
+                                        }
                                         break;
                                     }
                                     Ok(ref bytes) => {
@@ -197,7 +244,10 @@ impl<T: InvokeUiSession> Remote<T> {
                             } else {
                                 if self.handler.is_restarting_remote_device() {
                                     log::info!("Restart remote device");
-                                    self.handler.msgbox("restarting", "Restarting remote device", "remote_restarting_tip", "");
+                                    self.handler.on_remote_restarting();
+                                } else if !received && self.is_direct_target() {
+                                    // Closed before any message, see `DirectError::WrongService`.
+                                    self.handler.on_establish_connection_error("Reset by the peer".to_owned());
                                 } else {
                                     log::info!("Reset by the peer");
                                     self.handler.msgbox("error", "Connection Error", "Reset by the peer", "");
@@ -212,13 +262,22 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
-                        _msg = rx_clip_client.recv() => {
+                        _msg = rx_clip_client.recv(), if self.pending_fingerprint.is_none() => {
                             #[cfg(any(target_os="windows", target_os="linux", target_os = "macos"))]
                            self.handle_local_clipboard_msg(&mut peer, _msg).await;
                         }
                         _ = self.timer.tick() => {
-                            if last_recv_time.elapsed() >= SEC30 {
-                                self.handler.msgbox("error", "Connection Error", "Timeout", "");
+                            if self.pending_fingerprint.is_some() {
+                                // Nothing is read from the peer while paused.
+                                last_recv_time = Instant::now();
+                            } else if last_recv_time.elapsed() >= SEC30 {
+                                if self.handler.is_restarting_remote_device() {
+                                    self.handler.on_remote_restarting();
+                                } else if !received && self.is_direct_target() {
+                                    self.handler.on_establish_connection_error("Timeout".to_owned());
+                                } else {
+                                    self.handler.msgbox("error", "Connection Error", "Timeout", "");
+                                }
                                 break;
                             }
                             if !self.read_jobs.is_empty() {
@@ -238,9 +297,16 @@ impl<T: InvokeUiSession> Remote<T> {
                                 continue;
                             }
                             fps_instant = Instant::now();
-                            let mut speed = self.data_count.swap(0, Ordering::Relaxed);
-                            speed = speed * 1000 / elapsed as usize;
-                            let speed = format!("{:.2}kB/s", speed as f32 / 1024 as f32);
+                            let bytes = self.data_count.swap(0, Ordering::Relaxed);
+                            speed_rate.add(bytes as _, std::time::Instant::now());
+                            let speed = rates::format_speed(speed_rate.smoothed());
+                            if self.handler.is_audio_only_session() {
+                                self.handler.update_quality_status(QualityStatus {
+                                    speed: Some(speed),
+                                    ..Default::default()
+                                });
+                                continue;
+                            }
 
                             let mut frame_count_map_write = self.frame_count_map.write().unwrap();
                             let frame_count_map = frame_count_map_write.clone();
@@ -250,19 +316,18 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // Correcting the inaccuracy of status_timer
                                 (k.clone(), (*v as i32) * 1000 / elapsed as i32)
                             }).collect::<HashMap<usize, i32>>();
-                            let chroma = self.chroma.read().unwrap().clone();
-                            let chroma = match chroma {
-                                Some(Chroma::I444) => "4:4:4",
-                                Some(Chroma::I420) => "4:2:0",
-                                None => "-",
-                            };
-                            let chroma = Some(chroma.to_string());
+                            let decode_stats = self.decode_stats_map.read().unwrap().clone();
+                            let latency_breakdown = self.handler.latency.lock().unwrap().take_breakdown();
+                            let color_accuracy = self.handler.check_color_mode(&decode_stats);
                             self.handler.update_quality_status(QualityStatus {
                                 speed: Some(speed),
-                                fps,
-                                chroma,
-                                ..Default::default()
+                                latency_breakdown,
+                                color_accuracy: Some(color_accuracy),
+                                ..QualityStatus::of_displays(fps, &decode_stats)
                             });
+                            if let Some(set) = self.handler.on_video_congestion(self.is_video_congested()) {
+                                self.handler.capture_displays(vec![], vec![], set);
+                            }
                         }
                     }
                 }
@@ -273,7 +338,12 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
             Err(err) => {
-                self.handler.on_establish_connection_error(err.to_string());
+                if self.handler.is_restarting_remote_device() {
+                    // Retried by `Session::on_remote_restarting`.
+                    log::info!("The restarting remote device is not ready: {}", err);
+                } else {
+                    self.handler.on_establish_connection_error(err.to_string());
+                }
             }
         }
         // set_disconnected_ok is used to check if new connection round is started.
@@ -283,6 +353,9 @@ impl<T: InvokeUiSession> Remote<T> {
             .lock()
             .unwrap()
             .set_disconnected(round);
+        if _set_disconnected_ok {
+            self.handler.on_network_drop();
+        }
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         if _set_disconnected_ok {
@@ -316,6 +389,10 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.msgbox(&r#type, &title, &text, "");
                 }
                 _ => {
+                    if self.handler.is_view_only_session() {
+                        log::debug!("Drop system clipboard message in the view-only session");
+                        return;
+                    }
                     let is_stopping_allowed = clip.is_stopping_allowed();
                     let server_file_transfer_enabled =
                         *self.handler.server_file_transfer_enabled.read().unwrap();
@@ -343,7 +420,55 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Returns false if the text clipboard is blocked by the session hooks.
+    fn hook_clipboard(&self, cb: &mut Clipboard) -> bool {
+        let content = if cb.compress {
+            hbb_common::compress::decompress(&cb.content)
+        } else {
+            cb.content.to_vec()
+        };
+        let Ok(text) = String::from_utf8(content) else {
+            return true;
+        };
+        let mut hooked = text.clone();
+        if self.handler.hook_clipboard(&mut hooked) == HookAction::Block {
+            return false;
+        }
+        if hooked != text {
+            cb.compress = false;
+            cb.content = hooked.into();
+        }
+        true
+    }
+
+    // Returns false if the file transfer job is blocked by the session hooks.
+    fn hook_file_job_start(&mut self, job: JobMeta) -> bool {
+        if self.handler.hook_file_job_start(&job) == HookAction::Block {
+            self.handle_job_status(job.id, -1, Some("Blocked by plugin".to_owned()));
+            return false;
+        }
+        true
+    }
+
+    // The recursive remove may take long, it runs on its own thread and is canceled by the id.
+    fn delete_local_path(&self, id: i32, path: String, recursive: bool) {
+        let canceled = self.file_ops.start(id);
+        let file_ops = self.file_ops.clone();
+        let handler = self.handler.clone();
+        std::thread::spawn(move || {
+            let res = fs::file_op::remove_path(&path, recursive, &canceled, |removed, total| {
+                handler.file_op_progress(id, removed, total, false);
+            });
+            file_ops.finish(id);
+            match res {
+                Err(err) => handler.file_op_error(id, err.to_string(), false),
+                Ok(_) => handler.file_op_done(id, false),
+            }
+        });
+    }
+
     fn handle_job_status(&mut self, id: i32, file_num: i32, err: Option<String>) {
+        self.on_sync_job_status(id, &err);
         if let Some(job) = self.remove_jobs.get_mut(&id) {
             if job.no_confirm {
                 let file_num = (file_num + 1) as usize;
@@ -363,6 +488,13 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
             }
         }
+        // The final progress goes before the result.
+        if let Some(p) = self.job_progress.on_done(id) {
+            self.report_job_progress(p);
+        }
+        if let Some(summary) = self.job_progress.summary(std::time::Instant::now()) {
+            self.handler.transfer_summary(&summary);
+        }
         if let Some(err) = err {
             self.handler.job_error(id, err, file_num);
         } else {
@@ -377,6 +509,50 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Run the network probe over the connection, the result is pushed to the ui and `tx`.
+    fn start_network_probe(
+        &mut self,
+        force: bool,
+        tx: std::sync::mpsc::Sender<ResultType<String>>,
+    ) -> ResultType<()> {
+        if let Some(echo) = &self.network_probe_echo {
+            if !echo.is_closed() {
+                hbb_common::bail!("The network probe is running");
+            }
+        }
+        if !force && !(self.read_jobs.is_empty() && self.write_jobs.is_empty()) {
+            hbb_common::bail!("The network probe is refused while a file transfer job is active");
+        }
+        let (echo_tx, echoes) = mpsc::unbounded_channel();
+        self.network_probe_echo = Some(echo_tx);
+        let mut transport = network_probe::PeerTransport {
+            sender: self.sender.clone(),
+            echoes,
+        };
+        let handler = self.handler.clone();
+        tokio::spawn(async move {
+            let config = network_probe::ProbeConfig::default();
+            let res = network_probe::run(&mut transport, &config)
+                .await
+                .map(|mut r| {
+                    let lc = handler.lc.read().unwrap();
+                    r.direct = lc.direct;
+                    r.relay_server = lc.relay_server.clone();
+                    r.to_json()
+                });
+            match &res {
+                Ok(json) => handler.network_probe_result(json),
+                Err(e) => {
+                    log::info!("Network probe failed: {}", e);
+                    let error = serde_json::json!({ "error": e.to_string() }).to_string();
+                    handler.network_probe_result(&error);
+                }
+            }
+            tx.send(res).ok();
+        });
+        Ok(())
+    }
+
     // Start a voice call recorder, records audio and send to remote
     fn start_voice_call(&mut self) -> Option<std::sync::mpsc::Sender<()>> {
         if self.handler.is_file_transfer() || self.handler.is_port_forward() {
@@ -453,6 +629,12 @@ impl<T: InvokeUiSession> Remote<T> {
     }
 
     async fn handle_msg_from_ui(&mut self, data: Data, peer: &mut Stream) -> bool {
+        if self.pending_fingerprint.is_some()
+            && !matches!(data, Data::Close | Data::AcceptFingerprint)
+        {
+            log::debug!("Drop the ui message while waiting for the fingerprint to be accepted");
+            return true;
+        }
         match data {
             Data::Close => {
                 let mut misc = Misc::new();
@@ -467,19 +649,38 @@ impl<T: InvokeUiSession> Remote<T> {
                     .handle_login_from_ui(os_username, os_password, password, remember, peer)
                     .await;
             }
+            Data::Login2FA(code) => {
+                crate::client::send_2fa(code, peer).await;
+            }
             #[cfg(not(feature = "flutter"))]
             Data::ToggleClipboardFile => {
                 self.check_clipboard_file_context();
             }
-            Data::Message(msg) => {
+            Data::Message(mut msg) => {
+                if let Some(message::Union::Clipboard(cb)) = msg.union.as_mut() {
+                    if !self.hook_clipboard(cb) {
+                        return true;
+                    }
+                }
                 allow_err!(peer.send(&msg).await);
             }
             Data::SendFiles((id, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
-                let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+                if !self.hook_file_job_start(JobMeta {
+                    id,
+                    path: path.clone(),
+                    to: to.clone(),
+                    file_num,
+                    include_hidden,
+                    is_remote,
+                }) {
+                    return true;
+                }
+                let od = self.handler.peer_capabilities().resume_transfer;
+                let streams = self.file_transfer_streams();
                 if is_remote {
                     log::debug!("New job {}, write to {} from remote {}", id, to, path);
-                    self.write_jobs.push(fs::TransferJob::new_write(
+                    let mut job = fs::TransferJob::new_write(
                         id,
                         path.clone(),
                         to,
@@ -488,9 +689,12 @@ impl<T: InvokeUiSession> Remote<T> {
                         is_remote,
                         Vec::new(),
                         od,
-                    ));
+                    );
+                    job.streams = streams;
+                    self.write_jobs.push(job);
+                    self.handler.job_streams(id, streams);
                     allow_err!(
-                        peer.send(&fs::new_send(id, path, file_num, include_hidden))
+                        peer.send(&fs::new_send(id, path, file_num, include_hidden, streams))
                             .await
                     );
                 } else {
@@ -506,7 +710,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         Err(err) => {
                             self.handle_job_status(id, -1, Some(err.to_string()));
                         }
-                        Ok(job) => {
+                        Ok(mut job) => {
                             log::debug!(
                                 "New job {}, read {} to remote {}, {} files",
                                 id,
@@ -531,18 +735,33 @@ impl<T: InvokeUiSession> Remote<T> {
                                 fs::transform_windows_path(&mut files);
                             }
                             let total_size = job.total_size();
+                            job.streams = streams;
                             self.read_jobs.push(job);
+                            self.handler.job_streams(id, streams);
                             self.timer = time::interval(MILLI1);
                             allow_err!(
-                                peer.send(&fs::new_receive(id, to, file_num, files, total_size))
-                                    .await
+                                peer.send(&fs::new_receive(
+                                    id, to, file_num, files, total_size, streams
+                                ))
+                                .await
                             );
                         }
                     }
                 }
             }
             Data::AddJob((id, path, to, file_num, include_hidden, is_remote)) => {
-                let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
+                if !self.hook_file_job_start(JobMeta {
+                    id,
+                    path: path.clone(),
+                    to: to.clone(),
+                    file_num,
+                    include_hidden,
+                    is_remote,
+                }) {
+                    return true;
+                }
+                let od = self.handler.peer_capabilities().resume_transfer;
+                let streams = self.file_transfer_streams();
                 if is_remote {
                     log::debug!(
                         "new write waiting job {}, write to {} from remote {}",
@@ -561,6 +780,7 @@ impl<T: InvokeUiSession> Remote<T> {
                         od,
                     );
                     job.is_last_job = true;
+                    job.streams = streams;
                     self.write_jobs.push(job);
                 } else {
                     match fs::TransferJob::new_read(
@@ -591,6 +811,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 true,
                             );
                             job.is_last_job = true;
+                            job.streams = streams;
                             self.read_jobs.push(job);
                             self.timer = time::interval(MILLI1);
                         }
@@ -601,12 +822,14 @@ impl<T: InvokeUiSession> Remote<T> {
                 if is_remote {
                     if let Some(job) = get_job(id, &mut self.write_jobs) {
                         job.is_last_job = false;
+                        self.handler.job_streams(id, job.streams);
                         allow_err!(
                             peer.send(&fs::new_send(
                                 id,
                                 job.remote.clone(),
                                 job.file_num,
-                                job.show_hidden
+                                job.show_hidden,
+                                job.streams,
                             ))
                             .await
                         );
@@ -614,6 +837,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 } else {
                     if let Some(job) = get_job(id, &mut self.read_jobs) {
                         job.is_last_job = false;
+                        self.handler.job_streams(id, job.streams);
                         allow_err!(
                             peer.send(&fs::new_receive(
                                 id,
@@ -621,6 +845,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 job.file_num,
                                 job.files.clone(),
                                 job.total_size(),
+                                job.streams,
                             ))
                             .await
                         );
@@ -719,7 +944,32 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             }
+            Data::StartSync((id, path, to, options)) => {
+                if !self.sync_jobs.contains_key(&id) {
+                    log::info!("start sync job {}, {} to remote {}", id, path, to);
+                    let job = file_sync::SyncJob::new(id, path, to, options, self.sender.clone());
+                    self.sync_jobs.insert(id, job);
+                    self.handler.sync_state(id, &file_sync::SyncState::Scanning);
+                }
+            }
+            Data::SyncChanges((id, changes)) => {
+                if let Some(sync) = self.sync_jobs.get_mut(&id) {
+                    sync.pending.merge(changes);
+                    self.send_sync_batch(id, peer).await;
+                }
+            }
+            Data::SyncError((id, error)) => {
+                // The watch is stopped.
+                if self.sync_jobs.remove(&id).is_some() {
+                    log::error!("sync job {} failed: {}", id, error);
+                    self.handler
+                        .sync_state(id, &file_sync::SyncState::Error { error });
+                }
+            }
             Data::CancelJob(id) => {
+                if self.sync_jobs.remove(&id).is_some() {
+                    self.handler.sync_state(id, &file_sync::SyncState::Stopped);
+                }
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
                 file_action.set_cancel(FileTransferCancel {
@@ -734,6 +984,7 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
+                self.file_ops.cancel(id);
             }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
@@ -792,11 +1043,63 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             }
+            Data::RenameFile((id, path, new_name, is_remote)) => {
+                if is_remote && !self.handler.peer_capabilities().file_ops {
+                    self.handler.file_op_error(
+                        id,
+                        "Not supported by the remote side".to_owned(),
+                        true,
+                    );
+                } else if is_remote {
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_rename(FileRename {
+                        id,
+                        path,
+                        new_name,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                } else {
+                    match fs::file_op::rename(&path, &new_name) {
+                        Err(err) => self.handler.file_op_error(id, err.to_string(), false),
+                        Ok(_) => self.handler.file_op_done(id, false),
+                    }
+                }
+            }
+            Data::DeletePath((id, path, recursive, is_remote)) => {
+                if is_remote && !self.handler.peer_capabilities().file_ops {
+                    self.handler.file_op_error(
+                        id,
+                        "Not supported by the remote side".to_owned(),
+                        true,
+                    );
+                } else if is_remote {
+                    let mut msg_out = Message::new();
+                    let mut file_action = FileAction::new();
+                    file_action.set_remove_path(FileRemovePath {
+                        id,
+                        path,
+                        recursive,
+                        ..Default::default()
+                    });
+                    msg_out.set_file_action(file_action);
+                    allow_err!(peer.send(&msg_out).await);
+                } else {
+                    self.delete_local_path(id, path, recursive);
+                }
+            }
             Data::RecordScreen(start, display, w, h, id) => {
                 let _ = self
                     .video_sender
                     .send(MediaData::RecordScreen(start, display, w, h, id));
             }
+            Data::ForceSoftwareDecode(software) => {
+                let _ = self
+                    .video_sender
+                    .send(MediaData::ForceSoftwareDecode(software));
+            }
             Data::ElevateDirect => {
                 let mut request = ElevationRequest::new();
                 request.set_direct(true);
@@ -838,55 +1141,157 @@ impl<T: InvokeUiSession> Remote<T> {
                     .on_voice_call_closed("Closed manually by the peer");
                 allow_err!(peer.send(&msg).await);
             }
+            Data::AcceptFingerprint => {
+                if let Some(fingerprint) = self.pending_fingerprint.take() {
+                    crate::client::trust_fingerprint(&self.handler.get_id(), &fingerprint);
+                    let direct = self.handler.lc.read().unwrap().direct.unwrap_or(false);
+                    self.handler.on_connection_ready(peer.is_secured(), direct);
+                }
+            }
+            Data::NetworkProbe(force, tx) => {
+                if let Err(e) = self.start_network_probe(force, tx.clone()) {
+                    tx.send(Err(e)).ok();
+                }
+            }
             _ => {}
         }
         true
     }
 
     #[inline]
-    fn update_job_status(
-        job: &fs::TransferJob,
-        elapsed: i32,
-        last_update_jobs_status: &mut (Instant, HashMap<i32, u64>),
-        handler: &Session<T>,
-    ) {
-        if elapsed <= 0 {
+    // Send the pending changes of the sync job, if no batch is in flight.
+    async fn send_sync_batch(&mut self, id: i32, peer: &mut Stream) {
+        let od = self.handler.peer_capabilities().resume_transfer;
+        let streams = self.file_transfer_streams();
+        let peer_is_windows = self.handler.peer_platform() == "Windows";
+        let Some(sync) = self.sync_jobs.get_mut(&id) else {
+            return;
+        };
+        if sync.in_flight || sync.pending.is_empty() {
             return;
         }
-        let transferred = job.transferred();
-        let last_transferred = {
-            if let Some(v) = last_update_jobs_status.1.get(&job.id()) {
-                v.to_owned()
-            } else {
-                0
+        let changes = std::mem::take(&mut sync.pending);
+        if sync.options.delete {
+            let sep = if peer_is_windows { "\\" } else { "/" };
+            for (i, name) in changes.removed.iter().enumerate() {
+                let name = if peer_is_windows {
+                    name.replace('/', "\\")
+                } else {
+                    name.replace('\\', "/")
+                };
+                // The negative id, the result is not of the batch.
+                let mut file_action = FileAction::new();
+                file_action.set_remove_file(FileRemoveFile {
+                    id: -id,
+                    path: format!("{}{}{}", sync.to, sep, name),
+                    file_num: i as _,
+                    ..Default::default()
+                });
+                let mut msg = Message::new();
+                msg.set_file_action(file_action);
+                allow_err!(peer.send(&msg).await);
+            }
+        }
+        if changes.changed.is_empty() {
+            self.handler.sync_state(id, &file_sync::SyncState::Idle);
+            return;
+        }
+        let mut job = fs::TransferJob::new_read_files(
+            id,
+            sync.to.clone(),
+            sync.path.clone(),
+            changes.changed,
+            od,
+        );
+        job.set_overwrite_strategy(sync.options.overwrite);
+        job.streams = streams;
+        sync.in_flight = true;
+        #[cfg(not(windows))]
+        let files = job.files().clone();
+        #[cfg(windows)]
+        let mut files = job.files().clone();
+        #[cfg(windows)]
+        if !peer_is_windows {
+            fs::transform_windows_path(&mut files);
+        }
+        let (to, total_size) = (sync.to.clone(), job.total_size());
+        self.handler
+            .sync_state(id, &file_sync::SyncState::Syncing { files: files.len() });
+        self.read_jobs.push(job);
+        self.timer = time::interval(MILLI1);
+        allow_err!(
+            peer.send(&fs::new_receive(id, to, 0, files, total_size, streams))
+                .await
+        );
+    }
+
+    // The batch of the sync job is done, the next one is sent if any.
+    fn on_sync_job_status(&mut self, id: i32, err: &Option<String>) {
+        let err = err.as_ref().filter(|e| *e != "skipped");
+        if id < 0 {
+            // The removal of a file on the peer.
+            if let Some(err) = err {
+                if self.sync_jobs.contains_key(&-id) {
+                    let error = err.clone();
+                    self.handler
+                        .sync_state(-id, &file_sync::SyncState::Error { error });
+                }
             }
+            return;
+        }
+        let Some(sync) = self.sync_jobs.get_mut(&id) else {
+            return;
         };
-        last_update_jobs_status.1.insert(job.id(), transferred);
-        let speed = (transferred - last_transferred) as f64 / (elapsed as f64 / 1000.);
-        let file_num = job.file_num() - 1;
-        handler.job_progress(job.id(), file_num, speed, job.finished_size() as f64);
+        if !sync.in_flight {
+            return;
+        }
+        sync.in_flight = false;
+        if let Some(err) = err {
+            let error = err.clone();
+            self.handler
+                .sync_state(id, &file_sync::SyncState::Error { error });
+        } else if sync.pending.is_empty() {
+            self.handler.sync_state(id, &file_sync::SyncState::Idle);
+        }
+        if !sync.pending.is_empty() {
+            self.sender
+                .send(Data::SyncChanges((id, Default::default())))
+                .ok();
+        }
+    }
+
+    // The streams of the large files, if the peer can split them.
+    fn file_transfer_streams(&self) -> u32 {
+        if !self.handler.peer_capabilities().multi_stream_transfer {
+            return 1;
+        }
+        fs::multi_stream::streams_of(&LocalConfig::get_option(
+            fs::multi_stream::OPTION_FILE_TRANSFER_STREAMS,
+        ))
+    }
+
+    fn report_job_progress(&self, p: job_progress::JobProgress) {
+        self.handler
+            .job_progress(p.id, p.file_num, p.speed, p.finished_size as f64);
     }
 
+    // Sampled on every block, reported by `job_progress` at the configured rate.
     fn update_jobs_status(&mut self) {
-        let elapsed = self.last_update_jobs_status.0.elapsed().as_millis() as i32;
-        if elapsed >= 1000 {
-            for job in self.read_jobs.iter() {
-                Self::update_job_status(
-                    job,
-                    elapsed,
-                    &mut self.last_update_jobs_status,
-                    &self.handler,
-                );
-            }
-            for job in self.write_jobs.iter() {
-                Self::update_job_status(
-                    job,
-                    elapsed,
-                    &mut self.last_update_jobs_status,
-                    &mut self.handler,
-                );
+        let now = std::time::Instant::now();
+        for job in self.read_jobs.iter().chain(self.write_jobs.iter()) {
+            if let Some(p) = self.job_progress.on_sample(
+                job.id(),
+                job.file_num() - 1,
+                job.transferred(),
+                job.finished_size(),
+                job.total_size(),
+                now,
+            ) {
+                self.report_job_progress(p);
             }
-            self.last_update_jobs_status.0 = Instant::now();
+        }
+        if let Some(summary) = self.job_progress.summary(now) {
+            self.handler.transfer_summary(&summary);
         }
     }
 
@@ -924,6 +1329,23 @@ impl<T: InvokeUiSession> Remote<T> {
             msg_out.set_misc(misc);
             allow_err!(peer.send(&msg_out).await);
         }
+        let prioritize_quality = self.handler.get_option("prioritize-quality".to_owned()) == "Y";
+        if prioritize_quality {
+            let mut misc = Misc::new();
+            misc.set_prioritize_quality(true);
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            allow_err!(peer.send(&msg_out).await);
+        }
+        let latency = self.handler.get_option("latency-breakdown".to_owned()) == "Y";
+        self.handler.latency.lock().unwrap().set_enabled(latency);
+        if latency && self.handler.peer_capabilities().frame_timestamps {
+            let mut misc = Misc::new();
+            misc.set_frame_timestamps(true);
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            allow_err!(peer.send(&msg_out).await);
+        }
     }
 
     async fn send_toggle_privacy_mode_msg(&self, peer: &mut Stream) {
@@ -936,6 +1358,7 @@ impl<T: InvokeUiSession> Remote<T> {
             misc.set_toggle_privacy_mode(TogglePrivacyMode {
                 impl_key,
                 on: true,
+                display_off: lc.get_option("privacy-mode-display-off") == "Y",
                 ..Default::default()
             });
             let mut msg_out = Message::new();
@@ -944,6 +1367,32 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    /// Request no video, the session is ready without the first frame.
+    /// The video is requested again by switching to a display.
+    async fn start_audio_only(&mut self, peer: &mut Stream) {
+        let mut misc = Misc::new();
+        misc.set_capture_displays(CaptureDisplays::default());
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        allow_err!(peer.send(&msg_out).await);
+        self.handler.close_success();
+        self.handler.update_audio_only(true);
+    }
+
+    /// Turn the privacy mode back on before the first frame of the restarted peer is shown.
+    async fn rearm_privacy_mode(&mut self, peer: &mut Stream) {
+        let rearm = {
+            let lc = self.handler.lc.read().unwrap();
+            lc.version >= hbb_common::get_version_number("1.2.4")
+                && lc.get_toggle_option("privacy-mode")
+        };
+        if rearm {
+            self.send_toggle_privacy_mode_msg(peer).await;
+            self.privacy_rearmed = true;
+            self.privacy_rearm_deadline = Some(Instant::now() + PRIVACY_REARM_TIMEOUT);
+        }
+    }
+
     fn contains_key_frame(vf: &VideoFrame) -> bool {
         use video_frame::Union::*;
         match &vf.union {
@@ -956,6 +1405,23 @@ impl<T: InvokeUiSession> Remote<T> {
     }
 
     #[inline]
+    fn is_direct_target(&self) -> bool {
+        self.handler.lc.read().unwrap().direct_target.is_some()
+    }
+
+    #[inline]
+    // The congestion signaled by the adaptive quality, the network delay measured by the peer is
+    // high, or the frames pile up in the queues.
+    fn is_video_congested(&self) -> bool {
+        self.network_delay >= CONGESTED_DELAY
+            || self
+                .video_queue_map
+                .read()
+                .unwrap()
+                .values()
+                .any(|q| q.len() > q.capacity() / 2)
+    }
+
     fn fps_control(&mut self, direct: bool) {
         let custom_fps = self.handler.lc.read().unwrap().custom_fps.clone();
         let custom_fps = custom_fps.lock().unwrap().clone();
@@ -1035,20 +1501,78 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    async fn handle_print_job(&mut self, job: PrintJob, peer: &mut Stream) {
+        match self.print_receiver.handle(job) {
+            Some(print_job::PrintEvent::Progress {
+                id,
+                name,
+                received,
+                total,
+            }) => {
+                self.handler.print_job_progress(id, &name, received, total);
+            }
+            Some(print_job::PrintEvent::Received {
+                id,
+                name,
+                path,
+                evicted,
+            }) => {
+                log::info!("Print job {} received: {}", id, path.display());
+                if !evicted.is_empty() {
+                    self.handler.staging_evicted(&evicted);
+                }
+                self.handler
+                    .print_job_received(id, &name, &path.to_string_lossy());
+            }
+            Some(print_job::PrintEvent::Failed {
+                id,
+                name,
+                error,
+                reply,
+            }) => {
+                log::error!("Print job {} failed: {}", id, error);
+                if let Some(reply) = reply {
+                    let mut msg = Message::new();
+                    msg.set_print_job(reply);
+                    allow_err!(peer.send(&msg).await);
+                }
+                self.handler.print_job_failed(id, &name, &error);
+            }
+            None => {}
+        }
+    }
+
     async fn handle_msg_from_peer(&mut self, data: &[u8], peer: &mut Stream) -> bool {
         if let Ok(msg_in) = Message::parse_from_bytes(&data) {
             match msg_in.union {
                 Some(message::Union::VideoFrame(vf)) => {
+                    if self.handler.is_audio_only_session() {
+                        // Sent before the host handles the empty capture displays.
+                        return true;
+                    }
+                    if let Some(deadline) = self.privacy_rearm_deadline {
+                        if Instant::now() < deadline {
+                            return true;
+                        }
+                        log::warn!("Privacy mode is not back in time, show the frames");
+                        self.privacy_rearm_deadline = None;
+                    }
+                    if self.host_display_paused && self.first_frame {
+                        return true;
+                    }
                     if !self.first_frame {
                         self.first_frame = true;
                         self.handler.close_success();
                         self.handler.adapt_size();
                         self.send_opts_after_login(peer).await;
-                        self.send_toggle_privacy_mode_msg(peer).await;
+                        if !self.privacy_rearmed {
+                            self.send_toggle_privacy_mode_msg(peer).await;
+                        }
                     }
                     let incoming_format = CodecFormat::from(&vf);
                     if self.video_format != incoming_format {
                         self.video_format = incoming_format.clone();
+                        self.handler.on_codec_negotiated(&incoming_format);
                         self.handler.update_quality_status(QualityStatus {
                             codec_format: Some(incoming_format),
                             ..Default::default()
@@ -1056,6 +1580,14 @@ impl<T: InvokeUiSession> Remote<T> {
                     };
 
                     let display = vf.display as usize;
+                    if vf.capture_time != 0 {
+                        self.handler.latency.lock().unwrap().on_received(
+                            display,
+                            vf.capture_time,
+                            latency::now_micros(),
+                            std::time::Instant::now(),
+                        );
+                    }
                     let mut video_queue_write = self.video_queue_map.write().unwrap();
                     if !video_queue_write.contains_key(&display) {
                         video_queue_write.insert(
@@ -1089,7 +1621,14 @@ impl<T: InvokeUiSession> Remote<T> {
                         }
                     }
                     Some(login_response::Union::PeerInfo(pi)) => {
+                        let restarted = self.handler.lc.read().unwrap().restart_reconnecting;
                         self.handler.handle_peer_info(pi);
+                        if restarted {
+                            self.rearm_privacy_mode(peer).await;
+                        }
+                        if self.handler.is_audio_only_session() {
+                            self.start_audio_only(peer).await;
+                        }
                         self.check_clipboard_file_context();
                         if !(self.handler.is_file_transfer() || self.handler.is_port_forward()) {
                             #[cfg(feature = "flutter")]
@@ -1143,18 +1682,28 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::CursorPosition(cp)) => {
                     self.handler.set_cursor_position(cp);
                 }
-                Some(message::Union::Clipboard(cb)) => {
-                    if !self.handler.lc.read().unwrap().disable_clipboard.v {
+                Some(message::Union::Clipboard(mut cb)) => {
+                    if self.handler.clipboard_gate().is_inbound_allowed()
+                        && self.hook_clipboard(&mut cb)
+                    {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(cb, Some(&crate::client::get_old_clipboard_text()));
                         #[cfg(any(target_os = "android", target_os = "ios"))]
                         {
+                            use crate::client::text_clipboard::{
+                                truncate_text, MAX_TEXT_CLIPBOARD_LEN,
+                            };
                             let content = if cb.compress {
                                 hbb_common::compress::decompress(&cb.content)
                             } else {
                                 cb.content.into()
                             };
-                            if let Ok(content) = String::from_utf8(content) {
+                            if let Ok(mut content) = String::from_utf8(content) {
+                                if let Some(len) =
+                                    truncate_text(&mut content, MAX_TEXT_CLIPBOARD_LEN)
+                                {
+                                    self.handler.clipboard_truncated(len, MAX_TEXT_CLIPBOARD_LEN);
+                                }
                                 self.handler.clipboard(content);
                             }
                         }
@@ -1164,6 +1713,14 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::Cliprdr(clip)) => {
                     self.handle_cliprdr_msg(clip);
                 }
+                Some(message::Union::PrintJob(job)) => {
+                    self.handle_print_job(job, peer).await;
+                }
+                Some(message::Union::Annotation(annotation)) => {
+                    if let Some(annotation::Union::Ack(ack)) = annotation.union {
+                        self.handler.annotation_ack(ack.id, &ack.error);
+                    }
+                }
                 Some(message::Union::FileResponse(fr)) => {
                     match fr.union {
                         Some(file_response::Union::Dir(fd)) => {
@@ -1179,9 +1736,43 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                             self.handler
                                 .update_folder_files(fd.id, &entries, fd.path, false, false);
-                            if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) {
-                                log::info!("job set_files: {:?}", entries);
-                                job.set_files(entries);
+                            if fs::get_job(fd.id, &mut self.write_jobs).is_some() {
+                                let mut entries = entries;
+                                let policy = fs::file_name::NamePolicy::from_option(
+                                    &LocalConfig::get_option(
+                                        fs::file_name::OPTION_FILE_NAME_POLICY,
+                                    ),
+                                );
+                                match fs::file_name::sanitize_entries(
+                                    &mut entries,
+                                    fs::file_name::TargetOs::current(),
+                                    policy,
+                                ) {
+                                    Ok(renamed) => {
+                                        if !renamed.is_empty() {
+                                            log::info!("job {} renamed: {:?}", fd.id, renamed);
+                                            self.handler.job_renamed_files(fd.id, &renamed);
+                                        }
+                                        if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs)
+                                        {
+                                            log::info!("job set_files: {:?}", entries);
+                                            job.set_files(entries);
+                                        }
+                                    }
+                                    Err(err) => {
+                                        // Nothing is written, the peer stops sending.
+                                        let mut msg = Message::new();
+                                        let mut file_action = FileAction::new();
+                                        file_action.set_cancel(FileTransferCancel {
+                                            id: fd.id,
+                                            ..Default::default()
+                                        });
+                                        msg.set_file_action(file_action);
+                                        allow_err!(peer.send(&msg).await);
+                                        fs::remove_job(fd.id, &mut self.write_jobs);
+                                        self.handle_job_status(fd.id, -1, Some(err.to_string()));
+                                    }
+                                }
                             } else if let Some(job) = self.remove_jobs.get_mut(&fd.id) {
                                 job.files = entries;
                             }
@@ -1305,6 +1896,17 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                             self.handle_job_status(e.id, e.file_num, Some(e.error));
                         }
+                        Some(file_response::Union::OpProgress(p)) => {
+                            self.handler
+                                .file_op_progress(p.id, p.removed, p.total, true);
+                        }
+                        Some(file_response::Union::OpResult(r)) => {
+                            if r.error.is_empty() {
+                                self.handler.file_op_done(r.id, true);
+                            } else {
+                                self.handler.file_op_error(r.id, r.error, true);
+                            }
+                        }
                         _ => {}
                     }
                 }
@@ -1320,17 +1922,15 @@ impl<T: InvokeUiSession> Remote<T> {
                         // https://github.com/rustdesk/rustdesk/issues/3703#issuecomment-1474734754
                         match p.permission.enum_value() {
                             Ok(Permission::Keyboard) => {
+                                *self.handler.server_keyboard_enabled.write().unwrap() = p.enabled;
                                 #[cfg(feature = "flutter")]
-                                #[cfg(not(any(target_os = "android", target_os = "ios")))]
                                 crate::flutter::update_text_clipboard_required();
-                                *self.handler.server_keyboard_enabled.write().unwrap() = p.enabled;
                                 self.handler.set_permission("keyboard", p.enabled);
                             }
                             Ok(Permission::Clipboard) => {
+                                *self.handler.server_clipboard_enabled.write().unwrap() = p.enabled;
                                 #[cfg(feature = "flutter")]
-                                #[cfg(not(any(target_os = "android", target_os = "ios")))]
                                 crate::flutter::update_text_clipboard_required();
-                                *self.handler.server_clipboard_enabled.write().unwrap() = p.enabled;
                                 self.handler.set_permission("clipboard", p.enabled);
                             }
                             Ok(Permission::Audio) => {
@@ -1353,6 +1953,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             Ok(Permission::BlockInput) => {
                                 self.handler.set_permission("block_input", p.enabled);
                             }
+                            Ok(Permission::Printer) => {
+                                self.handler.set_permission("printer", p.enabled);
+                            }
+                            Ok(Permission::Annotation) => {
+                                self.handler.set_permission("annotation", p.enabled);
+                            }
                             _ => {}
                         }
                     }
@@ -1466,9 +2072,89 @@ impl<T: InvokeUiSession> Remote<T> {
                             );
                         }
                     }
+                    Some(misc::Union::HostDisplayWaking(waking)) => {
+                        self.handler.host_display_waking(waking);
+                    }
+                    Some(misc::Union::HostDisplayState(s)) => {
+                        let state = s.state.enum_value_or_default();
+                        let paused = state == host_display_state::State::DisplayOff
+                            && self
+                                .handler
+                                .get_toggle_option(OPTION_PAUSE_DECODING_DISPLAY_OFF.to_owned());
+                        if self.host_display_paused && !paused {
+                            // The frames before are dropped, request a key frame.
+                            self.handler
+                                .send(Data::Message(crate::client::LoginConfigHandler::refresh()));
+                        }
+                        self.host_display_paused = paused;
+                        self.handler.on_host_display_state(state);
+                    }
+                    Some(misc::Union::ViewOnly(view_only)) => {
+                        // Only sent as the answer of `Session::request_view_only_off`.
+                        self.handler.lc.write().unwrap().view_only_session = view_only;
+                        self.handler.cancel_msgbox(&format!(
+                            "{}-{}-{}-",
+                            ui_session_interface::VIEW_ONLY_OFF_MSGTYPE,
+                            ui_session_interface::VIEW_ONLY_OFF_TITLE,
+                            ui_session_interface::VIEW_ONLY_OFF_TEXT,
+                        ));
+                        #[cfg(feature = "flutter")]
+                        crate::flutter::update_text_clipboard_required();
+                        self.handler.update_view_only(view_only);
+                        if !view_only {
+                            // Restore the options implied by the view-only session.
+                            let lc = self.handler.lc.read().unwrap();
+                            let keyboard_off = lc.get_toggle_option("view-only");
+                            let clipboard_off =
+                                keyboard_off || lc.get_toggle_option("disable-clipboard");
+                            drop(lc);
+                            let bool_option = |off: bool| {
+                                if off {
+                                    option_message::BoolOption::Yes
+                                } else {
+                                    option_message::BoolOption::No
+                                }
+                            };
+                            let mut misc = Misc::new();
+                            misc.set_option(OptionMessage {
+                                disable_keyboard: bool_option(keyboard_off).into(),
+                                disable_clipboard: bool_option(clipboard_off).into(),
+                                ..Default::default()
+                            });
+                            let mut msg = Message::new();
+                            msg.set_misc(misc);
+                            self.handler.send(Data::Message(msg));
+                        } else {
+                            self.handler.msgbox(
+                                "custom-nocancel",
+                                ui_session_interface::VIEW_ONLY_OFF_TITLE,
+                                "view_only_off_declined_tip",
+                                "",
+                            );
+                        }
+                    }
+                    Some(misc::Union::RestartRemoteDeviceDenied(_)) => {
+                        self.handler.on_restart_remote_device_denied();
+                    }
+                    Some(misc::Union::RemoteSysinfo(sysinfo)) => {
+                        self.handler.on_remote_sysinfo(&sysinfo);
+                    }
+                    Some(misc::Union::InputPolicy(policy)) => {
+                        self.handler
+                            .input_policy(&policy.level, &policy.blocked, policy.dropped);
+                    }
+                    Some(misc::Union::HostThrottled(t)) => {
+                        self.handler.host_throttled(t.fps_percent, t.cpu, t.encode);
+                    }
+                    Some(misc::Union::WakeOnLanResult(res)) => {
+                        self.handler.wol_result(&res.mac, &res.error, res.sent);
+                    }
+                    Some(misc::Union::RemoteVolume(volume)) => {
+                        self.handler.on_remote_volume(&volume);
+                    }
                     Some(misc::Union::SwitchBack(_)) => {
                         #[cfg(feature = "flutter")]
-                        self.handler.switch_back(&self.handler.get_id());
+                        self.handler.on_switch_back();
                     }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -1493,6 +2179,18 @@ impl<T: InvokeUiSession> Remote<T> {
                     _ => {}
                 },
                 Some(message::Union::TestDelay(t)) => {
+                    if t.from_client {
+                        if let Some(tx) = &self.network_probe_echo {
+                            tx.send(t.probe_seq).ok();
+                        }
+                    } else {
+                        self.network_delay = t.last_delay;
+                        self.handler.latency.lock().unwrap().on_test_delay(
+                            t.time,
+                            get_time(),
+                            t.last_delay,
+                        );
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
@@ -1554,7 +2252,18 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::PeerInfo(pi)) => {
                     self.handler.set_displays(&pi.displays);
+                    self.handler
+                        .lc
+                        .write()
+                        .unwrap()
+                        .update_view_state_monitors(&pi.displays);
+                    crate::common::merge_platform_additions(
+                        &mut self.handler.lc.write().unwrap().platform_additions,
+                        &pi.platform_additions,
+                    );
                     self.handler.set_platform_additions(&pi.platform_additions);
+                    self.handler
+                        .update_peer_capabilities(&self.handler.peer_capabilities());
                 }
                 _ => {}
             }
@@ -1652,6 +2361,11 @@ impl<T: InvokeUiSession> Remote<T> {
         self.handler.save_config(config);
 
         self.handler.update_privacy_mode();
+        if self.privacy_rearm_deadline.take().is_some() {
+            // The frames before are dropped, request a key frame.
+            self.handler
+                .send(Data::Message(crate::client::LoginConfigHandler::refresh()));
+        }
     }
 
     async fn handle_back_msg_privacy_mode(
@@ -1676,8 +2390,17 @@ impl<T: InvokeUiSession> Remote<T> {
                 self.update_privacy_mode(impl_key, false);
             }
             back_notification::PrivacyModeState::PrvOnSucceeded => {
-                self.handler
-                    .msgbox("custom-nocancel", "Privacy mode", "Enter privacy mode", "");
+                // The warning of the displays not powered off.
+                self.handler.msgbox(
+                    "custom-nocancel",
+                    "Privacy mode",
+                    if details.is_empty() {
+                        "Enter privacy mode"
+                    } else {
+                        &details
+                    },
+                    "",
+                );
                 self.update_privacy_mode(impl_key, true);
             }
             back_notification::PrivacyModeState::PrvOnFailedDenied => {
@@ -0,0 +1,258 @@
+//! The progress of the file transfer jobs reported to the ui.
+//!
+//! The progress is sampled on every block sent or received, but each job is reported at most
+//! [`OPTION_JOB_PROGRESS_RATE`] times per second, [`DEFAULT_RATE`] by default. The latest sample
+//! not reported yet is kept, and reported before the job is done, so the last progress of a job
+//! is always the final one. The finished size reported never goes back.
+//!
+//! The speed is the smoothed rate of the bytes transferred, see `rates`. A summary of all the active jobs is reported every [`SUMMARY_INTERVAL`], and once more when
+//! no job is left.
+
+use super::rates::{self, Rate};
+use serde_derive::Serialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// The local option of the progress reports per second of each job.
+pub const OPTION_JOB_PROGRESS_RATE: &str = "job-progress-rate";
+pub const DEFAULT_RATE: u32 = 4;
+const MAX_RATE: u32 = 30;
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobProgress {
+    pub id: i32,
+    pub file_num: i32,
+    /// Bytes per second.
+    pub speed: f64,
+    pub finished_size: u64,
+}
+
+/// The overview of all the active jobs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransferSummary {
+    pub jobs: usize,
+    /// Bytes per second.
+    pub speed: f64,
+    pub remaining: u64,
+    /// Seconds, `None` if unknown, e.g. stalled.
+    pub eta: Option<u64>,
+}
+
+struct JobState {
+    // The bytes transferred.
+    rate: Rate,
+    last_reported: Option<Instant>,
+    finished_size: u64,
+    total_size: u64,
+    // The latest progress not reported yet.
+    pending: Option<JobProgress>,
+}
+
+pub fn rate_of(option: &str) -> u32 {
+    match option.trim().parse::<u32>() {
+        Ok(rate) if rate > 0 => rate.min(MAX_RATE),
+        _ => DEFAULT_RATE,
+    }
+}
+
+pub struct ProgressTracker {
+    interval: Duration,
+    jobs: HashMap<i32, JobState>,
+    last_summary: Option<Instant>,
+    // A summary of the active jobs is reported, the idle one is reported once.
+    summary_active: bool,
+}
+
+impl ProgressTracker {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate.max(1),
+            jobs: Default::default(),
+            last_summary: None,
+            summary_active: false,
+        }
+    }
+
+    /// The progress to report now, if due.
+    pub fn on_sample(
+        &mut self,
+        id: i32,
+        file_num: i32,
+        transferred: u64,
+        finished_size: u64,
+        total_size: u64,
+        now: Instant,
+    ) -> Option<JobProgress> {
+        let state = self.jobs.entry(id).or_insert_with(|| JobState {
+            rate: Default::default(),
+            last_reported: None,
+            finished_size: 0,
+            total_size,
+            pending: None,
+        });
+        state.total_size = total_size;
+        state.finished_size = state.finished_size.max(finished_size);
+        state.rate.sample_total(transferred, now);
+        let progress = JobProgress {
+            id,
+            file_num,
+            speed: state.rate.smoothed(),
+            finished_size: state.finished_size,
+        };
+        let due = state
+            .last_reported
+            .map_or(true, |t| now.saturating_duration_since(t) >= self.interval);
+        if !due {
+            state.pending = Some(progress);
+            return None;
+        }
+        state.last_reported = Some(now);
+        state.pending = None;
+        Some(progress)
+    }
+
+    /// The job is done or failed, returns the progress not reported yet.
+    pub fn on_done(&mut self, id: i32) -> Option<JobProgress> {
+        self.jobs.remove(&id)?.pending
+    }
+
+    /// The summary to report now, if due.
+    pub fn summary(&mut self, now: Instant) -> Option<TransferSummary> {
+        if self.jobs.is_empty() {
+            if !self.summary_active {
+                return None;
+            }
+            self.summary_active = false;
+        } else {
+            let due = self.last_summary.map_or(true, |t| {
+                now.saturating_duration_since(t) >= SUMMARY_INTERVAL
+            });
+            if !due {
+                return None;
+            }
+            self.summary_active = true;
+        }
+        self.last_summary = Some(now);
+        // The stalled jobs decay.
+        let speed: f64 = self.jobs.values().map(|s| s.rate.smoothed_at(now)).sum();
+        let remaining: u64 = self
+            .jobs
+            .values()
+            .map(|s| s.total_size.saturating_sub(s.finished_size))
+            .sum();
+        Some(TransferSummary {
+            jobs: self.jobs.len(),
+            speed,
+            remaining,
+            eta: rates::eta(remaining, speed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_of() {
+        assert_eq!(rate_of(""), DEFAULT_RATE);
+        assert_eq!(rate_of("0"), DEFAULT_RATE);
+        assert_eq!(rate_of("10"), 10);
+        assert_eq!(rate_of("1000"), MAX_RATE);
+    }
+
+    #[test]
+    fn test_throttled_progress() {
+        let now = Instant::now();
+        let ms = |n: u64| now + Duration::from_millis(n);
+        let mut tracker = ProgressTracker::new(DEFAULT_RATE);
+        let mut reported = vec![];
+        // A block every 10 ms for a second.
+        for i in 1..=100u64 {
+            if let Some(p) = tracker.on_sample(1, 0, i * 1000, i * 1000, 100_000, ms(i * 10)) {
+                reported.push(p);
+            }
+        }
+        assert_eq!(reported.len(), DEFAULT_RATE as usize);
+        // The final progress is kept until done.
+        let last = tracker.on_done(1).unwrap();
+        assert_eq!(last.finished_size, 100_000);
+        assert_eq!(tracker.on_done(1), None);
+        // 1000 bytes per 10 ms.
+        assert!((last.speed - 100_000.).abs() < 1.);
+
+        // Reported at once if nothing is pending.
+        assert!(tracker.on_sample(2, 0, 10, 10, 10, now).is_some());
+        assert_eq!(tracker.on_done(2), None);
+    }
+
+    #[test]
+    fn test_progress_monotonic() {
+        let now = Instant::now();
+        let mut tracker = ProgressTracker::new(MAX_RATE);
+        // The finished size of the job goes back, e.g. a file is retried.
+        let sizes = [0u64, 500, 1000, 800, 1200, 1100, 2000];
+        let mut last = 0;
+        for (i, size) in sizes.iter().enumerate() {
+            let t = now + Duration::from_secs(i as u64);
+            let p = tracker.on_sample(1, 0, *size, *size, 2000, t).unwrap();
+            assert!(p.finished_size >= last, "{} < {}", p.finished_size, last);
+            last = p.finished_size;
+        }
+        assert_eq!(last, 2000);
+    }
+
+    #[test]
+    fn test_speed_average() {
+        let now = Instant::now();
+        let mut tracker = ProgressTracker::new(1);
+        let secs = |n: u64| now + Duration::from_secs(n);
+        tracker.on_sample(1, 0, 0, 0, u64::MAX, now);
+        let p = tracker
+            .on_sample(1, 0, 1000, 1000, u64::MAX, secs(1))
+            .unwrap();
+        assert_eq!(p.speed, 1000.);
+        // A burst moves the average partly.
+        let p = tracker
+            .on_sample(1, 0, 11000, 11000, u64::MAX, secs(2))
+            .unwrap();
+        assert!(p.speed > 1000. && p.speed < 10000.);
+        // Stalled, decays.
+        let p = tracker
+            .on_sample(1, 0, 11000, 11000, u64::MAX, secs(3))
+            .unwrap();
+        assert!(p.speed > 0. && p.speed < 5000.);
+    }
+
+    #[test]
+    fn test_summary() {
+        let now = Instant::now();
+        let secs = |n: u64| now + Duration::from_secs(n);
+        let mut tracker = ProgressTracker::new(1);
+        assert_eq!(tracker.summary(now), None);
+        tracker.on_sample(1, 0, 0, 0, 3000, now);
+        tracker.on_sample(2, 0, 0, 0, 1000, now);
+        tracker.on_sample(1, 0, 1000, 1000, 3000, secs(1));
+        tracker.on_sample(2, 0, 500, 500, 1000, secs(1));
+        let summary = tracker.summary(secs(1)).unwrap();
+        assert_eq!(
+            summary,
+            TransferSummary {
+                jobs: 2,
+                speed: 1500.,
+                remaining: 2500,
+                eta: Some(2),
+            }
+        );
+        // Not due.
+        assert_eq!(tracker.summary(secs(1)), None);
+        tracker.on_done(1);
+        tracker.on_done(2);
+        // The idle summary is reported once.
+        assert_eq!(tracker.summary(secs(1)).unwrap().jobs, 0);
+        assert_eq!(tracker.summary(secs(3)), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! The breakdown of the latency of the frames, opt-in by the peer option "latency-breakdown".
+//!
+//! The host stamps the frames with the capture time of its clock, see
+//! `server::frame_timestamps`. The client records when each frame is received, decoded and handed
+//! off to the ui, and reports the average of the legs capture -> receive, receive -> decode and
+//! decode -> render with the quality status. The first leg crosses the machines, the capture time
+//! is converted to the local clock by the offset estimated from the delay tests, see
+//! [`ClockOffset`], so it's unknown till the second test. The other legs are measured by the
+//! monotonic clock.
+
+use serde_derive::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+// The delay tests kept for the clock offset.
+const MAX_OFFSET_SAMPLES: usize = 8;
+// The frames received but not rendered of a display, the older ones are skipped by the decoder.
+const MAX_PENDING_FRAMES: usize = 64;
+
+/// The time of the clock in microseconds, the unit of `VideoFrame::capture_time`.
+#[inline]
+pub fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or_default()
+}
+
+/// The offset of the local clock to the clock of the host.
+///
+/// The host sends its time with the round trip of the last test, the one-way delay is taken as
+/// half of it. The test with the least round trip is the least skewed by the queueing, so its
+/// offset is used.
+#[derive(Debug, Default)]
+pub struct ClockOffset {
+    tests: usize,
+    // (offset, round trip) in microseconds
+    samples: VecDeque<(i64, i64)>,
+}
+
+impl ClockOffset {
+    /// The test of the host time `host_ms` is received at `local_ms`, `rtt_ms` is the round trip
+    /// of the last test.
+    pub fn add(&mut self, host_ms: i64, local_ms: i64, rtt_ms: u32) {
+        self.tests += 1;
+        // The first test carries no round trip.
+        if self.tests == 1 {
+            return;
+        }
+        let rtt = rtt_ms as i64 * 1000;
+        if self.samples.len() >= MAX_OFFSET_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples
+            .push_back(((local_ms - host_ms) * 1000 - rtt / 2, rtt));
+    }
+
+    /// The local time minus the host time in microseconds, `None` if not estimated yet.
+    pub fn get(&self) -> Option<i64> {
+        self.samples
+            .iter()
+            .min_by_key(|(_, rtt)| *rtt)
+            .map(|(offset, _)| *offset)
+    }
+}
+
+/// The average of the legs in ms since the last report.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Breakdown {
+    /// `None` if the clock offset is not estimated yet.
+    pub capture_to_receive: Option<f64>,
+    pub receive_to_decode: f64,
+    pub decode_to_render: f64,
+    pub frames: usize,
+}
+
+#[derive(Debug)]
+struct Received {
+    // The capture time of the host clock.
+    capture: i64,
+    // The receive time of the local clock.
+    local: i64,
+    at: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Sums {
+    capture_to_receive: f64,
+    // The frames with the capture to receive leg.
+    crossed: usize,
+    receive_to_decode: f64,
+    decode_to_render: f64,
+    frames: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Tracker {
+    enabled: bool,
+    offset: ClockOffset,
+    received: HashMap<usize, VecDeque<Received>>,
+    sums: Sums,
+}
+
+impl Tracker {
+    /// The frames are only tracked if enabled, the state is reset on changing.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.received.clear();
+            self.sums = Default::default();
+        }
+        self.enabled = enabled;
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// A delay test from the host, see [`ClockOffset::add`].
+    #[inline]
+    pub fn on_test_delay(&mut self, host_ms: i64, local_ms: i64, rtt_ms: u32) {
+        self.offset.add(host_ms, local_ms, rtt_ms);
+    }
+
+    /// The frame of the display captured at `capture` is received at `local` and `now`.
+    pub fn on_received(&mut self, display: usize, capture: i64, local: i64, now: Instant) {
+        if !self.enabled || capture == 0 {
+            return;
+        }
+        let queue = self.received.entry(display).or_default();
+        if queue.len() >= MAX_PENDING_FRAMES {
+            queue.pop_front();
+        }
+        queue.push_back(Received {
+            capture,
+            local,
+            at: now,
+        });
+    }
+
+    /// The frame of the display captured at `capture` is decoded at `decoded`, and handed off to
+    /// the ui at `rendered`.
+    pub fn on_rendered(
+        &mut self,
+        display: usize,
+        capture: i64,
+        decoded: Instant,
+        rendered: Instant,
+    ) {
+        if !self.enabled || capture == 0 {
+            return;
+        }
+        let Some(queue) = self.received.get_mut(&display) else {
+            return;
+        };
+        // The frames before are skipped.
+        let Some(pos) = queue.iter().position(|r| r.capture == capture) else {
+            return;
+        };
+        let Some(received) = queue.drain(..=pos).last() else {
+            return;
+        };
+        let ms =
+            |from: Instant, to: Instant| to.saturating_duration_since(from).as_secs_f64() * 1000.;
+        if let Some(offset) = self.offset.get() {
+            // The error of the offset may make it negative.
+            let us = (received.local - offset - received.capture).max(0);
+            self.sums.capture_to_receive += us as f64 / 1000.;
+            self.sums.crossed += 1;
+        }
+        self.sums.receive_to_decode += ms(received.at, decoded);
+        self.sums.decode_to_render += ms(decoded, rendered);
+        self.sums.frames += 1;
+    }
+
+    /// The breakdown since the last report, `None` if no frame is rendered.
+    pub fn take_breakdown(&mut self) -> Option<Breakdown> {
+        let sums = std::mem::take(&mut self.sums);
+        if sums.frames == 0 {
+            return None;
+        }
+        let frames = sums.frames as f64;
+        Some(Breakdown {
+            capture_to_receive: (sums.crossed > 0)
+                .then(|| sums.capture_to_receive / sums.crossed as f64),
+            receive_to_decode: sums.receive_to_decode / frames,
+            decode_to_render: sums.decode_to_render / frames,
+            frames: sums.frames,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ms(start: Instant, n: u64) -> Instant {
+        start + Duration::from_millis(n)
+    }
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let mut offset = ClockOffset::default();
+        // The local clock is 5s ahead, the one-way delay is 20ms.
+        offset.add(1_000, 6_020, 0);
+        assert_eq!(offset.get(), None);
+        offset.add(2_000, 7_020, 40);
+        assert_eq!(offset.get(), Some(5_000_000));
+        // Queued, the one-way delay is longer than half of the round trip.
+        offset.add(3_000, 8_100, 60);
+        assert_eq!(offset.get(), Some(5_000_000));
+        for i in 0..MAX_OFFSET_SAMPLES as i64 {
+            offset.add(10_000 + i, 15_050 + i, 60);
+        }
+        assert_eq!(offset.get(), Some(5_020_000));
+    }
+
+    #[test]
+    fn test_breakdown() {
+        let start = Instant::now();
+        let mut tracker = Tracker::default();
+        tracker.on_received(0, 1, 1, start);
+        assert!(tracker.take_breakdown().is_none());
+
+        tracker.set_enabled(true);
+        // Without the clock offset.
+        tracker.on_received(0, 1_000_000, 6_030_000, start);
+        tracker.on_rendered(0, 1_000_000, ms(start, 8), ms(start, 10));
+        let b = tracker.take_breakdown().unwrap();
+        assert_eq!(b.capture_to_receive, None);
+        assert_near(b.receive_to_decode, 8.);
+        assert_near(b.decode_to_render, 2.);
+        assert!(tracker.take_breakdown().is_none());
+
+        // The host clock is 5s behind, the frames are captured every 10ms and received 30ms
+        // later, the second one is skipped by the decoder.
+        tracker.on_test_delay(0, 5_020, 0);
+        tracker.on_test_delay(1_000, 6_020, 40);
+        for i in 0..3u64 {
+            let capture = 2_000_000 + i as i64 * 10_000;
+            let at = ms(start, 1_000 + i * 10);
+            tracker.on_received(0, capture, capture + 5_030_000, at);
+        }
+        tracker.on_received(1, 2_000_000, 7_050_000, ms(start, 1_000));
+        tracker.on_rendered(0, 2_000_000, ms(start, 1_004), ms(start, 1_005));
+        tracker.on_rendered(0, 2_020_000, ms(start, 1_026), ms(start, 1_029));
+        tracker.on_rendered(0, 2_010_000, ms(start, 1_030), ms(start, 1_031));
+        tracker.on_rendered(1, 2_000_000, ms(start, 1_010), ms(start, 1_011));
+        let b = tracker.take_breakdown().unwrap();
+        assert_eq!(b.frames, 3);
+        assert_near(b.capture_to_receive.unwrap(), 110. / 3.);
+        assert_near(b.receive_to_decode, 20. / 3.);
+        assert_near(b.decode_to_render, 5. / 3.);
+
+        // A negative leg by the error of the offset is 0.
+        tracker.on_received(0, 3_000_000, 7_990_000, ms(start, 2_000));
+        tracker.on_rendered(0, 3_000_000, ms(start, 2_000), ms(start, 2_000));
+        assert_eq!(
+            tracker.take_breakdown().unwrap().capture_to_receive,
+            Some(0.)
+        );
+
+        tracker.on_received(0, 4_000_000, 9_030_000, ms(start, 3_000));
+        tracker.set_enabled(false);
+        tracker.set_enabled(true);
+        tracker.on_rendered(0, 4_000_000, ms(start, 3_004), ms(start, 3_005));
+        assert!(tracker.take_breakdown().is_none());
+    }
+}
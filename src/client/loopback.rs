@@ -0,0 +1,212 @@
+//! The in-memory transport of the sessions, connecting a client to a host in the same process,
+//! for the integration tests of the session layer without the rendezvous or relay server.
+//!
+//! [`listen`] registers a host for a peer id, then the next connection to that id, see
+//! `Client::start`, takes the other end of the pipe. Both ends are [`Stream`]s over
+//! `tokio::io::duplex`, framed the same as the tcp streams, so the io loop runs unmodified.
+//!
+//! The host is `server::Connection`, started by `Connection::start_loopback` without the cm and
+//! the services, so the login, the file transfers and the messages of the session go through the
+//! host logic. [`listen_scripted`] registers a [`LoopbackHost`] instead, which sends the messages
+//! of the host by hand, for the replies no real host sends, e.g. the blocks out of order.
+
+use hbb_common::{
+    bail, fs,
+    message_proto::*,
+    protobuf::Message as _,
+    tokio::{
+        self,
+        time::{self, timeout, Duration},
+    },
+    ResultType, Stream,
+};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex,
+    },
+};
+
+const PIPE_BUFFER_SIZE: usize = 1 << 20;
+/// The longest wait for a message of the client.
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    // peer id -> the client end, not connected yet
+    static ref PENDING: Mutex<HashMap<String, Stream>> = Default::default();
+}
+// Not overlapping the ids of the connections of the server, which are below 2000 at first.
+static NEXT_CONN_ID: AtomicI32 = AtomicI32::new(1 << 24);
+
+fn pipe(peer_id: &str) -> Stream {
+    let (client, host) = tokio::io::duplex(PIPE_BUFFER_SIZE);
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    PENDING
+        .lock()
+        .unwrap()
+        .insert(peer_id.to_owned(), Stream::from(client, addr));
+    Stream::from(host, addr)
+}
+
+/// Register the host of this process for `peer_id`, the connection of the next session to it is
+/// served in memory by `server::Connection`. The session logs in by the temporary password.
+pub fn listen(peer_id: &str) {
+    let stream = pipe(peer_id);
+    let id = NEXT_CONN_ID.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(crate::server::Connection::start_loopback(stream, id));
+}
+
+/// Register a scripted host for `peer_id`, see [`LoopbackHost`].
+pub fn listen_scripted(peer_id: &str) -> LoopbackHost {
+    LoopbackHost {
+        stream: pipe(peer_id),
+    }
+}
+
+/// The client end of the host registered for `peer_id`, taken once.
+pub(super) fn connect(peer_id: &str) -> Option<Stream> {
+    PENDING.lock().unwrap().remove(peer_id)
+}
+
+pub struct LoopbackHost {
+    stream: Stream,
+}
+
+impl LoopbackHost {
+    pub async fn send(&mut self, msg: &Message) -> ResultType<()> {
+        self.stream.send(msg).await
+    }
+
+    pub async fn next(&mut self) -> ResultType<Message> {
+        match timeout(RECV_TIMEOUT, self.stream.next()).await? {
+            Some(Ok(bytes)) => Ok(Message::parse_from_bytes(&bytes)?),
+            Some(Err(err)) => bail!("Failed to receive from the client: {}", err),
+            None => bail!("The client closed the connection"),
+        }
+    }
+
+    /// The first message `f` maps to some, the others are dropped,
+    /// e.g. the test delays and the options sent after the login.
+    pub async fn recv<T>(&mut self, f: impl Fn(Message) -> Option<T>) -> ResultType<T> {
+        loop {
+            if let Some(v) = f(self.next().await?) {
+                return Ok(v);
+            }
+        }
+    }
+
+    /// Send the hash and answer the login of the client with `pi`, the password is not checked.
+    pub async fn accept(&mut self, pi: PeerInfo) -> ResultType<LoginRequest> {
+        let mut msg = Message::new();
+        msg.set_hash(Hash {
+            salt: "loopback".to_owned(),
+            challenge: "loopback".to_owned(),
+            ..Default::default()
+        });
+        self.send(&msg).await?;
+        let lr = self
+            .recv(|msg| match msg.union {
+                Some(message::Union::LoginRequest(lr)) => Some(lr),
+                _ => None,
+            })
+            .await?;
+        let mut msg = Message::new();
+        msg.set_login_response(LoginResponse {
+            union: Some(login_response::Union::PeerInfo(pi)),
+            ..Default::default()
+        });
+        self.send(&msg).await?;
+        Ok(lr)
+    }
+
+    /// Serve the files requested by the client until the job is done, a block every `interval`.
+    pub async fn serve_file_job(&mut self, interval: Duration) -> ResultType<()> {
+        self.serve_file_job_ordered(interval, true).await
+    }
+
+    /// Serve the files as [`Self::serve_file_job`], the blocks of each file are held until it's
+    /// read and sent in the reverse order if not `in_order`, as the ranges completed out of order.
+    pub async fn serve_file_job_ordered(
+        &mut self,
+        interval: Duration,
+        in_order: bool,
+    ) -> ResultType<()> {
+        let mut jobs: Vec<fs::TransferJob> = Vec::new();
+        let mut held: Vec<FileTransferBlock> = Vec::new();
+        let mut timer = time::interval(interval);
+        loop {
+            tokio::select! {
+                msg = self.next() => {
+                    let Some(message::Union::FileAction(action)) = msg?.union else {
+                        continue;
+                    };
+                    match action.union {
+                        Some(file_action::Union::Send(s)) => {
+                            let mut job = fs::TransferJob::new_read(
+                                s.id,
+                                "".to_owned(),
+                                s.path.clone(),
+                                s.file_num,
+                                s.include_hidden,
+                                false,
+                                true,
+                            )?;
+                            self.send(&fs::new_dir(s.id, s.path, job.files().to_vec()))
+                                .await?;
+                            job.is_remote = true;
+                            job.streams = s.streams;
+                            jobs.push(job);
+                        }
+                        Some(file_action::Union::SendConfirm(r)) => {
+                            if let Some(job) = fs::get_job(r.id, &mut jobs) {
+                                job.confirm(&r);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ = timer.tick(), if !jobs.is_empty() => {
+                    if in_order {
+                        fs::handle_read_jobs(&mut jobs, &mut self.stream).await?;
+                    } else {
+                        self.read_jobs_reversed(&mut jobs, &mut held).await?;
+                    }
+                    if jobs.is_empty() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn read_jobs_reversed(
+        &mut self,
+        jobs: &mut Vec<fs::TransferJob>,
+        held: &mut Vec<FileTransferBlock>,
+    ) -> ResultType<()> {
+        let mut finished = vec![];
+        for job in jobs.iter_mut() {
+            match job.read(&mut self.stream).await? {
+                Some(block) if !block.data.is_empty() => held.push(block),
+                // The end of the file.
+                Some(block) => {
+                    for b in held.drain(..).rev() {
+                        self.send(&fs::new_block(b)).await?;
+                    }
+                    self.send(&fs::new_block(block)).await?;
+                }
+                None if job.job_completed() => {
+                    self.send(&fs::new_done(job.id(), job.file_num())).await?;
+                    finished.push(job.id());
+                }
+                None => {}
+            }
+        }
+        for id in finished {
+            fs::remove_job(id, jobs);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! The network probe of a session, run over the established connection to the peer.
+//!
+//! The probe sends [`TestDelay`] messages with `from_client` set, which are echoed by the host.
+//! The latency is measured by the pings for a few seconds, then the bandwidth by a short burst
+//! of padded probes, whose size is capped by [`ProbeConfig::max_burst_bytes`].
+
+use super::Data;
+use async_trait::async_trait;
+use hbb_common::{
+    bail, get_time,
+    message_proto::{Message, TestDelay},
+    tokio::{
+        sync::mpsc,
+        time::{timeout, Instant},
+    },
+    ResultType,
+};
+use serde_derive::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+/// The channel of the probes, injected so that the probe can run without a real peer.
+#[async_trait]
+pub trait ProbeTransport: Send {
+    /// Send a probe with `padding` bytes, which is echoed by the peer with the same `seq`.
+    async fn send_probe(&mut self, seq: u32, padding: usize) -> ResultType<()>;
+
+    /// The `seq` of the next echo, `None` if the connection is closed.
+    async fn recv_echo(&mut self) -> Option<u32>;
+}
+
+/// Send the probes by the io loop of the session, which forwards the echoes back.
+pub struct PeerTransport {
+    pub sender: mpsc::UnboundedSender<Data>,
+    pub echoes: mpsc::UnboundedReceiver<u32>,
+}
+
+#[async_trait]
+impl ProbeTransport for PeerTransport {
+    async fn send_probe(&mut self, seq: u32, padding: usize) -> ResultType<()> {
+        let mut msg = Message::new();
+        msg.set_test_delay(TestDelay {
+            time: get_time(),
+            from_client: true,
+            probe_seq: seq,
+            probe_padding: vec![0u8; padding].into(),
+            ..Default::default()
+        });
+        if self.sender.send(Data::Message(msg)).is_err() {
+            bail!("connection closed");
+        }
+        Ok(())
+    }
+
+    async fn recv_echo(&mut self) -> Option<u32> {
+        self.echoes.recv().await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeConfig {
+    /// How long the latency is measured.
+    pub duration: Duration,
+    pub ping_interval: Duration,
+    /// The probes not echoed within the timeout are lost.
+    pub echo_timeout: Duration,
+    pub burst_packet_size: usize,
+    /// The max bytes sent by the burst, not counting the echoes.
+    pub max_burst_bytes: usize,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(5),
+            ping_interval: Duration::from_millis(100),
+            echo_timeout: Duration::from_secs(2),
+            burst_packet_size: 16 * 1024,
+            max_burst_bytes: 256 * 1024,
+        }
+    }
+}
+
+/// The round trip times in milliseconds.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub sent: usize,
+    pub lost: usize,
+    pub min: u32,
+    pub avg: u32,
+    pub p50: u32,
+    pub p95: u32,
+    pub max: u32,
+    /// The mean difference of the consecutive samples.
+    pub jitter: u32,
+}
+
+impl LatencyStats {
+    /// `rtts` are in the order of arrival.
+    pub fn new(rtts: &[u32], sent: usize) -> Self {
+        let mut stats = Self {
+            sent,
+            lost: sent.saturating_sub(rtts.len()),
+            ..Default::default()
+        };
+        if rtts.is_empty() {
+            return stats;
+        }
+        let n = rtts.len();
+        let diff_sum: u64 = rtts
+            .windows(2)
+            .map(|w| (w[0] as i64 - w[1] as i64).unsigned_abs())
+            .sum();
+        stats.jitter = (diff_sum / (n as u64 - 1).max(1)) as u32;
+        let mut sorted = rtts.to_vec();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[((p * n + 99) / 100).max(1) - 1];
+        stats.min = sorted[0];
+        stats.max = sorted[n - 1];
+        stats.avg = (sorted.iter().map(|&x| x as u64).sum::<u64>() / n as u64) as u32;
+        stats.p50 = percentile(50);
+        stats.p95 = percentile(95);
+        stats
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ProbeResult {
+    pub latency: LatencyStats,
+    pub burst_bytes: usize,
+    pub burst_ms: u32,
+    /// The estimated bandwidth of the slower direction, `None` if the burst is not echoed.
+    pub bandwidth_kbps: Option<u32>,
+    /// Filled by the session, `None` if unknown.
+    pub direct: Option<bool>,
+    /// The relay server in use, `None` for the direct connections.
+    pub relay_server: Option<String>,
+}
+
+impl ProbeResult {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+struct Probe<'a, T: ProbeTransport> {
+    transport: &'a mut T,
+    config: &'a ProbeConfig,
+    seq: u32,
+}
+
+impl<T: ProbeTransport> Probe<'_, T> {
+    async fn measure_latency(&mut self) -> ResultType<LatencyStats> {
+        let start = Instant::now();
+        let end = start + self.config.duration;
+        let mut next_ping = start;
+        let mut pending: HashMap<u32, Instant> = HashMap::new();
+        let mut rtts = vec![];
+        let mut sent = 0;
+        loop {
+            let now = Instant::now();
+            if now < end && now >= next_ping {
+                self.seq += 1;
+                pending.insert(self.seq, now);
+                self.transport.send_probe(self.seq, 0).await?;
+                sent += 1;
+                next_ping += self.config.ping_interval;
+                continue;
+            }
+            let deadline = if now < end {
+                next_ping.min(end)
+            } else if pending.is_empty() {
+                break;
+            } else {
+                end + self.config.echo_timeout
+            };
+            if now >= deadline {
+                break;
+            }
+            match timeout(deadline - now, self.transport.recv_echo()).await {
+                Ok(Some(seq)) => {
+                    if let Some(t) = pending.remove(&seq) {
+                        rtts.push(t.elapsed().as_millis() as u32);
+                    }
+                }
+                Ok(None) => bail!("connection closed"),
+                Err(_) => {}
+            }
+        }
+        Ok(LatencyStats::new(&rtts, sent))
+    }
+
+    /// Returns the bytes sent, the time used and the bandwidth.
+    async fn measure_bandwidth(&mut self, base_rtt: u32) -> ResultType<(usize, u32, Option<u32>)> {
+        let size = self.config.burst_packet_size.max(1);
+        let count = (self.config.max_burst_bytes / size).max(1);
+        let start = Instant::now();
+        let mut pending = HashSet::new();
+        for _ in 0..count {
+            self.seq += 1;
+            pending.insert(self.seq);
+            self.transport.send_probe(self.seq, size).await?;
+        }
+        let deadline = start + self.config.echo_timeout;
+        while !pending.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match timeout(deadline - now, self.transport.recv_echo()).await {
+                Ok(Some(seq)) => {
+                    pending.remove(&seq);
+                }
+                Ok(None) => bail!("connection closed"),
+                Err(_) => break,
+            }
+        }
+        let burst_ms = start.elapsed().as_millis() as u32;
+        let bytes = count * size;
+        let bandwidth = if pending.is_empty() {
+            let ms = burst_ms.saturating_sub(base_rtt).max(1) as u64;
+            Some((bytes as u64 * 8 / ms) as u32)
+        } else {
+            None
+        };
+        Ok((bytes, burst_ms, bandwidth))
+    }
+}
+
+/// Measure the latency, then the bandwidth, the path is left to the caller.
+pub async fn run(
+    transport: &mut impl ProbeTransport,
+    config: &ProbeConfig,
+) -> ResultType<ProbeResult> {
+    let mut probe = Probe {
+        transport,
+        config,
+        seq: 0,
+    };
+    let latency = probe.measure_latency().await?;
+    let (burst_bytes, burst_ms, bandwidth_kbps) = probe.measure_bandwidth(latency.min).await?;
+    Ok(ProbeResult {
+        latency,
+        burst_bytes,
+        burst_ms,
+        bandwidth_kbps,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Echo the probes at once, except the ones rejected by `drop`.
+    struct MockTransport {
+        echoes: VecDeque<u32>,
+        drop: fn(u32, usize) -> bool,
+        closed: bool,
+        sent_bytes: usize,
+    }
+
+    impl MockTransport {
+        fn new(drop: fn(u32, usize) -> bool) -> Self {
+            Self {
+                echoes: VecDeque::new(),
+                drop,
+                closed: false,
+                sent_bytes: 0,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ProbeTransport for MockTransport {
+        async fn send_probe(&mut self, seq: u32, padding: usize) -> ResultType<()> {
+            self.sent_bytes += padding;
+            if !(self.drop)(seq, padding) {
+                self.echoes.push_back(seq);
+            }
+            Ok(())
+        }
+
+        async fn recv_echo(&mut self) -> Option<u32> {
+            if self.closed {
+                return None;
+            }
+            match self.echoes.pop_front() {
+                Some(seq) => Some(seq),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn config() -> ProbeConfig {
+        ProbeConfig {
+            duration: Duration::from_millis(200),
+            ping_interval: Duration::from_millis(20),
+            echo_timeout: Duration::from_millis(100),
+            burst_packet_size: 1000,
+            max_burst_bytes: 10_500,
+        }
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        assert_eq!(LatencyStats::new(&[], 3).lost, 3);
+        let stats = LatencyStats::new(&[30, 10, 20, 40], 5);
+        assert_eq!(stats.lost, 1);
+        assert_eq!((stats.min, stats.max, stats.avg), (10, 40, 25));
+        assert_eq!((stats.p50, stats.p95), (20, 40));
+        // |30 - 10| + |10 - 20| + |20 - 40| = 50
+        assert_eq!(stats.jitter, 16);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let mut transport = MockTransport::new(|seq, padding| padding == 0 && seq % 5 == 0);
+        let res = run(&mut transport, &config()).await.unwrap();
+        assert!(res.latency.sent >= 5);
+        assert_eq!(res.latency.lost, res.latency.sent / 5);
+        assert_eq!(res.burst_bytes, 10_000);
+        assert_eq!(transport.sent_bytes, 10_000);
+        assert!(res.bandwidth_kbps.is_some());
+        let v: serde_json::Value = serde_json::from_str(&res.to_json()).unwrap();
+        assert_eq!(v["burst_bytes"], 10_000);
+        assert!(v["relay_server"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_run_burst_lost() {
+        let mut transport = MockTransport::new(|seq, padding| padding > 0 && seq % 2 == 0);
+        let res = run(&mut transport, &config()).await.unwrap();
+        assert_eq!(res.latency.lost, 0);
+        assert!(res.bandwidth_kbps.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_closed() {
+        let mut transport = MockTransport::new(|_, _| false);
+        transport.closed = true;
+        assert!(run(&mut transport, &config()).await.is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//! Receive the jobs printed on the host, see `server::print_forward`.
+//!
+//! Each job is written to a file staged for the session, see [`super::staging`], as its chunks
+//! arrive, and handed to the ui once done, which opens the print dialog of the OS. The job is
+//! aborted by replying the error to the host, e.g. if it's larger than [`MAX_JOB_SIZE`].
+
+use super::staging::{SharedStaging, StagedFile};
+use hbb_common::{
+    bail,
+    message_proto::{print_job, PrintJob},
+    ResultType,
+};
+use std::{collections::HashMap, fs::File, io::Write, path::PathBuf};
+
+const STAGING_FEATURE: &str = "print";
+
+pub const OPTION_PRINT_FORWARD: &str = "enable-print-forward";
+pub const MAX_JOB_SIZE: u64 = 512 * 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum PrintEvent {
+    Progress {
+        id: u32,
+        name: String,
+        received: u64,
+        total: u64,
+    },
+    /// `evicted` are the staged files removed to make room for it.
+    Received {
+        id: u32,
+        name: String,
+        path: PathBuf,
+        evicted: Vec<StagedFile>,
+    },
+    /// The reply is sent to the host to abort the job.
+    Failed {
+        id: u32,
+        name: String,
+        error: String,
+        reply: Option<PrintJob>,
+    },
+}
+
+struct Receiving {
+    name: String,
+    path: PathBuf,
+    file: File,
+    received: u64,
+    total: u64,
+}
+
+// The file name of the job, the characters not allowed in file names are replaced.
+fn file_name(id: u32, name: &str, format: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || " -_.()".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+    let name = name.trim();
+    let name = if name.is_empty() { "print_job" } else { name };
+    let ext = if format == "xps" { "xps" } else { "pdf" };
+    format!("{}_{}_{}.{}", hbb_common::get_time(), id, name, ext)
+}
+
+pub struct PrintReceiver {
+    staging: SharedStaging,
+    jobs: HashMap<u32, Receiving>,
+}
+
+impl PrintReceiver {
+    pub fn new(staging: SharedStaging) -> Self {
+        Self {
+            staging,
+            jobs: Default::default(),
+        }
+    }
+
+    fn start(&self, id: u32, name: &str, format: &str, size: u64) -> ResultType<Receiving> {
+        if size > MAX_JOB_SIZE {
+            bail!("The print job is too large: {} bytes", size);
+        }
+        let path = self
+            .staging
+            .lock()
+            .unwrap()
+            .allocate(STAGING_FEATURE, &file_name(id, name, format))?;
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                self.staging.lock().unwrap().remove(&path);
+                bail!(e);
+            }
+        };
+        Ok(Receiving {
+            name: name.to_owned(),
+            file,
+            path,
+            received: 0,
+            total: size,
+        })
+    }
+
+    fn write(r: &mut Receiving, data: &[u8]) -> ResultType<()> {
+        if r.received + data.len() as u64 > r.total {
+            bail!("The print job is larger than announced");
+        }
+        r.file.write_all(data)?;
+        r.received += data.len() as u64;
+        Ok(())
+    }
+
+    fn fail(&mut self, id: u32, name: String, error: String, reply: bool) -> PrintEvent {
+        if let Some(r) = self.jobs.remove(&id) {
+            drop(r.file);
+            self.staging.lock().unwrap().remove(&r.path);
+        }
+        let reply = reply.then(|| PrintJob {
+            id,
+            union: Some(print_job::Union::Error(error.clone())),
+            ..Default::default()
+        });
+        PrintEvent::Failed {
+            id,
+            name,
+            error,
+            reply,
+        }
+    }
+
+    pub fn handle(&mut self, job: PrintJob) -> Option<PrintEvent> {
+        let id = job.id;
+        match job.union? {
+            print_job::Union::Start(s) => match self.start(id, &s.name, &s.format, s.size) {
+                Ok(r) => {
+                    self.jobs.insert(id, r);
+                    Some(PrintEvent::Progress {
+                        id,
+                        name: s.name,
+                        received: 0,
+                        total: s.size,
+                    })
+                }
+                Err(e) => Some(self.fail(id, s.name, e.to_string(), true)),
+            },
+            print_job::Union::Data(data) => {
+                let r = self.jobs.get_mut(&id)?;
+                match Self::write(r, &data) {
+                    Ok(_) => Some(PrintEvent::Progress {
+                        id,
+                        name: r.name.clone(),
+                        received: r.received,
+                        total: r.total,
+                    }),
+                    Err(e) => {
+                        let name = r.name.clone();
+                        Some(self.fail(id, name, e.to_string(), true))
+                    }
+                }
+            }
+            print_job::Union::Done(_) => {
+                let r = self.jobs.get(&id)?;
+                if r.received != r.total {
+                    let name = r.name.clone();
+                    let error = "The print job is incomplete".to_owned();
+                    return Some(self.fail(id, name, error, false));
+                }
+                let r = self.jobs.remove(&id)?;
+                drop(r.file);
+                let evicted = self.staging.lock().unwrap().commit(&r.path, r.total);
+                Some(PrintEvent::Received {
+                    id,
+                    name: r.name,
+                    path: r.path,
+                    evicted,
+                })
+            }
+            print_job::Union::Error(e) => {
+                let name = self.jobs.get(&id).map(|r| r.name.clone())?;
+                Some(self.fail(id, name, e, false))
+            }
+        }
+    }
+}
+
+impl Drop for PrintReceiver {
+    // The jobs not finished are left by the closed session.
+    fn drop(&mut self) {
+        for (_, r) in self.jobs.drain() {
+            drop(r.file);
+            self.staging.lock().unwrap().remove(&r.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::staging::Staging;
+    use hbb_common::message_proto::PrintJobStart;
+
+    fn msg(id: u32, union: print_job::Union) -> PrintJob {
+        PrintJob {
+            id,
+            union: Some(union),
+            ..Default::default()
+        }
+    }
+
+    fn start(id: u32, name: &str, size: u64) -> PrintJob {
+        msg(
+            id,
+            print_job::Union::Start(PrintJobStart {
+                name: name.to_owned(),
+                format: "pdf".to_owned(),
+                size,
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_receive_print_job() {
+        let dir = std::env::temp_dir().join(format!("print_job_{}", std::process::id()));
+        let staging = std::sync::Arc::new(std::sync::Mutex::new(Staging::new(dir.clone(), 2)));
+        let mut receiver = PrintReceiver::new(staging.clone());
+        assert!(matches!(
+            receiver.handle(start(1, "a/b:c", 3)),
+            Some(PrintEvent::Progress { total: 3, .. })
+        ));
+        assert!(matches!(
+            receiver.handle(msg(1, print_job::Union::Data(vec![1, 2].into()))),
+            Some(PrintEvent::Progress { received: 2, .. })
+        ));
+        receiver.handle(msg(1, print_job::Union::Data(vec![3].into())));
+        match receiver.handle(msg(1, print_job::Union::Done(true))) {
+            Some(PrintEvent::Received {
+                name,
+                path,
+                evicted,
+                ..
+            }) => {
+                assert_eq!(name, "a/b:c");
+                assert!(path.to_string_lossy().ends_with("_1_a_b_c.pdf"));
+                assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+                // Over the cap, but the last one is kept.
+                assert!(evicted.is_empty());
+                assert_eq!(staging.lock().unwrap().files().len(), 1);
+            }
+            e => panic!("received expected: {:?}", e),
+        }
+
+        // Too large, the reply aborts it.
+        match receiver.handle(start(2, "b", MAX_JOB_SIZE + 1)) {
+            Some(PrintEvent::Failed { reply, .. }) => assert!(reply.is_some()),
+            e => panic!("failed expected: {:?}", e),
+        }
+        // More data than announced.
+        receiver.handle(start(3, "c", 1));
+        assert!(matches!(
+            receiver.handle(msg(3, print_job::Union::Data(vec![1, 2].into()))),
+            Some(PrintEvent::Failed { .. })
+        ));
+        // Unknown job.
+        assert_eq!(receiver.handle(msg(4, print_job::Union::Done(true))), None);
+        // The failed ones are not staged.
+        assert_eq!(staging.lock().unwrap().files().len(), 1);
+        drop(receiver);
+        drop(staging);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,269 @@
+//! The rates of the counts sampled at irregular times, e.g. the bytes transferred, shared by the
+//! speed and the eta of the file transfers, the speed of the quality status and the rates of the
+//! port forward tunnels, so all the ui shows the consistent numbers.
+//!
+//! The instantaneous rate is the one between the last two samples, the smoothed rate is its
+//! exponentially weighted average, which decays with the time rather than the number of the
+//! samples. The samples closer than [`MIN_INTERVAL`] are merged into the next one, so a burst of
+//! samples is never divided by a tiny time. A gap longer than [`MAX_GAP`], e.g. the machine
+//! slept, is not a rate, the rate restarts from the end of it. While no sample comes, the
+//! smoothed rate decays as if nothing was counted, see [`Rate::smoothed_at`].
+//!
+//! The clock is `Instant`, which is monotonic, the samples older than the last one are counted
+//! into the next one.
+
+use std::time::{Duration, Instant};
+
+pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+pub const MAX_GAP: Duration = Duration::from_secs(30);
+// The weight of the older rates decays to 1/e in this time.
+pub const DEFAULT_TIME_CONSTANT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct Rate {
+    time_constant: f64,
+    // The time of the last sample, `None` before the first one.
+    last: Option<Instant>,
+    // The count since the last sample, merged into the next one.
+    pending: u64,
+    // The last total of `sample_total`.
+    total: Option<u64>,
+    instant: f64,
+    smoothed: Option<f64>,
+}
+
+impl Default for Rate {
+    fn default() -> Self {
+        Self::new(DEFAULT_TIME_CONSTANT)
+    }
+}
+
+impl Rate {
+    pub fn new(time_constant: Duration) -> Self {
+        Self {
+            time_constant: time_constant.as_secs_f64().max(f64::EPSILON),
+            last: None,
+            pending: 0,
+            total: None,
+            instant: 0.,
+            smoothed: None,
+        }
+    }
+
+    /// The rate counted from `now` and the total 0, otherwise from the first sample.
+    pub fn since(now: Instant) -> Self {
+        Self {
+            last: Some(now),
+            total: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// `n` more are counted at `now`.
+    pub fn add(&mut self, n: u64, now: Instant) {
+        let Some(last) = self.last else {
+            // The count before the first sample has no time.
+            self.last = Some(now);
+            return;
+        };
+        self.pending = self.pending.saturating_add(n);
+        let Some(elapsed) = now.checked_duration_since(last) else {
+            return;
+        };
+        if elapsed < MIN_INTERVAL {
+            return;
+        }
+        self.last = Some(now);
+        let pending = std::mem::take(&mut self.pending);
+        if elapsed > MAX_GAP {
+            self.instant = 0.;
+            self.smoothed = self
+                .smoothed
+                .map(|s| self.decay(s, 0., elapsed.as_secs_f64()));
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        self.instant = pending as f64 / secs;
+        self.smoothed = Some(match self.smoothed {
+            None => self.instant,
+            Some(s) => self.decay(s, self.instant, secs),
+        });
+    }
+
+    /// The total counted so far is `total` at `now`, a total going back restarts the count.
+    pub fn sample_total(&mut self, total: u64, now: Instant) {
+        let n = match self.total {
+            Some(last) => total.saturating_sub(last),
+            None => 0,
+        };
+        self.total = Some(total);
+        self.add(n, now);
+    }
+
+    #[inline]
+    fn decay(&self, smoothed: f64, sample: f64, secs: f64) -> f64 {
+        let alpha = 1. - (-secs / self.time_constant).exp();
+        smoothed + alpha * (sample - smoothed)
+    }
+
+    /// The rate between the last two samples, per second.
+    #[inline]
+    pub fn instant(&self) -> f64 {
+        self.instant
+    }
+
+    /// The average rate at the last sample, per second, 0 before the rate is known.
+    #[inline]
+    pub fn smoothed(&self) -> f64 {
+        self.smoothed.unwrap_or_default()
+    }
+
+    /// The average rate at `now`, with the count not sampled yet, so it decays while paused.
+    pub fn smoothed_at(&self, now: Instant) -> f64 {
+        let (Some(smoothed), Some(last)) = (self.smoothed, self.last) else {
+            return 0.;
+        };
+        let elapsed = now.saturating_duration_since(last);
+        if elapsed < MIN_INTERVAL {
+            return smoothed;
+        }
+        let secs = elapsed.as_secs_f64();
+        let sample = if elapsed > MAX_GAP {
+            0.
+        } else {
+            self.pending as f64 / secs
+        };
+        self.decay(smoothed, sample, secs)
+    }
+}
+
+/// The seconds to transfer `remaining` at `rate` per second, `None` if stalled.
+pub fn eta(remaining: u64, rate: f64) -> Option<u64> {
+    if remaining == 0 {
+        Some(0)
+    } else if rate >= 1. {
+        // Saturated if too large.
+        Some((remaining as f64 / rate).ceil() as u64)
+    } else {
+        None
+    }
+}
+
+/// The rate of bytes shown by the quality status.
+pub fn format_speed(bytes_per_sec: f64) -> String {
+    format!("{:.2}kB/s", bytes_per_sec / 1024.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(start: Instant, n: u64) -> Instant {
+        start + Duration::from_millis(n)
+    }
+
+    // A tiny xorshift, so the jitter is deterministic.
+    fn jitter(seed: &mut u64, max: u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed % (max + 1)
+    }
+
+    #[test]
+    fn test_constant_stream_converges() {
+        let start = Instant::now();
+        for (period, seed) in [(10u64, 1u64), (50, 7), (250, 42), (1000, 9)] {
+            let mut rate = Rate::since(start);
+            let mut seed = seed;
+            let mut t = 0;
+            let mut total = 0u64;
+            // 100 kB/s, sampled with jitter of the period.
+            while t < 30_000 {
+                let step = period + jitter(&mut seed, period / 2);
+                t += step;
+                total += step * 100;
+                rate.sample_total(total, ms(start, t));
+            }
+            let smoothed = rate.smoothed_at(ms(start, t));
+            assert!(
+                (smoothed - 100_000.).abs() < 2_000.,
+                "period {}: {}",
+                period,
+                smoothed
+            );
+        }
+    }
+
+    #[test]
+    fn test_pause_no_spike() {
+        let start = Instant::now();
+        let mut rate = Rate::since(start);
+        let mut total = 0;
+        for i in 1..=100 {
+            total += 10_000;
+            rate.sample_total(total, ms(start, i * 100));
+        }
+        let before = rate.smoothed();
+        assert!((before - 100_000.).abs() < 1.);
+        // Paused for 5 seconds, decays without samples.
+        let paused = rate.smoothed_at(ms(start, 15_000));
+        assert!(paused < before / 2., "{}", paused);
+        assert_eq!(rate.smoothed(), before);
+        // Resumed at the same speed, never above it.
+        let mut max: f64 = 0.;
+        for i in 1..=100 {
+            total += 10_000;
+            rate.sample_total(total, ms(start, 15_000 + i * 100));
+            max = max.max(rate.smoothed()).max(rate.instant());
+        }
+        assert!(max <= 100_000. + 1., "{}", max);
+        assert!((rate.smoothed() - 100_000.).abs() < 1_000.);
+
+        // The long gap, e.g. slept, is not a rate.
+        total += 1 << 30;
+        rate.sample_total(total, ms(start, 100_000));
+        assert_eq!(rate.instant(), 0.);
+        assert!(rate.smoothed() < 1.);
+        assert_eq!(rate.smoothed_at(ms(start, 1_000_000)), 0.);
+    }
+
+    #[test]
+    fn test_burst_and_clock() {
+        let start = Instant::now();
+        let mut rate = Rate::since(start);
+        // A burst in one instant is merged into the next sample.
+        for _ in 0..1000 {
+            rate.add(u64::MAX / 4, start);
+        }
+        assert_eq!(rate.smoothed(), 0.);
+        rate.add(0, ms(start, 1000));
+        assert_eq!(rate.instant(), u64::MAX as f64);
+        assert!(rate.smoothed().is_finite());
+
+        // The samples older than the last one are counted into the next one.
+        let mut rate = Rate::since(ms(start, 1000));
+        rate.add(1000, ms(start, 500));
+        rate.add(1000, ms(start, 2000));
+        assert_eq!(rate.instant(), 2000.);
+
+        // The total going back restarts the count.
+        let mut rate = Rate::default();
+        rate.sample_total(5000, start);
+        rate.sample_total(6000, ms(start, 1000));
+        assert_eq!(rate.instant(), 1000.);
+        rate.sample_total(100, ms(start, 2000));
+        assert_eq!(rate.instant(), 0.);
+        rate.sample_total(600, ms(start, 3000));
+        assert_eq!(rate.instant(), 500.);
+    }
+
+    #[test]
+    fn test_eta() {
+        assert_eq!(eta(0, 0.), Some(0));
+        assert_eq!(eta(1000, 0.5), None);
+        assert_eq!(eta(1000, 300.), Some(4));
+        assert_eq!(eta(u64::MAX, 1.), Some(u64::MAX));
+        assert_eq!(format_speed(1024.), "1.00kB/s");
+    }
+}
@@ -0,0 +1,250 @@
+//! The relay servers to choose from, and the preferred one of each peer.
+//!
+//! The candidates are the custom relay server, the extra ones listed by the option
+//! [`OPTION_RELAY_CANDIDATES`], and the default relay of each rendezvous server. Their latencies
+//! are measured on demand by [`probe_relays`], at most [`MAX_CONCURRENT_PROBES`] at a time. The
+//! results are cached for [`PROBE_TTL`], so redrawing the ui does not probe again, and a forced
+//! probe still reuses the results younger than [`MIN_PROBE_INTERVAL`].
+//!
+//! The preferred relay is stored in the peer option [`OPTION_PREFERRED_RELAY`], and is tried
+//! first when the session is relayed, falling back to the relay chosen by the rendezvous server.
+//! The host uses its own relay server if configured, in which case the preferred one fails and
+//! the fallback is taken.
+
+use hbb_common::{
+    config::{RELAY_PORT, RENDEZVOUS_SERVERS},
+    socket_client,
+    tokio::{sync::Semaphore, task::JoinSet},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The comma separated relay servers offered besides the default ones.
+pub const OPTION_RELAY_CANDIDATES: &str = "relay-candidates";
+/// The peer option of the relay server tried first.
+pub const OPTION_PREFERRED_RELAY: &str = "preferred-relay";
+pub const MAX_CONCURRENT_PROBES: usize = 4;
+pub const PROBE_TTL: Duration = Duration::from_secs(30);
+pub const MIN_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: u64 = 3_000;
+
+lazy_static::lazy_static! {
+    static ref PROBES: Mutex<ProbeCache> = Default::default();
+}
+
+fn candidates_of(custom: &str, extra: &str, rendezvous_servers: &[String]) -> Vec<String> {
+    let mut relays: Vec<String> = vec![];
+    let from_rendezvous = rendezvous_servers
+        .iter()
+        .map(|s| crate::increase_port(s, 1));
+    for relay in std::iter::once(custom.to_owned())
+        .chain(extra.split(',').map(|s| s.to_owned()))
+        .chain(from_rendezvous)
+    {
+        let relay = relay.trim().to_owned();
+        if !relay.is_empty() && !relays.contains(&relay) {
+            relays.push(relay);
+        }
+    }
+    relays
+}
+
+/// The relay servers configured, the custom one first.
+pub fn candidates() -> Vec<String> {
+    let custom_rendezvous = crate::get_custom_rendezvous_server(crate::ui_interface::get_option(
+        "custom-rendezvous-server",
+    ));
+    let rendezvous_servers = if custom_rendezvous.is_empty() {
+        RENDEZVOUS_SERVERS.iter().map(|s| s.to_string()).collect()
+    } else {
+        vec![custom_rendezvous]
+    };
+    candidates_of(
+        &crate::ui_interface::get_option("relay-server"),
+        &crate::ui_interface::get_option(OPTION_RELAY_CANDIDATES),
+        &rendezvous_servers,
+    )
+}
+
+/// The relay servers to try in order, the preferred one first.
+pub fn relays_to_try(preferred: &str, relay_server: &str) -> Vec<String> {
+    let mut relays = vec![];
+    for relay in [preferred, relay_server] {
+        if !relay.is_empty() && !relays.iter().any(|r| r == relay) {
+            relays.push(relay.to_owned());
+        }
+    }
+    relays
+}
+
+#[derive(Debug, Default)]
+struct ProbeCache {
+    // relay -> (the time probed, the latency in ms, `None` if unreachable)
+    results: HashMap<String, (Instant, Option<u32>)>,
+    in_flight: HashSet<String>,
+}
+
+impl ProbeCache {
+    /// Returns the cached results, and the relays to probe, which are marked in flight.
+    /// The relays in flight are skipped, their results are reported by the running probe.
+    fn plan(
+        &mut self,
+        relays: &[String],
+        force: bool,
+        now: Instant,
+    ) -> (Vec<(String, Option<u32>)>, Vec<String>) {
+        let ttl = if force { MIN_PROBE_INTERVAL } else { PROBE_TTL };
+        let mut cached = vec![];
+        let mut to_probe = vec![];
+        for relay in relays {
+            if self.in_flight.contains(relay) {
+                continue;
+            }
+            match self.results.get(relay) {
+                Some((t, latency)) if now.duration_since(*t) < ttl => {
+                    cached.push((relay.clone(), *latency));
+                }
+                _ => {
+                    self.in_flight.insert(relay.clone());
+                    to_probe.push(relay.clone());
+                }
+            }
+        }
+        (cached, to_probe)
+    }
+
+    fn finish(&mut self, relay: &str, latency: Option<u32>, now: Instant) {
+        self.in_flight.remove(relay);
+        self.results.insert(relay.to_owned(), (now, latency));
+    }
+}
+
+// The relays probed by a job, released if the job is cancelled before they are finished.
+struct InFlight(Vec<String>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let mut probes = PROBES.lock().unwrap();
+        for relay in self.0.iter() {
+            probes.in_flight.remove(relay);
+        }
+    }
+}
+
+/// The latency in ms of connecting to the relay, `None` if unreachable.
+async fn probe(relay: &str) -> Option<u32> {
+    let start = Instant::now();
+    match socket_client::connect_tcp(crate::check_port(relay, RELAY_PORT), PROBE_TIMEOUT).await {
+        Ok(_) => Some(start.elapsed().as_millis() as u32),
+        Err(e) => {
+            hbb_common::log::info!("Failed to probe the relay server {}: {}", relay, e);
+            None
+        }
+    }
+}
+
+/// Probe the relays, `on_result(relay, latency, cached)` is called for each of them, except the
+/// ones being probed by another job. The probes are aborted if the future is dropped.
+pub async fn probe_relays(
+    relays: Vec<String>,
+    force: bool,
+    on_result: impl Fn(&str, Option<u32>, bool),
+) {
+    let (cached, to_probe) = PROBES.lock().unwrap().plan(&relays, force, Instant::now());
+    for (relay, latency) in cached {
+        on_result(&relay, latency, true);
+    }
+    let mut in_flight = InFlight(to_probe.clone());
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_PROBES));
+    let mut probes = JoinSet::new();
+    for relay in to_probe {
+        let semaphore = semaphore.clone();
+        probes.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let latency = probe(&relay).await;
+            (relay, latency)
+        });
+    }
+    while let Some(res) = probes.join_next().await {
+        if let Ok((relay, latency)) = res {
+            PROBES
+                .lock()
+                .unwrap()
+                .finish(&relay, latency, Instant::now());
+            in_flight.0.retain(|r| *r != relay);
+            on_result(&relay, latency, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relays(relays: &[&str]) -> Vec<String> {
+        relays.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert_eq!(
+            candidates_of(
+                "relay.example.com",
+                "eu.example.com, ,us.example.com:21117,relay.example.com",
+                &relays(&["rs.example.com:21116", "rs2.example.com"]),
+            ),
+            relays(&[
+                "relay.example.com",
+                "eu.example.com",
+                "us.example.com:21117",
+                "rs.example.com:21117",
+                "rs2.example.com",
+            ])
+        );
+        assert_eq!(
+            relays_to_try("eu.example.com", "relay.example.com"),
+            relays(&["eu.example.com", "relay.example.com"])
+        );
+        assert_eq!(
+            relays_to_try("relay.example.com", "relay.example.com"),
+            relays(&["relay.example.com"])
+        );
+        assert_eq!(relays_to_try("", ""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_probe_cache() {
+        let now = Instant::now();
+        let all = relays(&["a", "b", "c"]);
+        let mut cache = ProbeCache::default();
+        let (cached, to_probe) = cache.plan(&all, false, now);
+        assert!(cached.is_empty());
+        assert_eq!(to_probe, all);
+        // In flight, not probed again.
+        assert_eq!(cache.plan(&all, true, now), (vec![], vec![]));
+
+        cache.finish("a", Some(20), now);
+        cache.finish("b", None, now);
+        let later = now + MIN_PROBE_INTERVAL;
+        assert_eq!(
+            cache.plan(&relays(&["a", "b"]), false, later),
+            (
+                vec![("a".to_owned(), Some(20)), ("b".to_owned(), None)],
+                vec![]
+            )
+        );
+        // Forced, probed again after the min interval.
+        assert_eq!(
+            cache.plan(&relays(&["a"]), true, later),
+            (vec![], relays(&["a"]))
+        );
+        // Expired.
+        assert_eq!(
+            cache.plan(&relays(&["b"]), false, now + PROBE_TTL),
+            (vec![], relays(&["b"]))
+        );
+    }
+}
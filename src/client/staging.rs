@@ -0,0 +1,277 @@
+//! The files received by the features of a session, e.g. the forwarded print jobs.
+//!
+//! Each session has its own directory under [`staging_root`], created once the first file is
+//! allocated, and removed once the session is closed. The files the user doesn't move away are
+//! removed after [`RETENTION`], and the oldest ones once the total size exceeds
+//! [`OPTION_STAGING_MAX_SIZE`].
+//!
+//! The directories failing to be removed, e.g. a file still opened by another program on Windows,
+//! are recorded in [`PENDING_FILE`] and removed again on the next startup.
+
+use hbb_common::{bail, config::LocalConfig, get_time, log, ResultType};
+use serde_derive::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// The local option of the size cap of each session in MB, [`DEFAULT_MAX_SIZE`] by default.
+pub const OPTION_STAGING_MAX_SIZE: &str = "staging-max-size";
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+/// The files not moved in this time are removed, in milliseconds.
+pub const RETENTION: i64 = 24 * 3600 * 1000;
+pub const PENDING_FILE: &str = "pending_cleanup";
+
+static SESSION_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+pub type SharedStaging = Arc<Mutex<Staging>>;
+
+pub fn staging_root() -> PathBuf {
+    std::env::temp_dir().join("rustdesk_staging")
+}
+
+pub fn max_size_of(option: &str) -> u64 {
+    match option.trim().parse::<u64>() {
+        Ok(mb) if mb > 0 => mb * 1024 * 1024,
+        _ => DEFAULT_MAX_SIZE,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StagedFile {
+    /// The feature allocating the file, e.g. "print".
+    pub feature: String,
+    pub path: String,
+    pub size: u64,
+    /// Milliseconds since the epoch.
+    pub created: i64,
+}
+
+pub struct Staging {
+    root: PathBuf,
+    dir: Option<PathBuf>,
+    max_size: u64,
+    // The oldest first.
+    files: Vec<StagedFile>,
+}
+
+impl Default for Staging {
+    fn default() -> Self {
+        Self::new(
+            staging_root(),
+            max_size_of(&LocalConfig::get_option(OPTION_STAGING_MAX_SIZE)),
+        )
+    }
+}
+
+impl Staging {
+    pub fn new(root: PathBuf, max_size: u64) -> Self {
+        Self {
+            root,
+            dir: None,
+            max_size,
+            files: vec![],
+        }
+    }
+
+    fn dir(&mut self) -> ResultType<PathBuf> {
+        if let Some(dir) = &self.dir {
+            return Ok(dir.clone());
+        }
+        let n = SESSION_DIRS.fetch_add(1, Ordering::SeqCst);
+        let dir = self
+            .root
+            .join(format!("session_{}_{}", std::process::id(), n));
+        std::fs::create_dir_all(&dir)?;
+        self.dir = Some(dir.clone());
+        Ok(dir)
+    }
+
+    /// A new path of the file `name` of `feature`, the file itself is created by the feature.
+    /// `name` must be a file name, not a path.
+    pub fn allocate(&mut self, feature: &str, name: &str) -> ResultType<PathBuf> {
+        if Path::new(name).file_name() != Some(std::ffi::OsStr::new(name)) {
+            bail!("Invalid staged file name: {}", name);
+        }
+        let dir = self.dir()?.join(feature);
+        std::fs::create_dir_all(&dir)?;
+        let mut path = dir.join(name);
+        let mut n = 1;
+        while path.exists() || self.files.iter().any(|f| Path::new(&f.path) == path) {
+            path = dir.join(format!("{}_{}", n, name));
+            n += 1;
+        }
+        self.files.push(StagedFile {
+            feature: feature.to_owned(),
+            path: path.to_string_lossy().to_string(),
+            size: 0,
+            created: get_time(),
+        });
+        Ok(path)
+    }
+
+    /// The file is written with `size` bytes.
+    /// Returns the files removed to keep the total size under the cap, and the expired ones.
+    pub fn commit(&mut self, path: &Path, size: u64) -> Vec<StagedFile> {
+        if let Some(f) = self.files.iter_mut().find(|f| Path::new(&f.path) == path) {
+            f.size = size;
+        }
+        self.evict(path, get_time())
+    }
+
+    // The files moved by the user are not counted.
+    fn refresh(&mut self) {
+        self.files.retain(|f| Path::new(&f.path).exists());
+    }
+
+    fn evict(&mut self, keep: &Path, now: i64) -> Vec<StagedFile> {
+        self.refresh();
+        let mut evicted = vec![];
+        let mut total: u64 = self.files.iter().map(|f| f.size).sum();
+        let mut i = 0;
+        while i < self.files.len() {
+            let f = &self.files[i];
+            let expired = now - f.created > RETENTION;
+            if Path::new(&f.path) != keep && (expired || total > self.max_size) {
+                let f = self.files.remove(i);
+                std::fs::remove_file(&f.path).ok();
+                total -= f.size;
+                evicted.push(f);
+            } else {
+                i += 1;
+            }
+        }
+        evicted
+    }
+
+    /// Remove the file, e.g. failing to be received.
+    pub fn remove(&mut self, path: &Path) {
+        self.files.retain(|f| Path::new(&f.path) != path);
+        std::fs::remove_file(path).ok();
+    }
+
+    /// The files staged and not moved away.
+    pub fn files(&mut self) -> Vec<StagedFile> {
+        self.refresh();
+        self.files.clone()
+    }
+
+    /// Remove all the files, and the directory of the session.
+    pub fn clear(&mut self) {
+        self.files.clear();
+        if let Some(dir) = self.dir.take() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                if dir.exists() {
+                    log::warn!("Failed to remove {}: {}", dir.display(), e);
+                    add_pending(&self.root, &dir);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+fn add_pending(root: &Path, dir: &Path) {
+    use std::io::Write;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(root.join(PENDING_FILE));
+    if let Ok(mut file) = file {
+        writeln!(file, "{}", dir.display()).ok();
+    }
+}
+
+/// Remove the directories failing to be removed before, called on startup.
+pub fn retry_pending_cleanup(root: &Path) {
+    let pending = root.join(PENDING_FILE);
+    let Ok(content) = std::fs::read_to_string(&pending) else {
+        return;
+    };
+    let mut left = vec![];
+    for dir in content.lines().filter(|l| !l.is_empty()) {
+        // Only the directories of the sessions.
+        let dir = Path::new(dir);
+        if dir.parent() != Some(root) {
+            continue;
+        }
+        if std::fs::remove_dir_all(dir).is_err() && dir.exists() {
+            left.push(dir.to_string_lossy().to_string());
+        }
+    }
+    if left.is_empty() {
+        std::fs::remove_file(&pending).ok();
+    } else {
+        std::fs::write(&pending, left.join("\n") + "\n").ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("staging_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_allocate_and_evict() {
+        let root = temp_root("evict");
+        let mut staging = Staging::new(root.clone(), 10);
+        assert!(staging.allocate("print", "../a").is_err());
+        assert!(staging.allocate("print", "").is_err());
+
+        let a = staging.allocate("print", "a.pdf").unwrap();
+        std::fs::write(&a, [0; 6]).unwrap();
+        assert!(staging.commit(&a, 6).is_empty());
+        // The same name is not reused.
+        let b = staging.allocate("print", "a.pdf").unwrap();
+        assert_ne!(a, b);
+        std::fs::write(&b, [0; 6]).unwrap();
+        // The oldest is removed to keep under the cap.
+        let evicted = staging.commit(&b, 6);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(Path::new(&evicted[0].path), a);
+        assert!(!a.exists());
+
+        // The moved files are not counted.
+        let c = staging.allocate("print", "c.pdf").unwrap();
+        std::fs::write(&c, [0; 4]).unwrap();
+        std::fs::rename(&b, root.join("moved")).unwrap();
+        assert!(staging.commit(&c, 4).is_empty());
+        assert_eq!(staging.files().len(), 1);
+
+        // Expired.
+        staging.files[0].created -= RETENTION + 1;
+        let evicted = staging.evict(Path::new(""), get_time());
+        assert_eq!(evicted.len(), 1);
+        assert!(!c.exists());
+
+        let dir = staging.dir.clone().unwrap();
+        drop(staging);
+        assert!(!dir.exists());
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_retry_pending_cleanup() {
+        let root = temp_root("pending");
+        let dir = root.join("session_0_0");
+        std::fs::create_dir_all(dir.join("print")).unwrap();
+        add_pending(&root, &dir);
+        // Not a directory of the sessions.
+        add_pending(&root, &std::env::temp_dir());
+        retry_pending_cleanup(&root);
+        assert!(!dir.exists());
+        assert!(!root.join(PENDING_FILE).exists());
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,106 @@
+//! The gating of the text clipboard of a session, shared by the platforms.
+//!
+//! The desktop clients sync the clipboard by the clipboard service, and the mobile clients by the
+//! ui, i.e. the ui reads the local clipboard on paste and writes the clipboard received. Both
+//! follow the same checks here, the platform glue is in `Session` and the io loop.
+
+/// The toggle option of the session, the local clipboard is never read for the peer.
+pub const OPTION_DISABLE_CLIPBOARD_READ: &str = "disable-clipboard-read";
+/// The longest text clipboard received in bytes, the longer ones are truncated.
+pub const MAX_TEXT_CLIPBOARD_LEN: usize = 1 << 20;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClipboardGate {
+    pub server_clipboard_enabled: bool,
+    pub server_keyboard_enabled: bool,
+    pub disable_clipboard: bool,
+    pub view_only: bool,
+    pub disable_read: bool,
+}
+
+impl ClipboardGate {
+    /// The local text clipboard can be read and sent to the peer.
+    pub fn is_outbound_allowed(&self) -> bool {
+        self.server_clipboard_enabled
+            && self.server_keyboard_enabled
+            && !self.disable_clipboard
+            && !self.view_only
+            && !self.disable_read
+    }
+
+    /// The text clipboard of the peer can be written to the local clipboard.
+    pub fn is_inbound_allowed(&self) -> bool {
+        !self.disable_clipboard
+    }
+}
+
+/// Truncate the text to at most `max` bytes on a char boundary.
+/// Returns the original length if truncated.
+pub fn truncate_text(text: &mut String, max: usize) -> Option<usize> {
+    if text.len() <= max {
+        return None;
+    }
+    let len = text.len();
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clipboard_gate() {
+        let gate = ClipboardGate {
+            server_clipboard_enabled: true,
+            server_keyboard_enabled: true,
+            ..Default::default()
+        };
+        assert!(gate.is_outbound_allowed());
+        assert!(gate.is_inbound_allowed());
+        for blocked in [
+            ClipboardGate {
+                server_keyboard_enabled: false,
+                ..gate
+            },
+            ClipboardGate {
+                view_only: true,
+                ..gate
+            },
+            ClipboardGate {
+                disable_read: true,
+                ..gate
+            },
+        ] {
+            assert!(!blocked.is_outbound_allowed());
+            assert!(blocked.is_inbound_allowed());
+        }
+        let disabled = ClipboardGate {
+            disable_clipboard: true,
+            ..gate
+        };
+        assert!(!disabled.is_outbound_allowed());
+        assert!(!disabled.is_inbound_allowed());
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        let mut text = "abc".to_owned();
+        assert_eq!(truncate_text(&mut text, 3), None);
+        assert_eq!(text, "abc");
+        // Not in the middle of "é".
+        let mut text = "aéb".to_owned();
+        assert_eq!(truncate_text(&mut text, 2), Some(4));
+        assert_eq!(text, "a");
+        let mut text = "x".repeat(MAX_TEXT_CLIPBOARD_LEN + 1);
+        assert_eq!(
+            truncate_text(&mut text, MAX_TEXT_CLIPBOARD_LEN),
+            Some(MAX_TEXT_CLIPBOARD_LEN + 1)
+        );
+        assert_eq!(text.len(), MAX_TEXT_CLIPBOARD_LEN);
+    }
+}
@@ -1,6 +1,9 @@
 use std::{
     future::Future,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
 #[derive(Debug, Eq, PartialEq)]
@@ -164,6 +167,252 @@ pub const PLATFORM_MACOS: &str = "Mac OS";
 pub const PLATFORM_ANDROID: &str = "Android";
 
 const MIN_VER_MULTI_UI_SESSION: &str = "1.2.4";
+const MIN_VER_VIRTUAL_DISPLAY_REQUEST: &str = "1.2.4";
+const MIN_VER_PRIVACY_MODE_FEATURE: &str = "1.2.0";
+const MIN_VER_RESTART: &str = "1.1.10";
+const MIN_VER_TOUCH_PAN: &str = "1.2.4";
+const MIN_VER_VIEWPORT: &str = "1.2.4";
+
+// The key in `platform_additions` indicating the peer can add or remove virtual displays on request.
+pub const PLATFORM_ADDITION_VIRTUAL_DISPLAY_REQUEST: &str = "support_virtual_display_request";
+pub const VIRTUAL_DISPLAY_ERROR_MSGTYPE: &str = "nook-nocancel-hasclose-virtual-display-error";
+// The key in `platform_additions` of the capability map, see [`PeerCapabilities`].
+pub const PLATFORM_ADDITION_CAPABILITIES: &str = "capabilities";
+pub const UNSUPPORTED_CAPABILITY_MSGTYPE: &str = "nook-nocancel-hasclose";
+
+/// The features of the peer, computed once from the version, the platform and
+/// the `platform_additions` of the peer info.
+///
+/// The features which may be missing at runtime, e.g. on Wayland, are supported if the peer
+/// doesn't report them, as the old versions.
+/// The features gated by the version are all unsupported by default.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct PeerCapabilities {
+    pub is_wayland: bool,
+    pub can_change_resolution: bool,
+    pub can_inject_input: bool,
+    /// Empty if unknown.
+    pub capture_backend: String,
+    /// The old versions don't answer the request of the system information.
+    pub remote_sysinfo: bool,
+    /// The output volume of the peer can be controlled.
+    pub remote_volume: bool,
+    pub multi_ui_session: bool,
+    /// The peer reports `privacy_mode` in the features of the peer info.
+    pub privacy_mode: bool,
+    pub restart: bool,
+    /// The touch pan events are injected, only by the mobile peers.
+    pub touch_pan: bool,
+    /// The virtual displays can be added or removed on request.
+    pub virtual_display_control: bool,
+    /// The file transfer can check the digest of the existing files, to skip or resume them.
+    pub resume_transfer: bool,
+    /// The large files can be transferred in ranges, see `hbb_common::fs::multi_stream`.
+    pub multi_stream_transfer: bool,
+    /// The peer broadcasts the magic packets on its networks for the client, see `lan::relay_wol`.
+    pub wake_on_lan: bool,
+    /// The peer captures the region of a display viewed, see `flutter::viewport`.
+    pub viewport: bool,
+    /// The peer stamps the frames with the capture time on request, see `client::latency`.
+    pub frame_timestamps: bool,
+    /// The files can be renamed or removed recursively in place, see `hbb_common::fs::file_op`.
+    pub file_ops: bool,
+    /// Declared by the peer in `PeerInfo::features`, see [`PeerCapabilities::with_features`].
+    pub features: PeerFeatures,
+}
+
+impl Default for PeerCapabilities {
+    fn default() -> Self {
+        Self {
+            is_wayland: false,
+            can_change_resolution: true,
+            can_inject_input: true,
+            capture_backend: "".to_owned(),
+            remote_sysinfo: false,
+            remote_volume: false,
+            multi_ui_session: false,
+            privacy_mode: false,
+            restart: false,
+            touch_pan: false,
+            virtual_display_control: false,
+            resume_transfer: false,
+            multi_stream_transfer: false,
+            wake_on_lan: false,
+            viewport: false,
+            frame_timestamps: false,
+            file_ops: false,
+            features: Default::default(),
+        }
+    }
+}
+
+impl PeerCapabilities {
+    pub fn new(
+        version: i64,
+        platform: &str,
+        additions: &serde_json::Map<String, serde_json::Value>,
+    ) -> Self {
+        let at_least = |min: &str| version >= hbb_common::get_version_number(min);
+        let caps = Self::from_platform_additions(additions);
+        Self {
+            multi_ui_session: is_support_multi_ui_session_num(version),
+            privacy_mode: at_least(MIN_VER_PRIVACY_MODE_FEATURE),
+            restart: at_least(MIN_VER_RESTART),
+            touch_pan: platform == PLATFORM_ANDROID && at_least(MIN_VER_TOUCH_PAN),
+            virtual_display_control: is_support_virtual_display_request_num(version)
+                && additions
+                    .get(PLATFORM_ADDITION_VIRTUAL_DISPLAY_REQUEST)
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false),
+            resume_transfer: hbb_common::fs::can_enable_overwrite_detection(version),
+            viewport: at_least(MIN_VER_VIEWPORT) && caps.viewport,
+            ..caps
+        }
+    }
+
+    /// Set the features declared in the peer info, `features` is `None` if not declared.
+    pub fn with_features(mut self, features: Option<&Features>) -> Self {
+        self.features = PeerFeatures::new(features, self.privacy_mode);
+        self
+    }
+
+    /// The capabilities reported in `platform_additions` only, the ones gated by the version
+    /// are left unsupported.
+    pub fn from_platform_additions(additions: &serde_json::Map<String, serde_json::Value>) -> Self {
+        let mut caps = Self::default();
+        // The old versions only report `is_wayland`, the resolution can't be changed on Wayland.
+        if let Some(is_wayland) = additions.get("is_wayland").and_then(|v| v.as_bool()) {
+            caps.is_wayland = is_wayland;
+            caps.can_change_resolution = !is_wayland;
+        }
+        let map = match additions.get(PLATFORM_ADDITION_CAPABILITIES) {
+            Some(serde_json::Value::Object(map)) => map,
+            _ => return caps,
+        };
+        let get_bool = |key: &str| map.get(key).and_then(|v| v.as_bool());
+        if let Some(v) = get_bool("is_wayland") {
+            caps.is_wayland = v;
+        }
+        if let Some(v) = get_bool("can_change_resolution") {
+            caps.can_change_resolution = v;
+        }
+        if let Some(v) = get_bool("can_inject_input") {
+            caps.can_inject_input = v;
+        }
+        if let Some(v) = get_bool("remote_sysinfo") {
+            caps.remote_sysinfo = v;
+        }
+        if let Some(v) = get_bool("remote_volume") {
+            caps.remote_volume = v;
+        }
+        if let Some(v) = get_bool("multi_stream_transfer") {
+            caps.multi_stream_transfer = v;
+        }
+        if let Some(v) = get_bool("wake_on_lan") {
+            caps.wake_on_lan = v;
+        }
+        if let Some(v) = get_bool("viewport") {
+            caps.viewport = v;
+        }
+        if let Some(v) = get_bool("frame_timestamps") {
+            caps.frame_timestamps = v;
+        }
+        if let Some(v) = get_bool("file_ops") {
+            caps.file_ops = v;
+        }
+        if let Some(v) = map.get("capture_backend").and_then(|v| v.as_str()) {
+            caps.capture_backend = v.to_owned();
+        }
+        caps
+    }
+}
+
+/// The features declared by the peer in `PeerInfo::features`, serialized into the peer info
+/// pushed to the ui, where the flags are 1 or 0.
+///
+/// The fields unknown to this version, declared by the newer peers, are kept in `extra` by their
+/// field numbers, e.g. "field_2", so they pass through untouched when the peer info is emitted
+/// again.
+#[derive(Debug, Clone, Default, PartialEq, serde_derive::Serialize)]
+pub struct PeerFeatures {
+    #[serde(serialize_with = "serialize_flag")]
+    pub privacy_mode: bool,
+    #[serde(flatten)]
+    pub extra: std::collections::BTreeMap<String, serde_json::Value>,
+}
+
+fn serialize_flag<S: serde::Serializer>(flag: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i32(*flag as i32)
+}
+
+impl PeerFeatures {
+    /// `privacy_mode_capable` is [`PeerCapabilities::privacy_mode`], see
+    /// [`declared_privacy_mode`].
+    pub fn new(features: Option<&Features>, privacy_mode_capable: bool) -> Self {
+        let Some(features) = features else {
+            return Self::default();
+        };
+        let extra = features
+            .special_fields
+            .unknown_fields()
+            .iter()
+            .map(|(number, value)| {
+                use hbb_common::protobuf::UnknownValueRef;
+                let value = match value {
+                    UnknownValueRef::Fixed32(v) => serde_json::Value::from(v),
+                    UnknownValueRef::Fixed64(v) => serde_json::Value::from(v),
+                    UnknownValueRef::Varint(v) => serde_json::Value::from(v),
+                    UnknownValueRef::LengthDelimited(v) => serde_json::Value::from(v.to_vec()),
+                };
+                (format!("field_{}", number), value)
+            })
+            .collect();
+        Self {
+            privacy_mode: declared_privacy_mode(features.privacy_mode, privacy_mode_capable),
+            extra,
+        }
+    }
+}
+
+/// Whether the privacy mode declared by the peer is usable.
+///
+/// The peers before 1.2.0 (e.g. 1.1.9) declare `privacy_mode` though they can't handle the
+/// privacy mode requests of the newer clients, so it's off for them, whatever they declare.
+#[inline]
+pub fn declared_privacy_mode(declared: bool, privacy_mode_capable: bool) -> bool {
+    declared && privacy_mode_capable
+}
+
+/// Merge the `platform_additions` json sent by the peer into `additions`, as the flutter side.
+/// The capability map is merged by the keys, so the partial updates don't wipe it.
+/// The virtual displays are only sent if there are any, so they are removed if missing.
+pub fn merge_platform_additions(
+    additions: &mut serde_json::Map<String, serde_json::Value>,
+    data: &str,
+) {
+    if data.is_empty() {
+        additions.remove("virtual_displays");
+        return;
+    }
+    let update: serde_json::Map<String, serde_json::Value> = match serde_json::from_str(data) {
+        Ok(update) => update,
+        Err(_) => return,
+    };
+    if !update.contains_key("virtual_displays") {
+        additions.remove("virtual_displays");
+    }
+    for (k, v) in update {
+        if k == PLATFORM_ADDITION_CAPABILITIES {
+            if let (Some(serde_json::Value::Object(old)), serde_json::Value::Object(new)) =
+                (additions.get_mut(&k), &v)
+            {
+                old.extend(new.clone());
+                continue;
+            }
+        }
+        additions.insert(k, v);
+    }
+}
 
 pub mod input {
     pub const MOUSE_TYPE_MOVE: i32 = 0;
@@ -194,6 +443,33 @@ lazy_static::lazy_static! {
     static ref IS_SERVER: bool = std::env::args().nth(1) == Some("--server".to_owned());
     // Is server logic running. The server code can invoked to run by the main process if --server is not running.
     static ref SERVER_RUNNING: Arc<RwLock<bool>> = Default::default();
+    // The startup failures to show in the ui, see `add_startup_diagnostic`.
+    static ref STARTUP_DIAGNOSTICS: Mutex<Vec<StartupDiagnostic>> = Default::default();
+}
+
+const MAX_STARTUP_DIAGNOSTICS: usize = 32;
+
+// The peer sessions closed by a panic of their io loop, see `add_session_crash`.
+static SESSION_CRASHES: AtomicU32 = AtomicU32::new(0);
+
+/// A categorized startup failure, `action` is the translation key of the suggested action.
+#[derive(Debug, Clone)]
+pub struct StartupDiagnostic {
+    pub stage: &'static str,
+    pub code: &'static str,
+    pub message: String,
+    pub action: &'static str,
+}
+
+impl StartupDiagnostic {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "stage": self.stage,
+            "code": self.code,
+            "message": self.message,
+            "action": self.action,
+        })
+    }
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -241,6 +517,11 @@ pub fn is_support_multi_ui_session_num(ver: i64) -> bool {
     ver >= hbb_common::get_version_number(MIN_VER_MULTI_UI_SESSION)
 }
 
+#[inline]
+pub fn is_support_virtual_display_request_num(ver: i64) -> bool {
+    ver >= hbb_common::get_version_number(MIN_VER_VIRTUAL_DISPLAY_REQUEST)
+}
+
 // is server process, with "--server" args
 #[inline]
 pub fn is_server() -> bool {
@@ -253,6 +534,61 @@ pub fn is_server_running() -> bool {
     *SERVER_RUNNING.read().unwrap()
 }
 
+/// Record a startup failure for the ui.
+/// It does not depend on the log crate, the logger may not be initialized yet.
+pub fn add_startup_diagnostic(
+    stage: &'static str,
+    code: &'static str,
+    message: String,
+    action: &'static str,
+) {
+    let diagnostic = StartupDiagnostic {
+        stage,
+        code,
+        message,
+        action,
+    };
+    {
+        let mut lock = STARTUP_DIAGNOSTICS.lock().unwrap();
+        if lock.len() >= MAX_STARTUP_DIAGNOSTICS {
+            return;
+        }
+        lock.push(diagnostic.clone());
+    }
+    // The ui may be started already, `None` if not and it gets all of them on registering.
+    #[cfg(feature = "flutter")]
+    {
+        let event = serde_json::json!({
+            "name": "startup_diagnostics",
+            "diagnostics": serde_json::json!([diagnostic.to_json()]).to_string(),
+        });
+        let _ = crate::flutter::push_global_event(crate::flutter::APP_TYPE_MAIN, event.to_string());
+    }
+}
+
+/// The startup failures as a json array of `{stage, code, message, action}`.
+pub fn get_startup_diagnostics() -> String {
+    let lock = STARTUP_DIAGNOSTICS.lock().unwrap();
+    serde_json::Value::Array(lock.iter().map(|d| d.to_json()).collect()).to_string()
+}
+
+#[inline]
+pub fn has_startup_diagnostics() -> bool {
+    !STARTUP_DIAGNOSTICS.lock().unwrap().is_empty()
+}
+
+/// Count a peer session closed by a panic, returns the count since the start.
+#[inline]
+pub fn add_session_crash() -> u32 {
+    SESSION_CRASHES.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// The peer sessions closed by a panic since the start, reported with the startup diagnostics.
+#[inline]
+pub fn get_session_crashes() -> u32 {
+    SESSION_CRASHES.load(Ordering::SeqCst)
+}
+
 #[inline]
 pub fn valid_for_numlock(evt: &KeyEvent) -> bool {
     if let Some(key_event::Union::ControlKey(ck)) = evt.union {
@@ -945,6 +1281,24 @@ pub fn is_modifier(evt: &KeyEvent) -> bool {
     }
 }
 
+/// If the message injects input into the peer, which is rejected in the view-only sessions.
+pub fn is_input_message(msg: &Message) -> bool {
+    match &msg.union {
+        Some(message::Union::MouseEvent(_))
+        | Some(message::Union::KeyEvent(_))
+        | Some(message::Union::PointerDeviceEvent(_))
+        | Some(message::Union::Clipboard(_))
+        | Some(message::Union::Cliprdr(_)) => true,
+        Some(message::Union::Misc(misc)) => match &misc.union {
+            Some(misc::Union::Option(o)) => {
+                o.block_input.enum_value() != Ok(option_message::BoolOption::NotSet)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
 pub fn check_software_update() {
     std::thread::spawn(move || allow_err!(check_software_update_()));
 }
@@ -1241,3 +1595,155 @@ pub fn check_process(arg: &str, same_uid: bool) -> bool {
     }
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &str) -> PeerCapabilities {
+        let mut additions = serde_json::Map::new();
+        merge_platform_additions(&mut additions, data);
+        PeerCapabilities::from_platform_additions(&additions)
+    }
+
+    #[test]
+    fn test_peer_capabilities() {
+        // Old versions send nothing.
+        assert_eq!(parse(""), PeerCapabilities::default());
+        assert_eq!(parse("{}"), PeerCapabilities::default());
+
+        let caps = parse(r#"{"is_wayland": true}"#);
+        assert!(caps.is_wayland && !caps.can_change_resolution && caps.can_inject_input);
+
+        let caps = parse(r#"{"capabilities": {"can_inject_input": false}}"#);
+        assert!(!caps.can_inject_input && caps.can_change_resolution);
+        assert_eq!(caps.capture_backend, "");
+        assert!(!caps.remote_sysinfo);
+        assert!(parse(r#"{"capabilities": {"remote_sysinfo": true}}"#).remote_sysinfo);
+        assert!(!caps.remote_volume);
+        assert!(parse(r#"{"capabilities": {"remote_volume": true}}"#).remote_volume);
+        assert!(!caps.multi_stream_transfer);
+        assert!(
+            parse(r#"{"capabilities": {"multi_stream_transfer": true}}"#).multi_stream_transfer
+        );
+        assert!(!caps.wake_on_lan);
+        assert!(parse(r#"{"capabilities": {"wake_on_lan": true}}"#).wake_on_lan);
+        assert!(parse(r#"{"capabilities": {"frame_timestamps": true}}"#).frame_timestamps);
+        assert!(!caps.file_ops);
+        assert!(parse(r#"{"capabilities": {"file_ops": true}}"#).file_ops);
+
+        // Gated on the version too.
+        let mut additions = serde_json::Map::new();
+        merge_platform_additions(&mut additions, r#"{"capabilities": {"viewport": true}}"#);
+        let version = |v: &str| hbb_common::get_version_number(v);
+        assert!(PeerCapabilities::new(version("1.2.4"), "Windows", &additions).viewport);
+        assert!(!PeerCapabilities::new(version("1.2.3"), "Windows", &additions).viewport);
+        assert!(!PeerCapabilities::new(version("1.2.4"), "Windows", &Default::default()).viewport);
+
+        let caps = parse(
+            r#"{"is_wayland": true, "capabilities": {"is_wayland": true,
+            "can_change_resolution": true, "capture_backend": "pipewire"}}"#,
+        );
+        assert!(caps.can_change_resolution);
+        assert_eq!(caps.capture_backend, "pipewire");
+    }
+
+    #[test]
+    fn test_peer_capabilities_of_version() {
+        let caps = |version: &str, platform: &str, additions: &str| {
+            let mut map = serde_json::Map::new();
+            merge_platform_additions(&mut map, additions);
+            let caps = PeerCapabilities::new(get_version_number(version), platform, &map);
+            [
+                caps.multi_ui_session,
+                caps.privacy_mode,
+                caps.restart,
+                caps.touch_pan,
+                caps.virtual_display_control,
+                caps.resume_transfer,
+            ]
+        };
+        let vd = r#"{"support_virtual_display_request": true}"#;
+        let cases = [
+            // version, platform, additions, [multi_ui_session, privacy_mode, restart, touch_pan,
+            // virtual_display_control, resume_transfer]
+            ("", PLATFORM_WINDOWS, "", [false; 6]),
+            ("1.1.9", PLATFORM_WINDOWS, vd, [false; 6]),
+            ("1.1.10", PLATFORM_ANDROID, "", [false, false, true, false, false, true]),
+            ("1.2.0", PLATFORM_WINDOWS, vd, [false, true, true, false, false, true]),
+            ("1.2.4", PLATFORM_WINDOWS, "", [true, true, true, false, false, true]),
+            ("1.2.4", PLATFORM_WINDOWS, vd, [true, true, true, false, true, true]),
+            ("1.2.4", PLATFORM_ANDROID, "", [true, true, true, true, false, true]),
+            ("1.3.0", PLATFORM_LINUX, vd, [true, true, true, false, true, true]),
+        ];
+        for (version, platform, additions, expected) in cases {
+            assert_eq!(
+                caps(version, platform, additions),
+                expected,
+                "{} {} {}",
+                version,
+                platform,
+                additions
+            );
+        }
+
+        // The runtime capabilities are still parsed from the additions.
+        let mut map = serde_json::Map::new();
+        merge_platform_additions(&mut map, r#"{"is_wayland": true}"#);
+        let caps = PeerCapabilities::new(get_version_number("1.2.4"), PLATFORM_LINUX, &map);
+        assert!(caps.is_wayland && !caps.can_change_resolution && caps.multi_ui_session);
+    }
+
+    #[test]
+    fn test_peer_features() {
+        // The quirk of 1.1.9.
+        assert!(!declared_privacy_mode(true, false));
+        assert!(!declared_privacy_mode(false, true));
+        assert!(declared_privacy_mode(true, true));
+
+        assert_eq!(PeerFeatures::new(None, true), PeerFeatures::default());
+        let mut features = Features {
+            privacy_mode: true,
+            ..Default::default()
+        };
+        let privacy_mode = |version: &str, features: &Features| {
+            let version = get_version_number(version);
+            PeerCapabilities::new(version, PLATFORM_WINDOWS, &Default::default())
+                .with_features(Some(features))
+                .features
+                .privacy_mode
+        };
+        assert!(!privacy_mode("1.1.9", &features));
+        assert!(privacy_mode("1.2.4", &features));
+
+        // The unknown fields of the newer peers pass through.
+        let unknown = features.special_fields.mut_unknown_fields();
+        unknown.add_varint(2, 1);
+        unknown.add_length_delimited(3, vec![1, 2]);
+        let json = serde_json::to_string(&PeerFeatures::new(Some(&features), true)).unwrap();
+        assert_eq!(json, r#"{"privacy_mode":1,"field_2":1,"field_3":[1,2]}"#);
+    }
+
+    #[test]
+    fn test_merge_platform_additions() {
+        let mut additions = serde_json::Map::new();
+        merge_platform_additions(
+            &mut additions,
+            r#"{"is_wayland": true, "capabilities": {"can_inject_input": false}}"#,
+        );
+        merge_platform_additions(&mut additions, r#"{"virtual_displays": [1]}"#);
+        merge_platform_additions(&mut additions, "invalid");
+        assert_eq!(additions["virtual_displays"], serde_json::json!([1]));
+
+        merge_platform_additions(&mut additions, r#"{"capabilities": {"capture_backend": "x"}}"#);
+        let caps = PeerCapabilities::from_platform_additions(&additions);
+        assert!(caps.is_wayland && !caps.can_inject_input);
+        assert_eq!(caps.capture_backend, "x");
+        assert!(!additions.contains_key("virtual_displays"));
+
+        merge_platform_additions(&mut additions, r#"{"virtual_displays": [2]}"#);
+        merge_platform_additions(&mut additions, "");
+        assert!(!additions.contains_key("virtual_displays"));
+        assert!(additions.contains_key(PLATFORM_ADDITION_CAPABILITIES));
+    }
+}
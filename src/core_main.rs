@@ -22,6 +22,24 @@ macro_rules! my_println{
     };
 }
 
+// Called before the logger is initialized, the failure is only kept in the startup diagnostics.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn check_config_dir() {
+    let dir = hbb_common::config::Config::path("");
+    let probe = dir.join(".write_probe");
+    let res = std::fs::create_dir_all(&dir)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    if let Err(e) = res {
+        crate::common::add_startup_diagnostic(
+            "config",
+            "config_dir_unwritable",
+            format!("{}: {}", dir.display(), e),
+            "startup_config_dir_tip",
+        );
+    }
+}
+
 #[inline]
 fn is_empty_uni_link(arg: &str) -> bool {
     if !arg.starts_with("rustdesk://") {
@@ -118,10 +136,18 @@ pub fn core_main() -> Option<Vec<String>> {
     }
     #[cfg(windows)]
     {
+        let is_elevated = crate::platform::is_elevated(None).unwrap_or_else(|e| {
+            crate::common::add_startup_diagnostic(
+                "elevation",
+                "elevation_check_failed",
+                e.to_string(),
+                "startup_elevation_tip",
+            );
+            false
+        });
         _is_quick_support |= !crate::platform::is_installed()
             && args.is_empty()
-            && (arg_exe.to_lowercase().contains("-qs-")
-                || (!click_setup && crate::platform::is_elevated(None).unwrap_or(false)));
+            && (arg_exe.to_lowercase().contains("-qs-") || (!click_setup && is_elevated));
         crate::portable_service::client::set_quick_support(_is_quick_support);
     }
     let mut log_name = "".to_owned();
@@ -131,6 +157,7 @@ pub fn core_main() -> Option<Vec<String>> {
             log_name = name;
         }
     }
+    check_config_dir();
     hbb_common::init_log(false, &log_name);
 
     // linux uni (url) go here.
@@ -148,6 +175,12 @@ pub fn core_main() -> Option<Vec<String>> {
     {
         use crate::portable_service::client;
         if let Err(e) = client::start_portable_service(client::StartPara::Direct) {
+            crate::common::add_startup_diagnostic(
+                "elevation",
+                "portable_service_failed",
+                e.to_string(),
+                "startup_portable_service_tip",
+            );
             log::error!("Failed to start portable service: {:?}", e);
         }
     }
@@ -426,6 +459,18 @@ pub fn core_main() -> Option<Vec<String>> {
             // call connection manager to establish connections
             // meanwhile, return true to call flutter window to show control panel
             crate::ui_interface::start_option_status_sync();
+        } else if args[0] == "--automation" {
+            crate::automation::start();
+            return None;
+        } else if args[0] == "--automation-request" {
+            if args.len() == 2 {
+                if let Err(err) = crate::automation::cli_request(&args[1]) {
+                    println!("{}", serde_json::json!({ "error": err.to_string() }));
+                }
+            } else {
+                println!("Usage: --automation-request <json>");
+            }
+            return None;
         } else if args[0] == "--cm-no-ui" {
             #[cfg(feature = "flutter")]
             #[cfg(not(any(target_os = "android", target_os = "ios", target_os = "windows")))]
@@ -1,7 +1,9 @@
 use crate::{
-    client::*,
+    client::{direct_target::DirectTarget, *},
     flutter_ffi::{EventToUI, SessionID},
-    ui_session_interface::{io_loop, InvokeUiSession, Session},
+    ui_session_interface::{
+        io_loop, ConnectionState, HookAction, InvokeUiSession, JobMeta, Session,
+    },
 };
 use flutter_rust_bridge::StreamSink;
 use hbb_common::{
@@ -11,7 +13,7 @@ use hbb_common::{
 #[cfg(feature = "flutter_texture_render")]
 use hbb_common::{
     dlopen::{
-        symbor::{Library, Symbol},
+        symbor::Library,
         Error as LibError,
     },
     libc::c_void,
@@ -19,13 +21,38 @@ use hbb_common::{
 use serde_json::json;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     ffi::CString,
     os::raw::{c_char, c_int},
     str::FromStr,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
+pub(super) mod async_tasks;
+pub(super) mod capture_reduction;
+pub mod composite;
+pub(super) mod config_bundle;
+pub(super) mod downscale;
+pub(super) mod peer_data;
+pub(super) mod profiles;
+pub(super) mod session_activity;
+pub(super) mod session_meta;
+pub(crate) mod session_restore;
+#[cfg(any(target_os = "android", test))]
+pub(super) mod service_calls;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub(super) mod thumbnail;
+pub(super) mod video_watchdog;
+pub(super) mod viewport;
+#[cfg(not(target_os = "ios"))]
+pub(super) mod wol_relay;
+#[cfg(feature = "flutter_texture_render")]
+use composite::{CompositeCanvas, COMPOSITE_DISPLAY};
+
 /// tag "main" for [Desktop Main Page] and [Mobile (Client and Server)] (the mobile don't need multiple windows, only one global event stream is needed)
 /// tag "cm" only for [Desktop CM Page]
 pub(crate) const APP_TYPE_MAIN: &str = "main";
@@ -33,6 +60,10 @@ pub(crate) const APP_TYPE_MAIN: &str = "main";
 pub(crate) const APP_TYPE_CM: &str = "cm";
 #[cfg(any(target_os = "android", target_os = "ios"))]
 pub(crate) const APP_TYPE_CM: &str = "main";
+/// tag "log" for the live log view, kept apart so that the other channels are not flooded
+pub(crate) const APP_TYPE_LOG: &str = "log";
+const MAX_LOG_RECORDS_PER_SEC: usize = 50;
+const LOG_RECORD_QUEUE_SIZE: usize = 256;
 
 // Do not remove the following constants.
 // Uncomment them when they are used.
@@ -44,7 +75,82 @@ pub type FlutterSession = Arc<Session<FlutterHandler>>;
 
 lazy_static::lazy_static! {
     pub(crate) static ref CUR_SESSION_ID: RwLock<SessionID> = Default::default();
-    static ref GLOBAL_EVENT_STREAM: RwLock<HashMap<String, StreamSink<String>>> = Default::default(); // rust to dart event channel
+    static ref GLOBAL_EVENT_STREAM: RwLock<HashMap<String, Box<dyn EventSink<String>>>> = Default::default(); // rust to dart event channel
+    // The last "peer_card_updated" event of each peer and when it is pushed.
+    static ref PEER_CARD_UPDATES: std::sync::Mutex<HashMap<String, (Instant, String)>> = Default::default();
+}
+
+/// The channel of the events to the ui, implemented by the flutter [`StreamSink`] and by the
+/// collectors of the tests.
+pub trait EventSink<T>: Send + Sync {
+    /// Returns false if the event is not delivered.
+    fn add(&self, event: T) -> bool;
+}
+
+impl EventSink<String> for StreamSink<String> {
+    #[inline]
+    fn add(&self, event: String) -> bool {
+        StreamSink::add(self, event)
+    }
+}
+
+impl EventSink<EventToUI> for StreamSink<EventToUI> {
+    #[inline]
+    fn add(&self, event: EventToUI) -> bool {
+        StreamSink::add(self, event)
+    }
+}
+
+impl<T, S: EventSink<T>> EventSink<T> for Arc<S> {
+    #[inline]
+    fn add(&self, event: T) -> bool {
+        (**self).add(event)
+    }
+}
+
+/// Keep the events pushed, for the tests to assert on.
+#[cfg(test)]
+pub(crate) struct EventCollector<T> {
+    events: std::sync::Mutex<Vec<T>>,
+}
+
+// Not derived, which requires `T: Default`.
+#[cfg(test)]
+impl<T> Default for EventCollector<T> {
+    fn default() -> Self {
+        Self {
+            events: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl<T: Send> EventSink<T> for EventCollector<T> {
+    fn add(&self, event: T) -> bool {
+        self.events.lock().unwrap().push(event);
+        true
+    }
+}
+
+#[cfg(test)]
+impl<T> EventCollector<T> {
+    pub(crate) fn take(&self) -> Vec<T> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+/// The json payloads of the [`EventToUI::Event`]s collected, parsed for asserting.
+#[cfg(test)]
+impl EventCollector<EventToUI> {
+    pub(crate) fn take_events(&self) -> Vec<serde_json::Value> {
+        self.take()
+            .into_iter()
+            .filter_map(|e| match e {
+                EventToUI::Event(s) => serde_json::from_str(&s).ok(),
+                EventToUI::Rgba(_) => None,
+            })
+            .collect()
+    }
 }
 
 #[cfg(all(target_os = "windows", feature = "flutter_texture_render"))]
@@ -98,26 +204,49 @@ pub extern "C" fn rustdesk_core_main_args(args_len: *mut c_int) -> *mut *mut c_c
     return std::ptr::null_mut() as _;
 }
 
-// https://gist.github.com/iskakaushik/1c5b8aa75c77479c33c4320913eebef6
+// The number of the arguments escaped by the last `rust_args_to_c_args`.
+#[cfg(any(windows, test))]
+static C_ARGS_ESCAPED: AtomicI32 = AtomicI32::new(0);
+
+/// The number of the arguments returned by the last [`rustdesk_core_main_args`] whose interior
+/// NULs are escaped as "%00", so that the caller can log it.
 #[cfg(windows)]
+#[no_mangle]
+pub extern "C" fn rustdesk_core_main_args_escaped() -> c_int {
+    C_ARGS_ESCAPED.load(Ordering::SeqCst)
+}
+
+// https://gist.github.com/iskakaushik/1c5b8aa75c77479c33c4320913eebef6
+#[cfg(any(windows, test))]
 fn rust_args_to_c_args(args: Vec<String>, outlen: *mut c_int) -> *mut *mut c_char {
     let mut v = vec![];
+    let mut escaped = 0;
 
-    // Let's fill a vector with null-terminated strings
+    // Let's fill a vector with null-terminated strings.
+    // An interior NUL is escaped instead of dropping all the arguments.
     for s in args {
         match CString::new(s) {
             Ok(s) => v.push(s),
-            Err(_) => return std::ptr::null_mut() as _,
+            Err(e) => {
+                escaped += 1;
+                let s = String::from_utf8_lossy(&e.into_vec()).replace('\0', "%00");
+                v.push(CString::new(s).unwrap_or_default());
+            }
         }
     }
+    if escaped > 0 {
+        log::warn!("{} of {} arguments contain NUL, escaped", escaped, v.len());
+    }
+    C_ARGS_ESCAPED.store(escaped, Ordering::SeqCst);
 
     // Turning each null-terminated string into a pointer.
     // `into_raw` takes ownershop, gives us the pointer and does NOT drop the data.
-    let mut out = v.into_iter().map(|s| s.into_raw()).collect::<Vec<_>>();
-
-    // Make sure we're not wasting space.
-    out.shrink_to_fit();
-    debug_assert!(out.len() == out.capacity());
+    // The boxed slice makes sure capacity == length, which `free_c_args` relies on.
+    let mut out = v
+        .into_iter()
+        .map(|s| s.into_raw())
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
 
     // Get the pointer to our vector.
     let len = out.len();
@@ -148,11 +277,137 @@ pub unsafe extern "C" fn free_c_args(ptr: *mut *mut c_char, len: c_int) {
     // Afterwards the vector will be dropped and thus freed.
 }
 
+/// The open sessions as a json array, for the external tools. See [`sessions::list_json`].
+/// Safe to call from any thread, the string must be freed by [`rustdesk_free_string`].
+#[no_mangle]
+pub extern "C" fn rustdesk_list_sessions() -> *mut c_char {
+    // No NUL in the json, the control chars are escaped.
+    CString::new(sessions::list_json())
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// Free a string returned by the core, e.g. [`rustdesk_list_sessions`]. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rustdesk_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The cursor must stay on another display for this long before the view follows it.
+/// This avoids thrashing `capture_displays` when the cursor moves back and forth across an edge.
+const FOLLOW_CURSOR_DWELL: Duration = Duration::from_millis(500);
+/// Identical `capture_displays` requests within this interval are sent only once,
+/// e.g. when multiple ui sessions are closed at once.
+const CAPTURE_DISPLAYS_DEDUP_INTERVAL: Duration = Duration::from_millis(300);
+const DEFAULT_CURSOR_CACHE_CAPACITY: usize = 64;
+const DEFAULT_MSGBOX_DEDUP_WINDOW_MS: u64 = 2000;
+const PRESENTER_HIGHLIGHT_INTERVAL: Duration = Duration::from_millis(50);
+const DEFAULT_PRESENTER_HIGHLIGHT_COLOR: &str = "#FFEB3B";
+const DEFAULT_PRESENTER_HIGHLIGHT_SIZE: i32 = 48;
+/// The events pushed by plugins are named "plugin|<key>|<name>", see [`push_plugin_event`].
+pub const PLUGIN_EVENT_PREFIX: &str = "plugin|";
+
+#[derive(Default)]
+struct FollowCursorState {
+    enabled: bool,
+    // The display shown by the ui session. `None` if multiple displays are shown.
+    display: Option<usize>,
+    // If `display` is set by the ui, not seeded by the login.
+    chosen: bool,
+    // The display the cursor is currently on, and the time it entered that display.
+    pending: Option<(usize, Instant)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FollowCursorAction {
+    None,
+    // The cursor entered another display, checked again after the dwell.
+    Arm,
+    Switch,
+}
+
+impl FollowCursorState {
+    #[inline]
+    fn set_display(&mut self, displays: &[i32]) {
+        self.display = if displays.len() == 1 {
+            Some(displays[0] as usize)
+        } else {
+            None
+        };
+        self.chosen = true;
+        self.pending = None;
+    }
+
+    // The ui shows the current display of the peer after login, until it chooses the displays.
+    fn seed_display(&mut self, current: usize) {
+        if !self.chosen {
+            self.display = Some(current);
+            self.pending = None;
+        }
+    }
+
+    fn on_cursor_display(&mut self, display: usize, now: Instant) -> FollowCursorAction {
+        if !self.enabled {
+            return FollowCursorAction::None;
+        }
+        let Some(current) = self.display else {
+            return FollowCursorAction::None;
+        };
+        if current == display {
+            self.pending = None;
+            return FollowCursorAction::None;
+        }
+        match self.pending {
+            Some((d, _)) if d == display => {
+                if self.on_deadline(now).is_some() {
+                    FollowCursorAction::Switch
+                } else {
+                    FollowCursorAction::None
+                }
+            }
+            _ => {
+                self.pending = Some((display, now));
+                FollowCursorAction::Arm
+            }
+        }
+    }
+
+    // Return the display to switch to if the cursor has stayed on it for the dwell,
+    // checked by the cursor positions and the timer armed.
+    fn on_deadline(&mut self, now: Instant) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        match self.pending {
+            Some((display, t)) if now.saturating_duration_since(t) >= FOLLOW_CURSOR_DWELL => {
+                self.pending = None;
+                Some(display)
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default)]
 struct SessionHandler {
-    event_stream: Option<StreamSink<EventToUI>>,
+    event_stream: Option<Arc<dyn EventSink<EventToUI>>>,
+    follow_cursor: FollowCursorState,
+    // The scale factor of the local display showing the ui session, 0 if unknown.
+    cursor_scale: f64,
+    // Send the unscaled cursor images, the ui scales them itself.
+    raw_cursor: bool,
+    // The events not pushed to this ui session.
+    filtered_events: HashSet<String>,
+    unread: session_activity::Unread,
+    // The order of the last focus, the larger the later, 0 if never focused.
+    focus_seq: u64,
+    // Attached to the peer session created by another ui session, which connects it.
+    attached: bool,
+    // Atomic, so that the rendering hot path only takes the read lock of the handlers.
     #[cfg(feature = "flutter_texture_render")]
-    notify_rendered: bool,
+    notify_rendered: AtomicBool,
     #[cfg(feature = "flutter_texture_render")]
     renderer: VideoRenderer,
 }
@@ -163,28 +418,385 @@ pub struct FlutterHandler {
     // ui session id -> display handler data
     session_handlers: Arc<RwLock<HashMap<SessionID, SessionHandler>>>,
     peer_info: Arc<RwLock<PeerInfo>>,
-    #[cfg(feature = "plugin_framework")]
+    // Computed in `set_peer_info`, updated with the platform additions.
+    peer_capabilities: Arc<RwLock<crate::common::PeerCapabilities>>,
+    // The last display set sent by `capture_displays`, and the time it was sent.
+    last_capture_displays: Arc<RwLock<Option<(Vec<i32>, Instant)>>>,
+    // The secure and direct flags of the connection, and when it was ready.
+    connection_ready: Arc<RwLock<Option<(bool, bool, Instant)>>>,
+    cursor_cache: Arc<RwLock<CursorCache>>,
+    presenter: Arc<RwLock<PresenterState>>,
+    msgbox_throttle: Arc<std::sync::Mutex<MsgboxThrottle>>,
+    // event name -> the latest event, pushed again to the ui sessions attaching later.
+    stateful_events: Arc<RwLock<HashMap<String, String>>>,
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    hooks: Arc<RwLock<HashMap<String, SessionHook>>>,
+    hooks: Arc<RwLock<SessionHooks>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    thumbnail: Arc<thumbnail::ThumbnailCapture>,
+    video_watchdog: Arc<video_watchdog::VideoWatchdog>,
+    activity: Arc<std::sync::Mutex<session_activity::SessionActivity>>,
+    // Shared by the ui sessions of the peer, see `session_meta`.
+    meta: Arc<std::sync::Mutex<session_meta::SessionMeta>>,
+    // The last "peer_data_truncated" of each field.
+    peer_data_reports: Arc<std::sync::Mutex<peer_data::Reports>>,
+    // The regions of the displays requested and captured, see `viewport`.
+    viewports: Arc<std::sync::Mutex<viewport::Viewports>>,
+    downscale: Arc<std::sync::Mutex<downscale::Downscaler>>,
+    // The frame downscaled, only used by the video thread.
+    downscaled: Arc<std::sync::Mutex<Option<scrap::ImageRgb>>>,
+    capture_reduction: Arc<std::sync::Mutex<capture_reduction::CaptureReduction>>,
 }
 
+// The number of the rgba buffers of a display.
+// The memory is bounded by the ring size times the frame size.
 #[cfg(not(feature = "flutter_texture_render"))]
-#[derive(Default, Clone)]
-struct RgbaData {
-    // SAFETY: [rgba] is guarded by [rgba_valid], and it's safe to reach [rgba] with `rgba_valid == true`.
-    // We must check the `rgba_valid` before reading [rgba].
-    data: Vec<u8>,
-    valid: bool,
+const RGBA_RING_SIZE: usize = 3;
+
+/// The frames of a display, written by the video thread and read by the ui.
+///
+/// The video thread always writes into a free buffer, recycling the oldest unconsumed frame,
+/// so a slow ui only drops the frames it can not catch up with.
+#[cfg(not(feature = "flutter_texture_render"))]
+#[derive(Default)]
+struct RgbaRing {
+    buffers: Vec<Vec<u8>>,
+    // The newest complete frame, not fetched by the ui yet.
+    ready: Option<usize>,
+    // SAFETY: The frame being read by the ui is never written until `release()`.
+    reading: Option<usize>,
+    // The number of the frames recycled before fetched by the ui.
+    dropped: u64,
+    // The ui has been notified and has not fetched since.
+    notified: bool,
+}
+
+#[cfg(not(feature = "flutter_texture_render"))]
+impl RgbaRing {
+    /// Swap the frame into a free buffer, the buffer swapped out is returned to the video thread
+    /// for reusing.
+    ///
+    /// Returns true if the ui should be notified, i.e. the ui has not been notified since its
+    /// last fetch.
+    fn push(&mut self, raw: &mut Vec<u8>) -> bool {
+        let Some(index) =
+            (0..RGBA_RING_SIZE).find(|i| Some(*i) != self.reading && Some(*i) != self.ready)
+        else {
+            // Unreachable, the ring size is larger than 2.
+            self.dropped += 1;
+            return false;
+        };
+        if self.buffers.len() <= index {
+            self.buffers.resize_with(index + 1, Vec::new);
+        }
+        std::mem::swap(raw, &mut self.buffers[index]);
+        if self.ready.replace(index).is_some() {
+            // The older frame is recycled.
+            self.dropped += 1;
+        }
+        !std::mem::replace(&mut self.notified, true)
+    }
+
+    /// The frame read by the ui, the newest complete frame is taken if the ui is not reading one.
+    ///
+    /// The notification is consumed even if the ui is still reading the older frame,
+    /// `release()` notifies again then.
+    fn acquire(&mut self) -> Option<&Vec<u8>> {
+        self.notified = false;
+        if self.reading.is_none() {
+            self.reading = self.ready.take();
+        }
+        self.reading.map(|i| &self.buffers[i])
+    }
+
+    /// Returns true if the ui should be notified of the frame left waiting.
+    fn release(&mut self) -> bool {
+        self.reading = None;
+        if self.ready.is_some() && !self.notified {
+            self.notified = true;
+            return true;
+        }
+        false
+    }
 }
 
 #[cfg(not(feature = "flutter_texture_render"))]
 #[derive(Default, Clone)]
 pub struct FlutterHandler {
     session_handlers: Arc<RwLock<HashMap<SessionID, SessionHandler>>>,
-    display_rgbas: Arc<RwLock<HashMap<usize, RgbaData>>>,
+    display_rgbas: Arc<RwLock<HashMap<usize, RgbaRing>>>,
     peer_info: Arc<RwLock<PeerInfo>>,
+    peer_capabilities: Arc<RwLock<crate::common::PeerCapabilities>>,
+    last_capture_displays: Arc<RwLock<Option<(Vec<i32>, Instant)>>>,
+    // The secure and direct flags of the connection, and when it was ready.
+    connection_ready: Arc<RwLock<Option<(bool, bool, Instant)>>>,
+    cursor_cache: Arc<RwLock<CursorCache>>,
+    presenter: Arc<RwLock<PresenterState>>,
+    msgbox_throttle: Arc<std::sync::Mutex<MsgboxThrottle>>,
+    // event name -> the latest event, pushed again to the ui sessions attaching later.
+    stateful_events: Arc<RwLock<HashMap<String, String>>>,
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    hooks: Arc<RwLock<HashMap<String, SessionHook>>>,
+    hooks: Arc<RwLock<SessionHooks>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    thumbnail: Arc<thumbnail::ThumbnailCapture>,
+    video_watchdog: Arc<video_watchdog::VideoWatchdog>,
+    activity: Arc<std::sync::Mutex<session_activity::SessionActivity>>,
+    // Shared by the ui sessions of the peer, see `session_meta`.
+    meta: Arc<std::sync::Mutex<session_meta::SessionMeta>>,
+    // The last "peer_data_truncated" of each field.
+    peer_data_reports: Arc<std::sync::Mutex<peer_data::Reports>>,
+    // The regions of the displays requested and captured, see `viewport`.
+    viewports: Arc<std::sync::Mutex<viewport::Viewports>>,
+    downscale: Arc<std::sync::Mutex<downscale::Downscaler>>,
+    // The frame downscaled, only used by the video thread.
+    downscaled: Arc<std::sync::Mutex<Option<scrap::ImageRgb>>>,
+    capture_reduction: Arc<std::sync::Mutex<capture_reduction::CaptureReduction>>,
+}
+
+/// Presenter mode, the ui draws a highlight around the remote cursor and ripples on clicks.
+/// The options are stored in the peer options "presenter-mode", "presenter-highlight-color"
+/// and "presenter-highlight-size".
+#[derive(Default)]
+struct PresenterState {
+    enabled: bool,
+    color: String,
+    size: i32,
+    last_highlight: Option<Instant>,
+}
+
+// The type, title and text of a msgbox.
+type MsgboxKey = (String, String, String);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct MsgboxBody {
+    link: String,
+    // The json map of the params, if the text is a lang key, see `msgbox_keyed`.
+    params: Option<String>,
+}
+
+/// The identical msgboxes in a short window are collapsed, e.g. of a flapping connection.
+/// The pushed ones carry the times of the msgbox, including the collapsed ones.
+#[derive(Default)]
+struct MsgboxThrottle {
+    // The (type, title, text) of the last msgbox pushed, and when.
+    last: Option<(MsgboxKey, Instant)>,
+    repeat: u32,
+    // The latest retry-capable msgbox collapsed, pushed when the window ends.
+    pending_retry: Option<MsgboxBody>,
+    // Increased on reset, the scheduled pushes of the old state are dropped.
+    epoch: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum MsgboxAction {
+    Push(u32),
+    Suppress,
+    // Push the pending one after the delay, see `MsgboxThrottle::take_pending`.
+    Schedule(Duration, u64),
+}
+
+impl MsgboxThrottle {
+    // The window is set by the local option "msgbox-dedup-window" in milliseconds, 0 to disable.
+    fn window() -> Duration {
+        let ms = LocalConfig::get_option("msgbox-dedup-window")
+            .parse()
+            .unwrap_or(DEFAULT_MSGBOX_DEDUP_WINDOW_MS);
+        Duration::from_millis(ms)
+    }
+
+    fn check(
+        &mut self,
+        key: MsgboxKey,
+        body: &MsgboxBody,
+        retry: bool,
+        window: Duration,
+        now: Instant,
+    ) -> MsgboxAction {
+        let elapsed = match &self.last {
+            Some((k, t)) if k == &key => now.saturating_duration_since(*t),
+            _ => {
+                self.reset();
+                self.last = Some((key, now));
+                self.repeat = 1;
+                return MsgboxAction::Push(1);
+            }
+        };
+        self.repeat += 1;
+        if elapsed >= window {
+            self.last = Some((key, now));
+            self.pending_retry = None;
+            return MsgboxAction::Push(self.repeat);
+        }
+        if !retry {
+            return MsgboxAction::Suppress;
+        }
+        match self.pending_retry.replace(body.clone()) {
+            Some(_) => MsgboxAction::Suppress,
+            None => MsgboxAction::Schedule(window - elapsed, self.epoch),
+        }
+    }
+
+    // The retry-capable msgbox collapsed, with its times, `None` if pushed or reset since.
+    fn take_pending(&mut self, epoch: u64, now: Instant) -> Option<(MsgboxKey, MsgboxBody, u32)> {
+        if epoch != self.epoch {
+            return None;
+        }
+        let body = self.pending_retry.take()?;
+        let (key, t) = self.last.as_mut()?;
+        *t = now;
+        Some((key.clone(), body, self.repeat))
+    }
+
+    fn reset(&mut self) {
+        *self = Self {
+            epoch: self.epoch + 1,
+            ..Default::default()
+        };
+    }
+}
+
+struct CachedCursor {
+    // The cursor data with decompressed colors.
+    data: CursorData,
+    // The serialized "cursor_data" event of the unscaled cursor.
+    payload: String,
+}
+
+/// LRU cache of the cursors, keyed by cursor id.
+#[derive(Default)]
+struct CursorCache {
+    entries: HashMap<u64, CachedCursor>,
+    // The least recently used id is at the front.
+    order: VecDeque<u64>,
+}
+
+impl CursorCache {
+    // The capacity is set by the local option "cursor-cache-capacity", 0 to disable the cache.
+    fn capacity() -> usize {
+        LocalConfig::get_option("cursor-cache-capacity")
+            .parse()
+            .unwrap_or(DEFAULT_CURSOR_CACHE_CAPACITY)
+    }
+
+    fn get(&mut self, id: u64) -> Option<&CachedCursor> {
+        if self.entries.contains_key(&id) {
+            self.touch(id);
+        }
+        self.entries.get(&id)
+    }
+
+    fn insert(&mut self, id: u64, cursor: CachedCursor, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.entries.insert(id, cursor);
+        self.touch(id);
+        while self.order.len() > capacity {
+            if let Some(id) = self.order.pop_front() {
+                self.entries.remove(&id);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    fn touch(&mut self, id: u64) {
+        if let Some(pos) = self.order.iter().position(|x| *x == id) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(id);
+    }
+}
+
+/// Scale a RGBA image. Colors are averaged with premultiplied alpha when downscaling,
+/// so the colors of transparent pixels don't bleed into the edges.
+/// Upscaling is nearest-neighbor, which keeps the cursors crisp.
+fn scale_rgba(src: &[u8], w: usize, h: usize, dst_w: usize, dst_h: usize) -> Vec<u8> {
+    let mut dst = vec![0u8; dst_w * dst_h * 4];
+    for y in 0..dst_h {
+        let y0 = y * h / dst_h;
+        let y1 = ((y + 1) * h / dst_h).max(y0 + 1);
+        for x in 0..dst_w {
+            let x0 = x * w / dst_w;
+            let x1 = ((x + 1) * w / dst_w).max(x0 + 1);
+            let (mut r, mut g, mut b, mut a, mut n) = (0u32, 0u32, 0u32, 0u32, 0u32);
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = (sy * w + sx) * 4;
+                    let alpha = src[i + 3] as u32;
+                    r += src[i] as u32 * alpha;
+                    g += src[i + 1] as u32 * alpha;
+                    b += src[i + 2] as u32 * alpha;
+                    a += alpha;
+                    n += 1;
+                }
+            }
+            let o = (y * dst_w + x) * 4;
+            if a > 0 {
+                dst[o] = (r / a) as u8;
+                dst[o + 1] = (g / a) as u8;
+                dst[o + 2] = (b / a) as u8;
+            }
+            dst[o + 3] = (a / n) as u8;
+        }
+    }
+    dst
+}
+
+// Scale the cursor with decompressed colors by `ratio`, the hotspot included.
+fn scale_cursor(cd: &CursorData, ratio: f64) -> CursorData {
+    let (w, h) = (cd.width.max(0) as usize, cd.height.max(0) as usize);
+    if w == 0 || h == 0 || cd.colors.len() < w * h * 4 {
+        return cd.clone();
+    }
+    let dst_w = ((w as f64 * ratio).round() as usize).max(1);
+    let dst_h = ((h as f64 * ratio).round() as usize).max(1);
+    CursorData {
+        id: cd.id,
+        hotx: ((cd.hotx as f64 * ratio).round() as i32).clamp(0, dst_w as i32 - 1),
+        hoty: ((cd.hoty as f64 * ratio).round() as i32).clamp(0, dst_h as i32 - 1),
+        width: dst_w as _,
+        height: dst_h as _,
+        colors: scale_rgba(&cd.colors, w, h, dst_w, dst_h).into(),
+        ..Default::default()
+    }
+}
+
+/// The changes of the display list, by display index.
+#[derive(Debug, Default, PartialEq)]
+struct DisplaysDiff {
+    added: Vec<usize>,
+    removed: Vec<usize>,
+    modified: Vec<usize>,
+}
+
+impl DisplaysDiff {
+    /// Return `None` if the change can't be expressed by indices safely,
+    /// e.g. a display in the middle is removed and the following displays are shifted.
+    fn new(old: &[DisplayInfo], new: &[DisplayInfo]) -> Option<Self> {
+        if old.is_empty() {
+            return None;
+        }
+        let mut diff = Self::default();
+        for (i, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+            if o.name != n.name {
+                return None;
+            }
+            if o != n {
+                diff.modified.push(i);
+            }
+        }
+        diff.added = (old.len()..new.len()).collect();
+        diff.removed = (new.len()..old.len()).collect();
+        Some(diff)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
 }
 
 #[cfg(feature = "flutter_texture_render")]
@@ -201,10 +813,57 @@ pub type FlutterRgbaRendererPluginOnRgba = unsafe extern "C" fn(
 pub(super) type TextureRgbaPtr = usize;
 
 #[cfg(feature = "flutter_texture_render")]
+lazy_static::lazy_static! {
+    // The count of the refs of each texture, see `TextureRef`.
+    static ref TEXTURE_REFS: (std::sync::Mutex<HashMap<TextureRgbaPtr, usize>>, std::sync::Condvar) =
+        Default::default();
+}
+
+// Held by the registered texture and the frames being rendered into it,
+// flutter frees the texture after all the refs are dropped, see `wait_texture_released`.
+#[cfg(feature = "flutter_texture_render")]
+struct TextureRef(TextureRgbaPtr);
+
+#[cfg(feature = "flutter_texture_render")]
+impl TextureRef {
+    fn new(ptr: TextureRgbaPtr) -> Arc<Self> {
+        *TEXTURE_REFS.0.lock().unwrap().entry(ptr).or_default() += 1;
+        Arc::new(Self(ptr))
+    }
+}
+
+#[cfg(feature = "flutter_texture_render")]
+impl Drop for TextureRef {
+    fn drop(&mut self) {
+        let mut refs = TEXTURE_REFS.0.lock().unwrap();
+        if let Some(count) = refs.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                refs.remove(&self.0);
+                TEXTURE_REFS.1.notify_all();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "flutter_texture_render")]
+#[derive(Clone, Default)]
 struct DisplaySessionInfo {
-    // TextureRgba pointer in flutter native.
-    texture_rgba_ptr: TextureRgbaPtr,
+    // TextureRgba pointer in flutter native, kept alive until the frames rendering into it are done.
+    texture: Option<Arc<TextureRef>>,
+    // Changed when the texture is replaced, the frames for the old texture are skipped.
+    generation: u64,
     size: (usize, usize),
+    // The size of the frames of a region of the display, see `viewport`.
+    frame_size: Option<(usize, usize)>,
+}
+
+#[cfg(feature = "flutter_texture_render")]
+impl DisplaySessionInfo {
+    #[inline]
+    fn texture_rgba_ptr(&self) -> TextureRgbaPtr {
+        self.texture.as_ref().map_or(0, |t| t.0)
+    }
 }
 
 // Video Texture Renderer in Flutter
@@ -213,7 +872,11 @@ struct DisplaySessionInfo {
 struct VideoRenderer {
     is_support_multi_ui_session: bool,
     map_display_sessions: Arc<RwLock<HashMap<usize, DisplaySessionInfo>>>,
-    on_rgba_func: Option<Symbol<'static, FlutterRgbaRendererPluginOnRgba>>,
+    // The merged canvas of all displays, used if the texture of `COMPOSITE_DISPLAY` is registered.
+    composite: Arc<std::sync::Mutex<Option<CompositeCanvas>>>,
+    // The last generation of the textures, increased when a texture is registered.
+    texture_generation: Arc<AtomicU64>,
+    on_rgba_func: Option<FlutterRgbaRendererPluginOnRgba>,
 }
 
 #[cfg(feature = "flutter_texture_render")]
@@ -225,7 +888,8 @@ impl Default for VideoRenderer {
                     lib.symbol::<FlutterRgbaRendererPluginOnRgba>("FlutterRgbaRendererPluginOnRgba")
                 };
                 match find_sym_res {
-                    Ok(sym) => Some(sym),
+                    // The library is never unloaded.
+                    Ok(sym) => Some(*sym),
                     Err(e) => {
                         log::error!("Failed to find symbol FlutterRgbaRendererPluginOnRgba, {e}");
                         None
@@ -239,6 +903,8 @@ impl Default for VideoRenderer {
         };
         Self {
             map_display_sessions: Default::default(),
+            composite: Default::default(),
+            texture_generation: Default::default(),
             is_support_multi_ui_session: false,
             on_rgba_func,
         }
@@ -256,88 +922,325 @@ impl VideoRenderer {
             sessions_lock.insert(
                 display,
                 DisplaySessionInfo {
-                    texture_rgba_ptr: usize::default(),
                     size: (width, height),
+                    ..Default::default()
                 },
             );
         }
     }
 
-    fn register_texture(&self, display: usize, ptr: usize) {
+    fn set_frame_size(&self, display: usize, frame_size: Option<(usize, usize)>) {
+        if let Some(info) = self.map_display_sessions.write().unwrap().get_mut(&display) {
+            info.frame_size = frame_size;
+        }
+    }
+
+    fn set_composite_size(&mut self, displays: &[DisplayInfo], width: usize, height: usize) {
+        self.set_size(COMPOSITE_DISPLAY, width, height);
+        *self.composite.lock().unwrap() = Some(CompositeCanvas::new(displays, width, height));
+    }
+
+    // Rebuild the composite canvas if the layout of the remote displays is changed.
+    fn on_displays_changed(&self, displays: &[DisplayInfo]) {
+        let mut lock = self.composite.lock().unwrap();
+        if let Some(canvas) = lock.as_ref() {
+            let (width, height) = canvas.size();
+            *lock = Some(CompositeCanvas::new(displays, width, height));
+        }
+    }
+
+    #[inline]
+    fn is_composite(&self) -> bool {
+        self.map_display_sessions
+            .read()
+            .unwrap()
+            .contains_key(&COMPOSITE_DISPLAY)
+    }
+
+    fn composite_to_remote(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if !self
+            .map_display_sessions
+            .read()
+            .unwrap()
+            .contains_key(&COMPOSITE_DISPLAY)
+        {
+            return None;
+        }
+        self.composite.lock().unwrap().as_ref()?.to_remote(x, y)
+    }
+
+    // Returns true if another texture of the display is replaced,
+    // e.g. the texture is re-created by flutter during resizing.
+    //
+    // Never blocked by the rendering, the old texture is released after the frame being rendered
+    // into it is done, see `wait_texture_released`.
+    fn register_texture(&self, display: usize, ptr: usize) -> bool {
         let mut sessions_lock = self.map_display_sessions.write().unwrap();
         if ptr == 0 {
             sessions_lock.remove(&display);
-        } else {
-            if let Some(info) = sessions_lock.get_mut(&display) {
-                if info.texture_rgba_ptr != 0 && info.texture_rgba_ptr != ptr as TextureRgbaPtr {
-                    log::error!("unreachable, texture_rgba_ptr is not null and not equal to ptr");
-                }
-                info.texture_rgba_ptr = ptr as _;
-            } else {
-                if ptr != 0 {
-                    sessions_lock.insert(
-                        display,
-                        DisplaySessionInfo {
-                            texture_rgba_ptr: ptr as _,
-                            size: (0, 0),
-                        },
-                    );
-                }
+            if display == COMPOSITE_DISPLAY {
+                drop(sessions_lock);
+                *self.composite.lock().unwrap() = None;
             }
+            return false;
         }
+        let info = sessions_lock.entry(display).or_default();
+        if info.texture_rgba_ptr() == ptr as TextureRgbaPtr {
+            return false;
+        }
+        let replaced = info.texture.is_some();
+        info.texture = Some(TextureRef::new(ptr as _));
+        info.generation = self.texture_generation.fetch_add(1, Ordering::SeqCst) + 1;
+        replaced
     }
 
     pub fn on_rgba(&self, display: usize, rgba: &scrap::ImageRgb) {
-        let read_lock = self.map_display_sessions.read().unwrap();
-        let opt_info = if !self.is_support_multi_ui_session {
-            read_lock.values().next()
-        } else {
-            read_lock.get(&display)
+        // Copy the texture info out and release the lock before calling the plugin,
+        // which may be slow, or call back into `register_texture` and `set_size`.
+        let (key, info) = {
+            let read_lock = self.map_display_sessions.read().unwrap();
+            if let Some(info) = read_lock.get(&COMPOSITE_DISPLAY) {
+                (COMPOSITE_DISPLAY, info.clone())
+            } else {
+                let opt_info = if !self.is_support_multi_ui_session {
+                    read_lock.iter().next()
+                } else {
+                    read_lock.get_key_value(&display)
+                };
+                let Some((key, info)) = opt_info else {
+                    return;
+                };
+                (*key, info.clone())
+            }
         };
-        let Some(info) = opt_info else {
+        if key == COMPOSITE_DISPLAY {
+            self.on_composite_rgba(&info, display, rgba);
             return;
-        };
-        if info.texture_rgba_ptr == usize::default() {
+        }
+        if info.texture.is_none() {
             return;
         }
 
         // It is also Ok to skip this check.
-        if info.size.0 != rgba.w || info.size.1 != rgba.h {
+        let size = info.frame_size.unwrap_or(info.size);
+        if size.0 != rgba.w || size.1 != rgba.h {
             log::error!(
                 "width/height mismatch: ({},{}) != ({},{})",
-                info.size.0,
-                info.size.1,
+                size.0,
+                size.1,
                 rgba.w,
                 rgba.h
             );
             return;
         }
-        if let Some(func) = &self.on_rgba_func {
-            unsafe {
-                func(
-                    info.texture_rgba_ptr as _,
-                    rgba.raw.as_ptr() as _,
-                    rgba.raw.len() as _,
-                    rgba.w as _,
-                    rgba.h as _,
-                    rgba.stride() as _,
-                )
-            };
-        }
+        self.call_on_rgba_func(key, &info, &rgba.raw, rgba.w, rgba.h, rgba.stride());
     }
-}
 
-impl SessionHandler {
-    pub fn on_waiting_for_image_dialog_show(&mut self) {
-        #[cfg(any(feature = "flutter_texture_render"))]
-        {
-            self.notify_rendered = false;
+    fn on_composite_rgba(&self, info: &DisplaySessionInfo, display: usize, rgba: &scrap::ImageRgb) {
+        if info.texture.is_none() || rgba.h == 0 {
+            return;
         }
-        // rgba array render will notify every frame
+        let mut lock = self.composite.lock().unwrap();
+        let Some(canvas) = lock.as_mut() else {
+            return;
+        };
+        if canvas.size() != info.size {
+            return;
+        }
+        if !canvas.blit(display, &rgba.raw, rgba.w, rgba.h, rgba.raw.len() / rgba.h) {
+            return;
+        }
+        let (width, height) = canvas.size();
+        let stride = canvas.stride();
+        self.call_on_rgba_func(
+            COMPOSITE_DISPLAY,
+            info,
+            canvas.data(),
+            width,
+            height,
+            stride,
+        );
     }
-}
+
+    // The frame is skipped if the texture of `key` is changed since `info` was copied.
+    // The texture of `info` is not freed before the call returns, `info` holds a ref of it.
+    fn call_on_rgba_func(
+        &self,
+        key: usize,
+        info: &DisplaySessionInfo,
+        buffer: &[u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) {
+        let Some(func) = self.on_rgba_func else {
+            return;
+        };
+        let generation = self
+            .map_display_sessions
+            .read()
+            .unwrap()
+            .get(&key)
+            .map(|info| info.generation);
+        if generation != Some(info.generation) {
+            log::debug!("The texture of {} is replaced, skip the frame", key);
+            return;
+        }
+        unsafe {
+            func(
+                info.texture_rgba_ptr() as _,
+                buffer.as_ptr() as _,
+                buffer.len() as _,
+                width as _,
+                height as _,
+                stride as _,
+            )
+        };
+    }
+}
+
+impl SessionHandler {
+    // Return the ratio to scale the cursor images, `None` if no scaling is needed.
+    fn cursor_scale_ratio(&self, remote_scale: f64) -> Option<f64> {
+        if self.raw_cursor || self.cursor_scale <= 0.0 {
+            return None;
+        }
+        let remote_scale = if remote_scale > 0.0 { remote_scale } else { 1.0 };
+        let ratio = self.cursor_scale / remote_scale;
+        if (ratio - 1.0).abs() < 0.01 {
+            None
+        } else {
+            Some(ratio)
+        }
+    }
+
+    pub fn on_waiting_for_image_dialog_show(&mut self) {
+        #[cfg(any(feature = "flutter_texture_render"))]
+        {
+            self.notify_rendered.store(false, Ordering::SeqCst);
+        }
+        // rgba array render will notify every frame
+    }
+}
+
+#[cfg(test)]
+impl FlutterHandler {
+    /// A handler with one ui session, whose events are collected instead of sent to flutter.
+    pub(crate) fn new_with_collector(
+        session_id: SessionID,
+    ) -> (Self, Arc<EventCollector<EventToUI>>) {
+        let handler = Self::default();
+        let collector: Arc<EventCollector<EventToUI>> = Default::default();
+        let sink: Arc<dyn EventSink<EventToUI>> = collector.clone();
+        handler.session_handlers.write().unwrap().insert(
+            session_id,
+            SessionHandler {
+                event_stream: Some(sink),
+                ..Default::default()
+            },
+        );
+        (handler, collector)
+    }
+}
 
 impl FlutterHandler {
+    /// The capabilities of the connected peer, see [`crate::common::PeerCapabilities`].
+    #[inline]
+    pub fn get_peer_capabilities(&self) -> crate::common::PeerCapabilities {
+        self.peer_capabilities.read().unwrap().clone()
+    }
+
+    #[cfg(not(feature = "flutter_texture_render"))]
+    fn notify_rgba(&self, display: usize) {
+        // Non-texture-render UI does not support multiple displays in the one UI session.
+        // It's Ok to notify each session for now.
+        for h in self.session_handlers.read().unwrap().values() {
+            if let Some(stream) = &h.event_stream {
+                stream.add(EventToUI::Rgba(display));
+            }
+        }
+    }
+
+    /// Called on the timer of the session, see [`start_video_watchdog`].
+    fn check_video_watchdog(&self, connected: bool, privacy_mode: bool) {
+        let now = Instant::now();
+        self.video_watchdog.set_paused(video_watchdog::PAUSE_PRIVACY_MODE, privacy_mode, now);
+        if !connected {
+            // The frames are expected again after reconnecting.
+            self.video_watchdog.reset(now);
+            return;
+        }
+        let captured = self
+            .last_capture_displays
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(set, _)| set.clone());
+        let timeout = video_watchdog::VideoWatchdog::timeout();
+        for event in self.video_watchdog.check(now, timeout, captured.as_deref()) {
+            self.push_video_watchdog_event(event);
+        }
+    }
+
+    fn push_video_watchdog_event(&self, event: video_watchdog::WatchdogEvent) {
+        match event {
+            video_watchdog::WatchdogEvent::Stalled { display, secs } => self.push_event(
+                "video_stalled",
+                vec![("display", &display.to_string()), ("secs", &secs.to_string())],
+            ),
+            video_watchdog::WatchdogEvent::Recovered { display } => {
+                self.push_event("video_recovered", vec![("display", &display.to_string())])
+            }
+        }
+    }
+
+    fn show_msgbox(&self, key: MsgboxKey, body: MsgboxBody, retry: bool) {
+        let window = MsgboxThrottle::window();
+        let action = {
+            let mut throttle = self.msgbox_throttle.lock().unwrap();
+            throttle.check(key.clone(), &body, retry, window, Instant::now())
+        };
+        match action {
+            MsgboxAction::Push(repeat) => self.push_msgbox(&key, &body, retry, repeat),
+            MsgboxAction::Suppress => {}
+            MsgboxAction::Schedule(delay, epoch) => {
+                // The latest retry-capable one is never lost.
+                let handler = self.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    let pending = handler
+                        .msgbox_throttle
+                        .lock()
+                        .unwrap()
+                        .take_pending(epoch, Instant::now());
+                    if let Some((key, body, repeat)) = pending {
+                        handler.push_msgbox(&key, &body, true, repeat);
+                    }
+                });
+            }
+        }
+    }
+
+    // The keyed ones are marked by "keyed", the old ui shows the key of the text.
+    fn push_msgbox(&self, key: &MsgboxKey, body: &MsgboxBody, retry: bool, repeat: u32) {
+        let (msgtype, title, text) = key;
+        let has_retry = if retry { "true" } else { "" };
+        let repeat = repeat.to_string();
+        let mut event = vec![
+            ("type", msgtype.as_str()),
+            ("title", title),
+            ("text", text),
+            ("link", &body.link),
+            ("hasRetry", has_retry),
+            ("repeat", &repeat),
+        ];
+        if let Some(params) = &body.params {
+            event.push(("keyed", "true"));
+            event.push(("params", params));
+        }
+        self.push_event("msgbox", event);
+    }
+
     /// Push an event to all the event queues.
     /// An event is stored as json in the event queues.
     ///
@@ -346,15 +1249,174 @@ impl FlutterHandler {
     /// * `name` - The name of the event.
     /// * `event` - Fields of the event content.
     pub fn push_event(&self, name: &str, event: Vec<(&str, &str)>) {
+        if !Self::check_core_event_name(name) {
+            return;
+        }
+        self.push_raw_event(name, Self::make_event(name, event));
+    }
+
+    fn push_raw_event(&self, name: &str, out: String) {
+        // Snapshot the sinks, so the lock is not held while pushing.
+        let streams: Vec<_> = self
+            .session_handlers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|session| !session.filtered_events.contains(name))
+            .filter_map(|session| session.event_stream.clone())
+            .collect();
+        for stream in streams {
+            stream.add(EventToUI::Event(out.clone()));
+        }
+    }
+
+    /// Push an event describing the current state of the peer, which is replayed to the ui
+    /// sessions attaching later by [`Self::replay_stateful_events`].
+    /// The event is forgotten if `event` is `None`, i.e. the default state.
+    fn push_stateful_event(&self, name: &str, event: Option<Vec<(&str, &str)>>) {
+        if !Self::check_core_event_name(name) {
+            return;
+        }
+        let Some(event) = event else {
+            self.stateful_events.write().unwrap().remove(name);
+            return;
+        };
+        let out = Self::make_event(name, event);
+        self.stateful_events
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), out.clone());
+        self.push_raw_event(name, out);
+    }
+
+    fn replay_stateful_events(&self, session_id: &SessionID) {
+        let events: Vec<_> = self
+            .stateful_events
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, out)| (name.clone(), out.clone()))
+            .collect();
+        let stream = match self.session_handlers.read().unwrap().get(session_id) {
+            Some(session) => session
+                .event_stream
+                .clone()
+                .map(|s| (s, session.filtered_events.clone())),
+            None => None,
+        };
+        if let Some((stream, filtered)) = stream {
+            for (name, out) in events {
+                if !filtered.contains(&name) {
+                    stream.add(EventToUI::Event(out));
+                }
+            }
+        }
+    }
+
+    /// Push an event to the event queue of the given ui session only.
+    pub fn push_event_to(&self, session_id: &SessionID, name: &str, event: Vec<(&str, &str)>) {
+        if !Self::check_core_event_name(name) {
+            return;
+        }
+        let out = Self::make_event(name, event);
+        let stream = match self.session_handlers.read().unwrap().get(session_id) {
+            Some(session) if !session.filtered_events.contains(name) => {
+                session.event_stream.clone()
+            }
+            _ => None,
+        };
+        if let Some(stream) = stream {
+            stream.add(EventToUI::Event(out));
+        }
+    }
+
+    /// Push a "peer_data_truncated" for each field limited by `sanitizer`, see `peer_data`.
+    fn report_peer_data(&self, sanitizer: peer_data::Sanitizer) {
+        let violations = sanitizer.into_violations();
+        if violations.is_empty() {
+            return;
+        }
+        let fields = self.peer_data_reports.lock().unwrap().filter(violations);
+        for field in fields {
+            log::warn!("The {} from the peer is truncated", field);
+            self.push_event("peer_data_truncated", vec![("field", field)]);
+        }
+    }
+
+    /// Push the "session_activity" to the ui sessions, `None` for all.
+    /// `update` updates the unread counters of a ui session, returns false to skip pushing.
+    fn push_session_activity(
+        &self,
+        session_id: Option<&SessionID>,
+        update: impl Fn(&mut session_activity::Unread) -> bool,
+    ) {
+        let events: Vec<_> = {
+            let activity = self.activity.lock().unwrap();
+            let mut handlers = self.session_handlers.write().unwrap();
+            handlers
+                .iter_mut()
+                .filter(|(id, _)| session_id.map_or(true, |s| s == *id))
+                .filter_map(|(id, h)| {
+                    update(&mut h.unread).then(|| (*id, activity.event(h.unread)))
+                })
+                .collect()
+        };
+        for (id, event) in events {
+            let event = event.iter().map(|(k, v)| (*k, v.as_str())).collect();
+            self.push_event_to(&id, "session_activity", event);
+        }
+    }
+
+    fn set_voice_call_activity(&self, state: &'static str) {
+        if self.activity.lock().unwrap().set_voice_call(state) {
+            self.push_session_activity(None, |_| true);
+        }
+    }
+
+    /// Reset the unread counter of the category in the ui session only, see [`session_activity`].
+    pub fn mark_activity_read(&self, session_id: &SessionID, category: &str) {
+        self.push_session_activity(Some(session_id), |unread| unread.mark_read(category));
+    }
+
+    pub fn set_recording_activity(&self, display: usize, on: bool) {
+        self.activity.lock().unwrap().set_recording(display, on);
+    }
+
+    /// What is interrupted by closing the ui session.
+    pub(crate) fn blocking_activity(
+        &self,
+        session_id: &SessionID,
+    ) -> session_activity::BlockingActivity {
+        let last = {
+            let handlers = self.session_handlers.read().unwrap();
+            handlers.len() == 1 && handlers.contains_key(session_id)
+        };
+        self.activity.lock().unwrap().blocking(last)
+    }
+
+    // The peer session kept in the background is closed once its transfers end.
+    fn check_background_finished(&self) {
+        if self.session_handlers.read().unwrap().is_empty() {
+            sessions::close_finished_background_sessions();
+        }
+    }
+
+    // The plugin namespace is reserved for `push_plugin_event`.
+    fn check_core_event_name(name: &str) -> bool {
+        let valid = !name.starts_with(PLUGIN_EVENT_PREFIX);
+        debug_assert!(valid, "Event name \"{}\" is reserved for plugins", name);
+        if !valid {
+            log::error!("Event name \"{}\" is reserved for plugins, skipped", name);
+        }
+        valid
+    }
+
+    #[inline]
+    fn make_event(name: &str, event: Vec<(&str, &str)>) -> String {
         let mut h: HashMap<&str, &str> = event.iter().cloned().collect();
         debug_assert!(h.get("name").is_none());
         h.insert("name", name);
-        let out = serde_json::ser::to_string(&h).unwrap_or("".to_owned());
-        for (_, session) in self.session_handlers.read().unwrap().iter() {
-            if let Some(stream) = &session.event_stream {
-                stream.add(EventToUI::Event(out.clone()));
-            }
-        }
+        serde_json::ser::to_string(&h).unwrap_or("".to_owned())
     }
 
     pub(crate) fn close_event_stream(&self, session_id: SessionID) {
@@ -365,53 +1427,93 @@ impl FlutterHandler {
         }
     }
 
-    fn make_displays_msg(displays: &Vec<DisplayInfo>) -> String {
-        let mut msg_vec = Vec::new();
-        for ref d in displays.iter() {
-            let mut h: HashMap<&str, i32> = Default::default();
-            h.insert("x", d.x);
-            h.insert("y", d.y);
-            h.insert("width", d.width);
-            h.insert("height", d.height);
-            h.insert("cursor_embedded", if d.cursor_embedded { 1 } else { 0 });
-            if let Some(original_resolution) = d.original_resolution.as_ref() {
-                h.insert("original_width", original_resolution.width);
-                h.insert("original_height", original_resolution.height);
-            }
-            msg_vec.push(h);
+    // The displays as shown by the ui, scaled if the frames are downscaled.
+    fn ui_displays(&self, displays: &[DisplayInfo]) -> Vec<DisplayInfo> {
+        let downscale = self.downscale.lock().unwrap();
+        displays.iter().map(|d| downscale.display(d)).collect()
+    }
+
+    fn ui_switch_display(&self, display: &SwitchDisplay) -> SwitchDisplay {
+        let downscale = self.downscale.lock().unwrap();
+        let mut display = display.clone();
+        if downscale.is_scaled() {
+            let (width, height) =
+                downscale.size(display.width.max(0) as _, display.height.max(0) as _);
+            display.x = downscale.to_ui(display.x);
+            display.y = downscale.to_ui(display.y);
+            display.width = width as _;
+            display.height = height as _;
         }
+        display
+    }
+
+    fn make_displays_msg(displays: &Vec<DisplayInfo>) -> String {
+        let msg_vec: Vec<_> = displays.iter().map(Self::make_display_map).collect();
         serde_json::ser::to_string(&msg_vec).unwrap_or("".to_owned())
     }
 
-    #[cfg(feature = "plugin_framework")]
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    pub(crate) fn add_session_hook(&self, key: String, hook: SessionHook) -> bool {
-        let mut hooks = self.hooks.write().unwrap();
-        if hooks.contains_key(&key) {
-            // Already has the hook with this key.
-            return false;
+    fn make_display_map(d: &DisplayInfo) -> HashMap<&'static str, serde_json::Value> {
+        let mut h: HashMap<&str, serde_json::Value> = Default::default();
+        h.insert("x", d.x.into());
+        h.insert("y", d.y.into());
+        h.insert("width", d.width.into());
+        h.insert("height", d.height.into());
+        h.insert("cursor_embedded", (d.cursor_embedded as i32).into());
+        if let Some(original_resolution) = d.original_resolution.as_ref() {
+            h.insert("original_width", original_resolution.width.into());
+            h.insert("original_height", original_resolution.height.into());
         }
-        let _ = hooks.insert(key, hook);
-        true
+        // Old peers do not send these fields, leave them absent.
+        if d.refresh_rate > 0 {
+            h.insert("refresh_rate", d.refresh_rate.into());
+        }
+        if d.scale > 0.0 {
+            h.insert("scale", d.scale.into());
+        }
+        h
     }
 
-    #[cfg(feature = "plugin_framework")]
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    pub(crate) fn remove_session_hook(&self, key: &String) -> bool {
-        let mut hooks = self.hooks.write().unwrap();
-        if !hooks.contains_key(key) {
-            // The hook with this key does not found.
-            return false;
+    fn make_displays_changed_msg(displays: &Vec<DisplayInfo>, indices: &[usize]) -> String {
+        let msg_vec: Vec<_> = indices
+            .iter()
+            .filter_map(|i| {
+                let mut h = Self::make_display_map(displays.get(*i)?);
+                h.insert("index", (*i).into());
+                Some(h)
+            })
+            .collect();
+        serde_json::ser::to_string(&msg_vec).unwrap_or("".to_owned())
+    }
+
+    /// Filter the display indices out of the range of the peer displays,
+    /// e.g. stale ui state after a monitor is unplugged.
+    ///
+    /// Return the valid indices (sorted and deduplicated) and the rejected indices.
+    fn validate_capture_displays(&self, requested: &[i32]) -> (Vec<i32>, Vec<i32>) {
+        let count = self.peer_info.read().unwrap().displays.len() as i32;
+        let (mut valid, rejected): (Vec<i32>, Vec<i32>) = requested
+            .iter()
+            .cloned()
+            .partition(|d| *d >= 0 && *d < count);
+        valid.sort();
+        valid.dedup();
+        (valid, rejected)
+    }
+
+    // Return true if the same display set has been sent just now.
+    fn is_capture_displays_duplicated(&self, set: &[i32]) -> bool {
+        let mut lock = self.last_capture_displays.write().unwrap();
+        if let Some((last, t)) = lock.as_ref() {
+            if last == set && t.elapsed() < CAPTURE_DISPLAYS_DEDUP_INTERVAL {
+                return true;
+            }
         }
-        let _ = hooks.remove(key);
-        true
+        *lock = Some((set.to_vec(), Instant::now()));
+        false
     }
-}
 
-impl InvokeUiSession for FlutterHandler {
-    fn set_cursor_data(&self, cd: CursorData) {
-        let colors = hbb_common::compress::decompress(&cd.colors);
-        self.push_event(
+    fn make_cursor_data_event(cd: &CursorData) -> String {
+        Self::make_event(
             "cursor_data",
             vec![
                 ("id", &cd.id.to_string()),
@@ -421,105 +1523,430 @@ impl InvokeUiSession for FlutterHandler {
                 ("height", &cd.height.to_string()),
                 (
                     "colors",
-                    &serde_json::ser::to_string(&colors).unwrap_or("".to_owned()),
+                    &serde_json::ser::to_string(&cd.colors[..]).unwrap_or("".to_owned()),
                 ),
             ],
-        );
-    }
-
-    fn set_cursor_id(&self, id: String) {
-        self.push_event("cursor_id", vec![("id", &id.to_string())]);
-    }
-
-    fn set_cursor_position(&self, cp: CursorPosition) {
-        self.push_event(
-            "cursor_position",
-            vec![("x", &cp.x.to_string()), ("y", &cp.y.to_string())],
-        );
+        )
     }
 
-    /// unused in flutter, use switch_display or set_peer_info
-    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool) {}
-
-    fn update_privacy_mode(&self) {
-        self.push_event("update_privacy_mode", [].into());
+    // The scale factor of the remote display shown by the ui session.
+    fn remote_display_scale(&self, h: &SessionHandler) -> f64 {
+        let pi = self.peer_info.read().unwrap();
+        let display = h
+            .follow_cursor
+            .display
+            .unwrap_or(pi.current_display as usize);
+        pi.displays.get(display).map(|d| d.scale).unwrap_or(0.0)
     }
 
-    fn set_permission(&self, name: &str, value: bool) {
-        self.push_event("permission", vec![(name, &value.to_string())]);
+    // Get the "cursor_data" event of the cursor for the ui session, scaled if needed.
+    fn cursor_data_event_for(&self, h: &SessionHandler, cursor: &CachedCursor) -> String {
+        let downscale = self.downscale.lock().unwrap().scale();
+        let ratio = h
+            .cursor_scale_ratio(self.remote_display_scale(h))
+            .unwrap_or(1.0)
+            * downscale;
+        if (ratio - 1.0).abs() < 0.01 {
+            cursor.payload.clone()
+        } else {
+            Self::make_cursor_data_event(&scale_cursor(&cursor.data, ratio))
+        }
     }
 
-    // unused in flutter
-    fn close_success(&self) {}
-
-    fn update_quality_status(&self, status: QualityStatus) {
-        const NULL: String = String::new();
+    fn push_cursor_highlight(&self, x: i32, y: i32) {
+        let (color, size) = {
+            let mut presenter = self.presenter.write().unwrap();
+            if !presenter.enabled {
+                return;
+            }
+            if let Some(t) = presenter.last_highlight {
+                if t.elapsed() < PRESENTER_HIGHLIGHT_INTERVAL {
+                    return;
+                }
+            }
+            presenter.last_highlight = Some(Instant::now());
+            (presenter.color.clone(), presenter.size.to_string())
+        };
         self.push_event(
-            "update_quality_status",
+            "cursor_highlight",
             vec![
-                ("speed", &status.speed.map_or(NULL, |it| it)),
-                (
-                    "fps",
-                    &serde_json::ser::to_string(&status.fps).unwrap_or(NULL.to_owned()),
-                ),
-                ("delay", &status.delay.map_or(NULL, |it| it.to_string())),
-                (
-                    "target_bitrate",
-                    &status.target_bitrate.map_or(NULL, |it| it.to_string()),
-                ),
-                (
-                    "codec_format",
-                    &status.codec_format.map_or(NULL, |it| it.to_string()),
-                ),
-                ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                ("x", &x.to_string()),
+                ("y", &y.to_string()),
+                ("color", &color),
+                ("size", &size),
             ],
         );
     }
 
-    fn set_connection_type(&self, is_secured: bool, direct: bool) {
+    /// Called when a mouse press is sent to the peer.
+    pub fn on_mouse_pressed(&self, x: i32, y: i32, button: &str) {
+        let (color, size) = {
+            let presenter = self.presenter.read().unwrap();
+            if !presenter.enabled {
+                return;
+            }
+            (presenter.color.clone(), presenter.size.to_string())
+        };
+        let (x, y) = {
+            let downscale = self.downscale.lock().unwrap();
+            (downscale.to_ui(x), downscale.to_ui(y))
+        };
         self.push_event(
-            "connection_ready",
+            "click_ripple",
             vec![
-                ("secure", &is_secured.to_string()),
-                ("direct", &direct.to_string()),
+                ("x", &x.to_string()),
+                ("y", &y.to_string()),
+                ("button", button),
+                ("color", &color),
+                ("size", &size),
             ],
         );
     }
 
-    fn set_fingerprint(&self, fingerprint: String) {
-        self.push_event("fingerprint", vec![("fingerprint", &fingerprint)]);
-    }
-
-    fn job_error(&self, id: i32, err: String, file_num: i32) {
-        self.push_event(
-            "job_error",
-            vec![
-                ("id", &id.to_string()),
-                ("err", &err),
-                ("file_num", &file_num.to_string()),
-            ],
-        );
+    fn display_of_point(displays: &[DisplayInfo], x: i32, y: i32) -> Option<usize> {
+        displays.iter().position(|d| {
+            x >= d.x && x < d.x + d.width && y >= d.y && y < d.y + d.height
+        })
     }
 
-    fn job_done(&self, id: i32, file_num: i32) {
-        self.push_event(
-            "job_done",
-            vec![("id", &id.to_string()), ("file_num", &file_num.to_string())],
-        );
+    // The displays which must be captured if reduced: the active one, i.e. the one received the
+    // most recent input or shown by the focused ui session, and the ones shown alone by the ui
+    // sessions.
+    fn capture_keep_displays(&self) -> Vec<i32> {
+        let handlers = self.session_handlers.read().unwrap();
+        let mut keep: Vec<i32> = handlers
+            .values()
+            .filter_map(|h| h.follow_cursor.display.map(|d| d as i32))
+            .collect();
+        let focused = handlers
+            .values()
+            .filter(|h| h.focus_seq > 0)
+            .max_by_key(|h| h.focus_seq)
+            .and_then(|h| h.follow_cursor.display.map(|d| d as i32));
+        let active = self
+            .capture_reduction
+            .lock()
+            .unwrap()
+            .input_display()
+            .or(focused)
+            .unwrap_or_else(|| self.peer_info.read().unwrap().current_display);
+        keep.push(active);
+        keep
     }
 
-    // unused in flutter
-    fn clear_all_jobs(&self) {}
-
-    fn load_last_job(&self, _cnt: i32, job_json: &str) {
-        self.push_event("load_last_job", vec![("value", job_json)]);
+    /// Record the display which received the input, `x` and `y` are the remote coordinates.
+    pub fn on_input_position(&self, x: i32, y: i32) {
+        let display = Self::display_of_point(&self.peer_info.read().unwrap().displays, x, y);
+        if let Some(display) = display {
+            self.capture_reduction
+                .lock()
+                .unwrap()
+                .set_input_display(display as _);
+        }
     }
 
-    fn update_folder_files(
-        &self,
-        id: i32,
-        entries: &Vec<FileEntry>,
-        path: String,
+    // Switch the ui sessions which follow the remote cursor to the display the cursor is on.
+    fn follow_remote_cursor(&self, x: i32, y: i32) {
+        if !self
+            .session_handlers
+            .read()
+            .unwrap()
+            .values()
+            .any(|h| h.follow_cursor.enabled)
+        {
+            return;
+        }
+        let Some(display) = Self::display_of_point(&self.peer_info.read().unwrap().displays, x, y)
+        else {
+            return;
+        };
+        let now = Instant::now();
+        let mut followed = vec![];
+        let mut armed = false;
+        for (id, h) in self.session_handlers.write().unwrap().iter_mut() {
+            match h.follow_cursor.on_cursor_display(display, now) {
+                FollowCursorAction::None => {}
+                FollowCursorAction::Arm => armed = true,
+                FollowCursorAction::Switch => followed.push((*id, display)),
+            }
+        }
+        self.switch_followed(followed);
+        if armed {
+            // Switched after the dwell even if the cursor doesn't move any more.
+            let handler = self.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(FOLLOW_CURSOR_DWELL);
+                let now = Instant::now();
+                let followed = handler
+                    .session_handlers
+                    .write()
+                    .unwrap()
+                    .iter_mut()
+                    .filter_map(|(id, h)| Some((*id, h.follow_cursor.on_deadline(now)?)))
+                    .collect();
+                handler.switch_followed(followed);
+            });
+        }
+    }
+
+    // The handlers lock must be released before switching, `session_switch_display()` locks it.
+    fn switch_followed(&self, followed: Vec<(SessionID, usize)>) {
+        for (session_id, display) in followed {
+            sessions::session_switch_display(
+                cfg!(not(any(target_os = "android", target_os = "ios"))),
+                session_id,
+                vec![display as i32],
+            );
+            self.push_event_to(
+                &session_id,
+                "display_followed",
+                vec![("display", &display.to_string())],
+            );
+        }
+    }
+
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub(crate) fn add_session_hook(&self, key: String, priority: i32, hook: SessionHook) -> bool {
+        self.hooks.write().unwrap().add(key, priority, hook)
+    }
+
+    /// Remove all the hooks with this key.
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub(crate) fn remove_session_hook(&self, key: &String) -> bool {
+        self.hooks.write().unwrap().remove(key)
+    }
+
+    /// The keys, kinds and priorities of the hooks, in the running order.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn list_session_hooks(&self) -> Vec<(String, &'static str, i32)> {
+        self.hooks.read().unwrap().list()
+    }
+
+    /// Run the hooks in order until one of them blocks the action.
+    ///
+    /// The hooks run out of the lock, so a hook may add or remove the hooks, which take effect from
+    /// the next run. The panics of the hooks are not caught, they are the callbacks of the plugins.
+    fn run_session_hooks(
+        &self,
+        action: &str,
+        mut f: impl FnMut(String, &SessionHook) -> HookAction,
+    ) -> HookAction {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        {
+            let entries = self.hooks.read().unwrap().0.clone();
+            for entry in entries {
+                if f(entry.key.clone(), &entry.hook) == HookAction::Block {
+                    log::info!("The {} action is blocked by plugin {}", action, entry.key);
+                    self.push_event(
+                        "action_blocked_by_plugin",
+                        vec![("plugin", &entry.key), ("action", action)],
+                    );
+                    return HookAction::Block;
+                }
+            }
+        }
+        #[cfg(any(target_os = "android", target_os = "ios"))]
+        let _ = (action, &mut f);
+        HookAction::Continue
+    }
+}
+
+impl InvokeUiSession for FlutterHandler {
+    fn set_cursor_data(&self, mut cd: CursorData) {
+        // The ui has cached the cursor, see `session_get_cached_cursor()` for the cache misses.
+        if self.cursor_cache.write().unwrap().get(cd.id).is_some() {
+            self.set_cursor_id(cd.id.to_string());
+            return;
+        }
+        cd.colors = hbb_common::compress::decompress(&cd.colors).into();
+        let cursor = CachedCursor {
+            payload: Self::make_cursor_data_event(&cd),
+            data: cd,
+        };
+        for h in self.session_handlers.read().unwrap().values() {
+            if let Some(stream) = &h.event_stream {
+                stream.add(EventToUI::Event(self.cursor_data_event_for(h, &cursor)));
+            }
+        }
+        let id = cursor.data.id;
+        self.cursor_cache
+            .write()
+            .unwrap()
+            .insert(id, cursor, CursorCache::capacity());
+    }
+
+    fn set_cursor_id(&self, id: String) {
+        self.push_event("cursor_id", vec![("id", &id.to_string())]);
+    }
+
+    fn set_cursor_position(&self, cp: CursorPosition) {
+        let (x, y) = {
+            let downscale = self.downscale.lock().unwrap();
+            (downscale.to_ui(cp.x), downscale.to_ui(cp.y))
+        };
+        self.push_event(
+            "cursor_position",
+            vec![("x", &x.to_string()), ("y", &y.to_string())],
+        );
+        self.push_cursor_highlight(x, y);
+        self.follow_remote_cursor(cp.x, cp.y);
+    }
+
+    /// unused in flutter, use switch_display or set_peer_info
+    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool) {}
+
+    fn update_privacy_mode(&self) {
+        self.push_event("update_privacy_mode", [].into());
+    }
+
+    fn set_permission(&self, name: &str, value: bool) {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if name == "recording" {
+            self.thumbnail.set_recording_allowed(value);
+        }
+        self.push_event("permission", vec![(name, &value.to_string())]);
+    }
+
+    fn set_permissions(&self, permissions: &[(&str, bool)]) {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some((_, value)) = permissions.iter().find(|(name, _)| *name == "recording") {
+            self.thumbnail.set_recording_allowed(*value);
+        }
+        let values: Vec<_> = permissions
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        self.push_event(
+            "permission",
+            values.iter().map(|(name, value)| (*name, value.as_str())).collect(),
+        );
+    }
+
+    // unused in flutter
+    fn close_success(&self) {}
+
+    fn update_quality_status(&self, status: QualityStatus) {
+        const NULL: String = String::new();
+        let downscale = self.downscale.lock().unwrap().scale();
+        self.push_event(
+            "update_quality_status",
+            vec![
+                ("speed", &status.speed.map_or(NULL, |it| it)),
+                (
+                    "fps",
+                    &serde_json::ser::to_string(&status.fps).unwrap_or(NULL.to_owned()),
+                ),
+                ("delay", &status.delay.map_or(NULL, |it| it.to_string())),
+                (
+                    "target_bitrate",
+                    &status.target_bitrate.map_or(NULL, |it| it.to_string()),
+                ),
+                (
+                    "codec_format",
+                    &status.codec_format.map_or(NULL, |it| it.to_string()),
+                ),
+                ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                (
+                    "displays",
+                    &if status.displays.is_empty() {
+                        NULL
+                    } else {
+                        serde_json::ser::to_string(&status.displays).unwrap_or(NULL.to_owned())
+                    },
+                ),
+                ("downscale", &format!("{:.2}", downscale)),
+                (
+                    "color_accuracy",
+                    &status.color_accuracy.map_or(NULL, |it| it.to_string()),
+                ),
+                (
+                    "latency_breakdown",
+                    &status.latency_breakdown.map_or(NULL, |it| {
+                        serde_json::ser::to_string(&it).unwrap_or(NULL.to_owned())
+                    }),
+                ),
+            ],
+        );
+    }
+
+    fn set_connection_type(&self, is_secured: bool, direct: bool) {
+        *self.connection_ready.write().unwrap() = Some((is_secured, direct, Instant::now()));
+        self.push_event(
+            "connection_ready",
+            vec![
+                ("secure", &is_secured.to_string()),
+                ("direct", &direct.to_string()),
+            ],
+        );
+    }
+
+    fn set_relay_server(&self, relay: &str) {
+        self.push_event("relay_server", vec![("relay", relay)]);
+    }
+
+    fn relay_fallback(&self, preferred: &str, err: &str) {
+        self.push_event("relay_fallback", vec![("preferred", preferred), ("error", err)]);
+    }
+
+    fn set_fingerprint(&self, fingerprint: String) {
+        self.push_event("fingerprint", vec![("fingerprint", &fingerprint)]);
+    }
+
+    fn fingerprint_mismatch(&self, old: &str, new: &str) {
+        self.push_event(
+            "fingerprint_mismatch",
+            vec![
+                ("type", "fingerprint-mismatch"),
+                ("title", "Fingerprint mismatch"),
+                ("text", "fingerprint_mismatch_tip"),
+                ("old", old),
+                ("new", new),
+            ],
+        );
+    }
+
+    fn job_error(&self, id: i32, err: String, file_num: i32) {
+        self.push_event(
+            "job_error",
+            vec![
+                ("id", &id.to_string()),
+                ("err", &err),
+                ("file_num", &file_num.to_string()),
+            ],
+        );
+        if self.activity.lock().unwrap().on_job_end(id) {
+            self.push_session_activity(None, |_| true);
+        }
+        self.check_background_finished();
+    }
+
+    fn job_done(&self, id: i32, file_num: i32) {
+        self.push_event(
+            "job_done",
+            vec![("id", &id.to_string()), ("file_num", &file_num.to_string())],
+        );
+        self.activity.lock().unwrap().on_job_end(id);
+        self.push_session_activity(None, |unread| {
+            unread.transfers += 1;
+            true
+        });
+        self.check_background_finished();
+    }
+
+    // unused in flutter
+    fn clear_all_jobs(&self) {}
+
+    fn load_last_job(&self, _cnt: i32, job_json: &str) {
+        self.push_event("load_last_job", vec![("value", job_json)]);
+    }
+
+    fn update_folder_files(
+        &self,
+        id: i32,
+        entries: &Vec<FileEntry>,
+        path: String,
         #[allow(unused_variables)] is_local: bool,
         only_count: bool,
     ) {
@@ -576,6 +2003,95 @@ impl InvokeUiSession for FlutterHandler {
                 ("finished_size", &finished_size.to_string()),
             ],
         );
+        if self.activity.lock().unwrap().on_progress(id, Instant::now()) {
+            self.push_session_activity(None, |_| true);
+        }
+    }
+
+    fn transfer_summary(&self, summary: &crate::client::job_progress::TransferSummary) {
+        self.push_event(
+            "transfer_summary",
+            vec![
+                ("jobs", &summary.jobs.to_string()),
+                ("speed", &summary.speed.to_string()),
+                ("remaining", &summary.remaining.to_string()),
+                (
+                    "eta",
+                    &summary.eta.map(|eta| eta.to_string()).unwrap_or_default(),
+                ),
+            ],
+        );
+    }
+
+    fn job_renamed_files(&self, id: i32, renamed: &[hbb_common::fs::file_name::Renamed]) {
+        self.push_event(
+            "job_renamed_files",
+            vec![
+                ("id", &id.to_string()),
+                (
+                    "files",
+                    &serde_json::to_string(renamed).unwrap_or("[]".into()),
+                ),
+            ],
+        );
+    }
+
+    fn job_streams(&self, id: i32, streams: u32) {
+        self.push_event(
+            "job_streams",
+            vec![("id", &id.to_string()), ("streams", &streams.to_string())],
+        );
+    }
+
+    fn sync_state(&self, id: i32, state: &crate::client::file_sync::SyncState) {
+        use crate::client::file_sync::SyncState;
+        let (files, error) = match state {
+            SyncState::Syncing { files } => (files.to_string(), ""),
+            SyncState::Error { error } => ("".to_owned(), error.as_str()),
+            _ => ("".to_owned(), ""),
+        };
+        self.push_event(
+            "sync_state",
+            vec![
+                ("id", &id.to_string()),
+                ("state", state.name()),
+                ("files", &files),
+                ("error", error),
+            ],
+        );
+    }
+
+    fn file_op_progress(&self, id: i32, removed: u64, total: u64, is_remote: bool) {
+        self.push_event(
+            "file_op_progress",
+            vec![
+                ("id", &id.to_string()),
+                ("removed", &removed.to_string()),
+                ("total", &total.to_string()),
+                ("is_remote", &is_remote.to_string()),
+            ],
+        );
+    }
+
+    fn file_op_done(&self, id: i32, is_remote: bool) {
+        self.push_event(
+            "file_op_done",
+            vec![
+                ("id", &id.to_string()),
+                ("is_remote", &is_remote.to_string()),
+            ],
+        );
+    }
+
+    fn file_op_error(&self, id: i32, err: String, is_remote: bool) {
+        self.push_event(
+            "file_op_error",
+            vec![
+                ("id", &id.to_string()),
+                ("err", &err),
+                ("is_remote", &is_remote.to_string()),
+            ],
+        );
     }
 
     // unused in flutter
@@ -584,78 +2100,109 @@ impl InvokeUiSession for FlutterHandler {
     #[inline]
     #[cfg(not(feature = "flutter_texture_render"))]
     fn on_rgba(&self, display: usize, rgba: &mut scrap::ImageRgb) {
-        // Give a chance for plugins or etc to hook a rgba data.
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-        for (key, hook) in self.hooks.read().unwrap().iter() {
-            match hook {
-                SessionHook::OnSessionRgba(cb) => {
-                    cb(key.to_owned(), rgba);
-                }
-            }
-        }
-        // If the current rgba is not fetched by flutter, i.e., is valid.
-        // We give up sending a new event to flutter.
-        let mut rgba_write_lock = self.display_rgbas.write().unwrap();
-        if let Some(rgba_data) = rgba_write_lock.get_mut(&display) {
-            if rgba_data.valid {
-                return;
-            } else {
-                rgba_data.valid = true;
-            }
-            // Return the rgba buffer to the video handler for reusing allocated rgba buffer.
-            std::mem::swap::<Vec<u8>>(&mut rgba.raw, &mut rgba_data.data);
+        self.thumbnail.capture(rgba);
+        let mut downscaled = self.downscaled.lock().unwrap();
+        let rgba = if self.downscale.lock().unwrap().apply(rgba, &mut downscaled) {
+            downscaled.as_mut().unwrap()
         } else {
-            let mut rgba_data = RgbaData::default();
-            std::mem::swap::<Vec<u8>>(&mut rgba.raw, &mut rgba_data.data);
-            rgba_write_lock.insert(display, rgba_data);
+            rgba
+        };
+        if let Some(event) = self.video_watchdog.on_frame(display, Instant::now()) {
+            self.push_video_watchdog_event(event);
         }
-        drop(rgba_write_lock);
-
-        // Non-texture-render UI does not support multiple displays in the one UI session.
-        // It's Ok to notify each session for now.
-        for h in self.session_handlers.read().unwrap().values() {
-            if let Some(stream) = &h.event_stream {
-                stream.add(EventToUI::Rgba(display));
+        // Give a chance for plugins or etc to hook a rgba data.
+        self.run_session_hooks("rgba", |key, hook| {
+            if let SessionHook::OnSessionRgba(cb) = hook {
+                cb(key, rgba);
             }
+            HookAction::Continue
+        });
+        // Flutter has been notified if the previous frame is not fetched yet.
+        let notify = self
+            .display_rgbas
+            .write()
+            .unwrap()
+            .entry(display)
+            .or_default()
+            .push(&mut rgba.raw);
+        if notify {
+            self.notify_rgba(display);
         }
     }
 
     #[inline]
     #[cfg(feature = "flutter_texture_render")]
     fn on_rgba(&self, display: usize, rgba: &mut scrap::ImageRgb) {
-        let mut try_notify_sessions = Vec::new();
-        for (id, session) in self.session_handlers.read().unwrap().iter() {
-            session.renderer.on_rgba(display, rgba);
-            if !session.notify_rendered {
-                try_notify_sessions.push(id.clone());
-            }
+        let (viewport, resized) = {
+            let mut viewports = self.viewports.lock().unwrap();
+            (
+                viewports.is_active(display),
+                viewports.take_resized(display),
+            )
+        };
+        // The frames of a region are not the display.
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if !viewport {
+            self.thumbnail.capture(rgba);
         }
-        if try_notify_sessions.len() > 0 {
-            let mut write_lock = self.session_handlers.write().unwrap();
-            for id in try_notify_sessions.iter() {
-                if let Some(session) = write_lock.get_mut(id) {
-                    if let Some(stream) = &session.event_stream {
-                        stream.add(EventToUI::Rgba(display));
-                        session.notify_rendered = true;
-                    }
-                }
-            }
+        let mut downscaled = self.downscaled.lock().unwrap();
+        let rgba = if self.downscale.lock().unwrap().apply(rgba, &mut downscaled) {
+            downscaled.as_mut().unwrap()
+        } else {
+            rgba
+        };
+        if let Some(event) = self.video_watchdog.on_frame(display, Instant::now()) {
+            self.push_video_watchdog_event(event);
+        }
+        let frame_size = viewport.then_some((rgba.w, rgba.h));
+        for session in self.session_handlers.read().unwrap().values() {
+            if resized {
+                session.renderer.set_frame_size(display, frame_size);
+            }
+            session.renderer.on_rgba(display, rgba);
+            let Some(stream) = &session.event_stream else {
+                continue;
+            };
+            // Only the first frame after `notify_rendered` is reset is notified.
+            if session
+                .notify_rendered
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                stream.add(EventToUI::Rgba(display));
+            }
         }
     }
 
     fn set_peer_info(&self, pi: &PeerInfo) {
-        let displays = Self::make_displays_msg(&pi.displays);
-        let mut features: HashMap<&str, i32> = Default::default();
-        for ref f in pi.features.iter() {
-            features.insert("privacy_mode", if f.privacy_mode { 1 } else { 0 });
-        }
-        // compatible with 1.1.9
-        if get_version_number(&pi.version) < get_version_number("1.2.0") {
-            features.insert("privacy_mode", 0);
-        }
-        let features = serde_json::ser::to_string(&features).unwrap_or("".to_owned());
+        let mut sanitizer = peer_data::Sanitizer::new(peer_data::Limits::load());
+        let pi = &PeerInfo {
+            username: sanitizer.text("username", &pi.username),
+            hostname: sanitizer.text("hostname", &pi.hostname),
+            platform: sanitizer.text("platform", &pi.platform),
+            version: sanitizer.text("version", &pi.version),
+            platform_additions: sanitizer.additions("platform_additions", &pi.platform_additions),
+            ..pi.clone()
+        };
+        self.downscale.lock().unwrap().set_displays(&pi.displays);
+        let displays = Self::make_displays_msg(&self.ui_displays(&pi.displays));
+        let mut additions = serde_json::Map::new();
+        crate::common::merge_platform_additions(&mut additions, &pi.platform_additions);
+        let capabilities = crate::common::PeerCapabilities::new(
+            get_version_number(&pi.version),
+            &pi.platform,
+            &additions,
+        )
+        .with_features(pi.features.as_ref());
+        let features = serde_json::ser::to_string(&capabilities.features).unwrap_or_default();
         let resolutions = serialize_resolutions(&pi.resolutions.resolutions);
         *self.peer_info.write().unwrap() = pi.clone();
+        // The states of the last connection.
+        self.stateful_events.write().unwrap().clear();
+        // The cursor ids are only valid in one connection.
+        self.cursor_cache.write().unwrap().clear();
+        self.viewports.lock().unwrap().reset();
         #[cfg(feature = "flutter_texture_render")]
         {
             self.session_handlers
@@ -663,10 +2210,15 @@ impl InvokeUiSession for FlutterHandler {
                 .unwrap()
                 .values_mut()
                 .for_each(|h| {
-                    h.renderer.is_support_multi_ui_session =
-                        crate::common::is_support_multi_ui_session(&pi.version);
+                    h.renderer.is_support_multi_ui_session = capabilities.multi_ui_session;
                 });
         }
+        *self.peer_capabilities.write().unwrap() = capabilities;
+        self.session_handlers
+            .write()
+            .unwrap()
+            .values_mut()
+            .for_each(|h| h.follow_cursor.seed_display(pi.current_display as usize));
         self.push_event(
             "peer_info",
             vec![
@@ -682,79 +2234,145 @@ impl InvokeUiSession for FlutterHandler {
                 ("platform_additions", &pi.platform_additions),
             ],
         );
+        self.report_peer_data(sanitizer);
     }
 
     fn set_displays(&self, displays: &Vec<DisplayInfo>) {
-        self.peer_info.write().unwrap().displays = displays.clone();
-        self.push_event(
-            "sync_peer_info",
-            vec![("displays", &Self::make_displays_msg(displays))],
-        );
+        let diff = {
+            let mut peer_info = self.peer_info.write().unwrap();
+            let diff = DisplaysDiff::new(&peer_info.displays, displays);
+            peer_info.displays = displays.clone();
+            diff
+        };
+        // All the displays are pushed again if the scale is changed.
+        let diff = diff.filter(|_| !self.downscale.lock().unwrap().set_displays(displays));
+        let displays = &self.ui_displays(displays);
+        #[cfg(feature = "flutter_texture_render")]
+        for h in self.session_handlers.read().unwrap().values() {
+            h.renderer.on_displays_changed(displays);
+        }
+        match diff {
+            Some(diff) => {
+                if !diff.is_empty() {
+                    self.push_event(
+                        "displays_changed",
+                        vec![
+                            (
+                                "added",
+                                &Self::make_displays_changed_msg(displays, &diff.added),
+                            ),
+                            (
+                                "removed",
+                                &serde_json::ser::to_string(&diff.removed)
+                                    .unwrap_or("".to_owned()),
+                            ),
+                            (
+                                "modified",
+                                &Self::make_displays_changed_msg(displays, &diff.modified),
+                            ),
+                        ],
+                    );
+                }
+            }
+            None => {
+                self.push_event(
+                    "sync_peer_info",
+                    vec![("displays", &Self::make_displays_msg(displays))],
+                );
+            }
+        }
     }
 
     fn set_platform_additions(&self, data: &str) {
+        let mut sanitizer = peer_data::Sanitizer::new(peer_data::Limits::load());
+        let data = sanitizer.additions("platform_additions", data);
         self.push_event(
             "sync_platform_additions",
             vec![("platform_additions", &data)],
-        )
+        );
+        self.report_peer_data(sanitizer);
     }
 
     fn on_connected(&self, _conn_type: ConnType) {}
 
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str, retry: bool) {
-        let has_retry = if retry { "true" } else { "" };
-        self.push_event(
-            "msgbox",
-            vec![
-                ("type", msgtype),
-                ("title", title),
-                ("text", text),
-                ("link", link),
-                ("hasRetry", has_retry),
-            ],
-        );
+        let key = (msgtype.to_owned(), title.to_owned(), text.to_owned());
+        let body = MsgboxBody {
+            link: link.to_owned(),
+            params: None,
+        };
+        self.show_msgbox(key, body, retry);
+    }
+
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        params: &[(&str, String)],
+        link: &str,
+        retry: bool,
+    ) {
+        let params: serde_json::Map<String, serde_json::Value> = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), json!(v)))
+            .collect();
+        let key = (msgtype.to_owned(), title.to_owned(), text_key.to_owned());
+        let body = MsgboxBody {
+            link: link.to_owned(),
+            params: Some(serde_json::Value::Object(params).to_string()),
+        };
+        self.show_msgbox(key, body, retry);
     }
 
     fn cancel_msgbox(&self, tag: &str) {
+        self.msgbox_throttle.lock().unwrap().reset();
         self.push_event("cancel_msgbox", vec![("tag", tag)]);
     }
 
     fn new_message(&self, msg: String) {
+        let mut sanitizer = peer_data::Sanitizer::new(peer_data::Limits::load());
+        let msg = sanitizer.chat("text", &msg);
         self.push_event("chat_client_mode", vec![("text", &msg)]);
+        self.report_peer_data(sanitizer);
+        self.push_session_activity(None, |unread| {
+            unread.chat += 1;
+            true
+        });
     }
 
     fn switch_display(&self, display: &SwitchDisplay) {
+        let display = &self.ui_switch_display(display);
         let resolutions = serialize_resolutions(&display.resolutions.resolutions);
-        self.push_event(
-            "switch_display",
-            vec![
-                ("display", &display.display.to_string()),
-                ("x", &display.x.to_string()),
-                ("y", &display.y.to_string()),
-                ("width", &display.width.to_string()),
-                ("height", &display.height.to_string()),
-                (
-                    "cursor_embedded",
-                    &{
-                        if display.cursor_embedded {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    .to_string(),
-                ),
-                ("resolutions", &resolutions),
-                (
-                    "original_width",
-                    &display.original_resolution.width.to_string(),
-                ),
-                (
-                    "original_height",
-                    &display.original_resolution.height.to_string(),
-                ),
-            ],
-        );
+        let index = display.display.to_string();
+        let x = display.x.to_string();
+        let y = display.y.to_string();
+        let width = display.width.to_string();
+        let height = display.height.to_string();
+        let cursor_embedded = if display.cursor_embedded { 1 } else { 0 }.to_string();
+        let original_width = display.original_resolution.width.to_string();
+        let original_height = display.original_resolution.height.to_string();
+        let refresh_rate = display.refresh_rate.to_string();
+        let scale = display.scale.to_string();
+        let mut event: Vec<(&str, &str)> = vec![
+            ("display", &index),
+            ("x", &x),
+            ("y", &y),
+            ("width", &width),
+            ("height", &height),
+            ("cursor_embedded", &cursor_embedded),
+            ("resolutions", &resolutions),
+            ("original_width", &original_width),
+            ("original_height", &original_height),
+        ];
+        // Old peers do not send these fields, leave them absent.
+        if display.refresh_rate > 0 {
+            event.push(("refresh_rate", &refresh_rate));
+        }
+        if display.scale > 0.0 {
+            event.push(("scale", &scale));
+        }
+        self.push_event("switch_display", event);
     }
 
     fn update_block_input_state(&self, on: bool) {
@@ -769,8 +2387,27 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("clipboard", vec![("content", &content)]);
     }
 
-    fn switch_back(&self, peer_id: &str) {
-        self.push_event("switch_back", [("peer_id", peer_id)].into());
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    fn clipboard_truncated(&self, len: usize, max: usize) {
+        self.push_event(
+            "clipboard_truncated",
+            vec![("len", &len.to_string()), ("max", &max.to_string())],
+        );
+    }
+
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    fn text_clipboard_required(&self, required: bool) {
+        self.push_event(
+            "text_clipboard_required",
+            vec![("required", &required.to_string())],
+        );
+    }
+
+    fn switch_back_state(&self, peer_id: &str, state: &str) {
+        self.push_event(
+            "switch_back_state",
+            [("peer_id", peer_id), ("state", state)].into(),
+        );
     }
 
     fn portable_service_running(&self, running: bool) {
@@ -780,28 +2417,259 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn host_display_waking(&self, waking: bool) {
+        self.push_event("host_display_waking", vec![("waking", &waking.to_string())]);
+    }
+
+    fn host_display_state(&self, state: &str) {
+        self.video_watchdog.set_paused(
+            video_watchdog::PAUSE_HOST_DISPLAY,
+            state != "active",
+            Instant::now(),
+        );
+        if state != "active" {
+            self.push_stateful_event("host_display_state", Some(vec![("state", state)]));
+            return;
+        }
+        let was_inactive = self
+            .stateful_events
+            .read()
+            .unwrap()
+            .contains_key("host_display_state");
+        // Not replayed, the active state is the default.
+        self.push_stateful_event("host_display_state", None);
+        self.push_event("host_display_state", vec![("state", state)]);
+        if was_inactive {
+            // The first new frame is shown at once, instead of the frozen one.
+            for h in self.session_handlers.write().unwrap().values_mut() {
+                h.on_waiting_for_image_dialog_show();
+            }
+        }
+    }
+
+    fn view_only_blocked(&self) {
+        self.push_event("view_only_blocked", [].into());
+    }
+
+    fn hotkey_blocked(&self, rule: &str, keys: &str) {
+        self.push_event("hotkey_blocked", vec![("rule", rule), ("keys", keys)]);
+    }
+
+    fn on_video_congestion(&self, congested: bool) -> Option<Vec<i32>> {
+        let keep = self.capture_keep_displays();
+        let action =
+            self.capture_reduction
+                .lock()
+                .unwrap()
+                .on_sample(congested, &keep, Instant::now())?;
+        let (name, reason, set) = match action {
+            capture_reduction::ReduceAction::Reduce(set) => ("capture_reduced", "congestion", set),
+            capture_reduction::ReduceAction::Restore(set) => ("capture_restored", "improved", set),
+        };
+        log::info!("Capture displays {:?}, {}", set, reason);
+        *self.last_capture_displays.write().unwrap() = Some((set.clone(), Instant::now()));
+        self.push_event(
+            name,
+            vec![
+                ("displays", &serde_json::ser::to_string(&set).unwrap_or_default()),
+                ("reason", reason),
+            ],
+        );
+        Some(set)
+    }
+
+    fn update_view_only(&self, view_only: bool) {
+        self.push_event("update_view_only", vec![("view_only", &view_only.to_string())]);
+    }
+
+    fn network_probe_result(&self, result: &str) {
+        self.push_event("network_probe_result", vec![("result", result)]);
+    }
+
+    fn print_job_progress(&self, id: u32, name: &str, received: u64, total: u64) {
+        self.push_event(
+            "print_job_progress",
+            vec![
+                ("id", &id.to_string()),
+                ("name", name),
+                ("received", &received.to_string()),
+                ("total", &total.to_string()),
+            ],
+        );
+    }
+
+    fn print_job_received(&self, id: u32, name: &str, path: &str) {
+        self.push_event(
+            "print_job_received",
+            vec![("id", &id.to_string()), ("name", name), ("path", path)],
+        );
+    }
+
+    fn print_job_failed(&self, id: u32, name: &str, err: &str) {
+        self.push_event(
+            "print_job_failed",
+            vec![("id", &id.to_string()), ("name", name), ("error", err)],
+        );
+    }
+
+    fn staging_evicted(&self, files: &[crate::client::staging::StagedFile]) {
+        self.push_event(
+            "staging_evicted",
+            vec![("files", &serde_json::to_string(files).unwrap_or_default())],
+        );
+    }
+
+    fn remote_sysinfo(&self, sysinfo: &str, error: &str) {
+        self.push_event("remote_sysinfo", vec![("sysinfo", sysinfo), ("error", error)]);
+    }
+
+    fn remote_volume_changed(&self, state: &str, percent: u32, muted: bool) {
+        self.push_event(
+            "remote_volume_changed",
+            vec![
+                ("state", state),
+                ("percent", &percent.to_string()),
+                ("muted", &muted.to_string()),
+            ],
+        );
+    }
+
+    fn update_audio_only(&self, audio_only: bool) {
+        self.push_event("audio_only", vec![("audio_only", &audio_only.to_string())]);
+    }
+
+    fn remote_restarting(&self, state: &str, attempt: usize) {
+        self.push_event(
+            "remote_restarting",
+            vec![("state", state), ("attempt", &attempt.to_string())],
+        );
+    }
+
+    fn update_peer_capabilities(&self, capabilities: &crate::common::PeerCapabilities) {
+        *self.peer_capabilities.write().unwrap() = capabilities.clone();
+        let capabilities = serde_json::to_string(capabilities).unwrap_or_default();
+        self.push_event("peer_capabilities", vec![("capabilities", &capabilities)]);
+    }
+
+    fn codec_negotiated(&self, codec: &str, reason: &str) {
+        self.push_event("codec_negotiated", vec![("codec", codec), ("reason", reason)]);
+    }
+
+    fn decoder_info(&self, display: usize, info: &crate::client::decoder_select::DecoderInfo) {
+        self.push_event(
+            "decoder_info",
+            vec![
+                ("display", &display.to_string()),
+                ("codec", &info.codec),
+                ("hardware", &info.hardware.to_string()),
+                ("adapter", &info.adapter),
+                ("reason", info.reason),
+            ],
+        );
+    }
+
+    fn color_mode_fallback(&self, fallback: &crate::client::color_mode::Fallback) {
+        self.push_event(
+            "color_mode_fallback",
+            vec![
+                ("display", &fallback.display.to_string()),
+                ("reason", fallback.reason),
+                ("codec", &fallback.codec),
+                ("bitrate_factor", &fallback.bitrate_factor.to_string()),
+            ],
+        );
+    }
+
+    fn annotation_ack(&self, id: u32, error: &str) {
+        self.push_event(
+            "annotation_ack",
+            vec![("id", &id.to_string()), ("error", error)],
+        );
+    }
+
+    fn wol_result(&self, _mac: &str, _error: &str, _sent: u32) {
+        #[cfg(not(target_os = "ios"))]
+        wol_relay::on_result(_mac, _error, _sent);
+    }
+
+    fn input_policy(&self, level: &str, blocked: &[String], dropped: u64) {
+        self.push_event(
+            "input_policy",
+            vec![
+                ("level", level),
+                ("blocked", &blocked.join(",")),
+                ("dropped", &dropped.to_string()),
+            ],
+        );
+    }
+
+    fn host_throttled(&self, fps_percent: u32, cpu: u32, encode: u32) {
+        self.push_stateful_event(
+            "host_throttled",
+            Some(vec![
+                ("fps_percent", &fps_percent.to_string()),
+                ("cpu", &cpu.to_string()),
+                ("encode", &encode.to_string()),
+            ]),
+        );
+    }
+
+    fn frame_viewport(&self, display: usize, viewport: Option<&Viewport>) {
+        let viewports = {
+            let mut viewports = self.viewports.lock().unwrap();
+            if !viewports.on_frame(display, viewport) {
+                return;
+            }
+            let downscale = self.downscale.lock().unwrap();
+            viewports
+                .active()
+                .iter()
+                .map(|(d, r)| {
+                    let v = serde_json::json!({
+                        "x": downscale.to_ui(r.x),
+                        "y": downscale.to_ui(r.y),
+                        "width": downscale.to_ui(r.width),
+                        "height": downscale.to_ui(r.height),
+                    });
+                    (d.to_string(), v)
+                })
+                .collect::<serde_json::Map<_, _>>()
+        };
+        self.push_stateful_event(
+            "frame_viewports",
+            Some(vec![(
+                "viewports",
+                &serde_json::Value::Object(viewports).to_string(),
+            )]),
+        );
+    }
+
     fn on_voice_call_started(&self) {
         self.push_event("on_voice_call_started", [].into());
+        self.set_voice_call_activity(session_activity::VOICE_CALL_STARTED);
     }
 
     fn on_voice_call_closed(&self, reason: &str) {
         let _res = self.push_event("on_voice_call_closed", [("reason", reason)].into());
+        self.set_voice_call_activity(session_activity::VOICE_CALL_NONE);
     }
 
     fn on_voice_call_waiting(&self) {
         self.push_event("on_voice_call_waiting", [].into());
+        self.set_voice_call_activity(session_activity::VOICE_CALL_WAITING);
     }
 
     fn on_voice_call_incoming(&self) {
         self.push_event("on_voice_call_incoming", [].into());
+        self.set_voice_call_activity(session_activity::VOICE_CALL_INCOMING);
     }
 
     #[inline]
     fn get_rgba(&self, _display: usize) -> *const u8 {
         #[cfg(not(feature = "flutter_texture_render"))]
-        if let Some(rgba_data) = self.display_rgbas.read().unwrap().get(&_display) {
-            if rgba_data.valid {
-                return rgba_data.data.as_ptr();
+        if let Some(ring) = self.display_rgbas.write().unwrap().get_mut(&_display) {
+            if let Some(data) = ring.acquire() {
+                return data.as_ptr();
             }
         }
         std::ptr::null_mut()
@@ -810,35 +2678,206 @@ impl InvokeUiSession for FlutterHandler {
     #[inline]
     fn next_rgba(&self, _display: usize) {
         #[cfg(not(feature = "flutter_texture_render"))]
-        if let Some(rgba_data) = self.display_rgbas.write().unwrap().get_mut(&_display) {
-            rgba_data.valid = false;
+        {
+            let notify = self
+                .display_rgbas
+                .write()
+                .unwrap()
+                .get_mut(&_display)
+                .map_or(false, |ring| ring.release());
+            if notify {
+                self.notify_rgba(_display);
+            }
         }
     }
+
+    fn update_socks_stats(&self, port: i32, stats: &str) {
+        self.push_event(
+            "socks_stats",
+            vec![("port", &port.to_string()), ("stats", stats)],
+        );
+    }
+
+    fn update_port_forward_stats(&self, stats: &str) {
+        self.push_event("port_forward_stats", vec![("stats", stats)]);
+    }
+
+    fn hook_key_event(&self, evt: &KeyEvent) -> HookAction {
+        self.run_session_hooks("keyboard", |key, hook| match hook {
+            SessionHook::OnKeyboardEvent(cb) => cb(key, evt),
+            _ => HookAction::Continue,
+        })
+    }
+
+    fn hook_clipboard(&self, content: &mut String) -> HookAction {
+        self.run_session_hooks("clipboard", |key, hook| match hook {
+            SessionHook::OnClipboard(cb) => cb(key, content),
+            _ => HookAction::Continue,
+        })
+    }
+
+    fn hook_file_job_start(&self, job: &JobMeta) -> HookAction {
+        self.run_session_hooks("file_transfer", |key, hook| match hook {
+            SessionHook::OnFileJobStart(cb) => cb(key, job),
+            _ => HookAction::Continue,
+        })
+    }
 }
 
 // This function is only used for the default connection session.
 pub fn session_add_existed(peer_id: String, session_id: SessionID) -> ResultType<()> {
-    sessions::insert_peer_session_id(peer_id, ConnType::DEFAULT_CONN, session_id);
+    sessions::insert_peer_session_id(peer_id, ConnType::DEFAULT_CONN, session_id)?;
     Ok(())
 }
 
+/// How a ui session is added to the peer sessions by `sessions::insert_session`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionInsertion {
+    /// A new peer session is created, connected by `session_start_` of the ui session.
+    CreatedNew,
+    /// Attached to the existing peer session of the same peer and conn type, in the state of its
+    /// connection, so no new connection is made.
+    AttachedToExisting(ConnectionState),
+}
+
+impl SessionInsertion {
+    #[inline]
+    pub fn is_attached(&self) -> bool {
+        matches!(self, Self::AttachedToExisting(_))
+    }
+
+    /// The json for the ui, e.g. `{"attached":true,"state":"connected"}`.
+    pub fn to_json(&self) -> String {
+        let json = match self {
+            Self::CreatedNew => serde_json::json!({ "attached": false }),
+            Self::AttachedToExisting(state) => serde_json::json!({
+                "attached": true,
+                "state": match state {
+                    ConnectionState::Connecting => "connecting",
+                    ConnectionState::Connected => "connected",
+                    ConnectionState::Disconnected => "disconnected",
+                },
+            }),
+        };
+        json.to_string()
+    }
+}
+
+/// The modes a ui session is added with, shared by all the ui sessions of a peer session.
+#[derive(Debug, Clone, PartialEq)]
+struct SessionModes {
+    view_only: bool,
+    audio_only: bool,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    rdp_options: crate::port_forward::RdpOptions,
+}
+
+impl SessionModes {
+    fn of(session: &FlutterSession) -> Self {
+        let lc = session.lc.read().unwrap();
+        Self {
+            view_only: lc.view_only_session,
+            audio_only: lc.audio_only_session,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            rdp_options: session.rdp_options.read().unwrap().clone(),
+        }
+    }
+
+    /// A ui session is only attached to the peer session with the same modes.
+    fn check_attach(&self, existing: &FlutterSession) -> ResultType<()> {
+        let modes = Self::of(existing);
+        if *self != modes {
+            bail!(
+                "The session of {} is running with the modes {:?}, not {:?}",
+                existing.get_id(),
+                modes,
+                self
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The session added by `session_add`.
+pub struct AddedSession {
+    /// The existing peer session if attached to it.
+    pub session: FlutterSession,
+    pub insertion: SessionInsertion,
+}
+
+/// The options of `session_add`, the defaults are a remote desktop session.
+#[derive(Debug, Default, Clone)]
+pub struct SessionAddOptions {
+    /// If the session is used for file transfer.
+    pub is_file_transfer: bool,
+    /// If the session is used for port forward.
+    pub is_port_forward: bool,
+    /// If the port forward is a rdp session.
+    pub is_rdp: bool,
+    pub switch_uuid: String,
+    pub force_relay: bool,
+    pub password: String,
+    /// The json of [`crate::port_forward::RdpOptions`], only for rdp sessions.
+    pub rdp_options: String,
+    /// If the id should be recorded as the most recently used remote id.
+    /// Port forward reconnects to a peer that already has a running session are never recorded.
+    pub record_remote_id: bool,
+    /// If no input should be sent to the peer, only for default sessions.
+    pub view_only: bool,
+    /// If only the audio of the peer is received, only for default sessions.
+    pub audio_only: bool,
+    /// The address of the peer connected without the rendezvous server, see [`DirectTarget`].
+    pub direct_target: String,
+}
+
 /// Create a new remote session with the given id.
 ///
+/// An existing session with the same session id is reused, or replaced if it's being switched back.
+/// The ui session is attached to the existing peer session of the same peer and conn type, see
+/// [`SessionInsertion`]. It's an error if the existing one runs with other `view_only`, `audio_only`
+/// or `rdp_options`.
+///
 /// # Arguments
 ///
 /// * `id` - The identifier of the remote session with prefix. Regex: [\w]*[\_]*[\d]+
-/// * `is_file_transfer` - If the session is used for file transfer.
-/// * `is_port_forward` - If the session is used for port forward.
+/// * `options` - See [`SessionAddOptions`].
 pub fn session_add(
     session_id: &SessionID,
     id: &str,
-    is_file_transfer: bool,
-    is_port_forward: bool,
-    is_rdp: bool,
-    switch_uuid: &str,
-    force_relay: bool,
-    password: String,
-) -> ResultType<FlutterSession> {
+    options: SessionAddOptions,
+) -> ResultType<AddedSession> {
+    let SessionAddOptions {
+        is_file_transfer,
+        is_port_forward,
+        is_rdp,
+        switch_uuid,
+        force_relay,
+        password,
+        rdp_options,
+        record_remote_id,
+        view_only,
+        audio_only,
+        direct_target,
+    } = options;
+    // The id of the direct target, the rendezvous server is skipped.
+    let direct_id;
+    let id = if direct_target.is_empty() {
+        id
+    } else {
+        direct_id = DirectTarget::parse(&direct_target)?.to_id();
+        if !id.is_empty() && id != direct_id {
+            bail!("The id {} is not the direct target {}", id, direct_id);
+        }
+        direct_id.as_str()
+    };
+    // Also the direct targets in the recent peers.
+    if DirectTarget::is_direct_id(id) {
+        if force_relay {
+            bail!("Relay is unavailable for the direct target {}", id);
+        }
+        DirectTarget::parse(id)?;
+    }
+
     let conn_type = if is_file_transfer {
         ConnType::FILE_TRANSFER
     } else if is_port_forward {
@@ -851,40 +2890,100 @@ pub fn session_add(
         ConnType::DEFAULT_CONN
     };
 
-    // to-do: check the same id session.
+    if !rdp_options.is_empty() && conn_type != ConnType::RDP {
+        bail!("rdp options are only valid for rdp sessions");
+    }
+    if view_only && conn_type != ConnType::DEFAULT_CONN {
+        bail!("view-only is only valid for remote desktop sessions");
+    }
+    if audio_only && conn_type != ConnType::DEFAULT_CONN {
+        bail!("audio-only is only valid for remote desktop sessions");
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    if conn_type == ConnType::RDP {
+        bail!("RDP is unsupported on this platform");
+    }
+    let modes = SessionModes {
+        view_only,
+        audio_only,
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        rdp_options: crate::port_forward::RdpOptions::parse(&rdp_options)?,
+    };
+
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         if session.lc.read().unwrap().conn_type != conn_type {
             bail!("same session id is found with different conn type?");
         }
-        // The same session is added before?
-        bail!("same session id is found");
+        if !session.is_switching_back() {
+            if session.get_id() == id {
+                modes.check_attach(&session)?;
+                let state = session.connection_round_state.lock().unwrap().state();
+                return Ok(AddedSession {
+                    session,
+                    insertion: SessionInsertion::AttachedToExisting(state),
+                });
+            }
+            bail!("same session id is found");
+        }
+        // Replaced by the switched session.
+        session.close_event_stream(*session_id);
+        if let Some(old) = sessions::remove_switching_back_session(&session.get_id(), conn_type) {
+            old.close();
+        }
+    }
+    // The peer session being switched back is never reused by the switched one.
+    if let Some(old) = sessions::remove_switching_back_session(id, conn_type) {
+        log::info!("Replace the session of {} being switched back", id);
+        old.close();
     }
 
-    LocalConfig::set_remote_id(&id);
-
+    let is_internal_reconnect = conn_type == ConnType::PORT_FORWARD
+        && sessions::get_session_count(id.to_owned(), conn_type) > 0;
     let session: Session<FlutterHandler> = Session {
         password,
         server_keyboard_enabled: Arc::new(RwLock::new(true)),
         server_file_transfer_enabled: Arc::new(RwLock::new(true)),
         server_clipboard_enabled: Arc::new(RwLock::new(true)),
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        rdp_options: Arc::new(RwLock::new(modes.rdp_options)),
         ..Default::default()
     };
 
     let switch_uuid = if switch_uuid.is_empty() {
         None
     } else {
-        Some(switch_uuid.to_string())
+        Some(switch_uuid)
     };
 
-    session
-        .lc
-        .write()
-        .unwrap()
-        .initialize(id.to_owned(), conn_type, switch_uuid, force_relay);
+    {
+        let mut lc = session.lc.write().unwrap();
+        lc.initialize(id.to_owned(), conn_type, switch_uuid, force_relay);
+        lc.view_only_session = modes.view_only;
+        lc.audio_only_session = modes.audio_only;
+    }
     let session = Arc::new(session.clone());
-    sessions::insert_session(session_id.to_owned(), conn_type, session.clone());
+    let insertion = sessions::insert_session(session_id.to_owned(), conn_type, session.clone())?;
+    // The new one is dropped if attached to the existing one, which is already loaded.
+    let session = match insertion {
+        SessionInsertion::CreatedNew => {
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            if conn_type == ConnType::DEFAULT_CONN {
+                session.thumbnail.attach(id.to_owned(), session.lc.clone());
+            }
+            load_presenter_options(&session);
+            load_downscale_options(&session);
+            *session.meta.lock().unwrap() = session_meta::SessionMeta::load(id);
+            session
+        }
+        SessionInsertion::AttachedToExisting(_) => {
+            sessions::get_session_by_session_id(session_id).unwrap_or(session)
+        }
+    };
+    if record_remote_id && !is_internal_reconnect {
+        LocalConfig::set_remote_id(&id);
+    }
 
-    Ok(session)
+    Ok(AddedSession { session, insertion })
 }
 
 /// start a session with the given id.
@@ -896,20 +2995,22 @@ pub fn session_add(
 pub fn session_start_(
     session_id: &SessionID,
     id: &str,
-    event_stream: StreamSink<EventToUI>,
+    event_stream: impl EventSink<EventToUI> + 'static,
 ) -> ResultType<()> {
-    // is_connected is used to indicate whether to start a peer connection. For two cases:
-    // 1. "Move tab to new window"
-    // 2. multi ui session within the same peer connnection.
+    // The peer connection is started by the ui session creating the peer session, not if:
+    // 1. "Move tab to new window", `is_connected`.
+    // 2. multi ui session within the same peer connnection, `is_attached`.
     let mut is_connected = false;
+    let mut is_attached = false;
     let mut is_found = false;
-    for s in sessions::get_sessions() {
+    let session = sessions::get_session_by_session_id(session_id);
+    if let Some(s) = &session {
         if let Some(h) = s.session_handlers.write().unwrap().get_mut(session_id) {
             is_connected = h.event_stream.is_some();
+            is_attached = h.attached;
             try_send_close_event(&h.event_stream);
-            h.event_stream = Some(event_stream);
+            h.event_stream = Some(Arc::new(event_stream));
             is_found = true;
-            break;
         }
     }
     if !is_found {
@@ -920,9 +3021,12 @@ pub fn session_start_(
         );
     }
 
-    if let Some(session) = sessions::get_session_by_session_id(session_id) {
-        let is_first_ui_session = session.session_handlers.read().unwrap().len() == 1;
-        if !is_connected && is_first_ui_session {
+    if let Some(session) = session {
+        if is_connected || is_attached {
+            session.replay_stateful_events(session_id);
+            session.push_session_activity(Some(session_id), |_| true);
+        }
+        if !is_connected && !is_attached {
             #[cfg(feature = "flutter_texture_render")]
             log::info!(
                 "Session {} start, render by flutter texture rgba plugin",
@@ -931,11 +3035,11 @@ pub fn session_start_(
             #[cfg(not(feature = "flutter_texture_render"))]
             log::info!("Session {} start, render by flutter paint widget", id);
 
+            if session.lc.read().unwrap().conn_type == ConnType::DEFAULT_CONN {
+                start_video_watchdog(&session);
+            }
             let session = (*session).clone();
-            std::thread::spawn(move || {
-                let round = session.connection_round_state.lock().unwrap().new_round();
-                io_loop(session, round);
-            });
+            std::thread::spawn(move || run_io_loop(session, io_loop));
         }
         Ok(())
     } else {
@@ -943,19 +3047,142 @@ pub fn session_start_(
     }
 }
 
-#[inline]
-fn try_send_close_event(event_stream: &Option<StreamSink<EventToUI>>) {
-    if let Some(stream) = &event_stream {
-        stream.add(EventToUI::Event("close".to_owned()));
+// The panic message in the "session_panicked" event is truncated to this many chars.
+const MAX_PANIC_MESSAGE_LEN: usize = 256;
+
+thread_local! {
+    // Whether the thread runs an io loop, whose panics are hashed by the hook.
+    static IS_IO_LOOP_THREAD: std::cell::Cell<bool> = std::cell::Cell::new(false);
+    // The hash of the backtrace of the last panic of the io loop.
+    static IO_LOOP_BACKTRACE: std::cell::Cell<Option<u64>> = std::cell::Cell::new(None);
+}
+
+/// Run the io loop of a new round of the peer session. If it panics, the ui sessions get a
+/// "session_panicked" event and are closed, instead of waiting for a connection that never comes.
+/// The panic is only caught by unwinding, which the release profile keeps, see Cargo.toml.
+fn run_io_loop(session: Session<FlutterHandler>, f: impl FnOnce(Session<FlutterHandler>, u32)) {
+    install_io_loop_panic_hook();
+    let round = session.connection_round_state.lock().unwrap().new_round();
+    IS_IO_LOOP_THREAD.with(|b| b.set(true));
+    let cloned = session.clone();
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(cloned, round)));
+    IS_IO_LOOP_THREAD.with(|b| b.set(false));
+    if let Err(payload) = res {
+        let backtrace = IO_LOOP_BACKTRACE.with(|b| b.take());
+        on_io_loop_panic(&session, round, panic_message(payload.as_ref()), backtrace);
     }
+    sessions::on_io_loop_exit(&session);
 }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
+// The backtrace is only available in the hook, the previous hook still runs.
+fn install_io_loop_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if IS_IO_LOOP_THREAD.with(|b| b.get()) {
+                let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                std::hash::Hash::hash(&backtrace, &mut hasher);
+                IO_LOOP_BACKTRACE.with(|b| b.set(Some(std::hash::Hasher::finish(&hasher))));
+            }
+            prev(info);
+        }));
+    });
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown"
+    };
+    message.chars().take(MAX_PANIC_MESSAGE_LEN).collect()
+}
+
+// Close the ui sessions the same way as closed by the user.
+fn on_io_loop_panic(
+    session: &Session<FlutterHandler>,
+    round: u32,
+    message: String,
+    backtrace: Option<u64>,
+) {
+    let crashes = crate::common::add_session_crash();
+    let backtrace = backtrace.map(|h| format!("{:016x}", h)).unwrap_or_default();
+    log::error!(
+        "The io loop of {} panicked: {}, backtrace {}, {} sessions crashed",
+        session.get_id(),
+        message,
+        backtrace,
+        crashes
+    );
+    session
+        .connection_round_state
+        .lock()
+        .unwrap()
+        .end_round(round);
+    session.push_event(
+        "session_panicked",
+        vec![
+            ("type", "error"),
+            ("title", "Error"),
+            ("text", "session_panicked_tip"),
+            ("message", &message),
+            ("backtrace", &backtrace),
+        ],
+    );
+    let ids: Vec<_> = session
+        .session_handlers
+        .read()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect();
+    for id in ids {
+        session.close_event_stream(id);
+        sessions::remove_session_by_session_id(&id);
+    }
+    session.close();
+}
+
+// The timer of the video watchdog, exits once the session is removed.
+fn start_video_watchdog(session: &FlutterSession) {
+    let session = Arc::downgrade(session);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(video_watchdog::CHECK_INTERVAL);
+        let Some(session) = session.upgrade() else {
+            break;
+        };
+        let connected = session.connection_round_state.lock().unwrap().is_connected();
+        let privacy_mode = session.lc.read().unwrap().get_toggle_option("privacy-mode");
+        session.check_video_watchdog(connected, privacy_mode);
+    });
+}
+
+#[inline]
+fn try_send_close_event(event_stream: &Option<Arc<dyn EventSink<EventToUI>>>) {
+    if let Some(stream) = &event_stream {
+        stream.add(EventToUI::Event("close".to_owned()));
+    }
+}
+
+/// Update the required state of the text clipboard, after the permissions or the options changed.
+/// The clipboard service is shared by the sessions on desktop, the mobile ui reads the clipboard
+/// per session.
 pub fn update_text_clipboard_required() {
-    let is_required = sessions::get_sessions()
-        .iter()
-        .any(|s| s.is_text_clipboard_required());
-    Client::set_is_text_clipboard_required(is_required);
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        let is_required = sessions::get_sessions()
+            .iter()
+            .any(|s| s.is_text_clipboard_required());
+        Client::set_is_text_clipboard_required(is_required);
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    for s in sessions::get_sessions() {
+        s.text_clipboard_required(s.is_text_clipboard_required());
+    }
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -976,10 +3203,18 @@ pub mod connection_manager {
     use hbb_common::log;
     #[cfg(any(target_os = "android"))]
     use scrap::android::call_main_service_set_by_name;
+    #[cfg(target_os = "android")]
+    use std::sync::atomic::Ordering;
 
-    use crate::ui_cm_interface::InvokeUiCM;
+    use crate::ui_cm_interface::{
+        notify::{NotifyCategory, NotifyMode},
+        Client, InvokeUiCM,
+    };
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    use crate::ui_session_interface::HookAction;
 
-    use super::GLOBAL_EVENT_STREAM;
+    use super::{EventSink, GLOBAL_EVENT_STREAM};
 
     #[derive(Clone)]
     struct FlutterHandler {}
@@ -990,16 +3225,21 @@ pub mod connection_manager {
             let client_json = serde_json::to_string(&client).unwrap_or("".into());
             // send to Android service, active notification no matter UI is shown or not.
             #[cfg(any(target_os = "android"))]
-            if let Err(e) =
-                call_main_service_set_by_name("add_connection", Some(&client_json), None)
-            {
-                log::debug!("call_service_set_by_name fail,{}", e);
-            }
+            call_service(
+                super::service_calls::ADD_CONNECTION,
+                client_json.clone(),
+                Some(client.id),
+            );
             // send to UI, refresh widget
             self.push_event("add_connection", vec![("client", &client_json)]);
         }
 
         fn remove_connection(&self, id: i32, close: bool) {
+            #[cfg(target_os = "android")]
+            {
+                let outcomes = SERVICE_CALLS.lock().unwrap().remove_connection(id);
+                push_service_call_results(outcomes);
+            }
             self.push_event(
                 "on_client_remove",
                 vec![("id", &id.to_string()), ("close", &close.to_string())],
@@ -1030,9 +3270,93 @@ pub mod connection_manager {
             self.push_event("update_voice_call_state", vec![("client", &client_json)]);
         }
 
+        fn update_view_only_state(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_view_only_state", vec![("client", &client_json)]);
+        }
+
+        fn update_audio_only_state(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_audio_only_state", vec![("client", &client_json)]);
+        }
+
+        fn update_session_recording_state(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event(
+                "update_session_recording_state",
+                vec![("client", &client_json)],
+            );
+        }
+
+        fn update_permission_state(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_permission_state", vec![("client", &client_json)]);
+        }
+
         fn file_transfer_log(&self, action: &str, log: &str) {
             self.push_event("cm_file_transfer_log", vec![(action, log)]);
         }
+
+        fn action_log(&self, id: i32, action: &str, log: &str) {
+            self.push_event(
+                "cm_action_log",
+                vec![("id", &id.to_string()), ("action", action), ("log", log)],
+            );
+        }
+
+        fn notify(&self, category: NotifyCategory, mode: NotifyMode, client: &Client) {
+            let id = client.id.to_string();
+            let event = vec![
+                ("category", category.name()),
+                ("mode", mode.name()),
+                ("id", &id),
+                ("peer_id", &client.peer_id),
+                ("peer_name", &client.name),
+            ];
+            // The notification shows up even if the ui is not open.
+            #[cfg(any(target_os = "android"))]
+            {
+                let h: HashMap<&str, &str> = event.iter().cloned().collect();
+                let json = serde_json::to_string(&h).unwrap_or("".into());
+                call_service("cm_notify", json, None);
+            }
+            self.push_event("cm_notify", event);
+        }
+
+        fn update_permission_grant(&self, id: i32, name: &str, secs: u64) {
+            self.push_event(
+                "update_permission_grant",
+                vec![
+                    ("id", &id.to_string()),
+                    ("name", name),
+                    ("secs", &secs.to_string()),
+                ],
+            );
+        }
+
+        fn chat_delivery(&self, id: i32, seq: u64, error: &str) {
+            self.push_event(
+                "cm_chat_delivery",
+                vec![
+                    ("id", &id.to_string()),
+                    ("seq", &seq.to_string()),
+                    ("error", error),
+                ],
+            );
+        }
+
+        fn watch_frame(&self, id: i32, display: i32, width: usize, height: usize, data: &str) {
+            self.push_event(
+                "cm_watch_frame",
+                vec![
+                    ("id", &id.to_string()),
+                    ("display", &display.to_string()),
+                    ("width", &width.to_string()),
+                    ("height", &height.to_string()),
+                    ("data", data),
+                ],
+            );
+        }
     }
 
     impl FlutterHandler {
@@ -1088,6 +3412,81 @@ pub mod connection_manager {
         start_listen_ipc_thread();
     }
 
+    #[cfg(target_os = "android")]
+    lazy_static::lazy_static! {
+        static ref SERVICE_CALLS: std::sync::Mutex<super::service_calls::ServiceCallQueue> =
+            Default::default();
+        static ref SERVICE_RETRY_RUNNING: std::sync::atomic::AtomicBool = Default::default();
+    }
+
+    #[cfg(target_os = "android")]
+    fn service_call(name: &str, arg: Option<&str>) -> hbb_common::ResultType<()> {
+        call_main_service_set_by_name(name, arg, None).map_err(|e| {
+            log::warn!("Failed to call the service {}, {}", name, e);
+            e
+        })
+    }
+
+    #[cfg(target_os = "android")]
+    fn push_service_call_results(outcomes: Vec<super::service_calls::CallOutcome>) {
+        for outcome in outcomes {
+            log::info!("The service call result: {:?}", outcome);
+            let event = outcome.event();
+            FlutterHandler {}.push_event(
+                "service_call_result",
+                event.iter().map(|(k, v)| (*k, v.as_str())).collect(),
+            );
+        }
+    }
+
+    // Call the android service, retried if failed, see `service_calls`.
+    #[cfg(target_os = "android")]
+    fn call_service(name: &str, arg: String, conn_id: Option<i32>) {
+        let mut queue = SERVICE_CALLS.lock().unwrap();
+        queue.call(
+            name,
+            Some(arg),
+            conn_id,
+            std::time::Instant::now(),
+            &mut service_call,
+        );
+        if queue.is_empty() {
+            return;
+        }
+        drop(queue);
+        if SERVICE_RETRY_RUNNING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        std::thread::spawn(|| loop {
+            let next = {
+                let mut queue = SERVICE_CALLS.lock().unwrap();
+                let outcomes = queue.retry_due(std::time::Instant::now(), &mut service_call);
+                let next = queue.next_due();
+                if next.is_none() {
+                    // Set while holding the lock, so a new call starts the thread again.
+                    SERVICE_RETRY_RUNNING.store(false, Ordering::SeqCst);
+                }
+                drop(queue);
+                push_service_call_results(outcomes);
+                next
+            };
+            let Some(next) = next else {
+                break;
+            };
+            std::thread::sleep(next.saturating_duration_since(std::time::Instant::now()));
+        });
+    }
+
+    /// The service is ready, the pending calls are tried again at once.
+    #[cfg(target_os = "android")]
+    pub fn on_service_ready() {
+        let outcomes = SERVICE_CALLS
+            .lock()
+            .unwrap()
+            .drain(std::time::Instant::now(), &mut service_call);
+        push_service_call_results(outcomes);
+    }
+
     #[cfg(target_os = "android")]
     use hbb_common::tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
@@ -1102,6 +3501,135 @@ pub mod connection_manager {
         };
         std::thread::spawn(move || start_listen(cm, rx, tx));
     }
+
+    /// Hooks for the connection manager, parallel to [`super::SessionHook`].
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    #[derive(Clone)]
+    pub enum CmHook {
+        /// Called before the connection is added to the cm, `Block` rejects the connection.
+        OnIncomingConnection(fn(&Client) -> HookAction),
+        OnConnectionRemoved(fn(&Client)),
+        /// Called with the chat message from the peer, `Block` drops the message.
+        OnCmChatMessage(fn(&Client, &str) -> HookAction),
+    }
+
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    impl CmHook {
+        pub fn kind(&self) -> &'static str {
+            match self {
+                CmHook::OnIncomingConnection(_) => "incoming_connection",
+                CmHook::OnConnectionRemoved(_) => "connection_removed",
+                CmHook::OnCmChatMessage(_) => "chat",
+            }
+        }
+    }
+
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    lazy_static::lazy_static! {
+        // (key, priority, hook), ordered by the priority, smaller runs first.
+        static ref CM_HOOKS: std::sync::RwLock<Vec<(String, i32, CmHook)>> = Default::default();
+    }
+
+    /// Returns false if there's already the same kind of hook with this key.
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn add_cm_hook(key: String, priority: i32, hook: CmHook) -> bool {
+        let mut hooks = CM_HOOKS.write().unwrap();
+        if hooks
+            .iter()
+            .any(|(k, _, h)| k == &key && h.kind() == hook.kind())
+        {
+            return false;
+        }
+        let pos = hooks.partition_point(|(_, p, _)| *p <= priority);
+        hooks.insert(pos, (key, priority, hook));
+        true
+    }
+
+    /// Remove all the cm hooks with this key.
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn remove_cm_hook(key: &str) -> bool {
+        let mut hooks = CM_HOOKS.write().unwrap();
+        let len = hooks.len();
+        hooks.retain(|(k, _, _)| k != key);
+        hooks.len() != len
+    }
+
+    /// Run the cm hooks in order, returns the key of the plugin blocking the action.
+    /// The hooks run out of the lock, so a hook may add or remove the hooks.
+    /// Panics of the hooks are not caught.
+    #[cfg(feature = "plugin_framework")]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub(crate) fn run_cm_hooks(mut f: impl FnMut(&CmHook) -> HookAction) -> Option<String> {
+        let hooks = CM_HOOKS.read().unwrap().clone();
+        for (key, _, hook) in hooks {
+            if f(&hook) == HookAction::Block {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::flutter::{set_global_event_sink, EventCollector, APP_TYPE_CM};
+        use std::sync::Arc;
+
+        #[test]
+        fn test_add_connection_event() {
+            let collector: Arc<EventCollector<String>> = Default::default();
+            set_global_event_sink(APP_TYPE_CM, Box::new(collector.clone()));
+            let client = crate::ui_cm_interface::Client::new_for_test(1, "123456789");
+            FlutterHandler {}.add_connection(&client);
+            let events = collector.take();
+            assert_eq!(events.len(), 1);
+            let event: serde_json::Value = serde_json::from_str(&events[0]).unwrap();
+            assert_eq!(event["name"], "add_connection");
+            let client: serde_json::Value =
+                serde_json::from_str(event["client"].as_str().unwrap()).unwrap();
+            assert_eq!(client["id"], 1);
+            assert_eq!(client["peer_id"], "123456789");
+            assert!(client.get("tx").is_none());
+        }
+
+        #[test]
+        #[cfg(feature = "plugin_framework")]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        fn test_cm_hooks_reentrant() {
+            // The hook replaces itself, the new one runs from the next time.
+            assert!(add_cm_hook(
+                "reentrant_a".to_owned(),
+                0,
+                CmHook::OnConnectionRemoved(|_| {
+                    remove_cm_hook("reentrant_a");
+                    add_cm_hook(
+                        "reentrant_b".to_owned(),
+                        0,
+                        CmHook::OnCmChatMessage(|_, _| HookAction::Block),
+                    );
+                }),
+            ));
+            let client = crate::ui_cm_interface::Client::new_for_test(1, "123456789");
+            let run = || {
+                run_cm_hooks(|hook| match hook {
+                    CmHook::OnConnectionRemoved(f) => {
+                        f(&client);
+                        HookAction::Continue
+                    }
+                    CmHook::OnCmChatMessage(f) => f(&client, ""),
+                    _ => HookAction::Continue,
+                })
+            };
+            assert_eq!(run(), None);
+            assert_eq!(run(), Some("reentrant_b".to_owned()));
+            assert!(remove_cm_hook("reentrant_b"));
+        }
+    }
 }
 
 pub fn make_fd_flutter(id: i32, entries: &Vec<FileEntry>, only_count: bool) -> String {
@@ -1140,7 +3668,9 @@ pub fn get_cur_peer_id() -> String {
         .unwrap_or("".to_string())
 }
 
+/// Set the current session, which is also the most recently focused one.
 pub fn set_cur_session_id(session_id: SessionID) {
+    sessions::set_focused(&session_id);
     if get_cur_session_id() != session_id {
         *CUR_SESSION_ID.write().unwrap() = session_id;
     }
@@ -1152,6 +3682,8 @@ fn serialize_resolutions(resolutions: &Vec<Resolution>) -> String {
     struct ResolutionSerde {
         width: i32,
         height: i32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        refresh_rate: Option<i32>,
     }
 
     let mut v = vec![];
@@ -1161,6 +3693,11 @@ fn serialize_resolutions(resolutions: &Vec<Resolution>) -> String {
             v.push(ResolutionSerde {
                 width: r.width,
                 height: r.height,
+                refresh_rate: if r.refresh_rate > 0 {
+                    Some(r.refresh_rate)
+                } else {
+                    None
+                },
             })
         })
         .count();
@@ -1177,6 +3714,22 @@ fn char_to_session_id(c: *const char) -> ResultType<SessionID> {
 }
 
 pub fn session_get_rgba_size(_session_id: SessionID, _display: usize) -> usize {
+    #[cfg(not(feature = "flutter_texture_render"))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        // The frame is acquired here, so that the size matches the following `session_get_rgba`.
+        return session
+            .display_rgbas
+            .write()
+            .unwrap()
+            .get_mut(&_display)
+            .and_then(|ring| ring.acquire().map(|data| data.len()))
+            .unwrap_or_default();
+    }
+    0
+}
+
+/// The number of the frames dropped because the ui is slower than the video.
+pub fn session_get_dropped_frames(_session_id: SessionID, _display: usize) -> u64 {
     #[cfg(not(feature = "flutter_texture_render"))]
     if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
         return session
@@ -1184,7 +3737,7 @@ pub fn session_get_rgba_size(_session_id: SessionID, _display: usize) -> usize {
             .read()
             .unwrap()
             .get(&_display)
-            .map_or(0, |rgba| rgba.data.len());
+            .map_or(0, |ring| ring.dropped);
     }
     0
 }
@@ -1209,7 +3762,12 @@ pub fn session_next_rgba(session_id: SessionID, display: usize) {
 #[inline]
 pub fn session_set_size(_session_id: SessionID, _display: usize, _width: usize, _height: usize) {
     #[cfg(feature = "flutter_texture_render")]
-    for s in sessions::get_sessions() {
+    if let Some(s) = sessions::get_session_by_session_id(&_session_id) {
+        let displays = if _display == COMPOSITE_DISPLAY {
+            Some(s.ui_handler.peer_info.read().unwrap().displays.clone())
+        } else {
+            None
+        };
         if let Some(h) = s
             .ui_handler
             .session_handlers
@@ -1217,9 +3775,12 @@ pub fn session_set_size(_session_id: SessionID, _display: usize, _width: usize,
             .unwrap()
             .get_mut(&_session_id)
         {
-            h.notify_rendered = false;
-            h.renderer.set_size(_display, _width, _height);
-            break;
+            h.notify_rendered.store(false, Ordering::SeqCst);
+            if let Some(displays) = displays {
+                h.renderer.set_composite_size(&displays, _width, _height);
+            } else {
+                h.renderer.set_size(_display, _width, _height);
+            }
         }
     }
 }
@@ -1227,18 +3788,38 @@ pub fn session_set_size(_session_id: SessionID, _display: usize, _width: usize,
 #[inline]
 pub fn session_register_texture(_session_id: SessionID, _display: usize, _ptr: usize) {
     #[cfg(feature = "flutter_texture_render")]
-    for s in sessions::get_sessions() {
-        if let Some(h) = s
+    if let Some(s) = sessions::get_session_by_session_id(&_session_id) {
+        let replaced = s
             .ui_handler
             .session_handlers
             .read()
             .unwrap()
             .get(&_session_id)
-        {
-            h.renderer.register_texture(_display, _ptr);
-            break;
+            .map_or(false, |h| h.renderer.register_texture(_display, _ptr));
+        if replaced {
+            // Flutter requests a fresh frame for the new texture.
+            let display = _display.to_string();
+            s.push_event_to(&_session_id, "texture_replaced", vec![("display", &display)]);
+        }
+    }
+}
+
+/// Wait until the texture unregistered or replaced is not rendered any more, then flutter can free
+/// it. Returns false on timeout.
+pub fn wait_texture_released(_ptr: usize, _timeout: Duration) -> bool {
+    #[cfg(feature = "flutter_texture_render")]
+    {
+        let refs = TEXTURE_REFS.0.lock().unwrap();
+        let (_refs, res) = TEXTURE_REFS
+            .1
+            .wait_timeout_while(refs, _timeout, |refs| refs.contains_key(&_ptr))
+            .unwrap();
+        if res.timed_out() {
+            log::warn!("The texture {:x} is not released in {:?}", _ptr, _timeout);
+            return false;
         }
     }
+    true
 }
 
 #[inline]
@@ -1248,11 +3829,108 @@ pub fn push_session_event(session_id: &SessionID, name: &str, event: Vec<(&str,
     }
 }
 
+/// Push an event of the plugin to the ui sessions of `session_id`, or to the main window
+/// if `session_id` is `None`.
+///
+/// The payload must be a json object with a string "name", which is namespaced as
+/// "plugin|<plugin_key>|<name>" so that it never collides with the core events.
+pub fn push_plugin_event(
+    plugin_key: &str,
+    session_id: Option<SessionID>,
+    payload_json: &str,
+) -> ResultType<()> {
+    let (name, event) = make_plugin_event(plugin_key, payload_json)?;
+    match session_id {
+        Some(session_id) => {
+            let Some(s) = sessions::get_session_by_session_id(&session_id) else {
+                bail!("Session {} not found", session_id);
+            };
+            s.push_raw_event(&name, event);
+        }
+        None => {
+            if push_global_event(APP_TYPE_MAIN, event).is_none() {
+                bail!("No {} event stream found", APP_TYPE_MAIN);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Returns the namespaced name and the event.
+fn make_plugin_event(plugin_key: &str, payload_json: &str) -> ResultType<(String, String)> {
+    if plugin_key.is_empty() || plugin_key.contains('|') {
+        bail!("Invalid plugin key \"{}\"", plugin_key);
+    }
+    let mut payload = match serde_json::from_str(payload_json) {
+        Ok(serde_json::Value::Object(payload)) => payload,
+        _ => bail!("The plugin event payload is not a json object"),
+    };
+    let name = match payload.get("name") {
+        Some(serde_json::Value::String(name)) if !name.is_empty() => {
+            format!("{}{}|{}", PLUGIN_EVENT_PREFIX, plugin_key, name)
+        }
+        _ => bail!("The plugin event payload has no name"),
+    };
+    payload.insert("name".to_owned(), json!(name));
+    let event = serde_json::Value::Object(payload).to_string();
+    Ok((name, event))
+}
+
 #[inline]
 pub fn push_global_event(channel: &str, event: String) -> Option<bool> {
     Some(GLOBAL_EVENT_STREAM.read().unwrap().get(channel)?.add(event))
 }
 
+// The ui sessions of the same peer may connect in a burst, with the same changes.
+const PEER_CARD_UPDATE_THROTTLE: Duration = Duration::from_secs(3);
+
+// The "peer_card_updated" event of the changed fields, `None` if nothing is changed,
+// or the same event of the peer is pushed within `PEER_CARD_UPDATE_THROTTLE`.
+fn peer_card_updated_event(
+    id: &str,
+    old: &hbb_common::config::PeerInfoSerde,
+    new: &hbb_common::config::PeerInfoSerde,
+) -> Option<String> {
+    let mut changes = serde_json::Map::new();
+    for (key, old, new) in [
+        ("platform", &old.platform, &new.platform),
+        ("hostname", &old.hostname, &new.hostname),
+        ("username", &old.username, &new.username),
+    ] {
+        if old != new {
+            changes.insert(key.to_owned(), json!(new));
+        }
+    }
+    if changes.is_empty() {
+        return None;
+    }
+    let event = json!({
+        "name": "peer_card_updated",
+        "id": id,
+        "changes": changes,
+    })
+    .to_string();
+    let mut updates = PEER_CARD_UPDATES.lock().unwrap();
+    updates.retain(|_, (t, _)| t.elapsed() < PEER_CARD_UPDATE_THROTTLE);
+    if updates.get(id).map(|(_, e)| e == &event).unwrap_or(false) {
+        return None;
+    }
+    updates.insert(id.to_owned(), (Instant::now(), event.clone()));
+    Some(event)
+}
+
+/// Push "peer_card_updated" to the main window if the stored info of the peer is changed,
+/// so the peer card is updated in place.
+pub(crate) fn push_peer_card_updated(
+    id: &str,
+    old: &hbb_common::config::PeerInfoSerde,
+    new: &hbb_common::config::PeerInfoSerde,
+) {
+    if let Some(event) = peer_card_updated_event(id, old, new) {
+        let _ = push_global_event(APP_TYPE_MAIN, event);
+    }
+}
+
 #[inline]
 pub fn get_global_event_channels() -> Vec<String> {
     GLOBAL_EVENT_STREAM
@@ -1265,22 +3943,91 @@ pub fn get_global_event_channels() -> Vec<String> {
 
 pub fn start_global_event_stream(s: StreamSink<String>, app_type: String) -> ResultType<()> {
     let app_type_values = app_type.split(",").collect::<Vec<&str>>();
+    if app_type_values[0] == APP_TYPE_MAIN && crate::common::has_startup_diagnostics() {
+        let event = json!({
+            "name": "startup_diagnostics",
+            "diagnostics": crate::common::get_startup_diagnostics(),
+        });
+        s.add(event.to_string());
+    }
+    if app_type_values[0] == APP_TYPE_MAIN {
+        if let Some(event) = crate::state_journal::recovered_event() {
+            s.add(event);
+        }
+    }
     let mut lock = GLOBAL_EVENT_STREAM.write().unwrap();
     if !lock.contains_key(app_type_values[0]) {
-        lock.insert(app_type_values[0].to_string(), s);
+        lock.insert(app_type_values[0].to_string(), Box::new(s));
     } else {
-        if let Some(_) = lock.insert(app_type.clone(), s) {
+        if let Some(_) = lock.insert(app_type.clone(), Box::new(s)) {
             log::warn!(
                 "Global event stream of type {} is started before, but now removed",
                 app_type
             );
         }
     }
+    drop(lock);
+    if app_type_values[0] == APP_TYPE_LOG {
+        start_log_stream();
+    }
     Ok(())
 }
 
+/// Replace the global event stream with a collector, see [`EventCollector`].
+#[cfg(test)]
+pub(crate) fn set_global_event_sink(app_type: &str, sink: Box<dyn EventSink<String>>) {
+    GLOBAL_EVENT_STREAM
+        .write()
+        .unwrap()
+        .insert(app_type.to_owned(), sink);
+}
+
 pub fn stop_global_event_stream(app_type: String) {
     let _ = GLOBAL_EVENT_STREAM.write().unwrap().remove(&app_type);
+    if app_type == APP_TYPE_LOG {
+        hbb_common::log_ring::set_listener(None);
+    }
+}
+
+pub fn log_record_to_json(r: &hbb_common::log_ring::LogRecord) -> serde_json::Value {
+    json!({
+        "level": r.level.as_str(),
+        "timestamp": r.timestamp,
+        "target": r.target,
+        "message": r.message,
+    })
+}
+
+// Push the new log records to the "log" channel, at most `MAX_LOG_RECORDS_PER_SEC`.
+// The records are received in this thread, the logging thread never touches the event streams.
+fn start_log_stream() {
+    let (tx, rx) = std::sync::mpsc::sync_channel(LOG_RECORD_QUEUE_SIZE);
+    // The previous thread exits when its sender is replaced.
+    hbb_common::log_ring::set_listener(Some(tx));
+    std::thread::spawn(move || {
+        let mut window = Instant::now();
+        let (mut sent, mut dropped) = (0, 0);
+        while let Ok(record) = rx.recv() {
+            if window.elapsed() >= Duration::from_secs(1) {
+                if dropped > 0 {
+                    let event = json!({ "name": "log_records_dropped", "count": dropped });
+                    let _ = push_global_event(APP_TYPE_LOG, event.to_string());
+                }
+                window = Instant::now();
+                (sent, dropped) = (0, 0);
+            }
+            if sent >= MAX_LOG_RECORDS_PER_SEC {
+                dropped += 1;
+                continue;
+            }
+            sent += 1;
+            let mut event = log_record_to_json(&record);
+            event["name"] = json!("log_record");
+            if push_global_event(APP_TYPE_LOG, event.to_string()).is_none() {
+                break;
+            }
+        }
+    });
 }
 
 #[inline]
@@ -1318,6 +4065,7 @@ fn session_send_touch_pan(
             v.get("y").and_then(|y| y.as_i64()),
         ) {
             (Some(x), Some(y)) => {
+                let (x, y) = session_composite_to_remote(&session_id, x as _, y as _);
                 if let Some(session) = sessions::get_session_by_session_id(&session_id) {
                     session
                         .send_touch_pan_event(pan_event, x as _, y as _, alt, ctrl, shift, command);
@@ -1362,22 +4110,229 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
     }
 }
 
-#[inline]
-pub fn session_on_waiting_for_image_dialog_show(session_id: SessionID) {
-    for s in sessions::get_sessions() {
-        if let Some(h) = s.session_handlers.write().unwrap().get_mut(&session_id) {
-            h.on_waiting_for_image_dialog_show();
+/// Translate a point of the ui to the remote coordinates, see `session_composite_to_remote`
+/// and `downscale`.
+pub fn session_ui_to_remote(session_id: &SessionID, x: i32, y: i32) -> (i32, i32) {
+    let (rx, ry) = session_composite_to_remote(session_id, x, y);
+    if (rx, ry) != (x, y) {
+        return (rx, ry);
+    }
+    match sessions::get_session_by_session_id(session_id) {
+        Some(s) => {
+            let downscale = s.downscale.lock().unwrap();
+            (downscale.to_remote(x), downscale.to_remote(y))
         }
+        None => (x, y),
     }
 }
 
-/// Hooks for session.
-#[derive(Clone)]
-pub enum SessionHook {
-    OnSessionRgba(fn(String, &mut scrap::ImageRgb)),
-}
-
-#[inline]
+/// Translate a point in the composite view to the remote coordinates.
+/// The point is returned unchanged if the ui session is not showing the composite view.
+pub fn session_composite_to_remote(_session_id: &SessionID, x: i32, y: i32) -> (i32, i32) {
+    #[cfg(feature = "flutter_texture_render")]
+    if let Some(s) = sessions::get_session_by_session_id(_session_id) {
+        if let Some(h) = s.session_handlers.read().unwrap().get(_session_id) {
+            if let Some(p) = h.renderer.composite_to_remote(x, y) {
+                return p;
+            }
+        }
+    }
+    (x, y)
+}
+
+/// Load the downscale option from the peer options, see `downscale`.
+pub fn load_downscale_options(session: &FlutterSession) {
+    let max_dim = downscale::max_dimension_of_option(
+        &session.get_option(downscale::OPTION_DOWNSCALE_FRAMES.to_owned()),
+    );
+    if !session.downscale.lock().unwrap().set_max_dimension(max_dim) {
+        return;
+    }
+    // Push the displays in the new scale, the ui lays out again.
+    let pi = session.peer_info.read().unwrap().clone();
+    if !pi.displays.is_empty() {
+        session.push_event(
+            "sync_peer_info",
+            vec![(
+                "displays",
+                &FlutterHandler::make_displays_msg(&session.ui_displays(&pi.displays)),
+            )],
+        );
+    }
+}
+
+/// Load the presenter mode options from the peer options.
+pub fn load_presenter_options(session: &FlutterSession) {
+    let color = session.get_option("presenter-highlight-color".to_owned());
+    let size = session
+        .get_option("presenter-highlight-size".to_owned())
+        .parse()
+        .unwrap_or(DEFAULT_PRESENTER_HIGHLIGHT_SIZE);
+    let mut presenter = session.presenter.write().unwrap();
+    presenter.enabled = session.get_option("presenter-mode".to_owned()) == "Y";
+    presenter.color = if color.is_empty() {
+        DEFAULT_PRESENTER_HIGHLIGHT_COLOR.to_owned()
+    } else {
+        color
+    };
+    presenter.size = size;
+}
+
+/// Get the "cursor_data" event of the cached cursor, e.g. the ui misses the cursor after a window move.
+pub fn session_get_cached_cursor(session_id: SessionID, id: u64) -> Option<String> {
+    let s = sessions::get_session_by_session_id(&session_id)?;
+    let handlers = s.session_handlers.read().unwrap();
+    let h = handlers.get(&session_id)?;
+    let mut cache = s.cursor_cache.write().unwrap();
+    let cursor = cache.get(id)?;
+    Some(s.cursor_data_event_for(h, cursor))
+}
+
+/// Set the scale factor of the local display showing the ui session, to scale the cursor images.
+/// The cursor images are not scaled if `raw` is true, or `scale` is not positive.
+pub fn session_set_cursor_scale(session_id: SessionID, scale: f64, raw: bool) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Some(h) = s.session_handlers.write().unwrap().get_mut(&session_id) {
+            h.cursor_scale = scale;
+            h.raw_cursor = raw;
+        }
+    }
+}
+
+/// Set the events not pushed to the ui session.
+pub fn session_set_event_filter(session_id: SessionID, events: Vec<String>) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Some(h) = s.session_handlers.write().unwrap().get_mut(&session_id) {
+            h.filtered_events = events.into_iter().collect();
+        }
+    }
+}
+
+pub fn session_mark_read(session_id: SessionID, category: String) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        s.mark_activity_read(&session_id, &category);
+    }
+}
+
+/// The session hooks in the running order, for diagnostics.
+pub fn session_list_hooks(session_id: SessionID) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        let hooks: Vec<_> = s
+            .ui_handler
+            .list_session_hooks()
+            .into_iter()
+            .map(|(key, kind, priority)| json!({"key": key, "kind": kind, "priority": priority}))
+            .collect();
+        return serde_json::to_string(&hooks).unwrap_or_default();
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let _ = session_id;
+    "[]".to_owned()
+}
+
+pub fn session_set_follow_remote_cursor(session_id: SessionID, enabled: bool) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Some(h) = s.session_handlers.write().unwrap().get_mut(&session_id) {
+            h.follow_cursor.enabled = enabled;
+            h.follow_cursor.pending = None;
+        }
+    }
+}
+
+pub fn session_get_follow_remote_cursor(session_id: SessionID) -> bool {
+    sessions::get_session_by_session_id(&session_id).map_or(false, |s| {
+        s.session_handlers
+            .read()
+            .unwrap()
+            .get(&session_id)
+            .map_or(false, |h| h.follow_cursor.enabled)
+    })
+}
+
+#[inline]
+pub fn session_on_waiting_for_image_dialog_show(session_id: SessionID) {
+    if let Some(s) = sessions::get_session_by_session_id(&session_id) {
+        if let Some(h) = s.session_handlers.write().unwrap().get_mut(&session_id) {
+            h.on_waiting_for_image_dialog_show();
+        }
+        s.video_watchdog.reset(Instant::now());
+    }
+}
+
+/// Hooks for session.
+#[derive(Clone)]
+pub enum SessionHook {
+    OnSessionRgba(fn(String, &mut scrap::ImageRgb)),
+    /// Called before a key event is sent to the peer.
+    OnKeyboardEvent(fn(String, &KeyEvent) -> HookAction),
+    /// Called with the text clipboard synced from or to the peer, the text can be modified.
+    OnClipboard(fn(String, &mut String) -> HookAction),
+    /// Called before a file transfer job is started.
+    OnFileJobStart(fn(String, &JobMeta) -> HookAction),
+}
+
+impl SessionHook {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SessionHook::OnSessionRgba(_) => "rgba",
+            SessionHook::OnKeyboardEvent(_) => "keyboard",
+            SessionHook::OnClipboard(_) => "clipboard",
+            SessionHook::OnFileJobStart(_) => "file_transfer",
+        }
+    }
+}
+
+pub struct SessionHookEntry {
+    key: String,
+    priority: i32,
+    hook: SessionHook,
+}
+
+/// The hooks of a session, ordered by the priority, smaller runs first.
+/// The hooks with the same priority run in the insertion order.
+#[derive(Default)]
+pub struct SessionHooks(Vec<Arc<SessionHookEntry>>);
+
+impl SessionHooks {
+    /// Returns false if there's already the same kind of hook with this key.
+    pub fn add(&mut self, key: String, priority: i32, hook: SessionHook) -> bool {
+        if self
+            .0
+            .iter()
+            .any(|e| e.key == key && e.hook.kind() == hook.kind())
+        {
+            return false;
+        }
+        let pos = self.0.partition_point(|e| e.priority <= priority);
+        self.0.insert(
+            pos,
+            Arc::new(SessionHookEntry {
+                key,
+                priority,
+                hook,
+            }),
+        );
+        true
+    }
+
+    /// Returns false if there's no hook with this key.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|e| e.key != key);
+        self.0.len() != len
+    }
+
+    pub fn list(&self) -> Vec<(String, &'static str, i32)> {
+        self.0
+            .iter()
+            .map(|e| (e.key.clone(), e.hook.kind(), e.priority))
+            .collect()
+    }
+}
+
+/// `None` if the current session is removed.
+#[inline]
 pub fn get_cur_session() -> Option<FlutterSession> {
     sessions::get_session_by_session_id(&*CUR_SESSION_ID.read().unwrap())
 }
@@ -1389,9 +4344,116 @@ pub mod sessions {
 
     use super::*;
 
+    // The entry of the ui session index.
+    #[derive(Clone)]
+    struct IndexEntry {
+        peer_id: String,
+        conn_type: ConnType,
+        session: FlutterSession,
+    }
+
     lazy_static::lazy_static! {
         // peer -> peer session, peer session -> ui sessions
         static ref SESSIONS: RwLock<HashMap<(String, ConnType), FlutterSession>> = Default::default();
+        // ui session -> peer session, the index of the lookups by the ui session id.
+        // It's updated while holding the write lock of `SESSIONS`, the lookups only take its own
+        // read lock, so they are neither blocked by the peer sessions nor scan them.
+        static ref SESSION_INDEX: RwLock<HashMap<SessionID, IndexEntry>> = Default::default();
+    }
+
+    /// The option of the max number of the concurrent peer sessions, 0 or empty means unlimited.
+    pub const OPTION_MAX_PEER_SESSIONS: &str = "max-peer-sessions";
+    /// The option of the max number of the ui sessions of one peer session,
+    /// 0 or empty means unlimited.
+    pub const OPTION_MAX_UI_SESSIONS_PER_PEER: &str = "max-ui-sessions-per-peer";
+    /// The reasons of "background_session_finished", the transfers ended or the connection ended.
+    pub const BACKGROUND_DONE: &str = "done";
+    pub const BACKGROUND_DISCONNECTED: &str = "disconnected";
+    /// The error when the max number of the peer sessions is reached, it's also a translation key.
+    pub const ERR_PEER_SESSION_LIMIT: &str = "peer_session_limit_reached_tip";
+    /// The error when the max number of the ui sessions of a peer is reached,
+    /// it's also a translation key.
+    pub const ERR_UI_SESSION_LIMIT: &str = "ui_session_limit_reached_tip";
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct SessionLimits {
+        pub max_peer_sessions: usize,
+        pub max_ui_sessions_per_peer: usize,
+    }
+
+    impl SessionLimits {
+        pub fn load() -> Self {
+            let get = |k: &str| {
+                hbb_common::config::Config::get_option(k)
+                    .trim()
+                    .parse::<usize>()
+                    .unwrap_or(0)
+            };
+            Self {
+                max_peer_sessions: get(OPTION_MAX_PEER_SESSIONS),
+                max_ui_sessions_per_peer: get(OPTION_MAX_UI_SESSIONS_PER_PEER),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub(super) struct LimitReached {
+        pub(super) reason: &'static str,
+        peer_sessions: usize,
+        ui_sessions: usize,
+    }
+
+    impl LimitReached {
+        // Push the "session_limit_reached" event to the main window and return the error.
+        fn notify(self, peer_id: &str, limits: &SessionLimits) -> hbb_common::anyhow::Error {
+            log::warn!(
+                "Failed to add session of {}, {}, peer sessions: {}, ui sessions: {}",
+                peer_id,
+                self.reason,
+                self.peer_sessions,
+                self.ui_sessions
+            );
+            let event = serde_json::ser::to_string(&json!({
+                "name": "session_limit_reached",
+                "reason": self.reason,
+                "peer_id": peer_id,
+                "peer_sessions": self.peer_sessions,
+                "max_peer_sessions": limits.max_peer_sessions,
+                "ui_sessions": self.ui_sessions,
+                "max_ui_sessions_per_peer": limits.max_ui_sessions_per_peer,
+            }))
+            .unwrap_or_default();
+            let _ = push_global_event(APP_TYPE_MAIN, event);
+            anyhow!(self.reason)
+        }
+    }
+
+    // Check if one more ui session of the peer session `key` can be added.
+    pub(super) fn check_limits(
+        sessions: &HashMap<(String, ConnType), FlutterSession>,
+        key: &(String, ConnType),
+        limits: &SessionLimits,
+    ) -> Result<(), LimitReached> {
+        let peer_sessions = sessions.len();
+        let ui_sessions = sessions
+            .get(key)
+            .map(|s| s.ui_handler.session_handlers.read().unwrap().len())
+            .unwrap_or(0);
+        let reason = if !sessions.contains_key(key) {
+            (limits.max_peer_sessions > 0 && peer_sessions >= limits.max_peer_sessions)
+                .then_some(ERR_PEER_SESSION_LIMIT)
+        } else {
+            (limits.max_ui_sessions_per_peer > 0 && ui_sessions >= limits.max_ui_sessions_per_peer)
+                .then_some(ERR_UI_SESSION_LIMIT)
+        };
+        match reason {
+            Some(reason) => Err(LimitReached {
+                reason,
+                peer_sessions,
+                ui_sessions,
+            }),
+            None => Ok(()),
+        }
     }
 
     #[inline]
@@ -1406,39 +4468,21 @@ pub mod sessions {
 
     #[inline]
     pub fn get_peer_id_by_session_id(id: &SessionID, conn_type: ConnType) -> Option<String> {
-        SESSIONS
+        SESSION_INDEX
             .read()
             .unwrap()
-            .iter()
-            .find_map(|((peer_id, t), s)| {
-                if *t == conn_type
-                    && s.ui_handler
-                        .session_handlers
-                        .read()
-                        .unwrap()
-                        .contains_key(id)
-                {
-                    Some(peer_id.clone())
-                } else {
-                    None
-                }
-            })
+            .get(id)
+            .filter(|e| e.conn_type == conn_type)
+            .map(|e| e.peer_id.clone())
     }
 
     #[inline]
     pub fn get_session_by_session_id(id: &SessionID) -> Option<FlutterSession> {
-        SESSIONS
+        SESSION_INDEX
             .read()
             .unwrap()
-            .values()
-            .find(|s| {
-                s.ui_handler
-                    .session_handlers
-                    .read()
-                    .unwrap()
-                    .contains_key(id)
-            })
-            .cloned()
+            .get(id)
+            .map(|e| e.session.clone())
     }
 
     #[inline]
@@ -1446,33 +4490,241 @@ pub mod sessions {
         SESSIONS.read().unwrap().get(&(peer_id, conn_type)).cloned()
     }
 
-    #[inline]
     pub fn remove_session_by_session_id(id: &SessionID) -> Option<FlutterSession> {
-        let mut remove_peer_key = None;
-        for (peer_key, s) in SESSIONS.write().unwrap().iter_mut() {
-            let mut write_lock = s.ui_handler.session_handlers.write().unwrap();
-            let remove_ret = write_lock.remove(id);
-            #[cfg(not(feature = "flutter_texture_render"))]
-            if remove_ret.is_some() {
-                if write_lock.is_empty() {
-                    remove_peer_key = Some(peer_key.clone());
-                }
-                break;
+        let removed = remove_session_by_session_id_(id);
+        on_ui_sessions_removed(&[*id]);
+        removed
+    }
+
+    fn remove_session_by_session_id_(id: &SessionID) -> Option<FlutterSession> {
+        let mut sessions = SESSIONS.write().unwrap();
+        let entry = SESSION_INDEX.write().unwrap().remove(id)?;
+        let s = &entry.session;
+        let mut write_lock = s.ui_handler.session_handlers.write().unwrap();
+        write_lock.remove(id)?;
+        if write_lock.is_empty() {
+            drop(write_lock);
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            if entry.conn_type == ConnType::DEFAULT_CONN {
+                thumbnail::remove_session_thumbnail(&entry.peer_id);
             }
-            #[cfg(feature = "flutter_texture_render")]
-            match remove_ret {
-                Some(_) => {
-                    if write_lock.is_empty() {
-                        remove_peer_key = Some(peer_key.clone());
-                    } else {
-                        check_remove_unused_displays(None, id, s, &write_lock);
-                    }
-                    break;
-                }
-                None => {}
+            return sessions.remove(&(entry.peer_id, entry.conn_type));
+        }
+        #[cfg(feature = "flutter_texture_render")]
+        let _ = check_remove_unused_displays(None, id, s, &write_lock);
+        drop(write_lock);
+        for v in s.viewports.lock().unwrap().remove_session(id) {
+            s.set_viewport(v);
+        }
+        None
+    }
+
+    /// Remove the peer session with all its ui sessions, if it's being switched back.
+    pub fn remove_switching_back_session(
+        peer_id: &str,
+        conn_type: ConnType,
+    ) -> Option<FlutterSession> {
+        let mut sessions = SESSIONS.write().unwrap();
+        let key = (peer_id.to_owned(), conn_type);
+        if !sessions.get(&key)?.is_switching_back() {
+            return None;
+        }
+        let session = sessions.remove(&key)?;
+        let ids: Vec<SessionID> = session
+            .session_handlers
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        let mut index = SESSION_INDEX.write().unwrap();
+        for id in ids.iter() {
+            index.remove(id);
+        }
+        drop(index);
+        drop(sessions);
+        on_ui_sessions_removed(&ids);
+        Some(session)
+    }
+
+    /// Remove the last ui session of the peer session, but keep the peer session without any ui
+    /// session while its transfers are in progress. It's closed once they end, or the connection
+    /// ends, then "background_session_finished" is pushed to the main window. It still counts for
+    /// the limits, and a ui session of the peer added meanwhile attaches to it.
+    ///
+    /// Returns false and removes nothing if it's not the last ui session or no transfer is in
+    /// progress, the ui session should be closed as usual.
+    pub fn remove_session_to_background(id: &SessionID) -> bool {
+        let sessions = SESSIONS.write().unwrap();
+        let mut index = SESSION_INDEX.write().unwrap();
+        let Some(entry) = index.get(id).cloned() else {
+            return false;
+        };
+        let s = &entry.session;
+        if !s.activity.lock().unwrap().has_transfers() {
+            return false;
+        }
+        let mut handlers = s.session_handlers.write().unwrap();
+        if handlers.len() != 1 || handlers.remove(id).is_none() {
+            return false;
+        }
+        drop(handlers);
+        index.remove(id);
+        drop(index);
+        drop(sessions);
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if entry.conn_type == ConnType::DEFAULT_CONN {
+            thumbnail::remove_session_thumbnail(&entry.peer_id);
+        }
+        log::info!(
+            "The session of {} is kept in the background for the transfers",
+            entry.peer_id
+        );
+        on_ui_sessions_removed(&[*id]);
+        true
+    }
+
+    #[inline]
+    fn is_background(session: &FlutterSession) -> bool {
+        session.session_handlers.read().unwrap().is_empty()
+    }
+
+    // Remove the background peer sessions matched.
+    fn take_background_sessions(
+        matched: impl Fn(&FlutterSession) -> bool,
+    ) -> Vec<((String, ConnType), FlutterSession)> {
+        let mut sessions = SESSIONS.write().unwrap();
+        let keys: Vec<_> = sessions
+            .iter()
+            .filter(|(_, s)| is_background(s) && matched(s))
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.into_iter()
+            .filter_map(|k| sessions.remove(&k).map(|s| (k, s)))
+            .collect()
+    }
+
+    fn finish_background_sessions(
+        finished: Vec<((String, ConnType), FlutterSession)>,
+        reason: &str,
+    ) {
+        for ((peer_id, conn_type), session) in finished {
+            log::info!("The background session of {} finished, {}", peer_id, reason);
+            session.close();
+            if session.is_default() {
+                super::session_restore::on_session_closed(&peer_id);
+            }
+            let event = serde_json::ser::to_string(&json!({
+                "name": "background_session_finished",
+                "peer_id": peer_id,
+                "conn_type": format!("{:?}", conn_type),
+                "reason": reason,
+            }))
+            .unwrap_or_default();
+            let _ = push_global_event(APP_TYPE_MAIN, event);
+        }
+    }
+
+    /// Close the background peer sessions whose transfers have ended.
+    pub fn close_finished_background_sessions() {
+        let finished = take_background_sessions(|s| !s.activity.lock().unwrap().has_transfers());
+        finish_background_sessions(finished, BACKGROUND_DONE);
+    }
+
+    /// The io loop of the peer session exits, it's not reconnected without any ui session.
+    pub(super) fn on_io_loop_exit(session: &FlutterSession) {
+        let finished = take_background_sessions(|s| {
+            Arc::ptr_eq(&s.session_handlers, &session.session_handlers)
+        });
+        finish_background_sessions(finished, BACKGROUND_DISCONNECTED);
+    }
+
+    /// Record the focus of the ui session, see [`set_cur_session_id`].
+    pub fn set_focused(id: &SessionID) {
+        static FOCUS_SEQ: AtomicU64 = AtomicU64::new(0);
+        let Some(entry) = SESSION_INDEX.read().unwrap().get(id).cloned() else {
+            return;
+        };
+        if let Some(h) = entry.session.session_handlers.write().unwrap().get_mut(id) {
+            h.focus_seq = FOCUS_SEQ.fetch_add(1, Ordering::SeqCst) + 1;
+        }
+    }
+
+    // The ui session focused most recently, of any peer. The ones never focused are skipped.
+    fn last_focused() -> Option<(SessionID, String)> {
+        let index = SESSION_INDEX.read().unwrap();
+        index
+            .iter()
+            .filter_map(|(id, entry)| {
+                let seq = entry.session.session_handlers.read().unwrap().get(id)?.focus_seq;
+                (seq > 0).then_some((seq, id, entry))
+            })
+            .max_by_key(|(seq, ..)| *seq)
+            .map(|(_, id, entry)| (*id, entry.peer_id.clone()))
+    }
+
+    // Re-point the current session if it's removed, to the ui session focused most recently,
+    // or the default one if none is left. "cur_session_changed" is pushed to the main window.
+    fn on_ui_sessions_removed(ids: &[SessionID]) {
+        if !ids.contains(&get_cur_session_id()) {
+            return;
+        }
+        let (session_id, peer_id) = match last_focused() {
+            Some((id, peer_id)) => (id, peer_id),
+            None => (SessionID::default(), "".to_owned()),
+        };
+        {
+            let mut cur = CUR_SESSION_ID.write().unwrap();
+            // Changed meanwhile.
+            if !ids.contains(&cur) {
+                return;
             }
+            *cur = session_id;
+        }
+        log::info!(
+            "The current session is removed, switch to {:?} of {:?}",
+            session_id,
+            peer_id
+        );
+        let event = serde_json::ser::to_string(&json!({
+            "name": "cur_session_changed",
+            "session_id": if peer_id.is_empty() { "".to_owned() } else { session_id.to_string() },
+            "peer_id": peer_id,
+        }))
+        .unwrap_or_default();
+        let _ = push_global_event(APP_TYPE_MAIN, event);
+    }
+
+    // Send the display set to capture after validating it.
+    // Return the rejected displays if no valid display remains.
+    fn capture_displays_set(session: &FlutterSession, set: Vec<i32>) -> Option<Vec<i32>> {
+        let (valid, rejected) = session.validate_capture_displays(&set);
+        if !rejected.is_empty() {
+            log::warn!("Ignore invalid capture displays {:?}", &rejected);
         }
-        SESSIONS.write().unwrap().remove(&remove_peer_key?)
+        if valid.is_empty() {
+            return Some(rejected);
+        }
+        session
+            .capture_reduction
+            .lock()
+            .unwrap()
+            .on_capture_set(&valid);
+        if !session.is_capture_displays_duplicated(&valid) {
+            session.capture_displays(vec![], vec![], valid);
+        }
+        None
+    }
+
+    fn push_capture_displays_error(session: &FlutterSession, session_id: &SessionID, rejected: &[i32]) {
+        session.push_event_to(
+            session_id,
+            "capture_displays_error",
+            vec![(
+                "rejected",
+                &serde_json::ser::to_string(rejected).unwrap_or("".to_owned()),
+            )],
+        );
     }
 
     #[cfg(feature = "flutter_texture_render")]
@@ -1481,8 +4733,9 @@ pub mod sessions {
         session_id: &SessionID,
         session: &FlutterSession,
         handlers: &HashMap<SessionID, SessionHandler>,
-    ) {
+    ) -> Option<Vec<i32>> {
         // Set capture displays if some are not used any more.
+        let displays_count = session.peer_info.read().unwrap().displays.len();
         let mut remains_displays = HashSet::new();
         if let Some(current) = current {
             remains_displays.insert(current);
@@ -1491,98 +4744,252 @@ pub mod sessions {
             if k == session_id {
                 continue;
             }
-            remains_displays.extend(
-                h.renderer
-                    .map_display_sessions
-                    .read()
-                    .unwrap()
-                    .keys()
-                    .cloned(),
-            );
+            for d in h.renderer.map_display_sessions.read().unwrap().keys() {
+                if *d == COMPOSITE_DISPLAY {
+                    // The composite view needs all displays.
+                    remains_displays.extend(0..displays_count);
+                } else {
+                    remains_displays.insert(*d);
+                }
+            }
         }
         if !remains_displays.is_empty() {
-            session.capture_displays(
-                vec![],
-                vec![],
+            capture_displays_set(
+                session,
                 remains_displays.iter().map(|d| *d as i32).collect(),
-            );
+            )
+        } else {
+            None
         }
     }
 
     pub fn session_switch_display(is_desktop: bool, session_id: SessionID, value: Vec<i32>) {
-        for s in SESSIONS.read().unwrap().values() {
-            let read_lock = s.ui_handler.session_handlers.read().unwrap();
-            if read_lock.contains_key(&session_id) {
-                if value.len() == 1 {
-                    // Switch display.
-                    // This operation will also cause the peer to send a switch display message.
-                    // The switch display message will contain `SupportedResolutions`, which is useful when changing resolutions.
-                    s.switch_display(value[0]);
-
-                    if !is_desktop {
-                        s.capture_displays(vec![], vec![], value);
+        if let Some(s) = &get_session_by_session_id(&session_id) {
+            let overridden = s
+                .capture_reduction
+                .lock()
+                .unwrap()
+                .on_user_capture(&value, Instant::now());
+            if overridden {
+                s.push_event(
+                    "capture_restored",
+                    vec![
+                        (
+                            "displays",
+                            &serde_json::ser::to_string(&value).unwrap_or_default(),
+                        ),
+                        ("reason", "user"),
+                    ],
+                );
+            }
+            let mut write_lock = s.ui_handler.session_handlers.write().unwrap();
+            if let Some(h) = write_lock.get_mut(&session_id) {
+                // Keep following the current display if the displays are all invalid.
+                if !s.validate_capture_displays(&value).0.is_empty() {
+                    h.follow_cursor.set_display(&value);
+                }
+                let rejected = if value.len() == 1 {
+                    if s.validate_capture_displays(&value).0.is_empty() {
+                        Some(value)
+                    } else if !is_desktop {
+                        // Switch display.
+                        // This operation will also cause the peer to send a switch display message.
+                        // The switch display message will contain `SupportedResolutions`, which is useful when changing resolutions.
+                        s.switch_display(value[0]);
+                        capture_displays_set(s, value)
                     } else {
+                        s.switch_display(value[0]);
                         // Check if other displays are needed.
+                        let rejected;
                         #[cfg(feature = "flutter_texture_render")]
-                        if value.len() == 1 {
-                            check_remove_unused_displays(
+                        {
+                            rejected = check_remove_unused_displays(
                                 Some(value[0] as _),
                                 &session_id,
-                                &s,
-                                &read_lock,
+                                s,
+                                &write_lock,
                             );
                         }
+                        #[cfg(not(feature = "flutter_texture_render"))]
+                        {
+                            rejected = None;
+                        }
+                        rejected
                     }
                 } else {
                     // Try capture all displays.
-                    s.capture_displays(vec![], vec![], value);
+                    capture_displays_set(s, value)
+                };
+                drop(write_lock);
+                if let Some(rejected) = rejected {
+                    push_capture_displays_error(s, &session_id, &rejected);
                 }
-                break;
             }
         }
     }
 
-    #[inline]
-    pub fn insert_session(session_id: SessionID, conn_type: ConnType, session: FlutterSession) {
-        SESSIONS
-            .write()
+    /// The ui session views the region of the display in the ui coordinates at the scale, the whole
+    /// display if the size is 0, see `viewport`.
+    pub fn session_set_viewport(
+        session_id: SessionID,
+        display: usize,
+        rect: (i32, i32, i32, i32),
+        scale: f64,
+    ) {
+        let Some(s) = &get_session_by_session_id(&session_id) else {
+            return;
+        };
+        if !s.get_peer_capabilities().viewport || !cfg!(feature = "flutter_texture_render") {
+            return;
+        }
+        // The composite canvas is of the whole displays.
+        #[cfg(feature = "flutter_texture_render")]
+        if s.session_handlers
+            .read()
             .unwrap()
-            .entry((session.get_id(), conn_type))
-            .or_insert(session)
-            .ui_handler
-            .session_handlers
-            .write()
+            .values()
+            .any(|h| h.renderer.is_composite())
+        {
+            return;
+        }
+        let (x, y, width, height) = rect;
+        let rect = {
+            let downscale = s.downscale.lock().unwrap();
+            viewport::Rect {
+                x: downscale.to_remote(x),
+                y: downscale.to_remote(y),
+                width: downscale.to_remote(width),
+                height: downscale.to_remote(height),
+                scale: (scale * downscale.scale()).min(1.),
+            }
+        };
+        let request = s
+            .viewports
+            .lock()
             .unwrap()
-            .insert(session_id, Default::default());
+            .request(session_id, display, rect);
+        if let Some(v) = request {
+            s.set_viewport(v);
+        }
     }
 
     #[inline]
-    pub fn insert_peer_session_id(
-        peer_id: String,
+    pub fn insert_session(
+        session_id: SessionID,
         conn_type: ConnType,
+        session: FlutterSession,
+    ) -> ResultType<SessionInsertion> {
+        insert_session_(
+            session.get_id(),
+            session_id,
+            conn_type,
+            session,
+            &SessionLimits::load(),
+        )
+    }
+
+    pub(super) fn insert_session_(
+        peer_id: String,
         session_id: SessionID,
-    ) -> bool {
-        if let Some(s) = SESSIONS.read().unwrap().get(&(peer_id, conn_type)) {
-            #[cfg(not(feature = "flutter_texture_render"))]
-            let h = SessionHandler::default();
-            #[cfg(feature = "flutter_texture_render")]
-            let mut h = SessionHandler::default();
-            #[cfg(feature = "flutter_texture_render")]
-            {
-                h.renderer.is_support_multi_ui_session = crate::common::is_support_multi_ui_session(
-                    &s.ui_handler.peer_info.read().unwrap().version,
-                );
+        conn_type: ConnType,
+        session: FlutterSession,
+        limits: &SessionLimits,
+    ) -> ResultType<SessionInsertion> {
+        let mut sessions = SESSIONS.write().unwrap();
+        let key = (peer_id.clone(), conn_type);
+        if let Err(e) = check_limits(&sessions, &key, limits) {
+            drop(sessions);
+            return Err(e.notify(&peer_id, limits));
+        }
+        let insertion = match sessions.get(&key) {
+            Some(s) => {
+                SessionModes::of(&session).check_attach(s)?;
+                SessionInsertion::AttachedToExisting(
+                    s.connection_round_state.lock().unwrap().state(),
+                )
             }
-            let _ = s
+            None => SessionInsertion::CreatedNew,
+        };
+        let session = sessions.entry(key).or_insert(session).clone();
+        session.ui_handler.session_handlers.write().unwrap().insert(
+            session_id,
+            SessionHandler {
+                attached: insertion.is_attached(),
+                ..Default::default()
+            },
+        );
+        SESSION_INDEX.write().unwrap().insert(
+            session_id,
+            IndexEntry {
+                peer_id,
+                conn_type,
+                session,
+            },
+        );
+        Ok(insertion)
+    }
+
+    /// Add a ui session to the existing peer session.
+    ///
+    /// Returns `Ok(false)` if there's no peer session with the peer id and the conn type.
+    #[inline]
+    pub fn insert_peer_session_id(
+        peer_id: String,
+        conn_type: ConnType,
+        session_id: SessionID,
+    ) -> ResultType<bool> {
+        insert_peer_session_id_(peer_id, conn_type, session_id, &SessionLimits::load())
+    }
+
+    pub(super) fn insert_peer_session_id_(
+        peer_id: String,
+        conn_type: ConnType,
+        session_id: SessionID,
+        limits: &SessionLimits,
+    ) -> ResultType<bool> {
+        let sessions = SESSIONS.write().unwrap();
+        let key = (peer_id.clone(), conn_type);
+        let Some(s) = sessions.get(&key) else {
+            return Ok(false);
+        };
+        if let Err(e) = check_limits(&sessions, &key, limits) {
+            drop(sessions);
+            return Err(e.notify(&peer_id, limits));
+        }
+        #[cfg(not(feature = "flutter_texture_render"))]
+        let h = SessionHandler {
+            attached: true,
+            ..Default::default()
+        };
+        #[cfg(feature = "flutter_texture_render")]
+        let mut h = SessionHandler {
+            attached: true,
+            ..Default::default()
+        };
+        #[cfg(feature = "flutter_texture_render")]
+        {
+            h.renderer.is_support_multi_ui_session = s
                 .ui_handler
-                .session_handlers
-                .write()
+                .peer_capabilities
+                .read()
                 .unwrap()
-                .insert(session_id, h);
-            true
-        } else {
-            false
+                .multi_ui_session;
         }
+        let _ = s
+            .ui_handler
+            .session_handlers
+            .write()
+            .unwrap()
+            .insert(session_id, h);
+        SESSION_INDEX.write().unwrap().insert(
+            session_id,
+            IndexEntry {
+                peer_id,
+                conn_type,
+                session: s.clone(),
+            },
+        );
+        Ok(true)
     }
 
     #[inline]
@@ -1590,6 +4997,42 @@ pub mod sessions {
         SESSIONS.read().unwrap().values().cloned().collect()
     }
 
+    /// The peer id, conn type, ui sessions, captured displays, connection flags and uptime of
+    /// each session. Only the read locks are taken, never the locks of the frames.
+    pub fn list_json() -> String {
+        let sessions: Vec<_> = SESSIONS
+            .read()
+            .unwrap()
+            .iter()
+            .map(|((id, conn_type), s)| (id.clone(), *conn_type, s.clone()))
+            .collect();
+        let list: Vec<_> = sessions
+            .iter()
+            .map(|(id, conn_type, s)| {
+                let displays = s
+                    .last_capture_displays
+                    .read()
+                    .unwrap()
+                    .as_ref()
+                    .map(|(displays, _)| displays.clone())
+                    .unwrap_or_default();
+                let ready = *s.connection_ready.read().unwrap();
+                let ui_sessions = s.session_handlers.read().unwrap().len();
+                json!({
+                    "peer_id": id,
+                    "conn_type": format!("{:?}", conn_type),
+                    "ui_sessions": ui_sessions,
+                    "background": ui_sessions == 0,
+                    "displays": displays,
+                    "secure": ready.map(|(secure, _, _)| secure),
+                    "direct": ready.map(|(_, direct, _)| direct),
+                    "uptime_secs": ready.map(|(_, _, t)| t.elapsed().as_secs()),
+                })
+            })
+            .collect();
+        serde_json::to_string(&list).unwrap_or_else(|_| "[]".to_owned())
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pub fn other_sessions_running(peer_id: String, conn_type: ConnType) -> bool {
@@ -1602,75 +5045,2560 @@ pub mod sessions {
     }
 }
 
-pub(super) mod async_tasks {
-    use hbb_common::{
-        bail,
-        tokio::{
-            self, select,
-            sync::mpsc::{unbounded_channel, UnboundedSender},
-        },
-        ResultType,
-    };
-    use std::{
-        collections::HashMap,
-        sync::{Arc, Mutex},
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui_session_interface::SessionIntent;
+    use hbb_common::tokio;
+
+    #[test]
+    fn test_serialize_resolutions() {
+        let v: serde_json::Value = serde_json::from_str(&serialize_resolutions(&vec![
+            Resolution {
+                width: 3840,
+                height: 2160,
+                ..Default::default()
+            },
+            Resolution {
+                width: 3840,
+                height: 2160,
+                refresh_rate: 144,
+                ..Default::default()
+            },
+        ]))
+        .unwrap();
+        assert_eq!(
+            v,
+            json!([
+                {"width": 3840, "height": 2160},
+                {"width": 3840, "height": 2160, "refresh_rate": 144}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_make_displays_msg() {
+        let displays = vec![
+            // Old peers.
+            DisplayInfo {
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            },
+            DisplayInfo {
+                x: 1920,
+                width: 3840,
+                height: 2160,
+                refresh_rate: 60,
+                scale: 1.5,
+                ..Default::default()
+            },
+        ];
+        let v: serde_json::Value =
+            serde_json::from_str(&FlutterHandler::make_displays_msg(&displays)).unwrap();
+        assert_eq!(v[0]["width"], 1920);
+        assert_eq!(v[0]["height"], 1080);
+        assert!(v[0].get("refresh_rate").is_none());
+        assert!(v[0].get("scale").is_none());
+        assert!(v[0].get("original_width").is_none());
+        assert_eq!(v[1]["x"], 1920);
+        assert_eq!(v[1]["refresh_rate"], 60);
+        assert_eq!(v[1]["scale"], 1.5);
+    }
+
+    fn display(name: &str, x: i32, width: i32, height: i32) -> DisplayInfo {
+        DisplayInfo {
+            name: name.to_owned(),
+            x,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_displays_diff() {
+        let old = vec![display("a", 0, 1920, 1080), display("b", 1920, 1920, 1080)];
+        // Unchanged.
+        assert!(DisplaysDiff::new(&old, &old).unwrap().is_empty());
+        // Add.
+        let mut new = old.clone();
+        new.push(display("c", 3840, 1920, 1080));
+        assert_eq!(
+            DisplaysDiff::new(&old, &new),
+            Some(DisplaysDiff {
+                added: vec![2],
+                ..Default::default()
+            })
+        );
+        // Remove the last one.
+        assert_eq!(
+            DisplaysDiff::new(&old, &old[..1]),
+            Some(DisplaysDiff {
+                removed: vec![1],
+                ..Default::default()
+            })
+        );
+        // Remove the first one, the indices are shifted.
+        assert_eq!(DisplaysDiff::new(&old, &old[1..]), None);
+        // Move.
+        let mut new = old.clone();
+        new[1].x = -1920;
+        assert_eq!(
+            DisplaysDiff::new(&old, &new),
+            Some(DisplaysDiff {
+                modified: vec![1],
+                ..Default::default()
+            })
+        );
+        // Resolution change.
+        let mut new = old.clone();
+        new[0].width = 2560;
+        new[0].height = 1440;
+        assert_eq!(
+            DisplaysDiff::new(&old, &new),
+            Some(DisplaysDiff {
+                modified: vec![0],
+                ..Default::default()
+            })
+        );
+        // No displays before, full sync.
+        assert_eq!(DisplaysDiff::new(&[], &old), None);
+    }
+
+    #[test]
+    fn test_validate_capture_displays() {
+        let handler = FlutterHandler::default();
+        handler.peer_info.write().unwrap().displays = vec![
+            display("a", 0, 1920, 1080),
+            display("b", 1920, 1920, 1080),
+            display("c", 3840, 1920, 1080),
+        ];
+        assert_eq!(
+            handler.validate_capture_displays(&[2, 0, 0]),
+            (vec![0, 2], vec![])
+        );
+        // Requests right after a monitor is unplugged.
+        handler.set_displays(&vec![display("a", 0, 1920, 1080)]);
+        assert_eq!(
+            handler.validate_capture_displays(&[0, 2]),
+            (vec![0], vec![2])
+        );
+        assert_eq!(
+            handler.validate_capture_displays(&[2, -1]),
+            (vec![], vec![2, -1])
+        );
+    }
+
+    fn cached_cursor(payload: &str) -> CachedCursor {
+        CachedCursor {
+            data: Default::default(),
+            payload: payload.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_cursor_cache() {
+        let mut cache = CursorCache::default();
+        let get = |cache: &mut CursorCache, id| cache.get(id).map(|c| c.payload.clone());
+        cache.insert(1, cached_cursor("1"), 2);
+        cache.insert(2, cached_cursor("2"), 2);
+        // Touch 1, then 2 is the least recently used.
+        assert_eq!(get(&mut cache, 1).as_deref(), Some("1"));
+        cache.insert(3, cached_cursor("3"), 2);
+        assert_eq!(get(&mut cache, 2), None);
+        assert_eq!(get(&mut cache, 1).as_deref(), Some("1"));
+        assert_eq!(get(&mut cache, 3).as_deref(), Some("3"));
+        // Update an existing id.
+        cache.insert(3, cached_cursor("33"), 2);
+        assert_eq!(get(&mut cache, 3).as_deref(), Some("33"));
+        assert_eq!(cache.order.len(), 2);
+        // Disabled.
+        cache.insert(4, cached_cursor("4"), 0);
+        assert_eq!(get(&mut cache, 4), None);
+        cache.clear();
+        assert_eq!(get(&mut cache, 1), None);
+    }
+
+    #[test]
+    fn test_scale_cursor() {
+        // 2x2, one opaque red pixel and three transparent green pixels.
+        let colors = vec![
+            255, 0, 0, 255, 0, 255, 0, 0, //
+            0, 255, 0, 0, 0, 255, 0, 0,
+        ];
+        let cd = CursorData {
+            hotx: 1,
+            hoty: 1,
+            width: 2,
+            height: 2,
+            colors: colors.into(),
+            ..Default::default()
+        };
+        let scaled = scale_cursor(&cd, 0.5);
+        assert_eq!((scaled.width, scaled.height), (1, 1));
+        assert_eq!((scaled.hotx, scaled.hoty), (0, 0));
+        // The transparent green must not bleed into the edge.
+        assert_eq!(&scaled.colors[..], &[255, 0, 0, 63]);
+
+        let scaled = scale_cursor(&cd, 2.0);
+        assert_eq!((scaled.width, scaled.height), (4, 4));
+        assert_eq!((scaled.hotx, scaled.hoty), (2, 2));
+        assert_eq!(&scaled.colors[0..8], &[255, 0, 0, 255, 255, 0, 0, 255]);
+        assert_eq!(scaled.colors[8 + 3], 0);
+
+        // Invalid colors are kept.
+        let invalid = CursorData {
+            width: 2,
+            height: 2,
+            ..Default::default()
+        };
+        assert_eq!(scale_cursor(&invalid, 2.0), invalid);
+    }
+
+    #[test]
+    fn test_cursor_scale_ratio() {
+        let mut h = SessionHandler::default();
+        assert_eq!(h.cursor_scale_ratio(2.0), None);
+        h.cursor_scale = 1.0;
+        assert_eq!(h.cursor_scale_ratio(2.0), Some(0.5));
+        assert_eq!(h.cursor_scale_ratio(0.0), None);
+        h.raw_cursor = true;
+        assert_eq!(h.cursor_scale_ratio(2.0), None);
+    }
+
+    #[test]
+    fn test_capture_displays_dedup() {
+        let handler = FlutterHandler::default();
+        assert!(!handler.is_capture_displays_duplicated(&[0, 1]));
+        assert!(handler.is_capture_displays_duplicated(&[0, 1]));
+        assert!(!handler.is_capture_displays_duplicated(&[0]));
+    }
+
+    #[test]
+    fn test_make_displays_changed_msg() {
+        let displays = vec![display("a", 0, 1920, 1080), display("b", 1920, 2560, 1440)];
+        let v: serde_json::Value =
+            serde_json::from_str(&FlutterHandler::make_displays_changed_msg(&displays, &[1]))
+                .unwrap();
+        assert_eq!(v.as_array().map(|a| a.len()), Some(1));
+        assert_eq!(v[0]["index"], 1);
+        assert_eq!(v[0]["width"], 2560);
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn test_session_hooks() {
+        let handler = FlutterHandler::default();
+        let mut content = "secret".to_owned();
+        assert_eq!(handler.hook_clipboard(&mut content), HookAction::Continue);
+
+        let mut hooks = handler.hooks.write().unwrap();
+        assert!(hooks.add(
+            "a".to_owned(),
+            0,
+            SessionHook::OnClipboard(|_, content| {
+                *content = content.replace("secret", "******");
+                HookAction::Continue
+            }),
+        ));
+        assert!(hooks.add(
+            "a".to_owned(),
+            0,
+            SessionHook::OnKeyboardEvent(|key, evt| {
+                assert_eq!(key, "a");
+                if evt.has_control_key() {
+                    HookAction::Block
+                } else {
+                    HookAction::Continue
+                }
+            }),
+        ));
+        assert!(hooks.add(
+            "b".to_owned(),
+            0,
+            SessionHook::OnFileJobStart(|_, job| {
+                if job.to.starts_with("/etc") {
+                    HookAction::Block
+                } else {
+                    HookAction::Continue
+                }
+            }),
+        ));
+        drop(hooks);
+
+        assert_eq!(handler.hook_clipboard(&mut content), HookAction::Continue);
+        assert_eq!(content, "******");
+
+        let mut evt = KeyEvent::new();
+        evt.set_chr('a' as _);
+        assert_eq!(handler.hook_key_event(&evt), HookAction::Continue);
+        evt.set_control_key(ControlKey::Delete);
+        assert_eq!(handler.hook_key_event(&evt), HookAction::Block);
+
+        let mut job = JobMeta {
+            to: "/tmp/a".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(handler.hook_file_job_start(&job), HookAction::Continue);
+        job.to = "/etc/passwd".to_owned();
+        assert_eq!(handler.hook_file_job_start(&job), HookAction::Block);
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn test_session_hooks_order() {
+        let handler = FlutterHandler::default();
+        let mut hooks = handler.hooks.write().unwrap();
+        let append = || {
+            SessionHook::OnClipboard(|key, content| {
+                content.push_str(&key);
+                HookAction::Continue
+            })
+        };
+        assert!(hooks.add("c".to_owned(), 1, append()));
+        assert!(hooks.add("a".to_owned(), 0, append()));
+        assert!(hooks.add("b".to_owned(), 1, append()));
+        assert!(!hooks.add("b".to_owned(), 2, append()));
+        assert_eq!(
+            hooks.list(),
+            vec![
+                ("a".to_owned(), "clipboard", 0),
+                ("c".to_owned(), "clipboard", 1),
+                ("b".to_owned(), "clipboard", 1),
+            ]
+        );
+        drop(hooks);
+
+        let mut content = String::new();
+        assert_eq!(handler.hook_clipboard(&mut content), HookAction::Continue);
+        assert_eq!(content, "acb");
+        assert!(handler.hooks.write().unwrap().remove("a"));
+        assert!(!handler.hooks.write().unwrap().remove("a"));
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn test_session_hooks_reentrant() {
+        lazy_static::lazy_static! {
+            static ref HANDLER: FlutterHandler = FlutterHandler::default();
+        }
+        // The hook replaces itself, the new one runs from the next time.
+        assert!(HANDLER.hooks.write().unwrap().add(
+            "a".to_owned(),
+            0,
+            SessionHook::OnClipboard(|_, content| {
+                let mut hooks = HANDLER.hooks.write().unwrap();
+                hooks.remove("a");
+                hooks.add(
+                    "b".to_owned(),
+                    0,
+                    SessionHook::OnClipboard(|_, content| {
+                        content.push('b');
+                        HookAction::Continue
+                    }),
+                );
+                content.push('a');
+                HookAction::Continue
+            }),
+        ));
+        let mut content = String::new();
+        assert_eq!(HANDLER.hook_clipboard(&mut content), HookAction::Continue);
+        assert_eq!(HANDLER.hook_clipboard(&mut content), HookAction::Continue);
+        assert_eq!(content, "ab");
+    }
+
+    #[test]
+    fn test_make_plugin_event() {
+        let (name, event) =
+            make_plugin_event("watermark", r#"{"name": "msgbox", "text": "hi", "n": 1}"#).unwrap();
+        assert_eq!(name, "plugin|watermark|msgbox");
+        let v: serde_json::Value = serde_json::from_str(&event).unwrap();
+        assert_eq!(v, json!({"name": "plugin|watermark|msgbox", "text": "hi", "n": 1}));
+
+        assert!(make_plugin_event("", r#"{"name": "a"}"#).is_err());
+        assert!(make_plugin_event("a|b", r#"{"name": "a"}"#).is_err());
+        assert!(make_plugin_event("a", r#"["name", "a"]"#).is_err());
+        assert!(make_plugin_event("a", r#"{"name": 1}"#).is_err());
+        assert!(make_plugin_event("a", r#"{"text": "a"}"#).is_err());
+    }
+
+    #[test]
+    fn test_push_plugin_event_route() {
+        // Session-scoped events are never delivered to a missing session.
+        let err = push_plugin_event("a", Some(SessionID::new_v4()), r#"{"name": "x"}"#);
+        assert!(err.unwrap_err().to_string().contains("not found"));
+        // Global events are delivered to the main event stream.
+        let err = push_plugin_event("a", None, r#"{"name": "x"}"#);
+        assert!(err.unwrap_err().to_string().contains(APP_TYPE_MAIN));
+        // The payload is validated before routing.
+        let err = push_plugin_event("a", None, "{}");
+        assert!(err.unwrap_err().to_string().contains("no name"));
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "reserved for plugins"))]
+    fn test_core_event_reject_plugin_name() {
+        assert!(FlutterHandler::check_core_event_name("msgbox"));
+        let handler = FlutterHandler::default();
+        handler.push_event("plugin|a|msgbox", vec![]);
+        assert!(!FlutterHandler::check_core_event_name("plugin|a|msgbox"));
+    }
+
+    #[test]
+    #[cfg(feature = "flutter_texture_render")]
+    fn test_on_rgba_no_write_lock() {
+        let handler = FlutterHandler::default();
+        for _ in 0..3 {
+            handler
+                .session_handlers
+                .write()
+                .unwrap()
+                .insert(SessionID::new_v4(), Default::default());
+        }
+        // Any write lock acquisition in the hot path blocks while the read guard is held.
+        let _guard = handler.session_handlers.read().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let cloned = handler.clone();
+        std::thread::spawn(move || {
+            let mut rgba = scrap::ImageRgb::new(scrap::ImageFormat::ARGB, 1);
+            for display in 0..100 {
+                cloned.on_rgba(display % 2, &mut rgba);
+            }
+            tx.send(()).ok();
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[cfg(feature = "flutter_texture_render")]
+    static FAKE_ON_RGBA_STARTED: AtomicBool = AtomicBool::new(false);
+
+    #[cfg(feature = "flutter_texture_render")]
+    unsafe extern "C" fn fake_slow_on_rgba(
+        _texture_rgba: *mut c_void,
+        _buffer: *const u8,
+        _len: c_int,
+        _width: c_int,
+        _height: c_int,
+        _dst_rgba_stride: c_int,
+    ) {
+        FAKE_ON_RGBA_STARTED.store(true, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(500));
+    }
+
+    #[test]
+    #[cfg(feature = "flutter_texture_render")]
+    fn test_register_texture_not_blocked_by_rendering() {
+        let mut renderer = VideoRenderer::default();
+        renderer.on_rgba_func = Some(fake_slow_on_rgba);
+        renderer.is_support_multi_ui_session = true;
+        // The refs of the textures are global, not the pointers of the other tests.
+        renderer.register_texture(0, 1001);
+        renderer.set_size(0, 2, 2);
+        let mut rgba = scrap::ImageRgb::new(scrap::ImageFormat::ARGB, 1);
+        rgba.w = 2;
+        rgba.h = 2;
+        rgba.raw = vec![0; 16];
+
+        let cloned = renderer.clone();
+        let render = std::thread::spawn(move || cloned.on_rgba(0, &rgba));
+        while !FAKE_ON_RGBA_STARTED.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let start = Instant::now();
+        renderer.register_texture(1, 1002);
+        renderer.set_size(1, 2, 2);
+        // Neither by replacing or unregistering the texture being rendered.
+        assert!(renderer.register_texture(0, 1003));
+        renderer.register_texture(0, 0);
+        assert!(start.elapsed() < Duration::from_millis(200));
+        // But the old texture is released only after the frame is done.
+        assert!(!wait_texture_released(1001, Duration::from_millis(10)));
+        assert!(wait_texture_released(1001, Duration::from_secs(5)));
+        render.join().unwrap();
+        assert!(wait_texture_released(1003, Duration::ZERO));
+        assert!(!wait_texture_released(1002, Duration::ZERO));
+        renderer.register_texture(1, 0);
+        assert!(wait_texture_released(1002, Duration::ZERO));
+    }
+
+    #[cfg(feature = "flutter_texture_render")]
+    static FAKE_RENDERED_TEXTURES: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(vec![]);
+
+    // The textures freed by flutter after they are replaced or unregistered.
+    #[cfg(feature = "flutter_texture_render")]
+    static FAKE_FREED_TEXTURES: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(vec![]);
+
+    #[cfg(feature = "flutter_texture_render")]
+    static FAKE_FREED_WRITTEN: AtomicBool = AtomicBool::new(false);
+
+    #[cfg(feature = "flutter_texture_render")]
+    unsafe extern "C" fn fake_record_on_rgba(
+        texture_rgba: *mut c_void,
+        _buffer: *const u8,
+        _len: c_int,
+        _width: c_int,
+        _height: c_int,
+        _dst_rgba_stride: c_int,
+    ) {
+        let texture = texture_rgba as usize;
+        // Writing the texture takes a while.
+        for _ in 0..2 {
+            if FAKE_FREED_TEXTURES.lock().unwrap().contains(&texture) {
+                FAKE_FREED_WRITTEN.store(true, Ordering::SeqCst);
+            }
+            std::thread::sleep(Duration::from_micros(50));
+        }
+        FAKE_RENDERED_TEXTURES.lock().unwrap().push(texture);
+    }
+
+    #[test]
+    #[cfg(feature = "flutter_texture_render")]
+    fn test_register_texture_interleaved() {
+        let mut renderer = VideoRenderer::default();
+        renderer.on_rgba_func = Some(fake_record_on_rgba);
+        renderer.is_support_multi_ui_session = true;
+        assert!(!renderer.register_texture(0, 1));
+        assert!(!renderer.register_texture(0, 1));
+        renderer.set_size(0, 2, 2);
+        let stop = Arc::new(AtomicBool::new(false));
+        let cloned = renderer.clone();
+        let cloned_stop = stop.clone();
+        let render = std::thread::spawn(move || {
+            let mut rgba = scrap::ImageRgb::new(scrap::ImageFormat::ARGB, 1);
+            rgba.w = 2;
+            rgba.h = 2;
+            rgba.raw = vec![0; 16];
+            while !cloned_stop.load(Ordering::SeqCst) {
+                cloned.on_rgba(0, &rgba);
+            }
+        });
+        // Resizing, the texture is replaced without unregistering,
+        // the old texture is freed by flutter as soon as it's released.
+        for ptr in 2..200 {
+            assert!(renderer.register_texture(0, ptr));
+            assert!(wait_texture_released(ptr - 1, Duration::from_secs(5)));
+            FAKE_FREED_TEXTURES.lock().unwrap().push(ptr - 1);
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        renderer.register_texture(0, 0);
+        assert!(wait_texture_released(199, Duration::from_secs(5)));
+        FAKE_FREED_TEXTURES.lock().unwrap().push(199);
+        std::thread::sleep(Duration::from_millis(10));
+        stop.store(true, Ordering::SeqCst);
+        render.join().unwrap();
+        assert!(!FAKE_FREED_WRITTEN.load(Ordering::SeqCst));
+
+        let rendered = FAKE_RENDERED_TEXTURES.lock().unwrap().clone();
+        assert!(!rendered.is_empty());
+        // A replaced texture is never rendered again.
+        assert!(rendered.windows(2).all(|w| w[0] <= w[1]));
+        // Unregistered.
+        let mut rgba = scrap::ImageRgb::new(scrap::ImageFormat::ARGB, 1);
+        rgba.w = 2;
+        rgba.h = 2;
+        rgba.raw = vec![0; 16];
+        renderer.on_rgba(0, &rgba);
+        assert_eq!(FAKE_RENDERED_TEXTURES.lock().unwrap().len(), rendered.len());
+    }
+
+    #[cfg(feature = "flutter_texture_render")]
+    static FAKE_STALE_RENDERED: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(vec![]);
+
+    #[cfg(feature = "flutter_texture_render")]
+    unsafe extern "C" fn fake_stale_on_rgba(
+        texture_rgba: *mut c_void,
+        _buffer: *const u8,
+        _len: c_int,
+        _width: c_int,
+        _height: c_int,
+        _dst_rgba_stride: c_int,
+    ) {
+        FAKE_STALE_RENDERED
+            .lock()
+            .unwrap()
+            .push(texture_rgba as usize);
+    }
+
+    #[test]
+    #[cfg(feature = "flutter_texture_render")]
+    fn test_stale_texture_generation() {
+        let mut renderer = VideoRenderer::default();
+        renderer.on_rgba_func = Some(fake_stale_on_rgba);
+        renderer.is_support_multi_ui_session = true;
+        renderer.register_texture(0, 2001);
+        renderer.set_size(0, 2, 2);
+        let get_info = || {
+            renderer
+                .map_display_sessions
+                .read()
+                .unwrap()
+                .get(&0)
+                .cloned()
+                .unwrap()
+        };
+        // Copied by the frame before the texture is replaced.
+        let stale = get_info();
+        assert!(renderer.register_texture(0, 2002));
+        let current = get_info();
+        assert!(current.generation > stale.generation);
+
+        let buffer = vec![0; 16];
+        renderer.call_on_rgba_func(0, &stale, &buffer, 2, 2, 8);
+        assert!(FAKE_STALE_RENDERED.lock().unwrap().is_empty());
+        renderer.call_on_rgba_func(0, &current, &buffer, 2, 2, 8);
+        assert_eq!(*FAKE_STALE_RENDERED.lock().unwrap(), vec![2002]);
+
+        // The old texture is not released before the stale frame is dropped.
+        assert!(!wait_texture_released(2001, Duration::ZERO));
+        drop(stale);
+        assert!(wait_texture_released(2001, Duration::ZERO));
+        renderer.register_texture(0, 0);
+        drop(current);
+        assert!(wait_texture_released(2002, Duration::ZERO));
+    }
+
+    #[test]
+    #[cfg(not(feature = "flutter_texture_render"))]
+    fn test_rgba_ring_slow_consumer() {
+        let mut ring = RgbaRing::default();
+        let mut raw = vec![];
+        let mut notified = 0;
+        let mut consumed = vec![];
+        // A 60fps producer, the consumer fetches a frame every 3 frames,
+        // and takes 2 frames to process it.
+        for frame in 0..60u8 {
+            raw.clear();
+            raw.resize(4, frame);
+            if ring.push(&mut raw) {
+                notified += 1;
+            }
+            match frame % 3 {
+                0 => {
+                    if let Some(data) = ring.acquire() {
+                        consumed.push(data[0]);
+                    }
+                }
+                2 => {
+                    ring.release();
+                }
+                _ => {}
+            }
+            // The frame being read is never written.
+            if let Some(i) = ring.reading {
+                assert_eq!(ring.buffers[i][0], *consumed.last().unwrap());
+            }
+            assert!(ring.buffers.len() <= RGBA_RING_SIZE);
+        }
+        // The newest frame is always fetched.
+        assert_eq!(consumed, (0..60).step_by(3).collect::<Vec<u8>>());
+        // The last frame is waiting for the consumer.
+        assert!(ring.ready.is_some());
+        assert_eq!(notified, consumed.len() + 1);
+        assert_eq!(ring.dropped, 60 - consumed.len() as u64 - 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "flutter_texture_render"))]
+    fn test_rgba_ring_release_notifies() {
+        let mut ring = RgbaRing::default();
+        let push = |ring: &mut RgbaRing, frame: u8| ring.push(&mut vec![frame; 4]);
+        assert!(push(&mut ring, 0));
+        assert_eq!(ring.acquire().map(|d| d[0]), Some(0));
+        // The ui is notified of the new frame while still reading the older one.
+        assert!(push(&mut ring, 1));
+        assert!(!push(&mut ring, 2));
+        // The notification is handled before the older frame is released.
+        assert_eq!(ring.acquire().map(|d| d[0]), Some(0));
+        // The frame left waiting must not be stranded.
+        assert!(ring.release());
+        assert_eq!(ring.acquire().map(|d| d[0]), Some(2));
+        // Nothing is waiting.
+        assert!(!ring.release());
+        assert!(push(&mut ring, 3));
+    }
+
+    #[test]
+    fn test_session_index_churn() {
+        let stop = Arc::new(AtomicBool::new(false));
+        // The stable session must always be found during the churn.
+        let stable_id = SessionID::new_v4();
+        sessions::insert_session(stable_id, ConnType::FILE_TRANSFER, Default::default()).unwrap();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let stop = stop.clone();
+                std::thread::spawn(move || {
+                    let mut lookups = 0;
+                    while !stop.load(Ordering::SeqCst) {
+                        assert!(sessions::get_session_by_session_id(&stable_id).is_some());
+                        let peer_id = sessions::get_peer_id_by_session_id(
+                            &stable_id,
+                            ConnType::FILE_TRANSFER,
+                        );
+                        assert_eq!(peer_id, Some("".to_owned()));
+                        assert!(
+                            sessions::get_peer_id_by_session_id(&stable_id, ConnType::RDP).is_none()
+                        );
+                        lookups += 1;
+                    }
+                    lookups
+                })
+            })
+            .collect();
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        let id = SessionID::new_v4();
+                        sessions::insert_session(id, ConnType::PORT_FORWARD, Default::default())
+                            .unwrap();
+                        assert!(sessions::get_session_by_session_id(&id).is_some());
+                        let id2 = SessionID::new_v4();
+                        assert!(sessions::insert_peer_session_id(
+                            "".to_owned(),
+                            ConnType::PORT_FORWARD,
+                            id2
+                        )
+                        .unwrap());
+                        assert!(sessions::get_session_by_session_id(&id2).is_some());
+                        sessions::remove_session_by_session_id(&id2);
+                        sessions::remove_session_by_session_id(&id);
+                        assert!(sessions::get_session_by_session_id(&id).is_none());
+                        assert!(sessions::get_session_by_session_id(&id2).is_none());
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+        stop.store(true, Ordering::SeqCst);
+        for r in readers {
+            assert!(r.join().unwrap() > 0);
+        }
+        assert!(sessions::get_session_by_peer_id("".to_owned(), ConnType::PORT_FORWARD).is_none());
+        assert!(sessions::remove_session_by_session_id(&stable_id).is_some());
+        assert!(sessions::get_session_by_session_id(&stable_id).is_none());
+    }
+
+    #[test]
+    fn test_session_add_direct_target() {
+        let add = |id: &str, force_relay: bool, direct_target: &str| {
+            let session_id = SessionID::new_v4();
+            let res = session_add(
+                &session_id,
+                id,
+                SessionAddOptions {
+                    force_relay,
+                    direct_target: direct_target.to_owned(),
+                    ..Default::default()
+                },
+            );
+            sessions::remove_session_by_session_id(&session_id);
+            res.map(|s| s.session.get_id())
+        };
+        assert_eq!(
+            add("", false, "[fe80::1]:21119").unwrap(),
+            "direct://[fe80::1]:21119"
+        );
+        // Reconnected from the recent peers.
+        assert_eq!(
+            add("direct://10.0.0.1:21118", false, "").unwrap(),
+            "direct://10.0.0.1:21118"
+        );
+        assert!(add("", true, "10.0.0.1").is_err());
+        assert!(add("direct://10.0.0.1:21118", true, "").is_err());
+        assert!(add("123456789", false, "10.0.0.1").is_err());
+        assert!(add("", false, "[fe80::1").is_err());
+    }
+
+    #[test]
+    fn test_session_add_keeps_remote_id() {
+        let before = LocalConfig::get_remote_id();
+        let peer_id = format!("mru-test-{}", SessionID::new_v4());
+        let session_id = SessionID::new_v4();
+        let res = session_add(&session_id, &peer_id, Default::default());
+        assert!(res.is_ok());
+        assert_eq!(LocalConfig::get_remote_id(), before);
+        assert!(sessions::remove_session_by_session_id(&session_id).is_some());
+    }
+
+    #[test]
+    fn test_session_add_attach() {
+        let add = |session_id: &SessionID, peer_id: &str| {
+            session_add(session_id, peer_id, Default::default()).unwrap()
+        };
+        let peer_id = format!("attach-test-{}", SessionID::new_v4());
+        let first = SessionID::new_v4();
+        let created = add(&first, &peer_id);
+        assert_eq!(created.insertion, SessionInsertion::CreatedNew);
+        assert_eq!(created.insertion.to_json(), r#"{"attached":false}"#);
+        {
+            let mut state = created.session.connection_round_state.lock().unwrap();
+            state.new_round();
+            state.set_connected();
+        }
+
+        // The ui session of another window is attached to the connected one.
+        let second = SessionID::new_v4();
+        let attached = add(&second, &peer_id);
+        assert_eq!(
+            attached.insertion,
+            SessionInsertion::AttachedToExisting(ConnectionState::Connected)
+        );
+        let json: serde_json::Value = serde_json::from_str(&attached.insertion.to_json()).unwrap();
+        assert_eq!(json["attached"], true);
+        assert_eq!(json["state"], "connected");
+        assert!(Arc::ptr_eq(&created.session, &attached.session));
+        {
+            let handlers = created.session.session_handlers.read().unwrap();
+            assert!(!handlers.get(&first).unwrap().attached);
+            assert!(handlers.get(&second).unwrap().attached);
+        }
+
+        // Started with the state of the existing connection, no new connection.
+        let collector: Arc<EventCollector<EventToUI>> = Default::default();
+        session_start_(&second, &peer_id, collector.clone()).unwrap();
+        let events = collector.take_events();
+        assert!(events.iter().any(|e| e["name"] == "session_activity"));
+
+        assert!(sessions::remove_session_by_session_id(&second).is_some());
+        assert!(sessions::remove_session_by_session_id(&first).is_some());
+    }
+
+    #[test]
+    fn test_session_add_attach_modes() {
+        let peer_id = format!("attach-modes-test-{}", SessionID::new_v4());
+        let view_only = || SessionAddOptions {
+            view_only: true,
+            ..Default::default()
+        };
+        let first = SessionID::new_v4();
+        let created = session_add(&first, &peer_id, view_only()).unwrap();
+        assert_eq!(created.insertion, SessionInsertion::CreatedNew);
+
+        // Not attached to the view-only session with the full control, or the audio-only.
+        let second = SessionID::new_v4();
+        assert!(session_add(&second, &peer_id, Default::default()).is_err());
+        let audio_only = SessionAddOptions {
+            view_only: true,
+            audio_only: true,
+            ..Default::default()
+        };
+        assert!(session_add(&second, &peer_id, audio_only).is_err());
+        assert!(sessions::get_session_by_session_id(&second).is_none());
+        // Nor by reusing the session id.
+        assert!(session_add(&first, &peer_id, Default::default()).is_err());
+        assert!(created.session.lc.read().unwrap().view_only_session);
+
+        let attached = session_add(&second, &peer_id, view_only()).unwrap();
+        assert!(attached.insertion.is_attached());
+        assert!(Arc::ptr_eq(&created.session, &attached.session));
+
+        assert!(sessions::remove_session_by_session_id(&second).is_some());
+        assert!(sessions::remove_session_by_session_id(&first).is_some());
+    }
+
+    #[test]
+    fn test_io_loop_panic() {
+        let peer_id = format!("panic-test-{}", SessionID::new_v4());
+        let session_id = SessionID::new_v4();
+        let session = session_add(&session_id, &peer_id, Default::default())
+            .unwrap()
+            .session;
+        let collector: Arc<EventCollector<EventToUI>> = Default::default();
+        session
+            .session_handlers
+            .write()
+            .unwrap()
+            .get_mut(&session_id)
+            .unwrap()
+            .event_stream = Some(collector.clone());
+        let crashes = crate::common::get_session_crashes();
+
+        let long = "x".repeat(MAX_PANIC_MESSAGE_LEN * 2);
+        let mut panicked_round = 0;
+        run_io_loop((*session).clone(), |_, round| {
+            panicked_round = round;
+            panic!("{}", long);
+        });
+
+        let events = collector.take();
+        assert!(matches!(events.last(), Some(EventToUI::Event(s)) if s == "close"));
+        let events: Vec<serde_json::Value> = events
+            .iter()
+            .filter_map(|e| match e {
+                EventToUI::Event(s) => serde_json::from_str(s).ok(),
+                EventToUI::Rgba(_) => None,
+            })
+            .collect();
+        let event = events
+            .iter()
+            .find(|e| e["name"] == "session_panicked")
+            .unwrap();
+        assert_eq!(event["type"], "error");
+        assert_eq!(
+            event["message"].as_str().unwrap().len(),
+            MAX_PANIC_MESSAGE_LEN
+        );
+        assert_eq!(event["backtrace"].as_str().unwrap().len(), 16);
+        assert!(crate::common::get_session_crashes() > crashes);
+
+        // Cleaned up as closed, the round of the panic is stale.
+        assert!(sessions::get_session_by_session_id(&session_id).is_none());
+        let state = session.connection_round_state.lock().unwrap();
+        assert!(state.is_round_gt(panicked_round));
+        assert_eq!(state.state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    fn test_switch_back_session_collision() {
+        let add = |session_id: &SessionID, peer_id: &str| {
+            session_add(session_id, peer_id, Default::default()).map(|s| s.session)
+        };
+        let peer_id = format!("switch-back-test-{}", SessionID::new_v4());
+        let first = SessionID::new_v4();
+        let old = add(&first, &peer_id).unwrap();
+        // The same session id of the same peer is reused.
+        assert!(Arc::ptr_eq(&old, &add(&first, &peer_id).unwrap()));
+        assert!(add(&first, "switch-back-test-other").is_err());
+
+        old.on_switch_back();
+        assert!(old.is_switching_back());
+        // The reconnection stands down.
+        old.reconnect(false);
+        assert!(old.thread.lock().unwrap().is_none());
+
+        // The switched session replaces the old one instead of being attached to it.
+        let second = SessionID::new_v4();
+        let new = add(&second, &peer_id).unwrap();
+        assert!(!Arc::ptr_eq(&old, &new));
+        assert!(sessions::get_session_by_session_id(&first).is_none());
+        assert!(Arc::ptr_eq(
+            &sessions::get_session_by_peer_id(peer_id.clone(), ConnType::DEFAULT_CONN).unwrap(),
+            &new
+        ));
+        // Not overridden by the close.
+        assert_eq!(old.get_intent(), SessionIntent::SwitchBack);
+
+        // The same session id is replaced too.
+        new.on_switch_back();
+        let newer = add(&second, &peer_id).unwrap();
+        assert!(!Arc::ptr_eq(&new, &newer));
+        assert_eq!(newer.get_intent(), SessionIntent::Active);
+        newer.close();
+        assert_eq!(newer.get_intent(), SessionIntent::UserClose);
+        assert!(sessions::remove_session_by_session_id(&second).is_some());
+    }
+
+    #[test]
+    fn test_switch_back_state_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            ..Default::default()
+        };
+        session.on_network_drop();
+        assert_eq!(session.get_intent(), SessionIntent::NetworkDrop);
+        session.on_switch_back();
+        // Not a network drop once switching back.
+        session.on_network_drop();
+        assert_eq!(session.get_intent(), SessionIntent::SwitchBack);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "switch_back_state");
+        assert_eq!(events[0]["state"], "initiated");
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let limits = sessions::SessionLimits {
+            max_peer_sessions: 0,
+            max_ui_sessions_per_peer: 2,
+        };
+        let peer_id = format!("limit-test-{}", SessionID::new_v4());
+        let conn_type = ConnType::FILE_TRANSFER;
+        let first = SessionID::new_v4();
+        sessions::insert_session_(
+            peer_id.clone(),
+            first,
+            conn_type,
+            Default::default(),
+            &limits,
+        )
+        .unwrap();
+        let second = SessionID::new_v4();
+        assert!(
+            sessions::insert_peer_session_id_(peer_id.clone(), conn_type, second, &limits).unwrap()
+        );
+        let third = SessionID::new_v4();
+        let err = sessions::insert_peer_session_id_(peer_id.clone(), conn_type, third, &limits)
+            .unwrap_err();
+        assert_eq!(err.to_string(), sessions::ERR_UI_SESSION_LIMIT);
+        assert!(sessions::get_session_by_session_id(&third).is_none());
+
+        // Closing one frees the capacity immediately.
+        sessions::remove_session_by_session_id(&second);
+        assert!(
+            sessions::insert_peer_session_id_(peer_id.clone(), conn_type, third, &limits).unwrap()
+        );
+        assert!(sessions::get_session_by_session_id(&third).is_some());
+        sessions::remove_session_by_session_id(&third);
+        assert!(sessions::remove_session_by_session_id(&first).is_some());
+
+        // The peer sessions cap, checked against a private map to not race with other tests.
+        let limits = sessions::SessionLimits {
+            max_peer_sessions: 2,
+            max_ui_sessions_per_peer: 0,
+        };
+        let key = |i: usize| (format!("peer-{}", i), ConnType::DEFAULT_CONN);
+        let mut map: HashMap<(String, ConnType), FlutterSession> = HashMap::new();
+        for i in 0..2 {
+            assert!(sessions::check_limits(&map, &key(i), &limits).is_ok());
+            let s: FlutterSession = Default::default();
+            s.session_handlers
+                .write()
+                .unwrap()
+                .insert(SessionID::new_v4(), Default::default());
+            map.insert(key(i), s);
+        }
+        let err = sessions::check_limits(&map, &key(2), &limits).unwrap_err();
+        assert_eq!(err.reason, sessions::ERR_PEER_SESSION_LIMIT);
+        // More ui sessions of an existing peer session are not limited by the peer sessions cap.
+        assert!(sessions::check_limits(&map, &key(1), &limits).is_ok());
+        map.remove(&key(0));
+        assert!(sessions::check_limits(&map, &key(2), &limits).is_ok());
+    }
 
-    type TxQueryOnlines = UnboundedSender<Vec<String>>;
     lazy_static::lazy_static! {
-        static ref TX_QUERY_ONLINES: Arc<Mutex<Option<TxQueryOnlines>>> = Default::default();
+        // The current session is global.
+        static ref CUR_SESSION_TEST_LOCK: std::sync::Mutex<()> = Default::default();
     }
 
-    #[inline]
-    pub fn start_flutter_async_runner() {
-        std::thread::spawn(start_flutter_async_runner_);
+    fn add_focus_test_session(peer_id: &str) -> SessionID {
+        let session_id = SessionID::new_v4();
+        let limits = sessions::SessionLimits::default();
+        if !sessions::insert_peer_session_id_(
+            peer_id.to_owned(),
+            ConnType::DEFAULT_CONN,
+            session_id,
+            &limits,
+        )
+        .unwrap()
+        {
+            sessions::insert_session_(
+                peer_id.to_owned(),
+                session_id,
+                ConnType::DEFAULT_CONN,
+                Default::default(),
+                &limits,
+            )
+            .unwrap();
+        }
+        session_id
     }
 
-    #[allow(dead_code)]
-    pub fn stop_flutter_async_runner() {
-        let _ = TX_QUERY_ONLINES.lock().unwrap().take();
+    #[test]
+    fn test_cur_session_close_while_focused() {
+        let _lock = CUR_SESSION_TEST_LOCK.lock().unwrap();
+        let peer_id = format!("focus-test-{}", SessionID::new_v4());
+        let session_id = add_focus_test_session(&peer_id);
+        set_cur_session_id(session_id);
+        assert_eq!(get_cur_peer_id(), peer_id);
+        assert!(get_cur_session().is_some());
+
+        sessions::remove_session_by_session_id(&session_id);
+        assert_eq!(get_cur_session_id(), SessionID::default());
+        assert!(get_cur_session().is_none());
+        assert_eq!(get_cur_peer_id(), "");
     }
 
-    #[tokio::main(flavor = "current_thread")]
-    async fn start_flutter_async_runner_() {
-        let (tx_onlines, mut rx_onlines) = unbounded_channel::<Vec<String>>();
-        TX_QUERY_ONLINES.lock().unwrap().replace(tx_onlines);
+    #[test]
+    fn test_cur_session_multi_window_focus() {
+        let _lock = CUR_SESSION_TEST_LOCK.lock().unwrap();
+        let (peer_a, peer_b) = (
+            format!("focus-test-{}", SessionID::new_v4()),
+            format!("focus-test-{}", SessionID::new_v4()),
+        );
+        // Two windows of peer a, one of peer b.
+        let a1 = add_focus_test_session(&peer_a);
+        let a2 = add_focus_test_session(&peer_a);
+        let b1 = add_focus_test_session(&peer_b);
+        set_cur_session_id(a1);
+        set_cur_session_id(b1);
+        sessions::set_focused(&a2);
+        set_cur_session_id(a1);
+
+        // Closing a session not current keeps the current one.
+        sessions::remove_session_by_session_id(&a2);
+        assert_eq!(get_cur_session_id(), a1);
+
+        // The most recently focused one is promoted, of another peer.
+        sessions::remove_session_by_session_id(&a1);
+        assert_eq!(get_cur_session_id(), b1);
+        assert_eq!(get_cur_peer_id(), peer_b);
+
+        sessions::remove_session_by_session_id(&b1);
+        assert_eq!(get_cur_session_id(), SessionID::default());
+    }
 
-        loop {
-            select! {
-                ids = rx_onlines.recv() => {
-                    match ids {
-                        Some(_ids) => {
-                            #[cfg(not(any(target_os = "ios")))]
-                            crate::rendezvous_mediator::query_online_states(_ids, handle_query_onlines).await
-                        }
-                        None => {
-                            break;
+    fn c_args_round_trip(args: &[&str]) -> (Vec<String>, i32) {
+        let mut len: c_int = -1;
+        let ptr = rust_args_to_c_args(args.iter().map(|s| s.to_string()).collect(), &mut len);
+        assert!(!ptr.is_null());
+        let out = (0..len as usize)
+            .map(|i| unsafe { std::ffi::CStr::from_ptr(*ptr.add(i)) })
+            .map(|s| s.to_str().unwrap().to_owned())
+            .collect();
+        let escaped = C_ARGS_ESCAPED.load(Ordering::SeqCst);
+        unsafe { free_c_args(ptr, len) };
+        (out, escaped)
+    }
+
+    #[test]
+    fn test_list_sessions() {
+        let _lock = CUR_SESSION_TEST_LOCK.lock().unwrap();
+        let peer_id = format!("list-test-{}", SessionID::new_v4());
+        let (s1, s2) = (
+            add_focus_test_session(&peer_id),
+            add_focus_test_session(&peer_id),
+        );
+        let session = sessions::get_session_by_session_id(&s1).unwrap();
+        session.set_connection_type(true, false);
+        *session.last_capture_displays.write().unwrap() = Some((vec![0, 2], Instant::now()));
+
+        // As a C caller does.
+        let ptr = rustdesk_list_sessions();
+        assert!(!ptr.is_null());
+        let json = unsafe { std::ffi::CStr::from_ptr(ptr) }
+            .to_str()
+            .unwrap()
+            .to_owned();
+        unsafe { rustdesk_free_string(ptr) };
+        unsafe { rustdesk_free_string(std::ptr::null_mut()) };
+
+        let list: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        let entry = list.iter().find(|e| e["peer_id"] == peer_id).unwrap();
+        assert_eq!(entry["conn_type"], "DEFAULT_CONN");
+        assert_eq!(entry["ui_sessions"], 2);
+        assert_eq!(entry["displays"], json!([0, 2]));
+        assert_eq!(entry["secure"], true);
+        assert_eq!(entry["direct"], false);
+        assert!(entry["uptime_secs"].is_u64());
+
+        sessions::remove_session_by_session_id(&s1);
+        sessions::remove_session_by_session_id(&s2);
+    }
+
+    #[test]
+    fn test_background_session() {
+        let _lock = CUR_SESSION_TEST_LOCK.lock().unwrap();
+        let peer_id = format!("background-test-{}", SessionID::new_v4());
+        let (s1, s2) = (
+            add_focus_test_session(&peer_id),
+            add_focus_test_session(&peer_id),
+        );
+        let session = sessions::get_session_by_session_id(&s1).unwrap();
+        // No transfer.
+        assert!(!session.blocking_activity(&s1).blocking);
+        assert!(!sessions::remove_session_to_background(&s1));
+
+        session.job_progress(7, 0, 1024., 4096.);
+        // Not the last ui session.
+        assert!(!session.blocking_activity(&s1).blocking);
+        assert!(!sessions::remove_session_to_background(&s1));
+        sessions::remove_session_by_session_id(&s2);
+        let blocking = session.blocking_activity(&s1);
+        assert!(blocking.blocking);
+        assert_eq!(blocking.transfers, vec![7]);
+
+        assert!(sessions::remove_session_to_background(&s1));
+        assert!(sessions::get_session_by_session_id(&s1).is_none());
+        assert!(
+            sessions::get_session_by_peer_id(peer_id.clone(), ConnType::DEFAULT_CONN).is_some()
+        );
+        let list: Vec<serde_json::Value> = serde_json::from_str(&sessions::list_json()).unwrap();
+        let entry = list.iter().find(|e| e["peer_id"] == peer_id).unwrap();
+        assert_eq!(entry["ui_sessions"], 0);
+        assert_eq!(entry["background"], true);
+
+        // Closed once the transfers end.
+        session.job_done(7, 0);
+        assert!(sessions::get_session_by_peer_id(peer_id, ConnType::DEFAULT_CONN).is_none());
+    }
+
+    // One test, `C_ARGS_ESCAPED` is shared.
+    #[test]
+    fn test_rust_args_to_c_args() {
+        assert_eq!(c_args_round_trip(&[]), (vec![], 0));
+        let unicode = ["rustdesk", "--connect", "中文 ID", "émoji 🚀"];
+        assert_eq!(
+            c_args_round_trip(&unicode),
+            (unicode.iter().map(|s| s.to_string()).collect(), 0)
+        );
+        assert_eq!(
+            c_args_round_trip(&["a", "b\0c", "\0", ""]),
+            (
+                vec![
+                    "a".to_owned(),
+                    "b%00c".to_owned(),
+                    "%00".to_owned(),
+                    "".to_owned()
+                ],
+                2
+            )
+        );
+    }
+
+    #[test]
+    fn test_push_event() {
+        let (session_id, other) = (SessionID::new_v4(), SessionID::new_v4());
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        handler.msgbox("error", "Title", "Text", "", true);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "msgbox");
+        assert_eq!(events[0]["type"], "error");
+        assert_eq!(events[0]["title"], "Title");
+        assert_eq!(events[0]["text"], "Text");
+        assert_eq!(events[0]["hasRetry"], "true");
+
+        handler.push_event_to(&other, "cancel_msgbox", vec![("tag", "x")]);
+        assert!(collector.take_events().is_empty());
+        handler.cancel_msgbox("x");
+        assert_eq!(collector.take_events()[0]["tag"], "x");
+
+        handler.close_event_stream(session_id);
+        let events = collector.take();
+        assert!(matches!(&events[..], [EventToUI::Event(s)] if s == "close"));
+    }
+
+    #[test]
+    fn test_view_only_session() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            server_keyboard_enabled: Arc::new(RwLock::new(true)),
+            server_clipboard_enabled: Arc::new(RwLock::new(true)),
+            ..Default::default()
+        };
+        session.lc.write().unwrap().view_only_session = true;
+        assert!(!session.is_text_clipboard_required());
+
+        send_mouse(0, 10, 10, false, false, false, false, &session);
+        send_pointer_device_event(
+            PointerDeviceEvent::new(),
+            false,
+            false,
+            false,
+            false,
+            &session,
+        );
+        session.send_key_event(&KeyEvent::new());
+        session.send(Data::Message(crate::create_clipboard_msg("text".to_owned())));
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            block_input: option_message::BoolOption::Yes.into(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        session.send(Data::Message(msg));
+        assert!(rx.try_recv().is_err());
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "view_only_blocked");
+
+        session.send_chat("hello".to_owned());
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_misc()));
+
+        session.lc.write().unwrap().view_only_session = false;
+        send_mouse(0, 10, 10, false, false, false, false, &session);
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_mouse_event()));
+        assert!(collector.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_hotkey_rules_of_session() {
+        use crate::client::hotkey_rules::HotkeyRules;
+
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            ..Default::default()
+        };
+        session.hotkey_rules.lock().unwrap().set(
+            HotkeyRules::parse(
+                r#"[{"name": "lock", "keys": "meta+l", "action": "blocked"},
+                    {"name": "switch", "keys": "alt+tab", "action": "local"}]"#,
+            )
+            .unwrap(),
+        );
+        let key = |ck: Option<ControlKey>, chr: u32, modifier: ControlKey| {
+            let mut evt = KeyEvent::new();
+            match ck {
+                Some(ck) => evt.set_control_key(ck),
+                None => evt.set_chr(chr),
+            }
+            evt.down = true;
+            evt.modifiers = vec![modifier.into()];
+            evt
+        };
+        for _ in 0..2 {
+            session.send_key_event(&key(None, 'l' as _, ControlKey::Meta));
+        }
+        session.send_key_event(&key(Some(ControlKey::Tab), 0, ControlKey::Alt));
+        assert!(rx.try_recv().is_err());
+        // Throttled.
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "hotkey_blocked");
+        assert_eq!(events[0]["rule"], "lock");
+
+        session.send_key_event(&key(Some(ControlKey::Tab), 0, ControlKey::Control));
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_key_event()));
+        assert!(session.get_hotkey_rules().contains("alt+tab"));
+    }
+
+    #[test]
+    fn test_restart_remote_device_denied() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            ..Default::default()
+        };
+        session.restart_remote_device();
+        assert!(session.is_restarting_remote_device());
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_misc()));
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "remote_restarting");
+        assert_eq!(events[0]["state"], "requested");
+
+        // The host has no permission to restart.
+        session.on_restart_remote_device_denied();
+        assert!(!session.is_restarting_remote_device());
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["state"], "denied");
+        assert_eq!(events[1]["name"], "msgbox");
+        assert_eq!(events[1]["text"], "remote_restart_denied_tip");
+
+        // Not reconnected if the connection is closed afterwards.
+        session.on_remote_restarting();
+        assert!(!session.lc.read().unwrap().restart_reconnecting);
+        assert!(collector.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_codec_negotiated_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            ..Default::default()
+        };
+        session.on_codec_negotiated(&scrap::CodecFormat::VP9);
+        // The peer is asked to renegotiate once.
+        session.on_hw_decoder_failed(scrap::CodecFormat::H264);
+        session.on_hw_decoder_failed(scrap::CodecFormat::H264);
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_misc()));
+        assert!(rx.try_recv().is_err());
+        assert!(!session.codec_capabilities().h264.local);
+        assert!(session.set_codec_preference("h263").is_err());
+
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "codec_negotiated");
+        assert_eq!(events[0]["codec"], "VP9");
+        assert_eq!(events[0]["reason"], "preference");
+    }
+
+    #[test]
+    fn test_decoder_info_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            ..Default::default()
+        };
+        // The frames of the hardware codecs are skipped till the host switches the codec.
+        let video_handler = crate::client::VideoHandler::new(0, true);
+        assert!(video_handler
+            .select_decoder(&scrap::CodecFormat::Unknown)
+            .is_none());
+        let selection = video_handler
+            .select_decoder(&scrap::CodecFormat::H264)
+            .unwrap();
+        assert!(selection.fall_back);
+        session.decoder_info(0, &selection.info);
+        let selection = video_handler
+            .select_decoder(&scrap::CodecFormat::VP9)
+            .unwrap();
+        assert!(!selection.fall_back);
+        session.decoder_info(0, &selection.info);
+
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "decoder_info");
+        assert_eq!(events[0]["codec"], "H264");
+        assert_eq!(events[0]["hardware"], "false");
+        assert_eq!(events[0]["reason"], "forced-software");
+        assert_eq!(events[1]["codec"], "VP9");
+        assert_eq!(events[1]["adapter"], "");
+    }
+
+    #[test]
+    fn test_permission_snapshot_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            server_clipboard_enabled: Arc::new(RwLock::new(false)),
+            ..Default::default()
+        };
+        session.on_connection_ready(true, true);
+        // Toggled by the options of the host mid-session.
+        session.set_permission("clipboard", false);
+        session.set_permission("clipboard", true);
+
+        let events = collector.take_events();
+        let names: Vec<_> = events.iter().map(|e| e["name"].as_str().unwrap()).collect();
+        assert_eq!(
+            names,
+            vec!["connection_ready", "permission", "permission", "permission"]
+        );
+        for name in crate::ui_session_interface::PERMISSIONS {
+            assert_eq!(events[1][name], "true");
+        }
+        assert_eq!(events[2]["clipboard"], "false");
+        assert_eq!(events[3]["clipboard"], "true");
+        // The permissions of the last connection are reset.
+        assert!(*session.server_clipboard_enabled.read().unwrap());
+    }
+
+    #[test]
+    fn test_quality_status_displays_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let decode_stats = HashMap::from([(
+            0,
+            crate::client::DecodeStats {
+                chroma: Some(Chroma::I420),
+                delay: Some(4),
+            },
+        )]);
+        handler.update_quality_status(QualityStatus {
+            speed: Some("1.00kB/s".to_owned()),
+            ..QualityStatus::of_displays(HashMap::from([(0, 30)]), &decode_stats)
+        });
+        handler.update_quality_status(QualityStatus {
+            delay: Some(20),
+            ..Default::default()
+        });
+
+        let events = collector.take_events();
+        // The totals are the same as the single display.
+        assert_eq!(events[0]["fps"], r#"{"0":30}"#);
+        assert_eq!(events[0]["chroma"], "4:2:0");
+        let displays: serde_json::Value =
+            serde_json::from_str(events[0]["displays"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            displays,
+            serde_json::json!({ "0": { "fps": 30, "delay": 4, "chroma": "4:2:0" } })
+        );
+        // The session level updates leave the displays untouched.
+        assert_eq!(events[1]["delay"], "20");
+        assert_eq!(events[1]["displays"], "");
+    }
+
+    #[test]
+    fn test_downscale_events() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        handler.downscale.lock().unwrap().set_max_dimension(1920);
+        let mut pi = PeerInfo::new();
+        pi.displays = vec![DisplayInfo {
+            width: 3840,
+            height: 2160,
+            ..Default::default()
+        }];
+        handler.set_peer_info(&pi);
+        handler.set_cursor_position(CursorPosition {
+            x: 101,
+            y: 50,
+            ..Default::default()
+        });
+        handler.update_quality_status(QualityStatus::default());
+
+        let events = collector.take_events();
+        let displays: serde_json::Value =
+            serde_json::from_str(events[0]["displays"].as_str().unwrap()).unwrap();
+        assert_eq!(displays[0]["width"], 1920);
+        assert_eq!(displays[0]["height"], 1080);
+        assert_eq!(events[1]["name"], "cursor_position");
+        assert_eq!(events[1]["x"], "51");
+        assert_eq!(events[1]["y"], "25");
+        assert_eq!(events[2]["downscale"], "0.50");
+        // The peer info is kept in the remote coordinates.
+        assert_eq!(handler.peer_info.read().unwrap().displays[0].width, 3840);
+    }
+
+    #[test]
+    fn test_follow_cursor_dwell() {
+        let mut state = FollowCursorState {
+            enabled: true,
+            ..Default::default()
+        };
+        let t = Instant::now();
+        // Not logged in yet.
+        assert_eq!(state.on_cursor_display(1, t), FollowCursorAction::None);
+        state.seed_display(0);
+        assert_eq!(state.display, Some(0));
+        assert_eq!(state.on_cursor_display(0, t), FollowCursorAction::None);
+
+        // Armed on entering another display, switched after the dwell.
+        assert_eq!(state.on_cursor_display(1, t), FollowCursorAction::Arm);
+        let half = FOLLOW_CURSOR_DWELL / 2;
+        assert_eq!(
+            state.on_cursor_display(1, t + half),
+            FollowCursorAction::None
+        );
+        assert_eq!(state.on_deadline(t + half), None);
+        assert_eq!(
+            state.on_cursor_display(1, t + FOLLOW_CURSOR_DWELL),
+            FollowCursorAction::Switch
+        );
+        assert!(state.pending.is_none());
+
+        // The dwell restarts on moving to a third display.
+        assert_eq!(state.on_cursor_display(2, t), FollowCursorAction::Arm);
+        assert_eq!(
+            state.on_cursor_display(3, t + half),
+            FollowCursorAction::Arm
+        );
+        assert_eq!(state.on_deadline(t + FOLLOW_CURSOR_DWELL), None);
+        assert_eq!(state.on_deadline(t + half + FOLLOW_CURSOR_DWELL), Some(3));
+        assert_eq!(state.on_deadline(t + half + FOLLOW_CURSOR_DWELL), None);
+
+        // Disarmed on moving back to the shown display.
+        assert_eq!(state.on_cursor_display(1, t), FollowCursorAction::Arm);
+        assert_eq!(state.on_cursor_display(0, t), FollowCursorAction::None);
+        assert_eq!(state.on_deadline(t + FOLLOW_CURSOR_DWELL * 2), None);
+
+        // The displays chosen by the ui are not overridden by the login.
+        state.set_display(&[2]);
+        state.seed_display(0);
+        assert_eq!(state.display, Some(2));
+        // Multiple displays shown.
+        state.set_display(&[0, 1]);
+        assert_eq!(state.on_cursor_display(2, t), FollowCursorAction::None);
+
+        state.set_display(&[0]);
+        assert_eq!(state.on_cursor_display(1, t), FollowCursorAction::Arm);
+        state.enabled = false;
+        assert_eq!(state.on_deadline(t + FOLLOW_CURSOR_DWELL), None);
+    }
+
+    #[test]
+    fn test_follow_cursor_timer() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let mut pi = PeerInfo::new();
+        pi.displays = (0..2)
+            .map(|i| DisplayInfo {
+                x: i * 1920,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            })
+            .collect();
+        pi.current_display = 1;
+        handler.set_peer_info(&pi);
+        {
+            let mut handlers = handler.session_handlers.write().unwrap();
+            let state = &mut handlers.get_mut(&session_id).unwrap().follow_cursor;
+            // Seeded by the login.
+            assert_eq!(state.display, Some(1));
+            state.enabled = true;
+        }
+        collector.take_events();
+
+        // Only one cursor position, the switch is done by the timer.
+        handler.follow_remote_cursor(10, 10);
+        let deadline = Instant::now() + FOLLOW_CURSOR_DWELL + Duration::from_secs(5);
+        let followed = loop {
+            let events = collector.take_events();
+            if let Some(e) = events.iter().find(|e| e["name"] == "display_followed") {
+                break e.clone();
+            }
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(20));
+        };
+        assert_eq!(followed["display"], "0");
+    }
+
+    #[test]
+    fn test_capture_reduction_keeps_displays() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let mut pi = PeerInfo::new();
+        pi.displays = (0..3)
+            .map(|i| DisplayInfo {
+                x: i * 1920,
+                width: 1920,
+                height: 1080,
+                ..Default::default()
+            })
+            .collect();
+        handler.set_peer_info(&pi);
+        // This ui session shows all the displays.
+        handler
+            .session_handlers
+            .write()
+            .unwrap()
+            .get_mut(&session_id)
+            .unwrap()
+            .follow_cursor
+            .set_display(&[0, 1, 2]);
+        // Another ui session shows the display 1 alone.
+        let mut other = SessionHandler::default();
+        other.follow_cursor.set_display(&[1]);
+        handler
+            .session_handlers
+            .write()
+            .unwrap()
+            .insert(SessionID::new_v4(), other);
+        handler
+            .capture_reduction
+            .lock()
+            .unwrap()
+            .on_capture_set(&[0, 1, 2]);
+        handler.on_input_position(1920 * 2 + 10, 10);
+        collector.take_events();
+
+        let reduced: Vec<_> = (0..capture_reduction::CONGESTED_SECS)
+            .filter_map(|_| handler.on_video_congestion(true))
+            .collect();
+        assert_eq!(reduced, vec![vec![1, 2]]);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "capture_reduced");
+        assert_eq!(events[0]["displays"], "[1,2]");
+        assert_eq!(
+            handler.last_capture_displays.read().unwrap().as_ref().unwrap().0,
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_peer_capabilities_of_peer_info() {
+        let (handler, collector) = FlutterHandler::new_with_collector(SessionID::new_v4());
+        assert!(!handler.get_peer_capabilities().multi_ui_session);
+        handler.set_peer_info(&PeerInfo {
+            version: "1.1.9".to_owned(),
+            platform: "Windows".to_owned(),
+            features: vec![Features {
+                privacy_mode: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let caps = handler.get_peer_capabilities();
+        assert!(!caps.privacy_mode && !caps.restart && !caps.multi_ui_session);
+        let events = collector.take_events();
+        assert_eq!(events[0]["features"], r#"{"privacy_mode":0}"#);
+        assert_eq!(caps.features, Default::default());
+
+        handler.set_peer_info(&PeerInfo {
+            version: "1.2.4".to_owned(),
+            platform: "Windows".to_owned(),
+            platform_additions: r#"{"support_virtual_display_request": true}"#.to_owned(),
+            ..Default::default()
+        });
+        let caps = handler.get_peer_capabilities();
+        assert!(caps.multi_ui_session && caps.virtual_display_control && !caps.touch_pan);
+
+        // Updated with the platform additions.
+        handler.update_peer_capabilities(&crate::common::PeerCapabilities {
+            can_inject_input: false,
+            ..caps
+        });
+        assert!(!handler.get_peer_capabilities().can_inject_input);
+    }
+
+    // Hostile strings of a peer, built from a fixed seed.
+    fn hostile_strings() -> Vec<String> {
+        let mut seed: u64 = 0x9e3779b97f4a7c15;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let pool = [
+            '\0', '\n', '\r', '\t', '\u{1b}', '\u{7f}', '\u{85}', '"', '\\', 'é', '字', '😀', 'a',
+        ];
+        let mut strings: Vec<String> = (0..64)
+            .map(|_| {
+                let len = (next() % 512) as usize;
+                (0..len)
+                    .map(|_| pool[(next() % pool.len() as u64) as usize])
+                    .collect()
+            })
+            .collect();
+        strings.push("x".repeat(4 << 20));
+        strings.push(format!("{{\"a\": \"{}\"}}", "y".repeat(1 << 20)));
+        strings.push("{\"a\": [".repeat(1000));
+        strings
+    }
+
+    #[test]
+    fn test_peer_data_sanitized() {
+        let (handler, collector) = FlutterHandler::new_with_collector(SessionID::new_v4());
+        let limits = peer_data::Limits::default();
+        for s in hostile_strings() {
+            handler.set_peer_info(&PeerInfo {
+                username: s.clone(),
+                hostname: s.clone(),
+                version: s.clone(),
+                platform_additions: s.clone(),
+                ..Default::default()
+            });
+            handler.new_message(s.clone());
+            handler.set_platform_additions(&s);
+            for e in collector.take() {
+                let EventToUI::Event(out) = e else {
+                    continue;
+                };
+                let event: HashMap<String, String> = serde_json::from_str(&out).unwrap();
+                match event["name"].as_str() {
+                    "peer_info" => {
+                        for field in ["username", "hostname", "version"] {
+                            assert!(event[field].len() <= limits.field);
+                            assert!(!event[field].chars().any(|c| c.is_control()));
                         }
+                        let additions = &event["platform_additions"];
+                        assert!(additions.is_empty() || additions.len() <= limits.additions);
+                    }
+                    "sync_platform_additions" => {
+                        let additions = &event["platform_additions"];
+                        assert!(
+                            additions.is_empty()
+                                || serde_json::from_str::<serde_json::Value>(additions)
+                                    .map_or(false, |v| v.is_object())
+                        );
+                    }
+                    "chat_client_mode" => {
+                        assert!(event["text"].len() <= limits.chat);
+                        assert!(!event["text"]
+                            .chars()
+                            .any(|c| c.is_control() && c != '\n'));
                     }
+                    _ => {}
                 }
             }
         }
+
+        // Reported once in a while.
+        handler.new_message("\u{7}".to_owned());
+        let events = collector.take_events();
+        assert!(!events.iter().any(|e| e["name"] == "peer_data_truncated"));
+        assert_eq!(events[0]["text"], "");
+
+        // The local events are not limited.
+        let long = "z\t".repeat(8 << 10);
+        handler.push_event("msgbox", vec![("text", &long)]);
+        assert_eq!(collector.take_events()[0]["text"], long);
+    }
+
+    #[test]
+    fn test_peer_data_truncated_event() {
+        let (handler, collector) = FlutterHandler::new_with_collector(SessionID::new_v4());
+        handler.set_peer_info(&PeerInfo {
+            hostname: "pc\u{1b}[2J".to_owned(),
+            platform_additions: "[]".to_owned(),
+            ..Default::default()
+        });
+        let events = collector.take_events();
+        assert_eq!(events[0]["hostname"], "pc[2J");
+        assert_eq!(events[0]["platform_additions"], "{}");
+        let truncated: Vec<_> = events
+            .iter()
+            .filter(|e| e["name"] == "peer_data_truncated")
+            .map(|e| e["field"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(truncated, vec!["hostname", "platform_additions"]);
     }
 
-    pub fn query_onlines(ids: Vec<String>) -> ResultType<()> {
-        if let Some(tx) = TX_QUERY_ONLINES.lock().unwrap().as_ref() {
-            let _ = tx.send(ids)?;
+    #[test]
+    fn test_frame_viewports_event() {
+        let (handler, collector) = FlutterHandler::new_with_collector(SessionID::new_v4());
+        let v = Viewport {
+            display: 1,
+            x: 100,
+            y: 200,
+            width: 640,
+            height: 480,
+            scale: 1.,
+            ..Default::default()
+        };
+        handler.frame_viewport(1, Some(&v));
+        handler.frame_viewport(1, Some(&v));
+        handler.frame_viewport(0, None);
+        handler.frame_viewport(1, None);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "frame_viewports");
+        let viewports: serde_json::Value =
+            serde_json::from_str(events[0]["viewports"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            viewports,
+            serde_json::json!({"1": {"x": 100, "y": 200, "width": 640, "height": 480}})
+        );
+        assert_eq!(events[1]["viewports"], "{}");
+    }
+
+    #[test]
+    fn test_color_mode_fallback_event() {
+        let (handler, collector) = FlutterHandler::new_with_collector(SessionID::new_v4());
+        handler.color_mode_fallback(&crate::client::color_mode::Fallback {
+            display: 1,
+            reason: crate::client::color_mode::REASON_CODEC,
+            codec: "H264".to_owned(),
+            bitrate_factor: 2.0,
+        });
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "color_mode_fallback");
+        assert_eq!(events[0]["display"], "1");
+        assert_eq!(events[0]["reason"], "codec");
+        assert_eq!(events[0]["codec"], "H264");
+        assert_eq!(events[0]["bitrate_factor"], "2");
+    }
+
+    #[test]
+    fn test_host_display_state_event() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        handler.host_display_state("locked");
+        handler.host_display_state("display_off");
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "host_display_state");
+        assert_eq!(events[0]["state"], "locked");
+        assert_eq!(events[1]["state"], "display_off");
+
+        // The ui session attaching later gets the latest state only.
+        let later = SessionID::new_v4();
+        let later_collector: Arc<EventCollector<EventToUI>> = Default::default();
+        let sink: Arc<dyn EventSink<EventToUI>> = later_collector.clone();
+        handler.session_handlers.write().unwrap().insert(
+            later,
+            SessionHandler {
+                event_stream: Some(sink),
+                ..Default::default()
+            },
+        );
+        handler.replay_stateful_events(&later);
+        let events = later_collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["state"], "display_off");
+        assert!(collector.take_events().is_empty());
+
+        handler.host_display_state("active");
+        assert_eq!(collector.take_events()[0]["state"], "active");
+        assert_eq!(later_collector.take_events()[0]["state"], "active");
+        handler.replay_stateful_events(&later);
+        assert!(later_collector.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_session_activity_per_ui_session() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let other = SessionID::new_v4();
+        let other_collector: Arc<EventCollector<EventToUI>> = Default::default();
+        let sink: Arc<dyn EventSink<EventToUI>> = other_collector.clone();
+        handler.session_handlers.write().unwrap().insert(
+            other,
+            SessionHandler {
+                event_stream: Some(sink),
+                ..Default::default()
+            },
+        );
+        let activity = |collector: &EventCollector<EventToUI>| {
+            collector
+                .take_events()
+                .into_iter()
+                .filter(|e| e["name"] == "session_activity")
+                .last()
+                .unwrap()
+        };
+
+        handler.new_message("hi".to_owned());
+        handler.new_message("hello".to_owned());
+        handler.job_progress(1, 0, 1.0, 1.0);
+        handler.on_voice_call_incoming();
+        let event = activity(&collector);
+        assert_eq!(event["unread_chat"], "2");
+        assert_eq!(event["transfers"], "1");
+        assert_eq!(event["voice_call"], "incoming");
+
+        // Read in one window only.
+        handler.mark_activity_read(&session_id, session_activity::CATEGORY_CHAT);
+        assert_eq!(activity(&collector)["unread_chat"], "0");
+        assert_eq!(activity(&other_collector)["unread_chat"], "2");
+        handler.mark_activity_read(&session_id, session_activity::CATEGORY_CHAT);
+        assert!(collector.take_events().is_empty());
+
+        handler.job_done(1, 0);
+        let event = activity(&collector);
+        assert_eq!(event["unread_chat"], "0");
+        assert_eq!(event["transfers"], "0");
+        assert_eq!(event["unread_transfer"], "1");
+        assert_eq!(activity(&other_collector)["unread_chat"], "2");
+    }
+
+    #[test]
+    fn test_audio_only_session() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            ..Default::default()
+        };
+        session.lc.write().unwrap().audio_only_session = true;
+
+        // Removing displays doesn't request the video.
+        session.capture_displays(vec![], vec![0], vec![]);
+        assert!(session.is_audio_only_session());
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.has_misc()));
+
+        session.switch_display(1);
+        assert!(!session.is_audio_only_session());
+        let Ok(Data::Message(m)) = rx.try_recv() else {
+            panic!("no option message");
+        };
+        let option = m.misc().option();
+        assert_eq!(option.audio_only.enum_value(), Ok(option_message::BoolOption::No));
+        assert!(matches!(rx.try_recv(), Ok(Data::Message(m)) if m.misc().has_switch_display()));
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "audio_only");
+        assert_eq!(events[0]["audio_only"], "false");
+
+        session.switch_display(0);
+        assert!(collector.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_remote_sysinfo() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        let (tx, mut rx) = hbb_common::tokio::sync::mpsc::unbounded_channel();
+        let session: Session<FlutterHandler> = Session {
+            ui_handler: handler,
+            sender: Arc::new(RwLock::new(Some(tx))),
+            ..Default::default()
+        };
+        // The old versions never answer.
+        session.get_remote_sysinfo();
+        assert!(rx.try_recv().is_err());
+        let events = collector.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["name"], "remote_sysinfo");
+        assert_eq!(events[0]["error"], "remote_sysinfo_unsupported_tip");
+
+        crate::common::merge_platform_additions(
+            &mut session.lc.write().unwrap().platform_additions,
+            r#"{"capabilities": {"remote_sysinfo": true}}"#,
+        );
+        session.get_remote_sysinfo();
+        let Ok(Data::Message(m)) = rx.try_recv() else {
+            panic!("no request");
+        };
+        assert!(m.misc().has_remote_sysinfo_request());
+        assert!(collector.take_events().is_empty());
+
+        session.on_remote_sysinfo(&RemoteSysinfo {
+            denied: true,
+            ..Default::default()
+        });
+        session.on_remote_sysinfo(&RemoteSysinfo {
+            json: r#"{"cpu_cores":8}"#.to_owned(),
+            ..Default::default()
+        });
+        let events = collector.take_events();
+        assert_eq!(events[0]["error"], "remote_sysinfo_denied_tip");
+        assert_eq!(events[1]["sysinfo"], r#"{"cpu_cores":8}"#);
+        assert_eq!(events[1]["error"], "");
+    }
+
+    #[test]
+    fn test_peer_card_updated_event() {
+        use hbb_common::config::PeerInfoSerde;
+        let id = "test_peer_card_updated_event";
+        let old = PeerInfoSerde {
+            username: "user".to_owned(),
+            hostname: "host".to_owned(),
+            platform: "Linux".to_owned(),
+        };
+        assert!(peer_card_updated_event(id, &old, &old.clone()).is_none());
+
+        let new = PeerInfoSerde {
+            hostname: "host2".to_owned(),
+            ..old.clone()
+        };
+        let event = peer_card_updated_event(id, &old, &new).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&event).unwrap();
+        assert_eq!(v["name"], "peer_card_updated");
+        assert_eq!(v["id"], id);
+        assert_eq!(v["changes"], json!({"hostname": "host2"}));
+        // Another ui session of the peer connects at the same time.
+        assert!(peer_card_updated_event(id, &old, &new).is_none());
+        assert!(peer_card_updated_event(id, &new, &new).is_none());
+    }
+
+    #[test]
+    fn test_msgbox_throttle() {
+        let key = || ("error".to_owned(), "Connection Error".to_owned(), "Reset".to_owned());
+        let window = Duration::from_secs(2);
+        let t0 = Instant::now();
+        let body = |link: &str| MsgboxBody {
+            link: link.to_owned(),
+            params: None,
+        };
+        let mut throttle = MsgboxThrottle::default();
+        assert_eq!(throttle.check(key(), &body(""), true, window, t0), MsgboxAction::Push(1));
+        let t1 = t0 + Duration::from_millis(500);
+        let epoch = throttle.epoch;
+        assert_eq!(
+            throttle.check(key(), &body("a"), true, window, t1),
+            MsgboxAction::Schedule(Duration::from_millis(1500), epoch)
+        );
+        assert_eq!(throttle.check(key(), &body("b"), true, window, t1), MsgboxAction::Suppress);
+        // The latest one is pushed when the window ends.
+        let t2 = t0 + window;
+        assert_eq!(throttle.take_pending(epoch, t2), Some((key(), body("b"), 3)));
+        assert_eq!(throttle.take_pending(epoch, t2), None);
+        assert_eq!(throttle.check(key(), &body(""), false, window, t2), MsgboxAction::Suppress);
+        let t3 = t2 + window;
+        assert_eq!(throttle.check(key(), &body(""), false, window, t3), MsgboxAction::Push(5));
+
+        // The pending one is dropped on reset.
+        assert!(matches!(
+            throttle.check(key(), &body(""), true, window, t3),
+            MsgboxAction::Schedule(..)
+        ));
+        throttle.reset();
+        assert_eq!(throttle.take_pending(epoch, t3 + window), None);
+        assert_eq!(throttle.check(key(), &body(""), false, window, t3), MsgboxAction::Push(1));
+        let action = throttle.check(key(), &body(""), false, Duration::ZERO, t3);
+        assert_eq!(action, MsgboxAction::Push(2));
+    }
+
+    #[test]
+    fn test_msgbox_dedup() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        for _ in 0..5 {
+            handler.msgbox("error", "Connection Error", "Reset by the peer", "", false);
+        }
+        handler.msgbox("error", "Connection Error", "Timeout", "", false);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["text"], "Reset by the peer");
+        assert_eq!(events[0]["repeat"], "1");
+        assert_eq!(events[1]["text"], "Timeout");
+
+        handler.msgbox("error", "Connection Error", "Timeout", "", false);
+        assert!(collector.take_events().is_empty());
+        handler.cancel_msgbox("");
+        handler.msgbox("error", "Connection Error", "Timeout", "", false);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "cancel_msgbox");
+        assert_eq!(events[1]["repeat"], "1");
+    }
+
+    #[test]
+    fn test_msgbox_keyed() {
+        let session_id = SessionID::new_v4();
+        let (handler, collector) = FlutterHandler::new_with_collector(session_id);
+        handler.msgbox("error", "Connection Error", "Reset by the peer", "", false);
+        let (key, params) = connection_error_key("Connection refused (os error 111)").unwrap();
+        handler.msgbox_keyed("error", "Connection Error", key, &params, "", true);
+        let events = collector.take_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["text"], "Reset by the peer");
+        assert!(events[0].get("keyed").is_none());
+        assert!(events[0].get("params").is_none());
+
+        assert_eq!(events[1]["text"], "connection_refused_tip");
+        assert_eq!(events[1]["keyed"], "true");
+        assert_eq!(events[1]["hasRetry"], "true");
+        let params: serde_json::Value =
+            serde_json::from_str(events[1]["params"].as_str().unwrap()).unwrap();
+        assert_eq!(params, json!({"error": "Connection refused (os error 111)"}));
+
+        assert!(connection_error_key("Remote desktop is offline").is_some());
+        let err = "Failed to connect via relay server: proxy_auth_failed: 10.0.0.1:3128: rejected";
+        assert_eq!(connection_error_key(err).unwrap().0, "proxy_auth_failed");
+        let err = "proxy_unreachable: 10.0.0.1:3128: Connection refused (os error 111)";
+        assert_eq!(connection_error_key(err).unwrap().0, "proxy_unreachable");
+        assert!(connection_error_key("Reset by the peer").is_none());
+        assert!(!check_if_retry("error", "Connection Error", "remote_offline_tip", false));
+        assert!(!check_if_retry("error", "Connection Error", "key_mismatch_tip", false));
+    }
+
+    fn add_loopback_session(
+        id: &str,
+        is_file_transfer: bool,
+        password: String,
+    ) -> (SessionID, Arc<EventCollector<EventToUI>>) {
+        let session_id = SessionID::new_v4();
+        session_add(
+            &session_id,
+            id,
+            SessionAddOptions {
+                is_file_transfer,
+                password,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let collector: Arc<EventCollector<EventToUI>> = Default::default();
+        session_start_(&session_id, id, collector.clone()).unwrap();
+        (session_id, collector)
+    }
+
+    /// The session to the host of this process, logged in by the temporary password.
+    fn start_loopback_session(
+        is_file_transfer: bool,
+    ) -> (SessionID, Arc<EventCollector<EventToUI>>) {
+        let id = format!("loopback-{}", SessionID::new_v4());
+        crate::client::loopback::listen(&id);
+        add_loopback_session(
+            &id,
+            is_file_transfer,
+            hbb_common::password_security::temporary_password(),
+        )
+    }
+
+    fn start_scripted_session(
+        is_file_transfer: bool,
+    ) -> (
+        SessionID,
+        Arc<EventCollector<EventToUI>>,
+        crate::client::loopback::LoopbackHost,
+    ) {
+        let id = format!("loopback-{}", SessionID::new_v4());
+        let host = crate::client::loopback::listen_scripted(&id);
+        let (session_id, collector) = add_loopback_session(&id, is_file_transfer, "".to_owned());
+        (session_id, collector, host)
+    }
+
+    fn close_loopback_session(session_id: &SessionID) {
+        if let Some(session) = sessions::remove_session_by_session_id(session_id) {
+            session.close();
+        }
+    }
+
+    fn loopback_peer_info() -> PeerInfo {
+        PeerInfo {
+            username: "loopback".to_owned(),
+            hostname: "loopback-host".to_owned(),
+            platform: "Linux".to_owned(),
+            version: crate::VERSION.to_owned(),
+            displays: vec![display("", 0, 1920, 1080)],
+            ..Default::default()
+        }
+    }
+
+    /// The events pushed until the first one named `name`, inclusive.
+    async fn wait_events(
+        collector: &EventCollector<EventToUI>,
+        name: &str,
+    ) -> Vec<serde_json::Value> {
+        let mut events = vec![];
+        let deadline = Instant::now() + crate::client::loopback::RECV_TIMEOUT;
+        while Instant::now() < deadline {
+            for event in collector.take_events() {
+                let found = event["name"] == name;
+                events.push(event);
+                if found {
+                    return events;
+                }
+            }
+            hbb_common::sleep(0.01).await;
+        }
+        panic!("No {} event, got {:?}", name, events);
+    }
+
+    #[tokio::test]
+    async fn test_loopback_host_login() {
+        let (session_id, collector) = start_loopback_session(true);
+        let events = wait_events(&collector, "peer_info").await;
+        assert!(events.iter().any(|e| e["name"] == "connection_ready"));
+        let pi = events.last().unwrap();
+        assert_eq!(pi["hostname"], whoami::hostname());
+        assert_eq!(pi["version"], crate::VERSION);
+        close_loopback_session(&session_id);
+
+        // Not logged in by the wrong password.
+        let id = format!("loopback-{}", SessionID::new_v4());
+        crate::client::loopback::listen(&id);
+        let (session_id, collector) = add_loopback_session(&id, true, "wrong".to_owned());
+        let msgbox = loop {
+            let events = wait_events(&collector, "msgbox").await;
+            assert!(!events.iter().any(|e| e["name"] == "peer_info"));
+            let msgbox = events.last().unwrap().clone();
+            if msgbox["type"] == "re-input-password" {
+                break msgbox;
+            }
+        };
+        assert_eq!(msgbox["title"], crate::client::LOGIN_MSG_PASSWORD_WRONG);
+        close_loopback_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn test_loopback_peer_info() {
+        let (session_id, collector, mut host) = start_scripted_session(false);
+        let lr = host.accept(loopback_peer_info()).await.unwrap();
+        assert_eq!(lr.version, crate::VERSION);
+
+        let events = wait_events(&collector, "peer_info").await;
+        assert!(events.iter().any(|e| e["name"] == "connection_ready"));
+        let pi = events.last().unwrap();
+        assert_eq!(pi["hostname"], "loopback-host");
+        assert_eq!(pi["platform"], "Linux");
+        let displays: serde_json::Value =
+            serde_json::from_str(pi["displays"].as_str().unwrap()).unwrap();
+        assert_eq!(displays[0]["width"], 1920);
+        close_loopback_session(&session_id);
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    #[tokio::test]
+    async fn test_loopback_clipboard_round_trip() {
+        lazy_static::lazy_static! {
+            static ref RECEIVED: std::sync::Mutex<Vec<String>> = Default::default();
+        }
+        let (session_id, collector, mut host) = start_scripted_session(false);
+        host.accept(loopback_peer_info()).await.unwrap();
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+        // The echo of the host is kept off the local clipboard.
+        assert!(session.hooks.write().unwrap().add(
+            "loopback".to_owned(),
+            0,
+            SessionHook::OnClipboard(|_, content| match content.strip_prefix("echo:") {
+                Some(text) => {
+                    RECEIVED.lock().unwrap().push(text.to_owned());
+                    HookAction::Block
+                }
+                None => HookAction::Continue,
+            }),
+        ));
+
+        session.send(Data::Message(crate::create_clipboard_msg(
+            "loopback clipboard".to_owned(),
+        )));
+        let cb = host
+            .recv(|msg| match msg.union {
+                Some(message::Union::Clipboard(cb)) => Some(cb),
+                _ => None,
+            })
+            .await
+            .unwrap();
+        let content = if cb.compress {
+            hbb_common::compress::decompress(&cb.content)
         } else {
-            bail!("No tx_query_onlines");
+            cb.content.to_vec()
+        };
+        let text = String::from_utf8(content).unwrap();
+        assert_eq!(text, "loopback clipboard");
+
+        host.send(&crate::create_clipboard_msg(format!("echo:{}", text)))
+            .await
+            .unwrap();
+        let events = wait_events(&collector, "action_blocked_by_plugin").await;
+        assert_eq!(events.last().unwrap()["action"], "clipboard");
+        assert_eq!(*RECEIVED.lock().unwrap(), vec!["loopback clipboard"]);
+        close_loopback_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn test_loopback_file_job_progress() {
+        let dir = std::env::temp_dir().join(format!("loopback-{}", SessionID::new_v4()));
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        // 8 blocks of the transfer.
+        let data = vec![7u8; 8 * 128 * 1024];
+        std::fs::write(from.join("a.bin"), &data).unwrap();
+
+        let (session_id, collector) = start_loopback_session(true);
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+        session.send_files(
+            1,
+            from.to_string_lossy().to_string(),
+            to.to_string_lossy().to_string(),
+            0,
+            false,
+            true,
+        );
+
+        let events = wait_events(&collector, "job_done").await;
+        let progress: Vec<_> = events
+            .iter()
+            .filter(|e| e["name"] == "job_progress")
+            .collect();
+        assert!(!progress.is_empty());
+        assert!(progress.iter().all(|e| e["id"] == "1"));
+        // Never goes back, and the final one is reported before done.
+        let sizes: Vec<f64> = progress
+            .iter()
+            .map(|e| e["finished_size"].as_str().unwrap().parse().unwrap())
+            .collect();
+        assert!(sizes.windows(2).all(|w| w[0] <= w[1]), "{:?}", sizes);
+        assert_eq!(*sizes.last().unwrap(), data.len() as f64);
+        assert!(events.iter().any(|e| e["name"] == "transfer_summary"));
+        assert_eq!(events.last().unwrap()["id"], "1");
+        assert_eq!(std::fs::read(to.join("a.bin")).unwrap(), data);
+        close_loopback_session(&session_id);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_loopback_multi_stream_transfer() {
+        check_multi_stream_transfer(false).await;
+    }
+
+    #[tokio::test]
+    async fn test_loopback_multi_stream_out_of_order() {
+        check_multi_stream_transfer(true).await;
+    }
+
+    // The file of 3 ranges sent by the host, out of order by the scripted host.
+    static LOCAL_OPTION_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    // Sets a local option, the old value is restored on drop, also if the test panics.
+    // The tests setting the options run one by one.
+    struct LocalOptionGuard {
+        key: String,
+        old: String,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    impl LocalOptionGuard {
+        fn set(key: &str, value: &str) -> Self {
+            let lock = LOCAL_OPTION_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let old = LocalConfig::get_option(key);
+            LocalConfig::set_option(key.to_owned(), value.to_owned());
+            Self {
+                key: key.to_owned(),
+                old,
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for LocalOptionGuard {
+        fn drop(&mut self) {
+            LocalConfig::set_option(self.key.clone(), std::mem::take(&mut self.old));
         }
-        Ok(())
     }
 
-    fn handle_query_onlines(onlines: Vec<String>, offlines: Vec<String>) {
-        let data = HashMap::from([
-            ("name", "callback_query_onlines".to_owned()),
-            ("onlines", onlines.join(",")),
-            ("offlines", offlines.join(",")),
-        ]);
-        let _res = super::push_global_event(
-            super::APP_TYPE_MAIN,
-            serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+    async fn check_multi_stream_transfer(scripted: bool) {
+        use hbb_common::fs::multi_stream::OPTION_FILE_TRANSFER_STREAMS;
+        let dir = std::env::temp_dir().join(format!("loopback-{}", SessionID::new_v4()));
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        std::fs::create_dir_all(&from).unwrap();
+        // 3 ranges, the last block partial.
+        let data: Vec<u8> = (0..12 * 1024 * 1024 + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(from.join("a.bin"), &data).unwrap();
+        let _streams = LocalOptionGuard::set(OPTION_FILE_TRANSFER_STREAMS, "3");
+
+        let (session_id, collector, host) = if scripted {
+            let (session_id, collector, mut host) = start_scripted_session(true);
+            host.accept(PeerInfo {
+                platform_additions: r#"{"capabilities": {"multi_stream_transfer": true}}"#
+                    .to_owned(),
+                ..loopback_peer_info()
+            })
+            .await
+            .unwrap();
+            (session_id, collector, Some(host))
+        } else {
+            let (session_id, collector) = start_loopback_session(true);
+            (session_id, collector, None)
+        };
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+        session.send_files(
+            1,
+            from.to_string_lossy().to_string(),
+            to.to_string_lossy().to_string(),
+            0,
+            false,
+            true,
         );
+        if let Some(mut host) = host {
+            // The blocks arrive in the reverse order, the ranges complete from the last.
+            host.serve_file_job_ordered(Duration::from_millis(1), false)
+                .await
+                .unwrap();
+        }
+        let events = wait_events(&collector, "job_done").await;
+        let streams = events.iter().find(|e| e["name"] == "job_streams").unwrap();
+        assert_eq!(streams["streams"], "3");
+        let last = events
+            .iter()
+            .filter(|e| e["name"] == "job_progress")
+            .last()
+            .unwrap();
+        assert_eq!(last["finished_size"], data.len().to_string());
+        assert_eq!(std::fs::read(to.join("a.bin")).unwrap(), data);
+        close_loopback_session(&session_id);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    async fn recv_volume(host: &mut crate::client::loopback::LoopbackHost) -> RemoteVolume {
+        host.recv(|msg| match msg.union {
+            Some(message::Union::Misc(misc)) => match misc.union {
+                Some(misc::Union::RemoteVolume(v)) => Some(v),
+                _ => None,
+            },
+            _ => None,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn volume_msg(volume: RemoteVolume) -> Message {
+        let mut misc = Misc::new();
+        misc.set_remote_volume(volume);
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_loopback_remote_volume() {
+        let (session_id, collector, mut host) = start_scripted_session(false);
+        host.accept(PeerInfo {
+            platform_additions: r#"{"capabilities": {"remote_volume": true}}"#.to_owned(),
+            ..loopback_peer_info()
+        })
+        .await
+        .unwrap();
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+
+        assert_eq!(session.query_remote_volume(), None);
+        assert!(recv_volume(&mut host).await.query);
+        host.send(&volume_msg(RemoteVolume {
+            percent: 70,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let events = wait_events(&collector, "remote_volume_changed").await;
+        let changed = events.last().unwrap();
+        assert_eq!(changed["state"], "ok");
+        assert_eq!(changed["percent"], "70");
+        assert_eq!(changed["muted"], "false");
+
+        // The mute keeps the volume.
+        session.toggle_remote_mute();
+        let v = recv_volume(&mut host).await;
+        assert_eq!((v.percent, v.muted, v.query), (70, true, false));
+        session.set_remote_volume(140);
+        assert_eq!(recv_volume(&mut host).await.percent, 100);
+
+        // Changed on the host.
+        host.send(&volume_msg(RemoteVolume {
+            percent: 30,
+            muted: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let changed = wait_events(&collector, "remote_volume_changed").await;
+        assert_eq!(changed.last().unwrap()["percent"], "30");
+        assert_eq!(session.query_remote_volume(), Some((30, true)));
+        recv_volume(&mut host).await;
+
+        host.send(&volume_msg(RemoteVolume {
+            denied: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        let changed = wait_events(&collector, "remote_volume_changed").await;
+        assert_eq!(changed.last().unwrap()["state"], "denied");
+        close_loopback_session(&session_id);
+    }
+
+    // The volume is set to the current one, not to change the volume of the host. Skipped if the
+    // host has no desktop to log in to.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    #[tokio::test]
+    async fn test_loopback_host_remote_volume() {
+        if crate::server::display_service::try_get_displays().map_or(true, |d| d.is_empty()) {
+            return;
+        }
+        let (session_id, collector) = start_loopback_session(false);
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+        session.query_remote_volume();
+        let events = wait_events(&collector, "remote_volume_changed").await;
+        let changed = events.last().unwrap();
+        if !crate::server::remote_volume::is_supported() {
+            assert_eq!(changed["state"], "unsupported");
+            close_loopback_session(&session_id);
+            return;
+        }
+        assert_eq!(changed["state"], "ok");
+        let percent: u32 = changed["percent"].as_str().unwrap().parse().unwrap();
+        let muted = changed["muted"].clone();
+        session.set_remote_volume(percent);
+        let events = wait_events(&collector, "remote_volume_changed").await;
+        let changed = events.last().unwrap();
+        assert_eq!(changed["state"], "ok");
+        assert_eq!(changed["percent"], percent.to_string());
+        assert_eq!(changed["muted"], muted);
+        close_loopback_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn test_loopback_remote_volume_unsupported() {
+        let (session_id, collector, mut host) = start_scripted_session(false);
+        host.accept(loopback_peer_info()).await.unwrap();
+        wait_events(&collector, "peer_info").await;
+        let session = sessions::get_session_by_session_id(&session_id).unwrap();
+        // Not sent to the host which doesn't report the capability.
+        session.set_remote_volume(50);
+        let changed = wait_events(&collector, "remote_volume_changed").await;
+        assert_eq!(changed.last().unwrap()["state"], "unsupported");
+        close_loopback_session(&session_id);
     }
 }
@@ -0,0 +1,856 @@
+//! The task bus of the flutter async runner.
+//!
+//! Jobs are submitted with [`submit`], which returns the job id and a [`CancelToken`].
+//! The results are pushed by `push_global_event` with the job id. Jobs submitted before
+//! the runner starts are queued.
+
+use hbb_common::{
+    config::{LocalConfig, PeerConfig},
+    log,
+    tokio::{
+        self, select,
+        sync::{
+            mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+            Notify,
+        },
+        time::{sleep_until, Instant as TokioInstant},
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// The requests arriving within the window are merged into one query.
+const QUERY_ONLINES_DEBOUNCE: Duration = Duration::from_millis(200);
+// The max number of ids in one rendezvous query.
+const QUERY_ONLINES_CHUNK_SIZE: usize = 64;
+// The online states are reused within the ttl without querying again.
+const ONLINE_STATE_TTL: Duration = Duration::from_secs(5);
+// The refresh interval of the subscribed ids, can be set by the option "online-refresh-interval"
+// in seconds.
+const DEFAULT_ONLINE_REFRESH_INTERVAL: u64 = 30;
+const MIN_ONLINE_REFRESH_INTERVAL: u64 = 5;
+const MAX_ONLINE_REFRESH_BACKOFF: Duration = Duration::from_secs(300);
+// The woken peers take a while to boot and register.
+#[cfg(not(target_os = "ios"))]
+const WATCH_ONLINE_INTERVAL: Duration = Duration::from_secs(5);
+#[cfg(not(target_os = "ios"))]
+const WATCH_ONLINE_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub type JobId = u64;
+
+pub enum OnlineRequest {
+    Query(Vec<String>),
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+pub enum Job {
+    Onlines(OnlineRequest),
+    /// Reload the peer configs of the ids, pushed by the "callback_refresh_peers" event.
+    RefreshPeers(Vec<String>),
+    /// Open the sessions of the last run one by one, see `session_restore`.
+    RestoreSessions,
+    /// Measure the latencies of the relay servers, see `relay_select`.
+    ProbeRelays {
+        relays: Vec<String>,
+        force: bool,
+    },
+    /// Poll the online state of the peer woken by `wol_relay` till online or the timeout.
+    #[cfg(not(target_os = "ios"))]
+    WatchOnline(String),
+}
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CancelToken(Arc<CancelState>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag, so a `cancel()` in between is not missed.
+            let notified = self.0.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Run the future until it completes or the token is cancelled.
+    pub async fn run<T>(&self, fut: impl Future<Output = T>) -> Option<T> {
+        select! {
+            _ = self.cancelled() => None,
+            res = fut => Some(res),
+        }
+    }
+}
+
+struct JobEntry {
+    id: JobId,
+    job: Job,
+    token: CancelToken,
+}
+
+struct Bus {
+    tx: UnboundedSender<JobEntry>,
+    // Taken by the running runner.
+    rx: Option<UnboundedReceiver<JobEntry>>,
+    // The jobs not finished.
+    jobs: HashMap<JobId, CancelToken>,
+    next_id: JobId,
+    // Cancelled when the runner is stopped.
+    runner: CancelToken,
+}
+
+impl Bus {
+    fn new() -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            tx,
+            rx: Some(rx),
+            jobs: Default::default(),
+            next_id: 0,
+            runner: Default::default(),
+        }
+    }
+
+    fn submit(&mut self, job: Job) -> (JobId, CancelToken) {
+        self.next_id += 1;
+        let id = self.next_id;
+        let token = CancelToken::default();
+        self.jobs.insert(id, token.clone());
+        // The receiver is kept in `rx` until the runner starts, so the job is queued.
+        self.tx
+            .send(JobEntry {
+                id,
+                job,
+                token: token.clone(),
+            })
+            .ok();
+        (id, token)
+    }
+
+    fn cancel(&self, id: JobId) -> bool {
+        match self.jobs.get(&id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish(&mut self, id: JobId) {
+        self.jobs.remove(&id);
+    }
+
+    /// Cancel all the jobs, and replace the channel so that the old runner exits.
+    fn stop(&mut self) {
+        self.runner.cancel();
+        for token in self.jobs.values() {
+            token.cancel();
+        }
+        self.jobs.clear();
+        let (tx, rx) = unbounded_channel();
+        self.tx = tx;
+        self.rx = Some(rx);
+        self.runner = Default::default();
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref BUS: Mutex<Bus> = Mutex::new(Bus::new());
+}
+
+#[inline]
+pub fn start_flutter_async_runner() {
+    std::thread::spawn(start_flutter_async_runner_);
+}
+
+#[allow(dead_code)]
+pub fn stop_flutter_async_runner() {
+    BUS.lock().unwrap().stop();
+}
+
+/// Submit a job to the runner, it is queued if the runner is not started.
+pub fn submit(job: Job) -> (JobId, CancelToken) {
+    BUS.lock().unwrap().submit(job)
+}
+
+/// Returns false if the job is not found, e.g. it is finished.
+pub fn cancel(id: JobId) -> bool {
+    BUS.lock().unwrap().cancel(id)
+}
+
+fn finish(id: JobId) {
+    BUS.lock().unwrap().finish(id);
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn start_flutter_async_runner_() {
+    let (mut rx, runner) = {
+        let mut bus = BUS.lock().unwrap();
+        match bus.rx.take() {
+            Some(rx) => (rx, bus.runner.clone()),
+            None => {
+                log::warn!("The flutter async runner is already running");
+                return;
+            }
+        }
+    };
+
+    let mut online_states = Onlines::default();
+    // The one-shot online queries to be answered by the next flush.
+    let mut online_jobs: Vec<(JobId, CancelToken)> = Vec::new();
+    let mut deadline: Option<TokioInstant> = None;
+    let mut next_refresh: Option<TokioInstant> = None;
+    loop {
+        select! {
+            _ = runner.cancelled() => {
+                break;
+            }
+            entry = rx.recv() => {
+                let Some(JobEntry { id, job, token }) = entry else {
+                    break;
+                };
+                if token.is_cancelled() {
+                    finish(id);
+                    handle_job_cancelled(id);
+                    continue;
+                }
+                match job {
+                    Job::Onlines(req) => {
+                        if let OnlineRequest::Query(_) = &req {
+                            online_jobs.push((id, token));
+                        } else {
+                            finish(id);
+                        }
+                        if online_states.on_request(req) && deadline.is_none() {
+                            deadline = Some(TokioInstant::now() + QUERY_ONLINES_DEBOUNCE);
+                        }
+                        if online_states.interest.is_empty() {
+                            // Pause the refresh.
+                            next_refresh = None;
+                        } else if next_refresh.is_none() {
+                            next_refresh = Some(TokioInstant::now() + online_states.refresh_delay());
+                        }
+                    }
+                    Job::RestoreSessions => {
+                        tokio::spawn(async move {
+                            let res = token.run(restore_sessions(id)).await;
+                            finish(id);
+                            if res.is_none() {
+                                handle_job_cancelled(id);
+                            }
+                        });
+                    }
+                    Job::ProbeRelays { relays, force } => {
+                        tokio::spawn(async move {
+                            let res = token.run(probe_relays(id, relays, force)).await;
+                            finish(id);
+                            if res.is_none() {
+                                handle_job_cancelled(id);
+                            }
+                        });
+                    }
+                    #[cfg(not(target_os = "ios"))]
+                    Job::WatchOnline(peer_id) => {
+                        tokio::spawn(async move {
+                            let res = token.run(watch_online(peer_id.clone())).await;
+                            finish(id);
+                            match res {
+                                Some(online) => super::wol_relay::on_watched(&peer_id, online),
+                                None => handle_job_cancelled(id),
+                            }
+                        });
+                    }
+                    Job::RefreshPeers(ids) => {
+                        tokio::spawn(async move {
+                            let res = token.run(refresh_peers(ids)).await;
+                            finish(id);
+                            match res {
+                                Some(peers) => handle_refresh_peers(id, peers),
+                                None => handle_job_cancelled(id),
+                            }
+                        });
+                    }
+                }
+            }
+            _ = sleep_until(deadline.unwrap_or_else(TokioInstant::now)), if deadline.is_some() => {
+                deadline = None;
+                let Some(res) = runner.run(online_states.queries.flush(&query_online_states)).await else {
+                    break;
+                };
+                let mut job_ids = vec![];
+                for (id, token) in online_jobs.drain(..) {
+                    finish(id);
+                    if token.is_cancelled() {
+                        handle_job_cancelled(id);
+                    } else {
+                        job_ids.push(id);
+                    }
+                }
+                if let Some((onlines, offlines, stale)) = online_states.on_result(res) {
+                    handle_query_onlines(onlines, offlines, stale, job_ids);
+                }
+                if !online_states.interest.is_empty() {
+                    next_refresh = Some(TokioInstant::now() + online_states.refresh_delay());
+                }
+            }
+            _ = sleep_until(next_refresh.unwrap_or_else(TokioInstant::now)), if next_refresh.is_some() => {
+                next_refresh = None;
+                online_states.refresh();
+                if deadline.is_none() {
+                    deadline = Some(TokioInstant::now());
+                }
+            }
+        }
+    }
+    for (id, _) in online_jobs.drain(..) {
+        handle_job_cancelled(id);
+    }
+    log::info!("The flutter async runner exits");
+}
+
+/// Query the online states once, the result is pushed by the "callback_query_onlines" event.
+pub fn query_onlines(ids: Vec<String>) -> JobId {
+    submit(Job::Onlines(OnlineRequest::Query(ids))).0
+}
+
+/// Keep refreshing the online states of the ids, only the changed states are pushed
+/// by the "callback_query_onlines" event.
+pub fn subscribe_onlines(ids: Vec<String>) {
+    submit(Job::Onlines(OnlineRequest::Subscribe(ids)));
+}
+
+pub fn unsubscribe_onlines(ids: Vec<String>) {
+    submit(Job::Onlines(OnlineRequest::Unsubscribe(ids)));
+}
+
+async fn refresh_peers(ids: Vec<String>) -> String {
+    tokio::task::spawn_blocking(move || {
+        let peers: Vec<HashMap<&str, String>> = PeerConfig::peers(Some(ids))
+            .into_iter()
+            .map(|(id, _, p)| crate::ui_interface::peer_to_map(id, p))
+            .collect();
+        serde_json::ser::to_string(&peers).unwrap_or("".to_owned())
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Restore the sessions of the last run, the progress is pushed by the "restore_sessions_progress"
+/// event, whose state is "offline", "open", "connected", "timeout" or "done".
+/// The ui opens the session on "open", the next one is opened after it's connected or timed out.
+pub fn restore_sessions_job() -> JobId {
+    submit(Job::RestoreSessions).0
+}
+
+async fn restore_sessions(id: JobId) {
+    let entries = super::session_restore::take_restorable();
+    let ids = entries.iter().map(|e| e.peer_id.clone()).collect();
+    // The peers are tried if the online states are unknown.
+    let offlines = query_online_states(ids)
+        .await
+        .map(|(_, offlines)| offlines)
+        .unwrap_or_default();
+    let total = entries.len();
+    for (i, entry) in entries.into_iter().enumerate() {
+        let progress = |state: &str| {
+            push_event(HashMap::from([
+                ("name", "restore_sessions_progress".to_owned()),
+                ("job_id", id.to_string()),
+                ("peer_id", entry.peer_id.clone()),
+                ("force_relay", entry.force_relay.to_string()),
+                ("view_only", entry.view_only.to_string()),
+                ("index", i.to_string()),
+                ("total", total.to_string()),
+                ("state", state.to_owned()),
+            ]));
+        };
+        if offlines.contains(&entry.peer_id) {
+            progress("offline");
+            continue;
+        }
+        let start = Instant::now();
+        progress("open");
+        let state = loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if super::session_restore::is_established_since(&entry.peer_id, start) {
+                break "connected";
+            }
+            if start.elapsed() > super::session_restore::RESTORE_TIMEOUT {
+                break "timeout";
+            }
+        };
+        progress(state);
+    }
+    push_event(HashMap::from([
+        ("name", "restore_sessions_progress".to_owned()),
+        ("job_id", id.to_string()),
+        ("total", total.to_string()),
+        ("state", "done".to_owned()),
+    ]));
+}
+
+/// Probe the relay servers, all the candidates if empty. Each result is pushed by the
+/// "relay_probe_result" event, whose latency is empty if unreachable, then "done" is pushed.
+pub fn probe_relays_job(relays: Vec<String>, force: bool) -> JobId {
+    submit(Job::ProbeRelays { relays, force }).0
+}
+
+async fn probe_relays(id: JobId, relays: Vec<String>, force: bool) {
+    let relays = if relays.is_empty() {
+        crate::client::relay_select::candidates()
+    } else {
+        relays
+    };
+    crate::client::relay_select::probe_relays(relays, force, |relay, latency, cached| {
+        push_event(HashMap::from([
+            ("name", "relay_probe_result".to_owned()),
+            ("job_id", id.to_string()),
+            ("relay", relay.to_owned()),
+            (
+                "latency",
+                latency.map(|l| l.to_string()).unwrap_or_default(),
+            ),
+            ("cached", cached.to_string()),
+        ]));
+    })
+    .await;
+    push_event(HashMap::from([
+        ("name", "relay_probe_result".to_owned()),
+        ("job_id", id.to_string()),
+        ("done", "true".to_owned()),
+    ]));
+}
+
+#[cfg(not(target_os = "ios"))]
+pub fn watch_online_job(peer_id: String) -> JobId {
+    submit(Job::WatchOnline(peer_id)).0
+}
+
+#[cfg(not(target_os = "ios"))]
+async fn watch_online(peer_id: String) -> bool {
+    let start = Instant::now();
+    loop {
+        tokio::time::sleep(WATCH_ONLINE_INTERVAL).await;
+        if let Some((onlines, _)) = query_online_states(vec![peer_id.clone()]).await {
+            if onlines.contains(&peer_id) {
+                return true;
+            }
+        }
+        if start.elapsed() > WATCH_ONLINE_TIMEOUT {
+            return false;
+        }
+    }
+}
+
+async fn query_online_states(_ids: Vec<String>) -> Option<(Vec<String>, Vec<String>)> {
+    let mut res = None;
+    #[cfg(not(any(target_os = "ios")))]
+    crate::rendezvous_mediator::query_online_states(_ids, |onlines, offlines| {
+        res = Some((onlines, offlines));
+    })
+    .await;
+    res
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct QueryResult {
+    onlines: Vec<String>,
+    offlines: Vec<String>,
+    // The ids failed to query.
+    failed: Vec<String>,
+}
+
+/// The one-shot queries and the subscriptions of the online states.
+#[derive(Default)]
+struct Onlines {
+    queries: OnlineQueries,
+    // The ids of the one-shot queries, their states are always pushed.
+    oneshot: HashSet<String>,
+    // The subscribed ids and their last pushed states.
+    interest: HashSet<String>,
+    states: HashMap<String, bool>,
+    // The number of the continuous failed queries.
+    failures: u32,
+    stale: bool,
+}
+
+impl Onlines {
+    /// Returns true if a query is needed.
+    fn on_request(&mut self, req: OnlineRequest) -> bool {
+        match req {
+            OnlineRequest::Query(ids) => {
+                self.oneshot.extend(ids.iter().cloned());
+                self.queries.add(ids);
+                true
+            }
+            OnlineRequest::Subscribe(ids) => {
+                let ids: Vec<String> = ids
+                    .into_iter()
+                    .filter(|id| self.interest.insert(id.clone()))
+                    .collect();
+                let changed = !ids.is_empty();
+                self.queries.add(ids);
+                changed
+            }
+            OnlineRequest::Unsubscribe(ids) => {
+                for id in ids.iter() {
+                    self.interest.remove(id);
+                    self.states.remove(id);
+                }
+                false
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        self.queries.add(self.interest.iter().cloned().collect());
+    }
+
+    fn refresh_delay(&self) -> Duration {
+        let interval = LocalConfig::get_option("online-refresh-interval")
+            .parse()
+            .unwrap_or(DEFAULT_ONLINE_REFRESH_INTERVAL)
+            .max(MIN_ONLINE_REFRESH_INTERVAL);
+        Self::backoff(Duration::from_secs(interval), self.failures)
+    }
+
+    fn backoff(interval: Duration, failures: u32) -> Duration {
+        if failures == 0 {
+            return interval;
+        }
+        (interval * 2u32.pow(failures.min(8))).min(MAX_ONLINE_REFRESH_BACKOFF)
+    }
+
+    /// Returns the onlines, offlines and the stale flag to push, `None` if nothing to push.
+    ///
+    /// The states of the one-shot queries are always pushed, the subscribed ids are pushed
+    /// only if their states are changed, or all of them once the stale states are recovered.
+    fn on_result(&mut self, res: QueryResult) -> Option<(Vec<String>, Vec<String>, bool)> {
+        let oneshot = std::mem::take(&mut self.oneshot);
+        let mut became_stale = false;
+        if !res.failed.is_empty() {
+            self.failures += 1;
+            if !self.stale && res.failed.iter().any(|id| self.interest.contains(id)) {
+                self.stale = true;
+                became_stale = true;
+            }
+        } else {
+            self.failures = 0;
+        }
+        let recovered = self.stale && res.failed.is_empty();
+        if recovered {
+            self.stale = false;
+        }
+        let mut filter = |ids: Vec<String>, online: bool| -> Vec<String> {
+            ids.into_iter()
+                .filter(|id| {
+                    let mut push = oneshot.contains(id);
+                    if self.interest.contains(id) {
+                        let changed = self.states.insert(id.clone(), online) != Some(online);
+                        push |= changed || recovered;
+                    }
+                    push
+                })
+                .collect()
+        };
+        let onlines = filter(res.onlines, true);
+        let offlines = filter(res.offlines, false);
+        if onlines.is_empty() && offlines.is_empty() && !recovered && !became_stale {
+            None
+        } else {
+            Some((onlines, offlines, self.stale))
+        }
+    }
+}
+
+/// The online queries merged by the debounce window.
+#[derive(Default)]
+struct OnlineQueries {
+    // The ids to query, in the order of the requests.
+    pending: Vec<String>,
+    pending_set: HashSet<String>,
+    // id -> (online, query time)
+    cache: HashMap<String, (bool, Instant)>,
+}
+
+impl OnlineQueries {
+    fn add(&mut self, ids: Vec<String>) {
+        for id in ids {
+            if self.pending_set.insert(id.clone()) {
+                self.pending.push(id);
+            }
+        }
+    }
+
+    /// Take the pending ids, returns the cached onlines and offlines, and the chunks to query.
+    fn take(&mut self) -> (Vec<String>, Vec<String>, Vec<Vec<String>>) {
+        self.pending_set.clear();
+        let mut onlines = Vec::new();
+        let mut offlines = Vec::new();
+        let mut ids = Vec::new();
+        for id in self.pending.drain(..) {
+            match self.cache.get(&id) {
+                Some((online, t)) if t.elapsed() < ONLINE_STATE_TTL => {
+                    if *online {
+                        onlines.push(id);
+                    } else {
+                        offlines.push(id);
+                    }
+                }
+                _ => ids.push(id),
+            }
+        }
+        let chunks = ids
+            .chunks(QUERY_ONLINES_CHUNK_SIZE)
+            .map(|c| c.to_vec())
+            .collect();
+        (onlines, offlines, chunks)
+    }
+
+    fn update_cache(&mut self, onlines: &[String], offlines: &[String]) {
+        self.cache.retain(|_, (_, t)| t.elapsed() < ONLINE_STATE_TTL);
+        let now = Instant::now();
+        for id in onlines {
+            self.cache.insert(id.clone(), (true, now));
+        }
+        for id in offlines {
+            self.cache.insert(id.clone(), (false, now));
+        }
+    }
+
+    /// Query the pending ids chunk by chunk, and aggregate the results of all the chunks.
+    async fn flush<F, Fut>(&mut self, query: &F) -> QueryResult
+    where
+        F: Fn(Vec<String>) -> Fut,
+        Fut: Future<Output = Option<(Vec<String>, Vec<String>)>>,
+    {
+        let (onlines, offlines, chunks) = self.take();
+        let mut res = QueryResult {
+            onlines,
+            offlines,
+            failed: vec![],
+        };
+        for chunk in chunks {
+            match query(chunk.clone()).await {
+                Some((onlines, offlines)) => {
+                    self.update_cache(&onlines, &offlines);
+                    res.onlines.extend(onlines);
+                    res.offlines.extend(offlines);
+                }
+                None => res.failed.extend(chunk),
+            }
+        }
+        res
+    }
+}
+
+fn push_event(data: HashMap<&str, String>) {
+    let _res = super::push_global_event(
+        super::APP_TYPE_MAIN,
+        serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+    );
+}
+
+fn handle_query_onlines(
+    onlines: Vec<String>,
+    offlines: Vec<String>,
+    stale: bool,
+    job_ids: Vec<JobId>,
+) {
+    let job_ids: Vec<String> = job_ids.iter().map(|id| id.to_string()).collect();
+    push_event(HashMap::from([
+        ("name", "callback_query_onlines".to_owned()),
+        ("onlines", onlines.join(",")),
+        ("offlines", offlines.join(",")),
+        ("stale", stale.to_string()),
+        ("job_ids", job_ids.join(",")),
+    ]));
+}
+
+fn handle_refresh_peers(id: JobId, peers: String) {
+    push_event(HashMap::from([
+        ("name", "callback_refresh_peers".to_owned()),
+        ("job_id", id.to_string()),
+        ("peers", peers),
+    ]));
+}
+
+fn handle_job_cancelled(id: JobId) {
+    push_event(HashMap::from([
+        ("name", "async_job_cancelled".to_owned()),
+        ("job_id", id.to_string()),
+    ]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn result(onlines: &[&str], offlines: &[&str], failed: &[&str]) -> QueryResult {
+        QueryResult {
+            onlines: ids(onlines),
+            offlines: ids(offlines),
+            failed: ids(failed),
+        }
+    }
+
+    // The ids starting with "on" are online.
+    fn mock_query(
+        calls: Arc<Mutex<Vec<Vec<String>>>>,
+    ) -> impl Fn(Vec<String>) -> std::future::Ready<Option<(Vec<String>, Vec<String>)>> {
+        move |ids| {
+            calls.lock().unwrap().push(ids.clone());
+            let (onlines, offlines): (Vec<String>, Vec<String>) =
+                ids.into_iter().partition(|id| id.starts_with("on"));
+            std::future::ready(Some((onlines, offlines)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_onlines_coalesce() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let query = mock_query(calls.clone());
+        let mut queries = OnlineQueries::default();
+        queries.add(ids(&["on1", "off1"]));
+        queries.add(ids(&["off1", "on2"]));
+        let res = queries.flush(&query).await;
+        assert_eq!(res, result(&["on1", "on2"], &["off1"], &[]));
+        assert_eq!(*calls.lock().unwrap(), vec![ids(&["on1", "off1", "on2"])]);
+
+        // Answered by the cache.
+        queries.add(ids(&["on1", "off1"]));
+        let res = queries.flush(&query).await;
+        assert_eq!(res, result(&["on1"], &["off1"], &[]));
+        assert_eq!(calls.lock().unwrap().len(), 1);
+
+        assert_eq!(queries.flush(&query).await, QueryResult::default());
+    }
+
+    #[tokio::test]
+    async fn test_query_onlines_chunk() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let query = mock_query(calls.clone());
+        let mut queries = OnlineQueries::default();
+        let n = QUERY_ONLINES_CHUNK_SIZE * 2 + 1;
+        queries.add((0..n).map(|i| format!("on{}", i)).collect());
+        let res = queries.flush(&query).await;
+        assert_eq!(res.onlines.len(), n);
+        assert!(res.offlines.is_empty() && res.failed.is_empty());
+        let sizes: Vec<usize> = calls.lock().unwrap().iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![QUERY_ONLINES_CHUNK_SIZE, QUERY_ONLINES_CHUNK_SIZE, 1]);
+    }
+
+    #[test]
+    fn test_online_subscription() {
+        let mut onlines = Onlines::default();
+        assert!(onlines.on_request(OnlineRequest::Subscribe(ids(&["a", "b"]))));
+        assert!(!onlines.on_request(OnlineRequest::Subscribe(ids(&["a"]))));
+        let res = onlines.on_result(result(&["a"], &["b"], &[]));
+        assert_eq!(res, Some((ids(&["a"]), ids(&["b"]), false)));
+
+        // Only the changes are pushed.
+        let res = onlines.on_result(result(&["a", "b"], &[], &[]));
+        assert_eq!(res, Some((ids(&["b"]), ids(&[]), false)));
+        assert_eq!(onlines.on_result(result(&["a", "b"], &[], &[])), None);
+
+        // The one-shot queries are always pushed.
+        assert!(onlines.on_request(OnlineRequest::Query(ids(&["a", "c"]))));
+        let res = onlines.on_result(result(&["a", "b"], &["c"], &[]));
+        assert_eq!(res, Some((ids(&["a"]), ids(&["c"]), false)));
+
+        // Stale once on failures, then all the states on recovery.
+        let res = onlines.on_result(result(&[], &[], &["a", "b"]));
+        assert_eq!(res, Some((ids(&[]), ids(&[]), true)));
+        assert_eq!(onlines.on_result(result(&[], &[], &["a", "b"])), None);
+        assert_eq!(onlines.failures, 2);
+        let res = onlines.on_result(result(&["a", "b"], &[], &[]));
+        assert_eq!(res, Some((ids(&["a", "b"]), ids(&[]), false)));
+        assert_eq!(onlines.failures, 0);
+
+        onlines.on_request(OnlineRequest::Unsubscribe(ids(&["a", "b"])));
+        assert!(onlines.interest.is_empty() && onlines.states.is_empty());
+    }
+
+    #[test]
+    fn test_online_refresh_backoff() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(Onlines::backoff(interval, 0), interval);
+        assert_eq!(Onlines::backoff(interval, 2), Duration::from_secs(120));
+        assert_eq!(Onlines::backoff(interval, 100), MAX_ONLINE_REFRESH_BACKOFF);
+    }
+
+    #[test]
+    fn test_bus_queue_before_start() {
+        let mut bus = Bus::new();
+        let (id1, _) = bus.submit(Job::RefreshPeers(ids(&["a"])));
+        let (id2, _) = bus.submit(Job::Onlines(OnlineRequest::Query(ids(&["b"]))));
+        assert_eq!((id1, id2), (1, 2));
+        let mut rx = bus.rx.take().unwrap();
+        assert_eq!(rx.try_recv().unwrap().id, id1);
+        assert_eq!(rx.try_recv().unwrap().id, id2);
+        assert!(rx.try_recv().is_err());
+
+        // Stop cancels the jobs, and the old receiver is disconnected.
+        let (id3, token) = bus.submit(Job::RefreshPeers(ids(&["c"])));
+        let runner = bus.runner.clone();
+        bus.stop();
+        assert!(token.is_cancelled() && runner.is_cancelled());
+        assert!(!bus.cancel(id3));
+        assert_eq!(rx.try_recv().unwrap().id, id3);
+        assert!(rx.try_recv().is_err());
+        assert!(bus.rx.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_token() {
+        let token = CancelToken::default();
+        assert_eq!(token.run(async { 1 }).await, Some(1));
+
+        let cloned = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            cloned.cancel();
+        });
+        let res = token.run(std::future::pending::<()>()).await;
+        assert_eq!(res, None);
+        assert!(token.is_cancelled());
+        assert_eq!(token.run(async { 1 }).await, None);
+
+        let mut bus = Bus::new();
+        let (id, token) = bus.submit(Job::RefreshPeers(ids(&["a"])));
+        assert!(bus.cancel(id));
+        assert!(token.is_cancelled());
+        bus.finish(id);
+        assert!(!bus.cancel(id));
+    }
+}
@@ -0,0 +1,214 @@
+//! Reduce the displays captured on the sustained congestion, e.g. capturing all the displays over
+//! a weak link makes every display unusable.
+//!
+//! The congestion is sampled every second by the io loop. After [`CONGESTED_SECS`] of congestion
+//! while multiple displays are captured, only the displays needed are kept: the active one, and
+//! the ones shown alone by the other ui sessions. The full set is restored once the link has been
+//! clear for a while, which is doubled on each restoring to avoid flapping.
+//!
+//! The capture set changed by the user overrides the reduction, and the automatic reduction is
+//! suspended for [`OVERRIDE_HOLD`]. The set changed by the bookkeeping of the ui sessions, see
+//! `check_remove_unused_displays`, also drops the reduction, as the displays newly shown alone
+//! must be captured. It's reduced again if still congested.
+
+use std::time::{Duration, Instant};
+
+/// The seconds of the sustained congestion before reduced.
+pub const CONGESTED_SECS: u32 = 5;
+/// The seconds of the clear link before restored, for the first reduction.
+pub const RESTORE_SECS: u32 = 20;
+const MAX_RESTORE_SECS: u32 = 300;
+/// The automatic reduction is suspended after the user overrides it.
+pub const OVERRIDE_HOLD: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReduceAction {
+    /// Capture the displays kept only.
+    Reduce(Vec<i32>),
+    /// Capture the full set again.
+    Restore(Vec<i32>),
+}
+
+#[derive(Debug)]
+pub struct CaptureReduction {
+    // The displays captured, as requested by the ui sessions.
+    captured: Vec<i32>,
+    // The displays kept if reduced.
+    reduced: Option<Vec<i32>>,
+    congested_secs: u32,
+    clear_secs: u32,
+    restore_secs: u32,
+    hold_until: Option<Instant>,
+    // The display which received the most recent input.
+    input_display: Option<i32>,
+}
+
+impl Default for CaptureReduction {
+    fn default() -> Self {
+        Self {
+            captured: vec![],
+            reduced: None,
+            congested_secs: 0,
+            clear_secs: 0,
+            restore_secs: RESTORE_SECS,
+            hold_until: None,
+            input_display: None,
+        }
+    }
+}
+
+impl CaptureReduction {
+    #[inline]
+    pub fn is_reduced(&self) -> bool {
+        self.reduced.is_some()
+    }
+
+    #[inline]
+    pub fn input_display(&self) -> Option<i32> {
+        self.input_display
+    }
+
+    #[inline]
+    pub fn set_input_display(&mut self, display: i32) {
+        self.input_display = Some(display);
+    }
+
+    /// The capture set is changed by the bookkeeping. Returns true if the reduction is dropped,
+    /// the new set is captured in full until congested again.
+    pub fn on_capture_set(&mut self, displays: &[i32]) -> bool {
+        self.captured = displays.to_vec();
+        self.congested_secs = 0;
+        self.clear_secs = 0;
+        self.reduced.take().is_some()
+    }
+
+    /// The user sets the displays to capture. Returns true if the reduction is overridden.
+    pub fn on_user_capture(&mut self, displays: &[i32], now: Instant) -> bool {
+        let overridden = self.on_capture_set(displays);
+        if overridden {
+            self.hold_until = Some(now + OVERRIDE_HOLD);
+        }
+        overridden
+    }
+
+    /// Sampled every second. `keep` is the displays which must be captured.
+    pub fn on_sample(
+        &mut self,
+        congested: bool,
+        keep: &[i32],
+        now: Instant,
+    ) -> Option<ReduceAction> {
+        if self.reduced.is_some() {
+            if congested {
+                self.clear_secs = 0;
+                return None;
+            }
+            self.clear_secs += 1;
+            if self.clear_secs < self.restore_secs {
+                return None;
+            }
+            self.reduced = None;
+            self.clear_secs = 0;
+            // Stay reduced longer if reduced again.
+            self.restore_secs = (self.restore_secs * 2).min(MAX_RESTORE_SECS);
+            return Some(ReduceAction::Restore(self.captured.clone()));
+        }
+        if self.captured.len() <= 1 || self.hold_until.map_or(false, |t| now < t) {
+            self.congested_secs = 0;
+            return None;
+        }
+        if !congested {
+            self.congested_secs = 0;
+            return None;
+        }
+        self.congested_secs += 1;
+        if self.congested_secs < CONGESTED_SECS {
+            return None;
+        }
+        self.congested_secs = 0;
+        let kept: Vec<i32> = self
+            .captured
+            .iter()
+            .filter(|d| keep.contains(d))
+            .cloned()
+            .collect();
+        if kept.is_empty() || kept.len() == self.captured.len() {
+            return None;
+        }
+        self.reduced = Some(kept.clone());
+        Some(ReduceAction::Reduce(kept))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(
+        r: &mut CaptureReduction,
+        congested: bool,
+        secs: u32,
+        keep: &[i32],
+        now: Instant,
+    ) -> Option<ReduceAction> {
+        (0..secs)
+            .filter_map(|_| r.on_sample(congested, keep, now))
+            .last()
+    }
+
+    #[test]
+    fn test_reduce_and_restore() {
+        let now = Instant::now();
+        let mut r = CaptureReduction::default();
+        r.on_capture_set(&[0, 1, 2]);
+        assert_eq!(run(&mut r, true, CONGESTED_SECS - 1, &[1], now), None);
+        // The congestion is not sustained.
+        assert_eq!(run(&mut r, false, 1, &[1], now), None);
+        assert_eq!(run(&mut r, true, CONGESTED_SECS - 1, &[1], now), None);
+        // The display shown alone by another ui session is kept too.
+        assert_eq!(
+            run(&mut r, true, 1, &[1, 2, 5], now),
+            Some(ReduceAction::Reduce(vec![1, 2]))
+        );
+        assert!(r.is_reduced());
+        assert_eq!(run(&mut r, false, RESTORE_SECS - 1, &[1], now), None);
+        assert_eq!(run(&mut r, true, 1, &[1], now), None);
+        assert_eq!(
+            run(&mut r, false, RESTORE_SECS, &[1], now),
+            Some(ReduceAction::Restore(vec![0, 1, 2]))
+        );
+        // Reduced again, restored later.
+        assert!(run(&mut r, true, CONGESTED_SECS, &[1], now).is_some());
+        assert_eq!(run(&mut r, false, RESTORE_SECS, &[1], now), None);
+        assert!(run(&mut r, false, RESTORE_SECS, &[1], now).is_some());
+
+        // Nothing to drop.
+        assert_eq!(run(&mut r, true, CONGESTED_SECS, &[0, 1, 2], now), None);
+        r.on_capture_set(&[1]);
+        assert_eq!(run(&mut r, true, CONGESTED_SECS, &[1], now), None);
+    }
+
+    #[test]
+    fn test_reduction_overridden() {
+        let now = Instant::now();
+        let mut r = CaptureReduction::default();
+        r.on_capture_set(&[0, 1]);
+        assert!(run(&mut r, true, CONGESTED_SECS, &[0], now).is_some());
+        // The bookkeeping drops the reduction.
+        assert!(r.on_capture_set(&[0, 1, 2]));
+        assert!(!r.is_reduced());
+        assert_eq!(
+            run(&mut r, true, CONGESTED_SECS, &[0], now),
+            Some(ReduceAction::Reduce(vec![0]))
+        );
+        // The user overrides, not reduced until the hold expires.
+        assert!(r.on_user_capture(&[0, 1, 2], now));
+        assert!(!r.on_user_capture(&[0, 1, 2], now));
+        assert_eq!(run(&mut r, true, CONGESTED_SECS * 2, &[0], now), None);
+        let later = now + OVERRIDE_HOLD;
+        assert_eq!(
+            run(&mut r, true, CONGESTED_SECS, &[0], later),
+            Some(ReduceAction::Reduce(vec![0]))
+        );
+    }
+}
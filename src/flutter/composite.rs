@@ -0,0 +1,324 @@
+//! Merged view of all remote displays in one texture.
+//!
+//! The canvas keeps the relative layout of the remote displays. The whole layout is scaled to fit
+//! the canvas, preserving its aspect ratio, and centered (letterboxing). Each display then gets a
+//! slot in the canvas. A decoded frame whose size doesn't match its slot, e.g. a display with a
+//! different scale factor, is fitted into the slot the same way: scaled preserving its aspect
+//! ratio and centered, the remaining area of the slot is black.
+
+use hbb_common::message_proto::DisplayInfo;
+
+/// The special display index used to register the composite texture.
+pub const COMPOSITE_DISPLAY: usize = usize::MAX;
+
+// Row alignment of the canvas buffer, passed to the texture renderer as the stride.
+const CANVAS_ALIGN: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    #[inline]
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.w && y >= self.y && y < self.y + self.h
+    }
+
+    #[inline]
+    fn clamp(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            x.clamp(self.x, self.x + (self.w - 1).max(0)),
+            y.clamp(self.y, self.y + (self.h - 1).max(0)),
+        )
+    }
+
+    #[inline]
+    fn distance2(&self, x: i32, y: i32) -> i64 {
+        let (cx, cy) = self.clamp(x, y);
+        let (dx, dy) = ((cx - x) as i64, (cy - y) as i64);
+        dx * dx + dy * dy
+    }
+}
+
+/// Fit a `src_w` x `src_h` area into `dst`, preserving the aspect ratio and centering it.
+pub fn fit_rect(src_w: i32, src_h: i32, dst: Rect) -> Rect {
+    if src_w <= 0 || src_h <= 0 || dst.w <= 0 || dst.h <= 0 {
+        return Rect {
+            x: dst.x,
+            y: dst.y,
+            w: 0,
+            h: 0,
+        };
+    }
+    // Compare `src_w / src_h` with `dst.w / dst.h` without floating point errors.
+    let (w, h) = if src_w as i64 * dst.h as i64 > dst.w as i64 * src_h as i64 {
+        (dst.w, (dst.w as i64 * src_h as i64 / src_w as i64) as i32)
+    } else {
+        ((dst.h as i64 * src_w as i64 / src_h as i64) as i32, dst.h)
+    };
+    Rect {
+        x: dst.x + (dst.w - w) / 2,
+        y: dst.y + (dst.h - h) / 2,
+        w,
+        h,
+    }
+}
+
+struct Slot {
+    // The display rect in the remote coordinates.
+    remote: Rect,
+    // The area of the display in the canvas.
+    slot: Rect,
+    // The area of the last frame in the canvas, fitted into `slot`.
+    frame: Option<Rect>,
+}
+
+pub struct CompositeCanvas {
+    width: usize,
+    height: usize,
+    slots: Vec<Slot>,
+    data: Vec<u8>,
+}
+
+impl CompositeCanvas {
+    pub fn new(displays: &[DisplayInfo], width: usize, height: usize) -> Self {
+        let remotes: Vec<Rect> = displays
+            .iter()
+            .map(|d| Rect {
+                x: d.x,
+                y: d.y,
+                w: d.width,
+                h: d.height,
+            })
+            .collect();
+        let min_x = remotes.iter().map(|r| r.x).min().unwrap_or(0);
+        let min_y = remotes.iter().map(|r| r.y).min().unwrap_or(0);
+        let max_x = remotes.iter().map(|r| r.x + r.w).max().unwrap_or(0);
+        let max_y = remotes.iter().map(|r| r.y + r.h).max().unwrap_or(0);
+        let (bound_w, bound_h) = (max_x - min_x, max_y - min_y);
+        let layout = fit_rect(
+            bound_w,
+            bound_h,
+            Rect {
+                x: 0,
+                y: 0,
+                w: width as _,
+                h: height as _,
+            },
+        );
+        let scale = |v: i32, total: i32, target: i32| -> i32 {
+            if total <= 0 {
+                0
+            } else {
+                (v as i64 * target as i64 / total as i64) as i32
+            }
+        };
+        let slots = remotes
+            .into_iter()
+            .map(|remote| {
+                let x0 = scale(remote.x - min_x, bound_w, layout.w);
+                let y0 = scale(remote.y - min_y, bound_h, layout.h);
+                let x1 = scale(remote.x + remote.w - min_x, bound_w, layout.w);
+                let y1 = scale(remote.y + remote.h - min_y, bound_h, layout.h);
+                Slot {
+                    remote,
+                    slot: Rect {
+                        x: layout.x + x0,
+                        y: layout.y + y0,
+                        w: x1 - x0,
+                        h: y1 - y0,
+                    },
+                    frame: None,
+                }
+            })
+            .collect();
+        let mut canvas = Self {
+            width,
+            height,
+            slots,
+            data: Vec::new(),
+        };
+        canvas.data = vec![0; canvas.row_bytes() * height];
+        canvas
+    }
+
+    #[inline]
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    #[inline]
+    pub fn row_bytes(&self) -> usize {
+        (self.width * 4 + CANVAS_ALIGN - 1) / CANVAS_ALIGN * CANVAS_ALIGN
+    }
+
+    #[inline]
+    pub fn stride(&self) -> usize {
+        CANVAS_ALIGN
+    }
+
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Copy the frame of `display` into its slot.
+    /// `src_row_bytes` is the length of a row of `src` in bytes.
+    ///
+    /// Return false if the display is not in the layout or the frame is invalid.
+    pub fn blit(
+        &mut self,
+        display: usize,
+        src: &[u8],
+        src_w: usize,
+        src_h: usize,
+        src_row_bytes: usize,
+    ) -> bool {
+        if src_w == 0 || src_h == 0 || src_row_bytes < src_w * 4 || src.len() < src_row_bytes * src_h
+        {
+            return false;
+        }
+        let row_bytes = self.row_bytes();
+        let Some(slot) = self.slots.get_mut(display) else {
+            return false;
+        };
+        let frame = fit_rect(src_w as _, src_h as _, slot.slot);
+        if slot.frame != Some(frame) {
+            // Clear the letterbox area of the old frame.
+            for y in slot.slot.y..slot.slot.y + slot.slot.h {
+                let start = y as usize * row_bytes + slot.slot.x as usize * 4;
+                self.data[start..start + slot.slot.w as usize * 4].fill(0);
+            }
+            slot.frame = Some(frame);
+        }
+        let (fw, fh) = (frame.w as usize, frame.h as usize);
+        for y in 0..fh {
+            let sy = y * src_h / fh;
+            let src_row = &src[sy * src_row_bytes..sy * src_row_bytes + src_w * 4];
+            let start = (frame.y as usize + y) * row_bytes + frame.x as usize * 4;
+            let dst_row = &mut self.data[start..start + fw * 4];
+            if fw == src_w {
+                dst_row.copy_from_slice(src_row);
+            } else {
+                for x in 0..fw {
+                    let sx = x * src_w / fw;
+                    dst_row[x * 4..x * 4 + 4].copy_from_slice(&src_row[sx * 4..sx * 4 + 4]);
+                }
+            }
+        }
+        true
+    }
+
+    /// Translate a point in the canvas to the remote coordinates.
+    /// Points in the letterbox area are clamped to the nearest display.
+    pub fn to_remote(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let area = |s: &Slot| s.frame.unwrap_or(s.slot);
+        let slot = self
+            .slots
+            .iter()
+            .filter(|s| area(s).w > 0 && area(s).h > 0)
+            .find(|s| area(s).contains(x, y))
+            .or_else(|| {
+                self.slots
+                    .iter()
+                    .filter(|s| area(s).w > 0 && area(s).h > 0)
+                    .min_by_key(|s| area(s).distance2(x, y))
+            })?;
+        let rect = area(slot);
+        let (x, y) = rect.clamp(x, y);
+        Some((
+            slot.remote.x + ((x - rect.x) as i64 * slot.remote.w as i64 / rect.w as i64) as i32,
+            slot.remote.y + ((y - rect.y) as i64 * slot.remote.h as i64 / rect.h as i64) as i32,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(x: i32, y: i32, width: i32, height: i32) -> DisplayInfo {
+        DisplayInfo {
+            x,
+            y,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn test_fit_rect() {
+        // Same aspect ratio.
+        assert_eq!(fit_rect(1920, 1080, rect(0, 0, 960, 540)), rect(0, 0, 960, 540));
+        // Wider source, letterbox.
+        assert_eq!(fit_rect(200, 100, rect(0, 0, 100, 100)), rect(0, 25, 100, 50));
+        // Taller source, pillarbox.
+        assert_eq!(fit_rect(100, 200, rect(10, 10, 100, 100)), rect(35, 10, 50, 100));
+        // Invalid sizes.
+        assert_eq!(fit_rect(0, 100, rect(5, 5, 100, 100)), rect(5, 5, 0, 0));
+    }
+
+    #[test]
+    fn test_layout() {
+        let displays = [display(0, 0, 1920, 1080), display(1920, 0, 1920, 1080)];
+        // Exact fit.
+        let canvas = CompositeCanvas::new(&displays, 3840, 1080);
+        assert_eq!(canvas.slots[0].slot, rect(0, 0, 1920, 1080));
+        assert_eq!(canvas.slots[1].slot, rect(1920, 0, 1920, 1080));
+        // Letterboxed canvas.
+        let canvas = CompositeCanvas::new(&displays, 1920, 1080);
+        assert_eq!(canvas.slots[0].slot, rect(0, 270, 960, 540));
+        assert_eq!(canvas.slots[1].slot, rect(960, 270, 960, 540));
+        // Negative offsets.
+        let displays = [display(0, 0, 100, 100), display(-100, 50, 100, 100)];
+        let canvas = CompositeCanvas::new(&displays, 200, 150);
+        assert_eq!(canvas.slots[0].slot, rect(100, 0, 100, 100));
+        assert_eq!(canvas.slots[1].slot, rect(0, 50, 100, 100));
+    }
+
+    #[test]
+    fn test_blit_letterbox() {
+        let displays = [display(0, 0, 4, 4)];
+        let mut canvas = CompositeCanvas::new(&displays, 4, 4);
+        // A 4x2 frame in a 4x4 slot, i.e. a display with a different scale.
+        let src = vec![0xffu8; 4 * 2 * 4];
+        assert!(canvas.blit(0, &src, 4, 2, 16));
+        assert_eq!(canvas.slots[0].frame, Some(rect(0, 1, 4, 2)));
+        let row_bytes = canvas.row_bytes();
+        let data = canvas.data();
+        assert!(data[0..16].iter().all(|v| *v == 0));
+        assert!(data[row_bytes..row_bytes + 16].iter().all(|v| *v == 0xff));
+        assert!(data[row_bytes * 2..row_bytes * 2 + 16].iter().all(|v| *v == 0xff));
+        assert!(data[row_bytes * 3..row_bytes * 3 + 16].iter().all(|v| *v == 0));
+        // Unknown display and short buffer.
+        assert!(!canvas.blit(1, &src, 4, 2, 16));
+        assert!(!canvas.blit(0, &src[1..], 4, 2, 16));
+    }
+
+    #[test]
+    fn test_to_remote() {
+        let displays = [display(0, 0, 1920, 1080), display(1920, 0, 1920, 1080)];
+        let mut canvas = CompositeCanvas::new(&displays, 1920, 1080);
+        assert_eq!(canvas.to_remote(0, 270), Some((0, 0)));
+        assert_eq!(canvas.to_remote(480, 540), Some((960, 540)));
+        assert_eq!(canvas.to_remote(960, 270), Some((1920, 0)));
+        // Letterbox area is clamped to the nearest display.
+        assert_eq!(canvas.to_remote(1919, 0), Some((3838, 0)));
+        // Frame fitted into the slot.
+        let src = vec![0u8; 960 * 270 * 4];
+        assert!(canvas.blit(0, &src, 960, 270, 960 * 4));
+        assert_eq!(canvas.slots[0].frame, Some(rect(0, 405, 960, 270)));
+        assert_eq!(canvas.to_remote(0, 405), Some((0, 0)));
+        assert_eq!(canvas.to_remote(480, 540), Some((960, 540)));
+        assert_eq!(CompositeCanvas::new(&[], 100, 100).to_remote(0, 0), None);
+    }
+}
@@ -0,0 +1,356 @@
+//! Export and import the per-peer settings as a versioned json bundle.
+//!
+//! A bundle carries the peer configs, the peer session profiles and the trusted fingerprints.
+//! The secrets, i.e. the remembered password and the os/rdp credentials, are only included if the
+//! bundle is encrypted with a passphrase.
+
+use super::{
+    profiles::{self, Profile},
+    push_global_event, APP_TYPE_MAIN,
+};
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    config::{FingerprintConfig, PeerConfig},
+    log,
+    sodiumoxide::{
+        base64,
+        crypto::{pwhash, secretbox},
+    },
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::BTreeMap, str::FromStr};
+
+const BUNDLE_VERSION: u64 = 1;
+// The options holding secrets, the same ones encrypted by `PeerConfig::store`.
+const SECRET_OPTIONS: [&str; 3] = ["rdp_password", "os-username", "os-password"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// Keep the existing peer untouched.
+    Skip,
+    /// Replace the existing peer config and the profiles with the same names.
+    Overwrite,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = hbb_common::anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            _ => bail!("Unknown conflict policy {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PeerEntry {
+    config: PeerConfig,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Encryption {
+    salt: String,
+    nonce: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Bundle {
+    version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    // The peers of a plain bundle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peers: Option<BTreeMap<String, PeerEntry>>,
+    // The encrypted json of the peers, base64 encoded.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    data: String,
+}
+
+fn strip_secrets(config: &mut PeerConfig) {
+    config.password.clear();
+    for opt in SECRET_OPTIONS {
+        config.options.remove(opt);
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &pwhash::Salt) -> ResultType<secretbox::Key> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    let secretbox::Key(ref mut buf) = key;
+    if pwhash::derive_key(
+        buf,
+        passphrase.as_bytes(),
+        salt,
+        pwhash::OPSLIMIT_INTERACTIVE,
+        pwhash::MEMLIMIT_INTERACTIVE,
+    )
+    .is_err()
+    {
+        bail!("Failed to derive the key of the passphrase");
+    }
+    Ok(key)
+}
+
+fn decode(v: &str, what: &str) -> ResultType<Vec<u8>> {
+    base64::decode(v, base64::Variant::Original).map_err(|_| anyhow!("Invalid {}", what))
+}
+
+fn make_bundle(mut peers: BTreeMap<String, PeerEntry>, passphrase: &str) -> ResultType<String> {
+    let mut bundle = Bundle {
+        version: BUNDLE_VERSION,
+        encryption: None,
+        peers: None,
+        data: "".to_owned(),
+    };
+    if passphrase.is_empty() {
+        for entry in peers.values_mut() {
+            strip_secrets(&mut entry.config);
+        }
+        bundle.peers = Some(peers);
+    } else {
+        let salt = pwhash::gen_salt();
+        let nonce = secretbox::gen_nonce();
+        let key = derive_key(passphrase, &salt)?;
+        let data = secretbox::seal(&serde_json::to_vec(&peers)?, &nonce, &key);
+        bundle.data = base64::encode(data, base64::Variant::Original);
+        bundle.encryption = Some(Encryption {
+            salt: base64::encode(salt.0, base64::Variant::Original),
+            nonce: base64::encode(nonce.0, base64::Variant::Original),
+        });
+    }
+    Ok(serde_json::to_string(&bundle)?)
+}
+
+// Return the peers and if the bundle is encrypted.
+fn parse_bundle(bundle: &str, passphrase: &str) -> ResultType<(BTreeMap<String, PeerEntry>, bool)> {
+    let value: serde_json::Value =
+        serde_json::from_str(bundle).map_err(|e| anyhow!("Corrupted bundle, {}", e))?;
+    // Check the version first, the layout of other versions may differ.
+    match value.get("version").and_then(|v| v.as_u64()) {
+        Some(BUNDLE_VERSION) => {}
+        Some(v) => bail!("Unsupported bundle version {}, expected {}", v, BUNDLE_VERSION),
+        None => bail!("Corrupted bundle, no version"),
+    }
+    let bundle: Bundle =
+        serde_json::from_value(value).map_err(|e| anyhow!("Corrupted bundle, {}", e))?;
+    let Some(encryption) = bundle.encryption else {
+        let Some(mut peers) = bundle.peers else {
+            bail!("Corrupted bundle, no peers");
+        };
+        // Never trust the secrets of a plain bundle.
+        for entry in peers.values_mut() {
+            strip_secrets(&mut entry.config);
+        }
+        return Ok((peers, false));
+    };
+    if passphrase.is_empty() {
+        bail!("The bundle is encrypted, passphrase is required");
+    }
+    let salt = pwhash::Salt::from_slice(&decode(&encryption.salt, "salt")?)
+        .ok_or_else(|| anyhow!("Invalid salt"))?;
+    let nonce = secretbox::Nonce::from_slice(&decode(&encryption.nonce, "nonce")?)
+        .ok_or_else(|| anyhow!("Invalid nonce"))?;
+    let key = derive_key(passphrase, &salt)?;
+    let data = secretbox::open(&decode(&bundle.data, "data")?, &nonce, &key)
+        .map_err(|_| anyhow!("Wrong passphrase or corrupted bundle"))?;
+    let peers = serde_json::from_slice(&data).map_err(|e| anyhow!("Corrupted bundle, {}", e))?;
+    Ok((peers, true))
+}
+
+/// Export the configs of the peers as a bundle.
+///
+/// # Arguments
+///
+/// * `peer_ids` - The peers to export, or empty to export all peers.
+/// * `passphrase` - Encrypt the bundle with the passphrase, the secrets are only exported if it's
+///   not empty.
+pub fn export_peer_config(peer_ids: Vec<String>, passphrase: &str) -> ResultType<String> {
+    let filters = if peer_ids.is_empty() {
+        None
+    } else {
+        Some(peer_ids)
+    };
+    let peers = PeerConfig::peers(filters)
+        .into_iter()
+        .map(|(id, _, config)| {
+            let profiles = profiles::peer_profiles(&id);
+            let fingerprint =
+                FingerprintConfig::get(crate::client::fingerprint_key(&id)).unwrap_or_default();
+            (
+                id,
+                PeerEntry {
+                    config,
+                    profiles,
+                    fingerprint,
+                },
+            )
+        })
+        .collect();
+    make_bundle(peers, passphrase)
+}
+
+fn import_peer(
+    id: &str,
+    entry: PeerEntry,
+    encrypted: bool,
+    policy: ConflictPolicy,
+) -> &'static str {
+    if id.is_empty() {
+        return "invalid";
+    }
+    let exists = PeerConfig::exists(id);
+    if exists && policy == ConflictPolicy::Skip {
+        return "skipped";
+    }
+    let mut config = entry.config;
+    if !encrypted && exists {
+        // Keep the local secrets, the plain bundle has none.
+        let local = PeerConfig::load(id);
+        config.password = local.password;
+        for opt in SECRET_OPTIONS {
+            if let Some(v) = local.options.get(opt) {
+                config.options.insert(opt.to_owned(), v.clone());
+            }
+        }
+    }
+    config.store(id);
+    profiles::merge_peer_profiles(id, entry.profiles, true);
+    if !entry.fingerprint.is_empty() {
+        crate::client::trust_fingerprint(id, &entry.fingerprint);
+    }
+    if exists {
+        "overwritten"
+    } else {
+        "imported"
+    }
+}
+
+/// Import the bundle, the result of each peer is pushed by "config_import_result".
+///
+/// # Arguments
+///
+/// * `bundle` - The bundle created by [`export_peer_config`].
+/// * `passphrase` - The passphrase of an encrypted bundle.
+/// * `policy` - What to do with the peers which already exist.
+pub fn import_peer_config(
+    bundle: &str,
+    passphrase: &str,
+    policy: ConflictPolicy,
+) -> ResultType<()> {
+    let (peers, encrypted) = parse_bundle(bundle, passphrase)?;
+    let results = peers
+        .into_iter()
+        .map(|(id, entry)| {
+            let result = import_peer(&id, entry, encrypted, policy);
+            json!({ "peer_id": id, "result": result })
+        })
+        .collect::<Vec<_>>();
+    log::info!("Imported the config bundle of {} peers", results.len());
+    let event = json!({
+        "name": "config_import_result",
+        "results": results,
+    });
+    let _ = push_global_event(APP_TYPE_MAIN, event.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::hotkey_rules::{HotkeyRules, OPTION_HOTKEY_RULES};
+
+    fn peers() -> BTreeMap<String, PeerEntry> {
+        let mut config = PeerConfig::default();
+        config.password = vec![1, 2, 3];
+        config.view_style = "adaptive".to_owned();
+        config
+            .options
+            .insert("os-password".to_owned(), "secret".to_owned());
+        config
+            .options
+            .insert("codec-preference".to_owned(), "av1".to_owned());
+        config.options.insert(
+            OPTION_HOTKEY_RULES.to_owned(),
+            r#"[{"name":"lock","keys":"meta+l","action":"blocked"}]"#.to_owned(),
+        );
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "lan".to_owned(),
+            Profile {
+                image_quality: Some("best".to_owned()),
+                ..Default::default()
+            },
+        );
+        let mut peers = BTreeMap::new();
+        peers.insert(
+            "123456789".to_owned(),
+            PeerEntry {
+                config,
+                profiles,
+                fingerprint: "abcd 1234".to_owned(),
+            },
+        );
+        peers
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let (plain, encrypted) = parse_bundle(&make_bundle(peers(), "").unwrap(), "").unwrap();
+        assert!(!encrypted);
+        let entry = &plain["123456789"];
+        assert!(entry.config.password.is_empty());
+        assert!(entry.config.options.get("os-password").is_none());
+        assert_eq!(entry.config.view_style, "adaptive");
+        let rules = entry.config.options.get(OPTION_HOTKEY_RULES).unwrap();
+        assert_eq!(HotkeyRules::parse(rules).unwrap()[0].name, "lock");
+        assert_eq!(entry.profiles, peers()["123456789"].profiles);
+        assert_eq!(entry.fingerprint, "abcd 1234");
+
+        let bundle = make_bundle(peers(), "pass").unwrap();
+        assert!(!bundle.contains("secret") && !bundle.contains("adaptive"));
+        let (peers2, encrypted) = parse_bundle(&bundle, "pass").unwrap();
+        assert!(encrypted);
+        assert_eq!(peers2["123456789"].config, peers()["123456789"].config);
+        assert!(parse_bundle(&bundle, "").is_err());
+        assert!(parse_bundle(&bundle, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_bundle_version_mismatch() {
+        let bundle = make_bundle(peers(), "").unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        value["version"] = json!(BUNDLE_VERSION + 1);
+        let err = parse_bundle(&value.to_string(), "").unwrap_err();
+        assert!(err.to_string().contains("Unsupported bundle version"));
+        value["version"] = json!("1");
+        assert!(parse_bundle(&value.to_string(), "").is_err());
+    }
+
+    #[test]
+    fn test_bundle_corrupted() {
+        let bundle = make_bundle(peers(), "").unwrap();
+        assert!(parse_bundle(&bundle[..bundle.len() / 2], "").is_err());
+        assert!(parse_bundle("", "").is_err());
+        assert!(parse_bundle(r#"{"version": 1}"#, "").is_err());
+        assert!(parse_bundle(r#"{"version": 1, "peers": {"1": {}}}"#, "").is_err());
+
+        let bundle = make_bundle(peers(), "pass").unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        let data = value["data"].as_str().unwrap().to_owned();
+        let mut raw = base64::decode(&data, base64::Variant::Original).unwrap();
+        raw[0] ^= 0xff;
+        value["data"] = json!(base64::encode(raw, base64::Variant::Original));
+        let err = parse_bundle(&value.to_string(), "pass").unwrap_err();
+        assert!(err.to_string().contains("corrupted"));
+        value["data"] = json!("not base64!");
+        assert!(parse_bundle(&value.to_string(), "pass").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Downscale the frames on the device, for the mobile clients low on memory, e.g. decoding a 4K
+//! remote display on a 2GB phone gets the app killed.
+//!
+//! The session uses one scale for all the displays, so that the layout of the displays is kept.
+//! The ui works in the scaled coordinates: the sizes and the positions pushed to the ui are
+//! scaled, and the positions of the input are scaled back before sent, see `session_send_mouse`.
+//!
+//! The peer option [`OPTION_DOWNSCALE_FRAMES`] is "Y" or "N", empty if enabled automatically on
+//! the devices with less memory than [`LOW_MEMORY_THRESHOLD`].
+
+use hbb_common::message_proto::DisplayInfo;
+use scrap::{ImageFormat, ImageRgb};
+
+pub const OPTION_DOWNSCALE_FRAMES: &str = "downscale-frames";
+/// The largest width or height of the frames scaled.
+pub const DOWNSCALE_MAX_DIMENSION: usize = 1920;
+/// The total memory in bytes.
+pub const LOW_MEMORY_THRESHOLD: u64 = 3 << 30;
+
+/// The max dimension of the frames of the session per the option, 0 if not downscaled.
+pub fn max_dimension_of_option(option: &str) -> usize {
+    let enabled = match option {
+        "Y" => true,
+        "N" => false,
+        _ => total_memory().map_or(false, |m| m < LOW_MEMORY_THRESHOLD),
+    };
+    if enabled {
+        DOWNSCALE_MAX_DIMENSION
+    } else {
+        0
+    }
+}
+
+// The desktop clients are never downscaled automatically.
+#[cfg(target_os = "android")]
+fn total_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(not(target_os = "android"))]
+fn total_memory() -> Option<u64> {
+    None
+}
+
+#[inline]
+fn scale_len(len: usize, scale: f64) -> usize {
+    ((len as f64 * scale).round() as usize).max(1)
+}
+
+#[derive(Default)]
+pub struct Downscaler {
+    // 0 if not downscaled.
+    max_dim: usize,
+    // The largest width or height of the remote displays.
+    largest: usize,
+}
+
+impl Downscaler {
+    /// Returns true if the scale is changed.
+    pub fn set_max_dimension(&mut self, max_dim: usize) -> bool {
+        let scale = self.scale();
+        self.max_dim = max_dim;
+        scale != self.scale()
+    }
+
+    /// Returns true if the scale is changed.
+    pub fn set_displays(&mut self, displays: &[DisplayInfo]) -> bool {
+        let scale = self.scale();
+        self.largest = displays
+            .iter()
+            .map(|d| d.width.max(d.height).max(0) as usize)
+            .max()
+            .unwrap_or_default();
+        scale != self.scale()
+    }
+
+    /// The scale of the frames, 1.0 if not downscaled.
+    pub fn scale(&self) -> f64 {
+        if self.max_dim > 0 && self.largest > self.max_dim {
+            self.max_dim as f64 / self.largest as f64
+        } else {
+            1.0
+        }
+    }
+
+    #[inline]
+    pub fn is_scaled(&self) -> bool {
+        self.scale() < 1.0
+    }
+
+    /// The remote coordinate shown by the ui.
+    pub fn to_ui(&self, v: i32) -> i32 {
+        (v as f64 * self.scale()).round() as i32
+    }
+
+    /// The ui coordinate on the remote display.
+    pub fn to_remote(&self, v: i32) -> i32 {
+        (v as f64 / self.scale()).round() as i32
+    }
+
+    /// The display as shown by the ui.
+    pub fn display(&self, d: &DisplayInfo) -> DisplayInfo {
+        let mut d = d.clone();
+        if self.is_scaled() {
+            let scale = self.scale();
+            d.x = self.to_ui(d.x);
+            d.y = self.to_ui(d.y);
+            d.width = scale_len(d.width.max(0) as usize, scale) as _;
+            d.height = scale_len(d.height.max(0) as usize, scale) as _;
+        }
+        d
+    }
+
+    /// The size of the frame as shown by the ui.
+    pub fn size(&self, width: usize, height: usize) -> (usize, usize) {
+        if !self.is_scaled() {
+            return (width, height);
+        }
+        let scale = self.scale();
+        (scale_len(width, scale), scale_len(height, scale))
+    }
+
+    /// Scale the frame into `out`, whose buffer is reused. Returns false if not downscaled.
+    pub fn apply(&self, rgba: &ImageRgb, out: &mut Option<ImageRgb>) -> bool {
+        if !self.is_scaled() || rgba.w == 0 || rgba.h == 0 || matches!(rgba.fmt, ImageFormat::Raw) {
+            return false;
+        }
+        let (w, h) = self.size(rgba.w, rgba.h);
+        let out = out.get_or_insert_with(|| ImageRgb::new(rgba.fmt, rgba.stride));
+        out.fmt = rgba.fmt;
+        out.stride = rgba.stride;
+        out.w = w;
+        out.h = h;
+        let align = rgba.stride.max(1);
+        let dst_row = (w * 4 + align - 1) / align * align;
+        out.raw.resize(dst_row * h, 0);
+        bilinear(rgba, out);
+        true
+    }
+}
+
+// Scale the 4 bytes per pixel image to the size of `dst`, in the 8 bits fixed point.
+fn bilinear(src: &ImageRgb, dst: &mut ImageRgb) {
+    let (sw, sh, src_row) = (src.w, src.h, src.raw.len() / src.h);
+    let (dw, dh, dst_row) = (dst.w, dst.h, dst.raw.len() / dst.h);
+    // The source position of the center of the destination pixel, the left or top neighbor and
+    // the weight of the other one.
+    let sample = |i: usize, s: usize, d: usize| -> (usize, usize, u32) {
+        let pos = ((i as f64 + 0.5) * s as f64 / d as f64 - 0.5).max(0.0);
+        let p0 = (pos as usize).min(s - 1);
+        let p1 = (p0 + 1).min(s - 1);
+        (p0, p1, ((pos - p0 as f64) * 256.0) as u32)
+    };
+    let xs: Vec<_> = (0..dw).map(|x| sample(x, sw, dw)).collect();
+    for y in 0..dh {
+        let (y0, y1, fy) = sample(y, sh, dh);
+        let row0 = &src.raw[y0 * src_row..];
+        let row1 = &src.raw[y1 * src_row..];
+        let out = &mut dst.raw[y * dst_row..];
+        for (x, &(x0, x1, fx)) in xs.iter().enumerate() {
+            for c in 0..4 {
+                let top = row0[x0 * 4 + c] as u32 * (256 - fx) + row0[x1 * 4 + c] as u32 * fx;
+                let bottom = row1[x0 * 4 + c] as u32 * (256 - fx) + row1[x1 * 4 + c] as u32 * fx;
+                out[x * 4 + c] = ((top * (256 - fy) + bottom * fy + (1 << 15)) >> 16) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(x: i32, width: i32, height: i32) -> DisplayInfo {
+        DisplayInfo {
+            x,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_downscale_scale_and_coordinates() {
+        let mut ds = Downscaler::default();
+        assert!(!ds.set_displays(&[display(0, 3840, 2160), display(3840, 1920, 1080)]));
+        assert!(!ds.is_scaled());
+        assert!(ds.set_max_dimension(1920));
+        assert_eq!(ds.scale(), 0.5);
+        let d = ds.display(&display(3840, 1920, 1080));
+        assert_eq!((d.x, d.width, d.height), (1920, 960, 540));
+        assert_eq!(ds.size(3840, 2160), (1920, 1080));
+        assert_eq!(ds.to_remote(ds.to_ui(1001)), 1002);
+        // The small displays are not scaled.
+        assert!(ds.set_displays(&[display(0, 1280, 720)]));
+        assert_eq!(ds.to_remote(1001), 1001);
+        assert_eq!(max_dimension_of_option("N"), 0);
+        assert_eq!(max_dimension_of_option("Y"), DOWNSCALE_MAX_DIMENSION);
+    }
+
+    #[test]
+    fn test_downscale_frame() {
+        let mut ds = Downscaler::default();
+        ds.set_max_dimension(2);
+        ds.set_displays(&[display(0, 4, 2)]);
+        // 4x2, the left half black and the right half white, the rows aligned to 64 bytes.
+        let mut rgba = ImageRgb::new(ImageFormat::ARGB, 64);
+        rgba.w = 4;
+        rgba.h = 2;
+        rgba.raw = vec![0; 64 * 2];
+        for y in 0..2 {
+            rgba.raw[y * 64 + 8..y * 64 + 16].fill(255);
+        }
+        let mut out = None;
+        assert!(ds.apply(&rgba, &mut out));
+        let scaled = out.as_ref().unwrap();
+        assert_eq!((scaled.w, scaled.h), (2, 1));
+        assert_eq!(scaled.raw.len(), 64);
+        assert_eq!(&scaled.raw[..8], &[0, 0, 0, 0, 255, 255, 255, 255]);
+        let ptr = scaled.raw.as_ptr();
+        // The buffer is reused.
+        assert!(ds.apply(&rgba, &mut out));
+        assert_eq!(out.as_ref().unwrap().raw.as_ptr(), ptr);
+
+        ds.set_max_dimension(0);
+        assert!(!ds.apply(&rgba, &mut out));
+    }
+}
@@ -0,0 +1,214 @@
+//! The limits of the strings sent by the peer, applied before they're pushed to the ui.
+//!
+//! A peer may send huge or control-character-laden values in the peer info or the chat, which
+//! bloat the event stream and break the json of the ui. The values over the limits are truncated,
+//! the control characters are stripped, except the newlines in the chat, and the platform additions
+//! not being a json object of bounded size are replaced by "{}". Each violation is reported to the
+//! ui by a "peer_data_truncated" event naming the field, at most once per [`REPORT_INTERVAL`].
+//!
+//! The limits are the local options "peer-field-max-len", "peer-chat-max-len" and
+//! "peer-platform-additions-max-len" in bytes. The events generated locally are never limited.
+
+use hbb_common::config::LocalConfig;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_FIELD_MAX_LEN: &str = "peer-field-max-len";
+pub const OPTION_CHAT_MAX_LEN: &str = "peer-chat-max-len";
+pub const OPTION_ADDITIONS_MAX_LEN: &str = "peer-platform-additions-max-len";
+const DEFAULT_FIELD_MAX_LEN: usize = 4 * 1024;
+const DEFAULT_CHAT_MAX_LEN: usize = 64 * 1024;
+const DEFAULT_ADDITIONS_MAX_LEN: usize = 64 * 1024;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub field: usize,
+    pub chat: usize,
+    pub additions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            field: DEFAULT_FIELD_MAX_LEN,
+            chat: DEFAULT_CHAT_MAX_LEN,
+            additions: DEFAULT_ADDITIONS_MAX_LEN,
+        }
+    }
+}
+
+impl Limits {
+    pub fn load() -> Self {
+        let get = |name: &str, default: usize| {
+            LocalConfig::get_option(name)
+                .parse::<usize>()
+                .ok()
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            field: get(OPTION_FIELD_MAX_LEN, DEFAULT_FIELD_MAX_LEN),
+            chat: get(OPTION_CHAT_MAX_LEN, DEFAULT_CHAT_MAX_LEN),
+            additions: get(OPTION_ADDITIONS_MAX_LEN, DEFAULT_ADDITIONS_MAX_LEN),
+        }
+    }
+}
+
+/// Sanitize the fields of one peer message, collecting the names of the fields changed.
+pub struct Sanitizer {
+    limits: Limits,
+    violations: Vec<&'static str>,
+}
+
+impl Sanitizer {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            violations: vec![],
+        }
+    }
+
+    /// A single line value, e.g. the hostname.
+    pub fn text(&mut self, field: &'static str, s: &str) -> String {
+        self.clean(field, s, self.limits.field, false)
+    }
+
+    pub fn chat(&mut self, field: &'static str, s: &str) -> String {
+        self.clean(field, s, self.limits.chat, true)
+    }
+
+    pub fn additions(&mut self, field: &'static str, s: &str) -> String {
+        if s.is_empty() {
+            return String::new();
+        }
+        let valid = s.len() <= self.limits.additions
+            && serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(s).is_ok();
+        if valid {
+            s.to_owned()
+        } else {
+            self.violations.push(field);
+            "{}".to_owned()
+        }
+    }
+
+    fn clean(&mut self, field: &'static str, s: &str, max_len: usize, multiline: bool) -> String {
+        let mut out = String::with_capacity(s.len().min(max_len));
+        let mut violated = false;
+        for c in s.chars() {
+            if c.is_control() && !(multiline && c == '\n') {
+                violated = true;
+                continue;
+            }
+            if out.len() + c.len_utf8() > max_len {
+                violated = true;
+                break;
+            }
+            out.push(c);
+        }
+        if violated {
+            self.violations.push(field);
+        }
+        out
+    }
+
+    pub fn into_violations(self) -> Vec<&'static str> {
+        self.violations
+    }
+}
+
+/// The last report of each field, so a peer flooding the bad values doesn't flood the ui.
+#[derive(Debug, Default)]
+pub struct Reports {
+    last: HashMap<&'static str, Instant>,
+}
+
+impl Reports {
+    /// The fields to report now.
+    pub fn filter(&mut self, violations: Vec<&'static str>) -> Vec<&'static str> {
+        self.filter_at(violations, Instant::now())
+    }
+
+    fn filter_at(&mut self, violations: Vec<&'static str>, now: Instant) -> Vec<&'static str> {
+        let mut fields = vec![];
+        for field in violations {
+            if fields.contains(&field) {
+                continue;
+            }
+            match self.last.get(field) {
+                Some(last) if now.saturating_duration_since(*last) < REPORT_INTERVAL => {}
+                _ => {
+                    self.last.insert(field, now);
+                    fields.push(field);
+                }
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        field: 8,
+        chat: 16,
+        additions: 64,
+    };
+
+    #[test]
+    fn test_text() {
+        let mut s = Sanitizer::new(LIMITS);
+        assert_eq!(s.text("hostname", "pc-01"), "pc-01");
+        assert!(s.violations.is_empty());
+        assert_eq!(s.text("hostname", "a\u{1b}[2Jb\r\n\0c"), "a[2Jbc");
+        assert_eq!(s.text("username", "0123456789"), "01234567");
+        // Never cut in a char.
+        assert_eq!(s.text("version", "ééééé"), "éééé");
+        assert_eq!(s.chat("text", "hi\r\nthere\t!"), "hi\nthere!");
+        assert_eq!(s.chat("text", &"x".repeat(1 << 20)).len(), LIMITS.chat);
+        assert_eq!(
+            s.into_violations(),
+            vec!["hostname", "username", "version", "text", "text"]
+        );
+    }
+
+    #[test]
+    fn test_additions() {
+        let mut s = Sanitizer::new(LIMITS);
+        assert_eq!(s.additions("platform_additions", ""), "");
+        let ok = r#"{"headless": true}"#;
+        assert_eq!(s.additions("platform_additions", ok), ok);
+        assert!(s.violations.is_empty());
+        for bad in [
+            r#"{"headless": tr"#,
+            "[1, 2]",
+            "\"str\"",
+            "\u{0}",
+            &format!(r#"{{"a": "{}"}}"#, "x".repeat(64)),
+        ] {
+            assert_eq!(s.additions("platform_additions", bad), "{}");
+        }
+        assert_eq!(s.into_violations().len(), 5);
+    }
+
+    #[test]
+    fn test_reports() {
+        let mut reports = Reports::default();
+        let now = Instant::now();
+        assert_eq!(
+            reports.filter_at(vec!["text", "hostname", "text"], now),
+            vec!["text", "hostname"]
+        );
+        assert!(reports
+            .filter_at(vec!["text"], now + REPORT_INTERVAL / 2)
+            .is_empty());
+        assert_eq!(
+            reports.filter_at(vec!["text", "username"], now + REPORT_INTERVAL),
+            vec!["text", "username"]
+        );
+    }
+}
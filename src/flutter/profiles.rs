@@ -0,0 +1,440 @@
+//! Session profiles, the named bundles of session options.
+//!
+//! Profiles are stored in the local option [`PROFILES_OPTION`] as json, either globally or for
+//! one peer. A peer profile shadows the global profile with the same name.
+//!
+//! Applying a profile writes the same peer config as the individual options, so the last writer
+//! wins if an option is changed again after the profile is applied.
+
+use super::FlutterSession;
+use crate::client::{Data, Interface};
+use hbb_common::{
+    bail,
+    config::{LocalConfig, PeerConfig},
+    log,
+    message_proto::Message,
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, sync::Mutex};
+
+pub const PROFILES_OPTION: &str = "session-profiles";
+const PROFILES_VERSION: u32 = 1;
+
+lazy_static::lazy_static! {
+    // Serializes the read-modify-write of the profiles option.
+    static ref PROFILES_LOCK: Mutex<()> = Default::default();
+}
+
+/// A named set of session options, the `None` options are left untouched when applied.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_quality: Option<String>,
+    /// Only applied if the image quality is "custom" after the profile is applied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_image_quality: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec_preference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show_remote_cursor: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_after_session_end: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_clipboard: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable_audio: Option<bool>,
+    // The options that are not supported (anymore), they're dropped by `Profiles::upgrade`.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Change {
+    ImageQuality(String),
+    CustomImageQuality(i32),
+    CodecPreference(String),
+    ViewStyle(String),
+    Toggle(&'static str, bool),
+}
+
+impl Change {
+    fn key(&self) -> &'static str {
+        match self {
+            Change::ImageQuality(_) => "image_quality",
+            Change::CustomImageQuality(_) => "custom_image_quality",
+            Change::CodecPreference(_) => "codec_preference",
+            Change::ViewStyle(_) => "view_style",
+            Change::Toggle(name, _) => *name,
+        }
+    }
+}
+
+impl Profile {
+    pub fn from_json(profile: &str) -> ResultType<Self> {
+        let profile: Self = serde_json::from_str(profile)?;
+        if !profile.unknown.is_empty() {
+            bail!(
+                "Unknown profile options: {:?}",
+                profile.unknown.keys().collect::<Vec<_>>()
+            );
+        }
+        Ok(profile)
+    }
+
+    // The changes to make `config` match the profile.
+    fn diff(&self, config: &PeerConfig) -> Vec<Change> {
+        let mut changes = vec![];
+        let mut image_quality = &config.image_quality;
+        if let Some(v) = &self.image_quality {
+            if *v != config.image_quality {
+                changes.push(Change::ImageQuality(v.clone()));
+            }
+            image_quality = v;
+        }
+        if let Some(v) = self.custom_image_quality {
+            if image_quality == "custom" && config.custom_image_quality.first() != Some(&v) {
+                changes.push(Change::CustomImageQuality(v));
+            }
+        }
+        if let Some(v) = &self.codec_preference {
+            let current = config.options.get("codec-preference");
+            if current.map(|c| c.as_str()).unwrap_or("auto") != v.as_str() {
+                changes.push(Change::CodecPreference(v.clone()));
+            }
+        }
+        if let Some(v) = &self.view_style {
+            if *v != config.view_style {
+                changes.push(Change::ViewStyle(v.clone()));
+            }
+        }
+        let toggles = [
+            ("show-remote-cursor", self.show_remote_cursor, config.show_remote_cursor.v),
+            ("privacy-mode", self.privacy_mode, config.privacy_mode.v),
+            (
+                "lock-after-session-end",
+                self.lock_after_session_end,
+                config.lock_after_session_end.v,
+            ),
+            ("disable-clipboard", self.disable_clipboard, config.disable_clipboard.v),
+            ("disable-audio", self.disable_audio, config.disable_audio.v),
+        ];
+        for (name, v, current) in toggles {
+            if let Some(v) = v {
+                if v != current {
+                    changes.push(Change::Toggle(name, v));
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Profiles {
+    version: u32,
+    global: BTreeMap<String, Profile>,
+    peers: BTreeMap<String, BTreeMap<String, Profile>>,
+}
+
+impl Profiles {
+    fn load() -> Self {
+        let s = LocalConfig::get_option(PROFILES_OPTION);
+        if s.is_empty() {
+            return Self::default();
+        }
+        match Self::parse(&s) {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                log::error!("Failed to load session profiles, {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(s: &str) -> ResultType<Self> {
+        let mut profiles: Self = serde_json::from_str(s)?;
+        if profiles.version > PROFILES_VERSION {
+            bail!(
+                "Session profiles version {} is newer than {}",
+                profiles.version,
+                PROFILES_VERSION
+            );
+        }
+        for dropped in profiles.upgrade() {
+            log::warn!("Drop the unsupported session profile option {}", dropped);
+        }
+        Ok(profiles)
+    }
+
+    // Upgrade the profiles of an older version, the options that no longer exist are dropped.
+    // Return the dropped options as "<peer id or empty>/<profile name>/<option>".
+    fn upgrade(&mut self) -> Vec<String> {
+        let mut dropped = vec![];
+        let mut drop_unknown = |id: &str, name: &str, profile: &mut Profile| {
+            for k in std::mem::take(&mut profile.unknown).into_keys() {
+                dropped.push(format!("{}/{}/{}", id, name, k));
+            }
+        };
+        for (name, profile) in self.global.iter_mut() {
+            drop_unknown("", name, profile);
+        }
+        for (id, m) in self.peers.iter_mut() {
+            for (name, profile) in m.iter_mut() {
+                drop_unknown(id, name, profile);
+            }
+        }
+        self.version = PROFILES_VERSION;
+        dropped
+    }
+
+    fn store(&mut self) {
+        self.version = PROFILES_VERSION;
+        self.peers.retain(|_, m| !m.is_empty());
+        let s = serde_json::to_string(self).unwrap_or_default();
+        LocalConfig::set_option(PROFILES_OPTION.to_owned(), s);
+    }
+
+    fn scope_mut(&mut self, peer_id: &str) -> &mut BTreeMap<String, Profile> {
+        if peer_id.is_empty() {
+            &mut self.global
+        } else {
+            self.peers.entry(peer_id.to_owned()).or_default()
+        }
+    }
+
+    fn get(&self, peer_id: &str, name: &str) -> Option<&Profile> {
+        self.peers
+            .get(peer_id)
+            .and_then(|m| m.get(name))
+            .or_else(|| self.global.get(name))
+    }
+}
+
+/// List the global profiles and the profiles of the peer as json,
+/// `{"global": {name: profile}, "peer": {name: profile}}`.
+///
+/// # Arguments
+///
+/// * `peer_id` - The peer id, or empty to list the global profiles only.
+pub fn list_profiles(peer_id: &str) -> String {
+    let profiles = Profiles::load();
+    let peer = profiles.peers.get(peer_id).cloned().unwrap_or_default();
+    serde_json::to_string(&json!({
+        "global": profiles.global,
+        "peer": peer,
+    }))
+    .unwrap_or_default()
+}
+
+/// Create the profile, or replace the existing one with the same name.
+///
+/// # Arguments
+///
+/// * `peer_id` - The peer id, or empty for a global profile.
+/// * `name` - The profile name.
+/// * `profile` - The json of [`Profile`].
+pub fn create_profile(peer_id: &str, name: &str, profile: &str) -> ResultType<()> {
+    if name.is_empty() {
+        bail!("Empty profile name");
+    }
+    let profile = Profile::from_json(profile)?;
+    let _lock = PROFILES_LOCK.lock().unwrap();
+    let mut profiles = Profiles::load();
+    profiles.scope_mut(peer_id).insert(name.to_owned(), profile);
+    profiles.store();
+    Ok(())
+}
+
+/// Delete the profile, return false if it's not found.
+pub fn delete_profile(peer_id: &str, name: &str) -> bool {
+    let _lock = PROFILES_LOCK.lock().unwrap();
+    let mut profiles = Profiles::load();
+    let removed = profiles.scope_mut(peer_id).remove(name).is_some();
+    if removed {
+        profiles.store();
+    }
+    removed
+}
+
+/// The profiles of the peer, not including the global ones.
+pub(super) fn peer_profiles(peer_id: &str) -> BTreeMap<String, Profile> {
+    Profiles::load().peers.remove(peer_id).unwrap_or_default()
+}
+
+/// Add the profiles to the peer, the existing profiles with the same names are replaced only if
+/// `overwrite` is true. Return the number of the added profiles.
+pub(super) fn merge_peer_profiles(
+    peer_id: &str,
+    profiles: BTreeMap<String, Profile>,
+    overwrite: bool,
+) -> usize {
+    if peer_id.is_empty() || profiles.is_empty() {
+        return 0;
+    }
+    let _lock = PROFILES_LOCK.lock().unwrap();
+    let mut stored = Profiles::load();
+    let scope = stored.scope_mut(peer_id);
+    let mut n = 0;
+    for (name, mut profile) in profiles {
+        if overwrite || !scope.contains_key(&name) {
+            // The options of a newer version are dropped.
+            profile.unknown.clear();
+            scope.insert(name, profile);
+            n += 1;
+        }
+    }
+    if n > 0 {
+        stored.store();
+    }
+    n
+}
+
+/// Apply the profile to the session, and push the "profile_applied" event with the changed
+/// options.
+///
+/// The options are changed while holding the write lock of the login config, so the session never
+/// sees a partially applied profile.
+pub fn apply_profile(session: &FlutterSession, name: &str) -> ResultType<()> {
+    let peer_id = session.get_id();
+    let Some(profile) = Profiles::load().get(&peer_id, name).cloned() else {
+        bail!("No session profile named {}", name);
+    };
+    let mut msgs: Vec<Message> = vec![];
+    let changes = {
+        let mut lc = session.lc.write().unwrap();
+        let changes = profile.diff(&lc);
+        for change in changes.iter() {
+            match change {
+                Change::ImageQuality(v) => {
+                    msgs.extend(lc.save_image_quality(v.clone()));
+                    if v != "custom" {
+                        // non custom quality use 30 fps
+                        msgs.push(lc.set_custom_fps(30, false));
+                    }
+                }
+                Change::CustomImageQuality(v) => msgs.push(lc.save_custom_image_quality(*v)),
+                Change::CodecPreference(v) => {
+                    lc.set_option("codec-preference".to_owned(), v.clone());
+                    msgs.push(lc.change_prefer_codec());
+                }
+                Change::ViewStyle(v) => lc.save_view_style(v.clone()),
+                Change::Toggle(toggle, _) => msgs.extend(lc.toggle_option(toggle.to_string())),
+            }
+        }
+        changes
+    };
+    for msg in msgs {
+        session.send(Data::Message(msg));
+    }
+    let changed = changes.iter().map(|c| c.key()).collect::<Vec<_>>();
+    let changed = serde_json::to_string(&changed).unwrap_or_default();
+    session.push_event("profile_applied", vec![("name", name), ("changed", &changed)]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profiles_round_trip() {
+        let mut profiles = Profiles::default();
+        profiles.global.insert(
+            "lan".to_owned(),
+            Profile {
+                image_quality: Some("best".to_owned()),
+                codec_preference: Some("av1".to_owned()),
+                show_remote_cursor: Some(true),
+                ..Default::default()
+            },
+        );
+        profiles.scope_mut("123456789").insert(
+            "hotel".to_owned(),
+            Profile {
+                image_quality: Some("custom".to_owned()),
+                custom_image_quality: Some(30),
+                view_style: Some("adaptive".to_owned()),
+                disable_audio: Some(true),
+                ..Default::default()
+            },
+        );
+        profiles.version = PROFILES_VERSION;
+        let s = serde_json::to_string(&profiles).unwrap();
+        assert!(!s.contains("privacy_mode"));
+        assert_eq!(Profiles::parse(&s).unwrap(), profiles);
+        assert_eq!(profiles.get("123456789", "lan"), profiles.global.get("lan"));
+        assert!(profiles.get("987654321", "hotel").is_none());
+
+        let p = Profile::from_json(r#"{"view_style": "original", "privacy_mode": true}"#).unwrap();
+        assert_eq!(p.view_style.as_deref(), Some("original"));
+        assert_eq!(p.privacy_mode, Some(true));
+        assert!(Profile::from_json(r#"{"no-such-option": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_profiles_upgrade() {
+        let s = r#"{
+            "global": {"lan": {"image_quality": "best", "enable-hw-boost": "Y"}},
+            "peers": {"123": {"hotel": {"view_style": "adaptive", "legacy-fps": 10}}}
+        }"#;
+        let mut profiles: Profiles = serde_json::from_str(s).unwrap();
+        assert_eq!(profiles.version, 0);
+        let mut dropped = profiles.upgrade();
+        dropped.sort();
+        assert_eq!(dropped, vec!["/lan/enable-hw-boost", "123/hotel/legacy-fps"]);
+        assert_eq!(profiles.version, PROFILES_VERSION);
+        assert_eq!(
+            profiles.global["lan"],
+            Profile {
+                image_quality: Some("best".to_owned()),
+                ..Default::default()
+            }
+        );
+        // The upgraded profiles are stored without the dropped options.
+        let s = serde_json::to_string(&profiles).unwrap();
+        assert!(!s.contains("legacy-fps"));
+        assert_eq!(Profiles::parse(&s).unwrap(), profiles);
+
+        let newer = format!(r#"{{"version": {}}}"#, PROFILES_VERSION + 1);
+        assert!(Profiles::parse(&newer).is_err());
+    }
+
+    #[test]
+    fn test_profile_diff() {
+        let mut config = PeerConfig::default();
+        config.image_quality = "balanced".to_owned();
+        config.view_style = "original".to_owned();
+        config.show_remote_cursor.v = true;
+        let profile = Profile {
+            image_quality: Some("custom".to_owned()),
+            custom_image_quality: Some(50),
+            view_style: Some("original".to_owned()),
+            show_remote_cursor: Some(false),
+            disable_audio: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            profile.diff(&config),
+            vec![
+                Change::ImageQuality("custom".to_owned()),
+                Change::CustomImageQuality(50),
+                Change::Toggle("show-remote-cursor", false),
+            ]
+        );
+        // The custom image quality is ignored if the image quality isn't custom.
+        let profile = Profile {
+            custom_image_quality: Some(50),
+            codec_preference: Some("auto".to_owned()),
+            ..Default::default()
+        };
+        assert!(profile.diff(&config).is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+//! The retry queue of the calls to the android foreground service from the cm, e.g.
+//! "add_connection" which shows the persistent notification to accept the connection.
+//!
+//! The service may not be ready yet, or be killed by some OEM systems, so a failed call is retried
+//! with backoff for [`RETRY_PERIOD`], and all the pending calls are tried again at once when the
+//! service reports ready. The outcome of a call failed once is pushed by "service_call_result",
+//! the ui falls back to the in-app dialog if it's not delivered.
+
+use hbb_common::ResultType;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub const ADD_CONNECTION: &str = "add_connection";
+/// The failed calls are given up after the period.
+pub const RETRY_PERIOD: Duration = Duration::from_secs(30);
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallResult {
+    Delivered,
+    /// Given up after the retry period, with the last error.
+    Failed(String),
+    /// The connection is removed before the call is delivered.
+    Dropped,
+}
+
+impl CallResult {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Failed(_) => "failed",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallOutcome {
+    pub name: String,
+    pub conn_id: Option<i32>,
+    pub tries: u32,
+    pub result: CallResult,
+}
+
+impl CallOutcome {
+    /// The fields of the "service_call_result" event.
+    pub fn event(&self) -> Vec<(&'static str, String)> {
+        let error = match &self.result {
+            CallResult::Failed(err) => err.clone(),
+            _ => "".to_owned(),
+        };
+        vec![
+            ("method", self.name.clone()),
+            (
+                "id",
+                self.conn_id.map(|id| id.to_string()).unwrap_or_default(),
+            ),
+            ("result", self.result.name().to_owned()),
+            ("tries", self.tries.to_string()),
+            ("error", error),
+        ]
+    }
+}
+
+struct PendingCall {
+    name: String,
+    arg: Option<String>,
+    conn_id: Option<i32>,
+    first_failure: Instant,
+    next_try: Instant,
+    backoff: Duration,
+    tries: u32,
+}
+
+#[derive(Default)]
+pub struct ServiceCallQueue {
+    pending: VecDeque<PendingCall>,
+}
+
+impl ServiceCallQueue {
+    /// Try the call at once, it's queued for the retries if failed.
+    ///
+    /// A pending call with the same name and connection is replaced, i.e. the retries of the
+    /// duplicate "add_connection"s are collapsed.
+    pub fn call(
+        &mut self,
+        name: &str,
+        arg: Option<String>,
+        conn_id: Option<i32>,
+        now: Instant,
+        f: &mut impl FnMut(&str, Option<&str>) -> ResultType<()>,
+    ) {
+        let replaced = self
+            .pending
+            .iter()
+            .position(|c| c.name == name && c.conn_id.is_some() && c.conn_id == conn_id)
+            .and_then(|i| self.pending.remove(i));
+        if f(name, arg.as_deref()).is_err() {
+            let (first_failure, tries) = replaced
+                .map(|c| (c.first_failure, c.tries))
+                .unwrap_or((now, 0));
+            self.pending.push_back(PendingCall {
+                name: name.to_owned(),
+                arg,
+                conn_id,
+                first_failure,
+                next_try: now + FIRST_BACKOFF,
+                backoff: FIRST_BACKOFF,
+                tries: tries + 1,
+            });
+        }
+    }
+
+    /// Drop the pending "add_connection" of the removed connection.
+    pub fn remove_connection(&mut self, conn_id: i32) -> Vec<CallOutcome> {
+        let mut outcomes = vec![];
+        self.pending.retain(|c| {
+            if c.name == ADD_CONNECTION && c.conn_id == Some(conn_id) {
+                outcomes.push(CallOutcome {
+                    name: c.name.clone(),
+                    conn_id: c.conn_id,
+                    tries: c.tries,
+                    result: CallResult::Dropped,
+                });
+                return false;
+            }
+            true
+        });
+        outcomes
+    }
+
+    /// Retry the calls due, called on a timer.
+    pub fn retry_due(
+        &mut self,
+        now: Instant,
+        f: &mut impl FnMut(&str, Option<&str>) -> ResultType<()>,
+    ) -> Vec<CallOutcome> {
+        self.retry(now, false, f)
+    }
+
+    /// Retry all the pending calls, the service is ready.
+    pub fn drain(
+        &mut self,
+        now: Instant,
+        f: &mut impl FnMut(&str, Option<&str>) -> ResultType<()>,
+    ) -> Vec<CallOutcome> {
+        self.retry(now, true, f)
+    }
+
+    /// When the next call is due, `None` if nothing is pending.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.iter().map(|c| c.next_try).min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    fn retry(
+        &mut self,
+        now: Instant,
+        all: bool,
+        f: &mut impl FnMut(&str, Option<&str>) -> ResultType<()>,
+    ) -> Vec<CallOutcome> {
+        let mut outcomes = vec![];
+        for mut c in std::mem::take(&mut self.pending) {
+            if !all && c.next_try > now {
+                self.pending.push_back(c);
+                continue;
+            }
+            c.tries += 1;
+            let result = match f(&c.name, c.arg.as_deref()) {
+                Ok(()) => CallResult::Delivered,
+                Err(err) => {
+                    if now.saturating_duration_since(c.first_failure) < RETRY_PERIOD {
+                        c.backoff = (c.backoff * 2).min(MAX_BACKOFF);
+                        c.next_try = now + c.backoff;
+                        self.pending.push_back(c);
+                        continue;
+                    }
+                    CallResult::Failed(err.to_string())
+                }
+            };
+            outcomes.push(CallOutcome {
+                name: c.name,
+                conn_id: c.conn_id,
+                tries: c.tries,
+                result,
+            });
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::bail;
+
+    // The service call failing `n` times before succeeding, recording the args delivered.
+    fn mock_service(
+        mut n: usize,
+        delivered: &mut Vec<String>,
+    ) -> impl FnMut(&str, Option<&str>) -> ResultType<()> + '_ {
+        move |name, arg| {
+            if n > 0 {
+                n -= 1;
+                bail!("service not ready");
+            }
+            delivered.push(format!("{}:{}", name, arg.unwrap_or_default()));
+            Ok(())
+        }
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let mut delivered = vec![];
+        let mut f = mock_service(3, &mut delivered);
+        let mut queue = ServiceCallQueue::default();
+        let start = Instant::now();
+        queue.call(
+            ADD_CONNECTION,
+            Some("c1".to_owned()),
+            Some(1),
+            start,
+            &mut f,
+        );
+        assert_eq!(queue.next_due(), Some(ms(start, 500)));
+        assert!(queue.retry_due(ms(start, 400), &mut f).is_empty());
+        // Failed again, then backoff 1s.
+        assert!(queue.retry_due(ms(start, 500), &mut f).is_empty());
+        assert_eq!(queue.next_due(), Some(ms(start, 1500)));
+        assert!(queue.retry_due(ms(start, 1500), &mut f).is_empty());
+        assert_eq!(queue.next_due(), Some(ms(start, 3500)));
+        let outcomes = queue.retry_due(ms(start, 3500), &mut f);
+        assert_eq!(
+            outcomes,
+            vec![CallOutcome {
+                name: ADD_CONNECTION.to_owned(),
+                conn_id: Some(1),
+                tries: 4,
+                result: CallResult::Delivered,
+            }]
+        );
+        assert!(queue.is_empty());
+        drop(f);
+        assert_eq!(delivered, vec!["add_connection:c1"]);
+
+        // Given up after the period.
+        let mut f = mock_service(usize::MAX, &mut delivered);
+        queue.call(ADD_CONNECTION, None, Some(2), start, &mut f);
+        let (now, outcomes) = loop {
+            let now = queue.next_due().unwrap();
+            let outcomes = queue.retry_due(now, &mut f);
+            if !outcomes.is_empty() {
+                break (now, outcomes);
+            }
+        };
+        assert!(now >= start + RETRY_PERIOD);
+        assert_eq!(outcomes[0].result.name(), "failed");
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_collapse_and_drain() {
+        let mut delivered = vec![];
+        let mut f = mock_service(3, &mut delivered);
+        let mut queue = ServiceCallQueue::default();
+        let start = Instant::now();
+        queue.call(
+            ADD_CONNECTION,
+            Some("c1".to_owned()),
+            Some(1),
+            start,
+            &mut f,
+        );
+        // The duplicate is collapsed.
+        queue.call(
+            ADD_CONNECTION,
+            Some("c1 v2".to_owned()),
+            Some(1),
+            start,
+            &mut f,
+        );
+        queue.call(
+            ADD_CONNECTION,
+            Some("c2".to_owned()),
+            Some(2),
+            start,
+            &mut f,
+        );
+        assert_eq!(queue.pending.len(), 2);
+        assert_eq!(queue.pending[0].tries, 2);
+
+        // Removed before delivered.
+        let outcomes = queue.remove_connection(2);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].result, CallResult::Dropped);
+        assert!(queue.remove_connection(2).is_empty());
+
+        // Drained at once when the service is ready.
+        let outcomes = queue.drain(ms(start, 10), &mut f);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].tries, 3);
+        assert_eq!(outcomes[0].event()[2], ("result", "delivered".to_owned()));
+        drop(f);
+        assert_eq!(delivered, vec!["add_connection:c1 v2"]);
+    }
+}
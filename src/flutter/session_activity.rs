@@ -0,0 +1,231 @@
+//! The activity of a session shown on its tabs, i.e. the unread chat messages, the file transfers
+//! and the voice call, pushed to the ui by the compact "session_activity" events.
+//!
+//! The unread counters are kept per ui session, a chat message or a finished transfer is unread in
+//! each window viewing the peer until read there, so reading the chat in one window doesn't clear
+//! the badge of another. The transfers in progress and the voice call are the state of the
+//! connection, shared by all the ui sessions.
+//!
+//! The same state, with the displays being recorded, tells the ui what is interrupted by closing
+//! the last ui session of the peer, see [`BlockingActivity`].
+
+use serde_derive::Serialize;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+pub const CATEGORY_CHAT: &str = "chat";
+pub const CATEGORY_TRANSFER: &str = "transfer";
+
+pub const VOICE_CALL_NONE: &str = "";
+pub const VOICE_CALL_WAITING: &str = "waiting";
+pub const VOICE_CALL_INCOMING: &str = "incoming";
+pub const VOICE_CALL_STARTED: &str = "started";
+
+// The updates driven by the progress of the jobs are throttled.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The unread counters of a ui session.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Unread {
+    pub chat: u32,
+    /// The finished transfers.
+    pub transfers: u32,
+}
+
+impl Unread {
+    /// Returns true if any was unread.
+    pub fn mark_read(&mut self, category: &str) -> bool {
+        let counter = match category {
+            CATEGORY_CHAT => &mut self.chat,
+            CATEGORY_TRANSFER => &mut self.transfers,
+            _ => return false,
+        };
+        std::mem::take(counter) > 0
+    }
+}
+
+/// The activity interrupted if the peer session is closed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockingActivity {
+    /// The ids of the jobs in progress, sorted.
+    pub transfers: Vec<i32>,
+    /// The displays being recorded, sorted.
+    pub recording: Vec<usize>,
+    pub voice_call: &'static str,
+    /// Closing the ui session closes the peer session, i.e. it's the last one.
+    pub last_ui_session: bool,
+    /// The ui should ask before closing.
+    pub blocking: bool,
+}
+
+#[derive(Default)]
+pub struct SessionActivity {
+    // The ids of the jobs in progress.
+    transfers: HashSet<i32>,
+    // The displays being recorded.
+    recording: HashSet<usize>,
+    voice_call: &'static str,
+    last_progress_push: Option<Instant>,
+    // A change driven by the progress, not pushed yet.
+    pending: bool,
+}
+
+impl SessionActivity {
+    /// Returns true if the activity should be pushed, at most once per [`PROGRESS_INTERVAL`].
+    pub fn on_progress(&mut self, job: i32, now: Instant) -> bool {
+        self.pending |= self.transfers.insert(job);
+        if !self.pending {
+            return false;
+        }
+        if let Some(last) = self.last_progress_push {
+            if now.saturating_duration_since(last) < PROGRESS_INTERVAL {
+                return false;
+            }
+        }
+        self.last_progress_push = Some(now);
+        self.pending = false;
+        true
+    }
+
+    /// The job is done or failed, the activity is pushed at once.
+    /// Returns true if the job was in progress.
+    pub fn on_job_end(&mut self, job: i32) -> bool {
+        let removed = self.transfers.remove(&job);
+        if removed {
+            // Pushed along with this change.
+            self.pending = false;
+        }
+        removed
+    }
+
+    /// Returns true if changed, see `VOICE_CALL_*`.
+    pub fn set_voice_call(&mut self, state: &'static str) -> bool {
+        std::mem::replace(&mut self.voice_call, state) != state
+    }
+
+    /// Returns true if changed.
+    pub fn set_recording(&mut self, display: usize, on: bool) -> bool {
+        if on {
+            self.recording.insert(display)
+        } else {
+            self.recording.remove(&display)
+        }
+    }
+
+    #[inline]
+    pub fn has_transfers(&self) -> bool {
+        !self.transfers.is_empty()
+    }
+
+    pub fn blocking(&self, last_ui_session: bool) -> BlockingActivity {
+        let mut transfers: Vec<_> = self.transfers.iter().cloned().collect();
+        transfers.sort();
+        let mut recording: Vec<_> = self.recording.iter().cloned().collect();
+        recording.sort();
+        let active =
+            !transfers.is_empty() || !recording.is_empty() || self.voice_call != VOICE_CALL_NONE;
+        BlockingActivity {
+            transfers,
+            recording,
+            voice_call: self.voice_call,
+            last_ui_session,
+            blocking: last_ui_session && active,
+        }
+    }
+
+    pub fn event(&self, unread: Unread) -> Vec<(&'static str, String)> {
+        vec![
+            ("unread_chat", unread.chat.to_string()),
+            ("unread_transfer", unread.transfers.to_string()),
+            ("transfers", self.transfers.len().to_string()),
+            ("voice_call", self.voice_call.to_owned()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_progress_throttle() {
+        let mut activity = SessionActivity::default();
+        let start = Instant::now();
+        assert!(activity.on_progress(1, start));
+        // Not changed.
+        assert!(!activity.on_progress(1, ms(start, 300)));
+        // Throttled, then pushed with the next progress.
+        assert!(!activity.on_progress(2, ms(start, 500)));
+        assert!(!activity.on_progress(1, ms(start, 900)));
+        assert!(activity.on_progress(2, ms(start, 1000)));
+        assert_eq!(activity.event(Unread::default())[2].1, "2");
+
+        // The end of a job is pushed at once, along with the pending change.
+        assert!(!activity.on_progress(3, ms(start, 1500)));
+        assert!(activity.on_job_end(1));
+        assert!(!activity.on_job_end(1));
+        assert!(!activity.on_progress(3, ms(start, 2500)));
+        assert_eq!(activity.event(Unread::default())[2].1, "2");
+    }
+
+    #[test]
+    fn test_blocking() {
+        let mut activity = SessionActivity::default();
+        assert!(!activity.blocking(true).blocking);
+        let start = Instant::now();
+        activity.on_progress(3, start);
+        activity.on_progress(1, start);
+        assert!(activity.set_recording(1, true));
+        assert!(!activity.set_recording(1, true));
+        assert!(activity.set_voice_call(VOICE_CALL_STARTED));
+        let blocking = activity.blocking(true);
+        assert_eq!(blocking.transfers, vec![1, 3]);
+        assert_eq!(blocking.recording, vec![1]);
+        assert_eq!(blocking.voice_call, VOICE_CALL_STARTED);
+        assert!(blocking.blocking);
+        // Another ui session is left.
+        assert!(!activity.blocking(false).blocking);
+
+        activity.on_job_end(1);
+        activity.on_job_end(3);
+        assert!(!activity.has_transfers());
+        assert!(activity.set_recording(1, false));
+        // The voice call only.
+        assert!(activity.blocking(true).blocking);
+        activity.set_voice_call(VOICE_CALL_NONE);
+        assert!(!activity.blocking(true).blocking);
+    }
+
+    #[test]
+    fn test_unread_per_ui_session() {
+        let mut activity = SessionActivity::default();
+        let (mut a, mut b) = (Unread::default(), Unread::default());
+        for unread in [&mut a, &mut b] {
+            unread.chat += 2;
+            unread.transfers += 1;
+        }
+        assert!(a.mark_read(CATEGORY_CHAT));
+        assert!(!a.mark_read(CATEGORY_CHAT));
+        assert!(!a.mark_read("unknown"));
+        assert_eq!((a.chat, a.transfers), (0, 1));
+        assert_eq!((b.chat, b.transfers), (2, 1));
+
+        assert!(activity.set_voice_call(VOICE_CALL_INCOMING));
+        assert!(!activity.set_voice_call(VOICE_CALL_INCOMING));
+        assert_eq!(
+            activity.event(b),
+            vec![
+                ("unread_chat", "2".to_owned()),
+                ("unread_transfer", "1".to_owned()),
+                ("transfers", "0".to_owned()),
+                ("voice_call", "incoming".to_owned()),
+            ]
+        );
+    }
+}
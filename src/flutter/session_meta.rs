@@ -0,0 +1,205 @@
+//! The metadata of a peer session assigned by the user, i.e. the alias, the color tag and the notes.
+//!
+//! The metadata is kept with the connection, so all the windows viewing the peer (multi ui session)
+//! share it, and each change is pushed to all of them by "session_meta_changed". The writes are
+//! last-writer-wins, each one bumps the revision carried by the event, so a ui whose write returned
+//! a revision older than the latest event knows it was overwritten.
+//!
+//! The alias and the color are stored in the local option "session-meta" for the next connection,
+//! the notes only live with the connection and are never in the events, see [`get_meta`].
+
+use super::FlutterSession;
+use hbb_common::{bail, config::LocalConfig, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+pub const OPTION_SESSION_META: &str = "session-meta";
+pub const FIELD_ALIAS: &str = "alias";
+pub const FIELD_COLOR: &str = "color";
+pub const FIELD_NOTES: &str = "notes";
+// In chars.
+const MAX_ALIAS_LEN: usize = 64;
+// In bytes.
+const MAX_NOTES_LEN: usize = 4 * 1024;
+
+/// The metadata kept for the next connection.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Stored {
+    alias: String,
+    // "#RRGGBB", or empty.
+    color: String,
+}
+
+impl Stored {
+    fn is_empty(&self) -> bool {
+        self.alias.is_empty() && self.color.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SessionMeta {
+    stored: Stored,
+    notes: String,
+    revision: u64,
+}
+
+impl SessionMeta {
+    pub fn load(peer_id: &str) -> Self {
+        Self {
+            stored: load_all().remove(peer_id).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    // Returns true if the field is stored for the next connection.
+    fn set(&mut self, field: &str, value: &str) -> ResultType<bool> {
+        let value = value.trim();
+        let stored = match field {
+            FIELD_ALIAS => {
+                if value.chars().count() > MAX_ALIAS_LEN {
+                    bail!("The alias is longer than {} chars", MAX_ALIAS_LEN);
+                }
+                self.stored.alias = value.to_owned();
+                true
+            }
+            FIELD_COLOR => {
+                if !value.is_empty() && !is_color(value) {
+                    bail!("Invalid color {}, #RRGGBB is expected", value);
+                }
+                self.stored.color = value.to_uppercase();
+                true
+            }
+            FIELD_NOTES => {
+                if value.len() > MAX_NOTES_LEN {
+                    bail!("The notes are larger than {} bytes", MAX_NOTES_LEN);
+                }
+                self.notes = value.to_owned();
+                false
+            }
+            _ => bail!("Unknown session meta field {}", field),
+        };
+        self.revision += 1;
+        Ok(stored)
+    }
+
+    fn to_json(&self, with_notes: bool) -> serde_json::Value {
+        let mut v = json!({
+            "alias": self.stored.alias,
+            "color": self.stored.color,
+            "has_notes": !self.notes.is_empty(),
+            "revision": self.revision,
+        });
+        if with_notes {
+            v["notes"] = json!(self.notes);
+        }
+        v
+    }
+}
+
+fn is_color(s: &str) -> bool {
+    s.len() == 7 && s.starts_with('#') && s[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn load_all() -> BTreeMap<String, Stored> {
+    let s = LocalConfig::get_option(OPTION_SESSION_META);
+    if s.is_empty() {
+        return Default::default();
+    }
+    serde_json::from_str(&s).unwrap_or_else(|e| {
+        log::error!("Failed to load the session meta, {}", e);
+        Default::default()
+    })
+}
+
+fn store(peer_id: &str, stored: &Stored) {
+    let mut all = load_all();
+    if stored.is_empty() {
+        all.remove(peer_id);
+    } else {
+        all.insert(peer_id.to_owned(), stored.clone());
+    }
+    let s = serde_json::to_string(&all).unwrap_or_default();
+    LocalConfig::set_option(OPTION_SESSION_META.to_owned(), s);
+}
+
+/// Set a field, "alias", "color" or "notes", and push the change to all the ui sessions of the
+/// peer. Returns the revision of this write.
+pub fn set_meta(
+    session: &FlutterSession,
+    writer: &str,
+    field: &str,
+    value: &str,
+) -> ResultType<u64> {
+    let (revision, stored, has_notes) = {
+        let mut meta = session.meta.lock().unwrap();
+        if meta.set(field, value)? {
+            store(&session.get_id(), &meta.stored);
+        }
+        (meta.revision, meta.stored.clone(), !meta.notes.is_empty())
+    };
+    session.push_stateful_event(
+        "session_meta_changed",
+        Some(vec![
+            ("alias", &stored.alias),
+            ("color", &stored.color),
+            ("has_notes", &has_notes.to_string()),
+            ("revision", &revision.to_string()),
+            ("field", field),
+            ("writer", writer),
+        ]),
+    );
+    Ok(revision)
+}
+
+/// The json of the metadata, the notes are only included if `with_notes` is true.
+pub fn get_meta(session: &FlutterSession, with_notes: bool) -> String {
+    session.meta.lock().unwrap().to_json(with_notes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let mut meta = SessionMeta::default();
+        assert!(meta.set(FIELD_ALIAS, " office ").unwrap());
+        assert_eq!(meta.stored.alias, "office");
+        assert!(meta.set(FIELD_COLOR, "#00ff7f").unwrap());
+        assert_eq!(meta.stored.color, "#00FF7F");
+        assert!(!meta.set(FIELD_NOTES, "reboot after 6pm").unwrap());
+        assert_eq!(meta.revision, 3);
+
+        // The rejected writes keep the revision.
+        assert!(meta.set(FIELD_COLOR, "red").is_err());
+        assert!(meta
+            .set(FIELD_ALIAS, &"a".repeat(MAX_ALIAS_LEN + 1))
+            .is_err());
+        assert!(meta
+            .set(FIELD_NOTES, &"n".repeat(MAX_NOTES_LEN + 1))
+            .is_err());
+        assert!(meta.set("tag", "x").is_err());
+        assert_eq!(meta.revision, 3);
+        assert_eq!(meta.stored.color, "#00FF7F");
+    }
+
+    #[test]
+    fn test_notes_excluded() {
+        let mut meta = SessionMeta::default();
+        meta.set(FIELD_NOTES, "secret").unwrap();
+        let v = meta.to_json(false);
+        assert!(v.get("notes").is_none());
+        assert_eq!(v["has_notes"], true);
+        assert_eq!(meta.to_json(true)["notes"], "secret");
+    }
+
+    #[test]
+    fn test_stored() {
+        let stored: Stored = serde_json::from_str(r#"{"alias":"nas","unknown":1}"#).unwrap();
+        assert_eq!(stored.alias, "nas");
+        assert!(stored.color.is_empty());
+        assert!(Stored::default().is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! The remote desktop sessions restored after the app restarts, e.g. updated or crashed.
+//!
+//! If the local option [`OPTION_RESTORE_SESSIONS`] is enabled, a session is recorded when it is
+//! established, and removed at once when closed by the user. The records left by the last run are
+//! offered by the ui, and opened one by one by `async_tasks::restore_sessions`, which skips the
+//! offline peers.
+//!
+//! The windows of the app write the same file, the read-modify-write is serialized by
+//! `RESTORE_LOCK`, and the file is replaced by renaming, so a killed process leaves either the
+//! old or the new records.
+
+use hbb_common::{config::Config, log};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const RESTORE_FILE: &str = "restore_sessions.json";
+pub const OPTION_RESTORE_SESSIONS: &str = "allow-restore-sessions";
+/// The longest wait for a session to be established before opening the next one.
+pub const RESTORE_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref RESTORE_LOCK: Mutex<()> = Default::default();
+    // The records left by the last run, taken once restored or discarded.
+    static ref LAST_RUN: Mutex<Option<Vec<RestoreEntry>>> = Default::default();
+    // peer id -> the time the session was established last.
+    static ref ESTABLISHED: Mutex<HashMap<String, Instant>> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestoreEntry {
+    pub peer_id: String,
+    #[serde(default)]
+    pub force_relay: bool,
+    #[serde(default)]
+    pub view_only: bool,
+}
+
+#[inline]
+fn restore_path() -> PathBuf {
+    Config::path(RESTORE_FILE)
+}
+
+fn load(path: &Path) -> Vec<RestoreEntry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn store(path: &Path, entries: &[RestoreEntry]) {
+    let res = if entries.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    } else {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string(entries).unwrap_or_default())
+            .and_then(|_| std::fs::rename(&tmp, path))
+    };
+    if let Err(e) = res {
+        log::error!("Failed to store the sessions to restore {:?}: {}", path, e);
+    }
+}
+
+// The records of the last run are loaded before the first write of this run.
+fn load_last_run(path: &Path) {
+    let mut last_run = LAST_RUN.lock().unwrap();
+    if last_run.is_none() {
+        *last_run = Some(load(path));
+    }
+}
+
+fn record_at(path: &Path, entry: RestoreEntry) {
+    let _lock = RESTORE_LOCK.lock().unwrap();
+    load_last_run(path);
+    let mut entries = load(path);
+    match entries.iter_mut().find(|e| e.peer_id == entry.peer_id) {
+        Some(e) if *e == entry => return,
+        Some(e) => *e = entry,
+        None => entries.push(entry),
+    }
+    store(path, &entries);
+}
+
+fn remove_at(path: &Path, peer_ids: &[String]) {
+    let _lock = RESTORE_LOCK.lock().unwrap();
+    load_last_run(path);
+    let mut entries = load(path);
+    let len = entries.len();
+    entries.retain(|e| !peer_ids.contains(&e.peer_id));
+    if entries.len() != len {
+        store(path, &entries);
+    }
+}
+
+fn restorable_at(path: &Path) -> Vec<RestoreEntry> {
+    let _lock = RESTORE_LOCK.lock().unwrap();
+    load_last_run(path);
+    LAST_RUN.lock().unwrap().clone().unwrap_or_default()
+}
+
+fn take_restorable_at(path: &Path) -> Vec<RestoreEntry> {
+    let _lock = RESTORE_LOCK.lock().unwrap();
+    load_last_run(path);
+    LAST_RUN.lock().unwrap().replace(vec![]).unwrap_or_default()
+}
+
+/// Record the established session, if restoring is enabled.
+pub fn on_session_established(peer_id: &str, force_relay: bool, view_only: bool) {
+    ESTABLISHED
+        .lock()
+        .unwrap()
+        .insert(peer_id.to_owned(), Instant::now());
+    if !crate::ui_interface::get_local_option(OPTION_RESTORE_SESSIONS.to_owned()).is_empty() {
+        record_at(
+            &restore_path(),
+            RestoreEntry {
+                peer_id: peer_id.to_owned(),
+                force_relay,
+                view_only,
+            },
+        );
+    }
+}
+
+/// The session is closed by the user, not restored anymore.
+pub fn on_session_closed(peer_id: &str) {
+    remove_at(&restore_path(), &[peer_id.to_owned()]);
+}
+
+pub fn is_established_since(peer_id: &str, since: Instant) -> bool {
+    ESTABLISHED
+        .lock()
+        .unwrap()
+        .get(peer_id)
+        .map_or(false, |t| *t >= since)
+}
+
+/// The sessions left by the last run, empty after restored or discarded.
+pub fn restorable() -> Vec<RestoreEntry> {
+    restorable_at(&restore_path())
+}
+
+/// Take the sessions to restore, they are recorded again when established.
+pub fn take_restorable() -> Vec<RestoreEntry> {
+    take_restorable_at(&restore_path())
+}
+
+/// The user declines restoring, the sessions of the last run are removed.
+pub fn discard() {
+    let path = restore_path();
+    let ids: Vec<String> = take_restorable_at(&path)
+        .into_iter()
+        .map(|e| e.peer_id)
+        .collect();
+    remove_at(&path, &ids);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(peer_id: &str) -> RestoreEntry {
+        RestoreEntry {
+            peer_id: peer_id.to_owned(),
+            force_relay: false,
+            view_only: false,
+        }
+    }
+
+    #[test]
+    fn test_restore_records() {
+        let path =
+            std::env::temp_dir().join(format!("restore_sessions_{}.json", std::process::id()));
+        store(&path, &[entry("1"), entry("2")]);
+        *LAST_RUN.lock().unwrap() = None;
+
+        // The records of the last run are kept after the writes of this run.
+        record_at(&path, entry("3"));
+        record_at(
+            &path,
+            RestoreEntry {
+                view_only: true,
+                ..entry("1")
+            },
+        );
+        remove_at(&path, &["2".to_owned()]);
+        assert_eq!(restorable_at(&path), vec![entry("1"), entry("2")]);
+        let stored = load(&path);
+        assert_eq!(stored.len(), 2);
+        assert!(stored[0].view_only);
+        assert_eq!(stored[1], entry("3"));
+
+        assert_eq!(take_restorable_at(&path).len(), 2);
+        assert!(restorable_at(&path).is_empty());
+        remove_at(&path, &["1".to_owned(), "3".to_owned()]);
+        assert!(!path.exists());
+        *LAST_RUN.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn test_established_since() {
+        let start = Instant::now();
+        assert!(!is_established_since("restore-test", start));
+        on_session_established("restore-test", false, false);
+        assert!(is_established_since("restore-test", start));
+    }
+}
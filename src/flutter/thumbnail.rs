@@ -0,0 +1,230 @@
+//! The thumbnails of the remote desktop sessions, shown by the peer cards of the main window.
+//!
+//! Opt-in by the local option [`OPTION_SESSION_THUMBNAILS`]. The video thread takes a downscaled
+//! copy of the latest frame every [`CAPTURE_INTERVAL`], at most [`MAX_WIDTH`] wide, so the copy
+//! is bounded and `on_rgba` is never blocked. The copies are encoded to jpeg by a worker thread,
+//! then pushed by "session_thumbnail" to the main window.
+//!
+//! No thumbnail is taken while the privacy mode is on, or if the host does not allow recording.
+
+use super::{push_global_event, APP_TYPE_MAIN};
+use crate::client::LoginConfigHandler;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hbb_common::{config::LocalConfig, log, ResultType};
+use scrap::{ImageFormat, ImageRgb};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+pub const OPTION_SESSION_THUMBNAILS: &str = "session-thumbnails";
+pub const MAX_WIDTH: usize = 320;
+pub const CAPTURE_INTERVAL: Duration = Duration::from_secs(3);
+const JPEG_QUALITY: u8 = 70;
+// The captures waiting for the worker, the later ones are dropped if it's busy.
+const WORKER_QUEUE_SIZE: usize = 4;
+
+lazy_static::lazy_static! {
+    // peer id -> the base64 jpeg
+    static ref THUMBNAILS: RwLock<HashMap<String, String>> = Default::default();
+    static ref WORKER: Mutex<Option<SyncSender<Job>>> = Default::default();
+}
+
+struct Job {
+    peer_id: String,
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+}
+
+/// The thumbnail capture of a session, only the default sessions are attached.
+#[derive(Default)]
+pub struct ThumbnailCapture {
+    target: RwLock<Option<(String, Arc<RwLock<LoginConfigHandler>>)>>,
+    last: Mutex<Option<Instant>>,
+    recording_denied: AtomicBool,
+}
+
+impl ThumbnailCapture {
+    pub fn attach(&self, peer_id: String, lc: Arc<RwLock<LoginConfigHandler>>) {
+        *self.target.write().unwrap() = Some((peer_id, lc));
+    }
+
+    /// Updated by the recording permission of the host.
+    pub fn set_recording_allowed(&self, allowed: bool) {
+        self.recording_denied.store(!allowed, Ordering::SeqCst);
+    }
+
+    /// Called by the video thread with each frame.
+    pub fn capture(&self, rgba: &ImageRgb) {
+        let Ok(mut last) = self.last.try_lock() else {
+            return;
+        };
+        if last.map_or(false, |t| t.elapsed() < CAPTURE_INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        drop(last);
+        if LocalConfig::get_option(OPTION_SESSION_THUMBNAILS) != "Y" {
+            return;
+        }
+        let Some(peer_id) = self.allowed_peer() else {
+            return;
+        };
+        if let Some((width, height, rgb)) = downscale(rgba, MAX_WIDTH) {
+            send_job(Job {
+                peer_id,
+                width,
+                height,
+                rgb,
+            });
+        }
+    }
+
+    // The peer id if a thumbnail can be taken.
+    fn allowed_peer(&self) -> Option<String> {
+        if self.recording_denied.load(Ordering::SeqCst) {
+            return None;
+        }
+        let target = self.target.try_read().ok()?;
+        let (peer_id, lc) = target.as_ref()?;
+        if lc.try_read().ok()?.get_toggle_option("privacy-mode") {
+            return None;
+        }
+        Some(peer_id.clone())
+    }
+}
+
+/// The base64 jpeg of the latest thumbnail of the peer.
+pub fn get_session_thumbnail(peer_id: &str) -> Option<String> {
+    THUMBNAILS.read().unwrap().get(peer_id).cloned()
+}
+
+/// Called when the session of the peer is closed.
+pub fn remove_session_thumbnail(peer_id: &str) {
+    THUMBNAILS.write().unwrap().remove(peer_id);
+}
+
+// Sample the frame to the rgb of at most `max_width` wide, keeping the aspect ratio.
+fn downscale(rgba: &ImageRgb, max_width: usize) -> Option<(usize, usize, Vec<u8>)> {
+    if rgba.w == 0 || rgba.h == 0 {
+        return None;
+    }
+    let (r, b) = match rgba.fmt {
+        // The byte order of libyuv.
+        ImageFormat::ARGB => (2, 0),
+        ImageFormat::ABGR => (0, 2),
+        ImageFormat::Raw => return None,
+    };
+    let row_bytes = rgba.raw.len() / rgba.h;
+    if row_bytes < rgba.w * 4 {
+        return None;
+    }
+    let width = rgba.w.min(max_width);
+    let height = (rgba.h * width / rgba.w).max(1);
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        let row = y * rgba.h / height * row_bytes;
+        for x in 0..width {
+            let p = row + x * rgba.w / width * 4;
+            rgb.extend_from_slice(&[rgba.raw[p + r], rgba.raw[p + 1], rgba.raw[p + b]]);
+        }
+    }
+    Some((width, height, rgb))
+}
+
+fn send_job(job: Job) {
+    let mut lock = WORKER.lock().unwrap();
+    let tx = lock.get_or_insert_with(|| {
+        let (tx, rx) = sync_channel(WORKER_QUEUE_SIZE);
+        std::thread::spawn(move || run_worker(rx));
+        tx
+    });
+    if let Err(TrySendError::Disconnected(_)) = tx.try_send(job) {
+        *lock = None;
+    }
+}
+
+fn run_worker(rx: Receiver<Job>) {
+    while let Ok(job) = rx.recv() {
+        let data = match encode_jpeg(job.width, job.height, &job.rgb) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to encode the thumbnail of {}: {}", job.peer_id, e);
+                continue;
+            }
+        };
+        let event = json!({
+            "name": "session_thumbnail",
+            "peer_id": job.peer_id,
+            "width": job.width,
+            "height": job.height,
+            "data": data,
+        });
+        THUMBNAILS.write().unwrap().insert(job.peer_id, data);
+        let _ = push_global_event(APP_TYPE_MAIN, event.to_string());
+    }
+}
+
+fn encode_jpeg(width: usize, height: usize, rgb: &[u8]) -> ResultType<String> {
+    let mut buf = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY).encode(
+        rgb,
+        width as _,
+        height as _,
+        image::ColorType::Rgb8,
+    )?;
+    Ok(STANDARD.encode(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(w: usize, h: usize) -> ImageRgb {
+        let mut rgba = ImageRgb::new(ImageFormat::ARGB, 1);
+        rgba.w = w;
+        rgba.h = h;
+        // b, g, r, a
+        rgba.raw = [10u8, 20, 30, 255].repeat(w * h);
+        rgba
+    }
+
+    #[test]
+    fn test_downscale() {
+        let (w, h, rgb) = downscale(&frame(1920, 1080), MAX_WIDTH).unwrap();
+        assert_eq!((w, h), (320, 180));
+        assert_eq!(rgb.len(), w * h * 3);
+        assert_eq!(&rgb[..3], &[30, 20, 10]);
+        // Not upscaled.
+        let (w, h, _) = downscale(&frame(100, 50), MAX_WIDTH).unwrap();
+        assert_eq!((w, h), (100, 50));
+        assert!(downscale(&frame(0, 0), MAX_WIDTH).is_none());
+        let mut raw = frame(4, 4);
+        raw.fmt = ImageFormat::Raw;
+        assert!(downscale(&raw, MAX_WIDTH).is_none());
+        assert!(encode_jpeg(w, h, &downscale(&frame(100, 50), MAX_WIDTH).unwrap().2).is_ok());
+    }
+
+    #[test]
+    fn test_capture_policy() {
+        let capture = ThumbnailCapture::default();
+        // Not a default session.
+        assert!(capture.allowed_peer().is_none());
+        let lc: Arc<RwLock<LoginConfigHandler>> = Default::default();
+        capture.attach("thumbnail-test".to_owned(), lc.clone());
+        assert_eq!(capture.allowed_peer(), Some("thumbnail-test".to_owned()));
+        capture.set_recording_allowed(false);
+        assert!(capture.allowed_peer().is_none());
+        capture.set_recording_allowed(true);
+        // Not blocking the video thread.
+        let _guard = lc.write().unwrap();
+        assert!(capture.allowed_peer().is_none());
+    }
+}
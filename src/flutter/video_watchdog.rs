@@ -0,0 +1,200 @@
+//! The watchdog of the video, telling the ui the frames of a display stopped coming while the
+//! connection is alive, i.e. "the screen froze".
+//!
+//! `on_rgba` records the time of the last frame of each display, the timer of the session checks
+//! them every [`CHECK_INTERVAL`]. "video_stalled" is pushed once a captured display gets no frame
+//! for the local option [`OPTION_VIDEO_STALL_TIMEOUT`] seconds, "video_recovered" once the frames
+//! resume.
+//!
+//! The expected blackouts, i.e. the privacy mode or the display of the host being off or locked,
+//! pause the watchdog.
+
+use hbb_common::config::LocalConfig;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_VIDEO_STALL_TIMEOUT: &str = "video-stall-timeout";
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const PAUSE_PRIVACY_MODE: &str = "privacy_mode";
+pub const PAUSE_HOST_DISPLAY: &str = "host_display";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchdogEvent {
+    Stalled { display: usize, secs: u64 },
+    Recovered { display: usize },
+}
+
+struct DisplayWatch {
+    last_frame: Instant,
+    stalled: bool,
+}
+
+/// The displays are watched from their first frames.
+#[derive(Default)]
+pub struct VideoWatchdog {
+    displays: Mutex<HashMap<usize, DisplayWatch>>,
+    // The reasons of the expected blackouts, see `PAUSE_*`.
+    paused: Mutex<HashSet<&'static str>>,
+}
+
+impl VideoWatchdog {
+    pub fn timeout() -> Duration {
+        LocalConfig::get_option(OPTION_VIDEO_STALL_TIMEOUT)
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Called with each frame, returns the recovery of the display if it was stalled.
+    pub fn on_frame(&self, display: usize, now: Instant) -> Option<WatchdogEvent> {
+        let mut displays = self.displays.lock().unwrap();
+        let watch = displays.entry(display).or_insert(DisplayWatch {
+            last_frame: now,
+            stalled: false,
+        });
+        watch.last_frame = now;
+        if watch.stalled {
+            watch.stalled = false;
+            return Some(WatchdogEvent::Recovered { display });
+        }
+        None
+    }
+
+    /// Check the displays on the timer of the session.
+    ///
+    /// `captured` is the display set requested by the ui, `None` if not requested,
+    /// the displays out of it are not watched any more.
+    pub fn check(
+        &self,
+        now: Instant,
+        timeout: Duration,
+        captured: Option<&[i32]>,
+    ) -> Vec<WatchdogEvent> {
+        let mut displays = self.displays.lock().unwrap();
+        if let Some(captured) = captured {
+            displays.retain(|display, _| captured.contains(&(*display as i32)));
+        }
+        if !self.paused.lock().unwrap().is_empty() {
+            return vec![];
+        }
+        let mut stalled = vec![];
+        for (display, watch) in displays.iter_mut() {
+            let elapsed = now.saturating_duration_since(watch.last_frame);
+            if !watch.stalled && elapsed >= timeout {
+                watch.stalled = true;
+                stalled.push((*display, elapsed.as_secs()));
+            }
+        }
+        stalled.sort();
+        stalled
+            .into_iter()
+            .map(|(display, secs)| WatchdogEvent::Stalled { display, secs })
+            .collect()
+    }
+
+    /// The interval restarts once the blackout is over, the stalled displays stay stalled.
+    pub fn set_paused(&self, reason: &'static str, paused: bool, now: Instant) {
+        let changed = {
+            let mut reasons = self.paused.lock().unwrap();
+            if paused {
+                reasons.insert(reason)
+            } else {
+                reasons.remove(reason) && reasons.is_empty()
+            }
+        };
+        if changed && !paused {
+            for watch in self.displays.lock().unwrap().values_mut() {
+                watch.last_frame = now;
+            }
+        }
+    }
+
+    /// Restart the interval of all the displays, e.g. the ui is waiting for the image again.
+    pub fn reset(&self, now: Instant) {
+        for watch in self.displays.lock().unwrap().values_mut() {
+            watch.last_frame = now;
+            watch.stalled = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_stall_and_recovery() {
+        let watchdog = VideoWatchdog::default();
+        let start = Instant::now();
+        // Not watched before the first frame.
+        assert!(watchdog.check(secs(start, 60), TIMEOUT, None).is_empty());
+
+        assert_eq!(watchdog.on_frame(0, start), None);
+        assert_eq!(watchdog.on_frame(1, start), None);
+        assert!(watchdog.check(secs(start, 4), TIMEOUT, None).is_empty());
+        assert_eq!(watchdog.on_frame(1, secs(start, 4)), None);
+        assert_eq!(
+            watchdog.check(secs(start, 6), TIMEOUT, None),
+            vec![WatchdogEvent::Stalled {
+                display: 0,
+                secs: 6
+            }]
+        );
+        // Pushed once.
+        assert!(watchdog.check(secs(start, 7), TIMEOUT, None).is_empty());
+        assert_eq!(
+            watchdog.check(secs(start, 9), TIMEOUT, None),
+            vec![WatchdogEvent::Stalled {
+                display: 1,
+                secs: 5
+            }]
+        );
+
+        assert_eq!(
+            watchdog.on_frame(0, secs(start, 10)),
+            Some(WatchdogEvent::Recovered { display: 0 })
+        );
+        assert_eq!(watchdog.on_frame(0, secs(start, 11)), None);
+
+        // The display not captured any more.
+        assert!(watchdog.check(secs(start, 20), TIMEOUT, Some(&[1])).is_empty());
+        assert_eq!(watchdog.on_frame(0, secs(start, 21)), None);
+    }
+
+    #[test]
+    fn test_suppression() {
+        let watchdog = VideoWatchdog::default();
+        let start = Instant::now();
+        watchdog.on_frame(0, start);
+
+        // The privacy mode and the host display overlap.
+        watchdog.set_paused(PAUSE_PRIVACY_MODE, true, secs(start, 1));
+        watchdog.set_paused(PAUSE_HOST_DISPLAY, true, secs(start, 2));
+        assert!(watchdog.check(secs(start, 30), TIMEOUT, None).is_empty());
+        watchdog.set_paused(PAUSE_PRIVACY_MODE, false, secs(start, 30));
+        assert!(watchdog.check(secs(start, 40), TIMEOUT, None).is_empty());
+
+        // The interval restarts after the blackout.
+        watchdog.set_paused(PAUSE_HOST_DISPLAY, false, secs(start, 40));
+        assert!(watchdog.check(secs(start, 44), TIMEOUT, None).is_empty());
+        assert_eq!(watchdog.check(secs(start, 45), TIMEOUT, None).len(), 1);
+
+        // Reset by the ui waiting for the image.
+        watchdog.reset(secs(start, 46));
+        assert!(watchdog.check(secs(start, 50), TIMEOUT, None).is_empty());
+        assert_eq!(watchdog.on_frame(0, secs(start, 50)), None);
+        assert_eq!(watchdog.check(secs(start, 55), TIMEOUT, None).len(), 1);
+    }
+}
@@ -0,0 +1,251 @@
+//! Request the peer to capture the region of a display the ui is viewing, see
+//! `server::viewport`, e.g. the part of a 4K display zoomed in on a small laptop.
+//!
+//! Each ui session requests the region it views of a display in the remote coordinates, or the
+//! whole display, the union of the requests of the display is sent to the peer, and the whole
+//! display is requested if any ui session views it in whole. The frames carry the region actually captured, which the ui draws
+//! at its place, so the layout and the coordinates of the input are the ones of the whole display
+//! whatever the region is. The peers without the capability are never requested, their frames
+//! are always of the whole display.
+
+use hbb_common::message_proto::Viewport;
+use std::collections::HashMap;
+
+use super::SessionID;
+
+// The region requested is enlarged by the percent of its size on each side, so a small scroll
+// doesn't reach the edge of the frames before the new region is captured.
+const MARGIN_PERCENT: i32 = 10;
+
+/// A region of a display, the whole display if the width or the height is 0.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// The size of the frames relative to the region.
+    pub scale: f64,
+}
+
+impl Rect {
+    #[inline]
+    pub fn is_whole(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    fn with_margin(&self) -> Self {
+        let (mx, my) = (
+            self.width * MARGIN_PERCENT / 100,
+            self.height * MARGIN_PERCENT / 100,
+        );
+        Self {
+            x: self.x - mx,
+            y: self.y - my,
+            width: self.width + mx * 2,
+            height: self.height + my * 2,
+            scale: self.scale,
+        }
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+            scale: self.scale.max(other.scale),
+        }
+    }
+
+    pub fn to_viewport(&self, display: usize) -> Viewport {
+        let mut v = Viewport {
+            display: display as _,
+            ..Default::default()
+        };
+        if !self.is_whole() {
+            v.x = self.x;
+            v.y = self.y;
+            v.width = self.width;
+            v.height = self.height;
+            v.scale = self.scale.min(1.);
+        }
+        v
+    }
+}
+
+impl From<&Viewport> for Rect {
+    fn from(v: &Viewport) -> Self {
+        Self {
+            x: v.x,
+            y: v.y,
+            width: v.width,
+            height: v.height,
+            scale: v.scale,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Viewports {
+    // (ui session, display) -> the region viewed.
+    requests: HashMap<(SessionID, usize), Rect>,
+    // display -> the region sent to the peer.
+    sent: HashMap<usize, Rect>,
+    // display -> the region of the last frame, without the whole displays.
+    active: HashMap<usize, Rect>,
+    // The displays whose region is changed since the last frame rendered.
+    resized: Vec<usize>,
+}
+
+impl Viewports {
+    /// The ui session views the region of the display. Returns the viewport to send if the union
+    /// of the display is changed.
+    pub fn request(
+        &mut self,
+        session_id: SessionID,
+        display: usize,
+        rect: Rect,
+    ) -> Option<Viewport> {
+        let rect = if rect.is_whole() {
+            Rect::default()
+        } else {
+            rect.with_margin()
+        };
+        self.requests.insert((session_id, display), rect);
+        self.update(display)
+    }
+
+    /// The ui session is closed, returns the viewports to send.
+    pub fn remove_session(&mut self, session_id: &SessionID) -> Vec<Viewport> {
+        let displays: Vec<_> = self
+            .requests
+            .keys()
+            .filter(|(id, _)| id == session_id)
+            .map(|(_, d)| *d)
+            .collect();
+        displays
+            .into_iter()
+            .filter_map(|d| {
+                self.requests.remove(&(*session_id, d));
+                self.update(d)
+            })
+            .collect()
+    }
+
+    fn update(&mut self, display: usize) -> Option<Viewport> {
+        let mut requests = self
+            .requests
+            .iter()
+            .filter(|((_, d), _)| *d == display)
+            .map(|(_, r)| *r);
+        let merged = match requests.next() {
+            Some(first) => requests
+                .try_fold(first, |a, b| {
+                    (!a.is_whole() && !b.is_whole()).then(|| a.union(&b))
+                })
+                .filter(|r| !r.is_whole())
+                .unwrap_or_default(),
+            None => Rect::default(),
+        };
+        let sent = self.sent.get(&display).cloned().unwrap_or_default();
+        if merged == sent {
+            return None;
+        }
+        self.sent.insert(display, merged);
+        Some(merged.to_viewport(display))
+    }
+
+    /// The region carried by a frame of the display. Returns true if changed.
+    pub fn on_frame(&mut self, display: usize, viewport: Option<&Viewport>) -> bool {
+        let rect = viewport.map(Rect::from).filter(|r| !r.is_whole());
+        if self.active.get(&display) == rect.as_ref() {
+            return false;
+        }
+        match rect {
+            Some(rect) => self.active.insert(display, rect),
+            None => self.active.remove(&display),
+        };
+        self.resized.push(display);
+        true
+    }
+
+    /// Returns true once for the first frame rendered after the region of the display changed.
+    pub fn take_resized(&mut self, display: usize) -> bool {
+        let len = self.resized.len();
+        self.resized.retain(|d| *d != display);
+        len != self.resized.len()
+    }
+
+    #[inline]
+    pub fn active(&self) -> &HashMap<usize, Rect> {
+        &self.active
+    }
+
+    #[inline]
+    pub fn is_active(&self, display: usize) -> bool {
+        self.active.contains_key(&display)
+    }
+
+    /// The peer forgets the requests of the last connection. The regions active are kept, so the
+    /// first whole frame of the new connection is reported.
+    pub fn reset(&mut self) {
+        self.sent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+            scale: 1.,
+        }
+    }
+
+    #[test]
+    fn test_request() {
+        let (a, b) = (SessionID::new_v4(), SessionID::new_v4());
+        let mut vp = Viewports::default();
+        // The whole display is requested by default.
+        assert_eq!(vp.request(a, 0, Rect::default()), None);
+        let v = vp.request(a, 0, rect(100, 100, 1000, 500)).unwrap();
+        assert_eq!((v.x, v.y, v.width, v.height), (0, 50, 1200, 600));
+        assert_eq!(vp.request(a, 0, rect(100, 100, 1000, 500)), None);
+        // Another ui session viewing the whole display.
+        let v = vp.request(b, 0, Rect::default()).unwrap();
+        assert_eq!(v.width, 0);
+        let v = vp.request(b, 0, rect(2000, 1000, 100, 100)).unwrap();
+        assert_eq!((v.x, v.y, v.width, v.height), (0, 50, 2110, 1060));
+        // Other displays are independent.
+        assert_eq!(vp.request(b, 1, Rect::default()), None);
+        let vs = vp.remove_session(&a);
+        assert_eq!(vs.len(), 1);
+        assert_eq!((vs[0].x, vs[0].width), (1990, 120));
+        let vs = vp.remove_session(&b);
+        assert_eq!(vs.len(), 1);
+        assert_eq!((vs[0].display, vs[0].width), (0, 0));
+    }
+
+    #[test]
+    fn test_on_frame() {
+        let mut vp = Viewports::default();
+        assert!(!vp.on_frame(0, None));
+        let v = rect(0, 0, 640, 480).to_viewport(0);
+        assert!(vp.on_frame(0, Some(&v)));
+        assert!(vp.is_active(0));
+        assert!(!vp.on_frame(0, Some(&v)));
+        assert!(vp.take_resized(0));
+        assert!(!vp.take_resized(0));
+        assert!(vp.on_frame(0, Some(&Viewport::default())));
+        assert!(!vp.is_active(0));
+        assert!(vp.take_resized(0));
+    }
+}
@@ -0,0 +1,178 @@
+//! Wake the peers sleeping on the network of another peer, which broadcasts the magic packets for
+//! the client, see `crate::lan::relay_wol`.
+//!
+//! The relay peer must be connected in a session. The results are pushed by the "wol_result"
+//! event, whose state is "sent" or "failed", then "online" or "timeout" if the id of the target is
+//! given, which is watched by [`super::async_tasks::watch_online_job`].
+
+use crate::lan::{format_mac, parse_mac, WOL_INVALID_MAC};
+use hbb_common::{log, rendezvous_proto::ConnType};
+use std::{collections::HashMap, sync::Mutex};
+
+pub const WOL_RELAY_OFFLINE: &str = "wol_relay_offline_tip";
+
+lazy_static::lazy_static! {
+    static ref REQUESTS: Mutex<WolRequests> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Request {
+    mac: String,
+    relay: String,
+    target_id: String,
+}
+
+/// The requests waiting for the results of the relays, and the targets watched till online.
+#[derive(Default)]
+struct WolRequests {
+    // The formatted mac -> the request.
+    pending: HashMap<String, Request>,
+    // The target id -> the request.
+    watching: HashMap<String, Request>,
+}
+
+impl WolRequests {
+    /// Returns the formatted mac to send to the relay.
+    fn request(
+        &mut self,
+        mac: &str,
+        relay: &str,
+        target_id: &str,
+        relay_connected: bool,
+    ) -> Result<String, &'static str> {
+        let mac = format_mac(&parse_mac(mac).ok_or(WOL_INVALID_MAC)?);
+        if !relay_connected {
+            return Err(WOL_RELAY_OFFLINE);
+        }
+        self.pending.insert(
+            mac.clone(),
+            Request {
+                mac: mac.clone(),
+                relay: relay.to_owned(),
+                target_id: target_id.to_owned(),
+            },
+        );
+        Ok(mac)
+    }
+
+    /// Returns the request and whether its target is to be watched.
+    fn on_result(&mut self, mac: &str, error: &str) -> Option<(Request, bool)> {
+        let mac = parse_mac(mac).map(|m| format_mac(&m))?;
+        let request = self.pending.remove(&mac)?;
+        let watch = error.is_empty() && !request.target_id.is_empty();
+        if watch {
+            self.watching
+                .insert(request.target_id.clone(), request.clone());
+        }
+        Some((request, watch))
+    }
+
+    fn on_watched(&mut self, target_id: &str) -> Option<Request> {
+        self.watching.remove(target_id)
+    }
+}
+
+fn push_result(request: &Request, state: &str, error: &str, sent: u32) {
+    let data = HashMap::from([
+        ("name", "wol_result".to_owned()),
+        ("mac", request.mac.clone()),
+        ("relay", request.relay.clone()),
+        ("target_id", request.target_id.clone()),
+        ("state", state.to_owned()),
+        ("error", error.to_owned()),
+        ("sent", sent.to_string()),
+    ]);
+    let _res = super::push_global_event(
+        super::APP_TYPE_MAIN,
+        serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+    );
+}
+
+/// Ask `relay_peer_id` to wake `target_mac`, the target is watched till online if `target_id` is
+/// not empty.
+pub fn wake_peer(target_mac: &str, relay_peer_id: &str, target_id: &str) {
+    let session =
+        super::sessions::get_session_by_peer_id(relay_peer_id.to_owned(), ConnType::DEFAULT_CONN)
+            .filter(|s| s.connection_round_state.lock().unwrap().is_connected());
+    let res =
+        REQUESTS
+            .lock()
+            .unwrap()
+            .request(target_mac, relay_peer_id, target_id, session.is_some());
+    match res {
+        Ok(mac) => {
+            if let Some(session) = session {
+                session.relay_wake_on_lan(&mac);
+            }
+        }
+        Err(e) => {
+            log::info!("Failed to wake {} by {}: {}", target_mac, relay_peer_id, e);
+            let request = Request {
+                mac: target_mac.to_owned(),
+                relay: relay_peer_id.to_owned(),
+                target_id: target_id.to_owned(),
+            };
+            push_result(&request, "failed", e, 0);
+        }
+    }
+}
+
+/// The result of the relay, see `InvokeUiSession::wol_result`.
+pub fn on_result(mac: &str, error: &str, sent: u32) {
+    let Some((request, watch)) = REQUESTS.lock().unwrap().on_result(mac, error) else {
+        log::warn!("Unexpected wol result of {}", mac);
+        return;
+    };
+    let state = if error.is_empty() { "sent" } else { "failed" };
+    push_result(&request, state, error, sent);
+    if watch {
+        super::async_tasks::watch_online_job(request.target_id);
+    }
+}
+
+/// The target is online, or not till the timeout.
+pub fn on_watched(target_id: &str, online: bool) {
+    if let Some(request) = REQUESTS.lock().unwrap().on_watched(target_id) {
+        push_result(&request, if online { "online" } else { "timeout" }, "", 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lan::WOL_DENIED;
+
+    #[test]
+    fn test_wol_requests() {
+        let mut requests = WolRequests::default();
+        let res = requests.request("aa:bb:cc:dd:ee", "relay", "", true);
+        assert_eq!(res, Err(WOL_INVALID_MAC));
+        let res = requests.request("AA-BB-CC-DD-EE-FF", "relay", "target", false);
+        assert_eq!(res, Err(WOL_RELAY_OFFLINE));
+        assert!(requests.pending.is_empty());
+
+        // Denied by the relay, not watched.
+        let mac = requests
+            .request("AA-BB-CC-DD-EE-FF", "relay", "target", true)
+            .unwrap();
+        assert_eq!(mac, "aa:bb:cc:dd:ee:ff");
+        let (request, watch) = requests.on_result(&mac, WOL_DENIED).unwrap();
+        assert_eq!(request.target_id, "target");
+        assert!(!watch);
+        assert!(requests.on_result(&mac, "").is_none());
+
+        // Sent, the target is watched.
+        requests
+            .request("aabbccddeeff", "relay", "target", true)
+            .unwrap();
+        let (_, watch) = requests.on_result("AA:BB:CC:DD:EE:FF", "").unwrap();
+        assert!(watch);
+        assert_eq!(requests.on_watched("target").unwrap().relay, "relay");
+        assert!(requests.on_watched("target").is_none());
+
+        // No target to watch.
+        requests.request(&mac, "relay", "", true).unwrap();
+        let (_, watch) = requests.on_result(&mac, "").unwrap();
+        assert!(!watch);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    client::file_trait::FileManager,
+    client::{file_trait::FileManager, Interface},
     common::is_keyboard_mode_supported,
     common::make_fd_to_json,
     flutter::{self, session_add, session_add_existed, session_start_, sessions},
@@ -41,6 +41,10 @@ lazy_static::lazy_static! {
 fn initialize(app_dir: &str) {
     flutter::async_tasks::start_flutter_async_runner();
     *config::APP_DIR.write().unwrap() = app_dir.to_owned();
+    std::thread::spawn(|| {
+        use crate::client::staging;
+        staging::retry_pending_cleanup(&staging::staging_root());
+    });
     #[cfg(target_os = "android")]
     {
         // flexi_logger can't work when android_logger initialized.
@@ -113,20 +117,33 @@ pub fn session_add_sync(
     switch_uuid: String,
     force_relay: bool,
     password: String,
+    rdp_options: String,
+    record_remote_id: bool,
+    view_only: bool,
+    audio_only: bool,
+    direct_target: String,
 ) -> SyncReturn<String> {
-    if let Err(e) = session_add(
+    let res = session_add(
         &session_id,
         &id,
-        is_file_transfer,
-        is_port_forward,
-        is_rdp,
-        &switch_uuid,
-        force_relay,
-        password,
-    ) {
-        SyncReturn(format!("Failed to add session with id {}, {}", &id, e))
-    } else {
-        SyncReturn("".to_owned())
+        flutter::SessionAddOptions {
+            is_file_transfer,
+            is_port_forward,
+            is_rdp,
+            switch_uuid,
+            force_relay,
+            password,
+            rdp_options,
+            record_remote_id,
+            view_only,
+            audio_only,
+            direct_target,
+        },
+    );
+    // The json of `SessionInsertion` if added, the error otherwise.
+    match res {
+        Ok(added) => SyncReturn(added.insertion.to_json()),
+        Err(e) => SyncReturn(format!("Failed to add session with id {}, {}", &id, e)),
     }
 }
 
@@ -179,11 +196,45 @@ pub fn session_login(
     }
 }
 
+/// Send the code of the "input-2fa" msgbox.
+pub fn session_send2fa(session_id: SessionID, code: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send2fa(code);
+    }
+}
+
 pub fn session_close(session_id: SessionID) {
     if let Some(session) = sessions::remove_session_by_session_id(&session_id) {
         session.close_event_stream(session_id);
         session.close();
+        if session.is_default() {
+            flutter::session_restore::on_session_closed(&session.get_id());
+        }
+    }
+}
+
+/// The json of the activity interrupted by closing the ui session, the transfers, the recording
+/// and the voice call, for the ui to ask before closing the last window of the peer.
+pub fn session_has_blocking_activity(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.blocking_activity(&session_id)).unwrap_or_default())
+}
+
+/// Close the ui session, but keep the connection in the background till the transfers end if
+/// it's the last ui session, see [`sessions::remove_session_to_background`].
+/// Returns false if closed as usual.
+pub fn session_close_in_background(session_id: SessionID) -> SyncReturn<bool> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn(false);
+    };
+    session.close_event_stream(session_id);
+    if sessions::remove_session_to_background(&session_id) {
+        return SyncReturn(true);
     }
+    session_close(session_id);
+    SyncReturn(false)
 }
 
 pub fn session_refresh(session_id: SessionID, display: usize) {
@@ -200,6 +251,7 @@ pub fn session_record_screen(
     height: usize,
 ) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_recording_activity(display, start);
         session.record_screen(start, display as _, width as _, height as _);
     }
 }
@@ -222,12 +274,46 @@ pub fn session_toggle_option(session_id: SessionID, value: String) {
         log::warn!("toggle option {}", &value);
         session.toggle_option(value.clone());
     }
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    if sessions::get_session_by_session_id(&session_id).is_some() && value == "disable-clipboard" {
+    if sessions::get_session_by_session_id(&session_id).is_some()
+        && (value == "disable-clipboard"
+            || value == crate::client::text_clipboard::OPTION_DISABLE_CLIPBOARD_READ)
+    {
         crate::flutter::update_text_clipboard_required();
     }
 }
 
+/// Whether the mobile ui can read the local clipboard for the peer.
+pub fn session_is_text_clipboard_required(session_id: SessionID) -> SyncReturn<bool> {
+    SyncReturn(
+        sessions::get_session_by_session_id(&session_id)
+            .map_or(false, |s| s.is_text_clipboard_required()),
+    )
+}
+
+/// Send the text clipboard read by the mobile ui, returns false if not allowed.
+pub fn session_send_text_clipboard(session_id: SessionID, text: String) -> SyncReturn<bool> {
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        return SyncReturn(session.send_text_clipboard(text));
+    }
+    let _ = (session_id, text);
+    SyncReturn(false)
+}
+
+/// Raise the thresholds of the load guard of the host for the session, see "host_throttled".
+pub fn session_prioritize_quality(session_id: SessionID, v: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.prioritize_quality(v);
+    }
+}
+
+/// Measure the latency of the frames, reported as "latency_breakdown" of the quality status.
+pub fn session_measure_latency(session_id: SessionID, v: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.measure_latency(v);
+    }
+}
+
 pub fn session_toggle_privacy_mode(session_id: SessionID, impl_key: String, on: bool) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.toggle_privacy_mode(impl_key, on);
@@ -391,6 +477,40 @@ pub fn session_set_use_all_my_displays_for_the_remote_session(
     }
 }
 
+/// `state` is the json of the window geometry and the view settings, empty to remove it.
+pub fn session_save_view_state(session_id: SessionID, display: i32, state: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.save_view_state(display, state);
+    }
+}
+
+/// Empty if not saved, or the remote display has changed since.
+pub fn session_get_view_state(session_id: SessionID, display: i32) -> SyncReturn<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        SyncReturn(session.get_view_state(display))
+    } else {
+        SyncReturn("".to_owned())
+    }
+}
+
+/// Set the hotkey rules of the peer, the json array of `{"name", "keys", "action"}`, where the
+/// action is "local", "remote" or "blocked".
+pub fn session_set_hotkey_rules(session_id: SessionID, rules: String) -> ResultType<()> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_hotkey_rules(&rules)
+    } else {
+        hbb_common::bail!("No session with id {}", session_id)
+    }
+}
+
+pub fn session_get_hotkey_rules(session_id: SessionID) -> SyncReturn<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        SyncReturn(session.get_hotkey_rules())
+    } else {
+        SyncReturn("".to_owned())
+    }
+}
+
 pub fn session_get_custom_image_quality(session_id: SessionID) -> Option<Vec<i32>> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_custom_image_quality())
@@ -443,6 +563,20 @@ pub fn session_switch_display(is_desktop: bool, session_id: SessionID, value: Ve
     sessions::session_switch_display(is_desktop, session_id, value);
 }
 
+/// Request the peer to capture the region of the display viewed, the whole display if the width
+/// or the height is 0. The coordinates are the ones of the ui, relative to the display.
+pub fn session_set_viewport(
+    session_id: SessionID,
+    display: usize,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    scale: f64,
+) {
+    sessions::session_set_viewport(session_id, display, (x, y, width, height), scale);
+}
+
 pub fn session_handle_flutter_key_event(
     session_id: SessionID,
     name: String,
@@ -516,7 +650,15 @@ pub fn session_send_chat(session_id: SessionID, text: String) {
 
 pub fn session_peer_option(session_id: SessionID, name: String, value: String) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        let is_presenter_option = name.starts_with("presenter-");
+        let is_downscale_option = name == flutter::downscale::OPTION_DOWNSCALE_FRAMES;
         session.set_option(name, value);
+        if is_presenter_option {
+            flutter::load_presenter_options(&session);
+        }
+        if is_downscale_option {
+            flutter::load_downscale_options(&session);
+        }
     }
 }
 
@@ -602,6 +744,27 @@ pub fn session_remove_all_empty_dirs(
     }
 }
 
+/// Mirror the local directory `path` to the remote one `to` until stopped, `overwrite` is
+/// "overwrite", "skip" or "ask".
+pub fn session_start_sync(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    to: String,
+    delete: bool,
+    overwrite: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.start_sync(act_id, path, to, delete, &overwrite);
+    }
+}
+
+pub fn session_stop_sync(session_id: SessionID, act_id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.stop_sync(act_id);
+    }
+}
+
 pub fn session_cancel_job(session_id: SessionID, act_id: i32) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.cancel_job(act_id);
@@ -614,6 +777,30 @@ pub fn session_create_dir(session_id: SessionID, act_id: i32, path: String, is_r
     }
 }
 
+pub fn session_rename_file(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    new_name: String,
+    is_remote: bool,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.rename_file(act_id, path, new_name, is_remote);
+    }
+}
+
+pub fn session_delete_path(
+    session_id: SessionID,
+    act_id: i32,
+    path: String,
+    recursive: bool,
+    is_remote: bool,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.delete_path(act_id, path, recursive, is_remote);
+    }
+}
+
 pub fn session_read_local_dir_sync(
     _session_id: SessionID,
     path: String,
@@ -682,15 +869,31 @@ pub fn session_switch_sides(session_id: SessionID) {
     }
 }
 
-pub fn session_change_resolution(session_id: SessionID, display: i32, width: i32, height: i32) {
+pub fn session_change_resolution(
+    session_id: SessionID,
+    display: i32,
+    width: i32,
+    height: i32,
+    refresh_rate: i32,
+) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
-        session.change_resolution(display, width, height);
+        session.change_resolution(display, width, height, refresh_rate);
     }
 }
 
-pub fn session_set_size(_session_id: SessionID, _display: usize, _width: usize, _height: usize) {
+pub fn session_set_size(
+    _session_id: SessionID,
+    _display: usize,
+    _width: usize,
+    _height: usize,
+) -> ResultType<()> {
     #[cfg(feature = "flutter_texture_render")]
-    super::flutter::session_set_size(_session_id, _display, _width, _height)
+    super::flutter::session_set_size(_session_id, _display, _width, _height);
+    #[cfg(not(feature = "flutter_texture_render"))]
+    if _display == flutter::composite::COMPOSITE_DISPLAY {
+        hbb_common::bail!("Composite view is not supported without texture rendering");
+    }
+    Ok(())
 }
 
 pub fn main_get_sound_inputs() -> Vec<String> {
@@ -771,6 +974,10 @@ pub fn main_test_if_valid_server(server: String) -> String {
     test_if_valid_server(server)
 }
 
+pub fn main_test_if_valid_proxy(proxy: String) -> String {
+    test_if_valid_proxy(proxy)
+}
+
 pub fn main_set_socks(proxy: String, username: String, password: String) {
     set_socks(proxy, username, password)
 }
@@ -808,6 +1015,20 @@ pub fn main_get_peer_sync(id: String) -> SyncReturn<String> {
     SyncReturn(serde_json::to_string(&conf).unwrap_or("".to_string()))
 }
 
+/// The base64 jpeg of the latest thumbnail of the open session of the peer, empty if none.
+/// The new ones are pushed by "session_thumbnail", see the local option "session-thumbnails".
+pub fn main_get_session_thumbnail(peer_id: String) -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    {
+        SyncReturn(flutter::thumbnail::get_session_thumbnail(&peer_id).unwrap_or_default())
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        let _ = peer_id;
+        SyncReturn("".to_owned())
+    }
+}
+
 pub fn main_get_lan_peers() -> String {
     serde_json::to_string(&get_lan_peers()).unwrap_or_default()
 }
@@ -857,6 +1078,11 @@ pub fn main_get_env(key: String) -> SyncReturn<String> {
 }
 
 pub fn main_set_local_option(key: String, value: String) {
+    // The cm is another process on desktop.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if crate::ui_cm_interface::notify::is_notify_option(&key) {
+        send_to_cm(&crate::ipc::Data::CmNotifyPolicy((key.clone(), value.clone())));
+    }
     set_local_option(key, value)
 }
 
@@ -1174,12 +1400,192 @@ pub fn session_remove_port_forward(session_id: SessionID, local_port: i32) {
     }
 }
 
+pub fn session_get_port_forward_stats(_session_id: SessionID) -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return SyncReturn(session.get_port_forward_stats());
+    }
+    SyncReturn("".to_owned())
+}
+
+/// Set the events not pushed to the ui session, e.g. "port_forward_stats".
+pub fn session_set_event_filter(session_id: SessionID, events: Vec<String>) {
+    flutter::session_set_event_filter(session_id, events);
+}
+
+/// Reset the unread counter of the "session_activity" in the ui session, "chat" or "transfer".
+pub fn session_mark_read(session_id: SessionID, category: String) {
+    flutter::session_mark_read(session_id, category);
+}
+
+pub fn session_list_hooks(session_id: SessionID) -> SyncReturn<String> {
+    SyncReturn(flutter::session_list_hooks(session_id))
+}
+
+/// Set the "alias", "color" or "notes" of the peer session, shared by all its ui sessions and
+/// pushed by "session_meta_changed". Returns the revision of this write.
+pub fn session_set_meta(session_id: SessionID, field: String, value: String) -> ResultType<u64> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        hbb_common::bail!("No session with id {}", session_id);
+    };
+    flutter::session_meta::set_meta(&session, &session_id.to_string(), &field, &value)
+}
+
+/// The json of the session metadata, the notes are only included if `with_notes` is true.
+pub fn session_get_meta(session_id: SessionID, with_notes: bool) -> SyncReturn<String> {
+    SyncReturn(
+        sessions::get_session_by_session_id(&session_id)
+            .map(|s| flutter::session_meta::get_meta(&s, with_notes))
+            .unwrap_or_default(),
+    )
+}
+
+/// List the session profiles of the peer and the global ones, pass an empty `peer_id` to list the
+/// global profiles only.
+pub fn main_list_session_profiles(peer_id: String) -> SyncReturn<String> {
+    SyncReturn(flutter::profiles::list_profiles(&peer_id))
+}
+
+/// Create or replace a session profile, returns the error or an empty string.
+pub fn main_create_session_profile(
+    peer_id: String,
+    name: String,
+    profile: String,
+) -> SyncReturn<String> {
+    match flutter::profiles::create_profile(&peer_id, &name, &profile) {
+        Ok(()) => SyncReturn("".to_owned()),
+        Err(e) => SyncReturn(format!("Failed to create session profile {}, {}", name, e)),
+    }
+}
+
+pub fn main_delete_session_profile(peer_id: String, name: String) -> SyncReturn<bool> {
+    SyncReturn(flutter::profiles::delete_profile(&peer_id, &name))
+}
+
+/// Export the configs of the peers, or all peers if `ids` is empty. The secrets are only exported
+/// if the bundle is encrypted with a non-empty passphrase.
+pub fn main_export_peer_config(ids: Vec<String>, passphrase: String) -> ResultType<String> {
+    flutter::config_bundle::export_peer_config(ids, &passphrase)
+}
+
+/// Import the peer configs, `conflict_policy` is "skip" or "overwrite".
+/// The result of each peer is pushed by "config_import_result".
+pub fn main_import_peer_config(
+    bundle: String,
+    passphrase: String,
+    conflict_policy: String,
+) -> ResultType<()> {
+    let policy = conflict_policy.parse()?;
+    flutter::config_bundle::import_peer_config(&bundle, &passphrase, policy)
+}
+
+/// Apply the session profile, the changed options are pushed by "profile_applied".
+pub fn session_apply_profile(session_id: SessionID, name: String) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn(format!("No session with id {}", session_id));
+    };
+    match flutter::profiles::apply_profile(&session, &name) {
+        Ok(()) => SyncReturn("".to_owned()),
+        Err(e) => SyncReturn(format!("Failed to apply session profile {}, {}", name, e)),
+    }
+}
+
+/// Start a SOCKS5 server on `local_port` which forwards the connections through the peer.
+/// The username and password are optional, leave both empty to disable the authentication.
+pub fn session_start_socks(
+    session_id: SessionID,
+    local_port: i32,
+    username: String,
+    password: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.start_socks(local_port, username, password);
+    }
+}
+
+pub fn session_stop_socks(session_id: SessionID, local_port: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.stop_socks(local_port);
+    }
+}
+
+/// Update the rdp options of the session, effective for the next launch of the rdp client.
+/// Returns the error message, empty on success.
+pub fn session_update_rdp_options(session_id: SessionID, options: String) -> SyncReturn<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) = session.update_rdp_options(&options) {
+            return SyncReturn(e.to_string());
+        }
+    }
+    SyncReturn("".to_owned())
+}
+
 pub fn session_new_rdp(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.new_rdp();
     }
 }
 
+pub fn session_run_network_probe(session_id: SessionID, force: bool) -> ResultType<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.run_network_probe(force)
+    } else {
+        hbb_common::bail!("No session with id {}", session_id)
+    }
+}
+
+pub fn session_get_remote_sysinfo(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_remote_sysinfo();
+    }
+}
+
+pub fn session_set_remote_volume(session_id: SessionID, percent: u32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_remote_volume(percent);
+    }
+}
+
+/// The known state as json, e.g. `{"percent": 50, "muted": false}`, empty if not known yet.
+/// The peer is asked too, and the answer is sent by the event "remote_volume_changed".
+pub fn session_get_remote_volume(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    let json = match session.query_remote_volume() {
+        Some((percent, muted)) => {
+            serde_json::json!({ "percent": percent, "muted": muted }).to_string()
+        }
+        None => "".to_owned(),
+    };
+    SyncReturn(json)
+}
+
+pub fn session_set_remote_muted(session_id: SessionID, muted: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_remote_muted(muted);
+    }
+}
+
+pub fn session_toggle_remote_mute(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.toggle_remote_mute();
+    }
+}
+
+pub fn session_is_view_only(session_id: SessionID) -> SyncReturn<bool> {
+    SyncReturn(
+        sessions::get_session_by_session_id(&session_id)
+            .map_or(false, |session| session.is_view_only_session()),
+    )
+}
+
+pub fn session_request_view_only_off(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_view_only_off();
+    }
+}
+
 pub fn session_request_voice_call(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.request_voice_call();
@@ -1231,6 +1637,172 @@ pub fn main_get_fingerprint() -> String {
     get_fingerprint()
 }
 
+/// The trusted fingerprints of the peers as json, `{peer_id: fingerprint}`.
+pub fn main_get_trusted_fingerprints() -> SyncReturn<String> {
+    let fingerprints = hbb_common::config::FingerprintConfig::list();
+    SyncReturn(serde_json::to_string(&fingerprints).unwrap_or_default())
+}
+
+/// Forget the trusted fingerprint, the next one of the peer is trusted on the first use.
+pub fn main_remove_trusted_fingerprint(id: String) -> SyncReturn<bool> {
+    SyncReturn(hbb_common::config::FingerprintConfig::remove(
+        crate::client::fingerprint_key(&id),
+    ))
+}
+
+/// The peer acl rules and groups as json, `{"rules": {key: acl}, "groups": {name: [id]}}`.
+/// `"error"` is set if the stored rules are invalid, all the peers are denied until they are
+/// reset by `main_reset_peer_acls`.
+pub fn main_get_peer_acls() -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "ios")))]
+    return SyncReturn(crate::server::peer_acl::get_peer_acls());
+    #[cfg(any(target_os = "ios"))]
+    return SyncReturn("".to_owned());
+}
+
+/// Add or replace the rule of the peer id, the id prefix ending with '*', or the group '@name'.
+/// The change is pushed by "peer_acl_changed".
+pub fn main_set_peer_acl(key: String, acl: String) -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::peer_acl::set_peer_acl(&key, &acl);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (key, acl);
+        hbb_common::bail!("Peer acl is not supported");
+    }
+}
+
+pub fn main_remove_peer_acl(key: String) -> ResultType<bool> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::peer_acl::remove_peer_acl(&key);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = key;
+        hbb_common::bail!("Peer acl is not supported");
+    }
+}
+
+pub fn main_reset_peer_acls() -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::peer_acl::reset_peer_acls();
+    #[cfg(any(target_os = "ios"))]
+    hbb_common::bail!("Peer acl is not supported");
+}
+
+/// Set the members of the peer acl group, the group is removed if `members` is empty.
+pub fn main_set_peer_acl_group(name: String, members: Vec<String>) -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::peer_acl::set_peer_acl_group(&name, members);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (name, members);
+        hbb_common::bail!("Peer acl is not supported");
+    }
+}
+
+/// The unattended access schedule as json,
+/// `{"enabled", "utc_offset", "grace_minutes", "ranges": {name: {"days", "start", "end"}}}`.
+pub fn main_get_access_schedule() -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "ios")))]
+    return SyncReturn(crate::server::access_schedule::get_access_schedule());
+    #[cfg(any(target_os = "ios"))]
+    return SyncReturn("".to_owned());
+}
+
+/// Remove the schedule, `"error"` of `main_get_access_schedule` is set if it's invalid.
+pub fn main_reset_access_schedule() -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::access_schedule::reset_access_schedule();
+    #[cfg(any(target_os = "ios"))]
+    hbb_common::bail!("Access schedule is not supported");
+}
+
+/// `utc_offset` is in minutes, the time zone of the host if none.
+pub fn main_set_access_schedule_options(
+    enabled: bool,
+    utc_offset: Option<i32>,
+    grace_minutes: u32,
+) -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::access_schedule::set_access_schedule_options(
+        enabled,
+        utc_offset,
+        grace_minutes,
+    );
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (enabled, utc_offset, grace_minutes);
+        hbb_common::bail!("Access schedule is not supported");
+    }
+}
+
+/// Add or replace the range,
+/// `range` is `{"days": [0-6 from Monday], "start": "HH:MM", "end": "HH:MM"}`.
+pub fn main_set_access_schedule_range(name: String, range: String) -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::access_schedule::set_access_schedule_range(&name, &range);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (name, range);
+        hbb_common::bail!("Access schedule is not supported");
+    }
+}
+
+pub fn main_remove_access_schedule_range(name: String) -> ResultType<bool> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::server::access_schedule::remove_access_schedule_range(&name);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = name;
+        hbb_common::bail!("Access schedule is not supported");
+    }
+}
+
+/// The incoming connections refused or closed by the policies, the oldest first.
+pub fn main_get_conn_history() -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "ios")))]
+    return SyncReturn(crate::server::conn_history::get_conn_history());
+    #[cfg(any(target_os = "ios"))]
+    return SyncReturn("[]".to_owned());
+}
+
+pub fn main_clear_conn_history() {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::server::conn_history::clear_conn_history();
+}
+
+pub fn main_has2fa() -> SyncReturn<bool> {
+    #[cfg(not(any(target_os = "ios")))]
+    return SyncReturn(crate::auth_2fa::is_enabled());
+    #[cfg(any(target_os = "ios"))]
+    return SyncReturn(false);
+}
+
+/// Generate a new 2FA secret, pushed by "totp_setup" with the provisioning uri for the qr code.
+/// The secret takes effect after `main_enable2fa` with a valid code.
+pub fn main_setup2fa() {
+    #[cfg(not(any(target_os = "ios")))]
+    {
+        let setup = crate::auth_2fa::setup(&get_id());
+        let event = serde_json::json!({
+            "name": "totp_setup",
+            "secret": setup.secret,
+            "uri": setup.uri,
+        });
+        let _ = flutter::push_global_event(flutter::APP_TYPE_MAIN, event.to_string());
+    }
+}
+
+pub fn main_enable2fa(code: String) -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::auth_2fa::enable(&code);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = code;
+        hbb_common::bail!("2FA is not supported");
+    }
+}
+
 pub fn cm_get_clients_state() -> String {
     crate::ui_cm_interface::get_clients_state()
 }
@@ -1352,6 +1924,11 @@ pub fn session_send_mouse(session_id: SessionID, msg: String) {
             .get("y")
             .map(|x| x.parse::<i32>().unwrap_or(0))
             .unwrap_or(0);
+        // The wheel and trackpad events carry deltas instead of positions.
+        let (x, y) = match m.get("type").map(|t| t.as_str()) {
+            Some("wheel") | Some("trackpad") => (x, y),
+            _ => flutter::session_ui_to_remote(&session_id, x, y),
+        };
         let mut mask = 0;
         if let Some(_type) = m.get("type") {
             mask = match _type.as_str() {
@@ -1374,10 +1951,25 @@ pub fn session_send_mouse(session_id: SessionID, msg: String) {
         }
         if let Some(session) = sessions::get_session_by_session_id(&session_id) {
             session.send_mouse(mask, x, y, alt, ctrl, shift, command);
+            if m.get("type").map(|t| t.as_str()) == Some("down") {
+                session.on_input_position(x, y);
+                session.on_mouse_pressed(
+                    x,
+                    y,
+                    m.get("buttons").map(|b| b.as_str()).unwrap_or_default(),
+                );
+            }
         }
     }
 }
 
+/// Trust the new fingerprint after "fingerprint_mismatch", close the session to abort instead.
+pub fn session_accept_new_fingerprint(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.accept_new_fingerprint();
+    }
+}
+
 pub fn session_restart_remote_device(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.restart_remote_device();
@@ -1415,10 +2007,127 @@ pub fn session_change_prefer_codec(session_id: SessionID) {
     }
 }
 
+/// Ask for 4:4:4 or not and renegotiate, returns the json of
+/// [`crate::client::color_mode::ColorMode`] for the ui to warn about the bitrate.
+pub fn session_set_color_accuracy(session_id: SessionID, on: bool) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.set_color_accuracy(on)).unwrap_or_default())
+}
+
+/// The json of [`crate::client::color_mode::ColorMode`].
+pub fn session_get_color_mode(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.get_color_mode()).unwrap_or_default())
+}
+
+/// The json of [`crate::client::codec_negotiation::CodecCapabilities`], what both ends support.
+pub fn session_get_codec_capabilities(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.codec_capabilities()).unwrap_or_default())
+}
+
+/// The json of [`crate::common::PeerCapabilities`], all the version gated ones are unsupported
+/// before the peer info is received.
+pub fn session_get_peer_capabilities(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.get_peer_capabilities()).unwrap_or_default())
+}
+
+/// Apply the preferred codec mid-session, "auto" for no preference.
+/// Returns the error message, empty on success.
+pub fn session_set_codec_preference(session_id: SessionID, codec: String) -> SyncReturn<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) = session.set_codec_preference(&codec) {
+            return SyncReturn(e.to_string());
+        }
+    }
+    SyncReturn("".to_owned())
+}
+
+/// Decode in software only or not, saved for the peer and applied without reconnecting.
+/// The active decoder is pushed by "decoder_info".
+pub fn session_force_software_decode(session_id: SessionID, software: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.force_software_decode(software);
+    }
+}
+
+/// The json of the files received by the features of the session, e.g. the print jobs.
+pub fn session_get_staged_files(session_id: SessionID) -> SyncReturn<String> {
+    let Some(session) = sessions::get_session_by_session_id(&session_id) else {
+        return SyncReturn("".to_owned());
+    };
+    SyncReturn(serde_json::to_string(&session.staged_files()).unwrap_or_default())
+}
+
+pub fn session_clear_staging(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.clear_staging();
+    }
+}
+
+/// `shape`: "freehand", "rectangle" or "arrow", acked by "annotation_ack".
+pub fn session_send_annotation(
+    session_id: SessionID,
+    id: u32,
+    display: i32,
+    shape: String,
+    points: Vec<i32>,
+    color: u32,
+    width: u32,
+    ttl_ms: u32,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_annotation(id, display, &shape, points, color, width, ttl_ms);
+    }
+}
+
+pub fn session_clear_annotations(session_id: SessionID) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.clear_annotations();
+    }
+}
+
 pub fn session_on_waiting_for_image_dialog_show(session_id: SessionID) {
     super::flutter::session_on_waiting_for_image_dialog_show(session_id);
 }
 
+pub fn session_add_virtual_display(session_id: SessionID, width: i32, height: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.add_virtual_display(width, height);
+    }
+}
+
+pub fn session_remove_virtual_display(session_id: SessionID, display_index: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.remove_virtual_display(display_index);
+    }
+}
+
+pub fn session_get_cached_cursor(session_id: SessionID, id: u64) -> SyncReturn<String> {
+    SyncReturn(super::flutter::session_get_cached_cursor(session_id, id).unwrap_or_default())
+}
+
+pub fn session_set_cursor_scale(session_id: SessionID, scale: f64, raw: bool) {
+    super::flutter::session_set_cursor_scale(session_id, scale, raw);
+}
+
+pub fn session_set_follow_remote_cursor(session_id: SessionID, value: bool) {
+    super::flutter::session_set_follow_remote_cursor(session_id, value);
+}
+
+pub fn session_get_follow_remote_cursor(session_id: SessionID) -> SyncReturn<bool> {
+    SyncReturn(super::flutter::session_get_follow_remote_cursor(session_id))
+}
+
 pub fn session_toggle_virtual_display(session_id: SessionID, index: i32, on: bool) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.toggle_virtual_display(index, on);
@@ -1502,6 +2211,28 @@ pub fn cm_send_chat(conn_id: i32, msg: String) {
     crate::ui_cm_interface::send_chat(conn_id, msg);
 }
 
+/// Returns the sequence number of the message, its delivery is pushed by "cm_chat_delivery".
+pub fn cm_send_message(conn_id: i32, text: String) -> ResultType<u64> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::ui_cm_interface::send_message(conn_id, text);
+    #[cfg(any(target_os = "ios"))]
+    {
+        let _ = (conn_id, text);
+        hbb_common::bail!("Chat is not supported");
+    }
+}
+
+/// Watch the frames sent to the connection, pushed by "cm_watch_frame" at most 5 fps and 480p.
+pub fn cm_watch(conn_id: i32, on: bool) -> ResultType<()> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return crate::ui_cm_interface::watch(conn_id, on);
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    {
+        let _ = (conn_id, on);
+        hbb_common::bail!("Watching is not supported");
+    }
+}
+
 pub fn cm_login_res(conn_id: i32, res: bool) {
     #[cfg(not(any(target_os = "ios")))]
     if res {
@@ -1538,6 +2269,28 @@ pub fn cm_switch_permission(conn_id: i32, name: String, enabled: bool) {
     crate::ui_cm_interface::switch_permission(conn_id, name, enabled)
 }
 
+/// Enable the permission for `secs` seconds, reverted automatically once expired.
+pub fn cm_grant_permission(conn_id: i32, name: String, secs: i32) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::grant_permission(conn_id, name, secs.max(0) as _)
+}
+
+pub fn cm_extend_permission_grant(conn_id: i32, name: String, secs: i32) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::extend_permission_grant(conn_id, name, secs.max(0) as _)
+}
+
+/// Keep the permission granted for a while, i.e. stop the timer.
+pub fn cm_cancel_permission_grant(conn_id: i32, name: String) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::cancel_permission_grant(conn_id, name)
+}
+
+pub fn cm_answer_view_only_off(conn_id: i32, accept: bool) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::answer_view_only_off(conn_id, accept)
+}
+
 pub fn cm_can_elevate() -> SyncReturn<bool> {
     SyncReturn(crate::ui_cm_interface::can_elevate())
 }
@@ -1552,6 +2305,23 @@ pub fn cm_switch_back(conn_id: i32) {
     crate::ui_cm_interface::switch_back(conn_id);
 }
 
+/// Disable 2FA on the local confirmation, refused while the device is remote controlled.
+pub fn cm_disable2fa() -> ResultType<()> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::auth_2fa::disable();
+    #[cfg(any(target_os = "ios"))]
+    hbb_common::bail!("2FA is not supported");
+}
+
+/// Replace the 2FA recovery codes on the local confirmation, refused while the device is
+/// remote controlled. The codes are only returned once.
+pub fn cm_generate2fa_recovery_codes() -> ResultType<Vec<String>> {
+    #[cfg(not(any(target_os = "ios")))]
+    return crate::auth_2fa::generate_recovery_codes();
+    #[cfg(any(target_os = "ios"))]
+    hbb_common::bail!("2FA is not supported");
+}
+
 pub fn cm_get_config(name: String) -> String {
     #[cfg(not(target_os = "ios"))]
     {
@@ -1579,6 +2349,10 @@ pub fn session_get_rgba_size(session_id: SessionID, display: usize) -> SyncRetur
     SyncReturn(super::flutter::session_get_rgba_size(session_id, display))
 }
 
+pub fn session_get_dropped_frames(session_id: SessionID, display: usize) -> SyncReturn<u64> {
+    SyncReturn(super::flutter::session_get_dropped_frames(session_id, display))
+}
+
 pub fn session_next_rgba(session_id: SessionID, display: usize) -> SyncReturn<()> {
     SyncReturn(super::flutter::session_next_rgba(session_id, display))
 }
@@ -1593,10 +2367,105 @@ pub fn session_register_texture(
     ))
 }
 
+/// Wait for the texture to be freed by flutter after it's unregistered, see
+/// `flutter::wait_texture_released`.
+pub fn wait_texture_released(ptr: usize) -> bool {
+    super::flutter::wait_texture_released(ptr, std::time::Duration::from_secs(3))
+}
+
 pub fn query_onlines(ids: Vec<String>) {
     let _ = flutter::async_tasks::query_onlines(ids);
 }
 
+pub fn subscribe_onlines(ids: Vec<String>) {
+    flutter::async_tasks::subscribe_onlines(ids);
+}
+
+pub fn unsubscribe_onlines(ids: Vec<String>) {
+    flutter::async_tasks::unsubscribe_onlines(ids);
+}
+
+/// Same as `query_onlines`, but returns the job id which can be cancelled.
+pub fn submit_query_onlines(ids: Vec<String>) -> SyncReturn<u64> {
+    SyncReturn(flutter::async_tasks::query_onlines(ids))
+}
+
+/// Reload the peers in the background, the result is pushed by "callback_refresh_peers".
+pub fn submit_refresh_peers(ids: Vec<String>) -> SyncReturn<u64> {
+    SyncReturn(flutter::async_tasks::submit(flutter::async_tasks::Job::RefreshPeers(ids)).0)
+}
+
+/// The remote desktop sessions left by the last run, the json list of
+/// `{"peer_id", "force_relay", "view_only"}`.
+pub fn main_get_restorable_sessions() -> SyncReturn<String> {
+    SyncReturn(
+        serde_json::to_string(&flutter::session_restore::restorable()).unwrap_or_default(),
+    )
+}
+
+/// Restore the sessions left by the last run, the progress is pushed by
+/// "restore_sessions_progress".
+pub fn main_restore_sessions() -> SyncReturn<u64> {
+    SyncReturn(flutter::async_tasks::restore_sessions_job())
+}
+
+pub fn main_discard_restorable_sessions() {
+    flutter::session_restore::discard();
+}
+
+/// The relay servers to choose from, the json list of addresses.
+pub fn main_get_relay_candidates() -> SyncReturn<String> {
+    SyncReturn(
+        serde_json::to_string(&crate::client::relay_select::candidates()).unwrap_or_default(),
+    )
+}
+
+/// Measure the latencies of the relays, all the candidates if empty. The results are pushed by
+/// "relay_probe_result", the recent ones are reused unless `force`.
+pub fn main_probe_relays(relays: Vec<String>, force: bool) -> SyncReturn<u64> {
+    SyncReturn(flutter::async_tasks::probe_relays_job(relays, force))
+}
+
+/// Wake `target_mac` by the magic packets broadcast by the connected peer `relay_peer_id`, then
+/// watch `target_id` till online if not empty. The results are pushed by the "wol_result" event.
+pub fn main_wake_peer(target_mac: String, relay_peer_id: String, target_id: String) {
+    #[cfg(not(target_os = "ios"))]
+    flutter::wol_relay::wake_peer(&target_mac, &relay_peer_id, &target_id);
+}
+
+/// The relay tried first when the session with the peer is relayed, empty to clear.
+pub fn main_set_peer_preferred_relay(id: String, relay: String) {
+    set_peer_option(
+        id,
+        crate::client::relay_select::OPTION_PREFERRED_RELAY.to_owned(),
+        relay,
+    )
+}
+
+pub fn main_get_peer_preferred_relay(id: String) -> SyncReturn<String> {
+    SyncReturn(get_peer_option(
+        id,
+        crate::client::relay_select::OPTION_PREFERRED_RELAY.to_owned(),
+    ))
+}
+
+/// The proxy of the sessions with the peer, "[scheme://][username[:password]@]host:port",
+/// "direct" to connect without the proxy, or empty for the global one.
+pub fn main_set_peer_proxy(id: String, proxy: String) {
+    set_peer_option(id, hbb_common::proxy::OPTION_PROXY.to_owned(), proxy)
+}
+
+pub fn main_get_peer_proxy(id: String) -> SyncReturn<String> {
+    SyncReturn(get_peer_option(
+        id,
+        hbb_common::proxy::OPTION_PROXY.to_owned(),
+    ))
+}
+
+pub fn cancel_async_job(job_id: u64) -> SyncReturn<bool> {
+    SyncReturn(flutter::async_tasks::cancel(job_id))
+}
+
 pub fn version_to_number(v: String) -> SyncReturn<i64> {
     SyncReturn(hbb_common::get_version_number(&v))
 }
@@ -1605,6 +2474,27 @@ pub fn option_synced() -> bool {
     crate::ui_interface::option_synced()
 }
 
+/// The recent log records as json, `level_filter` is like "info", all levels if invalid.
+pub fn get_recent_logs(level_filter: String, limit: usize) -> SyncReturn<String> {
+    let level = level_filter.parse().unwrap_or(hbb_common::log::LevelFilter::Trace);
+    let records = hbb_common::log_ring::recent(level, limit);
+    let records = records
+        .iter()
+        .map(flutter::log_record_to_json)
+        .collect::<Vec<_>>();
+    SyncReturn(serde_json::Value::Array(records).to_string())
+}
+
+/// The startup failures as json, also pushed as "startup_diagnostics" to the main event stream.
+pub fn get_startup_diagnostics() -> SyncReturn<String> {
+    SyncReturn(crate::common::get_startup_diagnostics())
+}
+
+/// The peer sessions closed by a panic since the start, see "session_panicked".
+pub fn get_session_crash_count() -> SyncReturn<u32> {
+    SyncReturn(crate::common::get_session_crashes())
+}
+
 pub fn main_is_installed() -> SyncReturn<bool> {
     SyncReturn(is_installed())
 }
@@ -1667,6 +2557,12 @@ pub fn set_cur_session_id(session_id: SessionID) {
     }
 }
 
+/// Record the focus of the ui session, the most recently focused one becomes the current one if
+/// the current one is closed.
+pub fn session_set_focused(session_id: SessionID) {
+    super::flutter::sessions::set_focused(&session_id);
+}
+
 fn set_cur_session_id_(session_id: SessionID, _keyboard_mode: &str) {
     super::flutter::set_cur_session_id(session_id);
     #[cfg(windows)]
@@ -1755,6 +2651,12 @@ pub fn cm_init() {
     crate::flutter::connection_manager::cm_init();
 }
 
+/// The foreground service is ready, called on android only.
+pub fn cm_service_ready() {
+    #[cfg(target_os = "android")]
+    crate::flutter::connection_manager::on_service_ready();
+}
+
 /// Start an ipc server for receiving the url scheme.
 ///
 /// * Should only be called in the main flutter window.
@@ -56,6 +56,16 @@ pub enum FS {
         path: String,
         id: i32,
     },
+    Rename {
+        path: String,
+        id: i32,
+        new_name: String,
+    },
+    RemovePath {
+        path: String,
+        id: i32,
+        recursive: bool,
+    },
     NewWrite {
         path: String,
         id: i32,
@@ -64,6 +74,7 @@ pub enum FS {
         overwrite_detection: bool,
         total_size: u64,
         conn_id: i32,
+        streams: u32,
     },
     CancelWrite {
         id: i32,
@@ -73,6 +84,8 @@ pub enum FS {
         file_num: i32,
         data: Bytes,
         compressed: bool,
+        offset: u64,
+        stream: u32,
     },
     WriteDone {
         id: i32,
@@ -171,15 +184,47 @@ pub enum Data {
         restart: bool,
         recording: bool,
         block_input: bool,
+        annotation: bool,
         from_switch: bool,
+        // "permanent", "temporary" or "one-time", empty if not authorized by password
+        password_form: String,
     },
     ChatMessage {
         text: String,
     },
+    // The chat started by the cm, answered by `ChatDelivery`.
+    CmChatMessage {
+        seq: u64,
+        text: String,
+    },
+    ChatDelivery {
+        seq: u64,
+        error: String,
+    },
+    // The cm watches the frames sent to the connection, answered by `CmWatchFrame`.
+    CmWatch(bool),
+    // The base64 jpeg of a frame, downscaled.
+    CmWatchFrame {
+        display: i32,
+        width: usize,
+        height: usize,
+        data: String,
+    },
     SwitchPermission {
         name: String,
         enabled: bool,
     },
+    // The view-only state of the connection, to the cm.
+    ViewOnly(bool),
+    // The keyboard level of the connection, see `input_policy::KeyboardLevel`.
+    KeyboardLevel(String),
+    // The peer asks to allow the input again, answered by `ViewOnlyOffResponse`.
+    ViewOnlyOffRequest,
+    ViewOnlyOffResponse(bool),
+    // The connection captures no video, to the cm.
+    AudioOnly(bool),
+    // The session is recorded by the host, see `server::session_recording`, to the cm.
+    SessionRecording(bool),
     SystemInfo(Option<String>),
     ClickTime(i64),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -227,9 +272,26 @@ pub enum Data {
     #[cfg(windows)]
     SyncWinCpuUsage(Option<f64>),
     FileTransferLog((String, String)),
+    // (action, log) of the actions requested by the peer, shown in the CM.
+    ActionLog((String, String)),
+    // The category of the event notifying the local user, see `ui_cm_interface::notify`.
+    CmNotify(String),
+    // (option, mode) of the notification policy changed in the main window.
+    CmNotifyPolicy((String, String)),
+    // (permission name, timed) of the permission granted for a while by the cm.
+    TimedPermission((String, bool)),
+    // The ipc to the cm exited, sent to the connection by itself.
+    CmExited,
     #[cfg(windows)]
     ControlledSessionCount(usize),
     CmErr(String),
+    // (plugin, action) of the action blocked by the cm hooks.
+    // The connection is rejected if the action is "connection".
+    #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    CmHookBlocked((String, String)),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    Automation(crate::automation::Automation),
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -404,6 +466,8 @@ async fn handle(data: Data, stream: &mut Connection) {
                     } else {
                         None
                     };
+                } else if crate::auth_2fa::PROTECTED_OPTIONS.contains(&name.as_str()) {
+                    value = Some(Config::get_option(&name));
                 } else if name == "hide_cm" {
                     value = if crate::hbbs_http::sync::is_pro() {
                         Some(hbb_common::password_security::hide_cm().to_string())
@@ -425,6 +489,12 @@ async fn handle(data: Data, stream: &mut Connection) {
                     Config::set_permanent_password(&value);
                 } else if name == "salt" {
                     Config::set_salt(&value);
+                } else if crate::auth_2fa::PROTECTED_OPTIONS.contains(&name.as_str()) {
+                    if !crate::auth_2fa::allow_change(&name, &value) {
+                        log::warn!("{} is not changed while the device is remote controlled", name);
+                        return;
+                    }
+                    Config::set_option(name.clone(), value);
                 } else {
                     return;
                 }
@@ -436,11 +506,12 @@ async fn handle(data: Data, stream: &mut Connection) {
                 let v = Config::get_options();
                 allow_err!(stream.send(&Data::Options(Some(v))).await);
             }
-            Some(value) => {
+            Some(mut value) => {
                 let _chk = CheckIfRestart::new();
                 if let Some(v) = value.get("privacy-mode-impl-key") {
                     crate::privacy_mode::switch(v);
                 }
+                crate::auth_2fa::keep_protected_options(&mut value);
                 Config::set_options(value);
                 allow_err!(stream.send(&Data::Options(None)).await);
             }
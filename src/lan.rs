@@ -90,6 +90,104 @@ pub fn send_wol(id: String) {
     }
 }
 
+/// The option of the host to broadcast the magic packets for the peers, see [`relay_wol`].
+pub const OPTION_ENABLE_REMOTE_WOL: &str = "enable-remote-wol";
+pub const WOL_DENIED: &str = "wol_denied_tip";
+pub const WOL_INVALID_MAC: &str = "wol_invalid_mac_tip";
+pub const WOL_SEND_FAILED: &str = "wol_send_failed_tip";
+const WOL_PORT: u16 = 9;
+
+/// "aa:bb:cc:dd:ee:ff", "aa-bb-cc-dd-ee-ff" or "aabbccddeeff", case insensitive.
+pub fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mac = mac.trim();
+    if !mac.is_ascii() {
+        return None;
+    }
+    let parts: Vec<&str> = if mac.contains(':') {
+        mac.split(':').collect()
+    } else if mac.contains('-') {
+        mac.split('-').collect()
+    } else if mac.len() == 12 {
+        (0..6).map(|i| &mac[i * 2..i * 2 + 2]).collect()
+    } else {
+        return None;
+    };
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut bytes = [0u8; 6];
+    for (b, part) in bytes.iter_mut().zip(parts) {
+        if part.len() != 2 || !part.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        *b = u8::from_str_radix(part, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn magic_packet(mac: &[u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+/// The (local address, broadcast address) of each network of `networks`, i.e. (address, netmask),
+/// to send the magic packets on.
+fn broadcast_addrs(networks: &[(Ipv4Addr, Ipv4Addr)]) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+    let mut addrs: Vec<(Ipv4Addr, Ipv4Addr)> = vec![];
+    for (addr, netmask) in networks {
+        if addr.is_loopback() || addr.is_link_local() || addr.is_unspecified() {
+            continue;
+        }
+        // The point-to-point links have no broadcast, e.g. the VPNs.
+        if u32::from(*netmask).count_ones() >= 31 {
+            continue;
+        }
+        let broadcast = Ipv4Addr::from(u32::from(*addr) | !u32::from(*netmask));
+        if !addrs.iter().any(|(_, b)| *b == broadcast) {
+            addrs.push((*addr, broadcast));
+        }
+    }
+    addrs
+}
+
+/// Broadcast the magic packet of `mac` on the networks of the host, for the peer asking to wake
+/// another one sleeping on them. Returns the number of the networks sent on, or the tip of the
+/// failure.
+pub fn relay_wol(mac: &str) -> Result<usize, &'static str> {
+    let mac = parse_mac(mac).ok_or(WOL_INVALID_MAC)?;
+    let networks: Vec<_> = default_net::get_interfaces()
+        .iter()
+        .flat_map(|i| i.ipv4.iter().map(|n| (n.addr, n.netmask)))
+        .collect();
+    let packet = magic_packet(&mac);
+    let mut sent = 0;
+    for (local, broadcast) in broadcast_addrs(&networks) {
+        let res = UdpSocket::bind((local, 0)).and_then(|socket| {
+            socket.set_broadcast(true)?;
+            socket.send_to(&packet, (broadcast, WOL_PORT))
+        });
+        match res {
+            Ok(_) => sent += 1,
+            Err(e) => log::warn!("Failed to send wol to {} from {}: {}", broadcast, local, e),
+        }
+    }
+    log::info!("Relay wol to {} on {} networks", format_mac(&mac), sent);
+    if sent == 0 {
+        return Err(WOL_SEND_FAILED);
+    }
+    Ok(sent)
+}
+
 #[inline]
 fn get_broadcast_port() -> u16 {
     (RENDEZVOUS_PORT + 3) as _
@@ -312,3 +410,72 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mac() {
+        let mac = [0xaa, 0xbb, 0xcc, 0x01, 0x02, 0xff];
+        for s in [
+            "aa:bb:cc:01:02:ff",
+            "AA-BB-CC-01-02-FF",
+            "aabbcc0102ff",
+            " aa:bb:cc:01:02:ff ",
+        ] {
+            assert_eq!(parse_mac(s), Some(mac), "{}", s);
+        }
+        assert_eq!(format_mac(&mac), "aa:bb:cc:01:02:ff");
+        let invalid = [
+            "",
+            "aa:bb:cc:01:02",
+            "aa:bb:cc:01:02:ff:00",
+            "aa:bb:cc:01:02:fg",
+            "aa:bb:cc:01:2:fff",
+            "aa:bb-cc:01:02:ff",
+            "+a:bb:cc:01:02:ff",
+            "aabbcc0102f",
+            "ääbbcc0102ff",
+        ];
+        for s in invalid {
+            assert_eq!(parse_mac(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_magic_packet() {
+        let mac = [1, 2, 3, 4, 5, 6];
+        let packet = magic_packet(&mac);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(packet[..6], [0xFF; 6]);
+        assert!(packet[6..].chunks(6).all(|c| c == mac));
+    }
+
+    #[test]
+    fn test_broadcast_addrs() {
+        let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+        let networks = [
+            (ip("127.0.0.1"), ip("255.0.0.0")),
+            (ip("169.254.3.4"), ip("255.255.0.0")),
+            (ip("192.168.1.20"), ip("255.255.255.0")),
+            // Another address on the same network.
+            (ip("192.168.1.21"), ip("255.255.255.0")),
+            (ip("10.8.0.2"), ip("255.255.255.255")),
+            (ip("10.1.2.3"), ip("255.255.240.0")),
+        ];
+        assert_eq!(
+            broadcast_addrs(&networks),
+            vec![
+                (ip("192.168.1.20"), ip("192.168.1.255")),
+                (ip("10.1.2.3"), ip("10.1.15.255")),
+            ]
+        );
+        assert!(broadcast_addrs(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_relay_wol_invalid_mac() {
+        assert_eq!(relay_wol("not a mac"), Err(WOL_INVALID_MAC));
+    }
+}
@@ -209,5 +209,136 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("input_source_1_tip", "Input source 1"),
         ("input_source_2_tip", "Input source 2"),
         ("capture_display_elevated_connections_tip", "Capturing multiple displays is not supported in the elevated user mode. Please try again after installation if you want to control multiple displays."),
+        ("peer_session_limit_reached_tip", "The maximum number of concurrent remote sessions is reached, please close a session and try again."),
+        ("ui_session_limit_reached_tip", "The maximum number of windows for this remote session is reached, please close one and try again."),
+        ("fingerprint_mismatch_tip", "The fingerprint of the remote device has changed since the last connection. It may have been reinstalled, or the connection may be intercepted. Only continue if you trust the new fingerprint."),
+        ("2fa_local_only_tip", "Disabling 2FA and generating recovery codes are not allowed while the device is remote controlled, please confirm on this device after the remote sessions are closed."),
+        ("startup_config_dir_tip", "The configuration directory is not writable, please check its permissions and the free disk space."),
+        ("startup_ipc_tip", "Failed to start the local service, please make sure no other instance is running, or restart the computer."),
+        ("startup_elevation_tip", "Failed to check the administrator privileges, please run as administrator or install the app."),
+        ("startup_portable_service_tip", "Failed to start the portable service, some features may be unavailable before installation."),
+        ("host_display_waking_tip", "Waking up the remote display, please wait..."),
+        ("view_only_off_wait_tip", "Waiting for the remote side to allow the input..."),
+        ("view_only_off_declined_tip", "The remote side keeps the session view-only."),
+        ("view_only_blocked_tip", "The input is not sent in the view-only session."),
+        ("view_only_off_request_tip", "The peer asks to control this device, which is view-only now."),
+        ("peer_cannot_change_resolution_tip", "The remote device does not support changing the resolution, e.g. on Wayland."),
+        ("peer_cannot_inject_input_tip", "The remote device can not receive the input, e.g. on Wayland without root permission."),
+        ("remote_restart_denied_tip", "The remote device does not allow you to restart it."),
+        ("remote_restart_timeout_tip", "The remote device does not come back after restarting, please try to connect later."),
+        ("remote_sysinfo_unsupported_tip", "The remote version does not support the system information."),
+        ("remote_sysinfo_denied_tip", "The remote side does not allow to view the system information."),
+        ("remote_offline_tip", "Remote desktop is offline"),
+        ("key_mismatch_tip", "Key mismatch"),
+        ("connection_refused_tip", "The connection is refused by the remote side: {error}"),
+        ("direct_refused_tip", "The connection to {addr} is refused, please check the address and the port, and that direct IP access is enabled on the remote side: {error}"),
+        ("direct_timeout_tip", "The connection to {addr} timed out, please check the address and the network: {error}"),
+        ("direct_wrong_service_tip", "{addr} is not the direct IP access service of the remote side, please check the port"),
+        ("cm_notify_connection_accepted", "Connection accepted"),
+        ("cm_notify_file_transfer_started", "File transfer started"),
+        ("cm_notify_clipboard_received", "Clipboard received"),
+        ("cm_notify_chat_message", "New chat message"),
+        ("service_notification_failed_tip", "The notification could not be shown, please accept the connection in the app."),
+        ("Downscale frames", ""),
+        ("Disable clipboard read", ""),
+        ("clipboard_read_blocked_tip", "The clipboard is not read for this session."),
+        ("clipboard_truncated_tip", "The clipboard received is too large and truncated."),
+        ("Restore previous sessions", ""),
+        ("Restore sessions after restart", ""),
+        ("restore_sessions_tip", "The sessions open when the app was closed last time can be restored, the offline peers are skipped."),
+        ("restore_session_offline_tip", "The peer is offline, not restored"),
+        ("restore_session_timeout_tip", "The session is not connected in time, restoring the next one"),
+        ("access_schedule_rejected_tip", "The unattended access to the remote device is not allowed at this time by its access schedule."),
+        ("access_schedule_ending_tip", "The access schedule of the remote device has ended, this session will be closed after the grace period."),
+        ("access_schedule_last_minute_tip", "This session will be closed within a minute by the access schedule of the remote device."),
+        ("Session closed as not allowed outside the access schedule", ""),
+        ("capture_reduced_tip", "The network is congested, only the active display is captured now. Show all displays again to override."),
+        ("capture_restored_tip", "The network has recovered, all the displays are captured again."),
+        ("relay_fallback_tip", "The preferred relay server is not reachable, the default one is used."),
+        ("Forward print jobs", ""),
+        ("Print job received", ""),
+        ("Print", ""),
+        ("print_job_receiving_tip", "Receiving the job printed on the remote device."),
+        ("print_job_received_tip", "The job printed on the remote device is received, print it on the local printer?"),
+        ("print_job_failed_tip", "Failed to receive the print job"),
+        ("Enable remote printer", ""),
+        ("Remote volume", ""),
+        ("Unmute", ""),
+        ("Enable remote volume control", ""),
+        ("remote_volume_unsupported_tip", "The remote side does not support controlling its volume."),
+        ("remote_volume_denied_tip", "The remote side does not allow to control its volume."),
+        ("job_renamed_files_tip", "Some files were renamed to be valid on this system"),
+        ("Streams", ""),
+        ("Force software decoding", ""),
+        ("Decoder", ""),
+        ("Forced", ""),
+        ("Initialization failed", ""),
+        ("staging_evicted_tip", "Some received files were removed to keep the staging area within its size limit"),
+        ("Enable remote annotation", ""),
+        ("Annotation is not allowed", ""),
+        ("Annotation is not supported on this platform", ""),
+        ("No such display", ""),
+        ("Invalid stroke", ""),
+        ("Enable remote Wake-on-LAN", ""),
+        ("wol_sent_tip", "The Wake-on-LAN packets were sent"),
+        ("wol_online_tip", "The woken device is online"),
+        ("wol_timeout_tip", "The woken device did not come online"),
+        ("wol_denied_tip", "Wake-on-LAN is not allowed by the relay device"),
+        ("wol_invalid_mac_tip", "Invalid MAC address"),
+        ("wol_send_failed_tip", "The relay device failed to send the Wake-on-LAN packets"),
+        ("wol_relay_offline_tip", "The relay device is not connected"),
+        ("wol_unsupported_tip", "The relay device does not support Wake-on-LAN"),
+        ("keyboard_level_full", "All keys"),
+        ("keyboard_level_no-system-keys", "No system keys"),
+        ("keyboard_level_text-only", "Text only"),
+        ("Keyboard restricted", ""),
+        ("Keys dropped", ""),
+        ("Power off the displays", ""),
+        ("privacy_mode_display_off_failed_tip", "Entered privacy mode, but some displays may not be powered off."),
+        ("privacy_mode_display_off_unsupported_tip", "Entered privacy mode, but powering off the displays is not supported."),
+        ("Lower the frame rate when busy", ""),
+        ("Prioritize quality", ""),
+        ("host_throttled_tip", "the busy host lowers the frame rate to"),
+        ("Failed to send the message", ""),
+        ("peer_data_truncated_tip", "The data of the remote device is too large or invalid, it's truncated"),
+        ("Watch", ""),
+        ("Stop watching", ""),
+        ("Failed to watch the session", ""),
+        ("session_recorded_notice", "This session is recorded by the remote device."),
+        ("Session recorded", ""),
+        ("Reusing the existing connection", ""),
+        ("session_panicked_tip", "An internal error occurred and the session is closed, please reconnect. Please report the error below if it persists."),
+        ("Measure latency", ""),
+        ("Capture to receive", ""),
+        ("Receive to decode", ""),
+        ("Decode to render", ""),
+        ("Please enter the new name", ""),
+        ("proxy_auth_failed", "The proxy rejected the authentication, please check the username and password of the proxy: {error}"),
+        ("proxy_unreachable", "Failed to connect to the proxy, please check the proxy settings: {error}"),
+        ("No proxy for", ""),
+        ("Resolve host names locally", ""),
+        ("color_accuracy_bitrate_tip", "True color needs more bandwidth, the bitrate may go up"),
+        ("color_mode_fallback_codec_tip", "The current codec does not support true color, the colors are subsampled"),
+        ("color_mode_fallback_host_tip", "The remote side keeps the colors subsampled for this display"),
+        ("close_active_transfers_tip", "File transfers in progress will be interrupted"),
+        ("close_active_recording_tip", "The screen recording will be stopped"),
+        ("close_active_voice_call_tip", "The voice call will be ended"),
+        ("Keep transfers in background", ""),
+        ("Reopen", ""),
+        ("background_session_done_tip", "The file transfers kept in the background are finished."),
+        ("background_session_disconnected_tip", "The connection kept in the background for the file transfers is closed before they finished."),
+        ("Trust and continue", ""),
+        ("Enable 2FA", ""),
+        ("enable_2fa_tip", "Add the secret below to your authenticator app, by the key or the link, then enter the 6-digit code it shows."),
+        ("2fa_code_tip", "6-digit code or recovery code"),
+        ("Disable 2FA", ""),
+        ("disable_2fa_tip", "The unattended access by the permanent password will no longer require the code of the authenticator app."),
+        ("Generate recovery codes", ""),
+        ("2fa_recovery_codes_tip", "Keep these codes in a safe place. Each code can be used once instead of the authenticator code. They are only shown once, the previous codes no longer work."),
+        ("Copy", ""),
+        ("Connect view-only", ""),
+        ("peer_acl_invalid_tip", "The access control rules of the peers are invalid, all the peers are denied the keyboard, clipboard, file transfer and the other permissions until they are reset."),
+        ("Reset", ""),
+        ("remote_restart_reconnecting_tip", "Remote device is restarting, it will be reconnected after a while. The permanent password is required if the temporary one changes."),
     ].iter().cloned().collect();
 }
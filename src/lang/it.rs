@@ -576,5 +576,143 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("input_source_1_tip", "Sorgente ingresso (1)"),
         ("input_source_2_tip", "Sorgente ingresso (2)"),
         ("capture_display_elevated_connections_tip", "La cattura di più display non è supportata nella modalità utente con privilegi elevati. Se vuoi controllare più display riprova dopo l'installazione."),
+        ("peer_session_limit_reached_tip", ""),
+        ("ui_session_limit_reached_tip", ""),
+        ("Fingerprint mismatch", ""),
+        ("fingerprint_mismatch_tip", ""),
+        ("2FA Required", ""),
+        ("Wrong 2FA Code", ""),
+        ("Too many wrong 2FA attempts", ""),
+        ("2fa_local_only_tip", ""),
+        ("startup_config_dir_tip", ""),
+        ("startup_ipc_tip", ""),
+        ("startup_elevation_tip", ""),
+        ("startup_portable_service_tip", ""),
+        ("Allow remote display to sleep", ""),
+        ("host_display_waking_tip", ""),
+        ("View-only", ""),
+        ("view_only_off_wait_tip", ""),
+        ("view_only_off_declined_tip", ""),
+        ("view_only_blocked_tip", ""),
+        ("view_only_off_request_tip", ""),
+        ("peer_cannot_change_resolution_tip", ""),
+        ("peer_cannot_inject_input_tip", ""),
+        ("remote_restart_denied_tip", ""),
+        ("remote_restart_timeout_tip", ""),
+        ("remote_sysinfo_unsupported_tip", ""),
+        ("remote_sysinfo_denied_tip", ""),
+        ("Enable remote system information", ""),
+        ("remote_offline_tip", ""),
+        ("key_mismatch_tip", ""),
+        ("connection_refused_tip", ""),
+        ("direct_refused_tip", ""),
+        ("direct_timeout_tip", ""),
+        ("direct_wrong_service_tip", ""),
+        ("cm_notify_connection_accepted", ""),
+        ("cm_notify_file_transfer_started", ""),
+        ("cm_notify_clipboard_received", ""),
+        ("cm_notify_chat_message", ""),
+        ("service_notification_failed_tip", ""),
+        ("Downscale frames", ""),
+        ("Disable clipboard read", ""),
+        ("clipboard_read_blocked_tip", ""),
+        ("clipboard_truncated_tip", ""),
+        ("Restore previous sessions", ""),
+        ("Restore sessions after restart", ""),
+        ("restore_sessions_tip", ""),
+        ("restore_session_offline_tip", ""),
+        ("restore_session_timeout_tip", ""),
+        ("access_schedule_rejected_tip", ""),
+        ("access_schedule_ending_tip", ""),
+        ("access_schedule_last_minute_tip", ""),
+        ("Session closed as not allowed outside the access schedule", ""),
+        ("capture_reduced_tip", ""),
+        ("capture_restored_tip", ""),
+        ("relay_fallback_tip", ""),
+        ("Forward print jobs", ""),
+        ("Print job received", ""),
+        ("Print", ""),
+        ("print_job_receiving_tip", ""),
+        ("print_job_received_tip", ""),
+        ("print_job_failed_tip", ""),
+        ("Enable remote printer", ""),
+        ("Remote volume", ""),
+        ("Unmute", ""),
+        ("Enable remote volume control", ""),
+        ("remote_volume_unsupported_tip", ""),
+        ("remote_volume_denied_tip", ""),
+        ("job_renamed_files_tip", ""),
+        ("Streams", ""),
+        ("Force software decoding", ""),
+        ("Decoder", ""),
+        ("Forced", ""),
+        ("Initialization failed", ""),
+        ("staging_evicted_tip", ""),
+        ("Enable remote annotation", ""),
+        ("Annotation is not allowed", ""),
+        ("Annotation is not supported on this platform", ""),
+        ("No such display", ""),
+        ("Invalid stroke", ""),
+        ("Enable remote Wake-on-LAN", ""),
+        ("wol_sent_tip", ""),
+        ("wol_online_tip", ""),
+        ("wol_timeout_tip", ""),
+        ("wol_denied_tip", ""),
+        ("wol_invalid_mac_tip", ""),
+        ("wol_send_failed_tip", ""),
+        ("wol_relay_offline_tip", ""),
+        ("wol_unsupported_tip", ""),
+        ("keyboard_level_full", ""),
+        ("keyboard_level_no-system-keys", ""),
+        ("keyboard_level_text-only", ""),
+        ("Keyboard restricted", ""),
+        ("Keys dropped", ""),
+        ("Power off the displays", ""),
+        ("privacy_mode_display_off_failed_tip", ""),
+        ("privacy_mode_display_off_unsupported_tip", ""),
+        ("Lower the frame rate when busy", ""),
+        ("Prioritize quality", ""),
+        ("host_throttled_tip", ""),
+        ("Failed to send the message", ""),
+        ("peer_data_truncated_tip", ""),
+        ("Watch", ""),
+        ("Stop watching", ""),
+        ("Failed to watch the session", ""),
+        ("session_recorded_notice", ""),
+        ("Session recorded", ""),
+        ("Reusing the existing connection", ""),
+        ("session_panicked_tip", ""),
+        ("Measure latency", ""),
+        ("Capture to receive", ""),
+        ("Receive to decode", ""),
+        ("Decode to render", ""),
+        ("Please enter the new name", ""),
+        ("proxy_auth_failed", ""),
+        ("proxy_unreachable", ""),
+        ("No proxy for", ""),
+        ("Resolve host names locally", ""),
+        ("color_accuracy_bitrate_tip", ""),
+        ("color_mode_fallback_codec_tip", ""),
+        ("color_mode_fallback_host_tip", ""),
+        ("close_active_transfers_tip", ""),
+        ("close_active_recording_tip", ""),
+        ("close_active_voice_call_tip", ""),
+        ("Keep transfers in background", ""),
+        ("Reopen", ""),
+        ("background_session_done_tip", ""),
+        ("background_session_disconnected_tip", ""),
+        ("Trust and continue", ""),
+        ("Enable 2FA", ""),
+        ("enable_2fa_tip", ""),
+        ("2fa_code_tip", ""),
+        ("Disable 2FA", ""),
+        ("disable_2fa_tip", ""),
+        ("Generate recovery codes", ""),
+        ("2fa_recovery_codes_tip", ""),
+        ("Copy", ""),
+        ("Connect view-only", ""),
+        ("peer_acl_invalid_tip", ""),
+        ("Reset", ""),
+        ("remote_restart_reconnecting_tip", ""),
     ].iter().cloned().collect();
 }
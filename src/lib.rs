@@ -11,6 +11,8 @@ mod server;
 pub use self::server::*;
 mod client;
 #[cfg(not(any(target_os = "ios")))]
+mod auth_2fa;
+#[cfg(not(any(target_os = "ios")))]
 mod lan;
 #[cfg(not(any(target_os = "ios")))]
 mod rendezvous_mediator;
@@ -44,6 +46,8 @@ mod lang;
 mod license;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod automation;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -63,5 +67,7 @@ pub mod clipboard_file;
 
 pub mod privacy_mode;
 
+pub mod state_journal;
+
 #[cfg(all(windows, feature = "virtual_display_driver"))]
 pub mod virtual_display_manager;
@@ -33,6 +33,7 @@ use wallpaper;
 type Xdo = *const c_void;
 
 pub const PA_SAMPLE_RATE: u32 = 48000;
+const WAKE_DISPLAY_TIMEOUT: Duration = Duration::from_secs(5);
 static mut UNMODIFIED: bool = true;
 
 lazy_static::lazy_static! {
@@ -819,76 +820,105 @@ fn get_xrandr_conn_pat(name: &str) -> String {
     )
 }
 
-pub fn resolutions(name: &str) -> Vec<Resolution> {
+// The modes of the output, and if it's the current one, one for each refresh rate.
+fn xrandr_modes(name: &str, xrandr_output: &str) -> Vec<(Resolution, bool)> {
     let resolutions_pat = r"(?P<resolutions>(\s*\d+x\d+\s+\d+.*\n)+)";
     let connected_pat = get_xrandr_conn_pat(name);
     let mut v = vec![];
-    if let Ok(re) = Regex::new(&format!("{}{}", connected_pat, resolutions_pat)) {
-        match run_cmds("xrandr --query | tr -s ' '") {
-            Ok(xrandr_output) => {
-                // There'are different kinds of xrandr output.
-                /*
-                1.
-                Screen 0: minimum 320 x 175, current 1920 x 1080, maximum 1920 x 1080
-                default connected 1920x1080+0+0 0mm x 0mm
-                 1920x1080 10.00*
-                 1280x720 25.00
-                 1680x1050 60.00
-                Virtual2 disconnected (normal left inverted right x axis y axis)
-                Virtual3 disconnected (normal left inverted right x axis y axis)
-
-                Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
-                eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm
-                1920x1080     60.01*+  60.01    59.97    59.96    59.93
-                1680x1050     59.95    59.88
-                1600x1024     60.17
-
-                XWAYLAND0 connected primary 1920x984+0+0 (normal left inverted right x axis y axis) 0mm x 0mm
-                Virtual1 connected primary 1920x984+0+0 (normal left inverted right x axis y axis) 0mm x 0mm
-                HDMI-0 connected (normal left inverted right x axis y axis)
-
-                rdp0 connected primary 1920x1080+0+0 0mm x 0mm
-                    */
-                if let Some(caps) = re.captures(&xrandr_output) {
-                    if let Some(resolutions) = caps.name("resolutions") {
-                        let resolution_pat =
-                            r"\s*(?P<width>\d+)x(?P<height>\d+)\s+(?P<rates>(\d+\.\d+\D*)+)\s*\n";
-                        let Ok(resolution_re) = Regex::new(&format!(r"{}", resolution_pat)) else {
-                            log::error!("Regex new failed");
-                            return vec![];
-                        };
-                        for resolution_caps in resolution_re.captures_iter(resolutions.as_str()) {
-                            if let Some((width, height)) =
-                                get_width_height_from_captures(&resolution_caps)
-                            {
-                                let resolution = Resolution {
-                                    width,
-                                    height,
-                                    ..Default::default()
-                                };
-                                if !v.contains(&resolution) {
-                                    v.push(resolution);
-                                }
-                            }
-                        }
+    let Ok(re) = Regex::new(&format!("{}{}", connected_pat, resolutions_pat)) else {
+        return v;
+    };
+    // There'are different kinds of xrandr output.
+    /*
+    1.
+    Screen 0: minimum 320 x 175, current 1920 x 1080, maximum 1920 x 1080
+    default connected 1920x1080+0+0 0mm x 0mm
+     1920x1080 10.00*
+     1280x720 25.00
+     1680x1050 60.00
+    Virtual2 disconnected (normal left inverted right x axis y axis)
+    Virtual3 disconnected (normal left inverted right x axis y axis)
+
+    Screen 0: minimum 320 x 200, current 1920 x 1080, maximum 16384 x 16384
+    eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm
+    1920x1080     60.01*+  60.01    59.97    59.96    59.93
+    1680x1050     59.95    59.88
+    1600x1024     60.17
+
+    XWAYLAND0 connected primary 1920x984+0+0 (normal left inverted right x axis y axis) 0mm x 0mm
+    Virtual1 connected primary 1920x984+0+0 (normal left inverted right x axis y axis) 0mm x 0mm
+    HDMI-0 connected (normal left inverted right x axis y axis)
+
+    rdp0 connected primary 1920x1080+0+0 0mm x 0mm
+        */
+    if let Some(caps) = re.captures(xrandr_output) {
+        if let Some(resolutions) = caps.name("resolutions") {
+            let resolution_pat =
+                r"\s*(?P<width>\d+)x(?P<height>\d+)\s+(?P<rates>(\d+\.\d+\D*)+)\s*\n";
+            let Ok(resolution_re) = Regex::new(&format!(r"{}", resolution_pat)) else {
+                log::error!("Regex new failed");
+                return vec![];
+            };
+            for resolution_caps in resolution_re.captures_iter(resolutions.as_str()) {
+                let Some((width, height)) = get_width_height_from_captures(&resolution_caps) else {
+                    continue;
+                };
+                // "60.01*+ 59.97", the current one is marked by "*".
+                let rates = resolution_caps.name("rates").map_or("", |r| r.as_str());
+                for rate in rates.split_whitespace() {
+                    let current = rate.contains('*');
+                    let Ok(rate) = rate
+                        .trim_end_matches(|c| c == '*' || c == '+')
+                        .parse::<f64>()
+                    else {
+                        continue;
+                    };
+                    let mode = (
+                        Resolution {
+                            width,
+                            height,
+                            refresh_rate: rate.round() as _,
+                            ..Default::default()
+                        },
+                        current,
+                    );
+                    match v.iter_mut().find(|(r, _)| *r == mode.0) {
+                        Some((_, c)) => *c |= current,
+                        None => v.push(mode),
                     }
                 }
             }
-            Err(e) => log::error!("Failed to run xrandr query, {}", e),
         }
     }
-
     v
 }
 
+pub fn resolutions(name: &str) -> Vec<Resolution> {
+    match run_cmds("xrandr --query | tr -s ' '") {
+        Ok(xrandr_output) => xrandr_modes(name, &xrandr_output)
+            .into_iter()
+            .map(|(r, _)| r)
+            .collect(),
+        Err(e) => {
+            log::error!("Failed to run xrandr query, {}", e);
+            vec![]
+        }
+    }
+}
+
 pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     let xrandr_output = run_cmds("xrandr --query | tr -s ' '")?;
     let re = Regex::new(&get_xrandr_conn_pat(name))?;
     if let Some(caps) = re.captures(&xrandr_output) {
         if let Some((width, height)) = get_width_height_from_captures(&caps) {
+            let refresh_rate = xrandr_modes(name, &xrandr_output)
+                .into_iter()
+                .find(|(r, current)| *current && r.width == width && r.height == height)
+                .map_or(0, |(r, _)| r.refresh_rate);
             return Ok(Resolution {
                 width,
                 height,
+                refresh_rate,
                 ..Default::default()
             });
         }
@@ -896,15 +926,19 @@ pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     bail!("Failed to find current resolution for {}", name);
 }
 
-pub fn change_resolution_directly(name: &str, width: usize, height: usize) -> ResultType<()> {
-    Command::new("xrandr")
-        .args(vec![
-            "--output",
-            name,
-            "--mode",
-            &format!("{}x{}", width, height),
-        ])
-        .spawn()?;
+pub fn change_resolution_directly(
+    name: &str,
+    width: usize,
+    height: usize,
+    refresh_rate: usize,
+) -> ResultType<()> {
+    let mode = format!("{}x{}", width, height);
+    let rate = refresh_rate.to_string();
+    let mut args = vec!["--output", name, "--mode", &mode];
+    if refresh_rate > 0 {
+        args.extend(["--rate", &rate]);
+    }
+    Command::new("xrandr").args(args).spawn()?;
     Ok(())
 }
 
@@ -1211,6 +1245,44 @@ impl WakeLock {
     }
 }
 
+/// Force the displays on by DPMS and wait until they are on, X11 only.
+pub fn wake_display() -> ResultType<()> {
+    if !is_x11() {
+        return Ok(());
+    }
+    run_cmds("xset dpms force on; xset s reset")?;
+    let start = Instant::now();
+    while start.elapsed() < WAKE_DISPLAY_TIMEOUT {
+        // "Monitor is Off" or "Monitor is in Standby", missing if DPMS is disabled.
+        let output = run_cmds("xset q")?;
+        if !output.contains("Monitor is") || output.contains("Monitor is On") {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    bail!("The display is not on after {:?}", WAKE_DISPLAY_TIMEOUT);
+}
+
+/// The locked hint of the active session by logind, false if unknown.
+pub fn is_session_locked() -> bool {
+    let sid = get_values_of_seat0(&[0])[0].clone();
+    if sid.is_empty() {
+        return false;
+    }
+    std::process::Command::new("loginctl")
+        .args(["show-session", &sid, "-p", "LockedHint", "--value"])
+        .output()
+        .map_or(false, |o| String::from_utf8_lossy(&o.stdout).trim() == "yes")
+}
+
+/// The displays are off by DPMS, X11 only, false if unknown.
+pub fn is_display_off() -> bool {
+    if !is_x11() {
+        return false;
+    }
+    run_cmds("xset q").map_or(false, |o| o.contains("Monitor is") && !o.contains("Monitor is On"))
+}
+
 fn has_cmd(cmd: &str) -> bool {
     std::process::Command::new("which")
         .arg(cmd)
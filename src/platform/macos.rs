@@ -676,7 +676,13 @@ pub fn current_resolution(name: &str) -> ResultType<Resolution> {
     }
 }
 
-pub fn change_resolution_directly(name: &str, width: usize, height: usize) -> ResultType<()> {
+/// The refresh rate can't be chosen by `MacSetMode`, the system picks it.
+pub fn change_resolution_directly(
+    name: &str,
+    width: usize,
+    height: usize,
+    _refresh_rate: usize,
+) -> ResultType<()> {
     let display = name.parse::<u32>().map_err(|e| anyhow!(e))?;
     unsafe {
         if NO == MacSetMode(display, width as _, height as _) {
@@ -720,6 +726,42 @@ pub fn elevate(args: Vec<&str>, prompt: &str) -> ResultType<bool> {
     }
 }
 
+/// Declare the user activity, which turns on the sleeping displays.
+/// `caffeinate -u` is the IOPMAssertionDeclareUserActivity assertion, held for a few seconds.
+pub fn wake_display() -> ResultType<()> {
+    let mut child = std::process::Command::new("caffeinate")
+        .args(["-u", "-t", "5"])
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The screen is locked by the login window.
+pub fn is_session_locked() -> bool {
+    extern "C" {
+        fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+    }
+    unsafe {
+        let dict = CGSessionCopyCurrentDictionary();
+        if dict.is_null() {
+            return false;
+        }
+        let key = NSString::alloc(nil).init_str("CGSSessionScreenIsLocked");
+        let value: id = msg_send![dict as id, objectForKey: key];
+        let locked: BOOL = if value == nil { NO } else { msg_send![value, boolValue] };
+        let _: () = msg_send![key, release];
+        CFRelease(dict as _);
+        locked == YES
+    }
+}
+
+pub fn is_display_off() -> bool {
+    extern "C" {
+        fn CGDisplayIsAsleep(display: u32) -> u32;
+    }
+    unsafe { CGDisplayIsAsleep(CGMainDisplayID()) != 0 }
+}
+
 pub struct WakeLock(Option<keepawake::AwakeHandle>);
 
 impl WakeLock {
@@ -54,7 +54,13 @@ pub fn breakdown_callback() {
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
-pub fn change_resolution(name: &str, width: usize, height: usize) -> ResultType<()> {
+/// `refresh_rate` is 0 to keep the one chosen by the system.
+pub fn change_resolution(
+    name: &str,
+    width: usize,
+    height: usize,
+    refresh_rate: usize,
+) -> ResultType<()> {
     let cur_resolution = current_resolution(name)?;
     // For MacOS
     // to-do: Make sure the following comparison works.
@@ -63,11 +69,20 @@ pub fn change_resolution(name: &str, width: usize, height: usize) -> ResultType<
     // For Windows
     // dmPelsWidth and dmPelsHeight is the same to width and height
     // Because this process is running in dpi awareness mode.
-    if cur_resolution.width as usize == width && cur_resolution.height as usize == height {
+    if cur_resolution.width as usize == width
+        && cur_resolution.height as usize == height
+        && (refresh_rate == 0 || cur_resolution.refresh_rate as usize == refresh_rate)
+    {
         return Ok(());
     }
-    hbb_common::log::warn!("Change resolution of '{}' to ({},{})", name, width, height);
-    change_resolution_directly(name, width, height)
+    hbb_common::log::warn!(
+        "Change resolution of '{}' to ({},{}), refresh rate {}",
+        name,
+        width,
+        height,
+        refresh_rate
+    );
+    change_resolution_directly(name, width, height, refresh_rate)
 }
 
 // Android
@@ -154,7 +169,7 @@ mod tests {
     fn test_resolution() {
         let name = r"\\.\DISPLAY1";
         println!("current:{:?}", current_resolution(name));
-        println!("change:{:?}", change_resolution(name, 2880, 1800));
+        println!("change:{:?}", change_resolution(name, 2880, 1800, 0));
         println!("resolutions:{:?}", resolutions(name));
     }
 }
@@ -39,14 +39,14 @@ use winapi::{
             GetCurrentProcess, GetCurrentProcessId, GetExitCodeProcess, OpenProcess,
             OpenProcessToken, PROCESS_INFORMATION, STARTUPINFOW,
         },
-        securitybaseapi::GetTokenInformation,
+        securitybaseapi::{EqualSid, GetTokenInformation},
         shellapi::ShellExecuteW,
         winbase::*,
         wingdi::*,
         winnt::{
-            TokenElevation, ES_AWAYMODE_REQUIRED, ES_CONTINUOUS, ES_DISPLAY_REQUIRED,
+            TokenElevation, TokenUser, ES_AWAYMODE_REQUIRED, ES_CONTINUOUS, ES_DISPLAY_REQUIRED,
             ES_SYSTEM_REQUIRED, HANDLE, PROCESS_QUERY_LIMITED_INFORMATION, TOKEN_ELEVATION,
-            TOKEN_QUERY,
+            TOKEN_QUERY, TOKEN_USER,
         },
         winreg::HKEY_CURRENT_USER,
         winuser::*,
@@ -1571,6 +1571,57 @@ pub fn is_elevated(process_id: Option<DWORD>) -> ResultType<bool> {
     }
 }
 
+// The TOKEN_USER of the process, in the buffer aligned for it.
+unsafe fn get_token_user(process: HANDLE) -> ResultType<Vec<u64>> {
+    use hbb_common::platform::windows::RAIIHandle;
+    let mut token: HANDLE = mem::zeroed();
+    if OpenProcessToken(process, TOKEN_QUERY, &mut token) == FALSE {
+        bail!(
+            "Failed to open process token, error {}",
+            io::Error::last_os_error()
+        )
+    }
+    let _token = RAIIHandle(token);
+    let mut size: DWORD = 0;
+    GetTokenInformation(token, TokenUser, NULL, 0, &mut size);
+    let mut buf = vec![0u64; (size as usize + 7) / 8];
+    if size == 0
+        || GetTokenInformation(
+            token,
+            TokenUser,
+            buf.as_mut_ptr() as *mut c_void,
+            size,
+            &mut size,
+        ) == FALSE
+    {
+        bail!(
+            "Failed to get token information, error {}",
+            io::Error::last_os_error()
+        )
+    }
+    Ok(buf)
+}
+
+/// Whether the process is of the same user as the current process.
+pub fn is_process_of_current_user(process_id: DWORD) -> ResultType<bool> {
+    use hbb_common::platform::windows::RAIIHandle;
+    unsafe {
+        let current = get_token_user(GetCurrentProcess())?;
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, process_id);
+        if handle == NULL {
+            bail!(
+                "Failed to open process, error {}",
+                io::Error::last_os_error()
+            )
+        }
+        let _handle = RAIIHandle(handle);
+        let other = get_token_user(handle)?;
+        let current = &*(current.as_ptr() as *const TOKEN_USER);
+        let other = &*(other.as_ptr() as *const TOKEN_USER);
+        Ok(EqualSid(current.User.Sid, other.User.Sid) != FALSE)
+    }
+}
+
 pub fn is_foreground_window_elevated() -> ResultType<bool> {
     unsafe {
         let mut process_id: DWORD = 0;
@@ -1726,6 +1777,7 @@ pub fn resolutions(name: &str) -> Vec<Resolution> {
             let r = Resolution {
                 width: dm.dmPelsWidth as _,
                 height: dm.dmPelsHeight as _,
+                refresh_rate: dm.dmDisplayFrequency as _,
                 ..Default::default()
             };
             if !v.contains(&r) {
@@ -1751,6 +1803,7 @@ pub fn current_resolution(name: &str) -> ResultType<Resolution> {
         let r = Resolution {
             width: dm.dmPelsWidth as _,
             height: dm.dmPelsHeight as _,
+            refresh_rate: dm.dmDisplayFrequency as _,
             ..Default::default()
         };
         Ok(r)
@@ -1761,6 +1814,7 @@ pub(super) fn change_resolution_directly(
     name: &str,
     width: usize,
     height: usize,
+    refresh_rate: usize,
 ) -> ResultType<()> {
     let device_name = str_to_device_name(name);
     unsafe {
@@ -1769,6 +1823,10 @@ pub(super) fn change_resolution_directly(
         dm.dmPelsWidth = width as _;
         dm.dmPelsHeight = height as _;
         dm.dmFields = DM_PELSHEIGHT | DM_PELSWIDTH;
+        if refresh_rate > 0 {
+            dm.dmDisplayFrequency = refresh_rate as _;
+            dm.dmFields |= DM_DISPLAYFREQUENCY;
+        }
         let res = ChangeDisplaySettingsExW(
             device_name.as_ptr(),
             &mut dm,
@@ -1787,6 +1845,24 @@ pub(super) fn change_resolution_directly(
     }
 }
 
+/// The DPI scale factor of the monitor containing the point, e.g. 1.5 for 144 DPI.
+pub fn display_scale(x: i32, y: i32) -> Option<f64> {
+    use winapi::um::shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
+    unsafe {
+        let monitor = MonitorFromPoint(POINT { x, y }, MONITOR_DEFAULTTONULL);
+        if monitor.is_null() {
+            return None;
+        }
+        let (mut dpi_x, mut dpi_y) = (0, 0);
+        if GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) != S_OK
+            || dpi_x == 0
+        {
+            return None;
+        }
+        Some(dpi_x as f64 / 96.)
+    }
+}
+
 pub fn user_accessible_folder() -> ResultType<PathBuf> {
     let disk = std::env::var("SystemDrive").unwrap_or("C:".to_string());
     let dir1 = PathBuf::from(format!("{}\\ProgramData", disk));
@@ -2134,6 +2210,53 @@ impl Drop for WakeLock {
     }
 }
 
+/// Signal the user activity, which turns on the sleeping displays.
+pub fn wake_display() -> ResultType<()> {
+    unsafe {
+        // Without ES_CONTINUOUS, it only resets the idle timers.
+        SetThreadExecutionState(ES_DISPLAY_REQUIRED | ES_SYSTEM_REQUIRED);
+        // A zero relative move, the cursor stays where it is.
+        let mut input: INPUT = mem::zeroed();
+        input.type_ = INPUT_MOUSE;
+        input.u.mi_mut().dwFlags = MOUSEEVENTF_MOVE;
+        if SendInput(1, &mut input, mem::size_of::<INPUT>() as _) == 0 {
+            bail!("Failed to send input: {}", io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The input desktop is not the default one when the workstation is locked.
+pub fn is_session_locked() -> bool {
+    unsafe {
+        let desk = OpenInputDesktop(0, FALSE, DESKTOP_READOBJECTS);
+        if desk.is_null() {
+            // Access denied on the secure desktop.
+            return true;
+        }
+        let mut name = [0u16; 64];
+        let mut len: DWORD = 0;
+        let ok = GetUserObjectInformationW(
+            desk as _,
+            UOI_NAME as _,
+            name.as_mut_ptr() as _,
+            (name.len() * mem::size_of::<u16>()) as _,
+            &mut len,
+        );
+        CloseDesktop(desk);
+        if ok == FALSE {
+            return false;
+        }
+        let name = String::from_utf16_lossy(&name[..name.iter().position(|&c| c == 0).unwrap_or(0)]);
+        !name.eq_ignore_ascii_case("Default")
+    }
+}
+
+/// The display power state is only notified to windows, not probed, so it's always false.
+pub fn is_display_off() -> bool {
+    false
+}
+
 pub fn uninstall_service(show_new_window: bool) -> bool {
     log::info!("Uninstalling service...");
     let filter = format!(" /FI \"PID ne {}\"", get_current_pid());
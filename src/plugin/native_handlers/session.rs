@@ -105,7 +105,12 @@ impl PluginNativeHandler for PluginNativeSessionHandler {
                 if let Some(id) = data.get("id") {
                     if let Some(id) = id.as_str() {
                         let cb: OnSessionRgbaCallback = unsafe { std::mem::transmute(raw) };
-                        SESSION_HANDLER.add_session_hook(id.to_string(), cb);
+                        // The hooks with smaller priority run first.
+                        let priority = data
+                            .get("priority")
+                            .and_then(|p| p.as_i64())
+                            .unwrap_or_default() as i32;
+                        SESSION_HANDLER.add_session_hook(id.to_string(), priority, cb);
                         return Some(super::NR {
                             return_type: 0,
                             data: std::ptr::null(),
@@ -142,13 +147,14 @@ impl PluginNativeSessionHandler {
         }
     }
 
-    fn add_session_hook(&self, session_id: String, cb: OnSessionRgbaCallback) {
+    fn add_session_hook(&self, session_id: String, priority: i32, cb: OnSessionRgbaCallback) {
         let sessions = self.sessions.read().unwrap();
         for session in sessions.iter() {
             if session.id == session_id {
                 self.cbs.write().unwrap().insert(session_id.to_owned(), cb);
                 session.ui_handler.add_session_hook(
                     session_id,
+                    priority,
                     crate::flutter::SessionHook::OnSessionRgba(session_rgba_cb),
                 );
                 break;
@@ -1,4 +1,13 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
+
+mod rdp;
+pub mod socks;
+
+pub use rdp::RdpOptions;
 
 use crate::client::*;
 use hbb_common::{
@@ -10,12 +19,175 @@ use hbb_common::{
     protobuf::Message as _,
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        net::TcpStream,
+        sync::mpsc,
+        time::{self, Duration},
+    },
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
+use serde_derive::Serialize;
+
+const MAX_CONNECTION_ROWS: usize = 20;
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+// The peer closes the unauthorized connection after 30 seconds without messages.
+const TFA_INPUT_TIMEOUT: u64 = 30_000;
+
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionStats {
+    id: u64,
+    source: String,
+    #[serde(skip)]
+    start: Instant,
+    // Milliseconds, updated when the stats are serialized.
+    duration: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    open: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+struct TunnelStats {
+    remote_host: String,
+    remote_port: i32,
+    accepted: u64,
+    open: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    // Bytes per second, updated when the stats are serialized.
+    rate_in: f64,
+    rate_out: f64,
+    #[serde(skip)]
+    rates: (rates::Rate, rates::Rate),
+    last_error: String,
+    // The most recent connections, the oldest first.
+    connections: VecDeque<ConnectionStats>,
+    #[serde(skip)]
+    next_id: u64,
+}
+
+impl TunnelStats {
+    fn connection(&mut self, id: u64) -> Option<&mut ConnectionStats> {
+        self.connections.iter_mut().find(|c| c.id == id)
+    }
+}
+
+/// Traffic statistics of the port forward tunnels of a session, keyed by the local port.
+///
+/// The stats of a tunnel are kept until it is removed, so they survive the peer closing
+/// the connections and the tunnel listening again.
+#[derive(Debug, Default)]
+pub struct PortForwardStats {
+    tunnels: BTreeMap<i32, TunnelStats>,
+    changed: bool,
+    last_taken: Option<Instant>,
+}
+
+pub type PortForwardStatsPtr = Arc<Mutex<PortForwardStats>>;
+
+impl PortForwardStats {
+    fn tunnel(&mut self, port: i32) -> &mut TunnelStats {
+        self.changed = true;
+        self.tunnels.entry(port).or_default()
+    }
+
+    pub fn remove(&mut self, port: i32) {
+        if self.tunnels.remove(&port).is_some() {
+            self.changed = true;
+        }
+    }
+
+    fn on_accepted(&mut self, port: i32, source: String) -> u64 {
+        let tunnel = self.tunnel(port);
+        tunnel.accepted += 1;
+        tunnel.next_id += 1;
+        let id = tunnel.next_id;
+        tunnel.connections.push_back(ConnectionStats {
+            id,
+            source,
+            start: Instant::now(),
+            duration: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            open: false,
+        });
+        while tunnel.connections.len() > MAX_CONNECTION_ROWS {
+            tunnel.connections.pop_front();
+        }
+        id
+    }
+
+    fn on_opened(&mut self, port: i32, id: u64) {
+        let tunnel = self.tunnel(port);
+        tunnel.open += 1;
+        if let Some(c) = tunnel.connection(id) {
+            c.open = true;
+        }
+    }
+
+    fn on_closed(&mut self, port: i32, id: u64) {
+        let tunnel = self.tunnel(port);
+        tunnel.open = tunnel.open.saturating_sub(1);
+        if let Some(c) = tunnel.connection(id) {
+            c.open = false;
+            c.duration = c.start.elapsed().as_millis() as _;
+        }
+    }
+
+    fn on_error(&mut self, port: i32, err: String) {
+        self.tunnel(port).last_error = err;
+    }
+
+    fn on_bytes(&mut self, port: i32, id: u64, bytes_in: usize, bytes_out: usize) {
+        let tunnel = self.tunnel(port);
+        tunnel.bytes_in += bytes_in as u64;
+        tunnel.bytes_out += bytes_out as u64;
+        let now = Instant::now();
+        tunnel.rates.0.add(bytes_in as _, now);
+        tunnel.rates.1.add(bytes_out as _, now);
+        if let Some(c) = tunnel.connection(id) {
+            c.bytes_in += bytes_in as u64;
+            c.bytes_out += bytes_out as u64;
+        }
+    }
+
+    pub fn to_json(&mut self) -> String {
+        let now = Instant::now();
+        for tunnel in self.tunnels.values_mut() {
+            tunnel.rate_in = tunnel.rates.0.smoothed_at(now);
+            tunnel.rate_out = tunnel.rates.1.smoothed_at(now);
+            for c in tunnel.connections.iter_mut().filter(|c| c.open) {
+                c.duration = c.start.elapsed().as_millis() as _;
+            }
+        }
+        serde_json::to_string(&self.tunnels).unwrap_or_default()
+    }
+
+    /// The json of the stats if they are changed since the last call,
+    /// at most once per `STATS_INTERVAL` for all the tunnels.
+    fn take_changed(&mut self) -> Option<String> {
+        if !self.changed {
+            return None;
+        }
+        if let Some(t) = self.last_taken {
+            if t.elapsed() < STATS_INTERVAL {
+                return None;
+            }
+        }
+        self.changed = false;
+        self.last_taken = Some(Instant::now());
+        Some(self.to_json())
+    }
+}
 
-fn run_rdp(port: u16) {
+// The rdp file of the local port, removed once the forward ends.
+fn rdp_file_path(port: u16) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("rustdesk_{}.rdp", port))
+}
+
+fn run_rdp(port: u16, options: &RdpOptions) {
     std::process::Command::new("cmdkey")
         .arg("/delete:localhost")
         .output()
@@ -36,10 +208,19 @@ fn run_rdp(port: u16) {
             .output()
             .ok();
     }
-    std::process::Command::new("mstsc")
-        .arg(format!("/v:localhost:{}", port))
-        .spawn()
-        .ok();
+    let mut args = options.mstsc_args();
+    match options.make_rdp_file(port) {
+        Some(content) => {
+            let path = rdp_file_path(port);
+            if let Err(err) = std::fs::write(&path, content) {
+                log::error!("Failed to write {:?}: {}", path, err);
+                return;
+            }
+            args.insert(0, path.to_string_lossy().to_string());
+        }
+        None => args.insert(0, format!("/v:localhost:{}", port)),
+    }
+    std::process::Command::new("mstsc").args(&args).spawn().ok();
 }
 
 pub async fn listen(
@@ -53,39 +234,71 @@ pub async fn listen(
     lc: Arc<RwLock<LoginConfigHandler>>,
     remote_host: String,
     remote_port: i32,
+    stats: PortForwardStatsPtr,
+    rdp_options: Arc<RwLock<RdpOptions>>,
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let listener = match tcp::new_listener(format!("0.0.0.0:{}", port), true).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            stats.lock().unwrap().on_error(port, err.to_string());
+            return Err(err);
+        }
+    };
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
+    {
+        let mut stats = stats.lock().unwrap();
+        let tunnel = stats.tunnel(port);
+        tunnel.remote_host = remote_host.clone();
+        tunnel.remote_port = remote_port;
+    }
     let is_rdp = port == 0;
     if is_rdp {
-        run_rdp(addr.port());
+        let options = rdp_options.read().unwrap().clone();
+        run_rdp(addr.port(), &options);
     }
     let mut ui_receiver = ui_receiver;
+    let mut stats_timer = time::interval(STATS_INTERVAL);
     loop {
         tokio::select! {
             Ok((forward, addr)) = listener.accept() => {
                 log::info!("new connection from {:?}", addr);
-                lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
+                let conn_id = stats.lock().unwrap().on_accepted(port, addr.to_string());
+                {
+                    let mut lc = lc.write().unwrap();
+                    lc.port_forward = (remote_host.clone(), remote_port);
+                    lc.port_forward_dynamic = false;
+                }
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
+                        let stats = stats.clone();
+                        stats.lock().unwrap().on_opened(port, conn_id);
                         tokio::spawn(async move {
-                            if let Err(err) = run_forward(forward, stream).await {
+                            if let Err(err) = run_forward(forward, stream, &stats, port, conn_id).await {
+                                stats.lock().unwrap().on_error(port, err.to_string());
                                 interface.msgbox("error", "Error", &err.to_string(), "");
                             }
+                            stats.lock().unwrap().on_closed(port, conn_id);
                             log::info!("connection from {:?} closed", addr);
                        });
                     }
                     Err(err) => {
+                        stats.lock().unwrap().on_error(port, err.to_string());
                         interface.on_establish_connection_error(err.to_string());
                     }
                     _ => {}
                 }
             }
+            _ = stats_timer.tick() => {
+                let changed = stats.lock().unwrap().take_changed();
+                if let Some(json) = changed {
+                    interface.update_port_forward_stats(&json);
+                }
+            }
             d = ui_receiver.recv() => {
                 match d {
                     Some(Data::Close) => {
@@ -93,22 +306,33 @@ pub async fn listen(
                     }
                     Some(Data::NewRDP) => {
                         println!("receive run_rdp from ui_receiver");
-                        run_rdp(addr.port());
+                        let options = rdp_options.read().unwrap().clone();
+                        run_rdp(addr.port(), &options);
                     }
                     _ => {}
                 }
             }
         }
     }
+    if is_rdp {
+        let path = rdp_file_path(addr.port());
+        if path.exists() {
+            allow_err!(std::fs::remove_file(&path));
+        }
+    }
     Ok(())
 }
 
+/// Connect and login to the peer.
+///
+/// The returned stream is raw if `forward` is given, the bytes received from `forward`
+/// during the login are sent after it. Otherwise the stream keeps the message framing.
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -118,14 +342,23 @@ async fn connect_and_login(
     } else {
         ConnType::PORT_FORWARD
     };
-    let (mut stream, direct, _pk) =
+    let (mut stream, direct, pk) =
         Client::start(id, key, token, conn_type, interface.clone()).await?;
     interface.update_direct(Some(direct));
+    // The same trust store as the other conn types, the login is paused until it's accepted.
+    let fingerprint = crate::common::pk_to_fingerprint(pk.unwrap_or_default());
+    let mut pending_fingerprint = None;
+    if let Some(old) = crate::client::check_fingerprint(id, &fingerprint) {
+        log::warn!("Fingerprint mismatch of {}, pause the login", id);
+        interface.on_fingerprint_mismatch(&old, &fingerprint);
+        pending_fingerprint = Some(fingerprint);
+    }
     let mut buffer = Vec::new();
     let mut received = false;
+    let mut read_timeout = READ_TIMEOUT;
     loop {
         tokio::select! {
-            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+            res = timeout(read_timeout, stream.next()), if pending_fingerprint.is_none() => match res {
                 Err(_) => {
                     bail!("Timeout");
                 }
@@ -142,7 +375,13 @@ async fn connect_and_login(
                         Some(message::Union::LoginResponse(lr)) => match lr.union {
                             Some(login_response::Union::Error(err)) => {
                                 interface.handle_login_error(&err);
-                                return Ok(None);
+                                // Wait for the 2FA code from the ui.
+                                if err != crate::client::LOGIN_MSG_2FA_REQUIRED
+                                    && err != crate::client::LOGIN_MSG_2FA_WRONG
+                                {
+                                    return Ok(None);
+                                }
+                                read_timeout = TFA_INPUT_TIMEOUT;
                             }
                             Some(login_response::Union::PeerInfo(pi)) => {
                                 interface.handle_peer_info(pi);
@@ -168,10 +407,28 @@ async fn connect_and_login(
                     Some(Data::Login((os_username, os_password, password, remember))) => {
                         interface.handle_login_from_ui(os_username, os_password, password, remember, &mut stream).await;
                     }
+                    Some(Data::Login2FA(code)) => {
+                        crate::client::send_2fa(code, &mut stream).await;
+                    }
+                    Some(Data::AcceptFingerprint) => {
+                        if let Some(fingerprint) = pending_fingerprint.take() {
+                            crate::client::trust_fingerprint(id, &fingerprint);
+                        }
+                    }
+                    Some(Data::Close) if pending_fingerprint.is_some() => {
+                        // Abort the login, and leave the close to the caller.
+                        interface.send(Data::Close);
+                        return Ok(None);
+                    }
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async {
+                match forward.as_mut() {
+                    Some(forward) => forward.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -180,6 +437,9 @@ async fn connect_and_login(
             },
         }
     }
+    if forward.is_none() {
+        return Ok(Some(stream));
+    }
     stream.set_raw();
     if !buffer.is_empty() {
         allow_err!(stream.send_bytes(buffer.into()).await);
@@ -187,7 +447,13 @@ async fn connect_and_login(
     Ok(Some(stream))
 }
 
-async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
+async fn run_forward(
+    forward: Framed<TcpStream, BytesCodec>,
+    stream: Stream,
+    stats: &PortForwardStatsPtr,
+    port: i32,
+    conn_id: u64,
+) -> ResultType<()> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
     let mut stream = stream;
@@ -195,6 +461,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
         tokio::select! {
             res = forward.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.lock().unwrap().on_bytes(port, conn_id, 0, bytes.len());
                     allow_err!(stream.send_bytes(bytes.into()).await);
                 } else {
                     break;
@@ -202,6 +469,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
             },
             res = stream.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.lock().unwrap().on_bytes(port, conn_id, bytes.len(), 0);
                     allow_err!(forward.send(bytes).await);
                 } else {
                     break;
@@ -211,3 +479,33 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_forward_stats() {
+        let mut stats = PortForwardStats::default();
+        let first = stats.on_accepted(8080, "127.0.0.1:5000".to_owned());
+        stats.on_opened(8080, first);
+        stats.on_bytes(8080, first, 10, 20);
+        stats.on_closed(8080, first);
+        for i in 0..MAX_CONNECTION_ROWS {
+            stats.on_accepted(8080, format!("127.0.0.1:{}", 5001 + i));
+        }
+        stats.on_error(8080, "Reset by the peer".to_owned());
+        let tunnel = &stats.tunnels[&8080];
+        assert_eq!(tunnel.accepted, MAX_CONNECTION_ROWS as u64 + 1);
+        assert_eq!(tunnel.open, 0);
+        assert_eq!((tunnel.bytes_in, tunnel.bytes_out), (10, 20));
+        assert_eq!(tunnel.connections.len(), MAX_CONNECTION_ROWS);
+        assert_eq!(tunnel.connections[0].source, "127.0.0.1:5001");
+        assert_eq!(tunnel.last_error, "Reset by the peer");
+
+        assert!(stats.take_changed().is_some());
+        assert!(stats.take_changed().is_none());
+        stats.remove(8080);
+        assert_eq!(stats.to_json(), "{}");
+    }
+}
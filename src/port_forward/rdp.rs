@@ -0,0 +1,201 @@
+use hbb_common::{bail, ResultType};
+use serde_derive::Deserialize;
+
+const MIN_SIZE: u32 = 200;
+const MAX_SIZE: u32 = 8192;
+
+/// The parameters of the local RDP client, passed as json to `session_add`.
+///
+/// All the options are only supported on Windows, where `mstsc` is launched.
+/// Setting any of them on other platforms is an error.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RdpOptions {
+    /// The resolution of the remote desktop, `width` and `height` must be set together.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Conflicts with `width` and `height`.
+    pub fullscreen: bool,
+    /// Use all the local monitors, conflicts with `width` and `height`.
+    pub multimon: bool,
+    /// Connect to the console session.
+    pub admin: bool,
+    /// Redirect the clipboard, `None` keeps the default of `mstsc`.
+    pub clipboard: Option<bool>,
+    /// The local drives to redirect, e.g. `["C", "D"]`, or `["*"]` for all the drives.
+    pub drives: Vec<String>,
+    /// Prefilled user name of the login dialog.
+    pub username_hint: String,
+}
+
+impl RdpOptions {
+    /// Parse and validate the options, an empty string is the default options.
+    pub fn parse(json: &str) -> ResultType<Self> {
+        if json.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let options: Self = match serde_json::from_str(json) {
+            Ok(options) => options,
+            Err(e) => bail!("Invalid rdp options: {}", e),
+        };
+        options.validate()?;
+        Ok(options)
+    }
+
+    fn validate(&self) -> ResultType<()> {
+        if !cfg!(windows) {
+            if let Some(name) = self.first_set_option() {
+                bail!("RDP option \"{}\" is unsupported on this platform", name);
+            }
+            return Ok(());
+        }
+        match (self.width, self.height) {
+            (Some(w), Some(h)) => {
+                if !(MIN_SIZE..=MAX_SIZE).contains(&w) || !(MIN_SIZE..=MAX_SIZE).contains(&h) {
+                    bail!(
+                        "RDP resolution {}x{} is out of range {}-{}",
+                        w,
+                        h,
+                        MIN_SIZE,
+                        MAX_SIZE
+                    );
+                }
+                if self.fullscreen || self.multimon {
+                    bail!("RDP resolution conflicts with fullscreen and multimon");
+                }
+            }
+            (None, None) => {}
+            _ => bail!("RDP width and height must be set together"),
+        }
+        for drive in self.drives.iter() {
+            let valid = drive == "*"
+                || (drive.len() == 1 && drive.chars().all(|c| c.is_ascii_alphabetic()));
+            if !valid {
+                bail!("Invalid RDP drive \"{}\"", drive);
+            }
+        }
+        if self.drives.len() > 1 && self.drives.iter().any(|d| d == "*") {
+            bail!("RDP drive \"*\" can not be combined with other drives");
+        }
+        if self.username_hint.contains(|c: char| c.is_control()) {
+            bail!("Invalid RDP username hint");
+        }
+        Ok(())
+    }
+
+    fn first_set_option(&self) -> Option<&'static str> {
+        if self.width.is_some() || self.height.is_some() {
+            Some("width")
+        } else if self.fullscreen {
+            Some("fullscreen")
+        } else if self.multimon {
+            Some("multimon")
+        } else if self.admin {
+            Some("admin")
+        } else if self.clipboard.is_some() {
+            Some("clipboard")
+        } else if !self.drives.is_empty() {
+            Some("drives")
+        } else if !self.username_hint.is_empty() {
+            Some("username_hint")
+        } else {
+            None
+        }
+    }
+
+    /// The options which can only be set by a .rdp file.
+    fn need_rdp_file(&self) -> bool {
+        self.clipboard.is_some() || !self.drives.is_empty() || !self.username_hint.is_empty()
+    }
+
+    /// The content of the .rdp file connecting to `localhost:port`, if it is needed.
+    pub fn make_rdp_file(&self, port: u16) -> Option<String> {
+        if !self.need_rdp_file() {
+            return None;
+        }
+        let mut lines = vec![format!("full address:s:localhost:{}", port)];
+        if let Some(clipboard) = self.clipboard {
+            lines.push(format!("redirectclipboard:i:{}", clipboard as i32));
+        }
+        if !self.drives.is_empty() {
+            let drives = self
+                .drives
+                .iter()
+                .map(|d| {
+                    if d == "*" {
+                        d.clone()
+                    } else {
+                        format!("{}:", d.to_uppercase())
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(";");
+            lines.push(format!("drivestoredirect:s:{}", drives));
+        }
+        if !self.username_hint.is_empty() {
+            lines.push(format!("username:s:{}", self.username_hint));
+        }
+        Some(lines.join("\r\n") + "\r\n")
+    }
+
+    /// The arguments of `mstsc`, except the connection or the .rdp file.
+    pub fn mstsc_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let (Some(w), Some(h)) = (self.width, self.height) {
+            args.push(format!("/w:{}", w));
+            args.push(format!("/h:{}", h));
+        }
+        if self.fullscreen {
+            args.push("/f".to_owned());
+        }
+        if self.multimon {
+            args.push("/multimon".to_owned());
+        }
+        if self.admin {
+            args.push("/admin".to_owned());
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rdp_options() {
+        assert_eq!(RdpOptions::parse("").unwrap(), RdpOptions::default());
+        assert!(RdpOptions::parse("{\"unknown\": 1}").is_err());
+        if !cfg!(windows) {
+            assert!(RdpOptions::parse("{}").is_ok());
+            assert!(RdpOptions::parse("{\"admin\": true}").is_err());
+            return;
+        }
+        let options = RdpOptions::parse("{\"width\": 1920, \"height\": 1080}").unwrap();
+        assert_eq!(options.mstsc_args(), vec!["/w:1920", "/h:1080"]);
+        assert!(options.make_rdp_file(3389).is_none());
+        assert!(RdpOptions::parse("{\"width\": 1920}").is_err());
+        assert!(RdpOptions::parse("{\"width\": 100, \"height\": 100}").is_err());
+        assert!(RdpOptions::parse(
+            "{\"width\": 1920, \"height\": 1080, \"fullscreen\": true}"
+        )
+        .is_err());
+        assert!(RdpOptions::parse("{\"drives\": [\"*\", \"C\"]}").is_err());
+        assert!(RdpOptions::parse("{\"drives\": [\"C:\"]}").is_err());
+        assert!(RdpOptions::parse("{\"username_hint\": \"a\\nb\"}").is_err());
+    }
+
+    #[test]
+    fn test_make_rdp_file() {
+        let options = RdpOptions {
+            clipboard: Some(true),
+            drives: vec!["c".to_owned(), "D".to_owned()],
+            username_hint: "admin".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.make_rdp_file(12345).unwrap(),
+            "full address:s:localhost:12345\r\nredirectclipboard:i:1\r\ndrivestoredirect:s:C:;D:\r\nusername:s:admin\r\n"
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! SOCKS5 dynamic forwarding.
+//!
+//! A local SOCKS5 server whose `CONNECT` requests are opened on the remote side.
+//! All the connections share one peer connection, see [`crate::server::tunnel`].
+
+use std::collections::HashMap;
+
+use crate::{
+    client::{Data, Interface},
+    server::tunnel::{
+        make_close, make_data, REPLY_CONNECTION_REFUSED, REPLY_GENERAL_FAILURE,
+        REPLY_NOT_ALLOWED, REPLY_SUCCEEDED,
+    },
+    ui_session_interface::{InvokeUiSession, Session},
+};
+use bytes::Bytes;
+use hbb_common::{
+    bail,
+    futures::{SinkExt, StreamExt},
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    tcp,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::{mpsc, oneshot},
+        time::{self, Duration},
+    },
+    tokio_util::codec::{BytesCodec, Framed},
+    ResultType, Stream,
+};
+use serde_derive::Serialize;
+
+const SOCKS_VERSION: u8 = 5;
+const AUTH_VERSION: u8 = 1;
+const METHOD_NO_AUTH: u8 = 0;
+const METHOD_USER_PASS: u8 = 2;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_COMMAND_NOT_SUPPORTED: i32 = 7;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: i32 = 8;
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The destination ports allowed through the tunnel, configured by the peer options
+/// "socks-allowed-ports" and "socks-denied-ports", e.g. "22,80,8000-8100".
+/// An empty allowlist allows all the ports, the denylist wins over the allowlist.
+#[derive(Debug, Default)]
+pub struct PortFilter {
+    allowed: Vec<(u16, u16)>,
+    denied: Vec<(u16, u16)>,
+}
+
+impl PortFilter {
+    pub fn new(allowed: &str, denied: &str) -> Self {
+        Self {
+            allowed: Self::parse(allowed),
+            denied: Self::parse(denied),
+        }
+    }
+
+    fn parse(s: &str) -> Vec<(u16, u16)> {
+        s.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .filter_map(|x| {
+                let range = match x.split_once('-') {
+                    Some((start, end)) => (start.trim().parse(), end.trim().parse()),
+                    None => (x.parse(), x.parse()),
+                };
+                match range {
+                    (Ok(start), Ok(end)) if start <= end => Some((start, end)),
+                    _ => {
+                        log::warn!("Invalid socks port range: {}", x);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_allowed(&self, port: u16) -> bool {
+        let contains = |ranges: &Vec<(u16, u16)>| {
+            ranges
+                .iter()
+                .any(|(start, end)| *start <= port && port <= *end)
+        };
+        (self.allowed.is_empty() || contains(&self.allowed)) && !contains(&self.denied)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+struct Stats {
+    accepted: u64,
+    open: u64,
+    rejected: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+enum Event {
+    Open {
+        channel: u32,
+        host: String,
+        port: u16,
+        reply: oneshot::Sender<i32>,
+        tx: mpsc::UnboundedSender<Bytes>,
+    },
+    Data(u32, Bytes),
+    Close(u32),
+    Rejected,
+}
+
+struct Channel {
+    tx: mpsc::UnboundedSender<Bytes>,
+    reply: Option<oneshot::Sender<i32>>,
+}
+
+pub async fn listen<T: InvokeUiSession>(
+    handler: Session<T>,
+    port: i32,
+    auth: Option<(String, String)>,
+    mut ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+) -> ResultType<()> {
+    let listener = tcp::new_listener(format!("127.0.0.1:{}", port), true).await?;
+    log::info!("socks listening on {:?}", listener.local_addr()?);
+    let filter = std::sync::Arc::new(PortFilter::new(
+        &handler.get_option("socks-allowed-ports".to_owned()),
+        &handler.get_option("socks-denied-ports".to_owned()),
+    ));
+    let auth = std::sync::Arc::new(auth);
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<Event>();
+    let mut stream: Option<Stream> = None;
+    let mut channels: HashMap<u32, Channel> = HashMap::new();
+    let mut next_channel: u32 = 0;
+    let mut stats = Stats::default();
+    let mut last_stats = stats.clone();
+    let mut stats_timer = time::interval(STATS_INTERVAL);
+    loop {
+        tokio::select! {
+            Ok((sock, addr)) = listener.accept() => {
+                log::debug!("new socks connection from {:?}", addr);
+                stats.accepted += 1;
+                next_channel = next_channel.wrapping_add(1);
+                tokio::spawn(serve(
+                    sock,
+                    next_channel,
+                    auth.clone(),
+                    filter.clone(),
+                    event_tx.clone(),
+                ));
+            }
+            Some(event) = event_rx.recv() => match event {
+                Event::Open { channel, host, port, reply, tx } => {
+                    if stream.is_none() {
+                        {
+                            let mut lc = handler.lc.write().unwrap();
+                            lc.port_forward = ("".to_owned(), 0);
+                            lc.port_forward_dynamic = true;
+                        }
+                        match super::connect_and_login(
+                            &handler.get_id(),
+                            &handler.password,
+                            &mut ui_receiver,
+                            handler.clone(),
+                            None,
+                            key,
+                            token,
+                            false,
+                        )
+                        .await
+                        {
+                            Ok(Some(s)) => stream = Some(s),
+                            Ok(None) => {
+                                reply.send(REPLY_GENERAL_FAILURE).ok();
+                                continue;
+                            }
+                            Err(err) => {
+                                handler.on_establish_connection_error(err.to_string());
+                                reply.send(REPLY_GENERAL_FAILURE).ok();
+                                continue;
+                            }
+                        }
+                    }
+                    let mut frame = TunnelFrame::new();
+                    frame.channel = channel;
+                    frame.set_open(PortForward {
+                        host,
+                        port: port as _,
+                        ..Default::default()
+                    });
+                    channels.insert(channel, Channel { tx, reply: Some(reply) });
+                    send_frame(&mut stream, frame).await;
+                }
+                Event::Data(channel, data) => {
+                    if channels.contains_key(&channel) {
+                        stats.bytes_out += data.len() as u64;
+                        send_frame(&mut stream, make_data(channel, data)).await;
+                    }
+                }
+                Event::Close(channel) => {
+                    if let Some(c) = channels.remove(&channel) {
+                        if c.reply.is_none() {
+                            stats.open = stats.open.saturating_sub(1);
+                        }
+                        send_frame(&mut stream, make_close(channel)).await;
+                    }
+                }
+                Event::Rejected => {
+                    stats.rejected += 1;
+                }
+            },
+            res = async {
+                match stream.as_mut() {
+                    Some(stream) => stream.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let msg_in = match res {
+                    Some(Ok(bytes)) => Message::parse_from_bytes(&bytes),
+                    _ => {
+                        log::info!("socks tunnel closed by the peer");
+                        // Dropping the channels closes the local connections.
+                        stream = None;
+                        channels.clear();
+                        stats.open = 0;
+                        continue;
+                    }
+                };
+                let Ok(Some(message::Union::TunnelFrame(frame))) = msg_in.map(|m| m.union) else {
+                    continue;
+                };
+                let channel = frame.channel;
+                match frame.union {
+                    Some(tunnel_frame::Union::OpenResult(r)) => {
+                        if let Some(c) = channels.get_mut(&channel) {
+                            if let Some(reply) = c.reply.take() {
+                                reply.send(r.code).ok();
+                            }
+                            if r.code == REPLY_SUCCEEDED {
+                                stats.open += 1;
+                            } else {
+                                channels.remove(&channel);
+                            }
+                        }
+                    }
+                    Some(tunnel_frame::Union::Data(data)) => {
+                        if let Some(c) = channels.get(&channel) {
+                            stats.bytes_in += data.len() as u64;
+                            c.tx.send(data).ok();
+                        }
+                    }
+                    Some(tunnel_frame::Union::Close(_)) => {
+                        if channels.remove(&channel).is_some() {
+                            stats.open = stats.open.saturating_sub(1);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            _ = stats_timer.tick() => {
+                if stats != last_stats {
+                    last_stats = stats.clone();
+                    if let Ok(s) = serde_json::to_string(&stats) {
+                        handler.update_socks_stats(port, &s);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn send_frame(stream: &mut Option<Stream>, frame: TunnelFrame) {
+    if let Some(s) = stream.as_mut() {
+        let mut msg = Message::new();
+        msg.set_tunnel_frame(frame);
+        if let Err(err) = s.send(&msg).await {
+            log::error!("Failed to send tunnel frame: {}", err);
+        }
+    }
+}
+
+async fn serve(
+    sock: TcpStream,
+    channel: u32,
+    auth: std::sync::Arc<Option<(String, String)>>,
+    filter: std::sync::Arc<PortFilter>,
+    event_tx: mpsc::UnboundedSender<Event>,
+) {
+    let mut sock = sock;
+    let (host, port) = match handshake(&mut sock, &auth).await {
+        Ok(Ok(target)) => target,
+        Ok(Err(code)) => {
+            reply(&mut sock, code).await.ok();
+            event_tx.send(Event::Rejected).ok();
+            return;
+        }
+        Err(err) => {
+            log::debug!("socks handshake failed: {}", err);
+            event_tx.send(Event::Rejected).ok();
+            return;
+        }
+    };
+    if !filter.is_allowed(port) {
+        log::info!("socks destination port {} is not allowed", port);
+        reply(&mut sock, REPLY_NOT_ALLOWED).await.ok();
+        event_tx.send(Event::Rejected).ok();
+        return;
+    }
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if event_tx
+        .send(Event::Open {
+            channel,
+            host,
+            port,
+            reply: reply_tx,
+            tx,
+        })
+        .is_err()
+    {
+        return;
+    }
+    let code = reply_rx.await.unwrap_or(REPLY_GENERAL_FAILURE);
+    if reply(&mut sock, code).await.is_err() || code != REPLY_SUCCEEDED {
+        if code == REPLY_SUCCEEDED {
+            event_tx.send(Event::Close(channel)).ok();
+        }
+        return;
+    }
+    let mut forward = Framed::new(sock, BytesCodec::new());
+    loop {
+        tokio::select! {
+            res = forward.next() => {
+                if let Some(Ok(bytes)) = res {
+                    event_tx.send(Event::Data(channel, bytes.freeze())).ok();
+                } else {
+                    break;
+                }
+            }
+            res = rx.recv() => {
+                if let Some(bytes) = res {
+                    if forward.send(bytes).await.is_err() {
+                        break;
+                    }
+                } else {
+                    // Closed by the remote side or the tunnel.
+                    return;
+                }
+            }
+        }
+    }
+    event_tx.send(Event::Close(channel)).ok();
+}
+
+/// Returns the target, or the reply code if the request is rejected.
+async fn handshake(
+    sock: &mut TcpStream,
+    auth: &Option<(String, String)>,
+) -> ResultType<Result<(String, u16), i32>> {
+    let mut buf = [0u8; 2];
+    sock.read_exact(&mut buf).await?;
+    if buf[0] != SOCKS_VERSION {
+        bail!("Unsupported socks version {}", buf[0]);
+    }
+    let mut methods = vec![0u8; buf[1] as usize];
+    sock.read_exact(&mut methods).await?;
+    let method = if auth.is_some() {
+        METHOD_USER_PASS
+    } else {
+        METHOD_NO_AUTH
+    };
+    if !methods.contains(&method) {
+        sock.write_all(&[SOCKS_VERSION, METHOD_NOT_ACCEPTABLE]).await?;
+        bail!("No acceptable socks auth method");
+    }
+    sock.write_all(&[SOCKS_VERSION, method]).await?;
+    if let Some((username, password)) = auth {
+        let mut buf = [0u8; 2];
+        sock.read_exact(&mut buf).await?;
+        let mut user = vec![0u8; buf[1] as usize];
+        sock.read_exact(&mut user).await?;
+        sock.read_exact(&mut buf[..1]).await?;
+        let mut pass = vec![0u8; buf[0] as usize];
+        sock.read_exact(&mut pass).await?;
+        if user != username.as_bytes() || pass != password.as_bytes() {
+            sock.write_all(&[AUTH_VERSION, 1]).await?;
+            bail!("Invalid socks username or password");
+        }
+        sock.write_all(&[AUTH_VERSION, 0]).await?;
+    }
+
+    let mut buf = [0u8; 4];
+    sock.read_exact(&mut buf).await?;
+    if buf[0] != SOCKS_VERSION {
+        bail!("Unsupported socks version {}", buf[0]);
+    }
+    let cmd = buf[1];
+    let host = match buf[3] {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            sock.read_exact(&mut addr).await?;
+            std::net::Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            sock.read_exact(&mut addr).await?;
+            std::net::Ipv6Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            sock.read_exact(&mut len).await?;
+            let mut domain = vec![0u8; len[0] as usize];
+            sock.read_exact(&mut domain).await?;
+            String::from_utf8_lossy(&domain).to_string()
+        }
+        _ => return Ok(Err(REPLY_ADDRESS_TYPE_NOT_SUPPORTED)),
+    };
+    let port = sock.read_u16().await?;
+    // BIND and UDP ASSOCIATE are not supported.
+    if cmd != CMD_CONNECT {
+        return Ok(Err(REPLY_COMMAND_NOT_SUPPORTED));
+    }
+    if port == 0 {
+        return Ok(Err(REPLY_CONNECTION_REFUSED));
+    }
+    // Formatted as "host:port" by the remote side.
+    let host = if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        host
+    };
+    Ok(Ok((host, port)))
+}
+
+async fn reply(sock: &mut TcpStream, code: i32) -> ResultType<()> {
+    sock.write_all(&make_reply(code)).await?;
+    Ok(())
+}
+
+#[inline]
+fn make_reply(code: i32) -> [u8; 10] {
+    // The bound address is not meaningful for the tunnel.
+    [SOCKS_VERSION, code as u8, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_filter() {
+        let filter = PortFilter::new("", "");
+        assert!(filter.is_allowed(22));
+        assert!(filter.is_allowed(65535));
+
+        let filter = PortFilter::new("22, 80,8000-8100", "8080");
+        assert!(filter.is_allowed(22));
+        assert!(filter.is_allowed(80));
+        assert!(filter.is_allowed(8000));
+        assert!(filter.is_allowed(8100));
+        assert!(!filter.is_allowed(8080));
+        assert!(!filter.is_allowed(443));
+
+        let filter = PortFilter::new("", "1-1024,abc,9-1");
+        assert!(!filter.is_allowed(22));
+        assert!(filter.is_allowed(1025));
+        assert_eq!(filter.denied, vec![(1, 1024)]);
+    }
+
+    #[test]
+    fn test_make_reply() {
+        assert_eq!(make_reply(REPLY_SUCCEEDED)[..4], [5, 0, 0, 1]);
+        assert_eq!(make_reply(REPLY_COMMAND_NOT_SUPPORTED)[1], 7);
+    }
+}
@@ -1,6 +1,9 @@
 #[cfg(all(windows, feature = "virtual_display_driver"))]
 use crate::platform::is_installed;
-use crate::ui_interface::get_option;
+use crate::{
+    state_journal::{self, StateRecord},
+    ui_interface::get_option,
+};
 #[cfg(windows)]
 use crate::{
     display_service,
@@ -15,6 +18,8 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+#[cfg(windows)]
+mod win_display_power;
 #[cfg(windows)]
 pub mod win_exclude_from_capture;
 #[cfg(windows)]
@@ -34,6 +39,7 @@ pub const OCCUPIED: &'static str = "Privacy occupied by another one";
 pub const TURN_OFF_OTHER_ID: &'static str =
     "Failed to turn off privacy mode that belongs to someone else";
 pub const NO_DISPLAYS: &'static str = "No displays";
+pub const DISPLAY_OFF_UNSUPPORTED: &'static str = "privacy_mode_display_off_unsupported_tip";
 
 #[cfg(windows)]
 pub const PRIVACY_MODE_IMPL_WIN_MAG: &str = win_mag::PRIVACY_MODE_IMPL;
@@ -44,6 +50,12 @@ pub const PRIVACY_MODE_IMPL_WIN_EXCLUDE_FROM_CAPTURE: &str =
 #[cfg(all(windows, feature = "virtual_display_driver"))]
 pub const PRIVACY_MODE_IMPL_WIN_VIRTUAL_DISPLAY: &str = win_virtual_display::PRIVACY_MODE_IMPL;
 
+/// What an impl supports besides hiding the screen, reported to the controlling side.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct PrivacyModeDetails {
+    pub display_off: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum PrivacyModeState {
@@ -63,6 +75,12 @@ pub trait PrivacyMode: Sync + Send {
 
     fn get_impl_key(&self) -> &str;
 
+    /// Power off the physical displays till the privacy mode is turned off.
+    /// The warning is returned if it's not supported or failed, the privacy mode is still on.
+    fn turn_off_displays(&mut self) -> Option<String> {
+        Some(DISPLAY_OFF_UNSUPPORTED.to_owned())
+    }
+
     #[inline]
     fn check_on_conn_id(&self, conn_id: i32) -> ResultType<bool> {
         let pre_conn_id = self.pre_conn_id();
@@ -121,7 +139,7 @@ lazy_static::lazy_static! {
 
     static ref PRIVACY_MODE: Arc<Mutex<Option<Box<dyn PrivacyMode>>>> = {
         let mut cur_impl = get_option("privacy-mode-impl-key".to_owned());
-        if !get_supported_privacy_mode_impl().iter().any(|(k, _)| k == &cur_impl) {
+        if !get_supported_privacy_mode_impl().iter().any(|(k, _, _)| k == &cur_impl) {
             cur_impl = DEFAULT_PRIVACY_MODE_IMPL.to_owned();
         }
 
@@ -168,7 +186,13 @@ pub fn init() -> Option<ResultType<()>> {
 
 #[inline]
 pub fn clear() -> Option<()> {
-    Some(PRIVACY_MODE.lock().unwrap().as_mut()?.clear())
+    let mut privacy_mode_lock = PRIVACY_MODE.lock().unwrap();
+    let privacy_mode = privacy_mode_lock.as_mut()?;
+    privacy_mode.clear();
+    state_journal::release(&StateRecord::PrivacyMode(
+        privacy_mode.get_impl_key().to_owned(),
+    ));
+    Some(())
 }
 
 #[inline]
@@ -187,14 +211,14 @@ pub fn switch(impl_key: &str) {
 
 fn get_supported_impl(impl_key: &str) -> String {
     let supported_impls = get_supported_privacy_mode_impl();
-    if supported_impls.iter().any(|(k, _)| k == &impl_key) {
+    if supported_impls.iter().any(|(k, _, _)| k == &impl_key) {
         return impl_key.to_owned();
     };
     // fallback
     let mut cur_impl = get_option("privacy-mode-impl-key".to_owned());
     if !get_supported_privacy_mode_impl()
         .iter()
-        .any(|(k, _)| k == &cur_impl)
+        .any(|(k, _, _)| k == &cur_impl)
     {
         // fallback
         cur_impl = DEFAULT_PRIVACY_MODE_IMPL.to_owned();
@@ -236,6 +260,7 @@ pub fn turn_on_privacy(impl_key: &str, conn_id: i32) -> Option<ResultType<bool>>
         {
             if let Some(privacy_mode) = privacy_mode_lock.as_mut() {
                 privacy_mode.clear();
+                state_journal::release(&StateRecord::PrivacyMode(cur_impl_key.clone()));
             }
 
             *privacy_mode_lock = Some(creator(&impl_key));
@@ -244,19 +269,38 @@ pub fn turn_on_privacy(impl_key: &str, conn_id: i32) -> Option<ResultType<bool>>
         }
     }
 
-    // turn on privacy mode
-    Some(privacy_mode_lock.as_mut()?.turn_on_privacy(conn_id))
+    // turn on privacy mode, it's turned off on the next start if the process is killed.
+    let privacy_mode = privacy_mode_lock.as_mut()?;
+    let record = StateRecord::PrivacyMode(impl_key);
+    state_journal::engage(record.clone());
+    let res = privacy_mode.turn_on_privacy(conn_id);
+    if !matches!(res, Ok(true)) {
+        state_journal::release(&record);
+    }
+    Some(res)
 }
 
 #[inline]
 pub fn turn_off_privacy(conn_id: i32, state: Option<PrivacyModeState>) -> Option<ResultType<()>> {
-    Some(
-        PRIVACY_MODE
-            .lock()
-            .unwrap()
-            .as_mut()?
-            .turn_off_privacy(conn_id, state),
-    )
+    let mut privacy_mode_lock = PRIVACY_MODE.lock().unwrap();
+    let privacy_mode = privacy_mode_lock.as_mut()?;
+    let res = privacy_mode.turn_off_privacy(conn_id, state);
+    if res.is_ok() && privacy_mode.pre_conn_id() == INVALID_PRIVACY_MODE_CONN_ID {
+        state_journal::release(&StateRecord::PrivacyMode(
+            privacy_mode.get_impl_key().to_owned(),
+        ));
+    }
+    Some(res)
+}
+
+#[inline]
+pub fn turn_off_displays(conn_id: i32) -> Option<String> {
+    let mut privacy_mode_lock = PRIVACY_MODE.lock().unwrap();
+    let privacy_mode = privacy_mode_lock.as_mut()?;
+    if privacy_mode.pre_conn_id() != conn_id {
+        return None;
+    }
+    privacy_mode.turn_off_displays()
 }
 
 #[inline]
@@ -283,7 +327,7 @@ async fn set_privacy_mode_state(
         .await
 }
 
-pub fn get_supported_privacy_mode_impl() -> Vec<(&'static str, &'static str)> {
+pub fn get_supported_privacy_mode_impl() -> Vec<(&'static str, &'static str, PrivacyModeDetails)> {
     #[cfg(target_os = "windows")]
     {
         let mut vec_impls = Vec::new();
@@ -292,10 +336,15 @@ pub fn get_supported_privacy_mode_impl() -> Vec<(&'static str, &'static str)> {
             vec_impls.push((
                 PRIVACY_MODE_IMPL_WIN_EXCLUDE_FROM_CAPTURE,
                 "privacy_mode_impl_mag_tip",
+                PrivacyModeDetails { display_off: true },
             ));
         } else {
             if display_service::is_privacy_mode_mag_supported() {
-                vec_impls.push((PRIVACY_MODE_IMPL_WIN_MAG, "privacy_mode_impl_mag_tip"));
+                vec_impls.push((
+                    PRIVACY_MODE_IMPL_WIN_MAG,
+                    "privacy_mode_impl_mag_tip",
+                    PrivacyModeDetails::default(),
+                ));
             }
         }
 
@@ -304,6 +353,7 @@ pub fn get_supported_privacy_mode_impl() -> Vec<(&'static str, &'static str)> {
             vec_impls.push((
                 PRIVACY_MODE_IMPL_WIN_VIRTUAL_DISPLAY,
                 "privacy_mode_impl_virtual_display_tip",
+                PrivacyModeDetails::default(),
             ));
         }
 
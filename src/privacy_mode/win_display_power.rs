@@ -0,0 +1,329 @@
+//! Power off the physical displays while the privacy mode is on, for the impls which only hide
+//! the windows from the capture, see `win_exclude_from_capture`.
+//!
+//! The monitors supporting DDC/CI are switched by the power mode VCP code. The others are switched
+//! by `SC_MONITORPOWER`, which is undone by any input, so it's sent again on each check. The
+//! monitors are checked every [`CHECK_INTERVAL`], so the ones plugged during the blackout are
+//! powered off too.
+
+use hbb_common::{allow_err, bail, log, ResultType};
+use std::{
+    collections::HashSet,
+    mem::{size_of, zeroed},
+    ptr::{null, null_mut},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::JoinHandle,
+    time::Duration,
+};
+use winapi::{
+    shared::{
+        minwindef::{BOOL, DWORD, LPARAM, TRUE},
+        windef::{HDC, HMONITOR, LPRECT},
+    },
+    um::{
+        lowlevelmonitorconfigurationapi::SetVCPFeature,
+        physicalmonitorenumerationapi::{
+            DestroyPhysicalMonitors, GetNumberOfPhysicalMonitorsFromHMONITOR,
+            GetPhysicalMonitorsFromHMONITOR, PHYSICAL_MONITOR,
+        },
+        winbase::SetThreadExecutionState,
+        winnt::ES_DISPLAY_REQUIRED,
+        winuser::*,
+    },
+};
+
+pub const DISPLAY_OFF_FAILED: &str = "privacy_mode_display_off_failed_tip";
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// The power mode of VESA MCCS.
+const VCP_POWER_MODE: u8 = 0xD6;
+const VCP_POWER_ON: DWORD = 0x01;
+const VCP_POWER_OFF: DWORD = 0x05;
+// The lParam of `SC_MONITORPOWER`.
+const MONITOR_ON: LPARAM = -1;
+const MONITOR_OFF: LPARAM = 2;
+
+/// The monitors attached and the ways to power them.
+trait Monitors {
+    /// The device names of the monitors.
+    fn list(&mut self) -> Vec<String>;
+    /// By DDC/CI.
+    fn set_power(&mut self, name: &str, on: bool) -> ResultType<()>;
+    /// All the monitors by `SC_MONITORPOWER`.
+    fn broadcast_power(&mut self, on: bool) -> ResultType<()>;
+}
+
+#[derive(Default)]
+struct Blackout {
+    // Powered off by DDC/CI.
+    off: HashSet<String>,
+    // The monitors of the last check.
+    seen: HashSet<String>,
+    // Some monitor is powered off by `SC_MONITORPOWER`.
+    broadcast: bool,
+}
+
+impl Blackout {
+    /// Power off the monitors not off yet, the error is returned if any monitor may be left on.
+    fn check(&mut self, monitors: &mut impl Monitors) -> ResultType<()> {
+        let list = monitors.list();
+        for name in list.iter() {
+            // Powered off and not unplugged since. A monitor plugged again is powered on.
+            if self.off.contains(name) && self.seen.contains(name) {
+                continue;
+            }
+            match monitors.set_power(name, false) {
+                Ok(()) => {
+                    self.off.insert(name.clone());
+                }
+                Err(e) => {
+                    log::debug!("Failed to power off {} by DDC/CI: {}", name, e);
+                    self.off.remove(name);
+                    self.broadcast = true;
+                }
+            }
+        }
+        self.seen = list.into_iter().collect();
+        if self.broadcast {
+            monitors.broadcast_power(false)?;
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, monitors: &mut impl Monitors) {
+        for name in self.off.drain() {
+            if let Err(e) = monitors.set_power(&name, true) {
+                log::warn!("Failed to power on {}: {}", name, e);
+            }
+        }
+        if std::mem::take(&mut self.broadcast) {
+            allow_err!(monitors.broadcast_power(true));
+        }
+    }
+}
+
+struct WinMonitors;
+
+unsafe extern "system" fn enum_monitor(
+    hmonitor: HMONITOR,
+    _hdc: HDC,
+    _rect: LPRECT,
+    data: LPARAM,
+) -> BOOL {
+    let monitors = &mut *(data as *mut Vec<(HMONITOR, String)>);
+    let mut info: MONITORINFOEXW = zeroed();
+    info.cbSize = size_of::<MONITORINFOEXW>() as _;
+    if GetMonitorInfoW(hmonitor, &mut info as *mut _ as _) != 0 {
+        let len = info
+            .szDevice
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(info.szDevice.len());
+        monitors.push((hmonitor, String::from_utf16_lossy(&info.szDevice[..len])));
+    }
+    TRUE
+}
+
+fn enum_monitors() -> Vec<(HMONITOR, String)> {
+    let mut monitors: Vec<(HMONITOR, String)> = vec![];
+    unsafe {
+        EnumDisplayMonitors(
+            null_mut(),
+            null(),
+            Some(enum_monitor),
+            &mut monitors as *mut _ as _,
+        );
+    }
+    monitors
+}
+
+impl Monitors for WinMonitors {
+    fn list(&mut self) -> Vec<String> {
+        enum_monitors().into_iter().map(|(_, name)| name).collect()
+    }
+
+    fn set_power(&mut self, name: &str, on: bool) -> ResultType<()> {
+        let Some((hmonitor, _)) = enum_monitors().into_iter().find(|(_, n)| n == name) else {
+            bail!("No such monitor");
+        };
+        unsafe {
+            let mut count: DWORD = 0;
+            if GetNumberOfPhysicalMonitorsFromHMONITOR(hmonitor, &mut count) == 0 || count == 0 {
+                bail!("No physical monitor: {}", std::io::Error::last_os_error());
+            }
+            let mut physical: Vec<PHYSICAL_MONITOR> = vec![zeroed(); count as _];
+            if GetPhysicalMonitorsFromHMONITOR(hmonitor, count, physical.as_mut_ptr()) == 0 {
+                bail!("No physical monitor: {}", std::io::Error::last_os_error());
+            }
+            let value = if on { VCP_POWER_ON } else { VCP_POWER_OFF };
+            let ok = physical
+                .iter()
+                .all(|p| SetVCPFeature(p.hPhysicalMonitor, VCP_POWER_MODE, value) != 0);
+            let e = std::io::Error::last_os_error();
+            DestroyPhysicalMonitors(count, physical.as_mut_ptr());
+            if !ok {
+                bail!("Failed to set the power mode: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn broadcast_power(&mut self, on: bool) -> ResultType<()> {
+        let lparam = if on { MONITOR_ON } else { MONITOR_OFF };
+        unsafe {
+            if PostMessageW(HWND_BROADCAST, WM_SYSCOMMAND, SC_MONITORPOWER, lparam) == 0 {
+                bail!(
+                    "Failed to post SC_MONITORPOWER: {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+            if on {
+                // Some displays are not woken by SC_MONITORPOWER.
+                SetThreadExecutionState(ES_DISPLAY_REQUIRED);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The displays are powered off till dropped.
+pub struct DisplayPower {
+    tx_exit: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DisplayPower {
+    /// The warning is returned if any display may be left on, the displays are checked again
+    /// later anyway.
+    pub fn off() -> (Self, Option<String>) {
+        let mut blackout = Blackout::default();
+        let warning = match blackout.check(&mut WinMonitors) {
+            Ok(()) => None,
+            Err(e) => {
+                log::warn!("Failed to power off the displays: {}", e);
+                Some(DISPLAY_OFF_FAILED.to_owned())
+            }
+        };
+        let (tx_exit, rx_exit) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx_exit.recv_timeout(CHECK_INTERVAL) {
+                if let Err(e) = blackout.check(&mut WinMonitors) {
+                    log::debug!("Failed to power off the displays: {}", e);
+                }
+            }
+            blackout.restore(&mut WinMonitors);
+            log::info!("The displays are powered on");
+        });
+        (
+            Self {
+                tx_exit: Some(tx_exit),
+                thread: Some(thread),
+            },
+            warning,
+        )
+    }
+}
+
+impl Drop for DisplayPower {
+    fn drop(&mut self) {
+        self.tx_exit.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeMonitors {
+        list: Vec<&'static str>,
+        no_ddc: Vec<&'static str>,
+        broadcast_fails: bool,
+        on: HashSet<String>,
+        broadcast: Option<bool>,
+    }
+
+    impl Monitors for FakeMonitors {
+        fn list(&mut self) -> Vec<String> {
+            self.list.iter().map(|m| m.to_string()).collect()
+        }
+
+        fn set_power(&mut self, name: &str, on: bool) -> ResultType<()> {
+            if self.no_ddc.contains(&name) {
+                bail!("no ddc");
+            }
+            if on {
+                self.on.insert(name.to_owned());
+            } else {
+                self.on.remove(name);
+            }
+            Ok(())
+        }
+
+        fn broadcast_power(&mut self, on: bool) -> ResultType<()> {
+            if self.broadcast_fails {
+                bail!("failed");
+            }
+            self.broadcast = Some(on);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_hot_plug() {
+        let mut monitors = FakeMonitors {
+            list: vec!["1"],
+            ..Default::default()
+        };
+        monitors.on.insert("1".to_owned());
+        let mut blackout = Blackout::default();
+        blackout.check(&mut monitors).unwrap();
+        assert!(monitors.on.is_empty());
+        assert_eq!(monitors.broadcast, None);
+
+        // Plugged during the blackout.
+        monitors.list = vec!["1", "2"];
+        monitors.on.insert("2".to_owned());
+        blackout.check(&mut monitors).unwrap();
+        assert!(monitors.on.is_empty());
+
+        // Dropped, then back on.
+        monitors.list = vec!["1"];
+        blackout.check(&mut monitors).unwrap();
+        monitors.list = vec!["1", "2"];
+        monitors.on.insert("2".to_owned());
+        blackout.check(&mut monitors).unwrap();
+        assert!(monitors.on.is_empty());
+
+        // Plugged without DDC/CI.
+        monitors.list = vec!["1", "2", "3"];
+        monitors.no_ddc = vec!["3"];
+        blackout.check(&mut monitors).unwrap();
+        assert_eq!(monitors.broadcast, Some(false));
+        // Sent again, undone by the input.
+        monitors.broadcast = None;
+        blackout.check(&mut monitors).unwrap();
+        assert_eq!(monitors.broadcast, Some(false));
+
+        blackout.restore(&mut monitors);
+        assert_eq!(monitors.on.len(), 2);
+        assert_eq!(monitors.broadcast, Some(true));
+    }
+
+    #[test]
+    fn test_failed() {
+        let mut monitors = FakeMonitors {
+            list: vec!["1", "2"],
+            no_ddc: vec!["2"],
+            broadcast_fails: true,
+            ..Default::default()
+        };
+        let mut blackout = Blackout::default();
+        assert!(blackout.check(&mut monitors).is_err());
+        // The one powered off is restored.
+        blackout.restore(&mut monitors);
+        assert!(monitors.on.contains("1"));
+    }
+}
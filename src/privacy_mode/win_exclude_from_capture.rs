@@ -1,6 +1,8 @@
-use hbb_common::platform::windows::is_windows_version_or_greater;
-
-pub use super::win_topmost_window::PrivacyModeImpl;
+use super::{
+    win_display_power::DisplayPower, win_topmost_window, PrivacyMode, PrivacyModeState,
+    INVALID_PRIVACY_MODE_CONN_ID,
+};
+use hbb_common::{platform::windows::is_windows_version_or_greater, ResultType};
 
 pub(super) const PRIVACY_MODE_IMPL: &str = "privacy_mode_impl_exclude_from_capture";
 
@@ -9,3 +11,65 @@ pub(super) fn is_supported() -> bool {
     // https://en.wikipedia.org/wiki/Windows_10_version_history
     is_windows_version_or_greater(10, 0, 19041, 0, 0)
 }
+
+/// The topmost window, and the displays powered off on request, because the window only hides
+/// the screen from the capture.
+pub struct PrivacyModeImpl {
+    inner: win_topmost_window::PrivacyModeImpl,
+    display_power: Option<DisplayPower>,
+}
+
+impl PrivacyModeImpl {
+    pub fn new(impl_key: &str) -> Self {
+        Self {
+            inner: win_topmost_window::PrivacyModeImpl::new(impl_key),
+            display_power: None,
+        }
+    }
+}
+
+impl PrivacyMode for PrivacyModeImpl {
+    fn init(&self) -> ResultType<()> {
+        self.inner.init()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+        self.display_power.take();
+    }
+
+    fn turn_on_privacy(&mut self, conn_id: i32) -> ResultType<bool> {
+        self.inner.turn_on_privacy(conn_id)
+    }
+
+    fn turn_off_privacy(
+        &mut self,
+        conn_id: i32,
+        state: Option<PrivacyModeState>,
+    ) -> ResultType<()> {
+        let res = self.inner.turn_off_privacy(conn_id, state);
+        if self.inner.pre_conn_id() == INVALID_PRIVACY_MODE_CONN_ID {
+            self.display_power.take();
+        }
+        res
+    }
+
+    fn turn_off_displays(&mut self) -> Option<String> {
+        if self.display_power.is_some() {
+            return None;
+        }
+        let (display_power, warning) = DisplayPower::off();
+        self.display_power = Some(display_power);
+        warning
+    }
+
+    #[inline]
+    fn pre_conn_id(&self) -> i32 {
+        self.inner.pre_conn_id()
+    }
+
+    #[inline]
+    fn get_impl_key(&self) -> &str {
+        self.inner.get_impl_key()
+    }
+}
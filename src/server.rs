@@ -52,13 +52,31 @@ pub const NAME_POS: &'static str = "";
 }
 }
 
+pub mod access_schedule;
+pub mod annotation;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod cm_watch;
+pub mod conn_history;
 mod connection;
 pub mod display_service;
+mod frame_timestamps;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod host_display_state;
+mod host_sysinfo;
+pub mod input_policy;
+mod load_guard;
+pub mod peer_acl;
+mod permission_options;
 #[cfg(windows)]
 pub mod portable_service;
+pub mod print_forward;
+pub mod remote_volume;
 mod service;
+pub mod session_recording;
+pub mod tunnel;
 mod video_qos;
 pub mod video_service;
+mod viewport;
 
 pub type Childs = Arc<Mutex<Vec<std::process::Child>>>;
 type ConnMap = HashMap<i32, ConnInner>;
@@ -411,6 +429,7 @@ pub fn check_zombie() {
 #[cfg(any(target_os = "android", target_os = "ios"))]
 #[tokio::main]
 pub async fn start_server(_is_server: bool) {
+    std::thread::spawn(session_recording::cleanup);
     crate::RendezvousMediator::start_all().await;
 }
 
@@ -436,13 +455,24 @@ pub async fn start_server(is_server: bool) {
 
     if is_server {
         crate::common::set_server_running(true);
+        crate::state_journal::recover();
         std::thread::spawn(move || {
             if let Err(err) = crate::ipc::start("") {
                 log::error!("Failed to start ipc: {}", err);
-                std::process::exit(-1);
+                crate::common::add_startup_diagnostic(
+                    "ipc",
+                    "ipc_bind_failed",
+                    err.to_string(),
+                    "startup_ipc_tip",
+                );
+                // Keep the ui process alive to show the diagnostics.
+                if crate::is_server() {
+                    std::process::exit(-1);
+                }
             }
         });
         input_service::fix_key_down_timeout_loop();
+        std::thread::spawn(session_recording::cleanup);
         #[cfg(target_os = "linux")]
         if crate::platform::current_is_wayland() {
             allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
@@ -0,0 +1,480 @@
+//! The schedule of the unattended access, e.g. the work hours.
+//!
+//! The schedule is stored as json in the option [`OPTION_ACCESS_SCHEDULE`]. If enabled, the
+//! connections authenticated without the local user, i.e. by the password or the recent session,
+//! are allowed only in the time ranges, while the ones accepted in the cm are always allowed.
+//!
+//! The ranges are in the wall-clock time of the host, or of the fixed `utc_offset` if set. So a
+//! range follows the clock on the daylight-saving transitions: the skipped hour never matches and
+//! the repeated hour matches twice.
+//!
+//! If the option is set but can not be parsed, the unattended access is never allowed.
+
+use hbb_common::{anyhow::anyhow, bail, config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_ACCESS_SCHEDULE: &str = "access-schedule";
+pub const DEFAULT_GRACE_MINUTES: u32 = 5;
+/// The reason of the records in the connection history.
+pub const REASON_ACCESS_SCHEDULE: &str = "access_schedule";
+/// The last warning before the session is disconnected.
+pub const LAST_WARNING: Duration = Duration::from_secs(60);
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleRange {
+    /// The days from Monday, 0 to 6.
+    pub days: Vec<u32>,
+    /// "HH:MM", the range ending before the start continues to the next day.
+    pub start: String,
+    /// "HH:MM", "24:00" for the end of the day.
+    pub end: String,
+}
+
+#[inline]
+fn fixed_offset(minutes: i32) -> Option<chrono::FixedOffset> {
+    minutes
+        .checked_mul(60)
+        .and_then(chrono::FixedOffset::east_opt)
+}
+
+// The minutes of the day, "24:00" is allowed.
+fn parse_time(s: &str) -> Option<u32> {
+    let (h, m) = s.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    let minutes = h.checked_mul(60)?.checked_add(m)?;
+    (m < 60 && minutes <= MINUTES_PER_DAY).then_some(minutes)
+}
+
+impl ScheduleRange {
+    fn validate(&self) -> ResultType<()> {
+        if self.days.is_empty() || self.days.iter().any(|d| *d > 6) {
+            bail!("Invalid days of the schedule: {:?}", self.days);
+        }
+        let (Some(start), Some(end)) = (parse_time(&self.start), parse_time(&self.end)) else {
+            bail!(
+                "Invalid time of the schedule: {} - {}",
+                self.start,
+                self.end
+            );
+        };
+        if start == end || start == MINUTES_PER_DAY {
+            bail!("Empty range of the schedule: {} - {}", self.start, self.end);
+        }
+        Ok(())
+    }
+
+    /// `day` is from Monday, `minute` is of the day.
+    fn contains(&self, day: u32, minute: u32) -> bool {
+        let (Some(start), Some(end)) = (parse_time(&self.start), parse_time(&self.end)) else {
+            return false;
+        };
+        if start < end {
+            self.days.contains(&day) && start <= minute && minute < end
+        } else {
+            self.days.contains(&day) && start <= minute
+                || self.days.contains(&((day + 6) % 7)) && minute < end
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessSchedule {
+    pub enabled: bool,
+    /// The offset from UTC in minutes, the time zone of the host if none.
+    pub utc_offset: Option<i32>,
+    /// The unattended sessions are disconnected after the grace period outside the ranges.
+    pub grace_minutes: u32,
+    pub ranges: BTreeMap<String, ScheduleRange>,
+    // The option can not be parsed, the unattended access is never allowed.
+    #[serde(skip)]
+    invalid: bool,
+}
+
+impl Default for AccessSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            utc_offset: None,
+            grace_minutes: DEFAULT_GRACE_MINUTES,
+            ranges: Default::default(),
+            invalid: false,
+        }
+    }
+}
+
+impl AccessSchedule {
+    /// Load the schedule in the server process.
+    pub fn load() -> Self {
+        Self::parse(&Config::get_option(OPTION_ACCESS_SCHEDULE)).unwrap_or_else(|e| {
+            log::error!("Invalid access schedule, deny the unattended access: {}", e);
+            Self {
+                invalid: true,
+                ..Default::default()
+            }
+        })
+    }
+
+    fn parse(s: &str) -> ResultType<Self> {
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Whether the unattended access is allowed now, always if not enabled.
+    pub fn allows_now(&self) -> bool {
+        if self.invalid {
+            return false;
+        }
+        if !self.enabled {
+            return true;
+        }
+        match self.utc_offset {
+            Some(offset) => match fixed_offset(offset) {
+                Some(tz) => self.allows_at(&chrono::Utc::now().with_timezone(&tz)),
+                None => self.allows_at(&chrono::Local::now()),
+            },
+            None => self.allows_at(&chrono::Local::now()),
+        }
+    }
+
+    fn allows_at<Tz: chrono::TimeZone>(&self, t: &chrono::DateTime<Tz>) -> bool {
+        use chrono::{Datelike, Timelike};
+        let day = t.weekday().num_days_from_monday();
+        if self.invalid {
+            return false;
+        }
+        let minute = t.hour() * 60 + t.minute();
+        !self.enabled || self.ranges.values().any(|r| r.contains(day, minute))
+    }
+
+    #[inline]
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_minutes as u64 * 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraceAction {
+    None,
+    /// Warn the peer, with the time left.
+    Warn(Duration),
+    Disconnect,
+}
+
+/// The grace period of an unattended session outside the schedule.
+#[derive(Debug, Default)]
+pub struct GraceTimer {
+    since: Option<Instant>,
+    last_warned: bool,
+}
+
+impl GraceTimer {
+    /// Called periodically, the grace period is restarted once allowed again.
+    pub fn poll(&mut self, allowed: bool, grace: Duration, now: Instant) -> GraceAction {
+        if allowed {
+            *self = Self::default();
+            return GraceAction::None;
+        }
+        let Some(since) = self.since else {
+            self.since = Some(now);
+            self.last_warned = grace <= LAST_WARNING;
+            return GraceAction::Warn(grace);
+        };
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed >= grace {
+            return GraceAction::Disconnect;
+        }
+        let left = grace - elapsed;
+        if !self.last_warned && left <= LAST_WARNING {
+            self.last_warned = true;
+            return GraceAction::Warn(left);
+        }
+        GraceAction::None
+    }
+}
+
+// The ui side, the schedule is synced to the server by the options.
+//
+// The invalid option is not overwritten by the edits, it can only be cleared by
+// `reset_access_schedule`.
+fn load_local() -> ResultType<AccessSchedule> {
+    AccessSchedule::parse(&crate::ui_interface::get_option(
+        OPTION_ACCESS_SCHEDULE.to_owned(),
+    ))
+    .map_err(|e| anyhow!("Invalid access schedule, unattended access denied: {}", e))
+}
+
+fn store_local(schedule: &AccessSchedule) -> ResultType<()> {
+    let value = if *schedule == AccessSchedule::default() {
+        "".to_owned()
+    } else {
+        serde_json::to_string(schedule)?
+    };
+    crate::ui_interface::set_option(OPTION_ACCESS_SCHEDULE.to_owned(), value);
+    log::info!("Access schedule changed");
+    Ok(())
+}
+
+/// The schedule as json, with `error` if the option is invalid and the unattended access is
+/// denied.
+pub fn get_access_schedule() -> String {
+    match load_local() {
+        Ok(schedule) => serde_json::to_string(&schedule).unwrap_or_default(),
+        Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+    }
+}
+
+/// Remove the schedule, e.g. to recover from an invalid option.
+pub fn reset_access_schedule() -> ResultType<()> {
+    store_local(&AccessSchedule::default())
+}
+
+/// `utc_offset` is in minutes, the time zone of the host if none.
+pub fn set_access_schedule_options(
+    enabled: bool,
+    utc_offset: Option<i32>,
+    grace_minutes: u32,
+) -> ResultType<()> {
+    if let Some(offset) = utc_offset {
+        if fixed_offset(offset).is_none() {
+            bail!("Invalid utc offset: {}", offset);
+        }
+    }
+    let mut schedule = load_local()?;
+    schedule.enabled = enabled;
+    schedule.utc_offset = utc_offset;
+    schedule.grace_minutes = grace_minutes;
+    store_local(&schedule)
+}
+
+/// Add or replace the range, `range` is the json of [`ScheduleRange`].
+pub fn set_access_schedule_range(name: &str, range: &str) -> ResultType<()> {
+    if name.is_empty() {
+        bail!("Empty name of the schedule range");
+    }
+    let range: ScheduleRange = serde_json::from_str(range)?;
+    range.validate()?;
+    let mut schedule = load_local()?;
+    schedule.ranges.insert(name.to_owned(), range);
+    store_local(&schedule)
+}
+
+pub fn remove_access_schedule_range(name: &str) -> ResultType<bool> {
+    let mut schedule = load_local()?;
+    if schedule.ranges.remove(name).is_none() {
+        return Ok(false);
+    }
+    store_local(&schedule)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+    // US Eastern of 2024, the daylight-saving time is from 2024-03-10 02:00 EST to
+    // 2024-11-03 02:00 EDT.
+    #[derive(Debug, Clone, Copy)]
+    struct Eastern2024;
+
+    fn est() -> FixedOffset {
+        FixedOffset::west_opt(5 * 3600).unwrap()
+    }
+
+    fn edt() -> FixedOffset {
+        FixedOffset::west_opt(4 * 3600).unwrap()
+    }
+
+    fn utc(m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+    }
+
+    impl TimeZone for Eastern2024 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Self
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let offsets: Vec<_> = [est(), edt()]
+                .into_iter()
+                .filter(|o| {
+                    let utc = *local - chrono::Duration::seconds(o.local_minus_utc() as _);
+                    self.offset_from_utc_datetime(&utc) == *o
+                })
+                .collect();
+            match offsets[..] {
+                [o] => LocalResult::Single(o),
+                [a, b] => LocalResult::Ambiguous(a, b),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, t: &NaiveDateTime) -> FixedOffset {
+            if *t >= utc(3, 10, 7, 0) && *t < utc(11, 3, 6, 0) {
+                edt()
+            } else {
+                est()
+            }
+        }
+    }
+
+    fn eastern(m: u32, d: u32, h: u32, min: u32) -> DateTime<Eastern2024> {
+        Utc.from_utc_datetime(&utc(m, d, h, min))
+            .with_timezone(&Eastern2024)
+    }
+
+    fn schedule(ranges: &[(&str, &[u32], &str, &str)]) -> AccessSchedule {
+        AccessSchedule {
+            enabled: true,
+            ranges: ranges
+                .iter()
+                .map(|(name, days, start, end)| {
+                    let range = ScheduleRange {
+                        days: days.to_vec(),
+                        start: start.to_string(),
+                        end: end.to_string(),
+                    };
+                    range.validate().unwrap();
+                    (name.to_string(), range)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_schedule_ranges() {
+        // Mon-Fri 09:00-17:00, and Fri 22:00 to Sat 02:00.
+        let s = schedule(&[
+            ("work", &[0, 1, 2, 3, 4], "09:00", "17:00"),
+            ("night", &[4], "22:00", "02:00"),
+        ]);
+        let at = |m, d, h, min| s.allows_at(&Utc.from_utc_datetime(&utc(m, d, h, min)));
+        // 2024-01-05 is a Friday.
+        assert!(at(1, 5, 9, 0));
+        assert!(!at(1, 5, 17, 0));
+        assert!(at(1, 5, 23, 0));
+        assert!(at(1, 6, 1, 59));
+        assert!(!at(1, 6, 2, 0));
+        assert!(!at(1, 6, 10, 0));
+        // Sunday night is not continued.
+        assert!(!at(1, 8, 1, 0));
+
+        let disabled = AccessSchedule {
+            enabled: false,
+            ..s.clone()
+        };
+        assert!(disabled.allows_at(&Utc.from_utc_datetime(&utc(1, 6, 10, 0))));
+        assert_eq!(
+            AccessSchedule::parse(&serde_json::to_string(&s).unwrap()).unwrap(),
+            s
+        );
+        assert!(AccessSchedule::parse("not json").is_err());
+        let invalid = AccessSchedule {
+            invalid: true,
+            ..Default::default()
+        };
+        assert!(!invalid.allows_now());
+        assert!(!invalid.allows_at(&Utc.from_utc_datetime(&utc(1, 6, 10, 0))));
+        assert_eq!(parse_time("4294967295:00"), None);
+        for (start, end) in [("09:00", "09:00"), ("24:00", "01:00"), ("9:60", "10:00")] {
+            let range = ScheduleRange {
+                days: vec![0],
+                start: start.to_owned(),
+                end: end.to_owned(),
+            };
+            assert!(range.validate().is_err());
+        }
+        assert!(ScheduleRange {
+            days: vec![7],
+            start: "00:00".to_owned(),
+            end: "24:00".to_owned(),
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_schedule_dst_spring_forward() {
+        // 2024-03-09 is a Saturday, 2024-03-10 a Sunday.
+        let s = schedule(&[
+            ("weekend", &[5, 6], "09:00", "17:00"),
+            ("early", &[6], "01:00", "02:30"),
+        ]);
+        // 09:00 is 14:00 UTC in EST, 13:00 UTC in EDT.
+        assert!(!s.allows_at(&eastern(3, 9, 13, 30)));
+        assert!(s.allows_at(&eastern(3, 9, 14, 0)));
+        assert!(s.allows_at(&eastern(3, 10, 13, 0)));
+        assert!(!s.allows_at(&eastern(3, 10, 21, 0)));
+        // 01:59 EST, then 03:00 EDT, the skipped 02:00-02:30 never matches.
+        assert!(s.allows_at(&eastern(3, 10, 6, 59)));
+        assert!(!s.allows_at(&eastern(3, 10, 7, 0)));
+    }
+
+    #[test]
+    fn test_schedule_dst_fall_back() {
+        // 2024-11-03 is a Sunday.
+        let s = schedule(&[("night", &[6], "00:00", "01:30")]);
+        // 01:15 EDT, then 01:15 EST again, both match.
+        assert!(s.allows_at(&eastern(11, 3, 5, 15)));
+        assert!(s.allows_at(&eastern(11, 3, 6, 15)));
+        // 01:45 EDT and 01:45 EST, neither matches.
+        assert!(!s.allows_at(&eastern(11, 3, 5, 45)));
+        assert!(!s.allows_at(&eastern(11, 3, 6, 45)));
+        // The range is 2.5 hours long in UTC on the day.
+        assert!(s.allows_at(&eastern(11, 3, 4, 0)));
+        assert!(!s.allows_at(&eastern(11, 3, 3, 59)));
+    }
+
+    #[test]
+    fn test_grace_timer() {
+        let grace = Duration::from_secs(300);
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+        let mut timer = GraceTimer::default();
+        assert_eq!(timer.poll(true, grace, start), GraceAction::None);
+        assert_eq!(timer.poll(false, grace, secs(1)), GraceAction::Warn(grace));
+        assert_eq!(timer.poll(false, grace, secs(200)), GraceAction::None);
+        assert_eq!(
+            timer.poll(false, grace, secs(241)),
+            GraceAction::Warn(Duration::from_secs(60))
+        );
+        assert_eq!(timer.poll(false, grace, secs(250)), GraceAction::None);
+        assert_eq!(timer.poll(false, grace, secs(301)), GraceAction::Disconnect);
+        // Restarted once allowed again.
+        assert_eq!(timer.poll(true, grace, secs(302)), GraceAction::None);
+        assert_eq!(
+            timer.poll(false, grace, secs(303)),
+            GraceAction::Warn(grace)
+        );
+
+        // A short grace period is warned once.
+        let mut timer = GraceTimer::default();
+        let grace = Duration::from_secs(30);
+        assert_eq!(timer.poll(false, grace, start), GraceAction::Warn(grace));
+        assert_eq!(timer.poll(false, grace, secs(10)), GraceAction::None);
+        assert_eq!(timer.poll(false, grace, secs(30)), GraceAction::Disconnect);
+    }
+}
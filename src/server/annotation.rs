@@ -0,0 +1,356 @@
+//! The strokes drawn by the controller on the screen of the host, e.g. a trainer circling a button
+//! for the person at the host.
+//!
+//! The points of the strokes are in the coordinates of the mouse events, i.e. the origin of the
+//! display is added by the client, and they're clipped to the display of the stroke. The strokes
+//! are removed once their ttl passes, or cleared by the controller, and each one is acked with the
+//! error if it's not drawn.
+//!
+//! The strokes are drawn by the [`Overlay`] of the platform, a transparent window above the others
+//! which doesn't take the input. Only Windows has one yet, [`new_overlay`] fails elsewhere.
+//! Allowed by the host with [`OPTION_ENABLE_REMOTE_ANNOTATION`], turning it off in the cm clears
+//! the strokes.
+
+use hbb_common::{
+    log,
+    message_proto::{
+        annotation, annotation_stroke::Shape, Annotation, AnnotationAck, AnnotationStroke,
+        DisplayInfo,
+    },
+    ResultType,
+};
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+mod win_overlay;
+
+pub const OPTION_ENABLE_REMOTE_ANNOTATION: &str = "enable-remote-annotation";
+/// The ttl of the strokes not given one.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5);
+pub const MAX_TTL: Duration = Duration::from_secs(60);
+/// The oldest strokes are removed once more are drawn.
+pub const MAX_STROKES: usize = 100;
+pub const MAX_POINTS: usize = 4096;
+pub const MAX_WIDTH: u32 = 32;
+const ARROW_HEAD_MIN: f64 = 16.0;
+
+pub const ERR_NOT_ALLOWED: &str = "Annotation is not allowed";
+pub const ERR_UNSUPPORTED: &str = "Annotation is not supported on this platform";
+pub const ERR_NO_DISPLAY: &str = "No such display";
+pub const ERR_INVALID_STROKE: &str = "Invalid stroke";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub id: u32,
+    pub shape: Shape,
+    /// The corners of the rectangle, or the tail and the head of the arrow.
+    pub points: Vec<(i32, i32)>,
+    /// ARGB
+    pub color: u32,
+    pub width: u32,
+    pub expire_at: Instant,
+}
+
+/// The window drawing the strokes.
+pub trait Overlay: Send {
+    /// Draw `strokes` in place of the ones drawn before, hidden if empty.
+    fn draw(&mut self, strokes: &[Stroke]) -> ResultType<()>;
+}
+
+pub fn new_overlay() -> ResultType<Box<dyn Overlay>> {
+    #[cfg(windows)]
+    return Ok(Box::new(win_overlay::WinOverlay::new()?));
+    #[cfg(not(windows))]
+    hbb_common::bail!(ERR_UNSUPPORTED);
+}
+
+/// The stroke of the peer, clipped to `display`.
+pub fn to_stroke(
+    stroke: &AnnotationStroke,
+    display: &DisplayInfo,
+    now: Instant,
+) -> Result<Stroke, &'static str> {
+    let shape = stroke.shape.enum_value().map_err(|_| ERR_INVALID_STROKE)?;
+    if stroke.points.len() % 2 != 0 || stroke.points.len() / 2 > MAX_POINTS {
+        return Err(ERR_INVALID_STROKE);
+    }
+    let clip = |v: i32, min: i32, len: i32| v.clamp(min, min + (len - 1).max(0));
+    let points: Vec<_> = stroke
+        .points
+        .chunks(2)
+        .map(|p| {
+            (
+                clip(p[0], display.x, display.width),
+                clip(p[1], display.y, display.height),
+            )
+        })
+        .collect();
+    let valid = match shape {
+        Shape::Freehand => points.len() >= 2,
+        Shape::Rectangle | Shape::Arrow => points.len() == 2,
+    };
+    if !valid {
+        return Err(ERR_INVALID_STROKE);
+    }
+    let ttl = if stroke.ttl_ms == 0 {
+        DEFAULT_TTL
+    } else {
+        MAX_TTL.min(Duration::from_millis(stroke.ttl_ms as _))
+    };
+    Ok(Stroke {
+        id: stroke.id,
+        shape,
+        points,
+        color: stroke.color,
+        width: stroke.width.clamp(1, MAX_WIDTH),
+        expire_at: now + ttl,
+    })
+}
+
+/// The ends of the two lines of the head of the arrow from `tail` to `head`.
+pub fn arrow_wings(tail: (i32, i32), head: (i32, i32), width: u32) -> [(i32, i32); 2] {
+    let len = ARROW_HEAD_MIN.max(width as f64 * 4.0);
+    let angle = ((tail.1 - head.1) as f64).atan2((tail.0 - head.0) as f64);
+    let wing = |a: f64| {
+        (
+            (head.0 as f64 + len * a.cos()).round() as i32,
+            (head.1 as f64 + len * a.sin()).round() as i32,
+        )
+    };
+    let spread = std::f64::consts::PI / 6.0;
+    [wing(angle + spread), wing(angle - spread)]
+}
+
+type OverlayFactory = Box<dyn Fn() -> ResultType<Box<dyn Overlay>> + Send>;
+
+/// The strokes of a connection, the overlay is created with the first one.
+pub struct Annotations {
+    strokes: Vec<Stroke>,
+    overlay: Option<Box<dyn Overlay>>,
+    new_overlay: OverlayFactory,
+}
+
+impl Default for Annotations {
+    fn default() -> Self {
+        Self::with_overlay(Box::new(new_overlay))
+    }
+}
+
+impl Annotations {
+    pub fn with_overlay(new_overlay: OverlayFactory) -> Self {
+        Self {
+            strokes: vec![],
+            overlay: None,
+            new_overlay,
+        }
+    }
+
+    /// Handle the message of the peer, returns the ack to reply.
+    /// `display` is the display info of the index, see `display_service::get_display_info`.
+    pub fn on_peer_message(
+        &mut self,
+        msg: Annotation,
+        allowed: bool,
+        display: impl Fn(usize) -> Option<DisplayInfo>,
+        now: Instant,
+    ) -> Option<Annotation> {
+        match msg.union {
+            Some(annotation::Union::Stroke(stroke)) => {
+                let error = if !allowed {
+                    ERR_NOT_ALLOWED.to_owned()
+                } else if let Some(display) = display(stroke.display as usize) {
+                    self.add(&stroke, &display, now).err().unwrap_or_default()
+                } else {
+                    ERR_NO_DISPLAY.to_owned()
+                };
+                let mut msg = Annotation::new();
+                msg.set_ack(AnnotationAck {
+                    id: stroke.id,
+                    error,
+                    ..Default::default()
+                });
+                Some(msg)
+            }
+            Some(annotation::Union::Clear(_)) => {
+                self.clear();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // The stroke of the same id is replaced, e.g. the freehand one growing while drawn.
+    fn add(
+        &mut self,
+        stroke: &AnnotationStroke,
+        display: &DisplayInfo,
+        now: Instant,
+    ) -> Result<(), String> {
+        let stroke = to_stroke(stroke, display, now)?;
+        if self.overlay.is_none() {
+            self.overlay = Some((self.new_overlay)().map_err(|e| e.to_string())?);
+        }
+        self.strokes.retain(|s| s.id != stroke.id);
+        self.strokes.push(stroke);
+        if self.strokes.len() > MAX_STROKES {
+            self.strokes.remove(0);
+        }
+        self.redraw().map_err(|e| e.to_string())
+    }
+
+    fn redraw(&mut self) -> ResultType<()> {
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.draw(&self.strokes)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the expired strokes, called on the timer of the connection.
+    pub fn expire(&mut self, now: Instant) {
+        let len = self.strokes.len();
+        self.strokes.retain(|s| s.expire_at > now);
+        if self.strokes.len() != len {
+            if let Err(e) = self.redraw() {
+                log::error!("Failed to redraw the annotations: {}", e);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.strokes.is_empty() {
+            return;
+        }
+        self.strokes.clear();
+        if let Err(e) = self.redraw() {
+            log::error!("Failed to clear the annotations: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::bail;
+    use std::sync::{Arc, Mutex};
+
+    fn display() -> DisplayInfo {
+        DisplayInfo {
+            x: 1920,
+            y: 0,
+            width: 1280,
+            height: 720,
+            ..Default::default()
+        }
+    }
+
+    fn stroke(id: u32, shape: Shape, points: Vec<i32>) -> AnnotationStroke {
+        AnnotationStroke {
+            id,
+            shape: shape.into(),
+            points,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_to_stroke() {
+        let now = Instant::now();
+        let s = stroke(1, Shape::Arrow, vec![1900, -5, 2000, 800]);
+        let s = to_stroke(&s, &display(), now).unwrap();
+        // Clipped to the display.
+        assert_eq!(s.points, vec![(1920, 0), (2000, 719)]);
+        assert_eq!(s.width, 1);
+        assert_eq!(s.expire_at, now + DEFAULT_TTL);
+
+        let mut s = stroke(2, Shape::Freehand, vec![2000, 10, 2001, 11]);
+        s.ttl_ms = 3600 * 1000;
+        s.width = 100;
+        let s = to_stroke(&s, &display(), now).unwrap();
+        assert_eq!(s.expire_at, now + MAX_TTL);
+        assert_eq!(s.width, MAX_WIDTH);
+
+        let invalid = [
+            stroke(3, Shape::Freehand, vec![2000, 10]),
+            stroke(4, Shape::Rectangle, vec![2000, 10, 2001, 11, 2002]),
+            stroke(5, Shape::Rectangle, vec![2000, 10, 2001, 11, 2002, 12]),
+        ];
+        for s in invalid.iter() {
+            assert_eq!(to_stroke(s, &display(), now), Err(ERR_INVALID_STROKE));
+        }
+    }
+
+    #[test]
+    fn test_arrow_wings() {
+        assert_eq!(arrow_wings((0, 0), (100, 0), 1), [(86, -8), (86, 8)]);
+        assert_eq!(arrow_wings((0, 0), (0, 100), 1), [(8, 86), (-8, 86)]);
+    }
+
+    struct FakeOverlay(Arc<Mutex<Vec<Vec<u32>>>>);
+
+    impl Overlay for FakeOverlay {
+        fn draw(&mut self, strokes: &[Stroke]) -> ResultType<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(strokes.iter().map(|s| s.id).collect());
+            Ok(())
+        }
+    }
+
+    fn ack_error(msg: Option<Annotation>) -> String {
+        msg.unwrap().ack().error.clone()
+    }
+
+    #[test]
+    fn test_annotations() {
+        let draws: Arc<Mutex<Vec<Vec<u32>>>> = Default::default();
+        let cloned = draws.clone();
+        let mut annotations =
+            Annotations::with_overlay(Box::new(move || -> ResultType<Box<dyn Overlay>> {
+                Ok(Box::new(FakeOverlay(cloned.clone())))
+            }));
+        let now = Instant::now();
+        let displays = |i: usize| if i == 0 { Some(display()) } else { None };
+        let msg = |s: AnnotationStroke| {
+            let mut msg = Annotation::new();
+            msg.set_stroke(s);
+            msg
+        };
+
+        let s = stroke(1, Shape::Rectangle, vec![2000, 10, 2100, 110]);
+        let ack = annotations.on_peer_message(msg(s.clone()), false, displays, now);
+        assert_eq!(ack_error(ack), ERR_NOT_ALLOWED);
+        let ack = annotations.on_peer_message(msg(s.clone()), true, displays, now);
+        assert_eq!(ack_error(ack), "");
+        // Replaced by the same id.
+        let ack = annotations.on_peer_message(msg(s), true, displays, now);
+        assert_eq!(ack_error(ack), "");
+        let mut s = stroke(2, Shape::Arrow, vec![2000, 10, 2100, 110]);
+        s.ttl_ms = 60 * 1000;
+        let ack = annotations.on_peer_message(msg(s.clone()), true, displays, now);
+        assert_eq!(ack_error(ack), "");
+        s.display = 1;
+        let ack = annotations.on_peer_message(msg(s), true, displays, now);
+        assert_eq!(ack_error(ack), ERR_NO_DISPLAY);
+        assert_eq!(*draws.lock().unwrap(), vec![vec![1], vec![1], vec![1, 2]]);
+
+        annotations.expire(now + DEFAULT_TTL);
+        let mut clear = Annotation::new();
+        clear.set_clear(true);
+        assert!(annotations
+            .on_peer_message(clear, true, displays, now)
+            .is_none());
+        // Nothing to clear.
+        annotations.clear();
+        let expected = vec![vec![1], vec![1], vec![1, 2], vec![2], vec![]];
+        assert_eq!(*draws.lock().unwrap(), expected);
+
+        let mut annotations =
+            Annotations::with_overlay(Box::new(|| -> ResultType<Box<dyn Overlay>> {
+                bail!(ERR_UNSUPPORTED)
+            }));
+        let s = stroke(3, Shape::Freehand, vec![2000, 10, 2100, 110]);
+        let ack = annotations.on_peer_message(msg(s), true, displays, now);
+        assert_eq!(ack_error(ack), ERR_UNSUPPORTED);
+    }
+}
@@ -0,0 +1,227 @@
+use super::{arrow_wings, Overlay, Stroke};
+use hbb_common::{bail, log, message_proto::annotation_stroke::Shape, ResultType};
+use std::{
+    mem::{size_of, zeroed},
+    ptr::{null, null_mut},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+use winapi::{
+    shared::{
+        minwindef::{LPARAM, LRESULT, TRUE, UINT, WPARAM},
+        windef::{COLORREF, HWND, POINT, RECT},
+        winerror::ERROR_CLASS_ALREADY_EXISTS,
+    },
+    um::{errhandlingapi::GetLastError, libloaderapi::GetModuleHandleW, wingdi::*, winuser::*},
+};
+
+const CLASS_NAME: &str = "RustDeskAnnotationOverlay";
+// The pixels of the key color are transparent, so the black strokes are drawn a little lighter.
+const COLOR_KEY: COLORREF = 0;
+const WM_REDRAW: UINT = WM_USER + 1;
+
+type Shared = Arc<Mutex<Vec<Stroke>>>;
+
+/// A layered window over the virtual screen, on its own thread, ignoring the input.
+pub struct WinOverlay {
+    // HWND is not Send.
+    hwnd: usize,
+    strokes: Shared,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WinOverlay {
+    pub fn new() -> ResultType<Self> {
+        let strokes: Shared = Default::default();
+        let cloned = strokes.clone();
+        let (tx, rx) = mpsc::channel();
+        let thread = std::thread::spawn(move || unsafe { run(cloned, tx) });
+        match rx.recv() {
+            Ok(Ok(hwnd)) => Ok(Self {
+                hwnd,
+                strokes,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => bail!(e),
+            Err(_) => bail!("The thread of the annotation overlay exited"),
+        }
+    }
+}
+
+impl Overlay for WinOverlay {
+    fn draw(&mut self, strokes: &[Stroke]) -> ResultType<()> {
+        *self.strokes.lock().unwrap() = strokes.to_vec();
+        if unsafe { PostMessageW(self.hwnd as _, WM_REDRAW, 0, 0) } == 0 {
+            bail!(
+                "Failed to redraw the annotation overlay: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WinOverlay {
+    fn drop(&mut self) {
+        unsafe {
+            PostMessageW(self.hwnd as _, WM_CLOSE, 0, 0);
+        }
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+unsafe fn virtual_screen() -> (i32, i32, i32, i32) {
+    (
+        GetSystemMetrics(SM_XVIRTUALSCREEN),
+        GetSystemMetrics(SM_YVIRTUALSCREEN),
+        GetSystemMetrics(SM_CXVIRTUALSCREEN),
+        GetSystemMetrics(SM_CYVIRTUALSCREEN),
+    )
+}
+
+unsafe fn run(strokes: Shared, tx: mpsc::Sender<Result<usize, String>>) {
+    let hinstance = GetModuleHandleW(null());
+    let class_name = wide(CLASS_NAME);
+    let mut wc: WNDCLASSEXW = zeroed();
+    wc.cbSize = size_of::<WNDCLASSEXW>() as _;
+    wc.lpfnWndProc = Some(wnd_proc);
+    wc.hInstance = hinstance;
+    wc.lpszClassName = class_name.as_ptr();
+    // Registered by the overlay of another connection.
+    if RegisterClassExW(&wc) == 0 && GetLastError() != ERROR_CLASS_ALREADY_EXISTS {
+        let e = std::io::Error::last_os_error();
+        tx.send(Err(format!("Failed to register the overlay class: {}", e)))
+            .ok();
+        return;
+    }
+    let (x, y, w, h) = virtual_screen();
+    let hwnd = CreateWindowExW(
+        WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+        class_name.as_ptr(),
+        class_name.as_ptr(),
+        WS_POPUP,
+        x,
+        y,
+        w,
+        h,
+        null_mut(),
+        null_mut(),
+        hinstance,
+        null_mut(),
+    );
+    if hwnd.is_null() {
+        let e = std::io::Error::last_os_error();
+        tx.send(Err(format!("Failed to create the overlay: {}", e)))
+            .ok();
+        return;
+    }
+    SetLayeredWindowAttributes(hwnd, COLOR_KEY, 0, LWA_COLORKEY);
+    let ptr = Box::into_raw(Box::new(strokes));
+    SetWindowLongPtrW(hwnd, GWLP_USERDATA, ptr as _);
+    tx.send(Ok(hwnd as usize)).ok();
+
+    let mut msg: MSG = zeroed();
+    while GetMessageW(&mut msg, null_mut(), 0, 0) > 0 {
+        TranslateMessage(&msg);
+        DispatchMessageW(&msg);
+    }
+    drop(Box::from_raw(ptr));
+    log::debug!("The annotation overlay exited");
+}
+
+unsafe fn shared<'a>(hwnd: HWND) -> Option<&'a Shared> {
+    (GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const Shared).as_ref()
+}
+
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_REDRAW => {
+            let empty = shared(hwnd)
+                .map(|s| s.lock().unwrap().is_empty())
+                .unwrap_or(true);
+            ShowWindow(hwnd, if empty { SW_HIDE } else { SW_SHOWNOACTIVATE });
+            InvalidateRect(hwnd, null(), TRUE);
+            0
+        }
+        // A display is added, removed or moved.
+        WM_DISPLAYCHANGE => {
+            let (x, y, w, h) = virtual_screen();
+            SetWindowPos(hwnd, HWND_TOPMOST, x, y, w, h, SWP_NOACTIVATE);
+            0
+        }
+        WM_PAINT => {
+            paint(hwnd);
+            0
+        }
+        WM_DESTROY => {
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+            PostQuitMessage(0);
+            0
+        }
+        _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+}
+
+fn colorref(argb: u32) -> COLORREF {
+    let (r, g, b) = ((argb >> 16) & 0xFF, (argb >> 8) & 0xFF, argb & 0xFF);
+    let color = r | (g << 8) | (b << 16);
+    if color == COLOR_KEY {
+        0x00010101
+    } else {
+        color
+    }
+}
+
+unsafe fn paint(hwnd: HWND) {
+    let mut ps: PAINTSTRUCT = zeroed();
+    let hdc = BeginPaint(hwnd, &mut ps);
+    let mut rect: RECT = zeroed();
+    GetClientRect(hwnd, &mut rect);
+    FillRect(hdc, &rect, GetStockObject(BLACK_BRUSH as _) as _);
+    // The strokes are in the coordinates of the virtual screen.
+    let mut origin: RECT = zeroed();
+    GetWindowRect(hwnd, &mut origin);
+    let point = |(x, y): (i32, i32)| POINT {
+        x: x - origin.left,
+        y: y - origin.top,
+    };
+    if let Some(strokes) = shared(hwnd) {
+        let old_brush = SelectObject(hdc, GetStockObject(NULL_BRUSH as _));
+        for stroke in strokes.lock().unwrap().iter() {
+            let pen = CreatePen(PS_SOLID as _, stroke.width as _, colorref(stroke.color));
+            let old_pen = SelectObject(hdc, pen as _);
+            let points: Vec<POINT> = stroke.points.iter().map(|p| point(*p)).collect();
+            match stroke.shape {
+                Shape::Freehand => {
+                    Polyline(hdc, points.as_ptr(), points.len() as _);
+                }
+                Shape::Rectangle => {
+                    let (a, b) = (points[0], points[1]);
+                    Rectangle(hdc, a.x.min(b.x), a.y.min(b.y), a.x.max(b.x), a.y.max(b.y));
+                }
+                Shape::Arrow => {
+                    let (tail, head) = (stroke.points[0], stroke.points[1]);
+                    let [left, right] = arrow_wings(tail, head, stroke.width);
+                    Polyline(hdc, points.as_ptr(), 2);
+                    let wings = [point(left), point(head), point(right)];
+                    Polyline(hdc, wings.as_ptr(), 3);
+                }
+            }
+            SelectObject(hdc, old_pen);
+            DeleteObject(pen as _);
+        }
+        SelectObject(hdc, old_brush);
+    }
+    EndPaint(hwnd, &ps);
+}
@@ -0,0 +1,277 @@
+//! Let the local user watch what a controller sees, a picture-in-picture in the cm.
+//!
+//! The cm subscribes to a connection by `ipc::Data::CmWatch`. The video threads take a downscaled
+//! copy of the frames sent to the connection, at most [`MAX_FPS`] per display and within
+//! [`MAX_WIDTH`] x [`MAX_HEIGHT`], so the copy is bounded and no second capture is made. The
+//! copies are encoded to jpeg by a worker thread, then sent to the cm by
+//! `ipc::Data::CmWatchFrame`. Nothing is sent to the peer.
+//!
+//! The watch ends when the cm unsubscribes or the connection is closed. Only the frames in BGRA
+//! or RGBA are copied, e.g. not the textures of the hardware capture.
+
+use crate::ipc::Data;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hbb_common::{lazy_static, log, tokio::sync::mpsc::UnboundedSender, ResultType};
+use scrap::{Pixfmt, TraitFrame};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const MAX_FPS: u32 = 5;
+pub const MAX_WIDTH: usize = 854;
+pub const MAX_HEIGHT: usize = 480;
+const JPEG_QUALITY: u8 = 60;
+// The copies waiting for the worker, the later ones are dropped if it's busy.
+const WORKER_QUEUE_SIZE: usize = 2;
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // conn id -> the watch of the cm
+    static ref WATCHES: Mutex<HashMap<i32, Watch>> = Default::default();
+    static ref WORKER: Mutex<Option<SyncSender<Job>>> = Default::default();
+}
+
+struct Watch {
+    tx: UnboundedSender<Data>,
+    // display -> the time of the last copy
+    last: HashMap<usize, Instant>,
+}
+
+struct Job {
+    display: usize,
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+    txs: Vec<UnboundedSender<Data>>,
+}
+
+#[inline]
+fn interval() -> Duration {
+    Duration::from_millis(1000 / MAX_FPS as u64)
+}
+
+/// The cm watches the connection by `tx`, or stops watching if `None`.
+pub fn set(conn_id: i32, tx: Option<UnboundedSender<Data>>) {
+    let mut watches = WATCHES.lock().unwrap();
+    match tx {
+        Some(tx) => {
+            log::info!("The cm watches conn {}", conn_id);
+            watches.insert(
+                conn_id,
+                Watch {
+                    tx,
+                    last: Default::default(),
+                },
+            );
+        }
+        None => {
+            if watches.remove(&conn_id).is_some() {
+                log::info!("The cm stops watching conn {}", conn_id);
+            }
+        }
+    }
+    WATCHING.store(!watches.is_empty(), Ordering::SeqCst);
+}
+
+#[inline]
+pub fn remove(conn_id: i32) {
+    set(conn_id, None);
+}
+
+/// The frame of the display is sent to the connections, called by the video threads.
+pub fn on_frame(display: usize, conn_ids: &HashSet<i32>, frame: &impl TraitFrame) {
+    if !WATCHING.load(Ordering::SeqCst) {
+        return;
+    }
+    let txs = due(display, conn_ids, Instant::now());
+    if txs.is_empty() {
+        return;
+    }
+    let Some((width, height, rgb)) = downscale(frame) else {
+        return;
+    };
+    send_job(Job {
+        display,
+        width,
+        height,
+        rgb,
+        txs,
+    });
+}
+
+// The watches of the connections due for a copy of the display.
+fn due(display: usize, conn_ids: &HashSet<i32>, now: Instant) -> Vec<UnboundedSender<Data>> {
+    let mut watches = WATCHES.lock().unwrap();
+    conn_ids
+        .iter()
+        .filter_map(|id| {
+            let watch = watches.get_mut(id)?;
+            let last = watch.last.get(&display);
+            if last.map_or(false, |t| now.saturating_duration_since(*t) < interval()) {
+                return None;
+            }
+            watch.last.insert(display, now);
+            Some(watch.tx.clone())
+        })
+        .collect()
+}
+
+// Scale the frame to fit in the max size by the nearest pixels, into rgb.
+fn downscale(frame: &impl TraitFrame) -> Option<(usize, usize, Vec<u8>)> {
+    let (r, b) = match frame.pixfmt() {
+        Pixfmt::BGRA => (2, 0),
+        Pixfmt::RGBA => (0, 2),
+        _ => return None,
+    };
+    let (sw, sh) = (frame.width(), frame.height());
+    let stride = frame.stride().first().cloned().unwrap_or_default();
+    let src = frame.data();
+    if sw == 0 || sh == 0 || stride < sw * 4 || src.len() < stride * sh {
+        return None;
+    }
+    let scale = (MAX_WIDTH as f64 / sw as f64)
+        .min(MAX_HEIGHT as f64 / sh as f64)
+        .min(1.);
+    let w = ((sw as f64 * scale).round() as usize).clamp(1, MAX_WIDTH);
+    let h = ((sh as f64 * scale).round() as usize).clamp(1, MAX_HEIGHT);
+    let mut rgb = Vec::with_capacity(w * h * 3);
+    for y in 0..h {
+        let row = &src[y * sh / h * stride..];
+        for x in 0..w {
+            let p = &row[x * sw / w * 4..];
+            rgb.extend_from_slice(&[p[r], p[1], p[b]]);
+        }
+    }
+    Some((w, h, rgb))
+}
+
+fn send_job(job: Job) {
+    let mut lock = WORKER.lock().unwrap();
+    let tx = lock.get_or_insert_with(|| {
+        let (tx, rx) = sync_channel(WORKER_QUEUE_SIZE);
+        std::thread::spawn(move || run_worker(rx));
+        tx
+    });
+    match tx.try_send(job) {
+        Ok(()) | Err(TrySendError::Full(_)) => {}
+        Err(TrySendError::Disconnected(_)) => *lock = None,
+    }
+}
+
+fn run_worker(rx: Receiver<Job>) {
+    while let Ok(job) = rx.recv() {
+        let data = match encode_jpeg(job.width, job.height, &job.rgb) {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("Failed to encode the watch frame: {}", e);
+                continue;
+            }
+        };
+        for tx in job.txs {
+            tx.send(Data::CmWatchFrame {
+                display: job.display as _,
+                width: job.width,
+                height: job.height,
+                data: data.clone(),
+            })
+            .ok();
+        }
+    }
+}
+
+fn encode_jpeg(width: usize, height: usize, rgb: &[u8]) -> ResultType<String> {
+    let mut buf = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, JPEG_QUALITY).encode(
+        rgb,
+        width as _,
+        height as _,
+        image::ColorType::Rgb8,
+    )?;
+    Ok(STANDARD.encode(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::tokio::sync::mpsc;
+
+    struct TestFrame {
+        data: Vec<u8>,
+        width: usize,
+        height: usize,
+        pixfmt: Pixfmt,
+    }
+
+    impl TraitFrame for TestFrame {
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+
+        fn width(&self) -> usize {
+            self.width
+        }
+
+        fn height(&self) -> usize {
+            self.height
+        }
+
+        fn stride(&self) -> Vec<usize> {
+            vec![self.width * 4]
+        }
+
+        fn pixfmt(&self) -> Pixfmt {
+            self.pixfmt
+        }
+    }
+
+    fn frame(width: usize, height: usize, pixfmt: Pixfmt) -> TestFrame {
+        TestFrame {
+            // b, g, r, a in BGRA
+            data: [10u8, 20, 30, 255].repeat(width * height),
+            width,
+            height,
+            pixfmt,
+        }
+    }
+
+    #[test]
+    fn test_downscale() {
+        let (w, h, rgb) = downscale(&frame(3840, 2160, Pixfmt::BGRA)).unwrap();
+        assert_eq!((w, h), (853, 480));
+        assert_eq!(rgb.len(), w * h * 3);
+        assert_eq!(&rgb[..3], &[30, 20, 10]);
+        let (w, h, rgb) = downscale(&frame(1080, 2400, Pixfmt::RGBA)).unwrap();
+        assert_eq!((w, h), (216, 480));
+        assert_eq!(&rgb[..3], &[10, 20, 30]);
+        // Never upscaled.
+        let (w, h, _) = downscale(&frame(320, 200, Pixfmt::BGRA)).unwrap();
+        assert_eq!((w, h), (320, 200));
+        assert!(downscale(&frame(320, 200, Pixfmt::I420)).is_none());
+        let mut short = frame(320, 200, Pixfmt::BGRA);
+        short.data.truncate(100);
+        assert!(downscale(&short).is_none());
+    }
+
+    #[test]
+    fn test_due() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        set(9101, Some(tx));
+        assert!(WATCHING.load(Ordering::SeqCst));
+        let ids: HashSet<i32> = [9101, 9102].into_iter().collect();
+        let now = Instant::now();
+        assert_eq!(due(0, &ids, now).len(), 1);
+        // At most `MAX_FPS` per display.
+        assert!(due(0, &ids, now + interval() / 2).is_empty());
+        assert_eq!(due(1, &ids, now + interval() / 2).len(), 1);
+        assert_eq!(due(0, &ids, now + interval()).len(), 1);
+        remove(9101);
+        assert!(due(0, &ids, now + interval() * 10).is_empty());
+    }
+}
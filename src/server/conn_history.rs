@@ -0,0 +1,117 @@
+//! The history of the incoming connections refused or closed by the policies of the host, e.g.
+//! the access schedule, and of the session recordings stopped.
+//!
+//! The records are written by the server process into the option [`OPTION_CONN_HISTORY`], so the
+//! ui reads them like the other options. Only the latest [`MAX_RECORDS`] are kept.
+
+use hbb_common::{config::Config, get_time, log};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+pub const OPTION_CONN_HISTORY: &str = "conn-history";
+pub const MAX_RECORDS: usize = 100;
+
+lazy_static::lazy_static! {
+    static ref HISTORY_LOCK: Mutex<()> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnAction {
+    Rejected,
+    Disconnected,
+    /// The recording of the session is stopped, the session goes on.
+    RecordingStopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnRecord {
+    /// The milliseconds since the epoch.
+    pub time: i64,
+    pub peer_id: String,
+    pub name: String,
+    pub ip: String,
+    pub action: ConnAction,
+    pub reason: String,
+}
+
+impl ConnRecord {
+    pub fn new(peer_id: &str, name: &str, ip: &str, action: ConnAction, reason: &str) -> Self {
+        Self {
+            time: get_time(),
+            peer_id: peer_id.to_owned(),
+            name: name.to_owned(),
+            ip: ip.to_owned(),
+            action,
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+fn parse(s: &str) -> Vec<ConnRecord> {
+    if s.is_empty() {
+        return vec![];
+    }
+    serde_json::from_str(s).unwrap_or_else(|e| {
+        log::error!("Failed to parse the connection history: {}", e);
+        vec![]
+    })
+}
+
+fn append(records: &mut Vec<ConnRecord>, record: ConnRecord) {
+    records.push(record);
+    if records.len() > MAX_RECORDS {
+        records.drain(..records.len() - MAX_RECORDS);
+    }
+}
+
+/// Record in the server process.
+pub fn record(record: ConnRecord) {
+    log::info!("Connection history: {:?}", record);
+    let _lock = HISTORY_LOCK.lock().unwrap();
+    let mut records = parse(&Config::get_option(OPTION_CONN_HISTORY));
+    append(&mut records, record);
+    match serde_json::to_string(&records) {
+        Ok(s) => Config::set_option(OPTION_CONN_HISTORY.to_owned(), s),
+        Err(e) => log::error!("Failed to store the connection history: {}", e),
+    }
+}
+
+/// The records as json, the oldest first.
+pub fn get_conn_history() -> String {
+    let records = parse(&crate::ui_interface::get_option(
+        OPTION_CONN_HISTORY.to_owned(),
+    ));
+    serde_json::to_string(&records).unwrap_or_default()
+}
+
+pub fn clear_conn_history() {
+    crate::ui_interface::set_option(OPTION_CONN_HISTORY.to_owned(), "".to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conn_history_append() {
+        let mut records = vec![];
+        for i in 0..MAX_RECORDS + 2 {
+            append(
+                &mut records,
+                ConnRecord::new(
+                    &i.to_string(),
+                    "",
+                    "",
+                    ConnAction::Rejected,
+                    "access_schedule",
+                ),
+            );
+        }
+        assert_eq!(records.len(), MAX_RECORDS);
+        assert_eq!(records[0].peer_id, "2");
+        let s = serde_json::to_string(&records).unwrap();
+        assert!(s.contains("\"action\":\"rejected\""));
+        assert_eq!(parse(&s), records);
+    }
+}
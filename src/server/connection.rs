@@ -17,7 +17,8 @@ use crate::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
     common::{get_default_sound_input, set_sound_input},
-    display_service, ipc, privacy_mode, video_service, VERSION,
+    display_service, ipc, privacy_mode, ui_cm_interface::notify::NotifyCategory, video_service,
+    VERSION,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
@@ -28,13 +29,14 @@ use hbb_common::platform::linux::run_cmds;
 #[cfg(target_os = "android")]
 use hbb_common::protobuf::EnumOrUnknown;
 use hbb_common::{
+    anyhow::anyhow,
     config::Config,
     fs,
     fs::can_enable_overwrite_detection,
     futures::{SinkExt, StreamExt},
     get_time, get_version_number,
     message_proto::{option_message::BoolOption, permission_info::Permission},
-    password_security::{self as password, ApproveMode},
+    password_security::{self as password, ApproveMode, PasswordForm},
     sleep, timeout,
     tokio::{
         net::TcpStream,
@@ -66,11 +68,14 @@ pub type Sender = mpsc::UnboundedSender<(Instant, Arc<Message>)>;
 
 lazy_static::lazy_static! {
     static ref LOGIN_FAILURES: Arc::<Mutex<HashMap<String, (i32, i32, i32)>>> = Default::default();
+    static ref TFA_FAILURES: Arc::<Mutex<HashMap<String, (i32, i32, i32)>>> = Default::default();
     static ref SESSIONS: Arc::<Mutex<HashMap<String, Session>>> = Default::default();
     static ref ALIVE_CONNS: Arc::<Mutex<Vec<i32>>> = Default::default();
     static ref AUTHED_CONNS: Arc::<Mutex<Vec<(i32, AuthConnType)>>> = Default::default();
     static ref SWITCH_SIDES_UUID: Arc::<Mutex<HashMap<String, (Instant, uuid::Uuid)>>> = Default::default();
     static ref WAKE_LOCK: Arc::<Mutex<Option<(crate::platform::WakeLock, bool)>>> = Default::default();
+    // The connections whose peers do not require the displays to be kept on.
+    static ref NO_KEEP_AWAKE_CONNS: Arc::<Mutex<std::collections::HashSet<i32>>> = Default::default();
 }
 
 #[cfg(any(target_os = "windows", target_os = "linux"))]
@@ -148,6 +153,7 @@ struct Session {
     session_id: u64,
     last_recv_time: Arc<Mutex<Instant>>,
     random_password: String,
+    password_form: PasswordForm,
 }
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -177,8 +183,18 @@ pub struct Connection {
     file_transfer: Option<(String, bool)>,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
     port_forward_address: String,
+    port_forward_tunnel: Option<super::tunnel::Tunnel>,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
+    // password passed, waiting for the 2FA code
+    require_2fa: bool,
+    // the form of the password which authorized this connection
+    password_form: Option<PasswordForm>,
+    // the key of the peer acl rule applied to this connection
+    peer_acl: Option<String>,
+    // authenticated without the local user, subject to the access schedule
+    unattended: bool,
+    schedule_grace: access_schedule::GraceTimer,
     keyboard: bool,
     clipboard: bool,
     audio: bool,
@@ -186,6 +202,8 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     block_input: bool,
+    printer: bool,
+    annotation: bool,
     last_test_delay: i64,
     network_delay: Option<u32>,
     lock_after_session_end: bool,
@@ -194,6 +212,14 @@ pub struct Connection {
     ip: String,
     // by peer
     disable_keyboard: bool,
+    // by peer, turning it off needs the confirmation of the cm
+    view_only: bool,
+    // by peer, no video is captured until the peer switches to a display
+    audio_only: bool,
+    // enabled by peer
+    print_forward: print_forward::PrintSender,
+    annotations: annotation::Annotations,
+    input_policy: input_policy::InputPolicy,
     // by peer
     disable_clipboard: bool,
     // by peer
@@ -231,6 +257,13 @@ pub struct Connection {
     auto_disconnect_timer: Option<(Instant, u64)>,
     authed_conn_id: Option<self::raii::AuthedConnID>,
     file_remove_log_control: FileRemoveLogControl,
+    permission_options: permission_options::PermissionOptions,
+    timed_grants: permission_options::TimedGrants,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    display_state_watch: Option<super::host_display_state::Watch>,
+    load_guard: Option<super::load_guard::Watch>,
+    // Watched once the peer asks for the output volume.
+    volume_watch: Option<super::remote_volume::Watch>,
 }
 
 impl ConnInner {
@@ -274,6 +307,8 @@ const MILLI1: Duration = Duration::from_millis(1);
 const SEND_TIMEOUT_VIDEO: u64 = 12_000;
 const SEND_TIMEOUT_OTHER: u64 = SEND_TIMEOUT_VIDEO * 10;
 const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const WAKE_DISPLAY_NOTIFY_TIMEOUT: u64 = 1_000;
 
 impl Connection {
     pub async fn start(
@@ -281,6 +316,25 @@ impl Connection {
         stream: super::Stream,
         id: i32,
         server: super::ServerPtrWeak,
+    ) {
+        Self::start_(addr, stream, id, server, true).await
+    }
+
+    /// Serve the in-memory connection of a session in the same process, see `client::loopback`.
+    /// No cm is started and no service is subscribed, the file transfers and the messages of the
+    /// session are handled as usual.
+    #[cfg(any(test, feature = "loopback"))]
+    pub async fn start_loopback(stream: super::Stream, id: i32) {
+        let addr = stream.local_addr();
+        Self::start_(addr, stream, id, Default::default(), false).await
+    }
+
+    async fn start_(
+        addr: SocketAddr,
+        stream: super::Stream,
+        id: i32,
+        server: super::ServerPtrWeak,
+        with_cm: bool,
     ) {
         let _raii_id = raii::ConnectionID::new(id);
         let hash = Hash {
@@ -305,6 +359,20 @@ impl Connection {
         let linux_headless_handle =
             LinuxHeadlessHandle::new(_rx_cm_stream_ready, _tx_desktop_ready);
 
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        let start_cm_ipc_para = if with_cm {
+            Some(StartCmIpcPara {
+                rx_to_cm,
+                tx_from_cm,
+                rx_desktop_ready,
+                tx_cm_stream_ready,
+            })
+        } else {
+            // The cm is not started, its messages are dropped.
+            let mut rx_to_cm = rx_to_cm;
+            tokio::spawn(async move { while rx_to_cm.recv().await.is_some() {} });
+            None
+        };
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let tx_cloned = tx.clone();
         let mut conn = Self {
@@ -323,8 +391,14 @@ impl Connection {
             file_transfer: None,
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
+            port_forward_tunnel: None,
             tx_to_cm,
             authorized: false,
+            require_2fa: false,
+            password_form: None,
+            peer_acl: None,
+            unattended: false,
+            schedule_grace: Default::default(),
             keyboard: Connection::permission("enable-keyboard"),
             clipboard: Connection::permission("enable-clipboard"),
             audio: Connection::permission("enable-audio"),
@@ -333,6 +407,8 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
+            printer: Connection::permission(print_forward::OPTION_ENABLE_REMOTE_PRINTER),
+            annotation: Connection::permission(annotation::OPTION_ENABLE_REMOTE_ANNOTATION),
             last_test_delay: 0,
             network_delay: None,
             lock_after_session_end: false,
@@ -343,6 +419,11 @@ impl Connection {
             enable_file_transfer: false,
             disable_clipboard: false,
             disable_keyboard: false,
+            view_only: false,
+            audio_only: false,
+            print_forward: Default::default(),
+            annotations: Default::default(),
+            input_policy: input_policy::InputPolicy::load(),
             tx_input,
             video_ack_required: false,
             server_audit_conn: "".to_owned(),
@@ -366,15 +447,16 @@ impl Connection {
             closed: false,
             delay_response_instant: Instant::now(),
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
-            start_cm_ipc_para: Some(StartCmIpcPara {
-                rx_to_cm,
-                tx_from_cm,
-                rx_desktop_ready,
-                tx_cm_stream_ready,
-            }),
+            start_cm_ipc_para,
             auto_disconnect_timer: None,
             authed_conn_id: None,
             file_remove_log_control: FileRemoveLogControl::new(id),
+            permission_options: permission_options::PermissionOptions::new(Connection::permission),
+            timed_grants: Default::default(),
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            display_state_watch: None,
+            load_guard: None,
+            volume_watch: None,
         };
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
@@ -384,7 +466,9 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "android")]
-        start_channel(rx_to_cm, tx_from_cm);
+        if with_cm {
+            start_channel(rx_to_cm, tx_from_cm);
+        }
         if !conn.keyboard {
             conn.send_permission(Permission::Keyboard, false).await;
         }
@@ -406,12 +490,18 @@ impl Connection {
         if !conn.block_input {
             conn.send_permission(Permission::BlockInput, false).await;
         }
+        if !conn.printer {
+            conn.send_permission(Permission::Printer, false).await;
+        }
+        if !conn.annotation {
+            conn.send_permission(Permission::Annotation, false).await;
+        }
         let mut test_delay_timer =
             time::interval_at(Instant::now() + TEST_DELAY_TIMEOUT, TEST_DELAY_TIMEOUT);
         let mut last_recv_time = Instant::now();
 
         conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
+            if conn.file_transfer.is_some() || conn.is_port_forward() {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
@@ -419,7 +509,7 @@ impl Connection {
         );
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
+        std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned, id));
         let mut second_timer = time::interval(Duration::from_secs(1));
 
         loop {
@@ -430,7 +520,7 @@ impl Connection {
                     match data {
                         ipc::Data::Authorize => {
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -441,6 +531,20 @@ impl Connection {
                             conn.on_close("connection manager", true).await;
                             break;
                         }
+                        #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        ipc::Data::CmHookBlocked((plugin, action)) => {
+                            conn.post_conn_audit(json!({
+                                "action": "blocked_by_plugin",
+                                "plugin": plugin,
+                                "blocked": action,
+                            }));
+                            if action == "connection" {
+                                conn.send_close_reason_no_retry("Rejected by plugin").await;
+                                conn.on_close(&format!("rejected by plugin {}", plugin), false).await;
+                                break;
+                            }
+                        }
                         ipc::Data::CmErr(e) => {
                             if e != "expected" {
                                 // cm closed before connection
@@ -449,6 +553,7 @@ impl Connection {
                             }
                         }
                         ipc::Data::ChatMessage{text} => {
+                            session_recording::on_chat(conn.inner.id(), false, &text);
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -459,45 +564,50 @@ impl Connection {
                             conn.send(msg_out).await;
                             conn.chat_unanswered = false;
                         }
+                        ipc::Data::CmChatMessage{seq, text} => {
+                            let error = if conn.file_transfer.is_some() || conn.is_port_forward() {
+                                "Chat is unavailable in file transfer or port forward connections".to_owned()
+                            } else {
+                                session_recording::on_chat(conn.inner.id(), false, &text);
+                                let mut misc = Misc::new();
+                                misc.set_chat_message(ChatMessage {
+                                    text,
+                                    ..Default::default()
+                                });
+                                let mut msg_out = Message::new();
+                                msg_out.set_misc(misc);
+                                conn.send(msg_out).await;
+                                "".to_owned()
+                            };
+                            conn.send_to_cm(ipc::Data::ChatDelivery { seq, error });
+                        }
+                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                        ipc::Data::CmWatch(on) => {
+                            let tx = (on && conn.file_transfer.is_none() && !conn.is_port_forward())
+                                .then(|| conn.tx_to_cm.clone());
+                            super::cm_watch::set(conn.inner.id(), tx);
+                        }
                         ipc::Data::SwitchPermission{name, enabled} => {
-                            log::info!("Change permission {} -> {}", name, enabled);
-                            if &name == "keyboard" {
-                                conn.keyboard = enabled;
-                                conn.send_permission(Permission::Keyboard, enabled).await;
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        NAME_CURSOR,
-                                        conn.inner.clone(), enabled || conn.show_remote_cursor);
-                                }
-                            } else if &name == "clipboard" {
-                                conn.clipboard = enabled;
-                                conn.send_permission(Permission::Clipboard, enabled).await;
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        super::clipboard_service::NAME,
-                                        conn.inner.clone(), conn.clipboard_enabled() && conn.peer_keyboard_enabled());
-                                }
-                            } else if &name == "audio" {
-                                conn.audio = enabled;
-                                conn.send_permission(Permission::Audio, enabled).await;
-                                if let Some(s) = conn.server.upgrade() {
-                                    s.write().unwrap().subscribe(
-                                        super::audio_service::NAME,
-                                        conn.inner.clone(), conn.audio_enabled());
-                                }
-                            } else if &name == "file" {
-                                conn.file = enabled;
-                                conn.send_permission(Permission::File, enabled).await;
-                            } else if &name == "restart" {
-                                conn.restart = enabled;
-                                conn.send_permission(Permission::Restart, enabled).await;
-                            } else if &name == "recording" {
-                                conn.recording = enabled;
-                                conn.send_permission(Permission::Recording, enabled).await;
-                            } else if &name == "block_input" {
-                                conn.block_input = enabled;
-                                conn.send_permission(Permission::BlockInput, enabled).await;
+                            // Switched by hand, or the grant for a while expired.
+                            conn.timed_grants.set(&name, false);
+                            conn.switch_permission(&name, enabled).await;
+                        }
+                        ipc::Data::TimedPermission((name, timed)) => {
+                            conn.timed_grants.set(&name, timed);
+                        }
+                        ipc::Data::CmExited => {
+                            conn.revert_timed_grants().await;
+                        }
+                        ipc::Data::ViewOnlyOffResponse(accept) => {
+                            log::info!("View-only off is accepted by the cm: {}", accept);
+                            if accept && conn.view_only {
+                                conn.set_view_only(false);
                             }
+                            let mut misc = Misc::new();
+                            misc.set_view_only(conn.view_only);
+                            let mut msg_out = Message::new();
+                            msg_out.set_misc(misc);
+                            conn.send(msg_out).await;
                         }
                         ipc::Data::RawMessage(bytes) => {
                             allow_err!(conn.stream.send_raw(bytes).await);
@@ -577,7 +687,17 @@ impl Connection {
                         break;
                     }
                 },
+                Some(job) = conn.print_forward.next_spooled() => {
+                    conn.print_forward.push(job);
+                    conn.file_timer = time::interval(MILLI1);
+                }
                 _ = conn.file_timer.tick() => {
+                    if conn.print_forward.is_busy() {
+                        if let Err(err) = conn.send_print_job_chunk().await {
+                            conn.on_close(&err.to_string(), false).await;
+                            break;
+                        }
+                    }
                     if !conn.read_jobs.is_empty() {
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
                         match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
@@ -591,7 +711,7 @@ impl Connection {
                                 break;
                             }
                         }
-                    } else {
+                    } else if !conn.print_forward.is_busy() {
                         conn.file_timer = time::interval_at(Instant::now() + SEC30, SEC30);
                     }
                 }
@@ -656,6 +776,24 @@ impl Connection {
                         }
                     }
                     conn.file_remove_log_control.on_timer().drain(..).map(|x| conn.send_to_cm(x)).count();
+                    if conn.authorized {
+                        conn.check_permission_options().await;
+                        conn.check_input_policy().await;
+                        if conn.unattended && conn.check_access_schedule().await {
+                            break;
+                        }
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if let Some(msg) = conn.display_state_watch.as_mut().and_then(|w| w.poll()) {
+                        conn.send(msg).await;
+                    }
+                    if let Some(msg) = conn.volume_watch.as_mut().and_then(|w| w.poll()) {
+                        conn.send(msg).await;
+                    }
+                    if let Some(msg) = conn.load_guard.as_mut().and_then(|w| w.poll()) {
+                        conn.send(msg).await;
+                    }
+                    conn.annotations.expire(std::time::Instant::now());
                 }
                 _ = test_delay_timer.tick() => {
                     if last_recv_time.elapsed() >= SEC30 {
@@ -713,7 +851,7 @@ impl Connection {
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender) {
+    fn handle_input(receiver: std_mpsc::Receiver<MessageInput>, tx: Sender, conn_id: i32) {
         let mut block_input_mode = false;
         #[cfg(any(target_os = "windows", target_os = "macos"))]
         {
@@ -743,7 +881,7 @@ impl Connection {
                         handle_pointer(&msg, id);
                     }
                     MessageInput::BlockOn => {
-                        let (ok, msg) = crate::platform::block_input(true);
+                        let (ok, msg) = Self::block_input(true, conn_id);
                         if ok {
                             block_input_mode = true;
                         } else {
@@ -755,7 +893,7 @@ impl Connection {
                         }
                     }
                     MessageInput::BlockOff => {
-                        let (ok, msg) = crate::platform::block_input(false);
+                        let (ok, msg) = Self::block_input(false, conn_id);
                         if ok {
                             block_input_mode = false;
                         } else {
@@ -769,7 +907,7 @@ impl Connection {
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     MessageInput::BlockOnPlugin(_peer) => {
-                        let (ok, _msg) = crate::platform::block_input(true);
+                        let (ok, _msg) = Self::block_input(true, conn_id);
                         if ok {
                             block_input_mode = true;
                         }
@@ -782,7 +920,7 @@ impl Connection {
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     MessageInput::BlockOffPlugin(_peer) => {
-                        let (ok, _msg) = crate::platform::block_input(false);
+                        let (ok, _msg) = Self::block_input(false, conn_id);
                         if ok {
                             block_input_mode = false;
                         }
@@ -804,15 +942,42 @@ impl Connection {
                 }
             }
         }
+        // The input blocked by the thread is unblocked with its exit.
+        if block_input_mode {
+            crate::state_journal::release(&crate::state_journal::StateRecord::BlockInput(conn_id));
+        }
         #[cfg(target_os = "linux")]
         clear_remapped_keycode();
         log::info!("Input thread exited");
     }
 
+    // The input is unblocked on the next start if the process is killed while it's blocked.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn block_input(on: bool, conn_id: i32) -> (bool, String) {
+        use crate::state_journal::{self, StateRecord};
+        if on {
+            state_journal::engage(StateRecord::BlockInput(conn_id));
+        }
+        let (ok, msg) = crate::platform::block_input(on);
+        // Kept while blocked, including failing to unblock.
+        if ok != on {
+            state_journal::release(&StateRecord::BlockInput(conn_id));
+        }
+        (ok, msg)
+    }
+
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        self.port_forward_socket.is_some() || self.port_forward_tunnel.is_some()
+    }
+
     async fn try_port_forward_loop(
         &mut self,
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
     ) -> ResultType<()> {
+        if self.port_forward_tunnel.is_some() {
+            return self.try_tunnel_loop(rx_from_cm).await;
+        }
         let mut last_recv_time = Instant::now();
         if let Some(mut forward) = self.port_forward_socket.take() {
             log::info!("Running port forwarding loop");
@@ -865,6 +1030,160 @@ impl Connection {
         Ok(())
     }
 
+    async fn try_tunnel_loop(
+        &mut self,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        let Some(mut tunnel) = self.port_forward_tunnel.take() else {
+            return Ok(());
+        };
+        log::info!("Running dynamic port forwarding loop");
+        let mut last_recv_time = Instant::now();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
+                        }
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
+                        }
+                        _ => {}
+                    }
+                }
+                Some(frame) = tunnel.next() => {
+                    let mut msg_out = Message::new();
+                    msg_out.set_tunnel_frame(frame);
+                    timeout(SEND_TIMEOUT_OTHER, self.stream.send(&msg_out)).await??;
+                }
+                res = self.stream.next() => {
+                    if let Some(res) = res {
+                        last_recv_time = Instant::now();
+                        let msg_in = Message::parse_from_bytes(&res?)?;
+                        if let Some(message::Union::TunnelFrame(frame)) = msg_in.union {
+                            tunnel.handle_frame(frame);
+                        }
+                    } else {
+                        bail!("Stream reset by the peer");
+                    }
+                },
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
+                    }
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+        }
+    }
+
+    // Switched by the cm, or by the options of the host.
+    async fn switch_permission(&mut self, name: &str, mut enabled: bool) {
+        log::info!("Change permission {} -> {}", name, enabled);
+        if enabled && !self.peer_acl_allows(name) {
+            log::info!("Permission {} is denied by the peer acl", name);
+            enabled = false;
+        }
+        if name == "keyboard" {
+            self.keyboard = enabled;
+            self.send_permission(Permission::Keyboard, enabled).await;
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    NAME_CURSOR,
+                    self.inner.clone(),
+                    enabled || self.show_remote_cursor,
+                );
+            }
+        } else if name == "clipboard" {
+            self.clipboard = enabled;
+            self.send_permission(Permission::Clipboard, enabled).await;
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    super::clipboard_service::NAME,
+                    self.inner.clone(),
+                    self.clipboard_enabled() && self.peer_keyboard_enabled(),
+                );
+            }
+        } else if name == "audio" {
+            self.audio = enabled;
+            self.send_permission(Permission::Audio, enabled).await;
+            if let Some(s) = self.server.upgrade() {
+                s.write().unwrap().subscribe(
+                    super::audio_service::NAME,
+                    self.inner.clone(),
+                    self.audio_enabled(),
+                );
+            }
+        } else if name == "file" {
+            self.file = enabled;
+            self.send_permission(Permission::File, enabled).await;
+        } else if name == "restart" {
+            self.restart = enabled;
+            self.send_permission(Permission::Restart, enabled).await;
+        } else if name == "recording" {
+            self.recording = enabled;
+            self.send_permission(Permission::Recording, enabled).await;
+        } else if name == "block_input" {
+            self.block_input = enabled;
+            self.send_permission(Permission::BlockInput, enabled).await;
+        } else if name == "annotation" {
+            self.annotation = enabled;
+            self.send_permission(Permission::Annotation, enabled).await;
+            if !enabled {
+                self.annotations.clear();
+            }
+        }
+    }
+
+    // The cm exited, the permissions granted for a while are reverted to the options.
+    async fn revert_timed_grants(&mut self) {
+        for (name, enabled) in self.timed_grants.revert(Connection::permission) {
+            log::info!("Revert the permission {} granted for a while", name);
+            self.switch_permission(name, enabled).await;
+        }
+    }
+
+    // The permissions flipped by the options of the host are told to the cm too.
+    async fn check_permission_options(&mut self) {
+        for (name, enabled) in self.permission_options.poll(Connection::permission) {
+            self.switch_permission(name, enabled).await;
+            self.send_to_cm(ipc::Data::SwitchPermission {
+                name: name.to_owned(),
+                enabled: self.permission_enabled(name),
+            });
+        }
+    }
+
+    // The level is told to the cm, the dropped keys are told to the peer.
+    async fn check_input_policy(&mut self) {
+        if self.input_policy.reload() {
+            log::info!("Keyboard level -> {}", self.input_policy.level().as_str());
+            self.send_to_cm(ipc::Data::KeyboardLevel(
+                self.input_policy.level().as_str().to_owned(),
+            ));
+        }
+        if let Some(msg) = self.input_policy.poll(std::time::Instant::now()) {
+            self.send(msg).await;
+        }
+    }
+
+    fn permission_enabled(&self, name: &str) -> bool {
+        match name {
+            "keyboard" => self.keyboard,
+            "clipboard" => self.clipboard,
+            "audio" => self.audio,
+            "file" => self.file,
+            _ => false,
+        }
+    }
+
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
         let mut misc = Misc::new();
         misc.set_permission_info(PermissionInfo {
@@ -877,6 +1196,120 @@ impl Connection {
         self.send(msg_out).await;
     }
 
+    // Reject the connection authenticated unattended outside the access schedule.
+    async fn reject_outside_schedule(&mut self) -> bool {
+        if access_schedule::AccessSchedule::load().allows_now() {
+            return false;
+        }
+        log::info!(
+            "Connection from {} rejected outside the access schedule",
+            self.lr.my_id
+        );
+        conn_history::record(conn_history::ConnRecord::new(
+            &self.lr.my_id,
+            &self.lr.my_name,
+            &self.ip,
+            conn_history::ConnAction::Rejected,
+            access_schedule::REASON_ACCESS_SCHEDULE,
+        ));
+        self.send_login_error(crate::client::LOGIN_MSG_OUTSIDE_SCHEDULE)
+            .await;
+        sleep(1.).await;
+        true
+    }
+
+    // Warn the unattended session outside the access schedule, and close it after the grace
+    // period. Returns true if closed.
+    async fn check_access_schedule(&mut self) -> bool {
+        let schedule = access_schedule::AccessSchedule::load();
+        let action = self.schedule_grace.poll(
+            schedule.allows_now(),
+            schedule.grace(),
+            std::time::Instant::now(),
+        );
+        match action {
+            access_schedule::GraceAction::None => false,
+            access_schedule::GraceAction::Warn(left) => {
+                let text = if left <= access_schedule::LAST_WARNING {
+                    "access_schedule_last_minute_tip"
+                } else {
+                    "access_schedule_ending_tip"
+                };
+                let mut msg_out = Message::new();
+                msg_out.set_message_box(MessageBox {
+                    msgtype: "nook-nocancel-hasclose".to_owned(),
+                    title: "Prompt".to_owned(),
+                    text: text.to_owned(),
+                    link: "".to_owned(),
+                    ..Default::default()
+                });
+                self.send(msg_out).await;
+                false
+            }
+            access_schedule::GraceAction::Disconnect => {
+                conn_history::record(conn_history::ConnRecord::new(
+                    &self.lr.my_id,
+                    &self.lr.my_name,
+                    &self.ip,
+                    conn_history::ConnAction::Disconnected,
+                    access_schedule::REASON_ACCESS_SCHEDULE,
+                ));
+                self.send_close_reason_no_retry(
+                    "Session closed as not allowed outside the access schedule",
+                )
+                .await;
+                self.on_close("access schedule", false).await;
+                true
+            }
+        }
+    }
+
+    // The rules are loaded again, so that the edits of the rule apply to the current sessions.
+    fn peer_acl_allows(&self, name: &str) -> bool {
+        match peer_acl::PeerAclStore::load().resolve(&self.lr.my_id) {
+            Some((_, acl)) => acl.allows(name),
+            None => true,
+        }
+    }
+
+    // Restrict the default permissions by the peer acl rule.
+    async fn apply_peer_acl(&mut self) {
+        let Some((key, acl)) = peer_acl::PeerAclStore::load().resolve(&self.lr.my_id) else {
+            self.peer_acl = None;
+            self.input_policy.set_acl_level(Default::default());
+            return;
+        };
+        if self.peer_acl.as_ref() != Some(&key) {
+            log::info!("Peer acl {} applied to {}", key, self.lr.my_id);
+        }
+        self.peer_acl = Some(key);
+        self.input_policy.set_acl_level(acl.keyboard_level);
+        if self.keyboard && !acl.keyboard {
+            self.keyboard = false;
+            self.send_permission(Permission::Keyboard, false).await;
+        }
+        if self.clipboard && !acl.clipboard {
+            self.clipboard = false;
+            self.send_permission(Permission::Clipboard, false).await;
+        }
+        if self.audio && !acl.audio {
+            self.audio = false;
+            self.send_permission(Permission::Audio, false).await;
+        }
+        if self.file && !acl.file {
+            self.file = false;
+            self.send_permission(Permission::File, false).await;
+        }
+        if self.restart && !acl.restart {
+            self.restart = false;
+            self.send_permission(Permission::Restart, false).await;
+        }
+        if self.recording && !acl.recording {
+            self.recording = false;
+            self.send_permission(Permission::Recording, false).await;
+        }
+    }
+
     async fn check_privacy_mode_on(&mut self) -> bool {
         if privacy_mode::is_in_privacy_mode() {
             self.send_login_error("Someone turns on privacy mode, exit")
@@ -1026,7 +1459,7 @@ impl Connection {
         }
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             (2, AuthConnType::PortForward)
         } else {
             (0, AuthConnType::Remote)
@@ -1035,9 +1468,12 @@ impl Connection {
             self.inner.id(),
             auth_conn_type,
         ));
-        self.post_conn_audit(
-            json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
-        );
+        self.post_conn_audit(json!({
+            "peer": ((&self.lr.my_id, &self.lr.my_name)),
+            "type": conn_type,
+            "password": self.password_form.map(|f| f.as_str()),
+            "acl": self.peer_acl,
+        }));
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1057,14 +1493,11 @@ impl Connection {
             pi.hostname = DEVICE_NAME.lock().unwrap().clone();
             pi.platform = "Android".into();
         }
-        #[cfg(all(target_os = "macos", not(feature = "unix-file-copy-paste")))]
-        let platform_additions = serde_json::Map::new();
-        #[cfg(any(
-            target_os = "windows",
-            target_os = "linux",
-            all(target_os = "macos", feature = "unix-file-copy-paste")
-        ))]
         let mut platform_additions = serde_json::Map::new();
+        platform_additions.insert(
+            crate::common::PLATFORM_ADDITION_CAPABILITIES.into(),
+            Self::get_capabilities(),
+        );
         #[cfg(target_os = "linux")]
         {
             if crate::platform::current_is_wayland() {
@@ -1090,6 +1523,12 @@ impl Connection {
                 if !virtual_displays.is_empty() {
                     platform_additions.insert("virtual_displays".into(), json!(&virtual_displays));
                 }
+                if virtual_display_manager::is_virtual_display_supported() {
+                    platform_additions.insert(
+                        crate::common::PLATFORM_ADDITION_VIRTUAL_DISPLAY_REQUEST.into(),
+                        json!(true),
+                    );
+                }
             }
             platform_additions.insert(
                 "supported_privacy_mode_impl".into(),
@@ -1108,14 +1547,11 @@ impl Connection {
             platform_additions.insert("has_file_clipboard".into(), json!(true));
         }
 
-        #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
-        if !platform_additions.is_empty() {
-            pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
-        }
+        pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
 
         pi.encoding = Some(scrap::codec::Encoder::supported_encoding()).into();
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1123,7 +1559,7 @@ impl Connection {
             return;
         }
         #[cfg(target_os = "linux")]
-        if !self.file_transfer.is_some() && !self.port_forward_socket.is_some() {
+        if !self.file_transfer.is_some() && !self.is_port_forward() {
             let dtype = crate::platform::linux::get_display_server();
             if dtype != crate::platform::linux::DISPLAY_SERVER_X11
                 && dtype != crate::platform::linux::DISPLAY_SERVER_WAYLAND
@@ -1191,6 +1627,7 @@ impl Connection {
             res.set_peer_info(pi);
         } else {
             try_activate_screen();
+            self.load_guard = Some(super::load_guard::Watch::new(self.inner.id()));
             if let Some(msg_out) = super::video_service::is_inited_msg() {
                 self.send(msg_out).await;
             }
@@ -1209,6 +1646,10 @@ impl Connection {
                 }
             }
             self.on_remote_authorized();
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            {
+                self.display_state_watch = Some(super::host_display_state::Watch::new());
+            }
         }
         let mut msg_out = Message::new();
         msg_out.set_login_response(res);
@@ -1224,6 +1665,7 @@ impl Connection {
             };
             self.read_dir(dir, show_hidden);
         } else if sub_service {
+            self.start_session_recording().await;
             if let Some(s) = self.server.upgrade() {
                 let mut noperms = Vec::new();
                 if !self.peer_keyboard_enabled() && !self.show_remote_cursor {
@@ -1248,8 +1690,32 @@ impl Connection {
         }
     }
 
+    // Record the session if required by the policy, and tell the peer.
+    async fn start_session_recording(&mut self) {
+        if !session_recording::start(
+            self.inner.id(),
+            &self.lr.my_id,
+            &self.lr.my_name,
+            &self.ip,
+            self.tx_to_cm.clone(),
+        ) {
+            return;
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: "nook-nocancel-hasclose".to_owned(),
+            title: "Prompt".to_owned(),
+            text: "session_recorded_notice".to_owned(),
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        self.send(msg_out).await;
+    }
+
     fn on_remote_authorized(&self) {
         self.update_codec_on_login();
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.wake_display();
         #[cfg(any(target_os = "windows", target_os = "linux"))]
         if !Config::get_option("allow-remove-wallpaper").is_empty() {
             // multi connections set once
@@ -1267,12 +1733,154 @@ impl Connection {
         }
     }
 
+    // Wake the sleeping displays before streaming, the peer is told if it takes a while.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn wake_display(&self) {
+        let mut inner = self.inner.clone();
+        tokio::spawn(async move {
+            let mut task = tokio::task::spawn_blocking(crate::platform::wake_display);
+            let res = match timeout(WAKE_DISPLAY_NOTIFY_TIMEOUT, &mut task).await {
+                Ok(res) => res,
+                Err(_) => {
+                    inner.send(Arc::new(Self::host_display_waking_msg(true)));
+                    let res = task.await;
+                    inner.send(Arc::new(Self::host_display_waking_msg(false)));
+                    res
+                }
+            };
+            match res {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => log::warn!("Failed to wake the display: {}", e),
+                Err(e) => log::error!("Failed to join the display waking task: {}", e),
+            }
+        });
+    }
+
+    // Collected in the background, the cpu usage is sampled for a while.
+    fn send_remote_sysinfo(&self) {
+        let denied = !Self::permission("enable-remote-sysinfo");
+        let mut inner = self.inner.clone();
+        tokio::spawn(async move {
+            let mut sysinfo = RemoteSysinfo::new();
+            if denied {
+                log::info!("Remote system information is denied");
+                sysinfo.denied = true;
+            } else {
+                match tokio::task::spawn_blocking(super::host_sysinfo::get_json).await {
+                    Ok(json) => sysinfo.json = json,
+                    Err(e) => {
+                        log::error!("Failed to collect the system information: {}", e);
+                        return;
+                    }
+                }
+            }
+            let mut misc = Misc::new();
+            misc.set_remote_sysinfo(sysinfo);
+            let mut msg_out = Message::new();
+            msg_out.set_misc(misc);
+            inner.send(Arc::new(msg_out));
+        });
+    }
+
+    // Broadcast for the peer waking another one sleeping on the networks of the host, logged to
+    // the cm and the audit server.
+    #[cfg(not(target_os = "ios"))]
+    async fn handle_wake_on_lan(&mut self, w: WakeOnLan) {
+        let res = if !Self::permission(crate::lan::OPTION_ENABLE_REMOTE_WOL) {
+            log::info!("Wake-on-LAN relay is denied");
+            Err(crate::lan::WOL_DENIED)
+        } else {
+            let mac = w.mac.clone();
+            tokio::task::spawn_blocking(move || crate::lan::relay_wol(&mac))
+                .await
+                .unwrap_or(Err(crate::lan::WOL_SEND_FAILED))
+        };
+        let mut result = WakeOnLanResult::new();
+        result.mac = w.mac;
+        match res {
+            Ok(sent) => result.sent = sent as _,
+            Err(e) => result.error = e.to_owned(),
+        }
+        let log = json!({
+            "mac": result.mac,
+            "sent": result.sent,
+            "error": result.error,
+        });
+        self.send_to_cm(ipc::Data::ActionLog((
+            "wake_on_lan".to_owned(),
+            log.to_string(),
+        )));
+        self.post_conn_audit(json!({
+            "action": "wake_on_lan",
+            "log": log,
+        }));
+        let mut misc = Misc::new();
+        misc.set_wake_on_lan_result(result);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    // The volume is changed only by the peer allowed to control, and queried by any peer.
+    async fn handle_remote_volume(&mut self, v: RemoteVolume) {
+        use super::remote_volume;
+        let mut reply = RemoteVolume::new();
+        if !Self::permission("enable-remote-volume") || (!v.query && !self.peer_keyboard_enabled())
+        {
+            log::info!("Controlling the output volume is denied");
+            reply.denied = true;
+        } else if !remote_volume::is_supported() {
+            reply.unsupported = true;
+        } else {
+            let res = tokio::task::spawn_blocking(move || {
+                if v.query {
+                    remote_volume::get()
+                } else {
+                    remote_volume::set(v.percent, v.muted)
+                }
+            })
+            .await;
+            match res {
+                Ok(Ok(state)) => {
+                    if !v.query {
+                        self.send_to_cm(ipc::Data::ActionLog((
+                            "remote_volume".to_owned(),
+                            json!({ "percent": state.percent, "muted": state.muted }).to_string(),
+                        )));
+                    }
+                    self.volume_watch
+                        .get_or_insert_with(remote_volume::Watch::new)
+                        .set_sent(state);
+                    reply = state.to_msg();
+                }
+                Ok(Err(e)) => {
+                    log::error!("Failed to control the output volume: {}", e);
+                    reply.unsupported = true;
+                }
+                Err(e) => {
+                    log::error!("Failed to join the volume task: {}", e);
+                    return;
+                }
+            }
+        }
+        self.send(remote_volume::make_msg(reply)).await;
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn host_display_waking_msg(waking: bool) -> Message {
+        let mut misc = Misc::new();
+        misc.set_host_display_waking(waking);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
     fn peer_keyboard_enabled(&self) -> bool {
-        self.keyboard && !self.disable_keyboard
+        self.keyboard && !self.disable_keyboard && !self.view_only
     }
 
     fn clipboard_enabled(&self) -> bool {
-        self.clipboard && !self.disable_clipboard
+        self.clipboard && !self.disable_clipboard && !self.view_only
     }
 
     fn audio_enabled(&self) -> bool {
@@ -1284,6 +1892,11 @@ impl Connection {
         self.file && self.enable_file_transfer
     }
 
+    // The files may be renamed or removed in place, the permission may be switched off by the cm.
+    fn file_op_permitted(&self) -> bool {
+        self.file && Connection::permission("enable-file-transfer")
+    }
+
     fn try_start_cm(&mut self, peer_id: String, name: String, authorized: bool) {
         self.send_to_cm(ipc::Data::Login {
             id: self.inner.id(),
@@ -1300,8 +1913,32 @@ impl Connection {
             restart: self.restart,
             recording: self.recording,
             block_input: self.block_input,
+            annotation: self.annotation,
             from_switch: self.from_switch,
+            password_form: self
+                .password_form
+                .map(|f| f.as_str().to_owned())
+                .unwrap_or_default(),
         });
+        self.send_to_cm(ipc::Data::KeyboardLevel(
+            self.input_policy.level().as_str().to_owned(),
+        ));
+        // The recording may be started before the cm knows the connection.
+        if session_recording::is_recording(self.inner.id()) {
+            self.send_to_cm(ipc::Data::SessionRecording(true));
+        }
+    }
+
+    fn set_view_only(&mut self, view_only: bool) {
+        self.view_only = view_only;
+        if let Some(s) = self.server.upgrade() {
+            s.write().unwrap().subscribe(
+                super::clipboard_service::NAME,
+                self.inner.clone(),
+                self.clipboard_enabled() && self.peer_keyboard_enabled(),
+            );
+        }
+        self.send_to_cm(ipc::Data::ViewOnly(view_only));
     }
 
     #[inline]
@@ -1309,6 +1946,12 @@ impl Connection {
         self.tx_to_cm.send(data).ok();
     }
 
+    // Notify the local user by the cm, per the policy of the category.
+    #[inline]
+    fn notify_cm(&mut self, category: NotifyCategory) {
+        self.send_to_cm(ipc::Data::CmNotify(category.name().to_owned()));
+    }
+
     #[inline]
     fn send_fs(&mut self, data: ipc::FS) {
         self.send_to_cm(ipc::Data::FS(data));
@@ -1377,8 +2020,17 @@ impl Connection {
 
     fn validate_password(&mut self) -> bool {
         if password::temporary_enabled() {
-            let password = password::temporary_password();
-            if self.validate_one_password(password.clone()) {
+            if let Some((password, form)) =
+                password::verify_temporary_password(|p| self.validate_one_password(p.to_owned()))
+            {
+                if form == PasswordForm::OneTime {
+                    log::info!("One-time password consumed, rotated");
+                    #[cfg(target_os = "android")]
+                    let _ = crate::flutter::push_global_event(
+                        crate::flutter::APP_TYPE_MAIN,
+                        json!({ "name": "temporary_password_rotated" }).to_string(),
+                    );
+                }
                 SESSIONS.lock().unwrap().insert(
                     self.lr.my_id.clone(),
                     Session {
@@ -1386,19 +2038,27 @@ impl Connection {
                         session_id: self.lr.session_id,
                         last_recv_time: self.last_recv_time.clone(),
                         random_password: password,
+                        password_form: form,
                     },
                 );
+                self.password_form = Some(form);
                 return true;
             }
         }
         if password::permanent_enabled() {
             if self.validate_one_password(Config::get_permanent_password()) {
+                self.password_form = Some(PasswordForm::Permanent);
                 return true;
             }
         }
         false
     }
 
+    // The 2FA code is only required for the permanent password.
+    fn is_2fa_required(&self) -> bool {
+        crate::auth_2fa::is_enabled() && self.password_form == Some(PasswordForm::Permanent)
+    }
+
     fn is_recent_session(&mut self) -> bool {
         SESSIONS
             .lock()
@@ -1423,8 +2083,10 @@ impl Connection {
                         session_id: self.lr.session_id,
                         last_recv_time: self.last_recv_time.clone(),
                         random_password: session.random_password,
+                        password_form: session.password_form,
                     },
                 );
+                self.password_form = Some(session.password_form);
                 return true;
             }
         }
@@ -1478,7 +2140,6 @@ impl Connection {
     fn try_start_cm_ipc(&mut self) {
         if let Some(p) = self.start_cm_ipc_para.take() {
             tokio::spawn(async move {
-                #[cfg(windows)]
                 let tx_from_cm_clone = p.tx_from_cm.clone();
                 if let Err(err) = start_ipc(
                     p.rx_to_cm,
@@ -1489,6 +2150,7 @@ impl Connection {
                 .await
                 {
                     log::error!("ipc to connection manager exit: {}", err);
+                    allow_err!(tx_from_cm_clone.send(Data::CmExited));
                     #[cfg(windows)]
                     if !crate::platform::is_prelogin() {
                         allow_err!(tx_from_cm_clone.send(Data::CmErr(err.to_string())));
@@ -1510,9 +2172,12 @@ impl Connection {
             if self.authorized {
                 return true;
             }
+            self.require_2fa = false;
+            self.password_form = None;
+            self.apply_peer_acl().await;
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
-                    if !Connection::permission("enable-file-transfer") {
+                    if !Connection::permission("enable-file-transfer") || !self.file {
                         self.send_login_error("No permission of file transfer")
                             .await;
                         sleep(1.).await;
@@ -1526,31 +2191,37 @@ impl Connection {
                         sleep(1.).await;
                         return false;
                     }
-                    let mut is_rdp = false;
-                    if pf.host == "RDP" && pf.port == 0 {
-                        pf.host = "localhost".to_owned();
-                        pf.port = 3389;
-                        is_rdp = true;
-                    }
-                    if pf.host.is_empty() {
-                        pf.host = "localhost".to_owned();
-                    }
-                    let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    if pf.dynamic {
+                        self.port_forward_address = "SOCKS".to_owned();
+                        self.port_forward_tunnel = Some(super::tunnel::Tunnel::new());
+                    } else {
+                        let mut is_rdp = false;
+                        if pf.host == "RDP" && pf.port == 0 {
+                            pf.host = "localhost".to_owned();
+                            pf.port = 3389;
+                            is_rdp = true;
+                        }
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
                         }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                        let mut addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        match timeout(3000, TcpStream::connect(&addr)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
-                            return false;
                         }
                     }
                 }
@@ -1607,13 +2278,17 @@ impl Connection {
                 self.send_login_error("Connection not allowed").await;
                 return false;
             } else if self.is_recent_session() {
+                if self.reject_outside_schedule().await {
+                    return false;
+                }
                 if err_msg.is_empty() {
                     #[cfg(all(target_os = "linux", feature = "linux_headless"))]
                     #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
                     self.linux_headless_handle.wait_desktop_cm_ready().await;
+                    self.unattended = true;
                     self.try_start_cm(lr.my_id.clone(), lr.my_name.clone(), true);
                     self.send_logon_response().await;
-                    if self.port_forward_socket.is_some() {
+                    if self.is_port_forward() {
                         return false;
                     }
                 } else {
@@ -1657,6 +2332,9 @@ impl Connection {
                                     "name": lr.my_name.clone(),
                         }),
                     );
+                } else if self.reject_outside_schedule().await {
+                    // Checked before the password, a one-time password is consumed by the check.
+                    return false;
                 } else if !self.validate_password() {
                     if failure.0 == time {
                         failure.1 += 1;
@@ -1684,13 +2362,18 @@ impl Connection {
                     if failure.0 != 0 {
                         LOGIN_FAILURES.lock().unwrap().remove(&self.ip);
                     }
-                    if err_msg.is_empty() {
+                    if err_msg.is_empty() && self.is_2fa_required() {
+                        self.require_2fa = true;
+                        self.send_login_error(crate::client::LOGIN_MSG_2FA_REQUIRED)
+                            .await;
+                    } else if err_msg.is_empty() {
                         #[cfg(all(target_os = "linux", feature = "linux_headless"))]
                         #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
+                        self.unattended = true;
                         self.send_logon_response().await;
                         self.try_start_cm(lr.my_id, lr.my_name, true);
-                        if self.port_forward_socket.is_some() {
+                        if self.is_port_forward() {
                             return false;
                         }
                     } else {
@@ -1698,6 +2381,51 @@ impl Connection {
                     }
                 }
             }
+        } else if let Some(message::Union::Auth2fa(tfa)) = msg.union {
+            if !self.require_2fa || self.authorized {
+                return true;
+            }
+            let mut failure = TFA_FAILURES
+                .lock()
+                .unwrap()
+                .get(&self.ip)
+                .map(|x| x.clone())
+                .unwrap_or((0, 0, 0));
+            let time = (get_time() / 60_000) as i32;
+            if failure.2 > 30 {
+                self.send_login_error("Too many wrong 2FA attempts").await;
+            } else if time == failure.0 && failure.1 > 6 {
+                self.send_login_error("Please try 1 minute later").await;
+            } else if !crate::auth_2fa::verify(&tfa.code) {
+                if failure.0 == time {
+                    failure.1 += 1;
+                    failure.2 += 1;
+                } else {
+                    failure.0 = time;
+                    failure.1 = 1;
+                    failure.2 += 1;
+                }
+                TFA_FAILURES
+                    .lock()
+                    .unwrap()
+                    .insert(self.ip.clone(), failure);
+                self.send_login_error(crate::client::LOGIN_MSG_2FA_WRONG)
+                    .await;
+            } else {
+                if failure.0 != 0 {
+                    TFA_FAILURES.lock().unwrap().remove(&self.ip);
+                }
+                self.require_2fa = false;
+                #[cfg(all(target_os = "linux", feature = "linux_headless"))]
+                #[cfg(not(any(feature = "flatpak", feature = "appimage")))]
+                self.linux_headless_handle.wait_desktop_cm_ready().await;
+                self.unattended = true;
+                self.send_logon_response().await;
+                self.try_start_cm(self.lr.my_id.clone(), self.lr.my_name.clone(), true);
+                if self.is_port_forward() {
+                    return false;
+                }
+            }
         } else if let Some(message::Union::TestDelay(t)) = msg.union {
             if t.from_client {
                 let mut msg_out = Message::new();
@@ -1735,6 +2463,10 @@ impl Connection {
                 }
             }
         } else if self.authorized {
+            if self.view_only && crate::common::is_input_message(&msg) {
+                log::debug!("Drop the input message of the view-only session");
+                return true;
+            }
             match msg.union {
                 Some(message::Union::MouseEvent(me)) => {
                     #[cfg(any(target_os = "android", target_os = "ios"))]
@@ -1794,20 +2526,11 @@ impl Connection {
                 Some(message::Union::KeyEvent(mut me)) => {
                     let is_press = (me.press || me.down) && !crate::is_modifier(&me);
 
-                    let key = match me.mode.enum_value() {
-                        Ok(KeyboardMode::Map) => {
-                            Some(crate::keyboard::keycode_to_rdev_key(me.chr()))
-                        }
-                        Ok(KeyboardMode::Translate) => {
-                            if let Some(key_event::Union::Chr(code)) = me.union {
-                                Some(crate::keyboard::keycode_to_rdev_key(code & 0x0000FFFF))
-                            } else {
-                                None
-                            }
-                        }
-                        _ => None,
+                    let key = key_event_rdev_key(&me);
+                    if !self.input_policy.allows(&me, key) {
+                        return true;
                     }
-                    .filter(crate::keyboard::is_modifier);
+                    let key = key.filter(crate::keyboard::is_modifier);
 
                     if let Some(key) = key {
                         if is_press {
@@ -1843,7 +2566,8 @@ impl Connection {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 Some(message::Union::KeyEvent(me)) => {
-                    if self.peer_keyboard_enabled() {
+                    let key = key_event_rdev_key(&me);
+                    if self.peer_keyboard_enabled() && self.input_policy.allows(&me, key) {
                         if is_enter(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         }
@@ -1856,20 +2580,7 @@ impl Connection {
                             me.press
                         };
 
-                        let key = match me.mode.enum_value() {
-                            Ok(KeyboardMode::Map) => {
-                                Some(crate::keyboard::keycode_to_rdev_key(me.chr()))
-                            }
-                            Ok(KeyboardMode::Translate) => {
-                                if let Some(key_event::Union::Chr(code)) = me.union {
-                                    Some(crate::keyboard::keycode_to_rdev_key(code & 0x0000FFFF))
-                                } else {
-                                    None
-                                }
-                            }
-                            _ => None,
-                        }
-                        .filter(crate::keyboard::is_modifier);
+                        let key = key.filter(crate::keyboard::is_modifier);
 
                         if let Some(key) = key {
                             if is_press {
@@ -1900,6 +2611,7 @@ impl Connection {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
                         update_clipboard(_cb, None);
+                        self.notify_cm(NotifyCategory::ClipboardReceived);
                     }
                 }
                 Some(message::Union::Cliprdr(_clip)) =>
@@ -1950,9 +2662,11 @@ impl Connection {
                                             .await;
                                         let mut files = job.files().to_owned();
                                         job.is_remote = true;
+                                        job.streams = s.streams.min(fs::multi_stream::MAX_STREAMS);
                                         job.conn_id = self.inner.id();
                                         self.read_jobs.push(job);
                                         self.file_timer = time::interval(MILLI1);
+                                        self.notify_cm(NotifyCategory::FileTransferStarted);
                                         self.post_file_audit(
                                             FileAuditType::RemoteSend,
                                             &s.path,
@@ -1986,7 +2700,9 @@ impl Connection {
                                     overwrite_detection: od,
                                     total_size: r.total_size,
                                     conn_id: self.inner.id(),
+                                    streams: r.streams,
                                 });
+                                self.notify_cm(NotifyCategory::FileTransferStarted);
                                 self.post_file_audit(
                                     FileAuditType::RemoteReceive,
                                     &r.path,
@@ -2031,6 +2747,57 @@ impl Connection {
                                     .unwrap_or_default(),
                                 )));
                             }
+                            Some(file_action::Union::Rename(r)) => {
+                                if !self.file_op_permitted() {
+                                    self.send(fs::file_op::new_result::<()>(
+                                        r.id,
+                                        &Err(anyhow!("No permission to change the files")),
+                                    ))
+                                    .await;
+                                    return true;
+                                }
+                                self.send_fs(ipc::FS::Rename {
+                                    path: r.path.clone(),
+                                    id: r.id,
+                                    new_name: r.new_name.clone(),
+                                });
+                                self.send_to_cm(ipc::Data::FileTransferLog((
+                                    "rename".to_string(),
+                                    serde_json::to_string(&FileRenameLog {
+                                        id: r.id,
+                                        conn_id: self.inner.id(),
+                                        path: r.path,
+                                        new_name: r.new_name,
+                                    })
+                                    .unwrap_or_default(),
+                                )));
+                            }
+                            Some(file_action::Union::RemovePath(r)) => {
+                                if !self.file_op_permitted() {
+                                    self.send(fs::file_op::new_result::<()>(
+                                        r.id,
+                                        &Err(anyhow!("No permission to change the files")),
+                                    ))
+                                    .await;
+                                    return true;
+                                }
+                                let dir = r.recursive || std::path::Path::new(&r.path).is_dir();
+                                self.send_fs(ipc::FS::RemovePath {
+                                    path: r.path.clone(),
+                                    id: r.id,
+                                    recursive: r.recursive,
+                                });
+                                self.send_to_cm(ipc::Data::FileTransferLog((
+                                    "remove".to_string(),
+                                    serde_json::to_string(&FileActionLog {
+                                        id: r.id,
+                                        conn_id: self.inner.id(),
+                                        path: r.path,
+                                        dir,
+                                    })
+                                    .unwrap_or_default(),
+                                )));
+                            }
                             Some(file_action::Union::Cancel(c)) => {
                                 self.send_fs(ipc::FS::CancelWrite { id: c.id });
                                 if let Some(job) = fs::get_job_immutable(c.id, &self.read_jobs) {
@@ -2057,6 +2824,8 @@ impl Connection {
                             file_num: block.file_num,
                             data: block.data,
                             compressed: block.compressed,
+                            offset: block.offset,
+                            stream: block.stream,
                         });
                     }
                     Some(file_response::Union::Done(d)) => {
@@ -2095,10 +2864,17 @@ impl Connection {
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
                         self.toggle_virtual_display(t).await;
                     }
+                    Some(misc::Union::AddVirtualDisplay(r)) => {
+                        self.add_virtual_display(r).await;
+                    }
+                    Some(misc::Union::RemoveVirtualDisplay(r)) => {
+                        self.remove_virtual_display(r).await;
+                    }
                     Some(misc::Union::TogglePrivacyMode(t)) => {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        session_recording::on_chat(self.inner.id(), true, &c.text);
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -2132,7 +2908,10 @@ impl Connection {
 
                     Some(misc::Union::RestartRemoteDevice(_)) => {
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                        if self.restart {
+                        let restart = self.restart;
+                        #[cfg(any(target_os = "android", target_os = "ios"))]
+                        let restart = false;
+                        if restart {
                             // force_reboot, not work on linux vm and macos 14
                             #[cfg(any(target_os = "linux", target_os = "windows"))]
                             match system_shutdown::force_reboot() {
@@ -2144,8 +2923,36 @@ impl Connection {
                                 Ok(_) => log::info!("Restart by the peer"),
                                 Err(e) => log::error!("Failed to restart: {}", e),
                             }
+                        } else {
+                            log::info!("Restart by the peer is denied");
+                            let mut misc = Misc::new();
+                            misc.set_restart_remote_device_denied(true);
+                            let mut msg_out = Message::new();
+                            msg_out.set_misc(misc);
+                            self.send(msg_out).await;
                         }
                     }
+                    Some(misc::Union::RemoteSysinfoRequest(_)) => {
+                        self.send_remote_sysinfo();
+                    }
+                    Some(misc::Union::RemoteVolume(v)) => {
+                        self.handle_remote_volume(v).await;
+                    }
+                    Some(misc::Union::PrioritizeQuality(v)) => {
+                        if let Some(w) = self.load_guard.as_mut() {
+                            w.set_prioritize_quality(v);
+                        }
+                    }
+                    Some(misc::Union::Viewport(v)) => {
+                        super::viewport::set(self.inner.id(), &v);
+                    }
+                    Some(misc::Union::FrameTimestamps(on)) => {
+                        super::frame_timestamps::set(self.inner.id(), on);
+                    }
+                    #[cfg(not(target_os = "ios"))]
+                    Some(misc::Union::WakeOnLan(w)) => {
+                        self.handle_wake_on_lan(w).await;
+                    }
                     #[cfg(windows)]
                     Some(misc::Union::ElevationRequest(r)) => match r.union {
                         Some(elevation_request::Union::Direct(_)) => {
@@ -2229,12 +3036,48 @@ impl Connection {
                 Some(message::Union::VoiceCallResponse(_response)) => {
                     // TODO: Maybe we can do a voice call from cm directly.
                 }
+                Some(message::Union::PrintJob(job)) => {
+                    if let Some(log) = self.print_forward.on_peer_message(job) {
+                        self.log_print_job(&log);
+                    }
+                }
+                Some(message::Union::Annotation(annotation)) => {
+                    if let Some(ack) = self.annotations.on_peer_message(
+                        annotation,
+                        self.annotation,
+                        display_service::get_display_info,
+                        std::time::Instant::now(),
+                    ) {
+                        let mut msg_out = Message::new();
+                        msg_out.set_annotation(ack);
+                        self.send(msg_out).await;
+                    }
+                }
                 _ => {}
             }
         }
         true
     }
 
+    async fn send_print_job_chunk(&mut self) -> ResultType<()> {
+        if let Some((job, log)) = self.print_forward.next_message() {
+            let mut msg_out = Message::new();
+            msg_out.set_print_job(job);
+            self.stream.send(&msg_out).await?;
+            if let Some(log) = log {
+                self.log_print_job(&log);
+            }
+        }
+        Ok(())
+    }
+
+    fn log_print_job(&mut self, log: &print_forward::PrintJobLog) {
+        self.send_to_cm(ipc::Data::ActionLog((
+            "print_job".to_owned(),
+            serde_json::to_string(log).unwrap_or_default(),
+        )));
+    }
+
     fn refresh_video_display(&self, display: Option<usize>) {
         video_service::refresh();
         self.server.upgrade().map(|s| {
@@ -2421,14 +3264,145 @@ impl Connection {
         }
     }
 
+    fn make_virtual_display_error_msg(text: String) -> Message {
+        let mut msg_out = Message::new();
+        msg_out.set_message_box(MessageBox {
+            msgtype: crate::common::VIRTUAL_DISPLAY_ERROR_MSGTYPE.to_owned(),
+            title: "Virtual display".to_owned(),
+            text,
+            link: "".to_owned(),
+            ..Default::default()
+        });
+        msg_out
+    }
+
+    async fn add_virtual_display(&mut self, r: AddVirtualDisplay) {
+        self.send_to_cm(ipc::Data::ActionLog((
+            "add_virtual_display".to_owned(),
+            format!("{}x{}", r.width, r.height),
+        )));
+        if let Err(e) = self.try_add_virtual_display(&r) {
+            log::error!("Failed to add virtual display: {}", e);
+            self.send(Self::make_virtual_display_error_msg(e.to_string()))
+                .await;
+        }
+    }
+
+    async fn remove_virtual_display(&mut self, r: RemoveVirtualDisplay) {
+        self.send_to_cm(ipc::Data::ActionLog((
+            "remove_virtual_display".to_owned(),
+            r.display.to_string(),
+        )));
+        if let Err(e) = self.try_remove_virtual_display(&r) {
+            log::error!("Failed to remove virtual display {}: {}", r.display, e);
+            self.send(Self::make_virtual_display_error_msg(e.to_string()))
+                .await;
+        }
+    }
+
+    #[cfg(all(windows, feature = "virtual_display_driver"))]
+    fn try_add_virtual_display(&self, r: &AddVirtualDisplay) -> ResultType<()> {
+        if !self.keyboard {
+            bail!("Permission denied");
+        }
+        if !virtual_display_manager::is_virtual_display_supported() {
+            bail!("idd_not_support_under_win10_2004_tip");
+        }
+        if r.width <= 0 || r.height <= 0 {
+            bail!("Invalid resolution {}x{}", r.width, r.height);
+        }
+        // The display list change is sent to the peer by the display service.
+        let indices =
+            virtual_display_manager::plug_in_peer_request(vec![vec![virtual_display::MonitorMode {
+                width: r.width as _,
+                height: r.height as _,
+                sync: 60,
+            }]])?;
+        if indices.is_empty() {
+            bail!("No available virtual display");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(all(windows, feature = "virtual_display_driver")))]
+    fn try_add_virtual_display(&self, _r: &AddVirtualDisplay) -> ResultType<()> {
+        bail!("Virtual display driver is not installed");
+    }
+
+    #[cfg(all(windows, feature = "virtual_display_driver"))]
+    fn try_remove_virtual_display(&self, r: &RemoveVirtualDisplay) -> ResultType<()> {
+        if !self.keyboard {
+            bail!("Permission denied");
+        }
+        let displays = display_service::try_get_displays()?;
+        let Some(display) = displays.get(r.display as usize) else {
+            bail!("Display {} does not exist", r.display);
+        };
+        let Some(index) = virtual_display_manager::get_peer_virtual_display_index(&display.name())
+        else {
+            bail!("Display {} is not a virtual display", r.display);
+        };
+        virtual_display_manager::plug_out_peer_request(&[index])
+    }
+
+    #[cfg(not(all(windows, feature = "virtual_display_driver")))]
+    fn try_remove_virtual_display(&self, _r: &RemoveVirtualDisplay) -> ResultType<()> {
+        bail!("Virtual display driver is not installed");
+    }
+
     async fn toggle_privacy_mode(&mut self, t: TogglePrivacyMode) {
+        if t.on && !self.peer_acl_allows("privacy_mode") {
+            let msg_out = crate::common::make_privacy_mode_msg(
+                back_notification::PrivacyModeState::PrvOnFailedDenied,
+                t.impl_key,
+            );
+            self.send(msg_out).await;
+            return;
+        }
         if t.on {
-            self.turn_on_privacy(t.impl_key).await;
+            self.turn_on_privacy(t.impl_key, t.display_off).await;
         } else {
             self.turn_off_privacy(t.impl_key).await;
         }
     }
 
+    /// See [`crate::common::PeerCapabilities`].
+    fn get_capabilities() -> serde_json::Value {
+        #[cfg(target_os = "linux")]
+        let is_wayland = crate::platform::current_is_wayland();
+        #[cfg(not(target_os = "linux"))]
+        let is_wayland = false;
+        // The input is injected by uinput on Wayland, which requires root.
+        #[cfg(target_os = "linux")]
+        let can_inject_input = !is_wayland || crate::platform::is_root();
+        #[cfg(not(target_os = "linux"))]
+        let can_inject_input = true;
+        let capture_backend = if is_wayland {
+            "pipewire"
+        } else if cfg!(target_os = "linux") {
+            "x11"
+        } else if cfg!(windows) {
+            "dxgi"
+        } else if cfg!(target_os = "macos") {
+            "quartz"
+        } else {
+            "media_projection"
+        };
+        json!({
+            "is_wayland": is_wayland,
+            "can_change_resolution": !is_wayland && !cfg!(target_os = "android"),
+            "can_inject_input": can_inject_input,
+            "capture_backend": capture_backend,
+            "remote_sysinfo": true,
+            "remote_volume": super::remote_volume::is_supported(),
+            "multi_stream_transfer": true,
+            "wake_on_lan": !cfg!(target_os = "ios"),
+            "viewport": true,
+            "frame_timestamps": true,
+            "file_ops": true,
+        })
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn change_resolution(&mut self, r: &Resolution) {
         if self.keyboard {
@@ -2450,14 +3424,18 @@ impl Connection {
                         (display.width() as _, display.height() as _),
                         (r.width, r.height),
                     );
-                    if let Err(e) =
-                        crate::platform::change_resolution(&name, r.width as _, r.height as _)
-                    {
+                    if let Err(e) = crate::platform::change_resolution(
+                        &name,
+                        r.width as _,
+                        r.height as _,
+                        r.refresh_rate.max(0) as _,
+                    ) {
                         log::error!(
-                            "Failed to change resolution '{}' to ({},{}): {:?}",
+                            "Failed to change resolution '{}' to ({},{}@{}): {:?}",
                             &name,
                             r.width,
                             r.height,
+                            r.refresh_rate,
                             e
                         );
                     }
@@ -2573,6 +3551,42 @@ impl Connection {
                 ));
             }
         }
+        if let Ok(q) = o.keep_awake.enum_value() {
+            if q != BoolOption::NotSet {
+                let id = self.inner.id();
+                if q == BoolOption::Yes {
+                    NO_KEEP_AWAKE_CONNS.lock().unwrap().remove(&id);
+                } else {
+                    NO_KEEP_AWAKE_CONNS.lock().unwrap().insert(id);
+                }
+                raii::AuthedConnID::check_wake_lock();
+            }
+        }
+        if let Ok(q) = o.print_forward.enum_value() {
+            if q == BoolOption::Yes {
+                if self.printer {
+                    self.print_forward.enable(print_forward::new_spooler());
+                } else {
+                    log::info!("Print forwarding requested, but not allowed");
+                }
+            } else if q == BoolOption::No {
+                self.print_forward.disable();
+            }
+        }
+        if let Ok(q) = o.view_only.enum_value() {
+            if q == BoolOption::Yes && !self.view_only {
+                self.set_view_only(true);
+            } else if q == BoolOption::No && self.view_only {
+                // Only the user of the host can allow the input again.
+                self.send_to_cm(ipc::Data::ViewOnlyOffRequest);
+            }
+        }
+        if let Ok(q) = o.audio_only.enum_value() {
+            if q != BoolOption::NotSet && self.audio_only != (q == BoolOption::Yes) {
+                self.audio_only = q == BoolOption::Yes;
+                self.send_to_cm(ipc::Data::AudioOnly(self.audio_only));
+            }
+        }
         if let Ok(q) = o.disable_clipboard.enum_value() {
             if q != BoolOption::NotSet {
                 self.disable_clipboard = q == BoolOption::Yes;
@@ -2610,7 +3624,7 @@ impl Connection {
                 if self.keyboard {
                     match q {
                         BoolOption::Yes => {
-                            self.turn_on_privacy("".to_owned()).await;
+                            self.turn_on_privacy("".to_owned(), false).await;
                         }
                         BoolOption::No => {
                             self.turn_off_privacy("".to_owned()).await;
@@ -2646,7 +3660,7 @@ impl Connection {
         }
     }
 
-    async fn turn_on_privacy(&mut self, impl_key: String) {
+    async fn turn_on_privacy(&mut self, impl_key: String, display_off: bool) {
         let msg_out = if !privacy_mode::is_privacy_mode_supported() {
             crate::common::make_privacy_mode_msg_with_details(
                 back_notification::PrivacyModeState::PrvNotSupported,
@@ -2679,8 +3693,15 @@ impl Connection {
                             5_000,
                         );
                         if err_msg.is_empty() {
-                            crate::common::make_privacy_mode_msg(
+                            // The privacy mode is on even if the displays are not powered off.
+                            let warning = if display_off {
+                                privacy_mode::turn_off_displays(self.inner.id)
+                            } else {
+                                None
+                            };
+                            crate::common::make_privacy_mode_msg_with_details(
                                 back_notification::PrivacyModeState::PrvOnSucceeded,
+                                warning.unwrap_or_default(),
                                 impl_key,
                             )
                         } else {
@@ -2782,6 +3803,7 @@ impl Connection {
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
         self.port_forward_socket.take();
+        self.port_forward_tunnel.take();
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -2819,7 +3841,7 @@ impl Connection {
     fn portable_check(&mut self) {
         if self.portable.is_installed
             || self.file_transfer.is_some()
-            || self.port_forward_socket.is_some()
+            || self.is_port_forward()
             || !self.keyboard
         {
             return;
@@ -2903,6 +3925,31 @@ pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
         .insert(id, (tokio::time::Instant::now(), uuid));
 }
 
+/// Whether an authorized connection may control the input of this device.
+pub fn is_remote_controlled() -> bool {
+    AUTHED_CONNS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|c| c.1 == AuthConnType::Remote)
+}
+
+// The key of the chr in the map and translate modes, the chr of the legacy mode is the character.
+#[cfg(not(target_os = "ios"))]
+fn key_event_rdev_key(me: &KeyEvent) -> Option<rdev::Key> {
+    match me.mode.enum_value() {
+        Ok(KeyboardMode::Map) => Some(crate::keyboard::keycode_to_rdev_key(me.chr())),
+        Ok(KeyboardMode::Translate) => {
+            if let Some(key_event::Union::Chr(code)) = me.union {
+                Some(crate::keyboard::keycode_to_rdev_key(code & 0x0000FFFF))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 async fn start_ipc(
     mut rx_to_cm: mpsc::UnboundedReceiver<ipc::Data>,
@@ -2910,8 +3957,6 @@ async fn start_ipc(
     mut _rx_desktop_ready: mpsc::Receiver<()>,
     tx_stream_ready: mpsc::Sender<()>,
 ) -> ResultType<()> {
-    use hbb_common::anyhow::anyhow;
-
     loop {
         if !crate::platform::is_prelogin() {
             break;
@@ -3031,8 +4076,10 @@ async fn start_ipc(
                         if let Data::FS(ipc::FS::WriteBlock{id,
                             file_num,
                             data,
-                            compressed}) = data {
-                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed})).await?;
+                            compressed,
+                            offset,
+                            stream: block_stream}) = data {
+                                stream.send(&Data::FS(ipc::FS::WriteBlock{id, file_num, data: Bytes::new(), compressed, offset, stream: block_stream})).await?;
                                 stream.send_raw(data).await?;
                         } else {
                             stream.send(&data).await?;
@@ -3077,6 +4124,15 @@ struct FileActionLog {
     dir: bool,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FileRenameLog {
+    id: i32,
+    conn_id: i32,
+    path: String,
+    new_name: String,
+}
+
 struct FileRemoveLogControl {
     conn_id: i32,
     instant: Instant,
@@ -3274,10 +4330,16 @@ mod raii {
         fn drop(&mut self) {
             let mut active_conns_lock = ALIVE_CONNS.lock().unwrap();
             active_conns_lock.retain(|&c| c != self.0);
+            NO_KEEP_AWAKE_CONNS.lock().unwrap().remove(&self.0);
             video_service::VIDEO_QOS
                 .lock()
                 .unwrap()
                 .on_connection_close(self.0);
+            crate::server::viewport::remove(self.0);
+            crate::server::frame_timestamps::remove(self.0);
+            crate::server::session_recording::stop(self.0);
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            crate::server::cm_watch::remove(self.0);
         }
     }
 
@@ -3295,15 +4357,16 @@ mod raii {
             Self(id, conn_type)
         }
 
-        fn check_wake_lock() {
+        /// The displays are kept on if any remote peer requires, see `OptionMessage::keep_awake`.
+        pub fn check_wake_lock() {
             let mut wake_lock = WAKE_LOCK.lock().unwrap();
-            let remote_count = AUTHED_CONNS
+            let no_keep_awake = NO_KEEP_AWAKE_CONNS.lock().unwrap();
+            let display = AUTHED_CONNS
                 .lock()
                 .unwrap()
                 .iter()
-                .filter(|c| c.1 == AuthConnType::Remote)
-                .count();
-            let display = remote_count > 0;
+                .any(|c| c.1 == AuthConnType::Remote && !no_keep_awake.contains(&c.0));
+            drop(no_keep_awake);
             if let Some((_, last_display)) = *wake_lock {
                 if last_display != display {
                     *wake_lock = None;
@@ -27,6 +27,9 @@ lazy_static::lazy_static! {
     // It should should not be updated when displays changed.
     pub static ref PRIMARY_DISPLAY_IDX: usize = get_primary();
     static ref SYNC_DISPLAYS: Arc<Mutex<SyncDisplaysInfo>> = Default::default();
+    // display name -> the size and the refresh rate of the display.
+    // Only queried again if the size changes, it runs "xrandr" on Linux.
+    static ref REFRESH_RATES: Mutex<HashMap<String, ((usize, usize), i32)>> = Default::default();
 }
 
 #[derive(Default)]
@@ -95,6 +98,8 @@ pub(super) fn check_display_changed(
 
 #[inline]
 pub fn set_last_changed_resolution(display_name: &str, original: (i32, i32), changed: (i32, i32)) {
+    // The refresh rate may be changed with the same size.
+    REFRESH_RATES.lock().unwrap().remove(display_name);
     let mut lock = CHANGED_RESOLUTIONS.write().unwrap();
     match lock.get_mut(display_name) {
         Some(res) => res.changed = changed,
@@ -112,7 +117,7 @@ pub fn set_last_changed_resolution(display_name: &str, original: (i32, i32), cha
 pub fn reset_resolutions() {
     for (name, res) in CHANGED_RESOLUTIONS.read().unwrap().iter() {
         let (w, h) = res.original;
-        if let Err(e) = crate::platform::change_resolution(name, w as _, h as _) {
+        if let Err(e) = crate::platform::change_resolution(name, w as _, h as _, 0) {
             log::error!(
                 "Failed to reset resolution of display '{}' to ({},{}): {}",
                 name,
@@ -251,6 +256,31 @@ pub(super) fn get_display_info(idx: usize) -> Option<DisplayInfo> {
     SYNC_DISPLAYS.lock().unwrap().displays.get(idx).cloned()
 }
 
+// The refresh rate and the DPI scale of the display, 0 if unknown.
+fn display_rate_scale(d: &Display, name: &str) -> (i32, f64) {
+    let size = (d.width(), d.height());
+    let mut rates = REFRESH_RATES.lock().unwrap();
+    let refresh_rate = match rates.get(name) {
+        Some((s, rate)) if *s == size => *rate,
+        _ => {
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            let rate = crate::platform::current_resolution(name)
+                .map(|r| r.refresh_rate)
+                .unwrap_or(0);
+            #[cfg(any(target_os = "android", target_os = "ios"))]
+            let rate = 0;
+            rates.insert(name.to_owned(), (size, rate));
+            rate
+        }
+    };
+    drop(rates);
+    #[cfg(windows)]
+    let scale = crate::platform::windows::display_scale(d.origin().0, d.origin().1).unwrap_or(0.);
+    #[cfg(not(windows))]
+    let scale = 0.;
+    (refresh_rate, scale)
+}
+
 // Display to DisplayInfo
 // The DisplayInfo is be sent to the peer.
 pub(super) fn check_update_displays(all: &Vec<Display>) {
@@ -259,6 +289,7 @@ pub(super) fn check_update_displays(all: &Vec<Display>) {
         .map(|d| {
             let display_name = d.name();
             let original_resolution = get_original_resolution(&display_name, d.width(), d.height());
+            let (refresh_rate, scale) = display_rate_scale(d, &display_name);
             DisplayInfo {
                 x: d.origin().0 as _,
                 y: d.origin().1 as _,
@@ -268,6 +299,8 @@ pub(super) fn check_update_displays(all: &Vec<Display>) {
                 online: d.is_online(),
                 cursor_embedded: false,
                 original_resolution,
+                refresh_rate,
+                scale,
                 ..Default::default()
             }
         })
@@ -0,0 +1,52 @@
+//! Stamp the frames with the capture time for the clients measuring the latency, see
+//! `client::latency`.
+//!
+//! Each connection asks by `Misc::frame_timestamps`. The frames of a display are shared by the
+//! connections subscribed, so they are stamped if any connection asks, and nothing is done
+//! otherwise.
+
+use hbb_common::{lazy_static, log};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static STAMPING: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref CONNS: Mutex<HashSet<i32>> = Default::default();
+}
+
+pub fn set(conn_id: i32, on: bool) {
+    let mut conns = CONNS.lock().unwrap();
+    let changed = if on {
+        conns.insert(conn_id)
+    } else {
+        conns.remove(&conn_id)
+    };
+    if changed {
+        log::info!("Conn {} frame timestamps: {}", conn_id, on);
+    }
+    STAMPING.store(!conns.is_empty(), Ordering::SeqCst);
+}
+
+#[inline]
+pub fn remove(conn_id: i32) {
+    set(conn_id, false);
+}
+
+/// The capture time in microseconds to stamp the frame captured just now, `None` if not asked.
+#[inline]
+pub fn capture_time() -> Option<i64> {
+    if !STAMPING.load(Ordering::SeqCst) {
+        return None;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_micros() as i64)
+}
@@ -0,0 +1,130 @@
+//! The lock and display power state of the host, sent to the peers when changed.
+//!
+//! One thread probes the state every [`PROBE_INTERVAL`] while any connection watches it,
+//! the connections compare the latest state with the one they sent on their timers.
+
+use hbb_common::message_proto::{host_display_state::State, HostDisplayState, Message, Misc};
+use std::{
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+static STATE: AtomicU8 = AtomicU8::new(State::Active as u8);
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The display off state wins, no frame is captured at all.
+fn state_of(locked: bool, display_off: bool) -> State {
+    if display_off {
+        State::DisplayOff
+    } else if locked {
+        State::Locked
+    } else {
+        State::Active
+    }
+}
+
+fn probe() -> State {
+    state_of(
+        crate::platform::is_session_locked(),
+        crate::platform::is_display_off(),
+    )
+}
+
+fn load() -> State {
+    match STATE.load(Ordering::SeqCst) {
+        x if x == State::Locked as u8 => State::Locked,
+        x if x == State::DisplayOff as u8 => State::DisplayOff,
+        _ => State::Active,
+    }
+}
+
+fn run() {
+    loop {
+        if WATCHERS.load(Ordering::SeqCst) == 0 {
+            RUNNING.store(false, Ordering::SeqCst);
+            // A watcher may come after the check, before `RUNNING` is cleared.
+            if WATCHERS.load(Ordering::SeqCst) == 0 || RUNNING.swap(true, Ordering::SeqCst) {
+                return;
+            }
+        }
+        STATE.store(probe() as u8, Ordering::SeqCst);
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+pub fn make_msg(state: State) -> Message {
+    let mut misc = Misc::new();
+    misc.set_host_display_state(HostDisplayState {
+        state: state.into(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+/// The state watched by a connection, the probing stops once all the watches are dropped.
+pub struct Watch {
+    // The peer assumes the active state until told.
+    sent: State,
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        WATCHERS.fetch_add(1, Ordering::SeqCst);
+        if !RUNNING.swap(true, Ordering::SeqCst) {
+            std::thread::spawn(run);
+        }
+        Self {
+            sent: State::Active,
+        }
+    }
+
+    /// The message to send if the state is changed since the last one sent.
+    pub fn poll(&mut self) -> Option<Message> {
+        check_changed(&mut self.sent, load())
+    }
+}
+
+fn check_changed(sent: &mut State, state: State) -> Option<Message> {
+    if state == *sent {
+        return None;
+    }
+    *sent = state;
+    Some(make_msg(state))
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_of() {
+        assert_eq!(state_of(false, false), State::Active);
+        assert_eq!(state_of(true, false), State::Locked);
+        assert_eq!(state_of(false, true), State::DisplayOff);
+        assert_eq!(state_of(true, true), State::DisplayOff);
+    }
+
+    #[test]
+    fn test_check_changed() {
+        let mut sent = State::Active;
+        assert!(check_changed(&mut sent, State::Active).is_none());
+        let msg = check_changed(&mut sent, State::Locked).unwrap();
+        assert_eq!(
+            msg.misc().host_display_state().state.enum_value_or_default(),
+            State::Locked
+        );
+        assert!(check_changed(&mut sent, State::Locked).is_none());
+        assert!(check_changed(&mut sent, State::Active).is_some());
+    }
+}
@@ -0,0 +1,369 @@
+//! The system information of the host, sent to the peer on request.
+//!
+//! The cpu usage is sampled for a while, so the collected info is cached for [`CACHE_TIMEOUT`]
+//! and reused by the requests of all the connections.
+
+use hbb_common::log;
+use serde_derive::Serialize;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const CACHE_TIMEOUT: Duration = Duration::from_secs(10);
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(300);
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<Option<(Instant, String)>> = Default::default();
+}
+
+/// The disk of the system, in bytes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Disk {
+    pub mount_point: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Battery {
+    pub percent: u8,
+    pub charging: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct HostSysinfo {
+    pub hostname: String,
+    pub os: String,
+    /// The build number on Windows, the kernel release on the others.
+    pub os_build: String,
+    pub cpu: String,
+    pub cpu_cores: usize,
+    /// In percent, of all the cores.
+    pub cpu_usage: f32,
+    /// In bytes.
+    pub memory_total: u64,
+    pub memory_used: u64,
+    pub disk: Option<Disk>,
+    pub uptime_secs: u64,
+    /// `None` if the host has no battery.
+    pub battery: Option<Battery>,
+    pub is_service: bool,
+    pub is_portable: bool,
+}
+
+impl HostSysinfo {
+    pub fn collect() -> Self {
+        use hbb_common::sysinfo::System;
+        let mut system = System::new();
+        system.refresh_memory();
+        system.refresh_cpu();
+        std::thread::sleep(CPU_SAMPLE_INTERVAL);
+        system.refresh_cpu();
+        let cpu = system
+            .cpus()
+            .first()
+            .map(|x| x.brand().trim_end().to_owned())
+            .unwrap_or_default();
+        Self {
+            hostname: crate::common::hostname(),
+            os: system.long_os_version().unwrap_or_default(),
+            os_build: system.kernel_version().unwrap_or_default(),
+            cpu,
+            cpu_cores: num_cpus::get(),
+            cpu_usage: system.global_cpu_info().cpu_usage(),
+            memory_total: system.total_memory(),
+            memory_used: system.used_memory(),
+            disk: imp::get_disk(),
+            uptime_secs: imp::get_uptime_secs(),
+            battery: imp::get_battery(),
+            is_service: imp::is_service(),
+            is_portable: imp::is_portable(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// The json of [`HostSysinfo`], collected again if the cached one is expired.
+pub fn get_json() -> String {
+    if let Some((t, json)) = CACHE.lock().unwrap().as_ref() {
+        if t.elapsed() < CACHE_TIMEOUT {
+            return json.clone();
+        }
+    }
+    let json = HostSysinfo::collect().to_json();
+    log::debug!("Collected the system information: {}", json);
+    *CACHE.lock().unwrap() = Some((Instant::now(), json.clone()));
+    json
+}
+
+/// The disk containing `path`, e.g. the space left for the files written to it.
+pub fn get_disk_of(path: &str) -> Option<Disk> {
+    #[cfg(not(windows))]
+    return statvfs_disk(path);
+    #[cfg(windows)]
+    return imp::disk_of(path);
+}
+
+// "350735.47 234388.90", the uptime and the idle time of all the cores.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "ios", test))]
+fn parse_proc_uptime(s: &str) -> Option<u64> {
+    let secs: f64 = s.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+// The `capacity` and `status` of `/sys/class/power_supply/BAT*`.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "ios", test))]
+fn parse_power_supply(capacity: &str, status: &str) -> Option<Battery> {
+    Some(Battery {
+        percent: capacity.trim().parse::<u8>().ok()?.min(100),
+        charging: status.trim() == "Charging",
+    })
+}
+
+// "{ sec = 1697000000, usec = 123456 } Wed Oct 11 04:53:20 2023"
+#[cfg(any(target_os = "macos", test))]
+fn parse_kern_boottime(s: &str) -> Option<u64> {
+    let s = s.split("sec =").nth(1)?;
+    s.split(',').next()?.trim().parse().ok()
+}
+
+// The output of `pmset -g batt`:
+// Now drawing from 'AC Power'
+//  -InternalBattery-0 (id=4653155)	87%; charging; 1:23 remaining present: true
+#[cfg(any(target_os = "macos", test))]
+fn parse_pmset_batt(s: &str) -> Option<Battery> {
+    let line = s.lines().find(|l| l.contains("InternalBattery"))?;
+    let mut fields = line.split(';');
+    let percent = fields.next()?.split_whitespace().last()?;
+    let status = fields.next().unwrap_or_default().trim();
+    Some(Battery {
+        percent: percent.trim_end_matches('%').parse::<u8>().ok()?.min(100),
+        charging: status == "charging" || status == "finishing charge",
+    })
+}
+
+#[cfg(not(windows))]
+fn statvfs_disk(mount_point: &str) -> Option<Disk> {
+    use hbb_common::libc;
+    let path = std::ffi::CString::new(mount_point).ok()?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    Some(Disk {
+        mount_point: mount_point.to_owned(),
+        total: st.f_blocks as u64 * st.f_frsize as u64,
+        available: st.f_bavail as u64 * st.f_frsize as u64,
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "ios"))]
+mod imp {
+    use super::*;
+
+    pub fn get_disk() -> Option<Disk> {
+        #[cfg(target_os = "android")]
+        return statvfs_disk("/data");
+        #[cfg(not(target_os = "android"))]
+        return statvfs_disk("/");
+    }
+
+    pub fn get_uptime_secs() -> u64 {
+        std::fs::read_to_string("/proc/uptime")
+            .ok()
+            .and_then(|s| parse_proc_uptime(&s))
+            .unwrap_or_default()
+    }
+
+    pub fn get_battery() -> Option<Battery> {
+        let dir = std::fs::read_dir("/sys/class/power_supply").ok()?;
+        for entry in dir.flatten() {
+            let path = entry.path();
+            let read = |name: &str| std::fs::read_to_string(path.join(name)).unwrap_or_default();
+            if read("type").trim() != "Battery" {
+                continue;
+            }
+            if let Some(battery) = parse_power_supply(&read("capacity"), &read("status")) {
+                return Some(battery);
+            }
+        }
+        None
+    }
+
+    // The server is run by the systemd service as root.
+    pub fn is_service() -> bool {
+        #[cfg(target_os = "linux")]
+        return crate::platform::is_root();
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+
+    pub fn is_portable() -> bool {
+        #[cfg(target_os = "linux")]
+        return !crate::platform::is_installed();
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::*;
+
+    fn run(cmd: &str, args: &[&str]) -> String {
+        std::process::Command::new(cmd)
+            .args(args)
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn get_disk() -> Option<Disk> {
+        statvfs_disk("/")
+    }
+
+    pub fn get_uptime_secs() -> u64 {
+        let boot = parse_kern_boottime(&run("sysctl", &["-n", "kern.boottime"]));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        boot.map(|b| now.saturating_sub(b)).unwrap_or_default()
+    }
+
+    pub fn get_battery() -> Option<Battery> {
+        parse_pmset_batt(&run("pmset", &["-g", "batt"]))
+    }
+
+    pub fn is_service() -> bool {
+        crate::platform::is_installed_daemon(false)
+    }
+
+    pub fn is_portable() -> bool {
+        !crate::platform::is_installed()
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::{
+        fileapi::GetDiskFreeSpaceExW,
+        sysinfoapi::GetTickCount64,
+        winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS},
+    };
+
+    pub fn get_disk() -> Option<Disk> {
+        let drive = std::env::var("SystemDrive").unwrap_or("C:".to_owned()) + "\\";
+        disk_of(&drive)
+    }
+
+    pub fn disk_of(path: &str) -> Option<Disk> {
+        let wide: Vec<u16> = std::ffi::OsStr::new(path)
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let (mut available, mut total) = (0u64, 0u64);
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available as *mut u64 as _,
+                &mut total as *mut u64 as _,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return None;
+        }
+        Some(Disk {
+            mount_point: path.to_owned(),
+            total,
+            available,
+        })
+    }
+
+    pub fn get_uptime_secs() -> u64 {
+        unsafe { GetTickCount64() / 1000 }
+    }
+
+    pub fn get_battery() -> Option<Battery> {
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+            return None;
+        }
+        // 128: no system battery, 255: unknown status.
+        if status.BatteryFlag & 128 != 0 || status.BatteryFlag == 255 {
+            return None;
+        }
+        Some(Battery {
+            percent: status.BatteryLifePercent.min(100),
+            charging: status.BatteryFlag & 8 != 0,
+        })
+    }
+
+    // The server is run by the windows service as the local system.
+    pub fn is_service() -> bool {
+        crate::platform::is_root()
+    }
+
+    pub fn is_portable() -> bool {
+        !crate::platform::is_installed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut info = HostSysinfo {
+            os_build: "22631".to_owned(),
+            memory_total: 16 << 30,
+            disk: Some(Disk {
+                mount_point: "C:\\".to_owned(),
+                total: 512 << 30,
+                available: 128 << 30,
+            }),
+            is_portable: true,
+            ..Default::default()
+        };
+        let v: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+        assert_eq!(v["os_build"], "22631");
+        assert_eq!(v["memory_total"], 16u64 << 30);
+        assert_eq!(v["disk"]["mount_point"], "C:\\");
+        assert_eq!(v["disk"]["available"], 128u64 << 30);
+        assert!(v["battery"].is_null());
+        assert_eq!(v["is_service"], false);
+        assert_eq!(v["is_portable"], true);
+
+        info.battery = Some(Battery {
+            percent: 87,
+            charging: true,
+        });
+        let v: serde_json::Value = serde_json::from_str(&info.to_json()).unwrap();
+        assert_eq!(v["battery"]["percent"], 87);
+        assert_eq!(v["battery"]["charging"], true);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_proc_uptime("350735.47 234388.90\n"), Some(350735));
+        assert_eq!(parse_proc_uptime(""), None);
+        let battery = parse_power_supply("87\n", "Discharging\n").unwrap();
+        assert_eq!((battery.percent, battery.charging), (87, false));
+        assert!(parse_power_supply("", "Full").is_none());
+        let boottime = "{ sec = 1697000000, usec = 123456 } Wed Oct 11 04:53:20 2023";
+        assert_eq!(parse_kern_boottime(boottime), Some(1697000000));
+        let batt = "Now drawing from 'AC Power'\n \
+            -InternalBattery-0 (id=4653155)\t87%; charging; 1:23 remaining present: true\n";
+        let battery = parse_pmset_batt(batt).unwrap();
+        assert_eq!((battery.percent, battery.charging), (87, true));
+        assert!(parse_pmset_batt("Now drawing from 'AC Power'\n").is_none());
+    }
+}
@@ -0,0 +1,513 @@
+//! The keyboard policy of the host, restricting the keys of the peers allowed the keyboard.
+//!
+//! The level is set by the option [`OPTION_KEYBOARD_LEVEL`], or by the peer acl rule if stricter.
+//! The keys listed in [`OPTION_KEYBOARD_BLOCKLIST`] are blocked at any level. The blocked key
+//! events are dropped before injected and counted, the peer is told by the `InputPolicy` message
+//! to show what is restricted.
+
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::{
+        key_event, ControlKey, InputPolicy as InputPolicyMsg, KeyEvent, Message, Misc,
+    },
+    protobuf::Enum,
+};
+use rdev::Key;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+pub const OPTION_KEYBOARD_LEVEL: &str = "keyboard-level";
+// Comma separated, the names of `ControlKey`, e.g. "F4", or the chr codes.
+pub const OPTION_KEYBOARD_BLOCKLIST: &str = "keyboard-blocklist";
+// The changes of the dropped count are sent at most once in the interval.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Ordered from the least restricted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyboardLevel {
+    #[default]
+    Full,
+    /// The meta keys, the function keys and the ctrl+alt sequences are blocked.
+    NoSystemKeys,
+    /// Only the text and the editing keys.
+    TextOnly,
+}
+
+impl KeyboardLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::NoSystemKeys => "no-system-keys",
+            Self::TextOnly => "text-only",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "" | "full" => Self::Full,
+            "no-system-keys" => Self::NoSystemKeys,
+            "text-only" => Self::TextOnly,
+            _ => {
+                log::warn!("Unknown keyboard level {:?}, text-only is used", s);
+                Self::TextOnly
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockedKey {
+    Control(ControlKey),
+    Chr(u32),
+}
+
+impl BlockedKey {
+    fn name(&self) -> String {
+        match self {
+            Self::Control(key) => format!("{:?}", key),
+            Self::Chr(chr) => chr.to_string(),
+        }
+    }
+}
+
+fn parse_blocklist(s: &str) -> Vec<BlockedKey> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .filter_map(|k| {
+            if let Ok(chr) = k.parse() {
+                return Some(BlockedKey::Chr(chr));
+            }
+            let key = ControlKey::VALUES
+                .iter()
+                .find(|c| format!("{:?}", c) == k)
+                .map(|c| BlockedKey::Control(*c));
+            if key.is_none() {
+                log::warn!("Unknown key {:?} in the keyboard blocklist", k);
+            }
+            key
+        })
+        .collect()
+}
+
+fn has_modifier(me: &KeyEvent, keys: &[ControlKey]) -> bool {
+    me.modifiers
+        .iter()
+        .any(|m| m.enum_value().map_or(false, |m| keys.contains(&m)))
+}
+
+fn is_system_key(me: &KeyEvent, key: Option<Key>) -> bool {
+    use ControlKey::*;
+    const META: [ControlKey; 2] = [Meta, RWin];
+    if has_modifier(me, &META)
+        || (has_modifier(me, &[Control, RControl]) && has_modifier(me, &[Alt, RAlt]))
+    {
+        return true;
+    }
+    if let Some(key_event::Union::ControlKey(ck)) = &me.union {
+        if let Ok(ck) = ck.enum_value() {
+            return META.contains(&ck)
+                || matches!(
+                    ck,
+                    CtrlAltDel
+                        | LockScreen
+                        | F1
+                        | F2
+                        | F3
+                        | F4
+                        | F5
+                        | F6
+                        | F7
+                        | F8
+                        | F9
+                        | F10
+                        | F11
+                        | F12
+                );
+        }
+    }
+    matches!(
+        key,
+        Some(
+            Key::MetaLeft
+                | Key::MetaRight
+                | Key::F1
+                | Key::F2
+                | Key::F3
+                | Key::F4
+                | Key::F5
+                | Key::F6
+                | Key::F7
+                | Key::F8
+                | Key::F9
+                | Key::F10
+                | Key::F11
+                | Key::F12
+        )
+    )
+}
+
+fn is_text_key(me: &KeyEvent, key: Option<Key>) -> bool {
+    use ControlKey::*;
+    const TEXT_MODIFIERS: [ControlKey; 4] = [Shift, RShift, CapsLock, NumLock];
+    if me.modifiers.iter().any(|m| {
+        m.enum_value()
+            .map_or(true, |m| !TEXT_MODIFIERS.contains(&m))
+    }) {
+        return false;
+    }
+    match &me.union {
+        Some(key_event::Union::Unicode(_)) | Some(key_event::Union::Seq(_)) => true,
+        Some(key_event::Union::ControlKey(ck)) => ck.enum_value().map_or(false, |ck| {
+            TEXT_MODIFIERS.contains(&ck)
+                || matches!(
+                    ck,
+                    Backspace
+                        | Delete
+                        | Return
+                        | NumpadEnter
+                        | Tab
+                        | Space
+                        | LeftArrow
+                        | RightArrow
+                        | UpArrow
+                        | DownArrow
+                        | Home
+                        | End
+                        | PageUp
+                        | PageDown
+                        | Numpad0
+                        | Numpad1
+                        | Numpad2
+                        | Numpad3
+                        | Numpad4
+                        | Numpad5
+                        | Numpad6
+                        | Numpad7
+                        | Numpad8
+                        | Numpad9
+                        | Multiply
+                        | Add
+                        | Subtract
+                        | Decimal
+                        | Divide
+                        | Equals
+                )
+        }),
+        // The chr of the legacy mode is the character.
+        Some(key_event::Union::Chr(_)) if key.is_none() => true,
+        Some(key_event::Union::Chr(_)) => matches!(
+            key,
+            Some(
+                Key::ShiftLeft
+                    | Key::ShiftRight
+                    | Key::CapsLock
+                    | Key::NumLock
+                    | Key::Backspace
+                    | Key::Delete
+                    | Key::Return
+                    | Key::KpReturn
+                    | Key::Tab
+                    | Key::Space
+                    | Key::LeftArrow
+                    | Key::RightArrow
+                    | Key::UpArrow
+                    | Key::DownArrow
+                    | Key::Home
+                    | Key::End
+                    | Key::PageUp
+                    | Key::PageDown
+                    | Key::KeyA
+                    | Key::KeyB
+                    | Key::KeyC
+                    | Key::KeyD
+                    | Key::KeyE
+                    | Key::KeyF
+                    | Key::KeyG
+                    | Key::KeyH
+                    | Key::KeyI
+                    | Key::KeyJ
+                    | Key::KeyK
+                    | Key::KeyL
+                    | Key::KeyM
+                    | Key::KeyN
+                    | Key::KeyO
+                    | Key::KeyP
+                    | Key::KeyQ
+                    | Key::KeyR
+                    | Key::KeyS
+                    | Key::KeyT
+                    | Key::KeyU
+                    | Key::KeyV
+                    | Key::KeyW
+                    | Key::KeyX
+                    | Key::KeyY
+                    | Key::KeyZ
+                    | Key::Num0
+                    | Key::Num1
+                    | Key::Num2
+                    | Key::Num3
+                    | Key::Num4
+                    | Key::Num5
+                    | Key::Num6
+                    | Key::Num7
+                    | Key::Num8
+                    | Key::Num9
+                    | Key::Kp0
+                    | Key::Kp1
+                    | Key::Kp2
+                    | Key::Kp3
+                    | Key::Kp4
+                    | Key::Kp5
+                    | Key::Kp6
+                    | Key::Kp7
+                    | Key::Kp8
+                    | Key::Kp9
+                    | Key::KpMinus
+                    | Key::KpPlus
+                    | Key::KpMultiply
+                    | Key::KpDivide
+                    | Key::KpDelete
+                    | Key::Minus
+                    | Key::Equal
+                    | Key::LeftBracket
+                    | Key::RightBracket
+                    | Key::SemiColon
+                    | Key::Quote
+                    | Key::BackQuote
+                    | Key::BackSlash
+                    | Key::IntlBackslash
+                    | Key::Comma
+                    | Key::Dot
+                    | Key::Slash
+            )
+        ),
+        _ => false,
+    }
+}
+
+/// The policy of a connection, the options are loaded again on the timer.
+#[derive(Default)]
+pub struct InputPolicy {
+    option_level: KeyboardLevel,
+    acl_level: KeyboardLevel,
+    blocked: Vec<BlockedKey>,
+    dropped: u64,
+    // (level, blocked, dropped, when) of the last message sent.
+    sent: Option<(KeyboardLevel, Vec<BlockedKey>, u64, Instant)>,
+}
+
+impl InputPolicy {
+    pub fn load() -> Self {
+        let mut policy = Self::default();
+        policy.reload();
+        policy
+    }
+
+    /// The stricter one of the option and the peer acl rule.
+    pub fn level(&self) -> KeyboardLevel {
+        self.option_level.max(self.acl_level)
+    }
+
+    /// The level of the peer acl rule, see `PeerAcl::keyboard_level`.
+    pub fn set_acl_level(&mut self, level: KeyboardLevel) {
+        self.acl_level = level;
+    }
+
+    /// Load the options again, returns true if the level is changed.
+    pub fn reload(&mut self) -> bool {
+        self.update(
+            &Config::get_option(OPTION_KEYBOARD_LEVEL),
+            &Config::get_option(OPTION_KEYBOARD_BLOCKLIST),
+        )
+    }
+
+    fn update(&mut self, level: &str, blocklist: &str) -> bool {
+        let old = self.level();
+        self.option_level = KeyboardLevel::parse(level);
+        self.blocked = parse_blocklist(blocklist);
+        old != self.level()
+    }
+
+    /// `key` is the key mapped from the chr of the map and translate modes. The releases are let
+    /// through, so that no key is left pressed on the host.
+    pub fn allows(&mut self, me: &KeyEvent, key: Option<Key>) -> bool {
+        if !me.down && !me.press {
+            return true;
+        }
+        let blocked = self.blocked.iter().any(|b| match (b, &me.union) {
+            (BlockedKey::Control(b), Some(key_event::Union::ControlKey(ck))) => {
+                ck.enum_value() == Ok(*b)
+            }
+            (BlockedKey::Chr(b), Some(key_event::Union::Chr(chr))) => chr == b,
+            _ => false,
+        });
+        let allowed = !blocked
+            && match self.level() {
+                KeyboardLevel::Full => true,
+                KeyboardLevel::NoSystemKeys => !is_system_key(me, key),
+                KeyboardLevel::TextOnly => is_text_key(me, key),
+            };
+        if !allowed {
+            self.dropped += 1;
+        }
+        allowed
+    }
+
+    /// The message to send if the policy is changed, or the dropped count after the interval.
+    pub fn poll(&mut self, now: Instant) -> Option<Message> {
+        let level = self.level();
+        if let Some((sent_level, sent_blocked, sent_dropped, when)) = &self.sent {
+            let policy_changed = *sent_level != level || *sent_blocked != self.blocked;
+            let dropped_changed = *sent_dropped != self.dropped
+                && now.saturating_duration_since(*when) >= REPORT_INTERVAL;
+            if !policy_changed && !dropped_changed {
+                return None;
+            }
+        }
+        self.sent = Some((level, self.blocked.clone(), self.dropped, now));
+        let mut misc = Misc::new();
+        misc.set_input_policy(InputPolicyMsg {
+            level: level.as_str().to_owned(),
+            blocked: self.blocked.iter().map(|b| b.name()).collect(),
+            dropped: self.dropped,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        Some(msg_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(level: &str, blocklist: &str) -> InputPolicy {
+        let mut policy = InputPolicy::default();
+        policy.update(level, blocklist);
+        policy
+    }
+
+    fn control(key: ControlKey, modifiers: &[ControlKey]) -> KeyEvent {
+        let mut me = KeyEvent::new();
+        me.set_control_key(key);
+        me.modifiers = modifiers.iter().map(|m| (*m).into()).collect();
+        me.down = true;
+        me
+    }
+
+    fn chr(chr: u32, modifiers: &[ControlKey]) -> KeyEvent {
+        let mut me = KeyEvent::new();
+        me.set_chr(chr);
+        me.modifiers = modifiers.iter().map(|m| (*m).into()).collect();
+        me.down = true;
+        me
+    }
+
+    fn unicode(c: char) -> KeyEvent {
+        let mut me = KeyEvent::new();
+        me.set_unicode(c as _);
+        me.press = true;
+        me
+    }
+
+    #[test]
+    fn test_no_system_keys() {
+        let mut p = policy("no-system-keys", "");
+        // Win+R, Ctrl+Alt+Del, Alt+F4.
+        assert!(!p.allows(&chr(19, &[ControlKey::Meta]), Some(Key::KeyR)));
+        assert!(!p.allows(&chr(0x5B, &[]), Some(Key::MetaLeft)));
+        assert!(!p.allows(&control(ControlKey::CtrlAltDel, &[]), None));
+        let del = control(ControlKey::Delete, &[ControlKey::Control, ControlKey::Alt]);
+        assert!(!p.allows(&del, None));
+        assert!(!p.allows(&control(ControlKey::F4, &[ControlKey::Alt]), None));
+        assert!(!p.allows(&chr(0x3E, &[]), Some(Key::F4)));
+        assert_eq!(p.dropped, 6);
+
+        assert!(p.allows(&chr(19, &[ControlKey::Control]), Some(Key::KeyR)));
+        assert!(p.allows(&control(ControlKey::Delete, &[ControlKey::Control]), None));
+        assert!(p.allows(&unicode('a'), None));
+        // The releases are let through.
+        let mut release = control(ControlKey::CtrlAltDel, &[]);
+        release.down = false;
+        assert!(p.allows(&release, None));
+        assert_eq!(p.dropped, 6);
+    }
+
+    #[test]
+    fn test_text_only() {
+        let mut p = policy("text-only", "");
+        assert!(p.allows(&unicode('a'), None));
+        assert!(p.allows(&chr('a' as _, &[ControlKey::Shift]), None));
+        assert!(p.allows(&chr(30, &[]), Some(Key::KeyA)));
+        assert!(p.allows(&chr(42, &[]), Some(Key::ShiftLeft)));
+        assert!(p.allows(&control(ControlKey::Backspace, &[]), None));
+        assert!(p.allows(&control(ControlKey::LeftArrow, &[ControlKey::Shift]), None));
+
+        assert!(!p.allows(&chr(46, &[ControlKey::Control]), Some(Key::KeyC)));
+        assert!(!p.allows(&chr(29, &[]), Some(Key::ControlLeft)));
+        assert!(!p.allows(&control(ControlKey::Escape, &[]), None));
+        assert!(!p.allows(&control(ControlKey::F5, &[]), None));
+        assert!(!p.allows(&chr(0x5B, &[]), Some(Key::MetaLeft)));
+        assert_eq!(p.dropped, 5);
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let mut p = policy("", "F5, Escape,57,unknown");
+        assert_eq!(p.level(), KeyboardLevel::Full);
+        assert_eq!(
+            p.blocked,
+            vec![
+                BlockedKey::Control(ControlKey::F5),
+                BlockedKey::Control(ControlKey::Escape),
+                BlockedKey::Chr(57)
+            ]
+        );
+        assert!(!p.allows(&control(ControlKey::F5, &[]), None));
+        assert!(!p.allows(&control(ControlKey::Escape, &[]), None));
+        assert!(!p.allows(&chr(57, &[]), Some(Key::Space)));
+        assert!(p.allows(&control(ControlKey::F6, &[]), None));
+        assert!(p.allows(&chr(58, &[]), Some(Key::CapsLock)));
+        assert_eq!(p.dropped, 3);
+    }
+
+    #[test]
+    fn test_level() {
+        let mut p = policy("no-system-keys", "");
+        p.set_acl_level(KeyboardLevel::TextOnly);
+        assert_eq!(p.level(), KeyboardLevel::TextOnly);
+        p.set_acl_level(KeyboardLevel::Full);
+        assert_eq!(p.level(), KeyboardLevel::NoSystemKeys);
+        assert!(p.update("text-only", ""));
+        assert!(!p.update("text-only", "F1"));
+        assert!(p.update("", ""));
+        assert_eq!(p.level(), KeyboardLevel::Full);
+        // Unknown ones are the strictest.
+        p.update("none", "");
+        assert_eq!(p.level(), KeyboardLevel::TextOnly);
+    }
+
+    #[test]
+    fn test_poll() {
+        let mut p = policy("text-only", "F1");
+        let now = Instant::now();
+        let msg = p.poll(now).unwrap();
+        let policy = msg.misc().input_policy();
+        assert_eq!(policy.level, "text-only");
+        assert_eq!(policy.blocked, vec!["F1".to_owned()]);
+        assert!(p.poll(now).is_none());
+
+        // The dropped count waits for the interval, the changes of the policy are sent at once.
+        p.allows(&control(ControlKey::Escape, &[]), None);
+        assert!(p.poll(now + Duration::from_secs(1)).is_none());
+        let msg = p.poll(now + REPORT_INTERVAL).unwrap();
+        assert_eq!(msg.misc().input_policy().dropped, 1);
+        p.update("", "F1");
+        let msg = p.poll(now + REPORT_INTERVAL).unwrap();
+        assert_eq!(msg.misc().input_policy().level, "full");
+    }
+}
@@ -323,25 +323,34 @@ fn run_pos(sp: EmptyExtraFieldService, state: &mut StatePos) -> ResultType<()> {
 fn run_cursor(sp: MouseCursorService, state: &mut StateCursor) -> ResultType<()> {
     if let Some(hcursor) = crate::get_cursor()? {
         if hcursor != state.hcursor {
-            let msg;
             if let Some(cached) = state.cached_cursor_data.get(&hcursor) {
                 super::log::trace!("Cursor data cached, hcursor: {}", hcursor);
-                msg = cached.clone();
+                // All the subscribers have got the cached data, see the snapshot below.
+                let mut msg = Message::new();
+                msg.set_cursor_id(hcursor);
+                sp.send(msg);
+                state.cursor_data = cached.clone();
             } else {
                 let mut data = crate::get_cursor_data(hcursor)?;
                 data.colors = hbb_common::compress::compress(&data.colors[..]).into();
                 let mut tmp = Message::new();
                 tmp.set_cursor_data(data);
-                msg = Arc::new(tmp);
+                let msg = Arc::new(tmp);
                 state.cached_cursor_data.insert(hcursor, msg.clone());
                 super::log::trace!("Cursor data updated, hcursor: {}", hcursor);
+                sp.send_shared(msg.clone());
+                state.cursor_data = msg;
             }
             state.hcursor = hcursor;
-            sp.send_shared(msg.clone());
-            state.cursor_data = msg;
         }
     }
     sp.snapshot(|sps| {
+        // Replay the cached cursors, the later changes to them are sent as the cursor ids only.
+        for (hcursor, msg) in state.cached_cursor_data.iter() {
+            if *hcursor != state.hcursor {
+                sps.send_shared(msg.clone());
+            }
+        }
         sps.send_shared(state.cursor_data.clone());
         Ok(())
     })?;
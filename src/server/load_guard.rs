@@ -0,0 +1,352 @@
+//! Lower the capture rate while the host is overloaded, so the machine being helped stays usable.
+//!
+//! One thread samples the system cpu and the busy ratio of the video threads every
+//! [`SAMPLE_INTERVAL`] while any connection watches. Each connection steps its own throttle level
+//! from the shared samples on its timer, see [`decide`], with the thresholds raised if the peer
+//! prioritizes the quality. The level lowers the fps of the connection in the video qos, and is
+//! sent to the peer when changed.
+//!
+//! The guard is enabled by the option "allow-load-guard", the thresholds are the options
+//! "load-guard-cpu-threshold" and "load-guard-encode-threshold" in percent.
+
+use super::video_service::VIDEO_QOS;
+use hbb_common::{
+    config::Config,
+    lazy_static, log,
+    message_proto::{HostThrottled, Message, Misc},
+    sysinfo::System,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const OPTION_ALLOW_LOAD_GUARD: &str = "allow-load-guard";
+pub const OPTION_CPU_THRESHOLD: &str = "load-guard-cpu-threshold";
+pub const OPTION_ENCODE_THRESHOLD: &str = "load-guard-encode-threshold";
+const DEFAULT_CPU_THRESHOLD: u32 = 85;
+const DEFAULT_ENCODE_THRESHOLD: u32 = 80;
+// The load is low below the thresholds minus the margin, and neither high nor low in between.
+const RECOVER_MARGIN: u32 = 20;
+const PRIORITIZE_QUALITY_RAISE: u32 = 10;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+// The samples in a row to step the level down, or up.
+const SUSTAINED_SAMPLES: u32 = 3;
+const RECOVER_SAMPLES: u32 = 5;
+// The fps in percent of each level.
+const LEVELS: [u32; 4] = [100, 75, 50, 25];
+
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // The busy time of the video threads since the last sample, by display.
+    static ref BUSY: Mutex<HashMap<usize, Duration>> = Default::default();
+    // The latest sample and its sequence number, 0 if none.
+    static ref SAMPLE: Mutex<(u64, Sample)> = Default::default();
+}
+
+/// The load in percent.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub cpu: u32,
+    // The busiest video thread, capturing and encoding.
+    pub encode: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub cpu: u32,
+    pub encode: u32,
+}
+
+impl Thresholds {
+    fn load(prioritize_quality: bool) -> Self {
+        let get = |name: &str, default: u32| {
+            Config::get_option(name)
+                .parse::<u32>()
+                .ok()
+                .filter(|v| (1..=100).contains(v))
+                .unwrap_or(default)
+        };
+        let thresholds = Self {
+            cpu: get(OPTION_CPU_THRESHOLD, DEFAULT_CPU_THRESHOLD),
+            encode: get(OPTION_ENCODE_THRESHOLD, DEFAULT_ENCODE_THRESHOLD),
+        };
+        if prioritize_quality {
+            thresholds.raised()
+        } else {
+            thresholds
+        }
+    }
+
+    fn raised(self) -> Self {
+        Self {
+            cpu: (self.cpu + PRIORITIZE_QUALITY_RAISE).min(100),
+            encode: (self.encode + PRIORITIZE_QUALITY_RAISE).min(100),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GuardState {
+    // The index of `LEVELS`.
+    pub level: usize,
+    // The high or low samples in a row.
+    high: u32,
+    low: u32,
+}
+
+/// Step the level down after [`SUSTAINED_SAMPLES`] high samples in a row, and back up one level
+/// after [`RECOVER_SAMPLES`] low samples in a row. A sample in the margin between resets both.
+pub fn decide(state: GuardState, sample: Sample, thresholds: Thresholds) -> GuardState {
+    let high = sample.cpu >= thresholds.cpu || sample.encode >= thresholds.encode;
+    let low = sample.cpu + RECOVER_MARGIN < thresholds.cpu
+        && sample.encode + RECOVER_MARGIN < thresholds.encode;
+    let mut state = state;
+    if high {
+        state.low = 0;
+        state.high = state.high.saturating_add(1);
+        if state.high >= SUSTAINED_SAMPLES && state.level + 1 < LEVELS.len() {
+            state.level += 1;
+            state.high = 0;
+        }
+    } else if low {
+        state.high = 0;
+        state.low = state.low.saturating_add(1);
+        if state.low >= RECOVER_SAMPLES && state.level > 0 {
+            state.level -= 1;
+            state.low = 0;
+        }
+    } else {
+        state.high = 0;
+        state.low = 0;
+    }
+    state
+}
+
+fn enabled() -> bool {
+    Config::get_option(OPTION_ALLOW_LOAD_GUARD) == "Y"
+}
+
+/// The time spent on capturing and encoding a frame, called by the video threads.
+pub fn record_busy(display: usize, busy: Duration) {
+    if WATCHERS.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    *BUSY.lock().unwrap().entry(display).or_default() += busy;
+}
+
+fn run() {
+    let mut system = System::new();
+    let mut last = Instant::now();
+    let mut seq = 0;
+    loop {
+        if WATCHERS.load(Ordering::SeqCst) == 0 {
+            RUNNING.store(false, Ordering::SeqCst);
+            // A watcher may come after the check, before `RUNNING` is cleared.
+            if WATCHERS.load(Ordering::SeqCst) == 0 || RUNNING.swap(true, Ordering::SeqCst) {
+                return;
+            }
+        }
+        std::thread::sleep(SAMPLE_INTERVAL);
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        let busy = std::mem::take(&mut *BUSY.lock().unwrap());
+        if !enabled() {
+            continue;
+        }
+        system.refresh_cpu();
+        let encode = busy
+            .values()
+            .map(|b| (b.as_secs_f64() * 100. / elapsed.as_secs_f64()) as u32)
+            .max()
+            .unwrap_or_default();
+        seq += 1;
+        *SAMPLE.lock().unwrap() = (
+            seq,
+            Sample {
+                cpu: system.global_cpu_info().cpu_usage() as u32,
+                encode: encode.min(100),
+            },
+        );
+    }
+}
+
+pub fn make_msg(level: usize, sample: Sample) -> Message {
+    let mut misc = Misc::new();
+    misc.set_host_throttled(HostThrottled {
+        fps_percent: LEVELS[level],
+        cpu: sample.cpu,
+        encode: sample.encode,
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+/// The throttle of a connection, the sampling stops once all the watches are dropped.
+pub struct Watch {
+    conn_id: i32,
+    state: GuardState,
+    // The sequence number of the last sample decided on.
+    seq: u64,
+    prioritize_quality: bool,
+}
+
+impl Watch {
+    pub fn new(conn_id: i32) -> Self {
+        WATCHERS.fetch_add(1, Ordering::SeqCst);
+        if !RUNNING.swap(true, Ordering::SeqCst) {
+            std::thread::spawn(run);
+        }
+        Self {
+            conn_id,
+            state: Default::default(),
+            seq: 0,
+            prioritize_quality: false,
+        }
+    }
+
+    /// The peer raises the thresholds for its session.
+    pub fn set_prioritize_quality(&mut self, v: bool) {
+        self.prioritize_quality = v;
+    }
+
+    /// The message to send if the level is changed by the latest sample.
+    pub fn poll(&mut self) -> Option<Message> {
+        let (seq, sample) = *SAMPLE.lock().unwrap();
+        let state = if !enabled() {
+            GuardState::default()
+        } else if seq != self.seq {
+            self.seq = seq;
+            decide(
+                self.state,
+                sample,
+                Thresholds::load(self.prioritize_quality),
+            )
+        } else {
+            return None;
+        };
+        let changed = state.level != self.state.level;
+        self.state = state;
+        if !changed {
+            return None;
+        }
+        log::info!(
+            "Load guard of conn {}: fps {}%, {:?}",
+            self.conn_id,
+            LEVELS[state.level],
+            sample
+        );
+        VIDEO_QOS
+            .lock()
+            .unwrap()
+            .user_load_throttle(self.conn_id, LEVELS[state.level]);
+        Some(make_msg(state.level, sample))
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TH: Thresholds = Thresholds {
+        cpu: 80,
+        encode: 80,
+    };
+    const HIGH: Sample = Sample {
+        cpu: 95,
+        encode: 10,
+    };
+    const LOW: Sample = Sample {
+        cpu: 20,
+        encode: 10,
+    };
+    // In the margin.
+    const MID: Sample = Sample {
+        cpu: 70,
+        encode: 10,
+    };
+
+    fn run(state: GuardState, samples: &[Sample]) -> GuardState {
+        samples.iter().fold(state, |s, x| decide(s, *x, TH))
+    }
+
+    #[test]
+    fn test_step_down() {
+        let state = run(GuardState::default(), &[HIGH, HIGH]);
+        assert_eq!(state.level, 0);
+        let state = run(state, &[HIGH]);
+        assert_eq!(state.level, 1);
+        // A spike in the middle is not sustained.
+        let state = run(state, &[HIGH, HIGH, MID, HIGH, HIGH]);
+        assert_eq!(state.level, 1);
+        let state = run(state, &[HIGH]);
+        assert_eq!(state.level, 2);
+        let state = run(state, &[HIGH; 10]);
+        assert_eq!(state.level, LEVELS.len() - 1);
+        let encode_high = Sample { cpu: 0, encode: 90 };
+        let state = run(GuardState::default(), &[encode_high; 3]);
+        assert_eq!(state.level, 1);
+    }
+
+    #[test]
+    fn test_recover() {
+        let throttled = run(GuardState::default(), &[HIGH; 6]);
+        assert_eq!(throttled.level, 2);
+        // No recovery in the margin.
+        assert_eq!(run(throttled, &[MID; 20]).level, 2);
+        let state = run(throttled, &[LOW; 4]);
+        assert_eq!(state.level, 2);
+        let state = run(state, &[LOW]);
+        assert_eq!(state.level, 1);
+        // One level at a time.
+        let state = run(state, &[LOW; 4]);
+        assert_eq!(state.level, 1);
+        let state = run(state, &[MID, LOW, LOW, LOW, LOW]);
+        assert_eq!(state.level, 1);
+        let state = run(state, &[LOW]);
+        assert_eq!(state.level, 0);
+        assert_eq!(run(state, &[LOW; 10]).level, 0);
+    }
+
+    #[test]
+    fn test_raised() {
+        let raised = TH.raised();
+        assert_eq!(
+            raised,
+            Thresholds {
+                cpu: 90,
+                encode: 90
+            }
+        );
+        let state = (0..3).fold(GuardState::default(), |s, _| {
+            decide(s, Sample { cpu: 85, encode: 0 }, raised)
+        });
+        assert_eq!(state.level, 0);
+        let max = Thresholds {
+            cpu: 95,
+            encode: 100,
+        }
+        .raised();
+        assert_eq!(
+            max,
+            Thresholds {
+                cpu: 100,
+                encode: 100
+            }
+        );
+    }
+}
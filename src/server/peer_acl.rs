@@ -0,0 +1,279 @@
+//! Per-peer access control of the incoming connections.
+//!
+//! The rules are stored as json in the option [`OPTION_PEER_ACL`]. The key of a rule is a peer
+//! id, an id prefix ending with `*`, or a group `@name` whose members are listed in `groups`.
+//! Peers without a matching rule keep the default permissions.
+//!
+//! If the option can not be parsed, all the peers are denied as [`INVALID_RULE_KEY`], instead of
+//! falling back to the default permissions.
+
+use super::input_policy::KeyboardLevel;
+use hbb_common::{anyhow::anyhow, bail, config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const OPTION_PEER_ACL: &str = "peer-acl";
+const GROUP_PREFIX: char = '@';
+const ID_PREFIX_SUFFIX: char = '*';
+/// The key of the rule resolved for all the peers if the option is invalid.
+pub const INVALID_RULE_KEY: &str = "<invalid>";
+
+/// The capabilities allowed by a rule, the missing ones are denied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerAcl {
+    pub keyboard: bool,
+    pub clipboard: bool,
+    pub file: bool,
+    pub audio: bool,
+    pub restart: bool,
+    pub recording: bool,
+    pub privacy_mode: bool,
+    /// Restricts the keys if the keyboard is allowed, the stricter one of the rule and the option
+    /// of the host is used.
+    pub keyboard_level: KeyboardLevel,
+}
+
+impl PeerAcl {
+    /// `name` is the permission name used by the cm, unknown ones are not controlled.
+    pub fn allows(&self, name: &str) -> bool {
+        match name {
+            "keyboard" => self.keyboard,
+            "clipboard" => self.clipboard,
+            "file" => self.file,
+            "audio" => self.audio,
+            "restart" => self.restart,
+            "recording" => self.recording,
+            "privacy_mode" => self.privacy_mode,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerAclStore {
+    pub rules: BTreeMap<String, PeerAcl>,
+    pub groups: BTreeMap<String, Vec<String>>,
+    // The option can not be parsed, every capability is denied.
+    #[serde(skip)]
+    invalid: bool,
+}
+
+impl PeerAclStore {
+    /// Load the rules in the server process.
+    pub fn load() -> Self {
+        Self::parse(&Config::get_option(OPTION_PEER_ACL)).unwrap_or_else(|e| {
+            log::error!("Failed to parse the peer acl, deny all the peers: {}", e);
+            Self {
+                invalid: true,
+                ..Default::default()
+            }
+        })
+    }
+
+    fn parse(s: &str) -> ResultType<Self> {
+        if s.is_empty() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Find the rule of the peer, the exact id first, then the groups, then the longest prefix.
+    pub fn resolve(&self, id: &str) -> Option<(String, PeerAcl)> {
+        if self.invalid {
+            return Some((INVALID_RULE_KEY.to_owned(), PeerAcl::default()));
+        }
+        if let Some(acl) = self.rules.get(id) {
+            return Some((id.to_owned(), *acl));
+        }
+        for (group, members) in self.groups.iter() {
+            if members.iter().any(|m| m == id) {
+                let key = format!("{}{}", GROUP_PREFIX, group);
+                if let Some(acl) = self.rules.get(&key) {
+                    return Some((key, *acl));
+                }
+            }
+        }
+        self.rules
+            .iter()
+            .filter_map(|(key, acl)| {
+                let prefix = key.strip_suffix(ID_PREFIX_SUFFIX)?;
+                id.starts_with(prefix).then_some((key, acl))
+            })
+            .max_by_key(|(key, _)| key.len())
+            .map(|(key, acl)| (key.clone(), *acl))
+    }
+}
+
+fn validate_key(key: &str) -> ResultType<()> {
+    if key.is_empty() || key.contains(char::is_whitespace) {
+        bail!("Invalid peer acl key: {:?}", key);
+    }
+    if let Some(group) = key.strip_prefix(GROUP_PREFIX) {
+        if group.is_empty() || group.contains(ID_PREFIX_SUFFIX) {
+            bail!("Invalid peer acl group: {:?}", key);
+        }
+    } else if key
+        .strip_suffix(ID_PREFIX_SUFFIX)
+        .unwrap_or(key)
+        .contains(ID_PREFIX_SUFFIX)
+    {
+        bail!("Only the trailing '*' is supported: {:?}", key);
+    }
+    Ok(())
+}
+
+// The ui side, the rules are synced to the server by the options.
+//
+// The invalid option is not overwritten by the edits, it can only be cleared by `reset_peer_acls`.
+fn load_local() -> ResultType<PeerAclStore> {
+    PeerAclStore::parse(&crate::ui_interface::get_option(OPTION_PEER_ACL))
+        .map_err(|e| anyhow!("Invalid peer acl, all the peers are denied: {}", e))
+}
+
+fn store_local(store: &PeerAclStore, key: &str) -> ResultType<()> {
+    let value = if store.rules.is_empty() && store.groups.is_empty() {
+        "".to_owned()
+    } else {
+        serde_json::to_string(store)?
+    };
+    crate::ui_interface::set_option(OPTION_PEER_ACL.to_owned(), value);
+    log::info!("Peer acl {} changed", key);
+    #[cfg(feature = "flutter")]
+    {
+        let event = serde_json::json!({ "name": "peer_acl_changed", "key": key });
+        let _ = crate::flutter::push_global_event(crate::flutter::APP_TYPE_MAIN, event.to_string());
+    }
+    Ok(())
+}
+
+/// The rules and groups as json, with `error` if the option is invalid and all the peers are
+/// denied.
+pub fn get_peer_acls() -> String {
+    match load_local() {
+        Ok(store) => serde_json::to_string(&store).unwrap_or_default(),
+        Err(e) => serde_json::json!({
+            "rules": {},
+            "groups": {},
+            "error": e.to_string(),
+        })
+        .to_string(),
+    }
+}
+
+/// Remove all the rules and groups, e.g. to recover from an invalid option.
+pub fn reset_peer_acls() -> ResultType<()> {
+    store_local(&PeerAclStore::default(), "all")
+}
+
+/// Add or replace the rule, `acl` is the json of [`PeerAcl`].
+pub fn set_peer_acl(key: &str, acl: &str) -> ResultType<()> {
+    validate_key(key)?;
+    let acl: PeerAcl = serde_json::from_str(acl)?;
+    let mut store = load_local()?;
+    store.rules.insert(key.to_owned(), acl);
+    store_local(&store, key)
+}
+
+pub fn remove_peer_acl(key: &str) -> ResultType<bool> {
+    let mut store = load_local()?;
+    if store.rules.remove(key).is_none() {
+        return Ok(false);
+    }
+    store_local(&store, key)?;
+    Ok(true)
+}
+
+/// Set the members of the group, the group is removed if `members` is empty.
+pub fn set_peer_acl_group(name: &str, members: Vec<String>) -> ResultType<()> {
+    let key = format!("{}{}", GROUP_PREFIX, name);
+    validate_key(&key)?;
+    let mut store = load_local()?;
+    if members.is_empty() {
+        store.groups.remove(name);
+    } else {
+        store.groups.insert(name.to_owned(), members);
+    }
+    store_local(&store, &key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let full = PeerAcl {
+            keyboard: true,
+            clipboard: true,
+            file: true,
+            audio: true,
+            restart: true,
+            recording: true,
+            privacy_mode: true,
+            keyboard_level: KeyboardLevel::Full,
+        };
+        let view = PeerAcl::default();
+        let audio = PeerAcl {
+            audio: true,
+            ..Default::default()
+        };
+        let store = PeerAclStore::parse(
+            &serde_json::json!({
+                "rules": {
+                    "123456789": full,
+                    "@office": audio,
+                    "12*": view,
+                    "1234*": full,
+                },
+                "groups": { "office": ["123456789", "555"] },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(store.resolve("123456789"), Some(("123456789".to_owned(), full)));
+        assert_eq!(store.resolve("555"), Some(("@office".to_owned(), audio)));
+        assert_eq!(store.resolve("123499"), Some(("1234*".to_owned(), full)));
+        assert_eq!(store.resolve("129"), Some(("12*".to_owned(), view)));
+        assert_eq!(store.resolve("987"), None);
+        assert!(!view.allows("keyboard"));
+        assert!(view.allows("block_input"));
+        assert!(PeerAclStore::parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_invalid_denies_all() {
+        let store = PeerAclStore {
+            invalid: true,
+            ..Default::default()
+        };
+        let (key, acl) = store.resolve("987").unwrap();
+        assert_eq!(key, INVALID_RULE_KEY);
+        assert_eq!(acl, PeerAcl::default());
+        assert!(!acl.allows("keyboard"));
+        assert!(!acl.allows("file"));
+    }
+
+    #[test]
+    fn test_keyboard_level() {
+        let acl: PeerAcl =
+            serde_json::from_str(r#"{"keyboard": true, "keyboard_level": "text-only"}"#).unwrap();
+        assert!(acl.allows("keyboard"));
+        assert_eq!(acl.keyboard_level, KeyboardLevel::TextOnly);
+        let acl: PeerAcl = serde_json::from_str(r#"{"keyboard": true}"#).unwrap();
+        assert_eq!(acl.keyboard_level, KeyboardLevel::Full);
+        assert!(serde_json::from_str::<PeerAcl>(r#"{"keyboard_level": "none"}"#).is_err());
+    }
+
+    #[test]
+    fn test_validate_key() {
+        assert!(validate_key("123456789").is_ok());
+        assert!(validate_key("12*").is_ok());
+        assert!(validate_key("@office").is_ok());
+        assert!(validate_key("").is_err());
+        assert!(validate_key("@").is_err());
+        assert!(validate_key("1*2").is_err());
+        assert!(validate_key("12 3").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! The permissions following the options of the host.
+//!
+//! The options are checked on the timer of each connection, the flipped ones are applied to the
+//! connection the same as switched by the cm, so the peer and the cm are told at once.
+//!
+//! The permissions granted by the cm for a while are reverted to the options if the cm exits
+//! before they expire, the grants are not kept over a restart of the cm.
+
+use std::collections::HashSet;
+
+/// (option, the permission name of the cm)
+pub const PERMISSION_OPTIONS: [(&str, &str); 4] = [
+    ("enable-keyboard", "keyboard"),
+    ("enable-clipboard", "clipboard"),
+    ("enable-audio", "audio"),
+    ("enable-file-transfer", "file"),
+];
+
+/// The last values of [`PERMISSION_OPTIONS`] seen by a connection.
+pub struct PermissionOptions {
+    last: [bool; PERMISSION_OPTIONS.len()],
+}
+
+impl PermissionOptions {
+    /// `permission` is the permission given by an option, i.e. `Connection::permission`.
+    pub fn new(permission: impl Fn(&str) -> bool) -> Self {
+        Self {
+            last: PERMISSION_OPTIONS.map(|(option, _)| permission(option)),
+        }
+    }
+
+    /// The (permission name, enabled) flipped since the last poll, in the order of
+    /// [`PERMISSION_OPTIONS`].
+    pub fn poll(&mut self, permission: impl Fn(&str) -> bool) -> Vec<(&'static str, bool)> {
+        let mut changed = vec![];
+        for (i, (option, name)) in PERMISSION_OPTIONS.iter().enumerate() {
+            let enabled = permission(option);
+            if enabled != self.last[i] {
+                self.last[i] = enabled;
+                changed.push((*name, enabled));
+            }
+        }
+        changed
+    }
+}
+
+/// The permissions granted for a while by the cm, see `ui_cm_interface::grants`.
+#[derive(Default)]
+pub struct TimedGrants(HashSet<String>);
+
+impl TimedGrants {
+    pub fn set(&mut self, name: &str, timed: bool) {
+        if timed {
+            self.0.insert(name.to_owned());
+        } else {
+            self.0.remove(name);
+        }
+    }
+
+    /// The (permission name, enabled) of the options of the host to revert the grants to, in the
+    /// order of [`PERMISSION_OPTIONS`]. The grants are cleared.
+    pub fn revert(&mut self, permission: impl Fn(&str) -> bool) -> Vec<(&'static str, bool)> {
+        let reverted = PERMISSION_OPTIONS
+            .iter()
+            .filter(|(_, name)| self.0.contains(*name))
+            .map(|(option, name)| (*name, permission(option)))
+            .collect();
+        self.0.clear();
+        reverted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+
+    // The options of the host, "N" to disable like `Config::get_option`.
+    #[derive(Default)]
+    struct FakeOptions(RefCell<HashMap<String, String>>);
+
+    impl FakeOptions {
+        fn set(&self, name: &str, value: &str) {
+            self.0.borrow_mut().insert(name.to_owned(), value.to_owned());
+        }
+
+        fn permission(&self, name: &str) -> bool {
+            self.0.borrow().get(name).map_or(true, |v| v.is_empty())
+        }
+    }
+
+    #[test]
+    fn test_poll_flipped_options() {
+        let options = FakeOptions::default();
+        options.set("enable-audio", "N");
+        let mut watch = PermissionOptions::new(|o| options.permission(o));
+        assert!(watch.poll(|o| options.permission(o)).is_empty());
+
+        // Toggled mid-session, reported in a fixed order.
+        options.set("enable-file-transfer", "N");
+        options.set("enable-clipboard", "N");
+        options.set("enable-audio", "");
+        assert_eq!(
+            watch.poll(|o| options.permission(o)),
+            vec![("clipboard", false), ("audio", true), ("file", false)]
+        );
+        assert!(watch.poll(|o| options.permission(o)).is_empty());
+
+        // Flipped back and forth between the polls.
+        options.set("enable-keyboard", "N");
+        options.set("enable-keyboard", "");
+        assert!(watch.poll(|o| options.permission(o)).is_empty());
+        options.set("enable-clipboard", "");
+        assert_eq!(
+            watch.poll(|o| options.permission(o)),
+            vec![("clipboard", true)]
+        );
+    }
+
+    #[test]
+    fn test_timed_grants_revert_on_cm_restart() {
+        let options = FakeOptions::default();
+        options.set("enable-file-transfer", "N");
+        let mut grants = TimedGrants::default();
+        // Granted for a while, then the clipboard one is cancelled, i.e. kept.
+        grants.set("file", true);
+        grants.set("clipboard", true);
+        grants.set("clipboard", false);
+        assert_eq!(grants.revert(|o| options.permission(o)), vec![("file", false)]);
+        // Nothing left for the restarted cm.
+        assert!(grants.revert(|o| options.permission(o)).is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+//! Forward the jobs printed on the host to the client, which hands them to its own printer.
+//!
+//! The jobs are captured by a [`PrintSpooler`] from the virtual printer of the platform, and sent
+//! by [`PrintSender`] in chunks of [`CHUNK_SIZE`], one chunk per tick of the file timer of the
+//! connection, like the file transfer. The forwarding is enabled only if the host allows it by
+//! the option [`OPTION_ENABLE_REMOTE_PRINTER`] and the client asks for it.
+//!
+//! No platform captures the spooled jobs yet, [`new_spooler`] returns a stub.
+
+use hbb_common::{
+    log,
+    message_proto::{print_job, PrintJob, PrintJobStart},
+    tokio::sync::mpsc,
+    ResultType,
+};
+use serde_derive::Serialize;
+use std::collections::VecDeque;
+
+pub const OPTION_ENABLE_REMOTE_PRINTER: &str = "enable-remote-printer";
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintFormat {
+    Pdf,
+    Xps,
+}
+
+impl PrintFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pdf => "pdf",
+            Self::Xps => "xps",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpooledJob {
+    pub name: String,
+    pub format: PrintFormat,
+    pub data: Vec<u8>,
+}
+
+/// The capture of the jobs printed to the virtual printer of the platform.
+pub trait PrintSpooler: Send {
+    /// Start capturing, the jobs are sent to `tx` once spooled.
+    fn start(&mut self, tx: mpsc::UnboundedSender<SpooledJob>) -> ResultType<()>;
+    fn stop(&mut self);
+}
+
+struct StubSpooler;
+
+impl PrintSpooler for StubSpooler {
+    fn start(&mut self, _tx: mpsc::UnboundedSender<SpooledJob>) -> ResultType<()> {
+        log::info!("Capturing the print jobs is not supported on this platform yet");
+        Ok(())
+    }
+
+    fn stop(&mut self) {}
+}
+
+pub fn new_spooler() -> Box<dyn PrintSpooler> {
+    Box::new(StubSpooler)
+}
+
+/// The record of a forwarded job, logged by the connection manager.
+#[derive(Debug, Serialize)]
+pub struct PrintJobLog {
+    pub id: u32,
+    pub name: String,
+    pub format: PrintFormat,
+    pub size: usize,
+    pub error: String,
+}
+
+struct Sending {
+    id: u32,
+    job: SpooledJob,
+    offset: usize,
+    started: bool,
+}
+
+#[derive(Default)]
+pub struct PrintSender {
+    spooler: Option<Box<dyn PrintSpooler>>,
+    rx: Option<mpsc::UnboundedReceiver<SpooledJob>>,
+    queue: VecDeque<SpooledJob>,
+    sending: Option<Sending>,
+    next_id: u32,
+}
+
+impl PrintSender {
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.spooler.is_some()
+    }
+
+    pub fn enable(&mut self, mut spooler: Box<dyn PrintSpooler>) {
+        if self.is_enabled() {
+            return;
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        if let Err(e) = spooler.start(tx) {
+            log::error!("Failed to start capturing the print jobs: {}", e);
+            return;
+        }
+        self.spooler = Some(spooler);
+        self.rx = Some(rx);
+    }
+
+    /// Stop capturing, the jobs captured are still sent.
+    pub fn disable(&mut self) {
+        if let Some(mut spooler) = self.spooler.take() {
+            spooler.stop();
+        }
+        self.rx = None;
+    }
+
+    /// The next job spooled, pending forever if disabled.
+    pub async fn next_spooled(&mut self) -> Option<SpooledJob> {
+        match self.rx.as_mut() {
+            Some(rx) => rx.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    pub fn push(&mut self, job: SpooledJob) {
+        log::info!("Print job spooled: {}, {} bytes", job.name, job.data.len());
+        self.queue.push_back(job);
+    }
+
+    #[inline]
+    pub fn is_busy(&self) -> bool {
+        self.sending.is_some() || !self.queue.is_empty()
+    }
+
+    /// The next message to send, and the log once a job is finished.
+    pub fn next_message(&mut self) -> Option<(PrintJob, Option<PrintJobLog>)> {
+        if self.sending.is_none() {
+            let job = self.queue.pop_front()?;
+            self.next_id += 1;
+            self.sending = Some(Sending {
+                id: self.next_id,
+                job,
+                offset: 0,
+                started: false,
+            });
+        }
+        let sending = self.sending.as_mut()?;
+        let mut msg = PrintJob {
+            id: sending.id,
+            ..Default::default()
+        };
+        if !sending.started {
+            sending.started = true;
+            msg.union = Some(print_job::Union::Start(PrintJobStart {
+                name: sending.job.name.clone(),
+                format: sending.job.format.as_str().to_owned(),
+                size: sending.job.data.len() as _,
+                ..Default::default()
+            }));
+            return Some((msg, None));
+        }
+        let len = sending.job.data.len();
+        if sending.offset < len {
+            let end = (sending.offset + CHUNK_SIZE).min(len);
+            msg.union = Some(print_job::Union::Data(
+                sending.job.data[sending.offset..end].to_vec().into(),
+            ));
+            sending.offset = end;
+            return Some((msg, None));
+        }
+        msg.union = Some(print_job::Union::Done(true));
+        let log = self.finish("");
+        Some((msg, log))
+    }
+
+    /// The job is aborted by the client, returns the log of it.
+    pub fn on_peer_message(&mut self, job: PrintJob) -> Option<PrintJobLog> {
+        match job.union {
+            Some(print_job::Union::Error(err)) if self.sending.as_ref()?.id == job.id => {
+                log::warn!("Print job {} aborted by the peer: {}", job.id, err);
+                self.finish(&err)
+            }
+            _ => None,
+        }
+    }
+
+    fn finish(&mut self, error: &str) -> Option<PrintJobLog> {
+        let sending = self.sending.take()?;
+        Some(PrintJobLog {
+            id: sending.id,
+            name: sending.job.name,
+            format: sending.job.format,
+            size: sending.job.data.len(),
+            error: error.to_owned(),
+        })
+    }
+}
+
+impl Drop for PrintSender {
+    fn drop(&mut self) {
+        self.disable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(name: &str, size: usize) -> SpooledJob {
+        SpooledJob {
+            name: name.to_owned(),
+            format: PrintFormat::Pdf,
+            data: vec![1; size],
+        }
+    }
+
+    #[test]
+    fn test_send_chunks() {
+        let mut sender = PrintSender::default();
+        assert!(!sender.is_busy());
+        assert!(sender.next_message().is_none());
+        sender.push(job("a", CHUNK_SIZE + 1));
+        sender.push(job("b", 0));
+
+        let (msg, log) = sender.next_message().unwrap();
+        assert_eq!(msg.id, 1);
+        assert!(log.is_none());
+        match msg.union {
+            Some(print_job::Union::Start(s)) => {
+                assert_eq!((s.name.as_str(), s.format.as_str()), ("a", "pdf"));
+                assert_eq!(s.size, CHUNK_SIZE as u64 + 1);
+            }
+            _ => panic!("start expected"),
+        }
+        let mut sizes = vec![];
+        while let Some((msg, log)) = sender.next_message() {
+            match msg.union {
+                Some(print_job::Union::Data(d)) => sizes.push(d.len()),
+                Some(print_job::Union::Done(_)) => {
+                    assert_eq!(log.unwrap().size, CHUNK_SIZE + 1);
+                    break;
+                }
+                _ => panic!("data expected"),
+            }
+        }
+        assert_eq!(sizes, vec![CHUNK_SIZE, 1]);
+
+        // The empty job, then aborted before done.
+        assert!(sender.next_message().is_some());
+        assert!(sender.is_busy());
+        let abort = PrintJob {
+            id: 2,
+            union: Some(print_job::Union::Error("disk full".to_owned())),
+            ..Default::default()
+        };
+        let log = sender.on_peer_message(abort).unwrap();
+        assert_eq!((log.name.as_str(), log.error.as_str()), ("b", "disk full"));
+        assert!(!sender.is_busy());
+    }
+}
@@ -0,0 +1,419 @@
+//! The output volume of the host, controlled by the peers.
+//!
+//! The volume and the mute of the default output device are changed, the gain of the audio
+//! stream sent to the peers is untouched. One thread probes the volume every [`PROBE_INTERVAL`]
+//! while any connection watches it, so the changes made by the local user or by the other peers
+//! are sent too, like `host_display_state`.
+
+use hbb_common::{
+    log,
+    message_proto::{Message, Misc, RemoteVolume},
+    ResultType,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<Option<VolumeState>> = Default::default();
+}
+static WATCHERS: AtomicUsize = AtomicUsize::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeState {
+    /// 0-100.
+    pub percent: u32,
+    pub muted: bool,
+}
+
+impl VolumeState {
+    pub fn to_msg(&self) -> RemoteVolume {
+        RemoteVolume {
+            percent: self.percent,
+            muted: self.muted,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn is_supported() -> bool {
+    imp::is_supported()
+}
+
+pub fn get() -> ResultType<VolumeState> {
+    let (percent, muted) = imp::get()?;
+    let state = VolumeState {
+        percent: percent.min(100),
+        muted,
+    };
+    *STATE.lock().unwrap() = Some(state);
+    Ok(state)
+}
+
+/// Returns the state read back, which may differ from the one set, e.g. rounded.
+pub fn set(percent: u32, muted: bool) -> ResultType<VolumeState> {
+    imp::set(percent.min(100), muted)?;
+    get()
+}
+
+pub fn make_msg(volume: RemoteVolume) -> Message {
+    let mut misc = Misc::new();
+    misc.set_remote_volume(volume);
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+fn run() {
+    loop {
+        if WATCHERS.load(Ordering::SeqCst) == 0 {
+            RUNNING.store(false, Ordering::SeqCst);
+            // A watcher may come after the check, before `RUNNING` is cleared.
+            if WATCHERS.load(Ordering::SeqCst) == 0 || RUNNING.swap(true, Ordering::SeqCst) {
+                return;
+            }
+        }
+        if let Err(e) = get() {
+            log::debug!("Failed to probe the output volume: {}", e);
+        }
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+/// The volume watched by a connection once the peer asks for it, the probing stops once all the
+/// watches are dropped.
+pub struct Watch {
+    sent: Option<VolumeState>,
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        WATCHERS.fetch_add(1, Ordering::SeqCst);
+        if !RUNNING.swap(true, Ordering::SeqCst) {
+            std::thread::spawn(run);
+        }
+        Self { sent: None }
+    }
+
+    /// The state is answered to the peer, not sent again by [`Watch::poll`].
+    pub fn set_sent(&mut self, state: VolumeState) {
+        self.sent = Some(state);
+    }
+
+    /// The message to send if the volume is changed since the last one sent.
+    pub fn poll(&mut self) -> Option<Message> {
+        let state = (*STATE.lock().unwrap())?;
+        check_changed(&mut self.sent, state)
+    }
+}
+
+fn check_changed(sent: &mut Option<VolumeState>, state: VolumeState) -> Option<Message> {
+    if *sent == Some(state) {
+        return None;
+    }
+    *sent = Some(state);
+    Some(make_msg(state.to_msg()))
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// The volume of PulseAudio, `0x10000` is 100%.
+#[cfg(any(target_os = "linux", test))]
+const PA_VOLUME_NORM: u32 = 0x10000;
+
+#[cfg(any(target_os = "linux", test))]
+fn pa_percent(volume: u32) -> u32 {
+    ((volume as u64 * 100 + PA_VOLUME_NORM as u64 / 2) / PA_VOLUME_NORM as u64) as u32
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn pa_volume(percent: u32) -> u32 {
+    (percent as u64 * PA_VOLUME_NORM as u64 / 100) as u32
+}
+
+// The output of `osascript -e "get volume settings"`:
+// output volume:50, input volume:75, alert volume:100, output muted:false
+#[cfg(any(target_os = "macos", test))]
+fn parse_volume_settings(s: &str) -> Option<(u32, bool)> {
+    let mut percent = None;
+    let mut muted = None;
+    for field in s.trim().split(',') {
+        match field.trim().split_once(':') {
+            Some(("output volume", v)) => percent = v.trim().parse().ok(),
+            Some(("output muted", v)) => muted = Some(v.trim() == "true"),
+            _ => {}
+        }
+    }
+    Some((percent?, muted?))
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use hbb_common::anyhow::anyhow;
+    use pulse::volume::{ChannelVolumes, Volume};
+    use pulsectl::controllers::{DeviceControl, SinkController};
+
+    fn sinks() -> ResultType<SinkController> {
+        SinkController::create().map_err(|e| anyhow!("Failed to connect PulseAudio: {:?}", e))
+    }
+
+    // PulseAudio is reached by the server run as the user.
+    pub fn is_supported() -> bool {
+        sinks().is_ok()
+    }
+
+    pub fn get() -> ResultType<(u32, bool)> {
+        let dev = sinks()?
+            .get_default_device()
+            .map_err(|e| anyhow!("Failed to get the default sink: {:?}", e))?;
+        Ok((pa_percent(dev.volume.avg().0), dev.mute))
+    }
+
+    pub fn set(percent: u32, muted: bool) -> ResultType<()> {
+        let mut sinks = sinks()?;
+        let dev = sinks
+            .get_default_device()
+            .map_err(|e| anyhow!("Failed to get the default sink: {:?}", e))?;
+        let mut volume: ChannelVolumes = dev.volume;
+        let channels = volume.len();
+        volume.set(channels, Volume(pa_volume(percent)));
+        sinks.set_device_volume_by_index(dev.index, &volume);
+        sinks.set_device_mute_by_index(dev.index, muted);
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::*;
+    use hbb_common::bail;
+
+    fn osascript(script: &str) -> ResultType<String> {
+        let output = std::process::Command::new("osascript")
+            .args(["-e", script])
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "osascript failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    pub fn is_supported() -> bool {
+        true
+    }
+
+    pub fn get() -> ResultType<(u32, bool)> {
+        let settings = osascript("get volume settings")?;
+        match parse_volume_settings(&settings) {
+            Some(v) => Ok(v),
+            // "missing value" if the output device has no volume, e.g. hdmi.
+            None => bail!("Unknown volume settings: {}", settings.trim()),
+        }
+    }
+
+    pub fn set(percent: u32, muted: bool) -> ResultType<()> {
+        let muted = if muted { "with" } else { "without" };
+        osascript(&format!(
+            "set volume output volume {} {} output muted",
+            percent, muted
+        ))?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::*;
+    use hbb_common::bail;
+    use winapi::{
+        shared::winerror::{FAILED, RPC_E_CHANGED_MODE},
+        um::{
+            combaseapi::{CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL},
+            endpointvolume::IAudioEndpointVolume,
+            mmdeviceapi::{eConsole, eRender, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator},
+            objbase::COINIT_MULTITHREADED,
+            unknwnbase::IUnknown,
+        },
+        Class, Interface,
+    };
+
+    // Released on drop.
+    struct Com<T: Interface>(*mut T);
+
+    impl<T: Interface> Drop for Com<T> {
+        fn drop(&mut self) {
+            unsafe { (*(self.0 as *mut IUnknown)).Release() };
+        }
+    }
+
+    // Called in the blocking threads, initialized and uninitialized per call.
+    fn with_endpoint<R>(f: impl FnOnce(&IAudioEndpointVolume) -> ResultType<R>) -> ResultType<R> {
+        unsafe {
+            let hr = CoInitializeEx(std::ptr::null_mut(), COINIT_MULTITHREADED);
+            let initialized = !FAILED(hr);
+            if !initialized && hr != RPC_E_CHANGED_MODE {
+                bail!("Failed to initialize COM: {:#x}", hr);
+            }
+            let res = (|| {
+                let mut enumerator: *mut IMMDeviceEnumerator = std::ptr::null_mut();
+                let hr = CoCreateInstance(
+                    &MMDeviceEnumerator::uuidof(),
+                    std::ptr::null_mut(),
+                    CLSCTX_ALL,
+                    &IMMDeviceEnumerator::uuidof(),
+                    &mut enumerator as *mut _ as _,
+                );
+                if FAILED(hr) {
+                    bail!("Failed to create the device enumerator: {:#x}", hr);
+                }
+                let enumerator = Com(enumerator);
+                let mut device: *mut IMMDevice = std::ptr::null_mut();
+                let hr = (*enumerator.0).GetDefaultAudioEndpoint(eRender, eConsole, &mut device);
+                if FAILED(hr) {
+                    bail!("Failed to get the default output device: {:#x}", hr);
+                }
+                let device = Com(device);
+                let mut endpoint: *mut IAudioEndpointVolume = std::ptr::null_mut();
+                let hr = (*device.0).Activate(
+                    &IAudioEndpointVolume::uuidof(),
+                    CLSCTX_ALL,
+                    std::ptr::null_mut(),
+                    &mut endpoint as *mut _ as _,
+                );
+                if FAILED(hr) {
+                    bail!("Failed to activate the endpoint volume: {:#x}", hr);
+                }
+                let endpoint = Com(endpoint);
+                f(&*endpoint.0)
+            })();
+            if initialized {
+                CoUninitialize();
+            }
+            res
+        }
+    }
+
+    pub fn is_supported() -> bool {
+        true
+    }
+
+    pub fn get() -> ResultType<(u32, bool)> {
+        with_endpoint(|endpoint| unsafe {
+            let mut scalar = 0f32;
+            let hr = endpoint.GetMasterVolumeLevelScalar(&mut scalar);
+            if FAILED(hr) {
+                bail!("Failed to get the volume: {:#x}", hr);
+            }
+            let mut muted = 0;
+            let hr = endpoint.GetMute(&mut muted);
+            if FAILED(hr) {
+                bail!("Failed to get the mute: {:#x}", hr);
+            }
+            Ok(((scalar * 100.).round() as u32, muted != 0))
+        })
+    }
+
+    pub fn set(percent: u32, muted: bool) -> ResultType<()> {
+        with_endpoint(|endpoint| unsafe {
+            let hr = endpoint.SetMasterVolumeLevelScalar(percent as f32 / 100., std::ptr::null());
+            if FAILED(hr) {
+                bail!("Failed to set the volume: {:#x}", hr);
+            }
+            let hr = endpoint.SetMute(muted as _, std::ptr::null());
+            if FAILED(hr) {
+                bail!("Failed to set the mute: {:#x}", hr);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+mod imp {
+    use super::*;
+    use hbb_common::bail;
+
+    pub fn is_supported() -> bool {
+        false
+    }
+
+    pub fn get() -> ResultType<(u32, bool)> {
+        bail!("Controlling the output volume is not supported on this platform");
+    }
+
+    pub fn set(_percent: u32, _muted: bool) -> ResultType<()> {
+        bail!("Controlling the output volume is not supported on this platform");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pa_volume() {
+        assert_eq!(pa_percent(PA_VOLUME_NORM), 100);
+        assert_eq!(pa_percent(0), 0);
+        assert_eq!(pa_percent(pa_volume(37)), 37);
+        // Above 100% is allowed by PulseAudio.
+        assert_eq!(pa_percent(PA_VOLUME_NORM * 3 / 2), 150);
+    }
+
+    #[test]
+    fn test_parse_volume_settings() {
+        assert_eq!(
+            parse_volume_settings(
+                "output volume:50, input volume:75, alert volume:100, output muted:false\n"
+            ),
+            Some((50, false))
+        );
+        assert_eq!(
+            parse_volume_settings("output volume:0, output muted:true"),
+            Some((0, true))
+        );
+        assert_eq!(
+            parse_volume_settings(
+                "output volume:missing value, input volume:missing value, output muted:missing value"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_changed() {
+        let mut sent = None;
+        let state = VolumeState {
+            percent: 30,
+            muted: false,
+        };
+        let msg = check_changed(&mut sent, state).unwrap();
+        assert_eq!(msg.misc().remote_volume().percent, 30);
+        assert!(check_changed(&mut sent, state).is_none());
+        let muted = VolumeState {
+            muted: true,
+            ..state
+        };
+        assert!(
+            check_changed(&mut sent, muted)
+                .unwrap()
+                .misc()
+                .remote_volume()
+                .muted
+        );
+    }
+}
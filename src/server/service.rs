@@ -240,6 +240,16 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         conn_ids
     }
 
+    /// The ids of the connections subscribed, including the new ones.
+    pub fn subscriber_ids(&self) -> Vec<i32> {
+        let lock = self.0.read().unwrap();
+        lock.subscribes
+            .keys()
+            .chain(lock.new_subscribes.keys())
+            .cloned()
+            .collect()
+    }
+
     pub fn send_without(&self, msg: Message, sub: i32) {
         let mut lock = self.0.write().unwrap();
         let msg = Arc::new(msg);
@@ -0,0 +1,530 @@
+//! Record the incoming remote control sessions on the host, if required by the policy, e.g. for
+//! the compliance.
+//!
+//! If [`OPTION_ENABLE`] is set, each accepted remote control connection records the video sent to
+//! it into [`dir`], a file per display named by the host, the connection and the peer, and the
+//! chat into a json lines transcript if [`OPTION_CHAT`] is set. The frames recorded are the ones
+//! encoded for the connection, so nothing is encoded again. The peer is told by the
+//! "session_recorded_notice" message box, and the cm shows the badge of the connection.
+//!
+//! The recording stops, but the session goes on, if the disk has less than [`MIN_FREE_SPACE`] or
+//! a file fails to be written, which is recorded in the connection history. The old recordings
+//! are removed by the retention options on the start of the server.
+
+use super::conn_history::{self, ConnAction, ConnRecord};
+use crate::ipc::Data;
+use hbb_common::{
+    config::Config,
+    log,
+    message_proto::{message, Message},
+    tokio::sync::mpsc::UnboundedSender,
+};
+use scrap::{
+    record::{RecordState, Recorder, RecorderContext},
+    CodecFormat,
+};
+use serde_derive::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const OPTION_ENABLE: &str = "enable-session-recording";
+/// The directory of the recordings, "sessions" in the default video save directory if empty.
+pub const OPTION_DIR: &str = "session-recording-dir";
+pub const OPTION_CHAT: &str = "session-recording-chat";
+/// The max total size of the recordings in MB, unlimited if empty or 0.
+pub const OPTION_MAX_SIZE: &str = "session-recording-max-size";
+/// The max age of the recordings in days, unlimited if empty or 0.
+pub const OPTION_MAX_AGE: &str = "session-recording-max-age";
+pub const MIN_FREE_SPACE: u64 = 256 * 1024 * 1024;
+/// The reasons of the records in the connection history.
+pub const REASON_DISK_FULL: &str = "recording_disk_full";
+pub const REASON_WRITE_FAILED: &str = "recording_write_failed";
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// In the names of the files, so the retention never removes the other files of the directory.
+const NAME_MARK: &str = "_session";
+const CHAT_EXT: &str = ".chat.jsonl";
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // conn id -> the recording, kept until the connection is closed.
+    static ref RECORDINGS: Mutex<HashMap<i32, Recording>> = Default::default();
+}
+
+/// The recording of a connection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RecordingState {
+    pub recording: bool,
+    /// The files written, the video of the displays and the chat transcript.
+    pub files: Vec<String>,
+    /// Why the recording is stopped before the connection is closed, empty if not stopped.
+    pub stopped_reason: String,
+}
+
+struct DisplayRecorder {
+    recorder: Recorder,
+    rx: Receiver<RecordState>,
+    // The file being written.
+    file: String,
+}
+
+impl DisplayRecorder {
+    fn update_files(&mut self, files: &mut Vec<String>) {
+        update_files(&self.rx, &mut self.file, files);
+    }
+
+    // Dropped to write the tail, the file too short is removed.
+    fn finish(self, files: &mut Vec<String>) {
+        let Self {
+            recorder,
+            rx,
+            mut file,
+        } = self;
+        drop(recorder);
+        update_files(&rx, &mut file, files);
+    }
+}
+
+fn update_files(rx: &Receiver<RecordState>, current: &mut String, files: &mut Vec<String>) {
+    for state in rx.try_iter() {
+        match state {
+            RecordState::NewFile(file) => {
+                files.push(file.clone());
+                *current = file;
+            }
+            RecordState::RemoveFile => files.retain(|f| f != current),
+            RecordState::NewFrame | RecordState::WriteTail => {}
+        }
+    }
+}
+
+struct Recording {
+    peer_id: String,
+    name: String,
+    ip: String,
+    dir: PathBuf,
+    // The name of the files without the display and the time.
+    id: String,
+    recorders: HashMap<usize, DisplayRecorder>,
+    chat: Option<File>,
+    last_disk_check: Option<Instant>,
+    tx: UnboundedSender<Data>,
+    state: RecordingState,
+}
+
+impl Recording {
+    // The disk is checked at most once in `DISK_CHECK_INTERVAL`.
+    fn disk_full(&mut self, now: Instant) -> bool {
+        if self.last_disk_check.map_or(false, |t| {
+            now.saturating_duration_since(t) < DISK_CHECK_INTERVAL
+        }) {
+            return false;
+        }
+        self.last_disk_check = Some(now);
+        is_disk_full(&self.dir)
+    }
+
+    fn update_files(&mut self) {
+        for r in self.recorders.values_mut() {
+            r.update_files(&mut self.state.files);
+        }
+    }
+
+    // Stop recording, the session goes on.
+    fn stop(&mut self, conn_id: i32, reason: &str) {
+        log::error!("Stop recording the session of conn {}: {}", conn_id, reason);
+        for (_, r) in self.recorders.drain() {
+            r.finish(&mut self.state.files);
+        }
+        self.chat = None;
+        self.state.recording = false;
+        self.state.stopped_reason = reason.to_owned();
+        conn_history::record(ConnRecord::new(
+            &self.peer_id,
+            &self.name,
+            &self.ip,
+            ConnAction::RecordingStopped,
+            reason,
+        ));
+        self.tx.send(Data::SessionRecording(false)).ok();
+    }
+
+    fn stop_on_error(&mut self, conn_id: i32) {
+        let reason = if is_disk_full(&self.dir) {
+            REASON_DISK_FULL
+        } else {
+            REASON_WRITE_FAILED
+        };
+        self.stop(conn_id, reason);
+    }
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    Config::get_option(OPTION_ENABLE) == "Y"
+}
+
+/// The directory of the recordings.
+pub fn dir() -> PathBuf {
+    let dir = Config::get_option(OPTION_DIR);
+    if dir.is_empty() {
+        PathBuf::from(crate::ui_interface::default_video_save_directory()).join("sessions")
+    } else {
+        PathBuf::from(dir)
+    }
+}
+
+fn is_disk_full(dir: &Path) -> bool {
+    super::host_sysinfo::get_disk_of(&dir.to_string_lossy())
+        .map_or(false, |d| d.available < MIN_FREE_SPACE)
+}
+
+// The peer id is from the peer, only the safe characters are kept in the names of the files.
+fn recording_id(host_id: &str, conn_id: i32, peer_id: &str) -> String {
+    let peer_id: String = peer_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    format!("{}{}{}_{}", host_id, NAME_MARK, conn_id, peer_id)
+}
+
+fn is_recording_file(name: &str) -> bool {
+    name.starts_with('s')
+        && name.contains(NAME_MARK)
+        && (name.ends_with(".webm") || name.ends_with(".mp4") || name.ends_with(CHAT_EXT))
+}
+
+/// Whether any session is recorded, the video is captured in whole and with the key frames then.
+#[inline]
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+fn update_active(recordings: &HashMap<i32, Recording>) {
+    ACTIVE.store(
+        recordings.values().any(|r| r.state.recording),
+        Ordering::SeqCst,
+    );
+}
+
+/// Start recording the accepted remote control connection if required by the policy. Returns
+/// true if started.
+pub fn start(conn_id: i32, peer_id: &str, name: &str, ip: &str, tx: UnboundedSender<Data>) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let mut recordings = RECORDINGS.lock().unwrap();
+    if recordings.contains_key(&conn_id) {
+        return false;
+    }
+    let dir = dir();
+    let mut recording = Recording {
+        peer_id: peer_id.to_owned(),
+        name: name.to_owned(),
+        ip: ip.to_owned(),
+        dir: dir.clone(),
+        id: recording_id(&Config::get_id(), conn_id, peer_id),
+        recorders: Default::default(),
+        chat: None,
+        last_disk_check: None,
+        tx,
+        state: RecordingState {
+            recording: true,
+            ..Default::default()
+        },
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::error!(
+            "Failed to create the directory of the recordings {:?}: {}",
+            dir,
+            e
+        );
+        recording.stop_on_error(conn_id);
+        recordings.insert(conn_id, recording);
+        return false;
+    }
+    if recording.disk_full(Instant::now()) {
+        recording.stop(conn_id, REASON_DISK_FULL);
+        recordings.insert(conn_id, recording);
+        return false;
+    }
+    if Config::get_option(OPTION_CHAT) == "Y" {
+        let path = dir.join(format!(
+            "s{}_{}{}",
+            recording.id,
+            chrono::Local::now().format("%Y%m%d%H%M%S%3f"),
+            CHAT_EXT
+        ));
+        match File::create(&path) {
+            Ok(file) => {
+                recording.chat = Some(file);
+                recording
+                    .state
+                    .files
+                    .push(path.to_string_lossy().to_string());
+            }
+            Err(e) => {
+                log::error!("Failed to create the chat transcript {:?}: {}", path, e);
+                recording.stop_on_error(conn_id);
+                recordings.insert(conn_id, recording);
+                return false;
+            }
+        }
+    }
+    log::info!("Record the session of conn {} into {:?}", conn_id, dir);
+    recording.tx.send(Data::SessionRecording(true)).ok();
+    recordings.insert(conn_id, recording);
+    update_active(&recordings);
+    true
+}
+
+/// The connection is closed, the files are completed.
+pub fn stop(conn_id: i32) {
+    let mut recordings = RECORDINGS.lock().unwrap();
+    if recordings.remove(&conn_id).is_some() {
+        log::info!("Stop recording the session of conn {}", conn_id);
+    }
+    update_active(&recordings);
+}
+
+/// The recording of the connection, `None` if not recorded.
+pub fn state(conn_id: i32) -> Option<RecordingState> {
+    let mut recordings = RECORDINGS.lock().unwrap();
+    let recording = recordings.get_mut(&conn_id)?;
+    recording.update_files();
+    Some(recording.state.clone())
+}
+
+#[inline]
+pub fn is_recording(conn_id: i32) -> bool {
+    RECORDINGS
+        .lock()
+        .unwrap()
+        .get(&conn_id)
+        .map_or(false, |r| r.state.recording)
+}
+
+/// The video frame of the display of `width` x `height` is sent to the connections, called by
+/// the video threads.
+pub fn on_video_frame(
+    display: usize,
+    conn_ids: &HashSet<i32>,
+    width: usize,
+    height: usize,
+    msg: &Message,
+) {
+    if !is_active() {
+        return;
+    }
+    let Some(message::Union::VideoFrame(vf)) = &msg.union else {
+        return;
+    };
+    let Some(frame) = &vf.union else {
+        return;
+    };
+    let now = Instant::now();
+    let mut recordings = RECORDINGS.lock().unwrap();
+    for conn_id in conn_ids {
+        let Some(recording) = recordings.get_mut(conn_id) else {
+            continue;
+        };
+        if !recording.state.recording {
+            continue;
+        }
+        if recording.disk_full(now) {
+            recording.stop(*conn_id, REASON_DISK_FULL);
+            continue;
+        }
+        if !recording.recorders.contains_key(&display) {
+            let (tx, rx) = channel();
+            let res = Recorder::new(RecorderContext {
+                server: true,
+                id: format!("{}_display{}", recording.id, display),
+                default_dir: "".to_owned(),
+                dir: recording.dir.to_string_lossy().to_string(),
+                filename: "".to_owned(),
+                width,
+                height,
+                format: CodecFormat::from(vf),
+                tx: Some(tx),
+            });
+            match res {
+                Ok(recorder) => {
+                    recording.recorders.insert(
+                        display,
+                        DisplayRecorder {
+                            recorder,
+                            rx,
+                            file: "".to_owned(),
+                        },
+                    );
+                }
+                Err(e) => {
+                    log::error!("Failed to create the recorder: {}", e);
+                    recording.stop_on_error(*conn_id);
+                    continue;
+                }
+            }
+        }
+        let res = recording
+            .recorders
+            .get_mut(&display)
+            .map(|r| r.recorder.write_frame(frame));
+        if let Some(Err(e)) = res {
+            log::error!("Failed to record the frame: {}", e);
+            recording.stop_on_error(*conn_id);
+            continue;
+        }
+        recording.update_files();
+    }
+    update_active(&recordings);
+}
+
+/// The chat message of the connection, from the peer or from the local user.
+pub fn on_chat(conn_id: i32, from_peer: bool, text: &str) {
+    let mut recordings = RECORDINGS.lock().unwrap();
+    let Some(recording) = recordings.get_mut(&conn_id) else {
+        return;
+    };
+    let Some(file) = recording.chat.as_mut() else {
+        return;
+    };
+    let line = serde_json::json!({
+        "time": hbb_common::get_time(),
+        "from": if from_peer { "peer" } else { "host" },
+        "text": text,
+    });
+    if let Err(e) = writeln!(file, "{}", line) {
+        log::error!("Failed to record the chat: {}", e);
+        recording.stop_on_error(conn_id);
+        update_active(&recordings);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RecordingFile {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+// The files to remove by the retention, the oldest first.
+fn expired(
+    mut files: Vec<RecordingFile>,
+    now: SystemTime,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+) -> Vec<PathBuf> {
+    files.sort_by_key(|f| f.modified);
+    let mut total: u64 = files.iter().map(|f| f.size).sum();
+    let mut removed = vec![];
+    for f in files {
+        let too_old = max_age.map_or(false, |age| {
+            now.duration_since(f.modified).unwrap_or_default() > age
+        });
+        let too_large = max_size.map_or(false, |size| total > size);
+        if !too_old && !too_large {
+            break;
+        }
+        total -= f.size;
+        removed.push(f.path);
+    }
+    removed
+}
+
+fn option_u64(name: &str) -> Option<u64> {
+    Config::get_option(name)
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|v| *v > 0)
+}
+
+/// Remove the old recordings by the retention options, on the start of the server.
+pub fn cleanup() {
+    let max_size = option_u64(OPTION_MAX_SIZE).map(|mb| mb.saturating_mul(1024 * 1024));
+    let max_age = option_u64(OPTION_MAX_AGE).map(|days| Duration::from_secs(days * 24 * 3600));
+    if max_size.is_none() && max_age.is_none() {
+        return;
+    }
+    let dir = dir();
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return;
+    };
+    let files = entries
+        .flatten()
+        .filter(|e| is_recording_file(&e.file_name().to_string_lossy()))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some(RecordingFile {
+                path: e.path(),
+                size: meta.len(),
+                modified: meta.modified().ok()?,
+            })
+        })
+        .collect();
+    for path in expired(files, SystemTime::now(), max_size, max_age) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => log::info!("Removed the expired recording {:?}", path),
+            Err(e) => log::error!("Failed to remove the recording {:?}: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_file() {
+        let id = recording_id("123456789", 3, "98/../7 65");
+        assert_eq!(id, "123456789_session3_98765");
+        assert!(is_recording_file(&format!(
+            "s{}_display0_20261015120000000_vp9.webm",
+            id
+        )));
+        assert!(is_recording_file(&format!(
+            "s{}_20261015120000000.chat.jsonl",
+            id
+        )));
+        // The recordings of the client and the other files.
+        assert!(!is_recording_file("c123456789_20261015120000000_vp9.webm"));
+        assert!(!is_recording_file(&format!("s{}.txt", id)));
+    }
+
+    #[test]
+    fn test_expired() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 3600);
+        let file = |name: &str, size: u64, days: u32| RecordingFile {
+            path: PathBuf::from(name),
+            size,
+            modified: now - day * days,
+        };
+        let files = vec![file("a", 100, 1), file("b", 100, 10), file("c", 100, 5)];
+        assert!(expired(files.clone(), now, None, None).is_empty());
+        assert_eq!(
+            expired(files.clone(), now, None, Some(day * 3)),
+            vec![PathBuf::from("b"), PathBuf::from("c")]
+        );
+        assert_eq!(
+            expired(files.clone(), now, Some(150), None),
+            vec![PathBuf::from("b"), PathBuf::from("c")]
+        );
+        assert_eq!(
+            expired(files.clone(), now, Some(250), Some(day * 7)),
+            vec![PathBuf::from("b")]
+        );
+        assert!(expired(files, now, Some(300), Some(day * 30)).is_empty());
+    }
+}
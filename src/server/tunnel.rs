@@ -0,0 +1,166 @@
+use bytes::Bytes;
+use hbb_common::{
+    futures::{SinkExt, StreamExt},
+    log,
+    message_proto::*,
+    timeout,
+    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio_util::codec::{BytesCodec, Framed},
+};
+use std::collections::HashMap;
+
+const CONNECT_TIMEOUT: u64 = 3000;
+
+// SOCKS5 reply codes, see RFC 1928.
+pub const REPLY_SUCCEEDED: i32 = 0;
+pub const REPLY_GENERAL_FAILURE: i32 = 1;
+pub const REPLY_NOT_ALLOWED: i32 = 2;
+pub const REPLY_HOST_UNREACHABLE: i32 = 4;
+pub const REPLY_CONNECTION_REFUSED: i32 = 5;
+
+/// The remote end of a dynamic port forward.
+///
+/// Every channel opened by the client is connected to its own target,
+/// and all of them share the peer connection.
+pub struct Tunnel {
+    channels: HashMap<u32, mpsc::UnboundedSender<Bytes>>,
+    tx: mpsc::UnboundedSender<TunnelFrame>,
+    rx: mpsc::UnboundedReceiver<TunnelFrame>,
+}
+
+impl Tunnel {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            channels: Default::default(),
+            tx,
+            rx,
+        }
+    }
+
+    /// The next frame to send to the client.
+    pub async fn next(&mut self) -> Option<TunnelFrame> {
+        let frame = self.rx.recv().await?;
+        match &frame.union {
+            Some(tunnel_frame::Union::Close(_)) => {
+                self.channels.remove(&frame.channel);
+            }
+            Some(tunnel_frame::Union::OpenResult(r)) if r.code != REPLY_SUCCEEDED => {
+                self.channels.remove(&frame.channel);
+            }
+            _ => {}
+        }
+        Some(frame)
+    }
+
+    pub fn handle_frame(&mut self, frame: TunnelFrame) {
+        let channel = frame.channel;
+        match frame.union {
+            Some(tunnel_frame::Union::Open(pf)) => {
+                if self.channels.contains_key(&channel) {
+                    log::warn!("Tunnel channel {} is already open", channel);
+                    return;
+                }
+                let (tx, rx) = mpsc::unbounded_channel();
+                self.channels.insert(channel, tx);
+                tokio::spawn(run_channel(channel, pf, rx, self.tx.clone()));
+            }
+            Some(tunnel_frame::Union::Data(data)) => {
+                if let Some(tx) = self.channels.get(&channel) {
+                    tx.send(data).ok();
+                }
+            }
+            Some(tunnel_frame::Union::Close(_)) => {
+                // Dropping the sender ends the channel.
+                self.channels.remove(&channel);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn make_open_result(channel: u32, code: i32) -> TunnelFrame {
+    let mut frame = TunnelFrame::new();
+    frame.channel = channel;
+    frame.set_open_result(TunnelOpenResult {
+        code,
+        ..Default::default()
+    });
+    frame
+}
+
+pub fn make_data(channel: u32, data: Bytes) -> TunnelFrame {
+    let mut frame = TunnelFrame::new();
+    frame.channel = channel;
+    frame.set_data(data);
+    frame
+}
+
+pub fn make_close(channel: u32) -> TunnelFrame {
+    let mut frame = TunnelFrame::new();
+    frame.channel = channel;
+    frame.set_close(true);
+    frame
+}
+
+fn connect_error_code(err: &std::io::Error) -> i32 {
+    match err.kind() {
+        std::io::ErrorKind::ConnectionRefused => REPLY_CONNECTION_REFUSED,
+        std::io::ErrorKind::AddrNotAvailable => REPLY_HOST_UNREACHABLE,
+        std::io::ErrorKind::PermissionDenied => REPLY_NOT_ALLOWED,
+        _ => REPLY_GENERAL_FAILURE,
+    }
+}
+
+async fn run_channel(
+    channel: u32,
+    pf: PortForward,
+    mut rx: mpsc::UnboundedReceiver<Bytes>,
+    tx: mpsc::UnboundedSender<TunnelFrame>,
+) {
+    let host = if pf.host.is_empty() {
+        "localhost"
+    } else {
+        &pf.host
+    };
+    let addr = format!("{}:{}", host, pf.port);
+    let sock = match timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+        Ok(Ok(sock)) => sock,
+        Ok(Err(err)) => {
+            log::info!("Tunnel channel {} failed to connect {}: {}", channel, addr, err);
+            tx.send(make_open_result(channel, connect_error_code(&err))).ok();
+            return;
+        }
+        Err(_) => {
+            log::info!("Tunnel channel {} timeout to connect {}", channel, addr);
+            tx.send(make_open_result(channel, REPLY_HOST_UNREACHABLE)).ok();
+            return;
+        }
+    };
+    tx.send(make_open_result(channel, REPLY_SUCCEEDED)).ok();
+    let mut forward = Framed::new(sock, BytesCodec::new());
+    loop {
+        tokio::select! {
+            res = forward.next() => {
+                if let Some(Ok(bytes)) = res {
+                    if tx.send(make_data(channel, bytes.freeze())).is_err() {
+                        return;
+                    }
+                } else {
+                    break;
+                }
+            }
+            res = rx.recv() => {
+                if let Some(bytes) = res {
+                    if forward.send(bytes).await.is_err() {
+                        break;
+                    }
+                } else {
+                    // Closed by the client.
+                    return;
+                }
+            }
+        }
+    }
+    tx.send(make_close(channel)).ok();
+}
@@ -36,6 +36,8 @@ struct UserData {
     delay: Option<Delay>,
     response_delayed: bool,
     record: bool,
+    // The fps in percent while the host is overloaded, see `load_guard`.
+    load_throttle: Option<u32>,
 }
 
 pub struct VideoQoS {
@@ -148,6 +150,10 @@ impl VideoQoS {
                     fps = MIN_FPS + 2;
                 }
             }
+            // host load
+            if let Some(percent) = u.load_throttle {
+                fps = std::cmp::max(fps * percent / 100, MIN_FPS);
+            }
             return fps;
         };
         let mut fps = self
@@ -371,6 +377,22 @@ impl VideoQoS {
         }
     }
 
+    pub fn user_load_throttle(&mut self, id: i32, percent: u32) {
+        let load_throttle = if percent < 100 { Some(percent) } else { None };
+        if let Some(user) = self.users.get_mut(&id) {
+            user.load_throttle = load_throttle;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    load_throttle,
+                    ..Default::default()
+                },
+            );
+        }
+        self.refresh(None);
+    }
+
     pub fn user_record(&mut self, id: i32, v: bool) {
         if let Some(user) = self.users.get_mut(&id) {
             user.record = v;
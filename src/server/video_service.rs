@@ -22,7 +22,7 @@ use super::{
     display_service::{check_display_changed, get_display_info},
     service::ServiceTmpl,
     video_qos::VideoQoS,
-    *,
+    viewport, *,
 };
 #[cfg(target_os = "linux")]
 use crate::common::SimpleCallOnReturn;
@@ -395,9 +395,26 @@ fn run(vs: VideoService) -> ResultType<()> {
     log::info!("initial quality: {quality:?}, abr enabled: {abr}");
     let codec_name = Encoder::negotiated_codec();
     let recorder = get_recorder(c.width, c.height, &codec_name);
-    let last_recording = recorder.lock().unwrap().is_some() || video_qos.record();
+    let last_recording = recorder.lock().unwrap().is_some()
+        || video_qos.record()
+        || super::session_recording::is_active();
     drop(video_qos);
-    let encoder_cfg = get_encoder_config(&c, quality, last_recording);
+    // The whole display is recorded.
+    let mut viewport_generation = viewport::generation();
+    let region = if last_recording {
+        None
+    } else {
+        viewport::region(display_idx, &sp.subscriber_ids(), c.width, c.height)
+    };
+    if let Some(region) = region.as_ref() {
+        log::info!(
+            "Capture the region of display {}: {:?}",
+            display_idx,
+            region
+        );
+    }
+    let (width, height) = region.map_or((c.width, c.height), |r| (r.out_width, r.out_height));
+    let encoder_cfg = get_encoder_config(width, height, quality, last_recording);
 
     let mut encoder;
     let use_i444 = Encoder::use_i444(&encoder_cfg);
@@ -426,6 +443,7 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut would_block_count = 0u32;
     let mut yuv = Vec::new();
     let mut mid_data = Vec::new();
+    let mut cropped = viewport::CroppedFrame::default();
 
     while sp.ok() {
         #[cfg(windows)]
@@ -439,7 +457,9 @@ fn run(vs: VideoService) -> ResultType<()> {
             allow_err!(encoder.set_quality(quality));
             video_qos.store_bitrate(encoder.bitrate());
         }
-        let recording = recorder.lock().unwrap().is_some() || video_qos.record();
+        let recording = recorder.lock().unwrap().is_some()
+            || video_qos.record()
+            || super::session_recording::is_active();
         if recording != last_recording {
             bail!("SWITCH");
         }
@@ -459,6 +479,14 @@ fn run(vs: VideoService) -> ResultType<()> {
         if Encoder::use_i444(&encoder_cfg) != use_i444 {
             bail!("SWITCH");
         }
+        if viewport_generation != viewport::generation() {
+            viewport_generation = viewport::generation();
+            if !last_recording
+                && region != viewport::region(display_idx, &sp.subscriber_ids(), c.width, c.height)
+            {
+                bail!("SWITCH");
+            }
+        }
         check_privacy_mode_changed(&sp, c.privacy_mode_id)?;
         #[cfg(windows)]
         {
@@ -483,16 +511,20 @@ fn run(vs: VideoService) -> ResultType<()> {
                 let time = now - start;
                 let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
                 if frame.data().len() != 0 {
+                    let encode_begin = Instant::now();
                     let send_conn_ids = handle_one_frame(
                         display_idx,
                         &sp,
                         frame,
+                        region.as_ref(),
+                        &mut cropped,
                         &mut yuv,
                         &mut mid_data,
                         ms,
                         &mut encoder,
                         recorder.clone(),
                     )?;
+                    super::load_guard::record_busy(display_idx, encode_begin.elapsed());
                     frame_controller.set_send(now, send_conn_ids);
                 }
                 #[cfg(windows)]
@@ -576,23 +608,28 @@ fn run(vs: VideoService) -> ResultType<()> {
     Ok(())
 }
 
-fn get_encoder_config(c: &CapturerInfo, quality: Quality, recording: bool) -> EncoderCfg {
+fn get_encoder_config(
+    width: usize,
+    height: usize,
+    quality: Quality,
+    recording: bool,
+) -> EncoderCfg {
     // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
     let keyframe_interval = if recording { Some(240) } else { None };
     match Encoder::negotiated_codec() {
         scrap::CodecName::H264(name) | scrap::CodecName::H265(name) => {
             EncoderCfg::HW(HwEncoderConfig {
                 name,
-                width: c.width,
-                height: c.height,
+                width,
+                height,
                 quality,
                 keyframe_interval,
             })
         }
         name @ (scrap::CodecName::VP8 | scrap::CodecName::VP9) => {
             EncoderCfg::VPX(VpxEncoderConfig {
-                width: c.width as _,
-                height: c.height as _,
+                width: width as _,
+                height: height as _,
                 quality,
                 codec: if name == scrap::CodecName::VP8 {
                     VpxVideoCodecId::VP8
@@ -603,8 +640,8 @@ fn get_encoder_config(c: &CapturerInfo, quality: Quality, recording: bool) -> En
             })
         }
         scrap::CodecName::AV1 => EncoderCfg::AOM(AomEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             keyframe_interval,
         }),
@@ -630,6 +667,7 @@ fn get_recorder(
             server: true,
             id: Config::get_id(),
             default_dir: crate::ui_interface::default_video_save_directory(),
+            dir: "".to_owned(),
             filename: "".to_owned(),
             width,
             height,
@@ -664,12 +702,15 @@ fn handle_one_frame(
     display: usize,
     sp: &GenericService,
     frame: Frame,
+    region: Option<&viewport::Region>,
+    cropped: &mut viewport::CroppedFrame,
     yuv: &mut Vec<u8>,
     mid_data: &mut Vec<u8>,
     ms: i64,
     encoder: &mut Encoder,
     recorder: Arc<Mutex<Option<Recorder>>>,
 ) -> ResultType<HashSet<i32>> {
+    let capture_time = super::frame_timestamps::capture_time();
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
         if sps.has_subscribes() {
@@ -679,9 +720,23 @@ fn handle_one_frame(
     })?;
 
     let mut send_conn_ids: HashSet<i32> = Default::default();
-    convert_to_yuv(&frame, encoder.yuvfmt(), yuv, mid_data)?;
+    match region {
+        Some(region) => {
+            if !cropped.crop(display, &frame, region) {
+                bail!("SWITCH");
+            }
+            convert_to_yuv(cropped, encoder.yuvfmt(), yuv, mid_data)?;
+        }
+        None => convert_to_yuv(&frame, encoder.yuvfmt(), yuv, mid_data)?,
+    }
     if let Ok(mut vf) = encoder.encode_to_message(yuv, ms) {
         vf.display = display as _;
+        if let Some(region) = region {
+            vf.viewport = hbb_common::protobuf::MessageField::some(region.to_viewport(display));
+        }
+        if let Some(capture_time) = capture_time {
+            vf.capture_time = capture_time;
+        }
         let mut msg = Message::new();
         msg.set_video_frame(vf);
         recorder
@@ -689,7 +744,23 @@ fn handle_one_frame(
             .unwrap()
             .as_mut()
             .map(|r| r.write_message(&msg));
-        send_conn_ids = sp.send_video_frame(msg);
+        let msg = Arc::new(msg);
+        send_conn_ids = sp.send_video_frame_shared(msg.clone());
+        // The cropped frames are never recorded, the capture is switched to the whole display.
+        if region.is_none() {
+            super::session_recording::on_video_frame(
+                display,
+                &send_conn_ids,
+                frame.width(),
+                frame.height(),
+                &msg,
+            );
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        match region {
+            Some(_) => super::cm_watch::on_frame(display, &send_conn_ids, cropped),
+            None => super::cm_watch::on_frame(display, &send_conn_ids, &frame),
+        }
     }
     Ok(send_conn_ids)
 }
@@ -775,6 +846,8 @@ pub fn make_display_changed_msg(
         })
         .into(),
         original_resolution: display.original_resolution,
+        refresh_rate: display.refresh_rate,
+        scale: display.scale,
         ..Default::default()
     });
     let mut msg_out = Message::new();
@@ -0,0 +1,355 @@
+//! Capture the region of a display viewed by the clients, instead of the whole display, so a
+//! client zoomed in on a 4K display doesn't pay for the full frames.
+//!
+//! Each connection requests the region it views of a display by [`Viewport`], the whole display
+//! if the size is 0. The frames of a display are shared by the connections subscribed, so the
+//! region captured is the union of their requests, and the whole display if any of them views the
+//! whole display, see [`merge`]. The video service is restarted to resize the encoder when the
+//! region is changed, and each frame carries the region captured.
+//!
+//! The region is only captured from the frames in BGRA or RGBA, the display is captured in whole
+//! from then on otherwise.
+
+use hbb_common::{lazy_static, log, message_proto::Viewport};
+use scrap::{Pixfmt, TraitFrame};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+// The smallest scale of the frames to the region.
+const MIN_SCALE: f64 = 0.25;
+// The region is not worth cropping if covering more of the display.
+const MAX_AREA_PERCENT: usize = 90;
+// The smallest side of the region in pixels.
+const MIN_SIDE: usize = 64;
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    // display -> conn id -> the region requested.
+    static ref REQUESTS: Mutex<HashMap<usize, HashMap<i32, Viewport>>> = Default::default();
+    // The displays whose frames can't be cropped.
+    static ref UNSUPPORTED: Mutex<HashSet<usize>> = Default::default();
+}
+
+/// The region cropped from the frames, and the size of the frames encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub out_width: usize,
+    pub out_height: usize,
+}
+
+impl Region {
+    pub fn to_viewport(&self, display: usize) -> Viewport {
+        Viewport {
+            display: display as _,
+            x: self.x as _,
+            y: self.y as _,
+            width: self.width as _,
+            height: self.height as _,
+            scale: self.out_width as f64 / self.width as f64,
+            ..Default::default()
+        }
+    }
+}
+
+/// Changed on each request, so the video services only merge the requests again if changed.
+#[inline]
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+pub fn set(conn_id: i32, viewport: &Viewport) {
+    let display = viewport.display.max(0) as usize;
+    let mut requests = REQUESTS.lock().unwrap();
+    let display_requests = requests.entry(display).or_default();
+    if viewport.width <= 0 || viewport.height <= 0 {
+        display_requests.remove(&conn_id);
+    } else {
+        display_requests.insert(conn_id, viewport.clone());
+    }
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn remove(conn_id: i32) {
+    let mut requests = REQUESTS.lock().unwrap();
+    for display_requests in requests.values_mut() {
+        display_requests.remove(&conn_id);
+    }
+    requests.retain(|_, r| !r.is_empty());
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// The region to capture of the display for the connections subscribed, `None` if the whole
+/// display.
+pub fn region(display: usize, conn_ids: &[i32], width: usize, height: usize) -> Option<Region> {
+    if conn_ids.is_empty() || UNSUPPORTED.lock().unwrap().contains(&display) {
+        return None;
+    }
+    let requests = REQUESTS.lock().unwrap();
+    let display_requests = requests.get(&display)?;
+    let viewports = conn_ids
+        .iter()
+        .map(|id| display_requests.get(id))
+        .collect::<Option<Vec<_>>>()?;
+    merge(&viewports, width, height)
+}
+
+/// The union of the regions, clamped to the display and aligned to even pixels for the yuv.
+pub fn merge(viewports: &[&Viewport], width: usize, height: usize) -> Option<Region> {
+    if viewports.is_empty() || width < MIN_SIDE || height < MIN_SIDE {
+        return None;
+    }
+    let (mut left, mut top, mut right, mut bottom) = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+    let mut scale = 0f64;
+    for v in viewports {
+        if v.width <= 0 || v.height <= 0 {
+            return None;
+        }
+        left = left.min(v.x as i64);
+        top = top.min(v.y as i64);
+        right = right.max(v.x as i64 + v.width as i64);
+        bottom = bottom.max(v.y as i64 + v.height as i64);
+        scale = scale.max(v.scale);
+    }
+    let clamp = |v: i64, max: usize| v.clamp(0, max as i64) as usize;
+    let even_down = |v: usize| v & !1;
+    let even_up = |v: usize, max: usize| ((v + 1) & !1).min(max & !1);
+    let x = even_down(clamp(left, width));
+    let y = even_down(clamp(top, height));
+    let right = even_up(clamp(right, width), width);
+    let bottom = even_up(clamp(bottom, height), height);
+    if right < x + MIN_SIDE || bottom < y + MIN_SIDE {
+        return None;
+    }
+    let (w, h) = (right - x, bottom - y);
+    let scale = if scale > 0. {
+        scale.clamp(MIN_SCALE, 1.)
+    } else {
+        1.
+    };
+    if w * h * 100 > width * height * MAX_AREA_PERCENT && scale >= 1. {
+        return None;
+    }
+    let out_width = (((w as f64 * scale) as usize).max(MIN_SIDE / 2) & !1).min(w);
+    let out_height = (((h as f64 * scale) as usize).max(MIN_SIDE / 2) & !1).min(h);
+    Some(Region {
+        x,
+        y,
+        width: w,
+        height: h,
+        out_width,
+        out_height,
+    })
+}
+
+/// The frame cropped and scaled, the buffer is reused.
+#[derive(Default)]
+pub struct CroppedFrame {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    pixfmt: Option<Pixfmt>,
+}
+
+impl TraitFrame for CroppedFrame {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn stride(&self) -> Vec<usize> {
+        vec![self.width * 4]
+    }
+
+    fn pixfmt(&self) -> Pixfmt {
+        self.pixfmt.unwrap_or(Pixfmt::BGRA)
+    }
+}
+
+impl CroppedFrame {
+    /// Crop the region of `frame`, scaled by the nearest pixels. Returns false if the frame can't
+    /// be cropped, the display is captured in whole from then on if the format is not supported.
+    pub fn crop(&mut self, display: usize, frame: &impl TraitFrame, region: &Region) -> bool {
+        let pixfmt = frame.pixfmt();
+        if !matches!(pixfmt, Pixfmt::BGRA | Pixfmt::RGBA) {
+            log::warn!(
+                "The frames of display {} in {:?} can't be cropped",
+                display,
+                pixfmt
+            );
+            UNSUPPORTED.lock().unwrap().insert(display);
+            GENERATION.fetch_add(1, Ordering::SeqCst);
+            return false;
+        }
+        let src_stride = frame.stride().first().cloned().unwrap_or_default();
+        let src = frame.data();
+        // The display is resized.
+        if region.x + region.width > frame.width()
+            || region.y + region.height > frame.height()
+            || src.len() < src_stride * frame.height()
+        {
+            return false;
+        }
+        let (w, h) = (region.out_width, region.out_height);
+        self.pixfmt = Some(pixfmt);
+        self.width = w;
+        self.height = h;
+        self.data.resize(w * h * 4, 0);
+        let xs: Vec<_> = (0..w)
+            .map(|x| (region.x + x * region.width / w) * 4)
+            .collect();
+        for y in 0..h {
+            let src_row = &src[(region.y + y * region.height / h) * src_stride..];
+            let dst_row = &mut self.data[y * w * 4..(y + 1) * w * 4];
+            if w == region.width {
+                dst_row.copy_from_slice(&src_row[region.x * 4..(region.x + w) * 4]);
+                continue;
+            }
+            for (x, &sx) in xs.iter().enumerate() {
+                dst_row[x * 4..x * 4 + 4].copy_from_slice(&src_row[sx..sx + 4]);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(x: i32, y: i32, width: i32, height: i32, scale: f64) -> Viewport {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+            scale,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = viewport(101, 51, 400, 300, 1.);
+        let r = merge(&[&a], 3840, 2160).unwrap();
+        assert_eq!((r.x, r.y, r.width, r.height), (100, 50, 402, 302));
+        assert_eq!((r.out_width, r.out_height), (402, 302));
+
+        // The union, with the largest scale.
+        let b = viewport(2000, 1000, 800, 600, 0.5);
+        let r = merge(&[&a, &b], 3840, 2160).unwrap();
+        assert_eq!((r.x, r.y, r.width, r.height), (100, 50, 2700, 1550));
+        assert_eq!((r.out_width, r.out_height), (2700, 1550));
+
+        // Clamped to the display.
+        let c = viewport(-100, 2000, 400, 400, 0.5);
+        let r = merge(&[&c], 3840, 2160).unwrap();
+        assert_eq!((r.x, r.y, r.width, r.height), (0, 2000, 300, 160));
+        assert_eq!((r.out_width, r.out_height), (150, 80));
+
+        // The whole display.
+        assert_eq!(merge(&[&a, &viewport(0, 0, 0, 0, 1.)], 3840, 2160), None);
+        assert_eq!(merge(&[&viewport(0, 0, 3800, 2100, 1.)], 3840, 2160), None);
+        assert_eq!(merge(&[&viewport(4000, 0, 100, 100, 1.)], 3840, 2160), None);
+        assert_eq!(merge(&[], 3840, 2160), None);
+        // Zoomed out, the whole display scaled.
+        let r = merge(&[&viewport(0, 0, 3840, 2160, 0.5)], 3840, 2160).unwrap();
+        assert_eq!((r.width, r.out_width, r.out_height), (3840, 1920, 1080));
+    }
+
+    #[test]
+    fn test_region() {
+        set(9001, &viewport(0, 0, 640, 480, 1.));
+        assert_eq!(region(7, &[9001], 3840, 2160), None);
+        let mut v = viewport(0, 0, 640, 480, 1.);
+        v.display = 7;
+        let generation = generation();
+        set(9001, &v);
+        assert!(generation() > generation);
+        assert!(region(7, &[9001], 3840, 2160).is_some());
+        // Another connection views the whole display.
+        assert_eq!(region(7, &[9001, 9002], 3840, 2160), None);
+        remove(9001);
+        assert_eq!(region(7, &[9001], 3840, 2160), None);
+    }
+
+    struct TestFrame {
+        data: Vec<u8>,
+        width: usize,
+        height: usize,
+        pixfmt: Pixfmt,
+    }
+
+    impl TraitFrame for TestFrame {
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+
+        fn width(&self) -> usize {
+            self.width
+        }
+
+        fn height(&self) -> usize {
+            self.height
+        }
+
+        fn stride(&self) -> Vec<usize> {
+            vec![self.width * 4]
+        }
+
+        fn pixfmt(&self) -> Pixfmt {
+            self.pixfmt
+        }
+    }
+
+    #[test]
+    fn test_crop() {
+        // The value of each pixel is its x and y.
+        let (width, height) = (256, 128);
+        let mut data = vec![0u8; width * height * 4];
+        for y in 0..height {
+            for x in 0..width {
+                data[(y * width + x) * 4] = x as u8;
+                data[(y * width + x) * 4 + 1] = y as u8;
+            }
+        }
+        let mut frame = TestFrame {
+            data,
+            width,
+            height,
+            pixfmt: Pixfmt::BGRA,
+        };
+        let mut cropped = CroppedFrame::default();
+        let region = merge(&[&viewport(10, 20, 128, 64, 1.)], width, height).unwrap();
+        assert!(cropped.crop(8, &frame, &region));
+        assert_eq!((cropped.width(), cropped.height()), (128, 64));
+        assert_eq!(&cropped.data()[..2], &[10, 20]);
+        let last = cropped.data().len() - 4;
+        assert_eq!(&cropped.data()[last..last + 2], &[137, 83]);
+
+        let region = merge(&[&viewport(0, 0, 128, 128, 0.5)], width, height).unwrap();
+        assert!(cropped.crop(8, &frame, &region));
+        assert_eq!((cropped.width(), cropped.height()), (64, 64));
+        assert_eq!(&cropped.data()[4..6], &[2, 0]);
+
+        frame.pixfmt = Pixfmt::I420;
+        assert!(!cropped.crop(8, &frame, &region));
+        assert!(UNSUPPORTED.lock().unwrap().contains(&8));
+    }
+}
@@ -0,0 +1,252 @@
+//! The journal of the reversible states engaged by the host, e.g. privacy mode.
+//!
+//! A record is written before a state is engaged, and removed when the state is released.
+//! The records left by a killed process are restored by [`recover`] on the next start of the
+//! server, the ones failed to be restored are kept for the start after. The ui in the same process
+//! is told by the "recovered_after_crash" event.
+
+use hbb_common::{config::Config, log, ResultType};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const JOURNAL_FILE: &str = "state_journal.json";
+
+lazy_static::lazy_static! {
+    // The journal is read and written by the threads of the connections.
+    static ref JOURNAL_LOCK: Mutex<()> = Default::default();
+    // Restored by the last `recover`, for the ui which is started later.
+    static ref RECOVERED: Mutex<Vec<StateRecord>> = Default::default();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", content = "c")]
+pub enum StateRecord {
+    /// The key of the privacy mode implementation.
+    PrivacyMode(String),
+    /// The id of the connection blocking the input, the input is blocked by any of them.
+    BlockInput(i32),
+    /// The index of the virtual display.
+    VirtualDisplay(u32),
+}
+
+#[inline]
+fn journal_path() -> PathBuf {
+    Config::path(JOURNAL_FILE)
+}
+
+// The records unknown to this version are skipped, not the others.
+fn load(path: &Path) -> Vec<StateRecord> {
+    let records: Vec<serde_json::Value> = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    records
+        .into_iter()
+        .filter_map(|r| match serde_json::from_value(r.clone()) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                log::warn!("Skip the unknown state record {}: {}", r, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// Written to a temporary file and renamed, a crash leaves either the old journal or the new one.
+fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)
+}
+
+fn store(path: &Path, records: &[StateRecord]) {
+    let res = if records.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    } else {
+        write_atomically(
+            path,
+            serde_json::to_string(records)
+                .unwrap_or_default()
+                .as_bytes(),
+        )
+    };
+    if let Err(e) = res {
+        log::error!("Failed to store the state journal {:?}: {}", path, e);
+    }
+}
+
+fn engage_at(path: &Path, record: StateRecord) {
+    let _lock = JOURNAL_LOCK.lock().unwrap();
+    let mut records = load(path);
+    if !records.contains(&record) {
+        records.push(record);
+        store(path, &records);
+    }
+}
+
+fn release_at(path: &Path, record: &StateRecord) {
+    let _lock = JOURNAL_LOCK.lock().unwrap();
+    let mut records = load(path);
+    let len = records.len();
+    records.retain(|r| r != record);
+    if records.len() != len {
+        store(path, &records);
+    }
+}
+
+// Returns the restored records, the failed ones are kept to be retried on the next start.
+fn recover_at(path: &Path, restore: impl Fn(&StateRecord) -> ResultType<()>) -> Vec<StateRecord> {
+    let _lock = JOURNAL_LOCK.lock().unwrap();
+    let (restored, failed): (Vec<_>, Vec<_>) =
+        load(path)
+            .into_iter()
+            .partition(|record| match restore(record) {
+                Ok(()) => {
+                    log::info!("Restored {:?} left by the last run", record);
+                    true
+                }
+                Err(e) => {
+                    log::error!(
+                        "Failed to restore {:?}, retry on the next start: {}",
+                        record,
+                        e
+                    );
+                    false
+                }
+            });
+    store(path, &failed);
+    restored
+}
+
+/// Record the state before engaging it.
+#[inline]
+pub fn engage(record: StateRecord) {
+    engage_at(&journal_path(), record);
+}
+
+/// Remove the record after the state is released.
+#[inline]
+pub fn release(record: &StateRecord) {
+    release_at(&journal_path(), record);
+}
+
+fn restore(record: &StateRecord) -> ResultType<()> {
+    match record {
+        StateRecord::PrivacyMode(impl_key) => {
+            crate::privacy_mode::switch(impl_key);
+            crate::privacy_mode::clear();
+        }
+        StateRecord::BlockInput(_) => {
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            {
+                let (ok, msg) = crate::platform::block_input(false);
+                if !ok {
+                    hbb_common::bail!(msg);
+                }
+            }
+        }
+        StateRecord::VirtualDisplay(_index) => {
+            #[cfg(all(windows, feature = "virtual_display_driver"))]
+            crate::virtual_display_manager::plug_out_leftover(*_index)?;
+        }
+    }
+    Ok(())
+}
+
+/// Restore the states left by the last run, which is not stopped in order.
+pub fn recover() {
+    let recovered = recover_at(&journal_path(), restore);
+    if recovered.is_empty() {
+        return;
+    }
+    *RECOVERED.lock().unwrap() = recovered;
+    #[cfg(feature = "flutter")]
+    if let Some(event) = recovered_event() {
+        let _ = crate::flutter::push_global_event(crate::flutter::APP_TYPE_MAIN, event);
+    }
+}
+
+/// The "recovered_after_crash" event, `None` if nothing is recovered.
+pub fn recovered_event() -> Option<String> {
+    let recovered = RECOVERED.lock().unwrap();
+    if recovered.is_empty() {
+        return None;
+    }
+    let event = serde_json::json!({
+        "name": "recovered_after_crash",
+        "states": serde_json::to_string(&*recovered).unwrap_or_default(),
+    });
+    Some(event.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover() {
+        let path = std::env::temp_dir().join(format!("state_journal_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let privacy_mode = StateRecord::PrivacyMode("privacy_mode_impl_mag".to_owned());
+        engage_at(&path, privacy_mode.clone());
+        engage_at(&path, StateRecord::BlockInput(1));
+        engage_at(&path, StateRecord::BlockInput(2));
+        engage_at(&path, StateRecord::VirtualDisplay(1));
+        engage_at(&path, StateRecord::VirtualDisplay(1));
+        // Still blocked by the other connection.
+        release_at(&path, &StateRecord::BlockInput(1));
+
+        // The failed ones are kept for the next start.
+        let restored = Mutex::new(vec![]);
+        let recovered = recover_at(&path, |r| {
+            restored.lock().unwrap().push(r.clone());
+            if r == &StateRecord::BlockInput(2) {
+                hbb_common::bail!("failed")
+            }
+            Ok(())
+        });
+        let expected = vec![
+            privacy_mode.clone(),
+            StateRecord::BlockInput(2),
+            StateRecord::VirtualDisplay(1),
+        ];
+        assert_eq!(*restored.lock().unwrap(), expected);
+        assert_eq!(
+            recovered,
+            vec![privacy_mode.clone(), StateRecord::VirtualDisplay(1)]
+        );
+        assert_eq!(load(&path), vec![StateRecord::BlockInput(2)]);
+        assert_eq!(
+            recover_at(&path, |_| Ok(())),
+            vec![StateRecord::BlockInput(2)]
+        );
+        assert!(!path.exists());
+
+        // The unknown records are skipped.
+        std::fs::write(
+            &path,
+            r#"[{"t":"BlockInput"},{"t":"VirtualDisplay","c":2}]"#,
+        )
+        .unwrap();
+        assert_eq!(load(&path), vec![StateRecord::VirtualDisplay(2)]);
+        std::fs::remove_file(&path).ok();
+
+        // The orderly runs leave nothing.
+        engage_at(&path, privacy_mode.clone());
+        release_at(&path, &privacy_mode);
+        assert!(!path.exists());
+        assert!(recover_at(&path, |_| unreachable!()).is_empty());
+    }
+}
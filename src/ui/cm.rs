@@ -1,6 +1,9 @@
 #[cfg(target_os = "linux")]
 use crate::ipc::start_pa;
-use crate::ui_cm_interface::{start_ipc, ConnectionManager, InvokeUiCM};
+use crate::ui_cm_interface::{
+    notify::{NotifyCategory, NotifyMode},
+    start_ipc, Client, ConnectionManager, InvokeUiCM,
+};
 
 use hbb_common::{allow_err, log};
 use sciter::{make_args, Element, Value, HELEMENT};
@@ -64,7 +67,37 @@ impl InvokeUiCM for SciterHandler {
         );
     }
 
+    fn update_view_only_state(&self, _client: &crate::ui_cm_interface::Client) {}
+
+    fn update_audio_only_state(&self, _client: &crate::ui_cm_interface::Client) {
+        // TODO
+    }
+
+    fn update_session_recording_state(&self, _client: &crate::ui_cm_interface::Client) {
+        // TODO
+    }
+
+    fn update_permission_state(&self, _client: &crate::ui_cm_interface::Client) {
+        // TODO
+    }
+
     fn file_transfer_log(&self, _action: &str, _log: &str) {}
+
+    fn action_log(&self, _id: i32, _action: &str, _log: &str) {}
+
+    fn notify(&self, _category: NotifyCategory, _mode: NotifyMode, _client: &Client) {
+        // TODO
+    }
+
+    fn update_permission_grant(&self, id: i32, name: &str, secs: u64) {
+        self.call("updatePermissionGrant", &make_args!(id, name.to_owned(), secs as i32));
+    }
+
+    // The sciter cm only replies by `send_chat`.
+    fn chat_delivery(&self, _id: i32, _seq: u64, _error: &str) {}
+
+    // The sciter cm never watches.
+    fn watch_frame(&self, _id: i32, _display: i32, _width: usize, _height: usize, _data: &str) {}
 }
 
 impl SciterHandler {
@@ -114,6 +147,18 @@ impl SciterConnectionManager {
         crate::ui_cm_interface::switch_permission(id, name, enabled);
     }
 
+    fn grant_permission(&self, id: i32, name: String, secs: i32) {
+        crate::ui_cm_interface::grant_permission(id, name, secs.max(0) as _);
+    }
+
+    fn extend_permission_grant(&self, id: i32, name: String, secs: i32) {
+        crate::ui_cm_interface::extend_permission_grant(id, name, secs.max(0) as _);
+    }
+
+    fn cancel_permission_grant(&self, id: i32, name: String) {
+        crate::ui_cm_interface::cancel_permission_grant(id, name);
+    }
+
     fn close(&self, id: i32) {
         crate::ui_cm_interface::close(id);
     }
@@ -166,6 +211,9 @@ impl sciter::EventHandler for SciterConnectionManager {
         fn quit();
         fn authorize(i32);
         fn switch_permission(i32, String, bool);
+        fn grant_permission(i32, String, i32);
+        fn extend_permission_grant(i32, String, i32);
+        fn cancel_permission_grant(i32, String);
         fn send_msg(i32, String);
         fn can_elevate();
         fn elevate_portable(i32);
@@ -20,7 +20,7 @@ use hbb_common::{
 
 use crate::{
     client::*,
-    ui_session_interface::{InvokeUiSession, Session},
+    ui_session_interface::{HookAction, InvokeUiSession, JobMeta, Session},
 };
 
 type Video = AssetPtr<video_destination>;
@@ -113,6 +113,12 @@ impl InvokeUiSession for SciterHandler {
         self.call2("setPermission", &make_args!(name, value));
     }
 
+    fn set_permissions(&self, permissions: &[(&str, bool)]) {
+        for (name, value) in permissions {
+            self.set_permission(name, *value);
+        }
+    }
+
     fn close_success(&self) {
         self.call2("closeSuccess", &make_args!());
     }
@@ -149,8 +155,23 @@ impl InvokeUiSession for SciterHandler {
         self.call("setConnectionType", &make_args!(is_secured, direct));
     }
 
+    fn set_relay_server(&self, _relay: &str) {}
+
+    fn relay_fallback(&self, _preferred: &str, _err: &str) {}
+
     fn set_fingerprint(&self, _fingerprint: String) {}
 
+    fn fingerprint_mismatch(&self, _old: &str, _new: &str) {
+        // The sciter version can only abort the session.
+        self.msgbox(
+            "error",
+            "Fingerprint mismatch",
+            "fingerprint_mismatch_tip",
+            "",
+            false,
+        );
+    }
+
     fn job_error(&self, id: i32, err: String, file_num: i32) {
         self.call("jobError", &make_args!(id, err, file_num));
     }
@@ -224,6 +245,20 @@ impl InvokeUiSession for SciterHandler {
         );
     }
 
+    fn transfer_summary(&self, _summary: &crate::client::job_progress::TransferSummary) {}
+
+    fn job_renamed_files(&self, _id: i32, _renamed: &[hbb_common::fs::file_name::Renamed]) {}
+
+    fn job_streams(&self, _id: i32, _streams: u32) {}
+
+    fn sync_state(&self, _id: i32, _state: &crate::client::file_sync::SyncState) {}
+
+    fn file_op_progress(&self, _id: i32, _removed: u64, _total: u64, _is_remote: bool) {}
+
+    fn file_op_done(&self, _id: i32, _is_remote: bool) {}
+
+    fn file_op_error(&self, _id: i32, _err: String, _is_remote: bool) {}
+
     fn adapt_size(&self) {
         self.call("adaptSize", &make_args!());
     }
@@ -277,6 +312,23 @@ impl InvokeUiSession for SciterHandler {
         );
     }
 
+    // Translated here, the sciter ui doesn't substitute the params.
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        params: &[(&str, String)],
+        link: &str,
+        retry: bool,
+    ) {
+        let mut text = crate::client::translate(text_key.to_owned());
+        for (k, v) in params {
+            text = text.replace(&format!("{{{}}}", k), v);
+        }
+        self.msgbox(msgtype, title, &text, link, retry);
+    }
+
     fn cancel_msgbox(&self, tag: &str) {
         self.call("cancel_msgbox", &make_args!(tag));
     }
@@ -293,10 +345,60 @@ impl InvokeUiSession for SciterHandler {
         self.call("updateBlockInputState", &make_args!(on));
     }
 
-    fn switch_back(&self, _id: &str) {}
+    fn switch_back_state(&self, _peer_id: &str, _state: &str) {}
 
     fn portable_service_running(&self, _running: bool) {}
 
+    fn host_display_waking(&self, _waking: bool) {}
+
+    fn host_display_state(&self, _state: &str) {}
+
+    fn view_only_blocked(&self) {}
+
+    fn hotkey_blocked(&self, _rule: &str, _keys: &str) {}
+
+    fn on_video_congestion(&self, _congested: bool) -> Option<Vec<i32>> {
+        None
+    }
+
+    fn update_view_only(&self, _view_only: bool) {}
+
+    fn network_probe_result(&self, _result: &str) {}
+
+    fn print_job_progress(&self, _id: u32, _name: &str, _received: u64, _total: u64) {}
+
+    fn print_job_received(&self, _id: u32, _name: &str, _path: &str) {}
+
+    fn print_job_failed(&self, _id: u32, _name: &str, _err: &str) {}
+
+    fn staging_evicted(&self, _files: &[crate::client::staging::StagedFile]) {}
+
+    fn remote_sysinfo(&self, _sysinfo: &str, _error: &str) {}
+
+    fn remote_volume_changed(&self, _state: &str, _percent: u32, _muted: bool) {}
+
+    fn update_audio_only(&self, _audio_only: bool) {}
+
+    fn remote_restarting(&self, _state: &str, _attempt: usize) {}
+
+    fn update_peer_capabilities(&self, _capabilities: &crate::common::PeerCapabilities) {}
+
+    fn codec_negotiated(&self, _codec: &str, _reason: &str) {}
+
+    fn decoder_info(&self, _display: usize, _info: &crate::client::decoder_select::DecoderInfo) {}
+
+    fn color_mode_fallback(&self, _fallback: &crate::client::color_mode::Fallback) {}
+
+    fn annotation_ack(&self, _id: u32, _error: &str) {}
+
+    fn wol_result(&self, _mac: &str, _error: &str, _sent: u32) {}
+
+    fn input_policy(&self, _level: &str, _blocked: &[String], _dropped: u64) {}
+
+    fn host_throttled(&self, _fps_percent: u32, _cpu: u32, _encode: u32) {}
+
+    fn frame_viewport(&self, _display: usize, _viewport: Option<&Viewport>) {}
+
     fn on_voice_call_started(&self) {
         self.call("onVoiceCallStart", &make_args!());
     }
@@ -319,6 +421,29 @@ impl InvokeUiSession for SciterHandler {
     }
 
     fn next_rgba(&self, _display: usize) {}
+
+    fn update_socks_stats(&self, _port: i32, _stats: &str) {
+        // Ignore for sciter version.
+    }
+
+    fn update_port_forward_stats(&self, _stats: &str) {
+        // Ignore for sciter version.
+    }
+
+    fn hook_key_event(&self, _evt: &KeyEvent) -> HookAction {
+        // Ignore for sciter version.
+        HookAction::Continue
+    }
+
+    fn hook_clipboard(&self, _content: &mut String) -> HookAction {
+        // Ignore for sciter version.
+        HookAction::Continue
+    }
+
+    fn hook_file_job_start(&self, _job: &JobMeta) -> HookAction {
+        // Ignore for sciter version.
+        HookAction::Continue
+    }
 }
 
 pub struct SciterSession(Session<SciterHandler>);
@@ -417,6 +542,7 @@ impl sciter::EventHandler for SciterSession {
         fn is_port_forward();
         fn is_rdp();
         fn login(String, String, String, bool);
+        fn send2fa(String);
         fn new_rdp();
         fn send_mouse(i32, i32, i32, bool, bool, bool, bool);
         fn enter(String);
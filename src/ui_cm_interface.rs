@@ -6,9 +6,10 @@ use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
     sync::{
-        atomic::{AtomicI64, Ordering},
-        RwLock,
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Mutex, RwLock,
     },
+    time::{Duration, Instant},
 };
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -35,8 +36,19 @@ use hbb_common::{
 };
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
+#[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::{
+    flutter::connection_manager::{run_cm_hooks, CmHook},
+    ui_session_interface::HookAction,
+};
+use grants::{GrantEvent, Grants};
+use notify::{NotifyCategory, NotifyMode};
 use serde_derive::Serialize;
 
+pub mod grants;
+pub mod notify;
+
 #[derive(Serialize, Clone)]
 pub struct Client {
     pub id: i32,
@@ -53,14 +65,57 @@ pub struct Client {
     pub restart: bool,
     pub recording: bool,
     pub block_input: bool,
+    pub annotation: bool,
+    /// "full", "no-system-keys" or "text-only", restricting the keys if the keyboard is allowed.
+    pub keyboard_level: String,
     pub from_switch: bool,
+    pub password_form: String,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
+    pub view_only: bool,
+    pub view_only_off_requested: bool,
+    pub audio_only: bool,
+    /// The session is recorded by the host, not the `recording` permission of the peer.
+    pub session_recorded: bool,
     #[serde(skip)]
     #[cfg(not(any(target_os = "ios")))]
     tx: UnboundedSender<Data>,
 }
 
+#[cfg(test)]
+impl Client {
+    pub(crate) fn new_for_test(id: i32, peer_id: &str) -> Self {
+        Self {
+            id,
+            authorized: true,
+            disconnected: false,
+            is_file_transfer: false,
+            port_forward: "".to_owned(),
+            name: "".to_owned(),
+            peer_id: peer_id.to_owned(),
+            keyboard: true,
+            clipboard: true,
+            audio: true,
+            file: true,
+            restart: true,
+            recording: true,
+            block_input: true,
+            annotation: true,
+            keyboard_level: "full".to_owned(),
+            from_switch: false,
+            password_form: "".to_owned(),
+            in_voice_call: false,
+            incoming_voice_call: false,
+            view_only: false,
+            view_only_off_requested: false,
+            audio_only: false,
+            session_recorded: false,
+            #[cfg(not(any(target_os = "ios")))]
+            tx: mpsc::unbounded_channel().0,
+        }
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 struct IpcTaskRunner<T: InvokeUiCM> {
     stream: Connection,
@@ -79,6 +134,9 @@ struct IpcTaskRunner<T: InvokeUiCM> {
 lazy_static::lazy_static! {
     static ref CLIENTS: RwLock<HashMap<i32, Client>> = Default::default();
     static ref CLICK_TIME: AtomicI64 = AtomicI64::new(0);
+    static ref GRANTS: Mutex<Grants> = Default::default();
+    static ref GRANTS_TICKER_STARTED: AtomicBool = AtomicBool::new(false);
+    static ref CHAT_SEQ: AtomicU64 = AtomicU64::new(0);
 }
 
 #[derive(Clone)]
@@ -101,7 +159,29 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
 
     fn update_voice_call_state(&self, client: &Client);
 
+    fn update_view_only_state(&self, client: &Client);
+
+    fn update_audio_only_state(&self, client: &Client);
+
+    /// The session is recorded by the host or the recording is stopped, the badge of the client.
+    fn update_session_recording_state(&self, client: &Client);
+
+    fn update_permission_state(&self, client: &Client);
+
     fn file_transfer_log(&self, action: &str, log: &str);
+
+    fn action_log(&self, id: i32, action: &str, log: &str);
+
+    fn notify(&self, category: NotifyCategory, mode: NotifyMode, client: &Client);
+
+    /// The seconds remaining of the permission granted for a while, 0 once expired.
+    fn update_permission_grant(&self, id: i32, name: &str, secs: u64);
+
+    /// The message started by `send_message` is sent to the peer, or failed if `error` is not empty.
+    fn chat_delivery(&self, id: i32, seq: u64, error: &str);
+
+    /// A frame sent to the connection watched by `watch`, the base64 jpeg downscaled.
+    fn watch_frame(&self, id: i32, display: i32, width: usize, height: usize, data: &str);
 }
 
 impl<T: InvokeUiCM> Deref for ConnectionManager<T> {
@@ -134,7 +214,9 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         restart: bool,
         recording: bool,
         block_input: bool,
+        annotation: bool,
         from_switch: bool,
+        password_form: String,
         #[cfg(not(any(target_os = "ios")))] tx: mpsc::UnboundedSender<Data>,
     ) {
         let client = Client {
@@ -152,18 +234,64 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             restart,
             recording,
             block_input,
+            annotation,
+            keyboard_level: "full".to_owned(),
             from_switch,
+            password_form,
             #[cfg(not(any(target_os = "ios")))]
             tx,
             in_voice_call: false,
             incoming_voice_call: false,
+            view_only: false,
+            view_only_off_requested: false,
+            audio_only: false,
+            session_recorded: false,
         };
+        #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some(plugin) = run_cm_hooks(|hook| match hook {
+            CmHook::OnIncomingConnection(cb) => cb(&client),
+            _ => HookAction::Continue,
+        }) {
+            on_cm_hook_blocked(&client, plugin, "connection");
+            return;
+        }
         CLIENTS
             .write()
             .unwrap()
             .retain(|_, c| !(c.disconnected && c.peer_id == client.peer_id));
         CLIENTS.write().unwrap().insert(id, client.clone());
         self.ui_handler.add_connection(&client);
+        if authorized {
+            self.notify(id, NotifyCategory::ConnectionAccepted);
+        }
+    }
+
+    fn on_chat_message(&self, id: i32, text: String) {
+        #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+            if let Some(plugin) = run_cm_hooks(|hook| match hook {
+                CmHook::OnCmChatMessage(cb) => cb(client, &text),
+                _ => HookAction::Continue,
+            }) {
+                on_cm_hook_blocked(client, plugin, "chat");
+                return;
+            }
+        }
+        self.ui_handler.new_message(id, text);
+        self.notify(id, NotifyCategory::ChatMessage);
+    }
+
+    // Notify the local user if enabled by the policy of the category.
+    fn notify(&self, id: i32, category: NotifyCategory) {
+        let mode = notify::mode(category);
+        if mode == NotifyMode::None {
+            return;
+        }
+        if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+            self.ui_handler.notify(category, mode, client);
+        }
     }
 
     #[inline]
@@ -178,6 +306,11 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
     }
 
     fn remove_connection(&self, id: i32, close: bool) {
+        let Some(_client) = CLIENTS.read().unwrap().get(&id).cloned() else {
+            // Not added, e.g. rejected by the cm hooks.
+            return;
+        };
+        GRANTS.lock().unwrap().remove_connection(id);
         if close {
             CLIENTS.write().unwrap().remove(&id);
         } else {
@@ -213,6 +346,15 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         }
 
         self.ui_handler.remove_connection(id, close);
+
+        #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        run_cm_hooks(|hook| {
+            if let CmHook::OnConnectionRemoved(cb) = hook {
+                cb(&_client);
+            }
+            HookAction::Continue
+        });
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -229,6 +371,82 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
         }
     }
 
+    fn view_only_changed(&self, id: i32, view_only: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.view_only = view_only;
+            client.view_only_off_requested = false;
+            self.ui_handler.update_view_only_state(client);
+        }
+    }
+
+    fn keyboard_level_changed(&self, id: i32, level: String) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.keyboard_level = level;
+            self.ui_handler.update_permission_state(client);
+        }
+    }
+
+    fn audio_only_changed(&self, id: i32, audio_only: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.audio_only = audio_only;
+            self.ui_handler.update_audio_only_state(client);
+        }
+    }
+
+    fn session_recording_changed(&self, id: i32, recording: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.session_recorded = recording;
+            self.ui_handler.update_session_recording_state(client);
+        }
+    }
+
+    // The permission switched by the host, mirrored in the client of the cm.
+    fn permission_changed(&self, id: i32, name: &str, enabled: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            let permission = match name {
+                "keyboard" => &mut client.keyboard,
+                "clipboard" => &mut client.clipboard,
+                "audio" => &mut client.audio,
+                "file" => &mut client.file,
+                "restart" => &mut client.restart,
+                "recording" => &mut client.recording,
+                "block_input" => &mut client.block_input,
+                "annotation" => &mut client.annotation,
+                _ => return,
+            };
+            *permission = enabled;
+            self.ui_handler.update_permission_state(client);
+        }
+    }
+
+    // Revert the expired grants the same as revoked by hand.
+    #[cfg(not(any(target_os = "ios")))]
+    fn tick_grants(&self, now: Instant) {
+        let events = GRANTS.lock().unwrap().tick(now);
+        for event in events {
+            match event {
+                GrantEvent::Countdown { id, name, secs } => {
+                    self.ui_handler.update_permission_grant(id, &name, secs);
+                }
+                GrantEvent::Expired { id, name } => {
+                    log::info!("The permission {} granted to connection {} expired", name, id);
+                    switch_permission(id, name.clone(), false);
+                    self.permission_changed(id, &name, false);
+                    self.ui_handler.update_permission_grant(id, &name, 0);
+                }
+            }
+        }
+    }
+
+    fn view_only_off_requested(&self, id: i32) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            if client.view_only {
+                client.view_only_off_requested = true;
+                self.ui_handler.update_view_only_state(client);
+            }
+        }
+    }
+
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn voice_call_incoming(&self, id: i32) {
         if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
@@ -248,6 +466,23 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
     }
 }
 
+// Notify the connection to record the blocked action in the audit, and reject the connection
+// if it's blocked.
+#[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn on_cm_hook_blocked(client: &Client, plugin: String, action: &str) {
+    log::info!(
+        "The {} of connection {} from {} is blocked by plugin {}",
+        action,
+        client.id,
+        client.peer_id,
+        plugin
+    );
+    allow_err!(client
+        .tx
+        .send(Data::CmHookBlocked((plugin, action.to_owned()))));
+}
+
 #[inline]
 #[cfg(not(any(target_os = "ios")))]
 pub fn check_click_time(id: i32) {
@@ -293,14 +528,111 @@ pub fn send_chat(id: i32, text: String) {
     }
 }
 
+/// The local user starts a conversation with the peer. Returns the sequence number of the
+/// message, its delivery is reported by `chat_delivery` once the connection sent it.
+#[cfg(not(any(target_os = "ios")))]
+pub fn send_message(id: i32, text: String) -> hbb_common::ResultType<u64> {
+    let clients = CLIENTS.read().unwrap();
+    let Some(client) = clients.get(&id) else {
+        hbb_common::bail!("No connection {}", id);
+    };
+    if client.disconnected {
+        hbb_common::bail!("The connection is closed");
+    }
+    if client.is_file_transfer || !client.port_forward.is_empty() {
+        hbb_common::bail!("Chat is unavailable in file transfer or port forward connections");
+    }
+    if text.trim().is_empty() {
+        hbb_common::bail!("Empty message");
+    }
+    let seq = CHAT_SEQ.fetch_add(1, Ordering::SeqCst) + 1;
+    client.tx.send(Data::CmChatMessage { seq, text })?;
+    Ok(seq)
+}
+
+/// The local user watches the frames sent to the connection, reported by `watch_frame` till it
+/// stops watching or the connection is closed.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn watch(id: i32, on: bool) -> hbb_common::ResultType<()> {
+    let clients = CLIENTS.read().unwrap();
+    let Some(client) = clients.get(&id) else {
+        hbb_common::bail!("No connection {}", id);
+    };
+    if on && (client.is_file_transfer || !client.port_forward.is_empty()) {
+        hbb_common::bail!("No video in file transfer or port forward connections");
+    }
+    if on && client.disconnected {
+        hbb_common::bail!("The connection is closed");
+    }
+    client.tx.send(Data::CmWatch(on))?;
+    Ok(())
+}
+
+/// Switched by hand, the timer of the permission granted for a while is cancelled.
 #[inline]
 #[cfg(not(any(target_os = "ios")))]
 pub fn switch_permission(id: i32, name: String, enabled: bool) {
     if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        GRANTS.lock().unwrap().cancel(id, &name);
         allow_err!(client.tx.send(Data::SwitchPermission { name, enabled }));
     };
 }
 
+/// Enable the permission for `secs` seconds, see [`grants`]. Granting again restarts the timer.
+#[cfg(not(any(target_os = "ios")))]
+pub fn grant_permission(id: i32, name: String, secs: u64) {
+    if !Grants::is_grantable(&name) || secs == 0 {
+        log::warn!("Invalid grant of the permission {} for {}s", name, secs);
+        return;
+    }
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        allow_err!(client.tx.send(Data::SwitchPermission {
+            name: name.clone(),
+            enabled: true,
+        }));
+        allow_err!(client.tx.send(Data::TimedPermission((name.clone(), true))));
+        GRANTS.lock().unwrap().grant(id, &name, Duration::from_secs(secs), Instant::now());
+    }
+}
+
+#[cfg(not(any(target_os = "ios")))]
+pub fn extend_permission_grant(id: i32, name: String, secs: u64) {
+    GRANTS.lock().unwrap().extend(id, &name, Duration::from_secs(secs));
+}
+
+/// Keep the permission, only the timer is cancelled.
+#[cfg(not(any(target_os = "ios")))]
+pub fn cancel_permission_grant(id: i32, name: String) {
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        if GRANTS.lock().unwrap().cancel(id, &name) {
+            allow_err!(client.tx.send(Data::TimedPermission((name, false))));
+        }
+    }
+}
+
+// The grants are checked every second, for all the connections.
+#[cfg(not(any(target_os = "ios")))]
+fn start_grants_ticker<T: InvokeUiCM>(cm: ConnectionManager<T>) {
+    if GRANTS_TICKER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(1));
+        cm.tick_grants(Instant::now());
+    });
+}
+
+/// Answer the request of the peer to allow the input of a view-only connection.
+#[cfg(not(any(target_os = "ios")))]
+pub fn answer_view_only_off(id: i32, accept: bool) {
+    if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+        if client.view_only_off_requested {
+            client.view_only_off_requested = false;
+            allow_err!(client.tx.send(Data::ViewOnlyOffResponse(accept)));
+        }
+    };
+}
+
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 #[inline]
 pub fn get_clients_state() -> String {
@@ -331,6 +663,7 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
 
         // for tmp use, without real conn id
         let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+        let file_ops = fs::file_op::Ops::default();
 
         #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
         let is_authorized = self.cm.is_authorized(self.conn_id);
@@ -380,9 +713,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                         }
                         Ok(Some(data)) => {
                             match data {
-                                Data::Login{id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, from_switch} => {
+                                Data::Login{id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, file_transfer_enabled: _file_transfer_enabled, restart, recording, block_input, annotation, from_switch, password_form} => {
                                     log::debug!("conn_id: {}", id);
-                                    self.cm.add_connection(id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, from_switch, self.tx.clone());
+                                    self.cm.add_connection(id, is_file_transfer, port_forward, peer_id, name, authorized, keyboard, clipboard, audio, file, restart, recording, block_input, annotation, from_switch, password_form, self.tx.clone());
                                     self.conn_id = id;
                                     #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
                                     {
@@ -408,16 +741,22 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     CLICK_TIME.store(ms, Ordering::SeqCst);
                                 }
                                 Data::ChatMessage { text } => {
-                                    self.cm.new_message(self.conn_id, text);
+                                    self.cm.on_chat_message(self.conn_id, text);
+                                }
+                                Data::ChatDelivery { seq, error } => {
+                                    self.cm.ui_handler.chat_delivery(self.conn_id, seq, &error);
+                                }
+                                Data::CmWatchFrame { display, width, height, data } => {
+                                    self.cm.ui_handler.watch_frame(self.conn_id, display, width, height, &data);
                                 }
                                 Data::FS(mut fs) => {
-                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
+                                    if let ipc::FS::WriteBlock { id, file_num, data: _, compressed, offset, stream } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
-                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
-                                            handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
+                                            fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed, offset, stream};
+                                            handle_fs(fs, &mut write_jobs, &file_ops, &self.tx, Some(&tx_log)).await;
                                         }
                                     } else {
-                                        handle_fs(fs, &mut write_jobs, &self.tx, Some(&tx_log)).await;
+                                        handle_fs(fs, &mut write_jobs, &file_ops, &self.tx, Some(&tx_log)).await;
                                     }
                                     let log = fs::serialize_transfer_jobs(&write_jobs);
                                     self.cm.ui_handler.file_transfer_log("transfer", &log);
@@ -425,6 +764,19 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::FileTransferLog((action, log)) => {
                                     self.cm.ui_handler.file_transfer_log(&action, &log);
                                 }
+                                Data::ActionLog((action, log)) => {
+                                    self.cm.ui_handler.action_log(self.conn_id, &action, &log);
+                                }
+                                Data::CmNotify(category) => {
+                                    if let Some(category) = NotifyCategory::from_name(&category) {
+                                        self.cm.notify(self.conn_id, category);
+                                    }
+                                }
+                                Data::CmNotifyPolicy((option, mode)) => {
+                                    if notify::is_notify_option(&option) {
+                                        LocalConfig::set_option(option, mode);
+                                    }
+                                }
                                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                                 Data::ClipboardFile(_clip) => {
                                     #[cfg(any(target_os = "windows", target_os="linux", target_os = "macos"))]
@@ -476,6 +828,29 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::ViewOnly(view_only) => {
+                                    self.cm.view_only_changed(self.conn_id, view_only);
+                                }
+                                Data::KeyboardLevel(level) => {
+                                    self.cm.keyboard_level_changed(self.conn_id, level);
+                                }
+                                Data::ViewOnlyOffRequest => {
+                                    self.cm.view_only_off_requested(self.conn_id);
+                                }
+                                Data::AudioOnly(audio_only) => {
+                                    self.cm.audio_only_changed(self.conn_id, audio_only);
+                                }
+                                Data::SessionRecording(recording) => {
+                                    self.cm.session_recording_changed(self.conn_id, recording);
+                                }
+                                // Switched by the options of the host.
+                                Data::SwitchPermission { name, enabled } => {
+                                    #[cfg(any(target_os="linux", target_os="windows", target_os = "macos"))]
+                                    if name == "file" {
+                                        self.file_transfer_enabled = enabled;
+                                    }
+                                    self.cm.permission_changed(self.conn_id, &name, enabled);
+                                }
                                 _ => {
 
                                 }
@@ -532,6 +907,7 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                 }
             }
         }
+        file_ops.cancel_all();
     }
 
     async fn ipc_task(stream: Connection, cm: ConnectionManager<T>) {
@@ -574,6 +950,7 @@ pub async fn start_ipc<T: InvokeUiCM>(cm: ConnectionManager<T>) {
         ),
     ))]
     ContextSend::enable(Config::get_option("enable-file-transfer").is_empty());
+    start_grants_ticker(cm.clone());
 
     match ipc::new_listener("_cm").await {
         Ok(mut incoming) => {
@@ -608,6 +985,8 @@ pub async fn start_listen<T: InvokeUiCM>(
 ) {
     let mut current_id = 0;
     let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+    let file_ops = fs::file_op::Ops::default();
+    start_grants_ticker(cm.clone());
     loop {
         match rx.recv().await {
             Some(Data::Login {
@@ -624,7 +1003,9 @@ pub async fn start_listen<T: InvokeUiCM>(
                 restart,
                 recording,
                 block_input,
+                annotation,
                 from_switch,
+                password_form,
                 ..
             }) => {
                 current_id = id;
@@ -642,15 +1023,43 @@ pub async fn start_listen<T: InvokeUiCM>(
                     restart,
                     recording,
                     block_input,
+                    annotation,
                     from_switch,
+                    password_form,
                     tx.clone(),
                 );
             }
             Some(Data::ChatMessage { text }) => {
-                cm.new_message(current_id, text);
+                cm.on_chat_message(current_id, text);
+            }
+            Some(Data::ChatDelivery { seq, error }) => {
+                cm.ui_handler.chat_delivery(current_id, seq, &error);
+            }
+            Some(Data::ViewOnly(view_only)) => {
+                cm.view_only_changed(current_id, view_only);
+            }
+            Some(Data::ViewOnlyOffRequest) => {
+                cm.view_only_off_requested(current_id);
+            }
+            Some(Data::AudioOnly(audio_only)) => {
+                cm.audio_only_changed(current_id, audio_only);
+            }
+            Some(Data::SessionRecording(recording)) => {
+                cm.session_recording_changed(current_id, recording);
+            }
+            Some(Data::KeyboardLevel(level)) => {
+                cm.keyboard_level_changed(current_id, level);
+            }
+            Some(Data::SwitchPermission { name, enabled }) => {
+                cm.permission_changed(current_id, &name, enabled);
             }
             Some(Data::FS(fs)) => {
-                handle_fs(fs, &mut write_jobs, &tx, None).await;
+                handle_fs(fs, &mut write_jobs, &file_ops, &tx, None).await;
+            }
+            Some(Data::CmNotify(category)) => {
+                if let Some(category) = NotifyCategory::from_name(&category) {
+                    cm.notify(current_id, category);
+                }
             }
             Some(Data::Close) => {
                 break;
@@ -668,6 +1077,7 @@ pub async fn start_listen<T: InvokeUiCM>(
 async fn handle_fs(
     fs: ipc::FS,
     write_jobs: &mut Vec<fs::TransferJob>,
+    file_ops: &fs::file_op::Ops,
     tx: &UnboundedSender<Data>,
     tx_log: Option<&UnboundedSender<String>>,
 ) {
@@ -693,6 +1103,19 @@ async fn handle_fs(
         ipc::FS::CreateDir { path, id } => {
             create_dir(path, id, tx).await;
         }
+        ipc::FS::Rename { path, id, new_name } => {
+            let res = spawn_blocking(move || fs::file_op::rename(&path, &new_name)).await;
+            let res = res.map_err(|err| err.into()).and_then(|res| res);
+            send_raw(fs::file_op::new_result(id, &res), tx);
+        }
+        ipc::FS::RemovePath {
+            path,
+            id,
+            recursive,
+        } => {
+            // Not awaited, to be canceled by the id.
+            remove_path(path, id, recursive, file_ops.clone(), tx.clone());
+        }
         ipc::FS::NewWrite {
             path,
             id,
@@ -701,7 +1124,35 @@ async fn handle_fs(
             overwrite_detection,
             total_size,
             conn_id,
+            streams,
         } => {
+            let mut files: Vec<FileEntry> = files
+                .drain(..)
+                .map(|f| FileEntry {
+                    name: f.0,
+                    modified_time: f.1,
+                    ..Default::default()
+                })
+                .collect();
+            let policy = fs::file_name::NamePolicy::from_option(&Config::get_option(
+                fs::file_name::OPTION_FILE_NAME_POLICY,
+            ));
+            match fs::file_name::sanitize_entries(
+                &mut files,
+                fs::file_name::TargetOs::current(),
+                policy,
+            ) {
+                Ok(renamed) => {
+                    if !renamed.is_empty() {
+                        log::info!("write job {} renamed: {:?}", id, renamed);
+                    }
+                }
+                Err(err) => {
+                    log::error!("write job {} rejected: {}", id, err);
+                    send_raw(fs::new_error(id, err, -1), tx);
+                    return;
+                }
+            }
             // cm has no show_hidden context
             // dummy remote, show_hidden, is_remote
             let mut job = fs::TransferJob::new_write(
@@ -711,21 +1162,16 @@ async fn handle_fs(
                 file_num,
                 false,
                 false,
-                files
-                    .drain(..)
-                    .map(|f| FileEntry {
-                        name: f.0,
-                        modified_time: f.1,
-                        ..Default::default()
-                    })
-                    .collect(),
+                files,
                 overwrite_detection,
             );
             job.total_size = total_size;
             job.conn_id = conn_id;
+            job.streams = streams;
             write_jobs.push(job);
         }
         ipc::FS::CancelWrite { id } => {
+            file_ops.cancel(id);
             if let Some(job) = fs::get_job(id, write_jobs) {
                 job.remove_download_file();
                 tx_log.map(|tx: &UnboundedSender<String>| {
@@ -754,6 +1200,8 @@ async fn handle_fs(
             file_num,
             data,
             compressed,
+            offset,
+            stream,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
                 if let Err(err) = job
@@ -762,6 +1210,8 @@ async fn handle_fs(
                         file_num,
                         data,
                         compressed,
+                        offset,
+                        stream,
                         ..Default::default()
                     })
                     .await
@@ -906,6 +1356,29 @@ async fn remove_dir(path: String, id: i32, recursive: bool, tx: &UnboundedSender
     .await;
 }
 
+#[cfg(not(any(target_os = "ios")))]
+fn remove_path(
+    path: String,
+    id: i32,
+    recursive: bool,
+    file_ops: fs::file_op::Ops,
+    tx: UnboundedSender<Data>,
+) {
+    let canceled = file_ops.start(id);
+    tokio::spawn(async move {
+        let tx_progress = tx.clone();
+        let res = spawn_blocking(move || {
+            fs::file_op::remove_path(&path, recursive, &canceled, |removed, total| {
+                send_raw(fs::file_op::new_progress(id, removed, total), &tx_progress);
+            })
+        })
+        .await;
+        file_ops.finish(id);
+        let res = res.map_err(|err| err.into()).and_then(|res| res);
+        send_raw(fs::file_op::new_result(id, &res), &tx);
+    });
+}
+
 #[cfg(not(any(target_os = "ios")))]
 fn send_raw(msg: Message, tx: &UnboundedSender<Data>) {
     match msg.write_to_bytes() {
@@ -968,3 +1441,252 @@ pub fn close_voice_call(id: i32) {
         allow_err!(client.tx.send(Data::CloseVoiceCall("".to_owned())));
     };
 }
+
+#[cfg(test)]
+#[cfg(all(feature = "flutter", feature = "plugin_framework"))]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod tests {
+    use super::*;
+    use crate::flutter::connection_manager::{add_cm_hook, remove_cm_hook};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct MockUi {
+        added: Arc<Mutex<Vec<String>>>,
+        updated: Arc<Mutex<Vec<Client>>>,
+    }
+
+    impl InvokeUiCM for MockUi {
+        fn add_connection(&self, client: &Client) {
+            self.added.lock().unwrap().push(client.peer_id.clone());
+        }
+
+        fn remove_connection(&self, _id: i32, _close: bool) {}
+
+        fn new_message(&self, _id: i32, _text: String) {}
+
+        fn change_theme(&self, _dark: String) {}
+
+        fn change_language(&self) {}
+
+        fn show_elevation(&self, _show: bool) {}
+
+        fn update_voice_call_state(&self, _client: &Client) {}
+
+        fn update_view_only_state(&self, _client: &Client) {}
+
+        fn update_audio_only_state(&self, _client: &Client) {}
+
+        fn update_session_recording_state(&self, _client: &Client) {}
+
+        fn update_permission_state(&self, client: &Client) {
+            self.updated.lock().unwrap().push(client.clone());
+        }
+
+        fn file_transfer_log(&self, _action: &str, _log: &str) {}
+
+        fn action_log(&self, _id: i32, _action: &str, _log: &str) {}
+
+        fn notify(&self, _category: NotifyCategory, _mode: NotifyMode, _client: &Client) {}
+
+        fn update_permission_grant(&self, _id: i32, _name: &str, _secs: u64) {}
+
+        fn chat_delivery(&self, _id: i32, _seq: u64, _error: &str) {}
+
+        fn watch_frame(&self, _id: i32, _display: i32, _width: usize, _height: usize, _data: &str) {
+        }
+    }
+
+    fn add(
+        cm: &ConnectionManager<MockUi>,
+        id: i32,
+        peer_id: &str,
+    ) -> mpsc::UnboundedReceiver<Data> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        cm.add_connection(
+            id,
+            false,
+            "".to_owned(),
+            peer_id.to_owned(),
+            "".to_owned(),
+            false,
+            true,
+            true,
+            true,
+            true,
+            true,
+            true,
+            true,
+            true,
+            false,
+            "".to_owned(),
+            tx,
+        );
+        rx
+    }
+
+    #[test]
+    fn test_cm_hook_reject() {
+        assert!(add_cm_hook(
+            "test".to_owned(),
+            0,
+            CmHook::OnIncomingConnection(|client| {
+                if client.peer_id == "123456789" {
+                    HookAction::Block
+                } else {
+                    HookAction::Continue
+                }
+            }),
+        ));
+        let cm = ConnectionManager {
+            ui_handler: MockUi::default(),
+        };
+        let mut rx = add(&cm, 9001, "123456789");
+        match rx.try_recv() {
+            Ok(Data::CmHookBlocked((plugin, action))) => {
+                assert_eq!(plugin, "test");
+                assert_eq!(action, "connection");
+            }
+            _ => panic!("the connection is not rejected"),
+        }
+        assert!(!CLIENTS.read().unwrap().contains_key(&9001));
+
+        let mut rx = add(&cm, 9002, "987654321");
+        assert!(rx.try_recv().is_err());
+        assert_eq!(*cm.ui_handler.added.lock().unwrap(), vec!["987654321"]);
+
+        assert!(remove_cm_hook("test"));
+        remove(9002);
+    }
+
+    #[test]
+    fn test_permission_changed_by_host() {
+        let cm = ConnectionManager {
+            ui_handler: MockUi::default(),
+        };
+        let _rx = add(&cm, 9003, "123123123");
+        cm.permission_changed(9003, "clipboard", false);
+        cm.permission_changed(9003, "file", false);
+        cm.permission_changed(9003, "unknown", false);
+        let updated = cm.ui_handler.updated.lock().unwrap().clone();
+        assert_eq!(updated.len(), 2);
+        assert!(!updated[0].clipboard && updated[0].file);
+        assert!(!updated[1].clipboard && !updated[1].file);
+        assert!(updated[1].keyboard && updated[1].audio);
+        let client = CLIENTS.read().unwrap().get(&9003).cloned().unwrap();
+        assert!(!client.clipboard && !client.file);
+        remove(9003);
+    }
+
+    #[test]
+    fn test_send_message() {
+        let cm = ConnectionManager {
+            ui_handler: MockUi::default(),
+        };
+        let mut rx = add(&cm, 9004, "456456456");
+        let seq = send_message(9004, "hello".to_owned()).unwrap();
+        match rx.try_recv() {
+            Ok(Data::CmChatMessage { seq: s, text }) => {
+                assert_eq!(s, seq);
+                assert_eq!(text, "hello");
+            }
+            _ => panic!("the message is not sent"),
+        }
+        assert!(send_message(9004, " ".to_owned()).is_err());
+        assert!(send_message(9999, "hello".to_owned()).is_err());
+
+        CLIENTS
+            .write()
+            .unwrap()
+            .get_mut(&9004)
+            .unwrap()
+            .is_file_transfer = true;
+        let e = send_message(9004, "hello".to_owned()).unwrap_err();
+        assert!(e.to_string().contains("file transfer"));
+        assert!(rx.try_recv().is_err());
+        remove(9004);
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn test_watch() {
+        let cm = ConnectionManager {
+            ui_handler: MockUi::default(),
+        };
+        let mut rx = add(&cm, 9005, "789789789");
+        watch(9005, true).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(Data::CmWatch(true))));
+        assert!(watch(9999, true).is_err());
+
+        CLIENTS
+            .write()
+            .unwrap()
+            .get_mut(&9005)
+            .unwrap()
+            .is_file_transfer = true;
+        let e = watch(9005, true).unwrap_err();
+        assert!(e.to_string().contains("file transfer"));
+        // Stopping is always sent.
+        watch(9005, false).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(Data::CmWatch(false))));
+        remove(9005);
+    }
+
+    #[tokio::test]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn test_file_ops() {
+        async fn response(rx: &mut mpsc::UnboundedReceiver<Data>) -> file_response::Union {
+            match rx.recv().await {
+                Some(Data::RawMessage(bytes)) => Message::parse_from_bytes(&bytes)
+                    .unwrap()
+                    .file_response()
+                    .union
+                    .clone()
+                    .unwrap(),
+                _ => panic!("no file response"),
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("cm_file_ops_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tree/sub")).unwrap();
+        std::fs::write(dir.join("tree/sub/a"), "a").unwrap();
+        let path = |p: &str| dir.join(p).to_string_lossy().to_string();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut write_jobs = vec![];
+        let file_ops = fs::file_op::Ops::default();
+
+        let fs = ipc::FS::Rename {
+            path: path("tree"),
+            id: 1,
+            new_name: "renamed".to_owned(),
+        };
+        handle_fs(fs, &mut write_jobs, &file_ops, &tx, None).await;
+        match response(&mut rx).await {
+            file_response::Union::OpResult(r) => assert_eq!((r.id, r.error.as_str()), (1, "")),
+            r => panic!("{:?}", r),
+        }
+
+        let fs = ipc::FS::RemovePath {
+            path: path("renamed"),
+            id: 2,
+            recursive: true,
+        };
+        handle_fs(fs, &mut write_jobs, &file_ops, &tx, None).await;
+        let mut progress = vec![];
+        loop {
+            match response(&mut rx).await {
+                file_response::Union::OpProgress(p) => progress.push((p.removed, p.total)),
+                file_response::Union::OpResult(r) => {
+                    assert_eq!((r.id, r.error.as_str()), (2, ""));
+                    break;
+                }
+                r => panic!("{:?}", r),
+            }
+        }
+        assert_eq!(progress.last(), Some(&(3, 3)));
+        assert!(!dir.join("renamed").exists());
+        // Canceled or finished, not running any more.
+        assert!(!file_ops.cancel(2));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
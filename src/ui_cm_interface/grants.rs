@@ -0,0 +1,181 @@
+//! The permissions enabled by the local user for a while, e.g. the file transfer for the next 10
+//! minutes, and reverted automatically the same as revoked by hand.
+//!
+//! The grants live in the memory of the cm only. The connections revert the timed permissions to
+//! the options of the host if the cm exits, see `server::permission_options::TimedGrants`, so a
+//! restarted cm starts over from the defaults.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// The permissions that can be granted for a while, the ones following the options of the host.
+pub const GRANT_PERMISSIONS: [&str; 4] = ["keyboard", "clipboard", "audio", "file"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GrantEvent {
+    /// The seconds remaining, pushed more often towards the expiry.
+    Countdown { id: i32, name: String, secs: u64 },
+    Expired { id: i32, name: String },
+}
+
+struct Grant {
+    expire: Instant,
+    // The remaining seconds last pushed.
+    last_pushed: Option<u64>,
+}
+
+/// (connection id, permission name) -> the grant
+#[derive(Default)]
+pub struct Grants(HashMap<(i32, String), Grant>);
+
+// Every minute, then every 10 seconds in the last minute, every second in the last 10.
+fn countdown_step(secs: u64) -> u64 {
+    if secs > 60 {
+        60
+    } else if secs > 10 {
+        10
+    } else {
+        1
+    }
+}
+
+impl Grants {
+    #[inline]
+    pub fn is_grantable(name: &str) -> bool {
+        GRANT_PERMISSIONS.contains(&name)
+    }
+
+    /// Start the timer, or restart it if the permission is granted already.
+    pub fn grant(&mut self, id: i32, name: &str, duration: Duration, now: Instant) {
+        self.0.insert(
+            (id, name.to_owned()),
+            Grant {
+                expire: now + duration,
+                last_pushed: None,
+            },
+        );
+    }
+
+    /// Returns false if the permission is not granted for a while.
+    pub fn extend(&mut self, id: i32, name: &str, duration: Duration) -> bool {
+        match self.0.get_mut(&(id, name.to_owned())) {
+            Some(grant) => {
+                grant.expire += duration;
+                grant.last_pushed = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop the timer, the permission is kept. Returns false if there is no timer.
+    pub fn cancel(&mut self, id: i32, name: &str) -> bool {
+        self.0.remove(&(id, name.to_owned())).is_some()
+    }
+
+    pub fn remove_connection(&mut self, id: i32) {
+        self.0.retain(|(conn_id, _), _| *conn_id != id);
+    }
+
+    /// Called every second, the expired grants are removed.
+    pub fn tick(&mut self, now: Instant) -> Vec<GrantEvent> {
+        let mut events = vec![];
+        self.0.retain(|(id, name), grant| {
+            let remaining = grant.expire.saturating_duration_since(now);
+            if remaining.is_zero() {
+                events.push(GrantEvent::Expired {
+                    id: *id,
+                    name: name.clone(),
+                });
+                return false;
+            }
+            // Rounded up, so the countdown ends with 1.
+            let secs = (remaining + Duration::from_millis(999)).as_secs();
+            let push = match grant.last_pushed {
+                None => true,
+                Some(last) => last.saturating_sub(secs) >= countdown_step(secs),
+            };
+            if push {
+                grant.last_pushed = Some(secs);
+                events.push(GrantEvent::Countdown {
+                    id: *id,
+                    name: name.clone(),
+                    secs,
+                });
+            }
+            true
+        });
+        events.sort_by_key(|e| match e {
+            GrantEvent::Countdown { id, name, .. } | GrantEvent::Expired { id, name } => {
+                (*id, name.clone())
+            }
+        });
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    fn countdown(id: i32, name: &str, secs: u64) -> GrantEvent {
+        GrantEvent::Countdown {
+            id,
+            name: name.to_owned(),
+            secs,
+        }
+    }
+
+    #[test]
+    fn test_grant_countdown_and_expiry() {
+        let mut grants = Grants::default();
+        let start = Instant::now();
+        grants.grant(1, "file", Duration::from_secs(120), start);
+        assert_eq!(grants.tick(secs(start, 1)), vec![countdown(1, "file", 119)]);
+        assert!(grants.tick(secs(start, 2)).is_empty());
+        assert!(grants.tick(secs(start, 59)).is_empty());
+        assert_eq!(grants.tick(secs(start, 60)), vec![countdown(1, "file", 60)]);
+        assert!(grants.tick(secs(start, 68)).is_empty());
+        assert_eq!(grants.tick(secs(start, 70)), vec![countdown(1, "file", 50)]);
+        assert_eq!(grants.tick(secs(start, 110)), vec![countdown(1, "file", 10)]);
+        assert_eq!(grants.tick(secs(start, 111)), vec![countdown(1, "file", 9)]);
+        assert_eq!(
+            grants.tick(secs(start, 120)),
+            vec![GrantEvent::Expired {
+                id: 1,
+                name: "file".to_owned()
+            }]
+        );
+        assert!(grants.tick(secs(start, 121)).is_empty());
+    }
+
+    #[test]
+    fn test_grant_extend_cancel_and_disconnect() {
+        let mut grants = Grants::default();
+        let start = Instant::now();
+        assert!(!grants.extend(1, "file", Duration::from_secs(60)));
+        grants.grant(1, "file", Duration::from_secs(30), start);
+        grants.grant(1, "clipboard", Duration::from_secs(30), start);
+        grants.grant(2, "file", Duration::from_secs(30), start);
+        assert_eq!(grants.tick(start).len(), 3);
+
+        // Pushed at once after extending.
+        assert!(grants.extend(1, "file", Duration::from_secs(60)));
+        assert_eq!(grants.tick(secs(start, 1)), vec![countdown(1, "file", 89)]);
+        assert!(grants.cancel(1, "clipboard"));
+        assert!(!grants.cancel(1, "clipboard"));
+        // The timers of the closed connection are dropped.
+        grants.remove_connection(2);
+        assert_eq!(grants.tick(secs(start, 60)), vec![countdown(1, "file", 30)]);
+        assert!(grants.tick(secs(start, 61)).is_empty());
+
+        assert!(Grants::is_grantable("clipboard"));
+        assert!(!Grants::is_grantable("restart"));
+    }
+}
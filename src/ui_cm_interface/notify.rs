@@ -0,0 +1,143 @@
+//! The notifications of the local user on the events of the connections, i.e. the toasts and the
+//! sounds of the cm.
+//!
+//! The mode of each category is the local option `cm-notify-<category>`, "none" if not set.
+//! The main window sends the changed options to the cm by the ipc, so they take effect at once.
+
+use hbb_common::config::LocalConfig;
+
+pub const OPTION_PREFIX: &str = "cm-notify-";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyCategory {
+    ConnectionAccepted,
+    FileTransferStarted,
+    ClipboardReceived,
+    ChatMessage,
+}
+
+impl NotifyCategory {
+    pub const ALL: [Self; 4] = [
+        Self::ConnectionAccepted,
+        Self::FileTransferStarted,
+        Self::ClipboardReceived,
+        Self::ChatMessage,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ConnectionAccepted => "connection_accepted",
+            Self::FileTransferStarted => "file_transfer_started",
+            Self::ClipboardReceived => "clipboard_received",
+            Self::ChatMessage => "chat_message",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    pub fn option(&self) -> String {
+        format!("{}{}", OPTION_PREFIX, self.name().replace('_', "-"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotifyMode {
+    #[default]
+    None,
+    /// A toast, or a notification on Android.
+    Visual,
+    /// The sound besides the visual cue.
+    Sound,
+}
+
+impl NotifyMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Visual => "visual",
+            Self::Sound => "sound",
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name {
+            "visual" => Self::Visual,
+            "sound" => Self::Sound,
+            _ => Self::None,
+        }
+    }
+}
+
+#[inline]
+pub fn is_notify_option(key: &str) -> bool {
+    key.starts_with(OPTION_PREFIX)
+}
+
+/// The mode of the category, `get_option` is the getter of the local options.
+pub fn mode_of(category: NotifyCategory, get_option: impl Fn(&str) -> String) -> NotifyMode {
+    NotifyMode::from_name(&get_option(&category.option()))
+}
+
+#[inline]
+pub fn mode(category: NotifyCategory) -> NotifyMode {
+    mode_of(category, LocalConfig::get_option)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_notify_policy_lookup() {
+        let options: HashMap<String, String> = [
+            ("cm-notify-connection-accepted", "sound"),
+            ("cm-notify-file-transfer-started", "visual"),
+            ("cm-notify-chat-message", "unknown"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        let get_option = |k: &str| options.get(k).cloned().unwrap_or_default();
+        let modes: Vec<_> = NotifyCategory::ALL
+            .into_iter()
+            .map(|c| mode_of(c, get_option))
+            .collect();
+        assert_eq!(
+            modes,
+            vec![
+                NotifyMode::Sound,
+                NotifyMode::Visual,
+                // Not set, or an invalid value.
+                NotifyMode::None,
+                NotifyMode::None,
+            ]
+        );
+
+        for category in NotifyCategory::ALL {
+            assert!(is_notify_option(&category.option()));
+            assert_eq!(NotifyCategory::from_name(category.name()), Some(category));
+            for mode in [NotifyMode::None, NotifyMode::Visual, NotifyMode::Sound] {
+                let get_option = |k: &str| {
+                    if k == category.option() {
+                        mode.name().to_owned()
+                    } else {
+                        "".to_owned()
+                    }
+                };
+                for other in NotifyCategory::ALL {
+                    let expected = if other == category {
+                        mode
+                    } else {
+                        NotifyMode::None
+                    };
+                    assert_eq!(mode_of(other, get_option), expected);
+                }
+            }
+        }
+        assert_eq!(NotifyCategory::from_name("unknown"), None);
+        assert!(!is_notify_option("lang"));
+    }
+}
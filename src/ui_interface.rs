@@ -270,6 +270,11 @@ pub fn test_if_valid_server(host: String) -> String {
     hbb_common::socket_client::test_if_valid_server(&host)
 }
 
+#[inline]
+pub fn test_if_valid_proxy(proxy: String) -> String {
+    hbb_common::socket_client::test_if_valid_proxy(&proxy)
+}
+
 #[inline]
 #[cfg(feature = "flutter")]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -1058,7 +1063,16 @@ async fn check_connect_status_(reconnect: bool, rx: mpsc::UnboundedReceiver<ipc:
                                         id = value;
                                     }
                                 } else if name == "temporary-password" {
-                                    *TEMPORARY_PASSWD.lock().unwrap() = value;
+                                    let mut passwd = TEMPORARY_PASSWD.lock().unwrap();
+                                    // Rotated by the server, e.g. the one-time password is used or expired.
+                                    #[cfg(feature = "flutter")]
+                                    if !passwd.is_empty() && *passwd != value {
+                                        let _ = crate::flutter::push_global_event(
+                                            crate::flutter::APP_TYPE_MAIN,
+                                            serde_json::json!({ "name": "temporary_password_rotated" }).to_string(),
+                                        );
+                                    }
+                                    *passwd = value;
                                 }
                             }
                             Ok(Some(ipc::Data::OnlineStatus(Some((mut x, _c))))) => {
@@ -17,7 +17,7 @@ use uuid::Uuid;
 #[cfg(not(feature = "flutter"))]
 use hbb_common::fs;
 use hbb_common::{
-    allow_err,
+    allow_err, bail,
     config::{Config, LocalConfig, PeerConfig},
     get_version_number, log,
     message_proto::*,
@@ -27,13 +27,19 @@ use hbb_common::{
         sync::mpsc,
         time::{Duration as TokioDuration, Instant},
     },
-    Stream,
+    ResultType, Stream,
 };
 
 use crate::client::io_loop::Remote;
 use crate::client::{
-    check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
+    check_if_retry,
+    codec_negotiation::{self, CodecCapabilities},
+    color_mode::{self, ColorMode},
+    decoder_select,
+    hotkey_rules::{HotkeyAction, HotkeyRules, OPTION_HOTKEY_RULES},
+    handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, start_video_audio_threads,
+    text_clipboard::{ClipboardGate, OPTION_DISABLE_CLIPBOARD_READ},
     FileManager, Key, LoginConfigHandler, QualityStatus, KEY_MAP,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -42,6 +48,28 @@ use crate::keyboard;
 use crate::{client::Data, client::Interface};
 
 const CHANGE_RESOLUTION_VALID_TIMEOUT_SECS: u64 = 15;
+const VIEW_ONLY_BLOCKED_NOTIFY_INTERVAL: TokioDuration = TokioDuration::from_secs(3);
+// The probe takes about 5 seconds, plus the echo timeouts of the pings and the burst.
+const NETWORK_PROBE_TIMEOUT: TokioDuration = TokioDuration::from_secs(15);
+// The remote device takes a while to boot, no reconnection is tried before.
+const RESTART_RECONNECT_DELAY: TokioDuration = TokioDuration::from_secs(30);
+const RESTART_RECONNECT_INTERVAL: TokioDuration = TokioDuration::from_secs(10);
+const RESTART_RECONNECT_TIMEOUT: TokioDuration = TokioDuration::from_secs(300);
+/// The permissions given by the host, see `permission_info::Permission`.
+pub const PERMISSIONS: [&str; 9] = [
+    "keyboard",
+    "clipboard",
+    "audio",
+    "file",
+    "restart",
+    "recording",
+    "block_input",
+    "printer",
+    "annotation",
+];
+pub(crate) const VIEW_ONLY_OFF_MSGTYPE: &str = "custom-nook-nocancel-hasclose";
+pub(crate) const VIEW_ONLY_OFF_TITLE: &str = "View-only";
+pub(crate) const VIEW_ONLY_OFF_TEXT: &str = "view_only_off_wait_tip";
 
 #[derive(Clone, Default)]
 pub struct Session<T: InvokeUiSession> {
@@ -56,6 +84,21 @@ pub struct Session<T: InvokeUiSession> {
     pub server_clipboard_enabled: Arc<RwLock<bool>>,
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub port_forward_stats: crate::port_forward::PortForwardStatsPtr,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub rdp_options: Arc<RwLock<crate::port_forward::RdpOptions>>,
+    /// The last time the UI is told that the input is blocked by the view-only mode.
+    pub view_only_blocked: Arc<Mutex<Option<Instant>>>,
+    pub intent: Arc<Mutex<SessionIntent>>,
+    /// Loaded from the peer config with the first key event.
+    pub hotkey_rules: Arc<Mutex<HotkeyRules>>,
+    /// The percent and the mute of the output device of the peer, once told by the peer.
+    pub remote_volume: Arc<Mutex<Option<(u32, bool)>>>,
+    /// The files received by the features, removed once the session is dropped.
+    pub staging: crate::client::staging::SharedStaging,
+    pub latency: Arc<Mutex<crate::client::latency::Tracker>>,
+    pub color_mode: Arc<Mutex<color_mode::Tracker>>,
 }
 
 #[derive(Clone)]
@@ -66,6 +109,26 @@ pub struct SessionPermissionConfig {
     pub server_clipboard_enabled: Arc<RwLock<bool>>,
 }
 
+/// The result of a session hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Stop the action, e.g. the key event is not sent to the peer.
+    Block,
+}
+
+/// The file transfer job passed to the session hooks.
+#[derive(Debug, Clone, Default)]
+pub struct JobMeta {
+    pub id: i32,
+    pub path: String,
+    pub to: String,
+    pub file_num: i32,
+    pub include_hidden: bool,
+    /// Transfer from the remote to the local if true.
+    pub is_remote: bool,
+}
+
 pub struct ChangeDisplayRecord {
     time: Instant,
     display: i32,
@@ -73,7 +136,9 @@ pub struct ChangeDisplayRecord {
     height: i32,
 }
 
-enum ConnectionState {
+/// The state of the connection to the peer, `Connecting` before the first round too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
     Connecting,
     Connected,
     Disconnected,
@@ -96,6 +161,15 @@ impl ConnectionRoundState {
         self.state = ConnectionState::Connected;
     }
 
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ConnectionState::Connected)
+    }
+
+    #[inline]
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
     pub fn is_round_gt(&self, round: u32) -> bool {
         if round == u32::MAX && self.round == 0 {
             true
@@ -112,6 +186,38 @@ impl ConnectionRoundState {
             true
         }
     }
+
+    /// The round is ended without disconnecting, e.g. its io loop panicked. The late events of
+    /// the round are stale, and the reconnecting starts a new round.
+    pub fn end_round(&mut self, round: u32) {
+        if round == self.round {
+            self.round += 1;
+            self.state = ConnectionState::Disconnected;
+        }
+    }
+}
+
+/// Why the session is going away, the reconnection only goes on after a network drop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionIntent {
+    Active,
+    UserClose,
+    NetworkDrop,
+    /// The peer asks to switch the sides back, the session is replaced by the switched one.
+    SwitchBack,
+}
+
+impl Default for SessionIntent {
+    fn default() -> Self {
+        Self::Active
+    }
+}
+
+impl SessionIntent {
+    #[inline]
+    pub fn allows_reconnect(&self) -> bool {
+        matches!(self, Self::Active | Self::NetworkDrop)
+    }
 }
 
 impl Default for ConnectionRoundState {
@@ -153,12 +259,30 @@ impl ChangeDisplayRecord {
     }
 }
 
+fn clipboard_gate(
+    lc: &RwLock<LoginConfigHandler>,
+    server_clipboard_enabled: &RwLock<bool>,
+    server_keyboard_enabled: &RwLock<bool>,
+) -> ClipboardGate {
+    let lc = lc.read().unwrap();
+    ClipboardGate {
+        server_clipboard_enabled: *server_clipboard_enabled.read().unwrap(),
+        server_keyboard_enabled: *server_keyboard_enabled.read().unwrap(),
+        disable_clipboard: lc.disable_clipboard.v,
+        view_only: lc.view_only_session,
+        disable_read: lc.get_toggle_option(OPTION_DISABLE_CLIPBOARD_READ),
+    }
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 impl SessionPermissionConfig {
     pub fn is_text_clipboard_required(&self) -> bool {
-        *self.server_clipboard_enabled.read().unwrap()
-            && *self.server_keyboard_enabled.read().unwrap()
-            && !self.lc.read().unwrap().disable_clipboard.v
+        clipboard_gate(
+            &self.lc,
+            &self.server_clipboard_enabled,
+            &self.server_keyboard_enabled,
+        )
+        .is_outbound_allowed()
     }
 }
 
@@ -181,6 +305,11 @@ impl<T: InvokeUiSession> Session<T> {
             .eq(&ConnType::FILE_TRANSFER)
     }
 
+    /// The remote desktop session.
+    pub fn is_default(&self) -> bool {
+        self.lc.read().unwrap().conn_type == ConnType::DEFAULT_CONN
+    }
+
     pub fn is_port_forward(&self) -> bool {
         let conn_type = self.lc.read().unwrap().conn_type;
         conn_type == ConnType::PORT_FORWARD || conn_type == ConnType::RDP
@@ -278,6 +407,14 @@ impl<T: InvokeUiSession> Session<T> {
             .save_displays_as_individual_windows(value);
     }
 
+    pub fn get_view_state(&self, display: i32) -> String {
+        self.lc.read().unwrap().get_view_state(display)
+    }
+
+    pub fn save_view_state(&self, display: i32, state: String) {
+        self.lc.read().unwrap().save_view_state(display, state);
+    }
+
     pub fn save_use_all_my_displays_for_the_remote_session(&self, value: String) {
         self.lc
             .write()
@@ -312,11 +449,54 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    /// Raise the thresholds of the load guard of the host for this session.
+    /// Kept in the peer option "prioritize-quality", and sent again after the next login.
+    pub fn prioritize_quality(&self, v: bool) {
+        self.set_option(
+            "prioritize-quality".to_owned(),
+            if v { "Y" } else { "" }.to_owned(),
+        );
+        let mut misc = Misc::new();
+        misc.set_prioritize_quality(v);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Measure the latency of the frames, reported with the quality status.
+    /// Kept in the peer option "latency-breakdown", and sent again after the next login.
+    pub fn measure_latency(&self, v: bool) {
+        self.set_option(
+            "latency-breakdown".to_owned(),
+            if v { "Y" } else { "" }.to_owned(),
+        );
+        self.latency.lock().unwrap().set_enabled(v);
+        if !self.peer_capabilities().frame_timestamps {
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_frame_timestamps(v);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Request the peer to capture the region of a display, the whole display if the size is 0.
+    pub fn set_viewport(&self, viewport: Viewport) {
+        let mut misc = Misc::new();
+        misc.set_viewport(viewport);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn toggle_privacy_mode(&self, impl_key: String, on: bool) {
         let mut misc = Misc::new();
+        let display_off = on && self.get_option("privacy-mode-display-off".to_owned()) == "Y";
         misc.set_toggle_privacy_mode(TogglePrivacyMode {
             impl_key,
             on,
+            display_off,
             ..Default::default()
         });
         let mut msg_out = Message::new();
@@ -333,16 +513,33 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().is_privacy_mode_supported()
     }
 
-    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn clipboard_gate(&self) -> ClipboardGate {
+        clipboard_gate(
+            &self.lc,
+            &self.server_clipboard_enabled,
+            &self.server_keyboard_enabled,
+        )
+    }
+
+    /// The local text clipboard can be read and sent to the peer.
     pub fn is_text_clipboard_required(&self) -> bool {
-        *self.server_clipboard_enabled.read().unwrap()
-            && *self.server_keyboard_enabled.read().unwrap()
-            && !self.lc.read().unwrap().disable_clipboard.v
+        self.clipboard_gate().is_outbound_allowed()
+    }
+
+    /// Send the text clipboard read by the mobile ui. Returns false if it's not allowed,
+    /// the ui should not read the clipboard then.
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    pub fn send_text_clipboard(&self, text: String) -> bool {
+        if !self.is_text_clipboard_required() {
+            return false;
+        }
+        self.send(Data::Message(crate::create_clipboard_msg(text)));
+        true
     }
 
     #[cfg(feature = "flutter")]
     pub fn refresh_video(&self, display: i32) {
-        if crate::common::is_support_multi_ui_session_num(self.lc.read().unwrap().version) {
+        if self.peer_capabilities().multi_ui_session {
             self.send(Data::Message(LoginConfigHandler::refresh_display(
                 display as _,
             )));
@@ -363,6 +560,168 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn peer_capabilities(&self) -> crate::common::PeerCapabilities {
+        let lc = self.lc.read().unwrap();
+        crate::common::PeerCapabilities::new(lc.version, &lc.info.platform, &lc.platform_additions)
+            .with_features(lc.features.as_ref())
+    }
+
+    /// Show the tip instead of sending the input which can't be injected by the peer.
+    fn check_can_inject_input(&self) -> bool {
+        if self.peer_capabilities().can_inject_input {
+            return true;
+        }
+        self.msgbox(
+            crate::common::UNSUPPORTED_CAPABILITY_MSGTYPE,
+            "Error",
+            "peer_cannot_inject_input_tip",
+            "",
+        );
+        false
+    }
+
+    /// Request the system information of the peer, which is answered by
+    /// [`InvokeUiSession::remote_sysinfo`].
+    pub fn get_remote_sysinfo(&self) {
+        if !self.peer_capabilities().remote_sysinfo {
+            self.remote_sysinfo("", "remote_sysinfo_unsupported_tip");
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_remote_sysinfo_request(true);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn on_remote_sysinfo(&self, sysinfo: &RemoteSysinfo) {
+        if sysinfo.denied {
+            self.remote_sysinfo("", "remote_sysinfo_denied_tip");
+        } else {
+            self.remote_sysinfo(&sysinfo.json, "");
+        }
+    }
+
+    /// Ask the peer to broadcast the magic packets of `mac` on its networks, answered by
+    /// [`InvokeUiSession::wol_result`].
+    pub fn relay_wake_on_lan(&self, mac: &str) {
+        if !self.peer_capabilities().wake_on_lan {
+            self.wol_result(mac, "wol_unsupported_tip", 0);
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_wake_on_lan(WakeOnLan {
+            mac: mac.to_owned(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// The output volume of the peer, answered and then kept in sync by
+    /// [`InvokeUiSession::remote_volume_changed`]. Returns the state known.
+    pub fn query_remote_volume(&self) -> Option<(u32, bool)> {
+        self.send_remote_volume(RemoteVolume {
+            query: true,
+            ..Default::default()
+        });
+        *self.remote_volume.lock().unwrap()
+    }
+
+    /// Set the output volume of the peer, `percent` is 0-100. The mute is kept.
+    pub fn set_remote_volume(&self, percent: u32) {
+        let muted = self.remote_volume.lock().unwrap().map_or(false, |(_, m)| m);
+        self.send_remote_volume(RemoteVolume {
+            percent: percent.min(100),
+            muted,
+            ..Default::default()
+        });
+    }
+
+    pub fn set_remote_muted(&self, muted: bool) {
+        // The volume is unknown until told, which must not be reset.
+        let known = *self.remote_volume.lock().unwrap();
+        let Some((percent, _)) = known else {
+            self.query_remote_volume();
+            return;
+        };
+        self.send_remote_volume(RemoteVolume {
+            percent,
+            muted,
+            ..Default::default()
+        });
+    }
+
+    pub fn toggle_remote_mute(&self) {
+        let muted = self.remote_volume.lock().unwrap().map_or(false, |(_, m)| m);
+        self.set_remote_muted(!muted);
+    }
+
+    fn send_remote_volume(&self, volume: RemoteVolume) {
+        if !self.peer_capabilities().remote_volume {
+            self.remote_volume_changed("unsupported", 0, false);
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_remote_volume(volume);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn on_remote_volume(&self, volume: &RemoteVolume) {
+        if volume.denied {
+            self.remote_volume_changed("denied", 0, false);
+        } else if volume.unsupported {
+            self.remote_volume_changed("unsupported", 0, false);
+        } else {
+            *self.remote_volume.lock().unwrap() = Some((volume.percent, volume.muted));
+            self.remote_volume_changed("ok", volume.percent, volume.muted);
+        }
+    }
+
+    pub fn add_virtual_display(&self, width: i32, height: i32) {
+        if !self.peer_capabilities().virtual_display_control {
+            self.msgbox(
+                crate::common::VIRTUAL_DISPLAY_ERROR_MSGTYPE,
+                "Virtual display",
+                "The remote side does not support adding virtual displays.",
+                "",
+            );
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_add_virtual_display(AddVirtualDisplay {
+            width,
+            height,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    pub fn remove_virtual_display(&self, display: i32) {
+        if !self.peer_capabilities().virtual_display_control {
+            self.msgbox(
+                crate::common::VIRTUAL_DISPLAY_ERROR_MSGTYPE,
+                "Virtual display",
+                "The remote side does not support removing virtual displays.",
+                "",
+            );
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_remove_virtual_display(RemoveVirtualDisplay {
+            display,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     #[cfg(not(feature = "flutter"))]
     pub fn refresh_video(&self, _display: i32) {
         self.send(Data::Message(LoginConfigHandler::refresh()));
@@ -454,11 +813,239 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn codec_capabilities(&self) -> CodecCapabilities {
+        let decoding = scrap::codec::Decoder::supported_decodings(None);
+        let lc = self.lc.read().unwrap();
+        CodecCapabilities::new(&decoding, &lc.supported_encoding, &lc.hw_decoder_failed)
+    }
+
+    /// Save the preferred codec and renegotiate, "auto" or empty for no preference.
+    /// The result is pushed by "codec_negotiated" once the active codec changes.
+    pub fn set_codec_preference(&self, codec: &str) -> ResultType<()> {
+        if !codec.is_empty() && codec != "auto" && codec_negotiation::parse_codec(codec).is_none()
+        {
+            bail!("Unknown codec {}", codec);
+        }
+        self.set_option(
+            codec_negotiation::OPTION_CODEC_PREFERENCE.to_owned(),
+            codec.to_owned(),
+        );
+        self.change_prefer_codec();
+        Ok(())
+    }
+
+    /// Ask for 4:4:4 or not, saved for the peer, see `color_mode`. The codec is renegotiated, the
+    /// displays staying in 4:2:0 are pushed by "color_mode_fallback".
+    pub fn set_color_accuracy(&self, on: bool) -> ColorMode {
+        self.set_option(
+            color_mode::OPTION_COLOR_ACCURACY.to_owned(),
+            if on { "Y" } else { "" }.to_owned(),
+        );
+        self.change_prefer_codec();
+        self.get_color_mode()
+    }
+
+    pub fn get_color_mode(&self) -> ColorMode {
+        let decoding = scrap::codec::Decoder::supported_decodings(None);
+        let lc = self.lc.read().unwrap();
+        let usable = self
+            .color_mode
+            .lock()
+            .unwrap()
+            .codec()
+            .map(|codec| color_mode::i444_usable(codec, &decoding, &lc.supported_encoding));
+        ColorMode {
+            requested: lc.get_toggle_option(color_mode::OPTION_COLOR_ACCURACY),
+            usable,
+            bitrate_factor: color_mode::I444_BITRATE_FACTOR,
+        }
+    }
+
+    /// Called with the quality status, tell the displays falling back to 4:2:0.
+    /// Returns whether 4:4:4 is asked.
+    pub fn check_color_mode(
+        &self,
+        decode_stats: &HashMap<usize, crate::client::DecodeStats>,
+    ) -> bool {
+        let mode = self.get_color_mode();
+        let chromas = decode_stats
+            .iter()
+            .map(|(display, stats)| (*display, stats.chroma))
+            .collect();
+        let fallbacks = self.color_mode.lock().unwrap().check(
+            mode.requested,
+            mode.usable.unwrap_or(false),
+            &chromas,
+            std::time::Instant::now(),
+        );
+        for fallback in fallbacks {
+            log::info!(
+                "Display {} falls back to 4:2:0, reason: {}, codec: {}",
+                fallback.display,
+                fallback.reason,
+                fallback.codec
+            );
+            self.color_mode_fallback(&fallback);
+        }
+        mode.requested
+    }
+
+    /// The files staged for the session and not moved away.
+    pub fn staged_files(&self) -> Vec<crate::client::staging::StagedFile> {
+        self.staging.lock().unwrap().files()
+    }
+
+    pub fn clear_staging(&self) {
+        self.staging.lock().unwrap().clear();
+    }
+
+    /// Decode in software only or not, saved for the peer.
+    /// The decoders are created again and the codec is renegotiated, without reconnecting.
+    pub fn force_software_decode(&self, software: bool) {
+        self.set_option(
+            decoder_select::OPTION_FORCE_SOFTWARE_DECODE.to_owned(),
+            if software { "Y" } else { "" }.to_owned(),
+        );
+        self.send(Data::ForceSoftwareDecode(software));
+        self.change_prefer_codec();
+    }
+
+    /// Called when the hardware decoder keeps failing, the peer is asked to use another codec.
+    pub fn on_hw_decoder_failed(&self, format: scrap::CodecFormat) {
+        let msg = {
+            let mut lc = self.lc.write().unwrap();
+            if lc.hw_decoder_failed.contains(&format) {
+                return;
+            }
+            log::warn!(
+                "The {} decoder fails, fall back to the software codecs",
+                format.to_string()
+            );
+            lc.hw_decoder_failed.push(format);
+            lc.change_prefer_codec()
+        };
+        self.send(Data::Message(msg));
+    }
+
+    /// Called when the codec of the video frames changes.
+    pub fn on_codec_negotiated(&self, active: &scrap::CodecFormat) {
+        self.color_mode
+            .lock()
+            .unwrap()
+            .on_codec(active, std::time::Instant::now());
+        let reason = {
+            let lc = self.lc.read().unwrap();
+            let prefer = codec_negotiation::parse_codec(
+                &lc.get_option(codec_negotiation::OPTION_CODEC_PREFERENCE),
+            );
+            codec_negotiation::negotiated_reason(prefer.as_ref(), active, &lc.hw_decoder_failed)
+        };
+        self.codec_negotiated(&active.to_string(), reason);
+    }
+
+    /// Called once connected, before any message of the peer.
+    /// The permissions are reset to the defaults of the host, which tells the disabled ones.
+    pub fn on_connection_ready(&self, is_secured: bool, direct: bool) {
+        *self.server_keyboard_enabled.write().unwrap() = true;
+        *self.server_clipboard_enabled.write().unwrap() = true;
+        *self.server_file_transfer_enabled.write().unwrap() = true;
+        self.set_connection_type(is_secured, direct);
+        if !direct {
+            let relay = self.lc.read().unwrap().relay_server.clone();
+            if let Some(relay) = relay {
+                self.set_relay_server(&relay);
+            }
+        }
+        let permissions: Vec<_> = PERMISSIONS.iter().map(|name| (*name, true)).collect();
+        self.set_permissions(&permissions);
+    }
+
+    /// Called when the host is locked, its display sleeps or it's back to active.
+    pub fn on_host_display_state(&self, state: host_display_state::State) {
+        self.host_display_state(match state {
+            host_display_state::State::Active => "active",
+            host_display_state::State::Locked => "locked",
+            host_display_state::State::DisplayOff => "display_off",
+        });
+    }
+
+    /// Trust the new fingerprint of the peer and resume the session paused by the mismatch.
+    pub fn accept_new_fingerprint(&self) {
+        self.send(Data::AcceptFingerprint);
+    }
+
+    /// The session is reconnected by [`Self::on_remote_restarting`] once the peer goes down.
     pub fn restart_remote_device(&self) {
         let mut lc = self.lc.write().unwrap();
         lc.restarting_remote_device = true;
         let msg = lc.restart_remote_device();
+        drop(lc);
         self.send(Data::Message(msg));
+        self.remote_restarting("requested", 0);
+    }
+
+    pub fn on_restart_remote_device_denied(&self) {
+        self.lc.write().unwrap().restarting_remote_device = false;
+        self.remote_restarting("denied", 0);
+        self.msgbox(
+            "error",
+            "Restart remote device",
+            "remote_restart_denied_tip",
+            "",
+        );
+    }
+
+    /// Called when the connection to the restarting peer is closed.
+    /// Reconnect until the peer answers, or the timeout expires.
+    pub fn on_remote_restarting(&self) {
+        {
+            let mut lc = self.lc.write().unwrap();
+            if !lc.restarting_remote_device || lc.restart_reconnecting {
+                return;
+            }
+            lc.restart_reconnecting = true;
+        }
+        self.msgbox(
+            "restarting",
+            "Restarting remote device",
+            "remote_restart_reconnecting_tip",
+            "",
+        );
+        let session = self.clone();
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            // Cleared by `handle_peer_info` once reconnected, or by `close`.
+            let restarting = || session.lc.read().unwrap().restarting_remote_device;
+            let wait = |d: TokioDuration| {
+                let end = Instant::now() + d;
+                while restarting() && Instant::now() < end {
+                    std::thread::sleep(std::time::Duration::from_millis(300));
+                }
+            };
+            wait(RESTART_RECONNECT_DELAY);
+            let mut attempt = 0;
+            while restarting() {
+                if start.elapsed() >= RESTART_RECONNECT_TIMEOUT {
+                    let mut lc = session.lc.write().unwrap();
+                    lc.restarting_remote_device = false;
+                    lc.restart_reconnecting = false;
+                    drop(lc);
+                    session.remote_restarting("timeout", attempt);
+                    session.msgbox(
+                        "error",
+                        "Restarting remote device",
+                        "remote_restart_timeout_tip",
+                        "",
+                    );
+                    return;
+                }
+                attempt += 1;
+                log::info!("Reconnect to the restarting remote device, attempt {}", attempt);
+                session.remote_restarting("waiting_for_host", attempt);
+                session.reconnect(false);
+                wait(RESTART_RECONNECT_INTERVAL);
+            }
+        });
     }
 
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
@@ -509,6 +1096,13 @@ impl<T: InvokeUiSession> Session<T> {
             .collect();
         self.save_config(config);
         self.send(Data::RemovePortForward(port));
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        self.port_forward_stats.lock().unwrap().remove(port);
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn get_port_forward_stats(&self) -> String {
+        self.port_forward_stats.lock().unwrap().to_json()
     }
 
     pub fn add_port_forward(&self, port: i32, remote_host: String, remote_port: i32) {
@@ -528,6 +1122,19 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::AddPortForward(pf));
     }
 
+    pub fn start_socks(&self, port: i32, username: String, password: String) {
+        let auth = if username.is_empty() && password.is_empty() {
+            None
+        } else {
+            Some((username, password))
+        };
+        self.send(Data::StartSocks((port, auth)));
+    }
+
+    pub fn stop_socks(&self, port: i32) {
+        self.send(Data::RemovePortForward(port));
+    }
+
     pub fn get_option(&self, k: String) -> String {
         if k.eq("remote_dir") {
             return self.lc.read().unwrap().get_remote_dir();
@@ -557,6 +1164,32 @@ impl<T: InvokeUiSession> Session<T> {
         self.lc.read().unwrap().restarting_remote_device
     }
 
+    #[inline]
+    pub fn get_intent(&self) -> SessionIntent {
+        *self.intent.lock().unwrap()
+    }
+
+    #[inline]
+    pub fn is_switching_back(&self) -> bool {
+        self.get_intent() == SessionIntent::SwitchBack
+    }
+
+    /// Called when the connection is lost without being asked to.
+    pub fn on_network_drop(&self) {
+        let mut intent = self.intent.lock().unwrap();
+        if *intent == SessionIntent::Active {
+            *intent = SessionIntent::NetworkDrop;
+        }
+    }
+
+    /// The peer asks to switch the sides back.
+    /// The session is marked so the reconnection stands down, then the ui switches the sides.
+    pub fn on_switch_back(&self) {
+        *self.intent.lock().unwrap() = SessionIntent::SwitchBack;
+        self.lc.write().unwrap().restarting_remote_device = false;
+        self.switch_back_state(&self.get_id(), "initiated");
+    }
+
     #[inline]
     pub fn peer_platform(&self) -> String {
         self.lc.read().unwrap().info.platform.clone()
@@ -669,6 +1302,12 @@ impl<T: InvokeUiSession> Session<T> {
     pub fn send_key_event(&self, evt: &KeyEvent) {
         // mode: legacy(0), map(1), translate(2), auto(3)
 
+        if self.hook_key_event(evt) == HookAction::Block {
+            return;
+        }
+        if !self.check_hotkey_rules(evt) {
+            return;
+        }
         let mut msg = evt.clone();
         self.swab_modifier_key(&mut msg);
         let mut msg_out = Message::new();
@@ -688,6 +1327,9 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn capture_displays(&self, add: Vec<i32>, sub: Vec<i32>, set: Vec<i32>) {
+        if !add.is_empty() || !set.is_empty() {
+            self.leave_audio_only();
+        }
         let mut misc = Misc::new();
         misc.set_capture_displays(CaptureDisplays {
             add,
@@ -700,7 +1342,32 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    pub fn is_audio_only_session(&self) -> bool {
+        self.lc.read().unwrap().audio_only_session
+    }
+
+    // The video is requested again, the session is a normal one since.
+    fn leave_audio_only(&self) {
+        {
+            let mut lc = self.lc.write().unwrap();
+            if !lc.audio_only_session {
+                return;
+            }
+            lc.audio_only_session = false;
+        }
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            audio_only: option_message::BoolOption::No.into(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+        self.update_audio_only(false);
+    }
+
     pub fn switch_display(&self, display: i32) {
+        self.leave_audio_only();
         let (w, h) = match self.lc.read().unwrap().get_custom_resolution(display) {
             Some((w, h)) => (w, h),
             None => (0, 0),
@@ -752,8 +1419,14 @@ impl<T: InvokeUiSession> Session<T> {
 
     // flutter only TODO new input
     pub fn input_string(&self, value: &str) {
+        if !self.check_can_inject_input() {
+            return;
+        }
         let mut key_event = KeyEvent::new();
         key_event.set_seq(value.to_owned());
+        if self.hook_key_event(&key_event) == HookAction::Block {
+            return;
+        }
         let mut msg_out = Message::new();
         msg_out.set_key_event(key_event);
         self.send(Data::Message(msg_out));
@@ -908,6 +1581,52 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    /// Draw a stroke on the screen of the host, acked by `annotation_ack`.
+    /// `points` are x0, y0, x1, y1, ... in the coordinates of the mouse events. The stroke of the
+    /// same `id` is replaced, e.g. the freehand one growing while drawn.
+    pub fn send_annotation(
+        &self,
+        id: u32,
+        display: i32,
+        shape: &str,
+        points: Vec<i32>,
+        color: u32,
+        width: u32,
+        ttl_ms: u32,
+    ) {
+        let shape = match shape {
+            "freehand" => annotation_stroke::Shape::Freehand,
+            "rectangle" => annotation_stroke::Shape::Rectangle,
+            "arrow" => annotation_stroke::Shape::Arrow,
+            _ => {
+                log::warn!("unknown annotation shape: {}", shape);
+                return;
+            }
+        };
+        let mut annotation = Annotation::new();
+        annotation.set_stroke(AnnotationStroke {
+            id,
+            display,
+            shape: shape.into(),
+            points,
+            color,
+            width,
+            ttl_ms,
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_annotation(annotation);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn clear_annotations(&self) {
+        let mut annotation = Annotation::new();
+        annotation.set_clear(true);
+        let mut msg = Message::new();
+        msg.set_annotation(annotation);
+        self.send(Data::Message(msg));
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn is_scroll_reverse_mode(&self) -> bool {
@@ -978,6 +1697,14 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn reconnect(&self, force_relay: bool) {
+        {
+            let mut intent = self.intent.lock().unwrap();
+            if !intent.allows_reconnect() {
+                log::info!("Skip reconnecting {}, intent: {:?}", self.get_id(), *intent);
+                return;
+            }
+            *intent = SessionIntent::Active;
+        }
         // 1. If current session is connecting, do not reconnect.
         // 2. If the connection is established, send `Data::Close`.
         // 3. If the connection is disconnected, do nothing.
@@ -1066,11 +1793,41 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Login((os_username, os_password, password, remember)));
     }
 
+    /// Send the code of the "input-2fa" msgbox.
+    pub fn send2fa(&self, code: String) {
+        self.send(Data::Login2FA(code));
+    }
+
     pub fn new_rdp(&self) {
         self.send(Data::NewRDP);
     }
 
+    /// Update the rdp options, effective for the next launch of the rdp client.
+    pub fn update_rdp_options(&self, options: &str) -> ResultType<()> {
+        if !self.is_rdp() {
+            bail!("Not a rdp session");
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        {
+            *self.rdp_options.write().unwrap() = crate::port_forward::RdpOptions::parse(options)?;
+            Ok(())
+        }
+        #[cfg(any(target_os = "android", target_os = "ios"))]
+        {
+            let _ = options;
+            bail!("RDP is unsupported on this platform");
+        }
+    }
+
     pub fn close(&self) {
+        {
+            let mut intent = self.intent.lock().unwrap();
+            if *intent != SessionIntent::SwitchBack {
+                *intent = SessionIntent::UserClose;
+            }
+        }
+        // Stop reconnecting to the restarting remote device.
+        self.lc.write().unwrap().restarting_remote_device = false;
         self.send(Data::Close);
     }
 
@@ -1109,11 +1866,25 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     #[cfg(any(target_os = "ios"))]
-    pub fn switch_sides(&self) {}
+    pub fn switch_sides(&self) {
+        if self.is_switching_back() {
+            self.switch_back_state(&self.get_id(), "failed");
+        }
+    }
 
+    #[cfg(not(any(target_os = "ios")))]
+    pub fn switch_sides(&self) {
+        let sent = self.send_switch_sides_request();
+        if self.is_switching_back() {
+            let state = if sent { "completed" } else { "failed" };
+            self.switch_back_state(&self.get_id(), state);
+        }
+    }
+
+    // Returns true if the request is sent to the peer.
     #[cfg(not(any(target_os = "ios")))]
     #[tokio::main(flavor = "current_thread")]
-    pub async fn switch_sides(&self) {
+    async fn send_switch_sides_request(&self) -> bool {
         match crate::ipc::connect(1000, "").await {
             Ok(mut conn) => {
                 if conn
@@ -1133,6 +1904,7 @@ impl<T: InvokeUiSession> Session<T> {
                                     let mut msg_out = Message::new();
                                     msg_out.set_misc(misc);
                                     self.send(Data::Message(msg_out));
+                                    return true;
                                 }
                             }
                             _ => {}
@@ -1144,6 +1916,7 @@ impl<T: InvokeUiSession> Session<T> {
                 log::info!("server not started (will try to start): {}", err);
             }
         }
+        false
     }
 
     fn set_custom_resolution(&self, display: &SwitchDisplay) {
@@ -1182,24 +1955,35 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     #[inline]
-    pub fn change_resolution(&self, display: i32, width: i32, height: i32) {
+    /// `refresh_rate` is 0 to let the peer choose it.
+    pub fn change_resolution(&self, display: i32, width: i32, height: i32, refresh_rate: i32) {
+        if !self.peer_capabilities().can_change_resolution {
+            self.msgbox(
+                crate::common::UNSUPPORTED_CAPABILITY_MSGTYPE,
+                "Error",
+                "peer_cannot_change_resolution_tip",
+                "",
+            );
+            return;
+        }
         *self.last_change_display.lock().unwrap() =
             ChangeDisplayRecord::new(display, width, height);
-        self.do_change_resolution(width, height);
+        self.do_change_resolution(width, height, refresh_rate);
     }
 
     #[inline]
     fn try_change_init_resolution(&self, display: i32) {
         if let Some((w, h)) = self.lc.read().unwrap().get_custom_resolution(display) {
-            self.do_change_resolution(w, h);
+            self.do_change_resolution(w, h, 0);
         }
     }
 
-    fn do_change_resolution(&self, width: i32, height: i32) {
+    fn do_change_resolution(&self, width: i32, height: i32, refresh_rate: i32) {
         let mut misc = Misc::new();
         misc.set_change_resolution(Resolution {
             width,
             height,
+            refresh_rate,
             ..Default::default()
         });
         let mut msg = Message::new();
@@ -1207,6 +1991,115 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn is_view_only_session(&self) -> bool {
+        self.lc.read().unwrap().view_only_session
+    }
+
+    /// Ask the peer to accept the input again, the session stays view-only until it confirms.
+    pub fn request_view_only_off(&self) {
+        if !self.is_view_only_session() {
+            return;
+        }
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            view_only: option_message::BoolOption::No.into(),
+            ..Default::default()
+        });
+        let mut msg = Message::new();
+        msg.set_misc(misc);
+        self.send(Data::Message(msg));
+        self.msgbox(
+            VIEW_ONLY_OFF_MSGTYPE,
+            VIEW_ONLY_OFF_TITLE,
+            VIEW_ONLY_OFF_TEXT,
+            "",
+        );
+    }
+
+    fn notify_view_only_blocked(&self) {
+        let mut last = self.view_only_blocked.lock().unwrap();
+        if last.map_or(false, |t| t.elapsed() < VIEW_ONLY_BLOCKED_NOTIFY_INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        drop(last);
+        self.ui_handler.view_only_blocked();
+    }
+
+    /// Returns false if the key event is kept from the peer by a hotkey rule.
+    fn check_hotkey_rules(&self, evt: &KeyEvent) -> bool {
+        let mut rules = self.hotkey_rules.lock().unwrap();
+        if !rules.is_loaded() {
+            let json = self.lc.read().unwrap().get_option(OPTION_HOTKEY_RULES);
+            rules.set(HotkeyRules::parse(&json).unwrap_or_else(|e| {
+                log::error!("Invalid hotkey rules of {}: {}", self.get_id(), e);
+                vec![]
+            }));
+        }
+        let mut peer = self.peer_platform().to_lowercase();
+        peer.retain(|c| !c.is_whitespace());
+        let Some(rule) = rules.check(evt, &peer).cloned() else {
+            return true;
+        };
+        match rule.action {
+            HotkeyAction::Remote => true,
+            HotkeyAction::Local => false,
+            HotkeyAction::Blocked => {
+                let press = evt.down || evt.press;
+                if press && rules.should_notify_blocked(&rule.name, std::time::Instant::now()) {
+                    drop(rules);
+                    self.ui_handler.hotkey_blocked(&rule.name, &rule.keys);
+                }
+                false
+            }
+            HotkeyAction::ToggleRemoteMute => {
+                if evt.down || evt.press {
+                    drop(rules);
+                    self.toggle_remote_mute();
+                }
+                false
+            }
+        }
+    }
+
+    /// Set the hotkey rules of the peer from json, applied at once and saved to the peer config.
+    pub fn set_hotkey_rules(&self, json: &str) -> ResultType<()> {
+        let rules = HotkeyRules::parse(json)?;
+        let value = if rules.is_empty() {
+            "".to_owned()
+        } else {
+            serde_json::to_string(&rules)?
+        };
+        self.hotkey_rules.lock().unwrap().set(rules);
+        self.lc
+            .write()
+            .unwrap()
+            .set_option(OPTION_HOTKEY_RULES.to_owned(), value);
+        Ok(())
+    }
+
+    pub fn get_hotkey_rules(&self) -> String {
+        let rules = self.hotkey_rules.lock().unwrap();
+        if rules.is_loaded() {
+            serde_json::to_string(&rules.rules()).unwrap_or_default()
+        } else {
+            self.lc.read().unwrap().get_option(OPTION_HOTKEY_RULES)
+        }
+    }
+
+    /// Run the network probe over the connection and return the json of
+    /// [`crate::client::network_probe::ProbeResult`], which is also pushed to the ui.
+    /// It's refused while a file transfer job is active unless `force` is set.
+    pub fn run_network_probe(&self, force: bool) -> ResultType<String> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.send(Data::NetworkProbe(force, tx));
+        match rx.recv_timeout(NETWORK_PROBE_TIMEOUT) {
+            Ok(res) => res,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => bail!("Network probe timeout"),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => bail!("Not connected"),
+        }
+    }
+
     #[inline]
     pub fn request_voice_call(&self) {
         self.send(Data::NewVoiceCall);
@@ -1230,10 +2123,17 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn on_connected(&self, conn_type: ConnType);
     fn update_privacy_mode(&self);
     fn set_permission(&self, name: &str, value: bool);
+    /// The snapshot of all the permissions.
+    fn set_permissions(&self, permissions: &[(&str, bool)]);
     fn close_success(&self);
     fn update_quality_status(&self, qs: QualityStatus);
     fn set_connection_type(&self, is_secured: bool, direct: bool);
+    /// The relay server in use, once connected via a relay.
+    fn set_relay_server(&self, relay: &str);
+    /// The preferred relay failed, the one chosen by the rendezvous server is tried instead.
+    fn relay_fallback(&self, preferred: &str, err: &str);
     fn set_fingerprint(&self, fingerprint: String);
+    fn fingerprint_mismatch(&self, old: &str, new: &str);
     fn job_error(&self, id: i32, err: String, file_num: i32);
     fn job_done(&self, id: i32, file_num: i32);
     fn clear_all_jobs(&self);
@@ -1259,20 +2159,93 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     );
     fn update_block_input_state(&self, on: bool);
     fn job_progress(&self, id: i32, file_num: i32, speed: f64, finished_size: f64);
+    fn transfer_summary(&self, summary: &crate::client::job_progress::TransferSummary);
+    /// The files of the job renamed to be valid on this system.
+    fn job_renamed_files(&self, id: i32, renamed: &[hbb_common::fs::file_name::Renamed]);
+    /// The streams the large files of the job may be split into.
+    fn job_streams(&self, id: i32, streams: u32);
+    fn sync_state(&self, id: i32, state: &crate::client::file_sync::SyncState);
+    // The renames and removes in place, see `fs::file_op`.
+    fn file_op_progress(&self, id: i32, removed: u64, total: u64, is_remote: bool);
+    fn file_op_done(&self, id: i32, is_remote: bool);
+    fn file_op_error(&self, id: i32, err: String, is_remote: bool);
     fn adapt_size(&self);
     fn on_rgba(&self, display: usize, rgba: &mut scrap::ImageRgb);
     fn msgbox(&self, msgtype: &str, title: &str, text: &str, link: &str, retry: bool);
+    /// The msgbox whose text is a lang key, translated with the params by the ui.
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        params: &[(&str, String)],
+        link: &str,
+        retry: bool,
+    );
     #[cfg(any(target_os = "android", target_os = "ios"))]
     fn clipboard(&self, content: String);
+    /// The text clipboard received is truncated from `len` bytes.
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    fn clipboard_truncated(&self, len: usize, max: usize);
+    /// Whether the local clipboard can be read for the peer, see `text_clipboard`.
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    fn text_clipboard_required(&self, required: bool);
     fn cancel_msgbox(&self, tag: &str);
-    fn switch_back(&self, id: &str);
+    /// `state`: "initiated", "completed" or "failed".
+    fn switch_back_state(&self, peer_id: &str, state: &str);
     fn portable_service_running(&self, running: bool);
+    fn host_display_waking(&self, waking: bool);
+    /// `state`: "active", "locked" or "display_off".
+    fn host_display_state(&self, state: &str);
+    fn view_only_blocked(&self);
+    /// The key event is blocked by the hotkey rule.
+    fn hotkey_blocked(&self, rule: &str, keys: &str);
+    /// Sampled every second by the io loop. Returns the displays to capture if they're reduced
+    /// on the sustained congestion, or restored.
+    fn on_video_congestion(&self, congested: bool) -> Option<Vec<i32>>;
+    fn update_view_only(&self, view_only: bool);
+    fn update_audio_only(&self, audio_only: bool);
+    fn network_probe_result(&self, result: &str);
+    /// The job printed on the host is being received.
+    fn print_job_progress(&self, id: u32, name: &str, received: u64, total: u64);
+    /// The job printed on the host is saved to `path`, to be printed locally.
+    fn print_job_received(&self, id: u32, name: &str, path: &str);
+    fn print_job_failed(&self, id: u32, name: &str, err: &str);
+    /// The staged files removed as the size of the staging area exceeds the cap, or expired.
+    fn staging_evicted(&self, files: &[crate::client::staging::StagedFile]);
+    /// `error` is empty on success, or the tip of the failure.
+    fn remote_sysinfo(&self, sysinfo: &str, error: &str);
+    /// `state`: "ok", "unsupported" or "denied", the volume is valid only if "ok".
+    fn remote_volume_changed(&self, state: &str, percent: u32, muted: bool);
+    /// `state`: "requested", "waiting_for_host", "reconnected", "denied" or "timeout".
+    fn remote_restarting(&self, state: &str, attempt: usize);
+    fn update_peer_capabilities(&self, capabilities: &crate::common::PeerCapabilities);
+    /// `reason`: "preference", "fallback" or "hw-init-failure".
+    fn codec_negotiated(&self, codec: &str, reason: &str);
+    fn decoder_info(&self, display: usize, info: &crate::client::decoder_select::DecoderInfo);
+    /// The display stays in 4:2:0 while 4:4:4 is asked, see `color_mode`.
+    fn color_mode_fallback(&self, fallback: &color_mode::Fallback);
+    /// The stroke `id` sent by `send_annotation` is drawn on the host, or the `error` if not.
+    fn annotation_ack(&self, id: u32, error: &str);
+    /// The peer has broadcast `sent` magic packets of `mac`, or the `error` if not.
+    fn wol_result(&self, mac: &str, error: &str, sent: u32);
+    /// The keys restricted by the host, and the key events dropped in the session.
+    fn input_policy(&self, level: &str, blocked: &[String], dropped: u64);
+    /// The capture is throttled by the host because of its load, `fps_percent` is 100 if not.
+    fn host_throttled(&self, fps_percent: u32, cpu: u32, encode: u32);
+    /// The region of the display in the frame decoded, `None` if the whole display.
+    fn frame_viewport(&self, display: usize, viewport: Option<&Viewport>);
     fn on_voice_call_started(&self);
     fn on_voice_call_closed(&self, reason: &str);
     fn on_voice_call_waiting(&self);
     fn on_voice_call_incoming(&self);
     fn get_rgba(&self, display: usize) -> *const u8;
     fn next_rgba(&self, display: usize);
+    fn update_socks_stats(&self, port: i32, stats: &str);
+    fn update_port_forward_stats(&self, stats: &str);
+    fn hook_key_event(&self, evt: &KeyEvent) -> HookAction;
+    fn hook_clipboard(&self, content: &mut String) -> HookAction;
+    fn hook_file_job_start(&self, job: &JobMeta) -> HookAction;
 }
 
 impl<T: InvokeUiSession> Deref for Session<T> {
@@ -1298,6 +2271,12 @@ impl<T: InvokeUiSession> Interface for Session<T> {
     }
 
     fn send(&self, data: Data) {
+        if let Data::Message(msg) = &data {
+            if self.lc.read().unwrap().view_only_session && crate::common::is_input_message(msg) {
+                self.notify_view_only_blocked();
+                return;
+            }
+        }
         if let Some(sender) = self.sender.read().unwrap().as_ref() {
             sender.send(data).ok();
         }
@@ -1311,17 +2290,36 @@ impl<T: InvokeUiSession> Interface for Session<T> {
         self.ui_handler.msgbox(msgtype, title, text, link, retry);
     }
 
+    fn msgbox_keyed(
+        &self,
+        msgtype: &str,
+        title: &str,
+        text_key: &str,
+        params: Vec<(&str, String)>,
+        link: &str,
+    ) {
+        let direct = self.lc.read().unwrap().direct;
+        let received = self.lc.read().unwrap().received;
+        let retry_for_relay = direct == Some(true) && !received;
+        let retry = check_if_retry(msgtype, title, text_key, retry_for_relay);
+        self.ui_handler.msgbox_keyed(msgtype, title, text_key, &params, link, retry);
+    }
+
     fn handle_login_error(&self, err: &str) -> bool {
         handle_login_error(self.lc.clone(), err, self)
     }
 
+    fn on_relay_fallback(&self, preferred: &str, err: &str) {
+        self.ui_handler.relay_fallback(preferred, err);
+    }
+
     fn handle_peer_info(&self, mut pi: PeerInfo) {
         log::debug!("handle_peer_info :{:?}", pi);
         pi.username = self.lc.read().unwrap().get_username(&pi);
         if pi.current_display as usize >= pi.displays.len() {
             pi.current_display = 0;
         }
-        if get_version_number(&pi.version) < get_version_number("1.1.10") {
+        if !self.peer_capabilities().restart {
             self.set_permission("restart", false);
         }
         if self.is_file_transfer() {
@@ -1351,9 +2349,30 @@ impl<T: InvokeUiSession> Interface for Session<T> {
             );
         }
         self.update_privacy_mode();
+        let restarted = {
+            let mut lc = self.lc.write().unwrap();
+            let restarted = lc.restart_reconnecting;
+            lc.restarting_remote_device = false;
+            lc.restart_reconnecting = false;
+            restarted
+        };
+        if restarted {
+            self.remote_restarting("reconnected", 0);
+        }
         // Save recent peers, then push event to flutter. So flutter can refresh peer page.
         self.lc.write().unwrap().handle_peer_info(&pi);
+        #[cfg(feature = "flutter")]
+        if self.is_default() {
+            let id = self.get_id();
+            let lc = self.lc.read().unwrap();
+            crate::flutter::session_restore::on_session_established(
+                &id,
+                lc.force_relay,
+                lc.view_only_session,
+            );
+        }
         self.set_peer_info(&pi);
+        self.update_peer_capabilities(&self.peer_capabilities());
         if self.is_file_transfer() {
             self.close_success();
         } else if !self.is_port_forward() {
@@ -1431,13 +2450,27 @@ impl<T: InvokeUiSession> Interface for Session<T> {
                 .collect();
         };
     }
+
+    fn update_port_forward_stats(&self, stats: &str) {
+        self.ui_handler.update_port_forward_stats(stats);
+    }
+
+    fn on_fingerprint_mismatch(&self, old: &str, new: &str) {
+        self.ui_handler.fingerprint_mismatch(old, new);
+    }
 }
 
 impl<T: InvokeUiSession> Session<T> {
     pub fn lock_screen(&self) {
+        if !self.check_can_inject_input() {
+            return;
+        }
         self.send_key_event(&crate::keyboard::client::event_lock_screen());
     }
     pub fn ctrl_alt_del(&self) {
+        if !self.check_can_inject_input() {
+            return;
+        }
         self.send_key_event(&crate::keyboard::client::event_ctrl_alt_del());
     }
 }
@@ -1510,6 +2543,34 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
                             .await;
                         });
                     }
+                    Some(Data::StartSocks((port, auth))) => {
+                        if port <= 0 || queues.contains_key(&port) {
+                            continue;
+                        }
+                        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+                        queues.insert(port, sender);
+                        let handler = handler.clone();
+                        let key = key.clone();
+                        let token = token.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = crate::port_forward::socks::listen(
+                                handler.clone(),
+                                port,
+                                auth,
+                                receiver,
+                                &key,
+                                &token,
+                            )
+                            .await
+                            {
+                                handler.on_error(&format!(
+                                    "Failed to listen on {}: {}",
+                                    port, err
+                                ));
+                            }
+                            log::info!("socks (:{}) exit", port);
+                        });
+                    }
                     Some(Data::RemovePortForward(port)) => {
                         if let Some(s) = queues.remove(&port) {
                             s.send(Data::Close).ok();
@@ -1552,7 +2613,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
     let frame_count_map: Arc<RwLock<HashMap<usize, usize>>> = Default::default();
     let frame_count_map_cl = frame_count_map.clone();
     let ui_handler = handler.ui_handler.clone();
-    let (video_sender, audio_sender, video_queue_map, decode_fps_map, chroma) =
+    let (video_sender, audio_sender, video_queue_map, decode_fps_map, decode_stats_map) =
         start_video_audio_threads(
             handler.clone(),
             move |display: usize, data: &mut scrap::ImageRgb| {
@@ -1573,7 +2634,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
         sender,
         frame_count_map,
         decode_fps_map,
-        chroma,
+        decode_stats_map,
     );
     remote.io_loop(&key, &token, round).await;
     remote.sync_jobs_status_to_local().await;
@@ -1600,6 +2661,8 @@ async fn start_one_port_forward<T: InvokeUiSession>(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        handler.port_forward_stats.clone(),
+        handler.rdp_options.clone(),
     )
     .await
     {
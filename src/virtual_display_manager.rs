@@ -1,3 +1,4 @@
+use crate::state_journal::{self, StateRecord};
 #[cfg(target_os = "windows")]
 use hbb_common::platform::windows::is_windows_version_or_greater;
 use hbb_common::{allow_err, bail, lazy_static, log, ResultType};
@@ -44,8 +45,11 @@ impl VirtualDisplayManager {
         Ok(())
     }
 
+    // The monitor is plugged out on the next start if the process is killed.
     fn plug_in_monitor(index: u32, modes: &[virtual_display::MonitorMode]) -> ResultType<()> {
+        state_journal::engage(StateRecord::VirtualDisplay(index));
         if let Err(e) = virtual_display::plug_in_monitor(index) {
+            state_journal::release(&StateRecord::VirtualDisplay(index));
             bail!("Plug in monitor failed {}", e);
         }
         if let Err(e) = virtual_display::update_monitor_modes(index, &modes) {
@@ -53,6 +57,18 @@ impl VirtualDisplayManager {
         }
         Ok(())
     }
+
+    fn plug_out_monitor(index: u32) -> ResultType<()> {
+        virtual_display::plug_out_monitor(index)?;
+        state_journal::release(&StateRecord::VirtualDisplay(index));
+        Ok(())
+    }
+}
+
+/// Plug out the monitor left by the last run, see [`crate::state_journal`].
+pub fn plug_out_leftover(index: u32) -> ResultType<()> {
+    let _manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
+    VirtualDisplayManager::plug_out_monitor(index)
 }
 
 pub fn is_virtual_display_supported() -> bool {
@@ -91,7 +107,7 @@ pub fn plug_in_headless() -> ResultType<()> {
 pub fn plug_out_headless() -> bool {
     let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
     if let Some((index, _)) = manager.headless_index_name.take() {
-        if let Err(e) = virtual_display::plug_out_monitor(index) {
+        if let Err(e) = VirtualDisplayManager::plug_out_monitor(index) {
             log::error!("Plug out monitor failed {}", e);
         }
         true
@@ -201,13 +217,23 @@ pub fn plug_out_peer_request(indices: &[u32]) -> ResultType<()> {
     let mut manager = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
     for idx in indices.iter() {
         if manager.peer_index_name.contains_key(idx) {
-            allow_err!(virtual_display::plug_out_monitor(*idx));
+            allow_err!(VirtualDisplayManager::plug_out_monitor(*idx));
             manager.peer_index_name.remove(idx);
         }
     }
     Ok(())
 }
 
+pub fn get_peer_virtual_display_index(name: &str) -> Option<u32> {
+    VIRTUAL_DISPLAY_MANAGER
+        .lock()
+        .unwrap()
+        .peer_index_name
+        .iter()
+        .find(|(_, v)| windows::is_device_name(v, name))
+        .map(|(k, _)| *k)
+}
+
 pub fn is_virtual_display(name: &str) -> bool {
     let lock = VIRTUAL_DISPLAY_MANAGER.lock().unwrap();
     if let Some((_, device_name)) = &lock.headless_index_name {
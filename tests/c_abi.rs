@@ -0,0 +1,58 @@
+//! Call the exported C functions from a C program linked against the cdylib.
+#![cfg(all(feature = "flutter", any(target_os = "linux", target_os = "macos")))]
+
+use std::{path::PathBuf, process::Command};
+
+// The cdylib is in the parent of the `deps` directory of the test executable.
+fn lib_dir() -> PathBuf {
+    let mut dir = std::env::current_exe().unwrap();
+    dir.pop();
+    if dir.ends_with("deps") {
+        dir.pop();
+    }
+    dir
+}
+
+#[test]
+fn test_list_sessions_from_c() {
+    let src = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/c_abi/list_sessions.c");
+    let exe = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("c_abi_list_sessions");
+    let lib_dir = lib_dir();
+    let compiler = cc::Build::new()
+        .target(env!("BUILD_TARGET"))
+        .host(env!("BUILD_TARGET"))
+        .opt_level(0)
+        .cargo_metadata(false)
+        .warnings_into_errors(true)
+        .get_compiler();
+    let status = compiler
+        .to_command()
+        .arg(src)
+        .arg("-o")
+        .arg(&exe)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg("-llibrustdesk")
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to build {}", src);
+
+    let lib_path_var = if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    let output = Command::new(&exe)
+        .env(lib_path_var, &lib_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // No session is open in the C program.
+    let json = String::from_utf8(output.stdout).unwrap();
+    let list: Vec<serde_json::Value> = serde_json::from_str(json.trim()).unwrap();
+    assert!(list.is_empty());
+}
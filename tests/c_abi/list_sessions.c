@@ -0,0 +1,33 @@
+// Calls the C functions of the core as the external tools do, see tests/c_abi.rs.
+#include <stdio.h>
+#include <string.h>
+
+// Must match the exported functions in src/flutter.rs.
+char *rustdesk_list_sessions(void);
+void rustdesk_free_string(char *s);
+
+int main(void) {
+    // Each call returns a new string owned by the caller.
+    char *first = rustdesk_list_sessions();
+    char *second = rustdesk_list_sessions();
+    if (!first || !second || first == second) {
+        fprintf(stderr, "expect two strings\n");
+        return 1;
+    }
+    size_t len = strlen(first);
+    if (len < 2 || first[0] != '[' || first[len - 1] != ']' ||
+        strcmp(first, second) != 0) {
+        fprintf(stderr, "unexpected json: %s\n", first);
+        return 1;
+    }
+    // Freeing one string does not touch the other.
+    rustdesk_free_string(second);
+    printf("%s\n", first);
+    rustdesk_free_string(first);
+    rustdesk_free_string(NULL);
+    // A double free or a mismatched allocator aborts here.
+    for (int i = 0; i < 1000; i++) {
+        rustdesk_free_string(rustdesk_list_sessions());
+    }
+    return 0;
+}